@@ -0,0 +1,123 @@
+//! Benchmarks for each stage of getting a chunk from nothing to the screen: generating its voxels, meshing them,
+//! and serializing them for the wire. The same chunks are generated from the same seed every run, at every LOD, so
+//! timings are comparable between runs.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+extern crate common;
+extern crate client_lib;
+extern crate terrain;
+
+extern crate cgmath;
+extern crate collision;
+
+extern crate env_logger;
+extern crate rand;
+extern crate time;
+
+use std::sync::Mutex;
+
+use common::{compression, id_allocator, voxel};
+use client_lib::{chunk, lod, terrain_mesh};
+
+/// How many chunks to sample along each axis, per LOD.
+const SAMPLE_WIDTH: i32 = 4;
+/// The lowest chunk sampled, along y. The samples straddle the ground, so they have surfaces to mesh.
+const LOW_CHUNK_Y: i32 = -2;
+
+fn ms_since(start: u64) -> f32 {
+  (time::precise_time_ns() - start) as f32 / 1e6
+}
+
+fn main() {
+  env_logger::init().unwrap();
+
+  let (structures, _) = terrain::structures::load(&Default::default());
+  let terrain =
+    terrain::T::new(
+      terrain::Seed::new(0),
+      terrain::LATEST_VERSION,
+      terrain::Shape::Biomes(Default::default(), structures),
+    );
+  let mut generator = terrain.generator();
+
+  let terrain_allocator = Mutex::new(id_allocator::new());
+  let grass_allocator = Mutex::new(id_allocator::new());
+  let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
+  let scheme = compression::negotiate(compression::SUPPORTED);
+
+  for &lod in lod::ALL.iter() {
+    let voxel_size = 1 << lod.lg_sample_size();
+
+    let mut voxelization_ms = 0.0;
+    let mut meshing_ms = 0.0;
+    let mut serialization_ms = 0.0;
+    let mut voxel_count = 0;
+    let mut serialized_bytes = 0;
+
+    for x in 0 .. SAMPLE_WIDTH {
+    for y in LOW_CHUNK_Y .. LOW_CHUNK_Y + SAMPLE_WIDTH {
+    for z in 0 .. SAMPLE_WIDTH {
+      let chunk_position = chunk::position::new(x, y, z);
+      let p = *chunk_position.as_pnt();
+      let bounds =
+        terrain_mesh::voxels_in(
+          &collision::Aabb3::new(
+            cgmath::Point3::new(
+              (p.x << chunk::LG_WIDTH) - voxel_size,
+              (p.y << chunk::LG_WIDTH) - voxel_size,
+              (p.z << chunk::LG_WIDTH) - voxel_size,
+            ),
+            cgmath::Point3::new(
+              ((p.x + 1) << chunk::LG_WIDTH) + voxel_size,
+              ((p.y + 1) << chunk::LG_WIDTH) + voxel_size,
+              ((p.z + 1) << chunk::LG_WIDTH) + voxel_size,
+            ),
+          ),
+          lod.lg_sample_size(),
+        );
+
+      // Chunks share the voxels around their edges. Ones already generated for a neighbor are looked up instead, as
+      // they would be on a server.
+      let start = time::precise_time_ns();
+      generator.expect(&bounds);
+      let voxels: Vec<(voxel::bounds::T, voxel::T)> =
+        bounds.into_iter().map(|bounds| (bounds, terrain.load_with(&mut generator, &bounds))).collect();
+      voxelization_ms += ms_since(start);
+      voxel_count += voxels.len();
+
+      let start = time::precise_time_ns();
+      let compressed = compression::compress(scheme, &voxels);
+      serialization_ms += ms_since(start);
+      serialized_bytes += compressed.compressed_len();
+
+      let start = time::precise_time_ns();
+      terrain_mesh::generate(
+        &*terrain.voxels.lock().unwrap(),
+        &chunk_position,
+        lod,
+        &[None; 6],
+        1.0,
+        true,
+        &[],
+        &terrain_allocator,
+        &grass_allocator,
+        &mut rng,
+      );
+      meshing_ms += ms_since(start);
+    }}}
+
+    println!(
+      "LOD {} (lg voxel size {}): {} voxels: voxelization {:.1}ms, meshing {:.1}ms, serialization {:.1}ms ({:?}, {} bytes)",
+      lod.0,
+      lod.lg_sample_size(),
+      voxel_count,
+      voxelization_ms,
+      meshing_ms,
+      serialization_ms,
+      scheme,
+      serialized_bytes,
+    );
+  }
+}
@@ -18,13 +18,13 @@ extern crate time;
 use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
 use client_lib::{chunk, lod, terrain_mesh};
-use server_lib::{server, update_gaia};
+use server_lib::{server, update_gaia, world};
 use update_gaia::LoadDestination;
 
 fn main() {
   env_logger::init().unwrap();
 
-  let server = server::new();
+  let server = server::new(Default::default());
 
   let load_position = cgmath::Point3::new(0.0, 512.0, 0.0);
   let load_position = chunk::position::of_world_position(&load_position);
@@ -60,7 +60,7 @@ fn main() {
     let lod;
     match load_type {
       LoadType::Load => {
-        lod = lod::of_distance(distance as u32);
+        lod = lod::of_distance(&lod::THRESHOLDS, distance as u32);
       },
       LoadType::Downgrade => {
         panic!("Downgrading should not happen");
@@ -88,7 +88,7 @@ fn main() {
         lod.lg_sample_size(),
       );
 
-    update_gaia::update_gaia(&server, update_gaia::Message::Load(0, voxels, LoadDestination::None));
+    update_gaia::update_gaia(&server, update_gaia::Message::Load(world::OVERWORLD, 0, voxels, LoadDestination::None));
   }
 
   let now = time::precise_time_ns();
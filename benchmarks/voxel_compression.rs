@@ -0,0 +1,73 @@
+//! Benchmarks for the size of terrain payloads on the wire, with and without compression.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+extern crate common;
+extern crate client_lib;
+extern crate server_lib;
+
+extern crate cgmath;
+extern crate collision;
+
+extern crate env_logger;
+extern crate time;
+
+use common::compression;
+use client_lib::{chunk, lod, terrain_mesh};
+use server_lib::{server, world};
+
+/// How many chunks to sample along each axis, per LOD.
+const SAMPLE_WIDTH: i32 = 4;
+
+fn main() {
+  env_logger::init().unwrap();
+
+  let server = server::new(Default::default());
+
+  for &lod in lod::ALL.iter() {
+    let voxel_size = 1 << lod.lg_sample_size();
+
+    let mut voxels = Vec::new();
+    for x in 0 .. SAMPLE_WIDTH {
+    for y in 0 .. SAMPLE_WIDTH {
+    for z in 0 .. SAMPLE_WIDTH {
+      let chunk_position = cgmath::Point3::new(x, y, z);
+      let bounds =
+        terrain_mesh::voxels_in(
+          &collision::Aabb3::new(
+            cgmath::Point3::new(
+              (chunk_position.x << chunk::LG_WIDTH) - voxel_size,
+              (chunk_position.y << chunk::LG_WIDTH) - voxel_size,
+              (chunk_position.z << chunk::LG_WIDTH) - voxel_size,
+            ),
+            cgmath::Point3::new(
+              ((chunk_position.x + 1) << chunk::LG_WIDTH) + voxel_size,
+              ((chunk_position.y + 1) << chunk::LG_WIDTH) + voxel_size,
+              ((chunk_position.z + 1) << chunk::LG_WIDTH) + voxel_size,
+            ),
+          ),
+          lod.lg_sample_size(),
+        );
+      for bounds in bounds {
+        let voxel = server.world(world::OVERWORLD).terrain_loader.load_voxel(&bounds);
+        voxels.push((bounds, voxel));
+      }
+    }}}
+
+    for &scheme in compression::SUPPORTED {
+      let start = time::precise_time_ns();
+      let compressed = compression::compress(scheme, &voxels);
+      let elapsed = time::precise_time_ns() - start;
+      println!(
+        "LOD {}: {:?}: {} -> {} bytes ({:.1}%) in {:.2}ms",
+        lod.0,
+        scheme,
+        compressed.uncompressed_len(),
+        compressed.compressed_len(),
+        100.0 * compressed.compressed_len() as f32 / compressed.uncompressed_len() as f32,
+        elapsed as f32 / 1e6,
+      );
+    }
+  }
+}
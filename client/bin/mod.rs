@@ -15,14 +15,24 @@ use std::env;
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
-  args.next().unwrap();
-  let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/client.ipc"));
-  let server_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  let transport = client_lib::Transport::from_args(&mut args).unwrap_or(client_lib::Transport::Nanomsg);
+  let replay = client_lib::replay::Mode::from_args(&mut args);
+  let headless = client_lib::headless::from_args(&mut args);
+  let mut args = args.into_iter();
+  let listen_url = args.next().unwrap_or_else(|| String::from(transport.default_client_url()));
+  let server_url = args.next().unwrap_or_else(|| String::from(transport.default_server_url()));
   assert!(args.next().is_none());
 
-  info!("Sending to {}.", server_url);
+  info!("Sending to {} over {:?}.", server_url, transport);
   info!("Listening on {}.", listen_url);
 
-  client_lib::run(listen_url.borrow(), server_url.borrow());
+  match headless {
+    None => client_lib::run(listen_url.borrow(), server_url.borrow(), transport, replay),
+    Some(count) => {
+      assert_eq!(replay, client_lib::replay::Mode::Live, "Headless clients can't record or replay");
+      info!("Running {} headless clients.", count);
+      client_lib::headless::run(listen_url.borrow(), server_url.borrow(), transport, count);
+    },
+  }
 }
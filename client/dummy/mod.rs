@@ -14,18 +14,27 @@ extern crate client_lib;
 
 use std::sync::{Mutex};
 
+use common::net;
 use common::protocol;
 
 use client_lib::client;
+use client_lib::config;
 use client_lib::server;
 use client_lib::update_thread::update_thread;
 
 #[allow(missing_docs)]
-pub fn run(listen_url: &str, server_url: &str) {
+pub fn run(listen_url: &str, server_url: &str, transport: net::Transport) {
   let quit = Mutex::new(false);
   let quit = &quit;
 
-  let server = server::new(&server_url, &listen_url);
+  let server =
+    match server::new(&server_url, &listen_url, transport) {
+      Ok(server) => server,
+      Err(err) => {
+        error!("Couldn't listen on {}: {}", listen_url, err);
+        return
+      },
+    };
 
   let client = connect_client(&listen_url, &server);
   *client.load_position.lock().unwrap() = Some(cgmath::Point3::new(0.0, 512.0, 0.0));
@@ -63,9 +72,9 @@ pub fn run(listen_url: &str, server_url: &str) {
           &mut |_| { },
           &mut |up| { server.talk.tell(&up) },
           &mut |msg| {
-            if let client_lib::terrain::Load::Voxels { time_requested: Some(_), .. } = msg {
+            if let client_lib::terrain::Load::Voxels { time_requested: Some(time_requested), .. } = msg {
               *loaded_count.lock().unwrap() += 1;
-              *client.pending_terrain_requests.lock().unwrap() -= 1;
+              client.chunk_requests.lock().unwrap().received(time_requested);
             }
           },
         );
@@ -88,16 +97,20 @@ pub fn run(listen_url: &str, server_url: &str) {
 
 fn connect_client(listen_url: &str, server: &server::T) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  server.talk.tell(&protocol::ClientToServer::Init(protocol::hello(listen_url)));
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+      protocol::ServerToClient::Incompatible { reason, .. } => {
+        panic!("Couldn't join the server: {}", reason);
+      },
+      protocol::ServerToClient::LeaseId { client_id, seed, session, features } => {
+        info!("Joined as {:?}, with {:?}", client_id, features);
+        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id, "Dummy".to_owned()));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              return client::new(client_id, session, player_id, position, seed, config::load());
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -0,0 +1,369 @@
+//! Skeletal animation for mobs and players.
+//! A rig is laid out like a glTF skin: bones listed parents-first, each turning around a joint, with the mesh
+//! authored in the rest pose and each vertex bound to one bone. Posing a rig samples a clip's keyframes and gives
+//! a skinning matrix per bone, which moves that bone's vertices from the rest pose into the pose.
+//! Rigs live in the unit box around the origin, facing +x, and are placed, sized and turned per entity.
+//! They're read from JSON files, where bones are named and refer to each other by name.
+
+use cgmath::{Matrix4, Point3, Quaternion, Rad, Rotation3, Vector3, EuclideanSpace, InnerSpace};
+use collision::Aabb3;
+use serde_json;
+use std;
+use std::io::Read;
+
+use common::protocol;
+
+/// Where the mobs' rig is read from, relative to the working directory.
+pub const MOB_RIG: &'static str = "rigs/quadruped.json";
+/// Where the players' rig is read from, relative to the working directory.
+pub const PLAYER_RIG: &'static str = "rigs/biped.json";
+
+/// A part of a rig.
+#[derive(Debug, Clone)]
+pub struct Bone {
+  /// The bone this one hangs from, which always comes earlier in the rig.
+  pub parent : Option<usize>,
+  /// The point the bone turns around, in the rest pose.
+  pub joint  : Point3<f32>,
+  /// The bone's part of the mesh, in the rest pose.
+  pub bounds : Aabb3<f32>,
+  /// Whether the bone's +x face is the entity's face, which is drawn in its facing color.
+  pub facing : bool,
+}
+
+/// How one bone turns over the course of a clip.
+#[derive(Debug, Clone)]
+pub struct Channel {
+  #[allow(missing_docs)]
+  pub bone   : usize,
+  #[allow(missing_docs)]
+  pub axis   : Vector3<f32>,
+  /// The bone's angle around `axis` at evenly spaced keyframes, in radians. Clips loop, so the last keyframe leads
+  /// back into the first.
+  pub angles : Vec<f32>,
+}
+
+/// A looping animation.
+#[derive(Debug, Clone)]
+pub struct Clip {
+  /// The length of one loop, in seconds.
+  pub duration : f32,
+  /// Bones without a channel stay in their rest pose.
+  pub channels : Vec<Channel>,
+}
+
+impl Clip {
+  /// The angle of a channel `seconds` into the clip.
+  fn angle(&self, channel: &Channel, seconds: f32) -> f32 {
+    let keyframes = channel.angles.len();
+    if keyframes == 0 {
+      return 0.0
+    }
+    let t = (seconds / self.duration).fract() * keyframes as f32;
+    let i = t.floor() as usize % keyframes;
+    let next = (i + 1) % keyframes;
+    let s = t.fract();
+    channel.angles[i] * (1.0 - s) + channel.angles[next] * s
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub bones : Vec<Bone>,
+  idle      : Clip,
+  walk      : Clip,
+  run       : Clip,
+  attack    : Clip,
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn clip(&self, animation: protocol::Animation) -> &Clip {
+    match animation {
+      protocol::Animation::Idle   => &self.idle,
+      protocol::Animation::Walk   => &self.walk,
+      protocol::Animation::Run    => &self.run,
+      protocol::Animation::Attack => &self.attack,
+    }
+  }
+
+  /// The skinning matrix of each bone, `seconds` into an animation.
+  pub fn pose(&self, animation: protocol::Animation, seconds: f32) -> Vec<Matrix4<f32>> {
+    let clip = self.clip(animation);
+    let mut rotations = vec!(Quaternion::new(1.0, 0.0, 0.0, 0.0); self.bones.len());
+    for channel in &clip.channels {
+      let angle = clip.angle(channel, seconds);
+      rotations[channel.bone] = rotations[channel.bone] * Quaternion::from_axis_angle(channel.axis, Rad(angle));
+    }
+
+    let mut skin: Vec<Matrix4<f32>> = Vec::with_capacity(self.bones.len());
+    for (bone, rotation) in self.bones.iter().zip(rotations.into_iter()) {
+      let joint = bone.joint.to_vec();
+      // Turn around the joint, then follow the parent.
+      let local = Matrix4::from_translation(joint) * Matrix4::from(rotation) * Matrix4::from_translation(-joint);
+      let matrix =
+        match bone.parent {
+          None => local,
+          Some(parent) => skin[parent] * local,
+        };
+      skin.push(matrix);
+    }
+    skin
+  }
+}
+
+/// A rig as it's written in a file.
+#[derive(Debug, Deserialize)]
+struct RigFile {
+  /// Parents first.
+  bones : Vec<BoneFile>,
+  clips : ClipsFile,
+}
+
+#[derive(Debug, Deserialize)]
+struct BoneFile {
+  name   : String,
+  /// Roots have no parent.
+  parent : Option<String>,
+  joint  : [f32; 3],
+  /// The low corner of the bone's part of the mesh.
+  low    : [f32; 3],
+  high   : [f32; 3],
+  #[serde(default)]
+  facing : bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClipsFile {
+  idle   : ClipFile,
+  walk   : ClipFile,
+  run    : ClipFile,
+  attack : ClipFile,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClipFile {
+  duration : f32,
+  #[serde(default)]
+  channels : Vec<ChannelFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelFile {
+  bone   : String,
+  axis   : [f32; 3],
+  angles : Vec<f32>,
+}
+
+fn point(p: [f32; 3]) -> Point3<f32> {
+  Point3::new(p[0], p[1], p[2])
+}
+
+fn parse(contents: &str) -> Result<T, String> {
+  let file: RigFile = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+  let find = |name: &str, bones: &[BoneFile]| bones.iter().position(|bone| bone.name == name);
+
+  let mut bones = Vec::with_capacity(file.bones.len());
+  for (i, bone) in file.bones.iter().enumerate() {
+    let parent =
+      match bone.parent {
+        None => None,
+        Some(ref parent) =>
+          match find(parent, &file.bones[.. i]) {
+            None => return Err(format!("{}'s parent {} isn't listed before it", bone.name, parent)),
+            Some(parent) => Some(parent),
+          },
+      };
+    bones.push(
+      Bone {
+        parent : parent,
+        joint  : point(bone.joint),
+        bounds : Aabb3::new(point(bone.low), point(bone.high)),
+        facing : bone.facing,
+      }
+    );
+  }
+
+  let clip = |clip: &ClipFile| {
+    if !(clip.duration > 0.0) {
+      return Err(format!("A clip lasts {} seconds", clip.duration))
+    }
+    let mut channels = Vec::with_capacity(clip.channels.len());
+    for channel in &clip.channels {
+      let bone =
+        match find(&channel.bone, &file.bones) {
+          None => return Err(format!("There's no bone called {}", channel.bone)),
+          Some(bone) => bone,
+        };
+      let axis = Vector3::new(channel.axis[0], channel.axis[1], channel.axis[2]);
+      if axis.magnitude2() == 0.0 {
+        return Err(format!("{} turns around a zero axis", channel.bone))
+      }
+      channels.push(
+        Channel {
+          bone   : bone,
+          axis   : axis.normalize(),
+          angles : channel.angles.clone(),
+        }
+      );
+    }
+    Ok(Clip {
+      duration : clip.duration,
+      channels : channels,
+    })
+  };
+
+  Ok(T {
+    bones  : bones,
+    idle   : clip(&file.clips.idle)?,
+    walk   : clip(&file.clips.walk)?,
+    run    : clip(&file.clips.run)?,
+    attack : clip(&file.clips.attack)?,
+  })
+}
+
+/// Read a rig from a JSON file.
+pub fn load(path: &str) -> Result<T, String> {
+  let mut contents = String::new();
+  std::fs::File::open(path).and_then(|mut file| file.read_to_string(&mut contents))
+    .map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+  parse(&contents).map_err(|err| format!("Couldn't parse {}: {}", path, err))
+}
+
+#[cfg(test)]
+fn bone(parent: Option<usize>, joint: [f32; 3], low: [f32; 3], high: [f32; 3]) -> Bone {
+  Bone {
+    parent : parent,
+    joint  : Point3::new(joint[0], joint[1], joint[2]),
+    bounds : Aabb3::new(Point3::new(low[0], low[1], low[2]), Point3::new(high[0], high[1], high[2])),
+    facing : false,
+  }
+}
+
+/// Swing bones back and forth around z, i.e. forward and back, the first ones in step and the rest opposite.
+#[cfg(test)]
+fn swing(in_step: &[usize], opposite: &[usize], amplitude: f32) -> Vec<Channel> {
+  let z = Vector3::new(0.0, 0.0, 1.0);
+  let angles = |a: f32| vec!(0.0, a, 0.0, -a);
+  in_step.iter().map(|&bone| Channel { bone: bone, axis: z, angles: angles(amplitude) })
+    .chain(opposite.iter().map(|&bone| Channel { bone: bone, axis: z, angles: angles(-amplitude) }))
+    .collect()
+}
+
+/// A two-legged player: a torso, a head, two arms and two legs. This is what `PLAYER_RIG` should hold.
+#[cfg(test)]
+fn biped() -> T {
+  const TORSO: usize = 0;
+  const RIGHT_ARM: usize = 2;
+  let torso = bone(None, [0.0, -0.05, 0.0], [-0.15, -0.05, -0.25], [0.15, 0.3, 0.25]);
+  let mut head = bone(Some(TORSO), [0.0, 0.3, 0.0], [-0.15, 0.3, -0.15], [0.15, 0.5, 0.15]);
+  head.facing = true;
+  let arm = |z: f32| bone(Some(TORSO), [0.0, 0.28, z], [-0.08, -0.05, z - 0.07], [0.08, 0.3, z + 0.07]);
+  let leg = |z: f32| bone(Some(TORSO), [0.0, -0.05, z], [-0.1, -0.5, z - 0.1], [0.1, -0.05, z + 0.1]);
+  // +z is to the right of +x.
+  let bones = vec!(torso, head, arm(0.32), arm(-0.32), leg(0.13), leg(-0.13));
+  // Each arm swings with the opposite leg.
+  let (right_arm_left_leg, left_arm_right_leg) = ([RIGHT_ARM, 5], [3, 4]);
+  let z = Vector3::new(0.0, 0.0, 1.0);
+
+  T {
+    bones  : bones,
+    idle   : Clip {
+      duration : 4.0,
+      channels : swing(&[RIGHT_ARM], &[3], 0.05),
+    },
+    walk   : Clip {
+      duration : 1.0,
+      channels : swing(&right_arm_left_leg, &left_arm_right_leg, 0.6),
+    },
+    run    : Clip {
+      duration : 0.6,
+      channels : swing(&right_arm_left_leg, &left_arm_right_leg, 0.9),
+    },
+    attack : Clip {
+      duration : 1.0 / 3.0,
+      channels : vec!(Channel { bone: RIGHT_ARM, axis: z, angles: vec!(0.0, 2.0, 1.0) }),
+    },
+  }
+}
+
+#[test]
+fn attacks_swing_the_arm_forward() {
+  use cgmath::Vector4;
+
+  let rig = biped();
+  let pose = rig.pose(protocol::Animation::Attack, 1.0 / 12.0);
+  // The torso isn't animated, but the hand is swung out in front of the shoulder.
+  assert_eq!(pose[0], Matrix4::from_scale(1.0));
+  let hand = pose[2] * Vector4::new(0.0, -0.05, 0.32, 1.0);
+  assert!(hand.x > 0.2);
+  assert!((hand.z - 0.32).abs() < 1e-5);
+
+  // Bones rest at the start of every clip.
+  for matrix in &rig.pose(protocol::Animation::Walk, 0.0) {
+    assert_eq!(*matrix, Matrix4::from_scale(1.0));
+  }
+}
+
+#[test]
+fn keyframes_are_interpolated_and_loop() {
+  let channel = Channel { bone: 0, axis: Vector3::new(0.0, 0.0, 1.0), angles: vec!(0.0, 1.0, 0.0, -1.0) };
+  let clip = Clip { duration: 4.0, channels: vec!() };
+  assert_eq!(clip.angle(&channel, 0.5), 0.5);
+  assert_eq!(clip.angle(&channel, 3.5), -0.5);
+  assert_eq!(clip.angle(&channel, 5.0), 1.0);
+}
+
+#[test]
+fn the_player_rig_file_matches_the_fixture() {
+  let loaded = parse(include_str!("../../rigs/biped.json")).unwrap();
+  let fixture = biped();
+  assert_eq!(loaded.bones.len(), fixture.bones.len());
+  for (loaded, fixture) in loaded.bones.iter().zip(fixture.bones.iter()) {
+    assert_eq!(loaded.parent, fixture.parent);
+    assert_eq!(loaded.facing, fixture.facing);
+    assert!((loaded.joint - fixture.joint).magnitude() < 1e-5);
+    assert!((loaded.bounds.min - fixture.bounds.min).magnitude() < 1e-5);
+    assert!((loaded.bounds.max - fixture.bounds.max).magnitude() < 1e-5);
+  }
+
+  let animations =
+    [protocol::Animation::Idle, protocol::Animation::Walk, protocol::Animation::Run, protocol::Animation::Attack];
+  for &animation in &animations {
+    for &seconds in &[0.0, 0.1, 0.25, 0.7, 1.9] {
+      for (loaded, fixture) in loaded.pose(animation, seconds).iter().zip(fixture.pose(animation, seconds).iter()) {
+        for column in 0 .. 4 {
+          assert!((loaded[column] - fixture[column]).magnitude() < 1e-4, "{:?} at {}s", animation, seconds);
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn the_mob_rig_file_loads() {
+  let rig = parse(include_str!("../../rigs/quadruped.json")).unwrap();
+  assert_eq!(rig.bones.len(), 6);
+  assert!(rig.bones.iter().skip(1).all(|bone| bone.parent == Some(0)));
+  assert_eq!(rig.bones.iter().filter(|bone| bone.facing).count(), 1);
+}
+
+#[test]
+fn broken_rigs_are_rejected() {
+  let rig = |bones: &str, channel_bone: &str| {
+    let clip =
+      format!(r#"{{"duration": 1.0, "channels": [{{"bone": "{}", "axis": [0, 0, 1], "angles": [0]}}]}}"#, channel_bone);
+    format!(
+      r#"{{"bones": [{}], "clips": {{"idle": {}, "walk": {}, "run": {}, "attack": {}}}}}"#,
+      bones, clip, clip, clip, clip,
+    )
+  };
+  let root = r#"{"name": "root", "joint": [0, 0, 0], "low": [0, 0, 0], "high": [1, 1, 1]}"#;
+  let child = r#"{"name": "child", "parent": "root", "joint": [0, 0, 0], "low": [0, 0, 0], "high": [1, 1, 1]}"#;
+
+  assert!(parse(&rig(&format!("{}, {}", root, child), "child")).is_ok());
+  // Parents come first.
+  assert!(parse(&rig(&format!("{}, {}", child, root), "child")).is_err());
+  assert!(parse(&rig(root, "tail")).is_err());
+  assert!(parse("{}").is_err());
+}
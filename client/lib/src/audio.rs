@@ -1,23 +1,63 @@
 //! own a set of playing audio tracks and combine them into a single buffer
 
+use cgmath::{Point3, Vector3, InnerSpace};
 use std;
 use std::sync::atomic;
 
+/// where sounds are heard from
+#[derive(Debug, Clone, Copy)]
+pub struct Listener {
+  #[allow(missing_docs)]
+  pub position: Point3<f32>,
+  /// unit vector pointing out of the listener's right ear
+  pub right: Vector3<f32>,
+}
+
+impl Listener {
+  /// Face the listener along `forward`. If `forward` is straight up or down, the listener's ears don't move.
+  pub fn face(&mut self, forward: &Vector3<f32>) {
+    let right = forward.cross(Vector3::new(0.0, 1.0, 0.0));
+    if right.magnitude2() > 0.0 {
+      self.right = right.normalize();
+    }
+  }
+
+  /// The gains of the left and right channels for a sound at `source`.
+  /// Sounds are at full volume within `reference_distance`, and fall off inversely with distance past that.
+  pub fn stereo_gains(&self, source: &Point3<f32>, reference_distance: f32) -> (f32, f32) {
+    let to_source = source - self.position;
+    let distance = to_source.magnitude();
+    let attenuation = reference_distance / f32::max(reference_distance, distance);
+    // How far to the right the sound is, in [-1, 1]. Sounds right on top of the listener are centered.
+    let pan =
+      if distance > 0.0 {
+        to_source.dot(self.right) / distance
+      } else {
+        0.0
+      };
+    (attenuation * f32::min(1.0, 1.0 - pan), attenuation * f32::min(1.0, 1.0 + pan))
+  }
+}
+
 /// one independent audio track
 pub struct Track {
   // TODO: Make this a ref.
   data: Vec<f32>,
   idx: usize,
   repeat: bool,
+  position: Point3<f32>,
+  reference_distance: f32,
 }
 
 impl Track {
-  #[allow(missing_docs)]
-  pub fn new(data: Vec<f32>, repeat: bool) -> Self {
+  /// `data` is interleaved stereo. The track is heard from `position`, at full volume within `reference_distance`.
+  pub fn new(data: Vec<f32>, repeat: bool, position: Point3<f32>, reference_distance: f32) -> Self {
     Track {
       data: data,
       idx: 0,
       repeat: repeat,
+      position: position,
+      reference_distance: reference_distance,
     }
   }
 }
@@ -50,7 +90,9 @@ impl Iterator for Track {
 pub struct TracksPlaying {
   tracks: Vec<Track>,
   ready: atomic::AtomicBool,
+  /// interleaved stereo
   buffer: Vec<f32>,
+  listener: Listener,
 }
 
 unsafe impl Sync for TracksPlaying {}
@@ -62,9 +104,20 @@ impl TracksPlaying {
       tracks: Vec::new(),
       ready: atomic::AtomicBool::new(false),
       buffer: std::iter::repeat(0.0).take(buffer_len).collect(),
+      listener:
+        Listener {
+          position: Point3::new(0.0, 0.0, 0.0),
+          right: Vector3::new(1.0, 0.0, 0.0),
+        },
     }
   }
 
+  /// move where the tracks are heard from
+  pub fn move_listener(&mut self, position: Point3<f32>, forward: &Vector3<f32>) {
+    self.listener.position = position;
+    self.listener.face(forward);
+  }
+
   /// add a new independently playing audio track
   pub fn push(&mut self, t: Track) {
     self.tracks.push(t);
@@ -81,10 +134,12 @@ impl TracksPlaying {
     }
 
     for track in &mut self.tracks {
-      for buffer in &mut self.buffer {
+      let (left, right) = self.listener.stereo_gains(&track.position, track.reference_distance);
+      for (i, buffer) in self.buffer.iter_mut().enumerate() {
+        let gain = if i % 2 == 0 { left } else { right };
         match track.next() {
           None => break,
-          Some(x) => *buffer = *buffer + x,
+          Some(x) => *buffer = *buffer + gain * x,
         }
       }
     }
@@ -111,3 +166,16 @@ impl TracksPlaying {
     }
   }
 }
+
+#[test]
+fn sounds_pan_and_fade() {
+  let listener =
+    Listener {
+      position: Point3::new(0.0, 0.0, 0.0),
+      right: Vector3::new(1.0, 0.0, 0.0),
+    };
+  assert_eq!(listener.stereo_gains(&Point3::new(0.0, 0.0, -1.0), 4.0), (1.0, 1.0));
+  assert_eq!(listener.stereo_gains(&Point3::new(2.0, 0.0, 0.0), 4.0), (0.0, 1.0));
+  assert_eq!(listener.stereo_gains(&Point3::new(-8.0, 0.0, 0.0), 4.0), (0.5, 0.0));
+  assert_eq!(listener.stereo_gains(&Point3::new(0.0, 0.0, 0.0), 4.0), (1.0, 1.0));
+}
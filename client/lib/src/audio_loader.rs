@@ -11,6 +11,12 @@ pub enum SoundId {
   Rainforest,
   #[allow(missing_docs)]
   Footstep(u8),
+  #[allow(missing_docs)]
+  Dig,
+  /// walking through grass
+  GrassRustle,
+  #[allow(missing_docs)]
+  Explosion,
 }
 
 impl SoundId {
@@ -19,6 +25,20 @@ impl SoundId {
     match *self {
       SoundId::Rainforest    => "sounds/rainforest_ambience-GlorySunz-1938133500.wav".to_owned(),
       SoundId::Footstep(idx) => format!("sounds/Walking_On_Gravel-SoundBible{}.wav", idx),
+      SoundId::Dig           => "sounds/Walking_On_Gravel-SoundBible.wav".to_owned(),
+      SoundId::GrassRustle   => "sounds/grass_rustle.wav".to_owned(),
+      SoundId::Explosion     => "sounds/explosion.wav".to_owned(),
+    }
+  }
+
+  /// How close the listener has to be to hear this sound at full volume.
+  pub fn reference_distance(&self) -> f32 {
+    match *self {
+      SoundId::Rainforest  => 64.0,
+      SoundId::Footstep(_) => 4.0,
+      SoundId::Dig         => 8.0,
+      SoundId::GrassRustle => 4.0,
+      SoundId::Explosion   => 32.0,
     }
   }
 }
@@ -1,5 +1,6 @@
 //! thread for playing audio messages
 
+use cgmath::{Point3, Vector3};
 #[cfg(feature="audio")]
 use portaudio;
 use std::sync::{Mutex};
@@ -9,20 +10,33 @@ use audio_loader;
 
 #[allow(missing_docs)]
 pub enum Message {
-  /// play a sound in a loop
-  PlayLoop(audio_loader::SoundId),
-  /// play a sound once
-  PlayOneShot(audio_loader::SoundId),
+  /// play a sound in a loop, from a position in the world
+  PlayLoop(audio_loader::SoundId, Point3<f32>),
+  /// play a sound once, from a position in the world
+  PlayOneShot(audio_loader::SoundId, Point3<f32>),
+  /// move where sounds are heard from
+  MoveListener {
+    #[allow(missing_docs)]
+    position : Point3<f32>,
+    /// the direction the listener is facing
+    forward  : Vector3<f32>,
+  },
 }
 
 #[allow(missing_docs)]
 #[cfg(not(feature="audio"))]
 pub fn audio_thread<RecvMessage>(
-  _quit: &Mutex<bool>,
-  _recv_message: &mut RecvMessage,
+  quit: &Mutex<bool>,
+  recv_message: &mut RecvMessage,
 ) where
   RecvMessage: FnMut() -> Option<Message>,
-{}
+{
+  // Nothing gets played, but don't let messages pile up.
+  while !*quit.lock().unwrap() {
+    while recv_message().is_some() {}
+    ::std::thread::sleep(::std::time::Duration::from_millis(10));
+  }
+}
 
 #[allow(missing_docs)]
 #[cfg(feature="audio")]
@@ -67,11 +81,14 @@ pub fn audio_thread<RecvMessage>(
   while !*quit.lock().unwrap() && stream.is_active() == Ok(true) {
     if let Some(up) = recv_message() {
       match up {
-        Message::PlayLoop(id) => {
-          tracks_playing.push(audio::Track::new(audio_loader.load(id).clone(), true))
+        Message::PlayLoop(id, position) => {
+          tracks_playing.push(audio::Track::new(audio_loader.load(id).clone(), true, position, id.reference_distance()))
+        },
+        Message::PlayOneShot(id, position) => {
+          tracks_playing.push(audio::Track::new(audio_loader.load(id).clone(), false, position, id.reference_distance()))
         },
-        Message::PlayOneShot(id) => {
-          tracks_playing.push(audio::Track::new(audio_loader.load(id).clone(), false))
+        Message::MoveListener { position, forward } => {
+          tracks_playing.move_listener(position, &forward);
         },
       }
     } else {
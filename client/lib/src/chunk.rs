@@ -1,10 +1,22 @@
 //! Position data structure for terrain blocks.
 
+use cgmath::Vector3;
+
 /// lg(WIDTH)
 pub const LG_WIDTH: u16 = 3;
 /// The width of a chunk of terrain.
 pub const WIDTH: u32 = 1 << LG_WIDTH;
 
+/// The directions to a chunk's six face neighbors: -x, +x, -y, +y, -z, +z.
+pub const FACES: [Vector3<i32>; 6] = [
+  Vector3 { x: -1, y:  0, z:  0 },
+  Vector3 { x:  1, y:  0, z:  0 },
+  Vector3 { x:  0, y: -1, z:  0 },
+  Vector3 { x:  0, y:  1, z:  0 },
+  Vector3 { x:  0, y:  0, z: -1 },
+  Vector3 { x:  0, y:  0, z:  1 },
+];
+
 #[allow(missing_docs)]
 pub mod position {
   use cgmath::{Point3, Vector3};
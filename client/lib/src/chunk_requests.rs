@@ -0,0 +1,164 @@
+//! Chunks this client is waiting on: ones that still need to be requested, in order of how soon they'll be seen,
+//! and ones the server is still working on, so those can be cancelled if they're no longer wanted.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use common::fnv_map;
+use common::surroundings_loader;
+
+use chunk;
+use lod;
+
+/// How many chunks may be waiting to be requested. Beyond this, new chunks wait to be noticed by the
+/// surroundings loader, which finds them in order of distance anyway.
+pub const MAX_QUEUED: usize = 64;
+
+/// Chunks outside the player's view are treated as this many times further away.
+const OUT_OF_VIEW_PENALTY: u32 = 3;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Chunks to request, and the LOD to request them at.
+  queued             : fnv_map::T<chunk::position::T, lod::T>,
+  /// Requests the server hasn't answered yet, by the time they were made.
+  outstanding        : fnv_map::T<u64, chunk::position::T>,
+  /// The latest outstanding request for each chunk, and the LOD it was for.
+  outstanding_chunks : fnv_map::T<chunk::position::T, (u64, lod::T)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    queued             : fnv_map::new(),
+    outstanding        : fnv_map::new(),
+    outstanding_chunks : fnv_map::new(),
+  }
+}
+
+/// How urgently a chunk is needed; lower is sooner. Chunks are ordered by distance from `center`,
+/// but the ones within `fov` radians of the way the player faces come first.
+fn priority(
+  center   : &Point3<i32>,
+  forward  : &Vector3<f32>,
+  fov      : f32,
+  chunk    : &Point3<i32>,
+) -> u32 {
+  let distance = surroundings_loader::distance_between(center, chunk);
+  if distance <= 1 {
+    return distance
+  }
+  let to_chunk = Vector3::new((chunk.x - center.x) as f32, (chunk.y - center.y) as f32, (chunk.z - center.z) as f32);
+  let in_view = to_chunk.normalize().dot(*forward) >= fov.cos();
+  if in_view {
+    distance
+  } else {
+    distance * OUT_OF_VIEW_PENALTY
+  }
+}
+
+impl T {
+  /// Request a chunk at some LOD once there's room.
+  pub fn enqueue(&mut self, position: chunk::position::T, lod: lod::T) {
+    if self.outstanding_chunks.get(&position).map(|&(_, requested)| requested) == Some(lod) {
+      return
+    }
+    self.queued.insert(position, lod);
+  }
+
+  #[allow(missing_docs)]
+  pub fn queued_len(&self) -> usize {
+    self.queued.len()
+  }
+
+  #[allow(missing_docs)]
+  pub fn outstanding_len(&self) -> usize {
+    self.outstanding.len()
+  }
+
+  /// Take the most urgent chunk to request. See `priority`.
+  pub fn pop(
+    &mut self,
+    center  : &Point3<i32>,
+    forward : &Vector3<f32>,
+    fov     : f32,
+  ) -> Option<(chunk::position::T, lod::T)> {
+    let next =
+      self.queued.iter()
+      .min_by_key(|&(position, _)| priority(center, forward, fov, position.as_pnt()))
+      .map(|(&position, &lod)| (position, lod));
+    if let Some((position, _)) = next {
+      self.queued.remove(&position);
+    }
+    next
+  }
+
+  /// Note that a chunk was requested from the server at `time_requested_ns`.
+  pub fn sent(&mut self, position: chunk::position::T, lod: lod::T, time_requested_ns: u64) {
+    self.outstanding.insert(time_requested_ns, position);
+    self.outstanding_chunks.insert(position, (time_requested_ns, lod));
+  }
+
+  /// Note that the server answered the request made at `time_requested_ns`.
+  /// Returns false if the request wasn't outstanding, e.g. because it was cancelled.
+  pub fn received(&mut self, time_requested_ns: u64) -> bool {
+    match self.outstanding.remove(&time_requested_ns) {
+      None => false,
+      Some(position) => {
+        if self.outstanding_chunks.get(&position).map(|&(at, _)| at) == Some(time_requested_ns) {
+          self.outstanding_chunks.remove(&position);
+        }
+        true
+      },
+    }
+  }
+
+  /// Stop waiting on every chunk, e.g. because the player's somewhere else now. Returns the times of the requests
+  /// that were made, so they can be cancelled.
+  pub fn clear(&mut self) -> Vec<u64> {
+    self.queued.clear();
+    self.outstanding_chunks.clear();
+    self.outstanding.drain().map(|(time_requested_ns, _)| time_requested_ns).collect()
+  }
+
+  /// Stop waiting on a chunk. If it's been requested, returns the time of the request, so it can be cancelled.
+  pub fn forget(&mut self, position: &chunk::position::T) -> Option<u64> {
+    self.queued.remove(position);
+    let (time_requested_ns, _) = self.outstanding_chunks.remove(position)?;
+    self.outstanding.remove(&time_requested_ns);
+    Some(time_requested_ns)
+  }
+}
+
+#[test]
+fn chunks_in_view_come_first() {
+  let mut requests = new();
+  let center = Point3::new(0, 0, 0);
+  let forward = Vector3::new(0.0, 0.0, -1.0);
+  let fov = 1.0;
+  requests.enqueue(chunk::position::new(0, 0, 6), lod::T(1));
+  requests.enqueue(chunk::position::new(0, 0, -8), lod::T(1));
+  requests.enqueue(chunk::position::new(1, 0, 0), lod::T(0));
+
+  assert_eq!(requests.pop(&center, &forward, fov), Some((chunk::position::new(1, 0, 0), lod::T(0))));
+  assert_eq!(requests.pop(&center, &forward, fov), Some((chunk::position::new(0, 0, -8), lod::T(1))));
+  assert_eq!(requests.pop(&center, &forward, fov), Some((chunk::position::new(0, 0, 6), lod::T(1))));
+  assert_eq!(requests.pop(&center, &forward, fov), None);
+}
+
+#[test]
+fn cancelled_requests_are_not_received() {
+  let mut requests = new();
+  let position = chunk::position::new(3, 0, 0);
+  requests.sent(position, lod::T(0), 100);
+  // Already on its way.
+  requests.enqueue(position, lod::T(0));
+  assert_eq!(requests.queued_len(), 0);
+
+  assert_eq!(requests.forget(&position), Some(100));
+  assert_eq!(requests.outstanding_len(), 0);
+  assert!(!requests.received(100));
+
+  requests.sent(position, lod::T(0), 200);
+  assert!(requests.received(200));
+  assert_eq!(requests.forget(&position), None);
+}
@@ -6,29 +6,53 @@ use rand;
 use rand::{Rng, SeedableRng};
 use std::sync::Mutex;
 
+use common::fnv_map;
+use common::health;
 use common::id_allocator;
+use common::journal;
 use common::protocol;
+use common::schematic;
 use common::surroundings_loader;
 
+use chunk_requests;
+use clock_sync;
+use config;
 use lod;
+use prediction;
+use render_distance;
+use replication;
 use terrain;
+use timings;
 use view;
 
-// TODO: Remove this once our RAM usage doesn't skyrocket with load distance.
-const MAX_LOAD_DISTANCE: u32 = 80;
-
 /// The main client state.
 pub struct T {
   #[allow(missing_docs)]
   pub id                       : protocol::ClientId,
+  /// the token to resume this client's session with, if the connection drops
+  pub session                  : protocol::SessionToken,
+  /// the seed the server generated the world from
+  pub seed                     : u32,
+  /// the world the player is in
+  pub world                    : Mutex<protocol::WorldId>,
   /// id for the player in vram
   pub player_id                : view::entity::id::Player,
   /// position of the player in world coordinates
   pub player_position          : Mutex<Point3<f32>>,
+  /// where we think the player is going, ahead of the server
+  pub prediction               : Mutex<prediction::T>,
+  /// the player's health, as of the last snapshot
+  pub health                   : Mutex<health::T>,
+  /// the entity snapshots received from the server
+  pub replication              : Mutex<replication::T>,
+  /// everyone's names, including this client's player's
+  pub player_names             : Mutex<fnv_map::T<view::entity::id::Player, String>>,
   /// the location where we last played a footstep sound
   pub last_footstep            : Mutex<Point3<f32>>,
   /// world position to center terrain loading around
   pub load_position            : Mutex<Option<Point3<f32>>>,
+  /// where the camera is while it's detached from the player
+  pub spectator_position       : Mutex<Option<Point3<f32>>>,
   #[allow(missing_docs)]
   pub terrain_allocator        : Mutex<id_allocator::T<view::entity::id::Terrain>>,
   #[allow(missing_docs)]
@@ -39,19 +63,33 @@ pub struct T {
   pub max_load_distance        : u32,
   #[allow(missing_docs)]
   pub terrain                  : Mutex<terrain::T>,
-  /// The number of terrain requests that are outstanding,
-  pub pending_terrain_requests : Mutex<u32>,
+  /// terrain edits the server's sent, to put back on top of voxels that were loaded before them
+  pub edits                    : Mutex<journal::T>,
+  /// the voxels last copied, to paste somewhere else
+  pub clipboard                : Mutex<Option<schematic::T>>,
+  /// how far away to load terrain, shrunk when frames take too long
+  pub render_distance          : Mutex<render_distance::T>,
+  /// chunks waiting to be requested from the server, and requests it hasn't answered yet
+  pub chunk_requests           : Mutex<chunk_requests::T>,
+  /// how long the update and view threads' main jobs are taking
+  pub timings                  : Mutex<timings::T>,
+  /// how long the server's ticks are taking, as of its last report
+  pub server_tick              : Mutex<Option<protocol::TickStats>>,
+  /// the round trip to the server, and how far its clock is from ours
+  pub clock_sync               : Mutex<clock_sync::T>,
   #[allow(missing_docs)]
   pub rng                      : Mutex<rand::XorShiftRng>,
+  #[allow(missing_docs)]
+  pub config                   : config::T,
 }
 
-fn load_distance(mut polygon_budget: i32) -> u32 {
+fn load_distance(thresholds: &[u32], mut polygon_budget: i32) -> u32 {
   // TODO: This should try to account for VRAM not used on a per-poly basis.
 
   let mut load_distance = 0;
   let mut prev_threshold = 0;
   let mut prev_square = 0;
-  for (i, &threshold) in lod::THRESHOLDS.iter().enumerate() {
+  for (i, &threshold) in thresholds.iter().enumerate() {
     let quality = lod::T(i as u32).edge_samples() as i32;
     let polygons_per_chunk = quality * quality * 4;
     for i in num::iter::range_inclusive(prev_threshold, threshold) {
@@ -91,7 +129,14 @@ fn load_distance(mut polygon_budget: i32) -> u32 {
 }
 
 #[allow(missing_docs)]
-pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, position: Point3<f32>) -> T {
+pub fn new(
+  client_id : protocol::ClientId,
+  session   : protocol::SessionToken,
+  player_id : view::entity::id::Player,
+  position  : Point3<f32>,
+  seed      : u32,
+  config    : config::T,
+) -> T {
   let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
   let s1 = rng.next_u32();
   let s2 = rng.next_u32();
@@ -99,11 +144,12 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
   let s4 = rng.next_u32();
   rng.reseed([s1, s2, s3, s4]);
 
-  let mut load_distance = load_distance(view::terrain_buffers::POLYGON_BUDGET as i32);
+  let polygon_budget = view::terrain_buffers::polygon_budget(config.vram_budget());
+  let mut load_distance = load_distance(&config.lod_thresholds, polygon_budget as i32);
 
-  if load_distance > MAX_LOAD_DISTANCE {
-    info!("load_distance {} capped at {}", load_distance, MAX_LOAD_DISTANCE);
-    load_distance = MAX_LOAD_DISTANCE;
+  if load_distance > config.max_load_distance {
+    info!("load_distance {} capped at {}", load_distance, config.max_load_distance);
+    load_distance = config.max_load_distance;
   } else {
     info!("load_distance {}", load_distance);
   }
@@ -111,22 +157,45 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
   let surroundings_loader = {
     surroundings_loader::new(
       load_distance,
-      lod::THRESHOLDS.iter().map(|&x| x as i32).collect(),
+      config.lod_thresholds.iter().map(|&x| x as i32).collect(),
     )
   };
 
   T {
     id                       : client_id,
+    session                  : session,
+    seed                     : seed,
+    world                    : Mutex::new(protocol::WorldId(0)),
     player_id                : player_id,
     player_position          : Mutex::new(position),
+    prediction               : Mutex::new(prediction::new(position)),
+    health                   : Mutex::new(health::full(health::PLAYER_MAX)),
+    replication              : Mutex::new(replication::new()),
+    player_names             : Mutex::new(fnv_map::new()),
     last_footstep            : Mutex::new(position),
     load_position            : Mutex::new(None),
+    spectator_position       : Mutex::new(None),
     terrain_allocator        : Mutex::new(id_allocator::new()),
     grass_allocator          : Mutex::new(id_allocator::new()),
     surroundings_loader      : Mutex::new(surroundings_loader),
     max_load_distance        : load_distance,
-    terrain                  : Mutex::new(terrain::new(load_distance as u32)),
-    pending_terrain_requests : Mutex::new(0),
+    terrain                  :
+      Mutex::new(
+        terrain::new(
+          load_distance as u32,
+          config.lod_thresholds,
+          view::terrain_buffers::chunk_budget(config.vram_budget()),
+          config.meshing_threads,
+        )
+      ),
+    edits                    : Mutex::new(journal::new()),
+    clipboard                : Mutex::new(None),
+    render_distance          : Mutex::new(render_distance::new(load_distance, config.lod_thresholds, config.target_fps)),
+    chunk_requests           : Mutex::new(chunk_requests::new()),
+    timings                  : Mutex::new(timings::new()),
+    server_tick              : Mutex::new(None),
+    clock_sync               : Mutex::new(clock_sync::new()),
     rng                      : Mutex::new(rng),
+    config                   : config,
   }
 }
@@ -0,0 +1,71 @@
+//! Schematic files the clipboard can be saved to and loaded from, so builds can be shared. Copying and pasting
+//! themselves go through the server, which has the voxels.
+
+use std;
+
+use common::schematic;
+
+/// Where schematics are saved, relative to the working directory. They're also usable as prefabs on a server.
+pub const DIRECTORY: &'static str = "schematics";
+
+/// Something to do with schematic files, typed into chat.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  /// Save the clipboard to a file by this name.
+  Save(String),
+  /// Load the clipboard from a file by this name.
+  Load(String),
+}
+
+/// How to use the schematic commands.
+pub const USAGE: &'static str = "Usage: /schematic save <name> or /schematic load <name>";
+
+/// Parse a chat message as a schematic command. Returns `None` if the message isn't one, so it can go to the server
+/// as usual, and `Some(Err(..))` if it's a schematic command that doesn't make sense.
+pub fn parse_command(text: &str) -> Option<Result<Command, String>> {
+  let mut words = text.trim().splitn(3, ' ');
+  if words.next() != Some("/schematic") {
+    return None
+  }
+  let name = |name: Option<&str>| {
+    match name.map(|name| name.trim()) {
+      None | Some("") => Err(String::from(USAGE)),
+      // Names become file names, so they can't go anywhere but `DIRECTORY`.
+      Some(name) if !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') =>
+        Err(String::from("Schematic names can only have letters, numbers, - and _.")),
+      Some(name) => Ok(String::from(name)),
+    }
+  };
+  let command =
+    match words.next() {
+      Some("save") => name(words.next()).map(Command::Save),
+      Some("load") => name(words.next()).map(Command::Load),
+      _ => Err(String::from(USAGE)),
+    };
+  Some(command)
+}
+
+fn path(name: &str) -> std::path::PathBuf {
+  std::path::Path::new(DIRECTORY).join(format!("{}.schematic", name))
+}
+
+/// Save a schematic to the file for `name`.
+pub fn save(schematic: &schematic::T, name: &str) -> Result<(), String> {
+  std::fs::create_dir_all(DIRECTORY).map_err(|err| format!("Couldn't create {}: {}", DIRECTORY, err))?;
+  schematic.save(&path(name))
+}
+
+/// Load the schematic saved for `name`.
+pub fn load(name: &str) -> Result<schematic::T, String> {
+  schematic::load(&path(name))
+}
+
+#[test]
+fn schematic_commands_are_parsed() {
+  assert_eq!(parse_command("hello"), None);
+  assert_eq!(parse_command("/schematic save tower_2"), Some(Ok(Command::Save(String::from("tower_2")))));
+  assert_eq!(parse_command("/schematic load tower_2"), Some(Ok(Command::Load(String::from("tower_2")))));
+  assert!(parse_command("/schematic save").unwrap().is_err());
+  assert!(parse_command("/schematic load ../server").unwrap().is_err());
+  assert!(parse_command("/schematic").unwrap().is_err());
+}
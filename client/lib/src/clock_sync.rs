@@ -0,0 +1,86 @@
+//! How long messages take to get to the server and back, and how far its clock is from ours, measured with pings.
+//! The server answers a ping with its time, which was its time about halfway through the round trip. Messages queued
+//! up along the way make that less true, so the clocks are compared using the recent ping with the fastest round trip.
+
+use std::collections::VecDeque;
+
+/// How often to ping the server, in ns.
+const PING_INTERVAL_NS: u64 = 1_000_000_000;
+/// How many of the most recent pings to go by.
+const SAMPLES: usize = 8;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The round trip each recent ping took, and how far ahead of ours it found the server's clock, in ns; oldest
+  /// first.
+  samples   : VecDeque<(u64, i64)>,
+  /// When the last ping was sent.
+  last_ping : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    samples   : VecDeque::new(),
+    last_ping : None,
+  }
+}
+
+impl T {
+  /// Whether it's time to ping the server again. If it is, the ping's taken to be sent at `now_ns`.
+  pub fn should_ping(&mut self, now_ns: u64) -> bool {
+    if self.last_ping.map_or(false, |last_ping| now_ns < last_ping + PING_INTERVAL_NS) {
+      return false
+    }
+    self.last_ping = Some(now_ns);
+    true
+  }
+
+  /// The server answered a ping we sent at `client_ns`, at `server_ns` on its clock, and the answer got back at
+  /// `now_ns`.
+  pub fn pong(&mut self, client_ns: u64, server_ns: u64, now_ns: u64) {
+    let rtt = now_ns.saturating_sub(client_ns);
+    let halfway = client_ns + rtt / 2;
+    self.samples.push_back((rtt, server_ns as i64 - halfway as i64));
+    while self.samples.len() > SAMPLES {
+      self.samples.pop_front();
+    }
+  }
+
+  /// The average round trip of the recent pings, in ns.
+  pub fn rtt_ns(&self) -> Option<u64> {
+    if self.samples.is_empty() {
+      return None
+    }
+    Some(self.samples.iter().map(|&(rtt, _)| rtt).sum::<u64>() / self.samples.len() as u64)
+  }
+
+  /// How far ahead of ours the server's clock is, in ns.
+  pub fn offset_ns(&self) -> Option<i64> {
+    self.samples.iter().min_by_key(|&&(rtt, _)| rtt).map(|&(_, offset)| offset)
+  }
+
+  /// The server's time when ours is `now_ns`, once a ping's been answered.
+  pub fn server_ns(&self, now_ns: u64) -> Option<u64> {
+    self.offset_ns().map(|offset| (now_ns as i64 + offset) as u64)
+  }
+}
+
+#[test]
+fn the_fastest_round_trip_sets_the_offset() {
+  let mut clock_sync = new();
+  assert!(clock_sync.should_ping(0));
+  assert!(!clock_sync.should_ping(PING_INTERVAL_NS / 2));
+  assert_eq!(clock_sync.server_ns(0), None);
+
+  // The server's clock is 5000ns ahead. The first answer took 100ns each way.
+  clock_sync.pong(0, 5100, 200);
+  assert_eq!(clock_sync.offset_ns(), Some(5000));
+  // The second ping sat in a queue on the way there, so the server seems further ahead than it is.
+  clock_sync.pong(1000, 6900, 1400);
+  assert_eq!(clock_sync.offset_ns(), Some(5000));
+  assert_eq!(clock_sync.rtt_ns(), Some(300));
+  assert_eq!(clock_sync.server_ns(2000), Some(7000));
+
+  assert!(clock_sync.should_ping(PING_INTERVAL_NS));
+}
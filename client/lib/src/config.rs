@@ -0,0 +1,238 @@
+//! Client settings, read from a TOML file at startup.
+
+use sdl2::keyboard::Keycode;
+use serde;
+use serde::Deserialize;
+use std;
+use std::io::Read;
+use toml;
+
+use lod;
+
+/// Where the settings are read from, relative to the working directory.
+pub const PATH: &'static str = "client.toml";
+
+/// Which key does what.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Keys {
+  #[serde(deserialize_with = "key")] pub forward        : Keycode,
+  #[serde(deserialize_with = "key")] pub back           : Keycode,
+  #[serde(deserialize_with = "key")] pub left           : Keycode,
+  #[serde(deserialize_with = "key")] pub right          : Keycode,
+  #[serde(deserialize_with = "key")] pub jump           : Keycode,
+  #[serde(deserialize_with = "key")] pub look_left      : Keycode,
+  #[serde(deserialize_with = "key")] pub look_right     : Keycode,
+  #[serde(deserialize_with = "key")] pub look_up        : Keycode,
+  #[serde(deserialize_with = "key")] pub look_down      : Keycode,
+  #[serde(deserialize_with = "key")] pub toggle_hud     : Keycode,
+  /// Show (or hide) timings, chunk counts and VRAM use.
+  #[serde(deserialize_with = "key")] pub debug_overlay  : Keycode,
+  /// Outline loaded chunks, colored by LOD, while the debug overlay is up.
+  #[serde(deserialize_with = "key")] pub debug_chunks   : Keycode,
+  /// Outline players' and mobs' bounding boxes, while the debug overlay is up.
+  #[serde(deserialize_with = "key")] pub debug_bounds   : Keycode,
+  /// Freeze the view frustum where it is and outline it, or let it go.
+  #[serde(deserialize_with = "key")] pub freeze_frustum : Keycode,
+  #[serde(deserialize_with = "key")] pub shadow_quality : Keycode,
+  #[serde(deserialize_with = "key")] pub tonemapping    : Keycode,
+  /// Make the sun and other bright things glow.
+  #[serde(deserialize_with = "key")] pub bloom          : Keycode,
+  /// Smooth jagged edges.
+  #[serde(deserialize_with = "key")] pub fxaa           : Keycode,
+  /// Switch the look keys between moving the camera and moving the sun.
+  #[serde(deserialize_with = "key")] pub sun_mode       : Keycode,
+  #[serde(deserialize_with = "key")] pub chat           : Keycode,
+  /// Open (or close) the crafting panel. While it's open, the look up and down keys pick a recipe, and the chat key
+  /// crafts it.
+  #[serde(deserialize_with = "key")] pub crafting       : Keycode,
+  #[serde(deserialize_with = "key")] pub drop_item      : Keycode,
+  #[serde(deserialize_with = "key")] pub throw          : Keycode,
+  /// Pick the next terrain brush. With a brush picked, the dig and place buttons use it instead.
+  #[serde(deserialize_with = "key")] pub brush          : Keycode,
+  #[serde(deserialize_with = "key")] pub brush_smaller  : Keycode,
+  #[serde(deserialize_with = "key")] pub brush_larger   : Keycode,
+  /// Undo this player's most recent brush or tree tool edit.
+  #[serde(deserialize_with = "key")] pub undo           : Keycode,
+  #[serde(deserialize_with = "key")] pub redo           : Keycode,
+  /// Mark a corner of the region to copy at the voxel under the crosshair, alternating between the two corners.
+  #[serde(deserialize_with = "key")] pub select         : Keycode,
+  /// Copy the selected region into the clipboard.
+  #[serde(deserialize_with = "key")] pub copy           : Keycode,
+  /// Paste the clipboard on top of the voxel under the crosshair.
+  #[serde(deserialize_with = "key")] pub paste          : Keycode,
+  /// Stop (or resume) loading terrain around the player.
+  #[serde(deserialize_with = "key")] pub freeze_loading : Keycode,
+  /// Switch between looking through the player's eyes and following them from behind.
+  #[serde(deserialize_with = "key")] pub third_person   : Keycode,
+  /// Detach the camera from the player to fly around freely, or put it back.
+  #[serde(deserialize_with = "key")] pub spectate       : Keycode,
+  /// Fly down while spectating; jump flies up.
+  #[serde(deserialize_with = "key")] pub descend        : Keycode,
+  #[serde(deserialize_with = "key")] pub screenshot     : Keycode,
+  /// Start (or stop) taking a screenshot every `timelapse_interval` seconds.
+  #[serde(deserialize_with = "key")] pub timelapse      : Keycode,
+  /// Switch between the minimap in the corner and the full map.
+  #[serde(deserialize_with = "key")] pub map            : Keycode,
+  #[serde(deserialize_with = "key")] pub map_zoom_in    : Keycode,
+  #[serde(deserialize_with = "key")] pub map_zoom_out   : Keycode,
+}
+
+impl Default for Keys {
+  fn default() -> Self {
+    Keys {
+      forward        : Keycode::W,
+      back           : Keycode::S,
+      left           : Keycode::A,
+      right          : Keycode::D,
+      jump           : Keycode::Space,
+      look_left      : Keycode::Left,
+      look_right     : Keycode::Right,
+      look_up        : Keycode::Up,
+      look_down      : Keycode::Down,
+      toggle_hud     : Keycode::H,
+      debug_overlay  : Keycode::F3,
+      debug_chunks   : Keycode::F4,
+      debug_bounds   : Keycode::F6,
+      freeze_frustum : Keycode::F7,
+      shadow_quality : Keycode::O,
+      tonemapping    : Keycode::F8,
+      bloom          : Keycode::F9,
+      fxaa           : Keycode::F10,
+      sun_mode       : Keycode::M,
+      chat           : Keycode::Return,
+      crafting       : Keycode::C,
+      drop_item      : Keycode::Q,
+      throw          : Keycode::F,
+      brush          : Keycode::B,
+      brush_smaller  : Keycode::LeftBracket,
+      brush_larger   : Keycode::RightBracket,
+      undo           : Keycode::Z,
+      redo           : Keycode::Y,
+      select         : Keycode::G,
+      copy           : Keycode::K,
+      paste          : Keycode::L,
+      freeze_loading : Keycode::P,
+      third_person   : Keycode::F5,
+      spectate       : Keycode::V,
+      descend        : Keycode::LShift,
+      screenshot     : Keycode::F12,
+      timelapse      : Keycode::F11,
+      map            : Keycode::N,
+      map_zoom_in    : Keycode::Equals,
+      map_zoom_out   : Keycode::Minus,
+    }
+  }
+}
+
+/// Keys are named the way SDL names them, e.g. "W", "Space" or "Left Shift".
+fn key<'de, D>(deserializer: D) -> Result<Keycode, D::Error> where D: serde::Deserializer<'de> {
+  let name = String::deserialize(deserializer)?;
+  Keycode::from_name(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown key {:?}", name)))
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct T {
+  /// The name shown above this client's player, and next to its chat messages.
+  pub name                  : String,
+  /// The furthest away to load terrain, in chunks.
+  // TODO: Raise the default once our RAM usage doesn't skyrocket with load distance.
+  pub max_load_distance     : u32,
+  /// The chunk distances at which terrain switches to the next LOD.
+  pub lod_thresholds        : [u32; lod::COUNT - 1],
+  /// The most VRAM terrain can take up, in megabytes. Once it's full, the furthest chunks are unloaded to make
+  /// room for nearer ones.
+  pub vram_budget_mb        : u32,
+  /// How many threads mesh terrain. 0 uses one per core.
+  pub meshing_threads       : usize,
+  /// The vertical field of view, in degrees.
+  pub fov_degrees           : f32,
+  /// Light terrain, grass and entities with every nearby point light, sorted into clusters, instead of only the
+  /// few nearest the camera. Terrain also gets a depth prepass.
+  pub clustered_lighting    : bool,
+  pub vsync                 : bool,
+  /// The render distance shrinks when frames take longer than this allows, and grows back when they're
+  /// well within it. 0 keeps it at `max_load_distance`.
+  pub target_fps            : f32,
+  /// Width and height, in pixels.
+  pub window_size           : [u32; 2],
+  /// Scales how far the camera turns when the mouse moves.
+  pub mouse_sensitivity     : f32,
+  /// How far behind the player the third-person camera sits, if there's no terrain in the way.
+  pub third_person_distance : f32,
+  /// Seconds between timelapse screenshots.
+  pub timelapse_interval    : f32,
+  pub keys                  : Keys,
+}
+
+impl Default for T {
+  fn default() -> Self {
+    T {
+      name                  : "Player".to_owned(),
+      max_load_distance     : 80,
+      lod_thresholds        : lod::THRESHOLDS,
+      vram_budget_mb        : 64,
+      meshing_threads       : 0,
+      fov_degrees           : 60.0,
+      clustered_lighting    : false,
+      vsync                 : false,
+      target_fps            : 30.0,
+      window_size           : [800, 600],
+      mouse_sensitivity     : 1.0,
+      third_person_distance : 6.0,
+      timelapse_interval    : 5.0,
+      keys                  : Keys::default(),
+    }
+  }
+}
+
+impl T {
+  /// The vertical field of view, in radians.
+  pub fn fov(&self) -> f32 {
+    self.fov_degrees.to_radians()
+  }
+
+  /// The most VRAM terrain can take up, in bytes.
+  pub fn vram_budget(&self) -> usize {
+    self.vram_budget_mb as usize * 1_000_000
+  }
+}
+
+fn parse(contents: &str) -> Result<T, toml::de::Error> {
+  toml::from_str(contents)
+}
+
+/// Read the settings file. Anything missing from it takes its default value.
+/// If the file is missing or broken, all the defaults are used.
+pub fn load() -> T {
+  let mut contents = String::new();
+  if let Err(err) = std::fs::File::open(PATH).and_then(|mut file| file.read_to_string(&mut contents)) {
+    info!("Couldn't read {}: {}. Using the default settings.", PATH, err);
+    return T::default()
+  }
+
+  match parse(&contents) {
+    Ok(config) => config,
+    Err(err) => {
+      warn!("Couldn't parse {}: {}. Using the default settings.", PATH, err);
+      T::default()
+    },
+  }
+}
+
+#[test]
+fn missing_settings_are_defaulted() {
+  let config = parse("vsync = true\n[keys]\njump = \"Left Shift\"\n").unwrap();
+  assert!(config.vsync);
+  assert_eq!(config.keys.jump, Keycode::LShift);
+  assert_eq!(config.keys.forward, Keycode::W);
+  assert_eq!(config.lod_thresholds, lod::THRESHOLDS);
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+  assert!(parse("[keys]\njump = \"Hyperspace\"\n").is_err());
+}
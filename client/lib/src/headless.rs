@@ -0,0 +1,155 @@
+//! Clients without a window, GL context or audio, for load-testing servers.
+//! Each one wanders around like a player would, requesting terrain as it goes.
+
+use cgmath::{Vector2, Vector3};
+use rand;
+use rand::{Rng, SeedableRng};
+use std;
+use std::f32::consts::PI;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thread_scoped;
+use time;
+
+use common::net;
+use common::protocol;
+
+use client;
+use prediction;
+use process_event;
+use run::connect_client;
+use server;
+use terrain;
+use update_thread::update_thread;
+
+/// How long a headless player walks before turning.
+const TURN_INTERVAL_MS: u64 = 3000;
+/// How long a headless player holds jump for, to get over whatever's in the way.
+const JUMP_MS: u64 = 300;
+
+/// Take `--headless` or `--headless=<count>` out of command-line arguments, and return how many headless clients
+/// to run, if any.
+pub fn from_args(args: &mut Vec<String>) -> Option<u32> {
+  const FLAG: &'static str = "--headless";
+  let mut count = None;
+  args.retain(|arg| {
+    if arg == FLAG {
+      count = Some(1);
+      false
+    } else if arg.starts_with(FLAG) && arg[FLAG.len() ..].starts_with('=') {
+      let n = &arg[FLAG.len() + 1 ..];
+      count = Some(n.parse().unwrap_or_else(|_| panic!("Headless client count must be a number, not {:?}", n)));
+      false
+    } else {
+      true
+    }
+  });
+  count
+}
+
+/// Run `count` headless clients against the server at `server_url` until the process is killed.
+/// Each client listens on its own variation of `listen_url`; see `Transport::nth_url`.
+pub fn run(listen_url: &str, server_url: &str, transport: net::Transport, count: u32) {
+  let chunks_received = std::sync::Arc::new(AtomicUsize::new(0));
+
+  for i in 0 .. count {
+    let listen_url = transport.nth_url(listen_url, i);
+    let server_url = server_url.to_owned();
+    let chunks_received = chunks_received.clone();
+    std::thread::spawn(move || {
+      run_one(i, &listen_url, &server_url, transport, &chunks_received);
+    });
+  }
+
+  let start = time::precise_time_ns();
+  loop {
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    let chunks_received = chunks_received.load(Ordering::Relaxed);
+    let elapsed = (time::precise_time_ns() - start) as f32 / 1e9;
+    info!("{} clients have received {} chunks ({} Hz)", count, chunks_received, chunks_received as f32 / elapsed);
+  }
+}
+
+fn run_one(
+  index           : u32,
+  listen_url      : &str,
+  server_url      : &str,
+  transport       : net::Transport,
+  chunks_received : &AtomicUsize,
+) {
+  let quit = Mutex::new(false);
+  let server =
+    match server::new(server_url, listen_url, transport) {
+      Ok(server) => server,
+      Err(err) => {
+        error!("Headless client {} couldn't listen on {}: {}", index, listen_url, err);
+        return
+      },
+    };
+  let client = connect_client(listen_url, &server);
+  info!("Headless client {} connected as {:?}", index, client.player_id);
+
+  let client = &client;
+  let quit = &quit;
+  let _wander_thread = {
+    let server = server.clone();
+    unsafe {
+      thread_scoped::scoped(move || {
+        wander(index, client, quit, &mut |up| { server.talk.tell(&up) });
+      })
+    }
+  };
+
+  update_thread(
+    quit,
+    client,
+    &mut || { server.listen.try() },
+    &mut |_| {},
+    &mut |_| {},
+    &mut |_| {},
+    &mut |up| { server.talk.tell(&up) },
+    // Terrain is counted, but not kept; the server decides where the player really is.
+    &mut |msg| {
+      if let terrain::Load::Voxels { time_requested: Some(time_requested), .. } = msg {
+        chunks_received.fetch_add(1, Ordering::Relaxed);
+        client.chunk_requests.lock().unwrap().received(time_requested);
+      }
+    },
+  );
+}
+
+/// Walk forward, turning and jumping every so often.
+fn wander<UpdateServer>(
+  index         : u32,
+  client        : &client::T,
+  quit          : &Mutex<bool>,
+  update_server : &mut UpdateServer,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  // Seed each client differently, so they don't all walk the same path.
+  let mut rng: rand::XorShiftRng = SeedableRng::from_seed([1, 2, 3, index + 1]);
+
+  process_event::input(client, update_server, prediction::Input::Walk(Vector3::new(0.0, 0.0, -1.0)));
+  while !*quit.lock().unwrap() {
+    std::thread::sleep(std::time::Duration::from_millis(TURN_INTERVAL_MS));
+
+    let angle = rng.gen_range(-PI / 2.0, PI / 2.0);
+    update_server(protocol::ClientToServer::RotatePlayer(client.player_id, Vector2::new(angle, 0.0)));
+    client.prediction.lock().unwrap().rotate_lateral(angle);
+
+    process_event::input(client, update_server, prediction::Input::StartJump);
+    std::thread::sleep(std::time::Duration::from_millis(JUMP_MS));
+    process_event::input(client, update_server, prediction::Input::StopJump);
+  }
+}
+
+#[test]
+fn headless_flag_is_removed_from_args() {
+  let mut args = vec!("a".to_owned(), "--headless=16".to_owned());
+  assert_eq!(from_args(&mut args), Some(16));
+  assert_eq!(args, vec!("a".to_owned()));
+
+  let mut args = vec!("--headless".to_owned());
+  assert_eq!(from_args(&mut args), Some(1));
+  assert_eq!(from_args(&mut args), None);
+}
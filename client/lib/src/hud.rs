@@ -3,21 +3,43 @@
 use cgmath::Point2;
 
 use common::color::Color4;
+use common::health;
 
 use vertex::{ColoredVertex};
 use view;
 
-/// Add HUD data into `view`.
+/// The lower-left corner of the health bar, in HUD coordinates. It sits just above the hotbar.
+const HEALTH_BAR_CORNER: Point2<f32> = Point2 { x: -0.3, y: -0.8 };
+const HEALTH_BAR_WIDTH: f32 = 0.6;
+const HEALTH_BAR_HEIGHT: f32 = 0.03;
+
+/// A bar that empties from the right as health is lost.
+fn health_bar(health: &health::T) -> Vec<ColoredVertex> {
+  let min = HEALTH_BAR_CORNER;
+  let max = Point2::new(min.x + HEALTH_BAR_WIDTH, min.y + HEALTH_BAR_HEIGHT);
+  let mut triangles: Vec<_> =
+    ColoredVertex::square(min, max, Color4::of_rgba(0.0, 0.0, 0.0, 0.4)).iter().cloned().collect();
+  if !health.is_dead() {
+    let filled = Point2::new(min.x + HEALTH_BAR_WIDTH * health.fraction(), max.y);
+    triangles.extend(ColoredVertex::square(min, filled, Color4::of_rgba(0.8, 0.1, 0.1, 0.8)).iter().cloned());
+  }
+  triangles
+}
+
+/// (Re)build the HUD triangles in `view`.
 pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
   let cursor_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.75);
 
-  let triangles: Vec<_> =
+  let mut triangles: Vec<_> =
     ColoredVertex::square(
       Point2 { x: -0.02, y: -0.02 },
       Point2 { x:  0.02, y:  0.02 },
       cursor_color
     ).iter().cloned().collect();
+  triangles.extend(view.hotbar.triangles());
+  triangles.extend(health_bar(&view.health));
 
+  view.hud_triangles = view::new_hud_triangles(&mut view.gl, &view.shaders.hud_color_shader);
   view.hud_triangles.bind(&mut view.gl);
   view.hud_triangles.push(&mut view.gl, triangles.as_ref());
 }
@@ -28,9 +28,18 @@ const LG_SAMPLE_SIZE: [i16; COUNT] = [
 /// max LOD where grass will display
 pub const MAX_GRASS_LOD: T = T(3);
 
-/// The distances at which LOD switches.
+/// min LOD whose meshes are simplified
+pub const MIN_SIMPLIFIED_LOD: T = T(1);
+
+/// The default distances at which LOD switches.
 pub const THRESHOLDS: [u32; COUNT-1] = [1, 15, 31, 47];
 
+/// How many chunks past the edge of its LOD's band a loaded chunk can be before it switches LOD.
+pub const HYSTERESIS: u32 = 1;
+
+/// The least time a chunk stays at an LOD before it switches again.
+pub const MIN_DWELL_NS: u64 = 2_000_000_000;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// A strongly-typed index into various LOD-indexed arrays.
 /// 0 is the highest LOD.
@@ -54,15 +63,50 @@ impl T {
   }
 }
 
-/// determine the LOD for a certain chunk distance away
-pub fn of_distance(distance: u32) -> T {
+/// determine the LOD for a certain chunk distance away, given the distances at which LOD switches
+pub fn of_distance(thresholds: &[u32], distance: u32) -> T {
   let mut lod = 0;
   while
-    lod < THRESHOLDS.len()
-    && THRESHOLDS[lod] < distance
+    lod < thresholds.len()
+    && thresholds[lod] < distance
   {
     lod += 1;
   }
   T(num::traits::FromPrimitive::from_usize(lod).unwrap())
 }
 
+/// Like `of_distance`, but a chunk that's already loaded at `current` stays there until it's more than `HYSTERESIS`
+/// chunks past the edge of that LOD's band, so hovering around a threshold doesn't flip it back and forth.
+pub fn of_distance_from(thresholds: &[u32], distance: u32, current: Option<T>) -> T {
+  let lod = of_distance(thresholds, distance);
+  match current {
+    Some(current) if current != lod => {
+      let i = current.0 as usize;
+      // The band for LOD i is (thresholds[i - 1], thresholds[i]].
+      let near = if i == 0 { 0 } else { thresholds.get(i - 1).map_or(0, |&t| t + 1) };
+      let far = thresholds.get(i).map_or(u32::max_value(), |&t| t.saturating_add(HYSTERESIS));
+      if distance + HYSTERESIS >= near && distance <= far {
+        current
+      } else {
+        lod
+      }
+    },
+    _ => lod,
+  }
+}
+
+#[test]
+fn lod_sticks_near_thresholds() {
+  let thresholds = [1, 15, 31, 47];
+  assert_eq!(of_distance_from(&thresholds, 16, None), T(2));
+  // Moving away.
+  assert_eq!(of_distance_from(&thresholds, 16, Some(T(1))), T(1));
+  assert_eq!(of_distance_from(&thresholds, 17, Some(T(1))), T(2));
+  // Moving closer.
+  assert_eq!(of_distance_from(&thresholds, 15, Some(T(2))), T(2));
+  assert_eq!(of_distance_from(&thresholds, 14, Some(T(2))), T(1));
+  // Far past the band, the LOD changes all at once.
+  assert_eq!(of_distance_from(&thresholds, 40, Some(T(0))), T(3));
+  assert_eq!(of_distance_from(&thresholds, 60, Some(T(4))), T(4));
+}
+
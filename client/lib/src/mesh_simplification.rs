@@ -0,0 +1,418 @@
+//! Decimate a terrain mesh by collapsing edges, cheapest first, for as long as the surface stays within a tolerance
+//! of the original. The cost of a collapse is measured with quadric error metrics (Garland and Heckbert): each vertex
+//! keeps the planes of the triangles merged into it, and the cost is the sum of its squared distances to them.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use common::fnv_map;
+
+use terrain_mesh::{tri, Triangle};
+
+/// A collapse can't turn any triangle by more than this (as a cosine), or flip it against its vertex normals.
+const MIN_TURN_COSINE: f32 = 0.5;
+/// Quadrics lose a little precision, so even collapses within a plane can cost slightly more than nothing.
+const SLACK: f64 = 1e-6;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy)]
+pub struct Polygon {
+  pub vertices : Triangle<Point3<f32>>,
+  pub normals  : Triangle<Vector3<f32>>,
+  pub material : i32,
+}
+
+/// The sum of the squared distances to some planes, as a function of position. Stores the upper triangle of the
+/// symmetric 4x4 matrix.
+#[derive(Debug, Clone, Copy)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+  fn zero() -> Quadric {
+    Quadric([0.0; 10])
+  }
+
+  /// The plane through `p` with unit normal `n`.
+  fn plane(p: &Point3<f32>, n: &Vector3<f32>) -> Quadric {
+    let (a, b, c) = (n.x as f64, n.y as f64, n.z as f64);
+    let d = -(a * p.x as f64 + b * p.y as f64 + c * p.z as f64);
+    Quadric([a*a, a*b, a*c, a*d, b*b, b*c, b*d, c*c, c*d, d*d])
+  }
+
+  fn add(&self, other: &Quadric) -> Quadric {
+    let mut sum = *self;
+    for (x, y) in sum.0.iter_mut().zip(other.0.iter()) {
+      *x += *y;
+    }
+    sum
+  }
+
+  fn error(&self, p: &Point3<f32>) -> f64 {
+    let q = &self.0;
+    let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+    q[0]*x*x + 2.0*q[1]*x*y + 2.0*q[2]*x*z + 2.0*q[3]*x +
+    q[4]*y*y + 2.0*q[5]*y*z + 2.0*q[6]*y +
+    q[7]*z*z + 2.0*q[8]*z +
+    q[9]
+  }
+}
+
+/// Merging vertex `v` into its neighbor `u`.
+struct Collapse {
+  cost    : f64,
+  v       : usize,
+  u       : usize,
+  /// `v`'s version when this was worked out.
+  version : u32,
+}
+
+impl PartialEq for Collapse {
+  fn eq(&self, other: &Collapse) -> bool {
+    self.cost == other.cost
+  }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+  fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Collapse {
+  // `BinaryHeap` is a max-heap, so "greater" collapses are the cheaper ones.
+  fn cmp(&self, other: &Collapse) -> Ordering {
+    other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+  }
+}
+
+/// An indexed mesh, with vertices welded by position.
+struct Mesh {
+  positions : Vec<Point3<f32>>,
+  normals   : Vec<Vector3<f32>>,
+  /// The planes merged into each vertex.
+  quadrics  : Vec<Quadric>,
+  removable : Vec<bool>,
+  /// Bumped whenever the mesh around a vertex changes, to tell stale collapses apart.
+  versions  : Vec<u32>,
+  /// Removed triangles are None.
+  triangles : Vec<Option<[usize; 3]>>,
+  materials : Vec<i32>,
+  /// The triangles using each vertex. This can include removed triangles.
+  incident  : Vec<Vec<usize>>,
+}
+
+fn key(p: &Point3<f32>) -> [u32; 3] {
+  [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+}
+
+fn weld(polygons: &[Polygon]) -> Mesh {
+  let mut mesh =
+    Mesh {
+      positions : Vec::new(),
+      normals   : Vec::new(),
+      quadrics  : Vec::new(),
+      removable : Vec::new(),
+      versions  : Vec::new(),
+      triangles : Vec::new(),
+      materials : Vec::new(),
+      incident  : Vec::new(),
+    };
+  let mut indices: fnv_map::T<[u32; 3], usize> = fnv_map::new();
+
+  for polygon in polygons {
+    let mut triangle = [0; 3];
+    let vertices = [polygon.vertices.v1, polygon.vertices.v2, polygon.vertices.v3];
+    let normals = [polygon.normals.v1, polygon.normals.v2, polygon.normals.v3];
+    for i in 0 .. 3 {
+      let next = mesh.positions.len();
+      let index = *indices.entry(key(&vertices[i])).or_insert(next);
+      if index == next {
+        mesh.positions.push(vertices[i]);
+        mesh.normals.push(normals[i]);
+        mesh.quadrics.push(Quadric::zero());
+        mesh.removable.push(false);
+        mesh.versions.push(0);
+        mesh.incident.push(Vec::new());
+      }
+      triangle[i] = index;
+    }
+
+    if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[2] == triangle[0] {
+      continue
+    }
+
+    let t = mesh.triangles.len();
+    if let Some(normal) = mesh.face_normal(&triangle) {
+      let plane = Quadric::plane(&mesh.positions[triangle[0]], &normal);
+      for &v in &triangle {
+        mesh.quadrics[v] = mesh.quadrics[v].add(&plane);
+      }
+    }
+    for &v in &triangle {
+      mesh.incident[v].push(t);
+    }
+    mesh.triangles.push(Some(triangle));
+    mesh.materials.push(polygon.material);
+  }
+
+  mesh
+}
+
+impl Mesh {
+  fn face_normal(&self, triangle: &[usize; 3]) -> Option<Vector3<f32>> {
+    let a = self.positions[triangle[0]];
+    let n = (self.positions[triangle[1]] - a).cross(self.positions[triangle[2]] - a);
+    if n.magnitude2() < 1e-12 {
+      None
+    } else {
+      Some(n.normalize())
+    }
+  }
+
+  fn live_incident(&self, v: usize) -> Vec<usize> {
+    self.incident[v].iter().cloned().filter(|&t| self.triangles[t].map_or(false, |t| t.contains(&v))).collect()
+  }
+
+  /// The vertices sharing a triangle with `v`, once per triangle.
+  fn ring(&self, v: usize) -> Vec<usize> {
+    let mut ring = Vec::new();
+    for t in self.live_incident(v) {
+      ring.extend(self.triangles[t].unwrap().iter().cloned().filter(|&u| u != v));
+    }
+    ring
+  }
+
+  fn neighbors(&self, v: usize) -> Vec<usize> {
+    let mut neighbors = self.ring(v);
+    neighbors.sort();
+    neighbors.dedup();
+    neighbors
+  }
+
+  /// Whether `v` can be merged into `u` without tearing, folding or flipping the surface.
+  fn can_collapse(&self, v: usize, u: usize, triangles: &[usize], neighbors: &[usize]) -> bool {
+    // Collapsing v into u must only remove the two triangles along the edge (u, v),
+    // or it would fold the surface onto itself.
+    let u_neighbors = self.neighbors(u);
+    let shared = neighbors.iter().filter(|w| u_neighbors.contains(w)).count();
+    if shared != 2 {
+      return false
+    }
+
+    triangles.iter()
+      .map(|&t| self.triangles[t].unwrap())
+      .filter(|triangle| !triangle.contains(&u))
+      .all(|triangle| {
+        let before = self.face_normal(&triangle);
+        let mut moved = triangle;
+        for w in moved.iter_mut() {
+          if *w == v {
+            *w = u;
+          }
+        }
+        match (before, self.face_normal(&moved)) {
+          (Some(before), Some(after)) =>
+            after.dot(before) >= MIN_TURN_COSINE &&
+            moved.iter().all(|&w| after.dot(self.normals[w]) > 0.0),
+          _ => false,
+        }
+      })
+  }
+
+  /// The cheapest way to remove `v`, if there is one that costs at most `max_cost`.
+  fn cheapest_collapse(&self, v: usize, max_cost: f64) -> Option<Collapse> {
+    if !self.removable[v] {
+      return None
+    }
+
+    let triangles = self.live_incident(v);
+    if triangles.is_empty() {
+      return None
+    }
+
+    let material = self.materials[triangles[0]];
+    if triangles.iter().any(|&t| self.materials[t] != material) {
+      return None
+    }
+
+    // Vertices on the edge of an open surface have neighbors that only appear once around them.
+    let ring = self.ring(v);
+    let neighbors = self.neighbors(v);
+    if neighbors.iter().any(|&u| ring.iter().filter(|&&w| w == u).count() != 2) {
+      return None
+    }
+
+    let mut collapses: Vec<Collapse> =
+      neighbors.iter()
+      .map(|&u| {
+        Collapse {
+          cost    : self.quadrics[v].add(&self.quadrics[u]).error(&self.positions[u]),
+          v       : v,
+          u       : u,
+          version : self.versions[v],
+        }
+      })
+      .filter(|collapse| collapse.cost <= max_cost)
+      .collect();
+    // Cheapest first.
+    collapses.sort_by(|x, y| y.cmp(x));
+    collapses.into_iter().find(|collapse| self.can_collapse(v, collapse.u, &triangles, &neighbors))
+  }
+
+  /// Merge `v` into `u`, and return the vertices whose surroundings changed.
+  fn collapse(&mut self, v: usize, u: usize) -> Vec<usize> {
+    for t in self.live_incident(v) {
+      let mut triangle = self.triangles[t].unwrap();
+      if triangle.contains(&u) {
+        self.triangles[t] = None;
+      } else {
+        for w in triangle.iter_mut() {
+          if *w == v {
+            *w = u;
+          }
+        }
+        self.triangles[t] = Some(triangle);
+        self.incident[u].push(t);
+      }
+    }
+    self.incident[v].clear();
+    self.quadrics[u] = self.quadrics[u].add(&self.quadrics[v]);
+
+    let mut changed = self.neighbors(u);
+    changed.push(u);
+    for &w in &changed {
+      self.versions[w] += 1;
+    }
+    changed
+  }
+}
+
+/// Remove vertices from the single-material regions of a mesh, and stretch their neighbors' triangles over the gaps,
+/// as long as the surface moves no further than about `tolerance` from where it was. Vertices where `can_remove` is
+/// false stay put, e.g. so a chunk's edges still line up with its neighbors'.
+pub fn simplify<CanRemove>(polygons: &[Polygon], tolerance: f32, mut can_remove: CanRemove) -> Vec<Polygon>
+  where CanRemove: FnMut(&Point3<f32>) -> bool
+{
+  let mut mesh = weld(polygons);
+  for v in 0 .. mesh.positions.len() {
+    mesh.removable[v] = can_remove(&mesh.positions[v]);
+  }
+
+  let max_cost = tolerance as f64 * tolerance as f64 + SLACK;
+  let mut collapses: BinaryHeap<Collapse> =
+    (0 .. mesh.positions.len()).filter_map(|v| mesh.cheapest_collapse(v, max_cost)).collect();
+  while let Some(collapse) = collapses.pop() {
+    if collapse.version != mesh.versions[collapse.v] {
+      continue
+    }
+    // Changes a couple of vertices away (e.g. around `u`) don't bump `v`'s version, so check again.
+    match mesh.cheapest_collapse(collapse.v, max_cost) {
+      None => {},
+      Some(fresh) => {
+        if fresh.cost > collapse.cost {
+          collapses.push(fresh);
+          continue
+        }
+        for w in mesh.collapse(fresh.v, fresh.u) {
+          if let Some(collapse) = mesh.cheapest_collapse(w, max_cost) {
+            collapses.push(collapse);
+          }
+        }
+      },
+    }
+  }
+
+  mesh.triangles.iter().zip(mesh.materials.iter())
+    .filter_map(|(triangle, &material)| {
+      triangle.map(|t| {
+        Polygon {
+          vertices : tri(mesh.positions[t[0]], mesh.positions[t[1]], mesh.positions[t[2]]),
+          normals  : tri(mesh.normals[t[0]], mesh.normals[t[1]], mesh.normals[t[2]]),
+          material : material,
+        }
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+fn grid(n: usize) -> Vec<Polygon> {
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let p = |x: usize, z: usize| Point3::new(x as f32, 0.0, z as f32);
+  let mut polygons = Vec::new();
+  for x in 0 .. n {
+  for z in 0 .. n {
+    polygons.push(Polygon { vertices: tri(p(x, z), p(x, z + 1), p(x + 1, z + 1)), normals: tri(up, up, up), material: 1 });
+    polygons.push(Polygon { vertices: tri(p(x, z), p(x + 1, z + 1), p(x + 1, z)), normals: tri(up, up, up), material: 1 });
+  }}
+  polygons
+}
+
+#[cfg(test)]
+fn area(polygons: &[Polygon]) -> f32 {
+  polygons.iter()
+    .map(|p| (p.vertices.v2 - p.vertices.v1).cross(p.vertices.v3 - p.vertices.v1).magnitude() / 2.0)
+    .sum()
+}
+
+#[test]
+fn flat_grid_keeps_its_shape() {
+  let n = 8;
+  let polygons = grid(n);
+  let interior = |p: &Point3<f32>| 0.0 < p.x && p.x < n as f32 && 0.0 < p.z && p.z < n as f32;
+  let simplified = simplify(&polygons, 0.0, interior);
+
+  assert!(simplified.len() * 2 < polygons.len());
+  assert!((area(&simplified) - area(&polygons)).abs() < 1e-3);
+  // The edges of the grid are all still there.
+  for p in &polygons {
+    for v in &[p.vertices.v1, p.vertices.v2, p.vertices.v3] {
+      if !interior(v) {
+        assert!(simplified.iter().any(|q| q.vertices.v1 == *v || q.vertices.v2 == *v || q.vertices.v3 == *v));
+      }
+    }
+  }
+}
+
+#[test]
+fn fixed_vertices_stay() {
+  let polygons = grid(4);
+  assert_eq!(simplify(&polygons, 0.0, |_| false).len(), polygons.len());
+}
+
+#[test]
+fn bent_grid_is_not_flattened() {
+  let mut polygons = grid(2);
+  let peak = Point3::new(1.0, 0.0, 1.0);
+  for p in &mut polygons {
+    for v in &mut [&mut p.vertices.v1, &mut p.vertices.v2, &mut p.vertices.v3] {
+      if **v == peak {
+        v.y = 1.0;
+      }
+    }
+  }
+  assert_eq!(simplify(&polygons, 0.0, |_| true).len(), polygons.len());
+}
+
+#[test]
+fn small_bumps_are_smoothed_within_tolerance() {
+  let n = 8;
+  let mut polygons = grid(n);
+  let bump = Point3::new(4.0, 0.0, 4.0);
+  for p in &mut polygons {
+    for v in &mut [&mut p.vertices.v1, &mut p.vertices.v2, &mut p.vertices.v3] {
+      if **v == bump {
+        v.y = 0.05;
+      }
+    }
+  }
+  let interior = |p: &Point3<f32>| 0.0 < p.x && p.x < n as f32 && 0.0 < p.z && p.z < n as f32;
+
+  let exact = simplify(&polygons, 0.0, interior);
+  let smoothed = simplify(&polygons, 0.1, interior);
+  // The bump stops the exact pass from clearing the middle of the grid, but it's within tolerance.
+  assert!(smoothed.len() < exact.len());
+}
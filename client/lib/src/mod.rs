@@ -3,7 +3,6 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-extern crate bincode;
 extern crate cgmath;
 extern crate collision;
 extern crate common;
@@ -15,34 +14,60 @@ extern crate isosurface_extraction;
 #[macro_use]
 extern crate log;
 extern crate libc;
+extern crate lru_cache;
 extern crate num;
 #[cfg(feature="audio")]
 extern crate portaudio;
 extern crate rand;
+extern crate rayon;
 extern crate sdl2;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate stopwatch;
+#[cfg(test)]
+extern crate terrain;
 extern crate thread_scoped;
 extern crate time;
+extern crate toml;
 extern crate voxel_data;
 extern crate yaglw;
 
+pub mod animation;
 pub mod audio;
 pub mod audio_loader;
 pub mod audio_thread;
 pub mod chunk;
+pub mod chunk_requests;
 pub mod chunk_stats;
 pub mod client;
+pub mod clipboard;
+pub mod clock_sync;
+pub mod config;
+pub mod headless;
 pub mod hud;
 pub mod lod;
+pub mod mesh_simplification;
+pub mod particles;
+pub mod prediction;
 pub mod process_event;
+pub mod reconnect;
 pub mod record_book;
+pub mod render_distance;
+pub mod replay;
+pub mod replication;
 pub mod run;
 pub mod server;
 pub mod server_update;
 pub mod terrain;
 pub mod terrain_mesh;
+pub mod timings;
 pub mod update_thread;
 pub mod vertex;
 pub mod view;
+pub mod voxel_light;
+pub mod waypoint;
 
+pub use common::net::Transport;
 pub use run::run;
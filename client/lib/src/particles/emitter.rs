@@ -0,0 +1,203 @@
+//! Particle emitters, and the particles they give off.
+
+use cgmath::{Point3, Vector3};
+use rand::Rng;
+
+use common::color::Color4;
+use common::voxel;
+
+/// A burst of particles.
+#[derive(Debug, Clone, Copy)]
+pub struct Emitter {
+  /// Where the particles start.
+  pub position : Point3<f32>,
+  /// How far from `position` particles can start, on each axis.
+  pub spread   : Vector3<f32>,
+  /// The number of particles to give off.
+  pub count    : u32,
+  #[allow(missing_docs)]
+  pub color    : Color4<f32>,
+  /// The particles' average initial velocity, in units per second.
+  pub velocity : Vector3<f32>,
+  /// How far each particle's initial velocity can be from `velocity`, on each axis.
+  pub jitter   : Vector3<f32>,
+  /// Downward acceleration, in units per second squared.
+  pub gravity  : f32,
+  /// How long each particle lives, in seconds.
+  pub lifetime : f32,
+  /// The width of each particle.
+  pub size     : f32,
+}
+
+/// The color of the bits that fly off when some material is dug out.
+fn material_color(material: voxel::Material) -> Color4<f32> {
+  match material {
+    voxel::Material::Empty   => Color4::of_rgba(0.0 , 0.0 , 0.0 , 0.0),
+    voxel::Material::Terrain => Color4::of_rgba(0.4 , 0.3 , 0.2 , 1.0),
+    voxel::Material::Bark    => Color4::of_rgba(0.35, 0.2 , 0.1 , 1.0),
+    voxel::Material::Leaves  => Color4::of_rgba(0.2 , 0.5 , 0.1 , 1.0),
+    voxel::Material::Stone   => Color4::of_rgba(0.5 , 0.5 , 0.5 , 1.0),
+    voxel::Material::Marble  => Color4::of_rgba(0.9 , 0.9 , 0.85, 1.0),
+    voxel::Material::Sand    => Color4::of_rgba(0.85, 0.8 , 0.55, 1.0),
+    voxel::Material::Snow    => Color4::of_rgba(0.95, 0.95, 1.0 , 1.0),
+    voxel::Material::Water   => Color4::of_rgba(0.3 , 0.4 , 0.8 , 0.6),
+  }
+}
+
+/// Chunks of material thrown out of a hole that was just dug.
+pub fn debris(position: Point3<f32>, material: voxel::Material) -> Emitter {
+  Emitter {
+    position : position,
+    spread   : Vector3::new(1.0, 1.0, 1.0),
+    count    : 48,
+    color    : material_color(material),
+    velocity : Vector3::new(0.0, 4.0, 0.0),
+    jitter   : Vector3::new(3.0, 2.0, 3.0),
+    gravity  : 12.0,
+    lifetime : 1.2,
+    size     : 0.2,
+  }
+}
+
+/// A cloud of smoke and sparks thrown out by an explosion.
+pub fn explosion(position: Point3<f32>, radius: f32) -> Emitter {
+  Emitter {
+    position : position,
+    spread   : Vector3::new(radius, radius, radius) * 0.5,
+    count    : 96,
+    color    : Color4::of_rgba(0.35, 0.3, 0.25, 0.8),
+    velocity : Vector3::new(0.0, 2.0, 0.0),
+    jitter   : Vector3::new(3.0, 3.0, 3.0) * radius,
+    gravity  : 2.0,
+    lifetime : 1.5,
+    size     : 0.4,
+  }
+}
+
+/// A puff of dust kicked up by a footstep.
+pub fn dust(position: Point3<f32>) -> Emitter {
+  Emitter {
+    position : position,
+    spread   : Vector3::new(0.3, 0.0, 0.3),
+    count    : 8,
+    color    : Color4::of_rgba(0.6, 0.55, 0.45, 0.5),
+    velocity : Vector3::new(0.0, 0.5, 0.0),
+    jitter   : Vector3::new(0.5, 0.3, 0.5),
+    gravity  : 0.5,
+    lifetime : 0.6,
+    size     : 0.25,
+  }
+}
+
+/// Rain falling over a square `width` wide, starting above `center`.
+pub fn rain(center: Point3<f32>, width: f32) -> Emitter {
+  Emitter {
+    position : center,
+    spread   : Vector3::new(width / 2.0, 2.0, width / 2.0),
+    count    : 64,
+    color    : Color4::of_rgba(0.6, 0.65, 0.8, 0.5),
+    velocity : Vector3::new(0.0, -16.0, 0.0),
+    jitter   : Vector3::new(0.2, 2.0, 0.2),
+    gravity  : 0.0,
+    lifetime : 2.0,
+    size     : 0.08,
+  }
+}
+
+/// Snow drifting down over a square `width` wide, starting above `center`.
+pub fn snow(center: Point3<f32>, width: f32) -> Emitter {
+  Emitter {
+    position : center,
+    spread   : Vector3::new(width / 2.0, 2.0, width / 2.0),
+    count    : 32,
+    color    : Color4::of_rgba(0.95, 0.95, 1.0, 0.8),
+    velocity : Vector3::new(0.0, -2.0, 0.0),
+    jitter   : Vector3::new(0.6, 0.5, 0.6),
+    gravity  : 0.0,
+    lifetime : 8.0,
+    size     : 0.1,
+  }
+}
+
+/// A single particle in flight.
+#[derive(Debug, Clone, Copy)]
+pub struct Particle {
+  #[allow(missing_docs)]
+  pub position  : Point3<f32>,
+  #[allow(missing_docs)]
+  pub velocity  : Vector3<f32>,
+  #[allow(missing_docs)]
+  pub color     : Color4<f32>,
+  #[allow(missing_docs)]
+  pub size      : f32,
+  gravity       : f32,
+  lifetime      : f32,
+  /// How much longer the particle has to live, in seconds.
+  pub remaining : f32,
+}
+
+fn jitter<R: Rng>(rng: &mut R, v: Vector3<f32>) -> Vector3<f32> {
+  let mut component = |x: f32| if x > 0.0 { rng.gen_range(-x, x) } else { 0.0 };
+  Vector3::new(component(v.x), component(v.y), component(v.z))
+}
+
+impl Emitter {
+  /// Give off this emitter's particles.
+  pub fn emit<R: Rng>(&self, rng: &mut R, particles: &mut Vec<Particle>) {
+    for _ in 0 .. self.count {
+      particles.push(
+        Particle {
+          position  : self.position + jitter(rng, self.spread),
+          velocity  : self.velocity + jitter(rng, self.jitter),
+          color     : self.color,
+          size      : self.size,
+          gravity   : self.gravity,
+          lifetime  : self.lifetime,
+          remaining : self.lifetime,
+        }
+      );
+    }
+  }
+}
+
+impl Particle {
+  /// Move the particle `dt` seconds forward. Returns false once it has died.
+  pub fn step(&mut self, dt: f32) -> bool {
+    self.remaining -= dt;
+    if self.remaining <= 0.0 {
+      return false
+    }
+    self.velocity.y -= self.gravity * dt;
+    self.position += self.velocity * dt;
+    true
+  }
+
+  /// The particle's color, faded out over the last part of its life.
+  pub fn faded_color(&self) -> Color4<f32> {
+    let fade = f32::min(1.0, 4.0 * self.remaining / self.lifetime);
+    Color4 { a: self.color.a * fade, .. self.color }
+  }
+}
+
+#[test]
+fn particles_fall_and_die() {
+  let mut particles = Vec::new();
+  let emitter =
+    Emitter {
+      spread : Vector3::new(0.0, 0.0, 0.0),
+      jitter : Vector3::new(0.0, 0.0, 0.0),
+      .. debris(Point3::new(0.0, 0.0, 0.0), voxel::Material::Stone)
+    };
+  let mut rng: ::rand::XorShiftRng = ::rand::SeedableRng::from_seed([1, 2, 3, 4]);
+  emitter.emit(&mut rng, &mut particles);
+  assert_eq!(particles.len(), emitter.count as usize);
+
+  let mut particle = particles[0];
+  assert!(particle.step(0.1));
+  assert!(particle.position.y > 0.0);
+  for _ in 0 .. 5 {
+    assert!(particle.step(0.1));
+  }
+  assert!(particle.velocity.y < 0.0);
+  assert!(!particle.step(emitter.lifetime));
+}
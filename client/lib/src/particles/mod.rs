@@ -0,0 +1,155 @@
+//! Particles: short-lived sprites, simulated on the CPU and drawn as GPU instances of a single quad.
+
+pub mod emitter;
+
+pub use self::emitter::{Emitter, Particle};
+
+use cgmath::Point3;
+use gl;
+use rand;
+use std;
+use yaglw;
+use yaglw::gl_context::GLContext;
+
+use common::color::Color4;
+
+/// The most particles alive at once. When there are more, the oldest are dropped.
+const MAX_PARTICLES: usize = 1 << 12;
+/// Each particle is drawn as two triangles.
+const VERTICES_PER_PARTICLE: i32 = 6;
+
+/// The per-instance data of a particle in VRAM.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Instance {
+  #[allow(missing_docs)]
+  pub position : Point3<f32>,
+  #[allow(missing_docs)]
+  pub size     : f32,
+  #[allow(missing_docs)]
+  pub color    : Color4<f32>,
+}
+
+/// The particles in flight, and their VRAM buffer.
+pub struct T<'a> {
+  particles   : Vec<Particle>,
+  rng         : rand::XorShiftRng,
+  /// When the particles were last moved, in ns.
+  last_update : Option<u64>,
+
+  gl_array    : yaglw::vertex_buffer::ArrayHandle<'a>,
+  instances   : yaglw::vertex_buffer::GLBuffer<'a, Instance>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b:'a>(
+  gl: &'a mut GLContext,
+  shader: &yaglw::shader::Shader<'a>,
+) -> T<'b>
+{
+  use yaglw::vertex_buffer;
+
+  let gl_array = vertex_buffer::ArrayHandle::new(gl);
+  let instances = vertex_buffer::GLBuffer::new(gl, MAX_PARTICLES);
+
+  unsafe {
+    gl::BindVertexArray(gl_array.gl_id);
+  }
+  shader.use_shader(gl);
+  instances.byte_buffer.bind(gl);
+  let attrib_span =
+    vertex_buffer::VertexAttribData::apply(
+      &[
+        vertex_buffer::VertexAttribData {
+          name: "position",
+          size: 3,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+        vertex_buffer::VertexAttribData {
+          name: "size",
+          size: 1,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+        vertex_buffer::VertexAttribData {
+          name: "in_color",
+          size: 4,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+      ],
+      gl,
+      shader,
+    );
+  assert!(attrib_span == std::mem::size_of::<Instance>() as u32);
+
+  T {
+    particles   : Vec::new(),
+    rng         : rand::SeedableRng::from_seed([5, 6, 7, 8]),
+    last_update : None,
+
+    gl_array    : gl_array,
+    instances   : instances,
+  }
+}
+
+impl<'a> T<'a> {
+  /// Start a burst of particles.
+  pub fn emit(&mut self, emitter: &Emitter) {
+    emitter.emit(&mut self.rng, &mut self.particles);
+    if self.particles.len() > MAX_PARTICLES {
+      let excess = self.particles.len() - MAX_PARTICLES;
+      self.particles.drain(0 .. excess);
+    }
+  }
+
+  /// Move the particles along to time `now` (in ns), and upload them to VRAM.
+  pub fn update(&mut self, gl: &mut GLContext, now: u64) {
+    let dt = self.last_update.map_or(0.0, |last| (now - last) as f32 / 1e9);
+    self.last_update = Some(now);
+
+    let particles = std::mem::replace(&mut self.particles, Vec::new());
+    self.particles =
+      particles.into_iter()
+      .filter_map(|mut particle| if particle.step(dt) { Some(particle) } else { None })
+      .collect();
+
+    if self.particles.is_empty() {
+      return
+    }
+
+    let instances: Vec<Instance> =
+      self.particles.iter()
+      .map(|particle| {
+        Instance {
+          position : particle.position,
+          size     : particle.size,
+          color    : particle.faded_color(),
+        }
+      })
+      .collect();
+
+    self.instances.byte_buffer.bind(gl);
+    unsafe {
+      self.instances.byte_buffer.update(
+        gl,
+        0,
+        instances.as_ptr() as *const u8,
+        std::mem::size_of::<Instance>() * instances.len(),
+      );
+    }
+  }
+
+  /// Draw the particles.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, _gl: &mut GLContext) {
+    if self.particles.is_empty() {
+      return
+    }
+    unsafe {
+      gl::BindVertexArray(self.gl_array.gl_id);
+      gl::DrawArraysInstanced(gl::TRIANGLES, 0, VERTICES_PER_PARTICLE, self.particles.len() as i32);
+    }
+  }
+}
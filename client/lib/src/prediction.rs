@@ -0,0 +1,230 @@
+//! Move this client's player locally as soon as it's told to, instead of waiting for the server.
+//! When the server says where the player really is, we start over from there and redo whatever
+//! the server hasn't caught up on yet.
+
+use cgmath;
+use cgmath::{Matrix3, Point3, Vector3};
+use collision::Aabb3;
+use std;
+use time;
+
+use common::interval_timer::IntervalTimer;
+use common::movement;
+use common::protocol;
+use common::voxel;
+
+use terrain;
+
+/// A movement input, which changes how the player moves from then on.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+  Walk(Vector3<f32>),
+  StartJump,
+  StopJump,
+}
+
+impl Input {
+  fn apply(self, movement: &mut movement::T) {
+    match self {
+      Input::Walk(da)  => movement.walk(da),
+      Input::StartJump => movement.start_jump(),
+      Input::StopJump  => movement.stop_jump(),
+    }
+  }
+}
+
+/// The player's body, among the voxels this client has cached.
+struct TerrainBody<'a> {
+  terrain : &'a terrain::T,
+  bounds  : &'a mut Aabb3<f32>,
+}
+
+impl<'a> movement::Body for TerrainBody<'a> {
+  type Obstacle = ();
+
+  fn bounds(&self) -> Aabb3<f32> {
+    *self.bounds
+  }
+
+  fn translate(&mut self, shift: Vector3<f32>) -> Option<(Aabb3<f32>, ())> {
+    let new_bounds = Aabb3::new(self.bounds.min + shift, self.bounds.max + shift);
+    match highest_solid_voxel(self.terrain, &new_bounds) {
+      Some(obstacle) => Some((obstacle, ())),
+      None => {
+        *self.bounds = new_bounds;
+        None
+      },
+    }
+  }
+
+  fn submersion(&self) -> f32 {
+    movement::submersion(&*self.bounds, |bounds| {
+      match self.terrain.voxel(bounds) {
+        Some(voxel::Volume(voxel::Material::Water)) => true,
+        _ => false,
+      }
+    })
+  }
+}
+
+/// The highest of the smallest voxels overlapping `bounds` that can't be walked through.
+/// Like on the server, voxels we don't have yet are treated as solid.
+fn highest_solid_voxel(terrain: &terrain::T, bounds: &Aabb3<f32>) -> Option<Aabb3<f32>> {
+  let low = Point3::new(bounds.min.x.floor() as i32, bounds.min.y.floor() as i32, bounds.min.z.floor() as i32);
+  let high = Point3::new(bounds.max.x.ceil() as i32, bounds.max.y.ceil() as i32, bounds.max.z.ceil() as i32);
+  let mut highest = None;
+  for y in (low.y .. high.y).rev() {
+    for x in low.x .. high.x {
+    for z in low.z .. high.z {
+      let voxel_bounds = voxel::bounds::new(x, y, z, 0);
+      match terrain.voxel(&voxel_bounds) {
+        Some(voxel::Volume(voxel::Material::Empty)) |
+        Some(voxel::Volume(voxel::Material::Water)) => {},
+        _ => {
+          let (low, high) = voxel_bounds.corners();
+          highest = Some(Aabb3::new(low, high));
+        },
+      }
+    }}
+    if highest.is_some() {
+      break
+    }
+  }
+  highest
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  bounds           : Aabb3<f32>,
+  movement         : movement::T,
+  lateral_rotation : f32,
+  next_input       : movement::InputId,
+  /// The number of updates run so far.
+  updates          : u64,
+  /// Inputs the server hasn't applied yet, with the number of updates that had been run when each was made.
+  unapplied        : std::collections::VecDeque<(movement::InputId, u64, Input)>,
+  timer            : IntervalTimer,
+}
+
+#[allow(missing_docs)]
+pub fn new(position: Point3<f32>) -> T {
+  let half_size = movement::PLAYER_SIZE / 2.0;
+  T {
+    bounds           : Aabb3::new(position + -half_size, position + half_size),
+    movement         : movement::new(),
+    lateral_rotation : movement::SPAWN_LATERAL_ROTATION,
+    next_input       : 0,
+    updates          : 0,
+    unapplied        : std::collections::VecDeque::new(),
+    timer            : {
+      let nanoseconds_per_second = 1000000000;
+      IntervalTimer::new(nanoseconds_per_second / movement::UPDATES_PER_SECOND, time::precise_time_ns())
+    },
+  }
+}
+
+impl T {
+  /// The player's predicted bounding box.
+  pub fn bounds(&self) -> Aabb3<f32> {
+    self.bounds
+  }
+
+  /// Apply an input right away. Returns the id to send it to the server with.
+  pub fn input(&mut self, input: Input) -> movement::InputId {
+    let id = self.next_input;
+    self.next_input += 1;
+    input.apply(&mut self.movement);
+    self.unapplied.push_back((id, self.updates, input));
+    id
+  }
+
+  /// Rotate the player around the y axis, by `r` radians, the same way the server does.
+  pub fn rotate_lateral(&mut self, r: f32) {
+    self.lateral_rotation += r;
+  }
+
+  /// Whether the player's eyes are underwater.
+  pub fn is_submerged(&self) -> bool {
+    self.movement.is_submerged()
+  }
+
+  /// The direction the player faces, as an angle from +x toward +z.
+  pub fn heading(&self) -> f32 {
+    movement::heading(self.lateral_rotation)
+  }
+
+  /// What the player is visibly doing.
+  pub fn animation(&self) -> protocol::Animation {
+    self.movement.animation()
+  }
+
+  /// The horizontal direction the player faces.
+  pub fn forward(&self) -> Vector3<f32> {
+    Matrix3::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(self.lateral_rotation))
+    * Vector3::new(0.0, 0.0, -1.0)
+  }
+
+  fn update(&mut self, terrain: &terrain::T) {
+    let mut body = TerrainBody { terrain: terrain, bounds: &mut self.bounds };
+    self.movement.update(&mut body, self.lateral_rotation);
+  }
+
+  /// Run any updates that are due. Returns true if any were.
+  pub fn tick(&mut self, terrain: &terrain::T) -> bool {
+    let updates = self.timer.update(time::precise_time_ns());
+    for _ in 0 .. updates {
+      self.update(terrain);
+      self.updates += 1;
+    }
+    updates > 0
+  }
+
+  /// Move the player's center to `position` at a standstill, e.g. because the server teleported it there.
+  pub fn teleport(&mut self, position: Point3<f32>) {
+    let half_size = movement::PLAYER_SIZE / 2.0;
+    self.bounds = Aabb3::new(position + -half_size, position + half_size);
+    self.movement.speed = Vector3::new(0.0, 0.0, 0.0);
+  }
+
+  /// Take the server's word for where the player is, then redo the inputs it hasn't applied yet
+  /// (and the updates since they were made) on top of that.
+  pub fn reconcile(
+    &mut self,
+    terrain    : &terrain::T,
+    bounds     : Aabb3<f32>,
+    movement   : movement::T,
+    last_input : Option<movement::InputId>,
+  ) {
+    if let Some(last_input) = last_input {
+      while self.unapplied.front().map_or(false, |&(id, _, _)| id <= last_input) {
+        self.unapplied.pop_front();
+      }
+    }
+
+    self.bounds = bounds;
+    self.movement = movement;
+
+    let first_update =
+      match self.unapplied.front() {
+        None => return,
+        Some(&(_, at, _)) => at,
+      };
+
+    let unapplied = self.unapplied.clone();
+    let mut unapplied = unapplied.iter().peekable();
+    for update in first_update .. self.updates {
+      while let Some(&&(_, at, input)) = unapplied.peek() {
+        if at > update {
+          break
+        }
+        input.apply(&mut self.movement);
+        unapplied.next();
+      }
+      self.update(terrain);
+    }
+    for &(_, _, input) in unapplied {
+      input.apply(&mut self.movement);
+    }
+  }
+}
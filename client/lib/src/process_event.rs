@@ -1,17 +1,21 @@
 //! SDL input event processing code.
 
-use cgmath::{Vector2, Vector3};
+use cgmath::{Point3, Vector2, Vector3};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseButton;
 use std::f32::consts::PI;
 use stopwatch;
+use time;
 
-use common::entity;
 use common::protocol;
 
 use client;
+use clipboard;
+use hud;
+use prediction;
 use view;
+use waypoint;
 
 #[allow(missing_docs)]
 pub fn process_event<UpdateServer>(
@@ -21,31 +25,140 @@ pub fn process_event<UpdateServer>(
   event: Event,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  let event =
+    match view.input_mode {
+      view::InputMode::Chat => {
+        match chat_event(update_server, view, client, event) {
+          None => return,
+          Some(event) => event,
+        }
+      },
+      _ => event,
+    };
+
   match event {
     Event::KeyDown{keycode, repeat, ..} => {
       keycode.map(|keycode| {
         if !repeat {
+          view.held_keys.insert(keycode);
           key_press(update_server, view, client, keycode);
         }
       });
     },
     Event::KeyUp{keycode, repeat, ..} => {
       keycode.map(|keycode| {
-        if !repeat {
-          key_release(client.player_id, update_server, keycode);
+        // Keys pressed while typing were never handled as presses, so their releases are ignored too.
+        if !repeat && view.held_keys.remove(&keycode) {
+          key_release(client, update_server, view, keycode);
         }
       });
     },
     Event::MouseMotion{xrel, yrel, ..} => {
-      mouse_move(client.player_id, update_server, view, xrel, yrel);
+      mouse_move(client, update_server, view, xrel, yrel);
     },
     Event::MouseButtonDown{mouse_btn, ..} => {
-      mouse_press(client.player_id, update_server, mouse_btn);
+      mouse_press(client, view, update_server, mouse_btn);
+    },
+    Event::MouseWheel{y, ..} => {
+      if view.spectator.is_active() && y != 0 {
+        view.spectator.scale_speed(y > 0);
+      }
     },
     _ => {},
   }
 }
 
+/// Start moving the player locally, and tell the server.
+pub fn input<UpdateServer>(
+  client: &client::T,
+  update_server: &mut UpdateServer,
+  input: prediction::Input,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  let id = client.prediction.lock().unwrap().input(input);
+  let player_id = client.player_id;
+  update_server(
+    match input {
+      prediction::Input::Walk(da)  => protocol::ClientToServer::Walk(player_id, da, id),
+      prediction::Input::StartJump => protocol::ClientToServer::StartJump(player_id, id),
+      prediction::Input::StopJump  => protocol::ClientToServer::StopJump(player_id, id),
+    }
+  );
+}
+
+/// Walk the player in a direction, or fly the camera if it's detached.
+fn walk<UpdateServer>(
+  client: &client::T,
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  direction: Vector3<f32>,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  view.spectator.push(direction);
+  if !view.spectator.is_active() {
+    input(client, update_server, prediction::Input::Walk(direction));
+  }
+}
+
+/// Start or stop jumping, or flying up if the camera is detached.
+fn jump<UpdateServer>(
+  client: &client::T,
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  jumping: bool,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  view.spectator.set_ascending(jumping);
+  if !view.spectator.is_active() {
+    input(client, update_server, if jumping { prediction::Input::StartJump } else { prediction::Input::StopJump });
+  }
+}
+
+/// Detach the camera from the player, or put it back.
+fn toggle_spectator<UpdateServer>(
+  client: &client::T,
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  view.spectator.toggle();
+
+  // Whatever movement keys are held move over to the camera (or back to the player), so the player stops in place
+  // and doesn't walk off once the keys are released.
+  let walk = view.spectator.walk();
+  let walking = walk != Vector3::new(0.0, 0.0, 0.0);
+  let jumping = view.spectator.is_ascending();
+  if view.spectator.is_active() {
+    if walking {
+      input(client, update_server, prediction::Input::Walk(-walk));
+    }
+    if jumping {
+      input(client, update_server, prediction::Input::StopJump);
+    }
+    *client.spectator_position.lock().unwrap() = Some(view.camera.position);
+  } else {
+    if walking {
+      input(client, update_server, prediction::Input::Walk(walk));
+    }
+    if jumping {
+      input(client, update_server, prediction::Input::StartJump);
+    }
+    *client.spectator_position.lock().unwrap() = None;
+    let eye = view.eye;
+    view.camera.translate_to(eye);
+  }
+}
+
+/// The inventory slot selected by a number key, if `key` is one.
+fn slot_key(key: Keycode) -> Option<usize> {
+  let keys = [
+    Keycode::Num1, Keycode::Num2, Keycode::Num3,
+    Keycode::Num4, Keycode::Num5, Keycode::Num6,
+    Keycode::Num7, Keycode::Num8, Keycode::Num9,
+  ];
+  keys.iter().take(protocol::INVENTORY_SLOTS).position(|&k| k == key)
+}
+
 fn key_press<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
@@ -60,11 +173,13 @@ fn key_press<UpdateServer>(
       view::InputMode::Camera => {
         let angle = k * PI / 12.0;
         update_server(RotatePlayer(client.player_id, Vector2::new(angle, 0.0)));
+        client.prediction.lock().unwrap().rotate_lateral(angle);
         view.camera.rotate_lateral(angle);
       },
       view::InputMode::Sun => {
-        view.sun.rotation += k * PI / 512.0;
+        view.sky.rotation += k * PI / 512.0;
       },
+      view::InputMode::Chat | view::InputMode::Crafting => {},
     }
   };
 
@@ -76,68 +191,348 @@ fn key_press<UpdateServer>(
         view.camera.rotate_vertical(angle);
       },
       view::InputMode::Sun => {
-        view.sun.progression += k * PI / 512.0;
+        view.sky.time_of_day += k * PI / 512.0;
       },
+      view::InputMode::Crafting => {
+        view.crafting.move_selection(k < 0.0);
+      },
+      view::InputMode::Chat => {},
     }
   };
 
+  let keys = &client.config.keys;
+
   stopwatch::time("event.key_press", || {
     match key {
-      Keycode::A => {
-        update_server(Walk(client.player_id, Vector3::new(-1.0, 0.0, 0.0)));
+      key if key == keys.left => {
+        walk(client, update_server, view, Vector3::new(-1.0, 0.0, 0.0));
       },
-      Keycode::D => {
-        update_server(Walk(client.player_id, Vector3::new(1.0, 0.0, 0.0)));
+      key if key == keys.right => {
+        walk(client, update_server, view, Vector3::new(1.0, 0.0, 0.0));
       },
-      Keycode::Space => {
-        update_server(StartJump(client.player_id));
+      key if key == keys.jump => {
+        jump(client, update_server, view, true);
       },
-      Keycode::W => {
-        update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, -1.0)));
+      key if key == keys.descend => {
+        view.spectator.set_descending(true);
       },
-      Keycode::S => {
-        update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, 1.0)));
+      key if key == keys.forward => {
+        walk(client, update_server, view, Vector3::new(0.0, 0.0, -1.0));
       },
-      Keycode::Left => {
+      key if key == keys.back => {
+        walk(client, update_server, view, Vector3::new(0.0, 0.0, 1.0));
+      },
+      key if key == keys.look_left => {
         lr(update_server, view, 1.0);
       },
-      Keycode::Right => {
+      key if key == keys.look_right => {
         lr(update_server, view, -1.0);
       },
-      Keycode::Up => {
+      key if key == keys.look_up => {
         ud(update_server, view, 1.0);
       },
-      Keycode::Down => {
+      key if key == keys.look_down => {
         ud(update_server, view, -1.0);
       },
-      Keycode::H => {
+      key if key == keys.toggle_hud => {
         view.show_hud = !view.show_hud;
       },
-      Keycode::M => {
+      key if key == keys.debug_overlay => {
+        view.show_debug = !view.show_debug;
+      },
+      key if key == keys.debug_chunks => {
+        view.debug_lines.show_chunks = !view.debug_lines.show_chunks;
+      },
+      key if key == keys.debug_bounds => {
+        view.debug_lines.show_bounds = !view.debug_lines.show_bounds;
+      },
+      key if key == keys.freeze_frustum => {
+        view.debug_lines.frozen_frustum =
+          match view.debug_lines.frozen_frustum {
+            None => Some(view.camera.projection_matrix()),
+            Some(_) => None,
+          };
+      },
+      key if key == keys.shadow_quality => {
+        let quality = view.shadow.quality().next();
+        info!("Shadow quality: {:?}", quality);
+        view::update::apply_client_to_view(view, view::update::SetShadowQuality(quality));
+      },
+      key if key == keys.tonemapping => {
+        view.post_process.tonemapping = !view.post_process.tonemapping;
+      },
+      key if key == keys.bloom => {
+        view.post_process.bloom = !view.post_process.bloom;
+      },
+      key if key == keys.fxaa => {
+        view.post_process.fxaa = !view.post_process.fxaa;
+      },
+      key if key == keys.sun_mode => {
         view.input_mode =
           match view.input_mode {
             view::InputMode::Camera => view::InputMode::Sun,
             view::InputMode::Sun => view::InputMode::Camera,
+            view::InputMode::Chat => view::InputMode::Chat,
+            view::InputMode::Crafting => view::InputMode::Crafting,
+          };
+      },
+      key if key == keys.crafting => {
+        view.input_mode =
+          match view.input_mode {
+            view::InputMode::Crafting => view::InputMode::Camera,
+            _ => {
+              view.crafting.refresh();
+              view::InputMode::Crafting
+            },
           };
       },
-      Keycode::P => {
+      key if key == keys.chat => {
+        if let view::InputMode::Crafting = view.input_mode {
+          update_server(Craft(client.player_id, view.crafting.selected()));
+        } else {
+          view.input_mode = view::InputMode::Chat;
+          view.chat.start_typing();
+        }
+      },
+      key if key == keys.drop_item => {
+        update_server(DropItem(client.player_id));
+      },
+      key if key == keys.throw => {
+        update_server(Throw(client.player_id));
+      },
+      key if key == keys.brush => {
+        view.brush.next_shape();
+      },
+      key if key == keys.brush_smaller => {
+        view.brush.scale(false);
+      },
+      key if key == keys.brush_larger => {
+        view.brush.scale(true);
+      },
+      key if key == keys.undo => {
+        update_server(Undo(client.player_id));
+      },
+      key if key == keys.redo => {
+        update_server(Redo(client.player_id));
+      },
+      key if key == keys.select => {
+        if let Some(voxel) = target_voxel(view) {
+          view.selection.mark(voxel);
+        }
+      },
+      key if key == keys.copy => {
+        match view.selection.region() {
+          None => view.chat.push_message("Select two corners to copy first."),
+          Some((low, high)) => {
+            update_server(
+              Copy {
+                player : client.player_id,
+                world  : *client.world.lock().unwrap(),
+                low    : low,
+                high   : high,
+              }
+            );
+          },
+        }
+      },
+      key if key == keys.paste => {
+        let schematic = client.clipboard.lock().unwrap().clone();
+        match (schematic, target_voxel(view)) {
+          (None, _) => view.chat.push_message("There's nothing to paste."),
+          (Some(_), None) => {},
+          (Some(schematic), Some(voxel)) => {
+            update_server(
+              Paste {
+                player    : client.player_id,
+                world     : *client.world.lock().unwrap(),
+                position  : voxel + Vector3::new(0, 1, 0),
+                schematic : schematic,
+              }
+            );
+          },
+        }
+      },
+      key if slot_key(key).is_some() => {
+        let slot = slot_key(key).unwrap();
+        view.hotbar.select(slot);
+        hud::make_hud(view);
+        update_server(SelectItem(client.player_id, slot as u32));
+      },
+      key if key == keys.freeze_loading => {
         let mut load_position = client.load_position.lock().unwrap();
         match *load_position {
           None => *load_position = Some(*client.player_position.lock().unwrap()),
           Some(_) => *load_position = None,
         }
       },
+      key if key == keys.third_person => {
+        view.third_person.toggle();
+        if !view.third_person.is_active() && !view.spectator.is_active() {
+          let eye = view.eye;
+          view.camera.translate_to(eye);
+        }
+      },
+      key if key == keys.spectate => {
+        toggle_spectator(client, update_server, view);
+      },
+      key if key == keys.screenshot => {
+        view.screenshots.request();
+      },
+      key if key == keys.timelapse => {
+        let interval = (client.config.timelapse_interval * 1e9) as u64;
+        view.screenshots.toggle_timelapse(interval, time::precise_time_ns());
+      },
+      key if key == keys.map => {
+        view.minimap.fullscreen = !view.minimap.fullscreen;
+      },
+      key if key == keys.map_zoom_in => {
+        view.minimap.zoom(true);
+      },
+      key if key == keys.map_zoom_out => {
+        view.minimap.zoom(false);
+      },
       _ => {},
     }
   })
 }
 
+/// The voxel under the crosshair, if any.
+fn target_voxel(view: &view::T) -> Option<Point3<i32>> {
+  view.target.map(|bounds| {
+    let (low, _) = bounds.corners();
+    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
+  })
+}
+
+/// Handle an event while typing a chat message. Key presses and text go to the chat; anything else, like releasing
+/// a key that was held before typing started, is given back to be handled as usual.
+fn chat_event<UpdateServer>(
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  client: &client::T,
+  event: Event,
+) -> Option<Event> where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  stopwatch::time("event.chat", || {
+    match event {
+      Event::TextInput{text, ..} => {
+        view.chat.type_text(&text);
+      },
+      Event::KeyDown{keycode: Some(Keycode::Backspace), ..} => {
+        view.chat.backspace();
+      },
+      Event::KeyDown{keycode: Some(Keycode::Return), repeat: false, ..} => {
+        view.input_mode = view::InputMode::Camera;
+        if let Some(text) = view.chat.finish_typing() {
+          match (waypoint::parse_command(&text), clipboard::parse_command(&text), teleport_point(&text)) {
+            (Some(command), _, _) => waypoint_command(view, command),
+            (None, Some(command), _) => schematic_command(client, view, command),
+            (None, None, Some(Ok(point))) =>
+              update_server(protocol::ClientToServer::RequestTeleport(client.player_id, point)),
+            (None, None, Some(Err(usage))) => view.chat.push_message(usage),
+            (None, None, None) => update_server(protocol::ClientToServer::Chat(client.player_id, text)),
+          }
+        }
+      },
+      Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+        view.input_mode = view::InputMode::Camera;
+        view.chat.finish_typing();
+      },
+      Event::KeyDown{..} => {},
+      event => return Some(event),
+    }
+    None
+  })
+}
+
+/// The teleport point named by a `/teleport <point>` chat command, if that's what `text` is, or how to use the
+/// command if it's missing the point.
+fn teleport_point(text: &str) -> Option<Result<String, &'static str>> {
+  let mut words = text.trim().splitn(2, ' ');
+  if words.next() != Some("/teleport") {
+    return None
+  }
+  match words.next().map(|point| point.trim()) {
+    None | Some("") => Some(Err("Usage: /teleport <point>")),
+    Some(point) => Some(Ok(String::from(point))),
+  }
+}
+
+/// Carry out a schematic command typed into chat.
+fn schematic_command(client: &client::T, view: &mut view::T, command: Result<clipboard::Command, String>) {
+  let reply =
+    match command {
+      Err(usage) => usage,
+      Ok(clipboard::Command::Save(name)) => {
+        match *client.clipboard.lock().unwrap() {
+          None => String::from("There's nothing copied to save."),
+          Some(ref schematic) => {
+            match clipboard::save(schematic, &name) {
+              Ok(()) => format!("Saved schematic {}.", name),
+              Err(err) => err,
+            }
+          },
+        }
+      },
+      Ok(clipboard::Command::Load(name)) => {
+        match clipboard::load(&name) {
+          Ok(schematic) => {
+            *client.clipboard.lock().unwrap() = Some(schematic);
+            format!("Loaded schematic {}.", name)
+          },
+          Err(err) => err,
+        }
+      },
+    };
+  view.chat.push_message(&reply);
+}
+
+/// Carry out a waypoint command typed into chat.
+fn waypoint_command(view: &mut view::T, command: Result<waypoint::Command, String>) {
+  let eye = view.eye;
+  let reply =
+    match command {
+      Err(usage) => usage,
+      Ok(waypoint::Command::Place(name)) => {
+        view.waypoints.place(&name, eye);
+        format!("Placed waypoint {}.", name)
+      },
+      Ok(waypoint::Command::Remove(name)) => {
+        if view.waypoints.remove(&name) {
+          format!("Removed waypoint {}.", name)
+        } else {
+          format!("There's no waypoint called {} here.", name)
+        }
+      },
+      Ok(waypoint::Command::List) => view.waypoints.describe(&eye),
+    };
+  view.chat.push_message(&reply);
+}
+
 fn mouse_press<UpdateServer>(
-  player_id: entity::id::Player,
+  client: &client::T,
+  view: &view::T,
   update_server: &mut UpdateServer,
   mouse_btn: MouseButton,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  let player_id = client.player_id;
+  let brush = view.brush.brush();
+  // Apply the picked brush where the player's looking.
+  let modify = |update_server: &mut UpdateServer, action| {
+    if let (Some(brush), Some(target)) = (brush, view.target) {
+      update_server(
+        protocol::ClientToServer::ModifyVoxels {
+          player   : player_id,
+          world    : *client.world.lock().unwrap(),
+          brush    : brush,
+          position : target.center(),
+          action   : action,
+        }
+      );
+    }
+  };
+
   stopwatch::time("event.mouse_press", || {
     match mouse_btn {
       MouseButton::Left => {
@@ -145,39 +540,58 @@ fn mouse_press<UpdateServer>(
           protocol::ClientToServer::Add(player_id)
         );
       },
+      MouseButton::Right if brush.is_some() => {
+        modify(update_server, protocol::BrushAction::Remove);
+      },
       MouseButton::Right => {
         update_server(
           protocol::ClientToServer::Remove(player_id)
         );
       },
+      MouseButton::Middle if brush.is_some() => {
+        if let Some(material) = view.hotbar.selected_material() {
+          modify(update_server, protocol::BrushAction::Add(material));
+        }
+      },
+      MouseButton::Middle => {
+        update_server(
+          protocol::ClientToServer::Place(player_id)
+        );
+      },
       _ => {},
     }
   })
 }
 
 fn key_release<UpdateServer>(
-  player_id: entity::id::Player,
+  client: &client::T,
   update_server: &mut UpdateServer,
+  view: &mut view::T,
   key: Keycode,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  let keys = &client.config.keys;
+
   stopwatch::time("event.key_release", || {
     match key {
       // accelerations are negated from those in key_press.
-      Keycode::A => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(1.0, 0.0, 0.0)));
+      key if key == keys.left => {
+        walk(client, update_server, view, Vector3::new(1.0, 0.0, 0.0));
       },
-      Keycode::D => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(-1.0, 0.0, 0.0)));
+      key if key == keys.right => {
+        walk(client, update_server, view, Vector3::new(-1.0, 0.0, 0.0));
       },
-      Keycode::Space => {
-        update_server(protocol::ClientToServer::StopJump(player_id));
+      key if key == keys.jump => {
+        jump(client, update_server, view, false);
       },
-      Keycode::W => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, 1.0)));
+      key if key == keys.descend => {
+        view.spectator.set_descending(false);
       },
-      Keycode::S => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, -1.0)));
+      key if key == keys.forward => {
+        walk(client, update_server, view, Vector3::new(0.0, 0.0, 1.0));
+      },
+      key if key == keys.back => {
+        walk(client, update_server, view, Vector3::new(0.0, 0.0, -1.0));
       },
       _ => {}
     }
@@ -186,19 +600,21 @@ fn key_release<UpdateServer>(
 
 // x and y are relative to last position.
 fn mouse_move<UpdateServer>(
-  player_id: entity::id::Player,
+  client: &client::T,
   update_server: &mut UpdateServer,
   view: &mut view::T,
   dx: i32, dy: i32,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  let player_id = client.player_id;
   stopwatch::time("event.mouse_move", || {
     let d = Vector2::new(dx, dy);
     // To-radians coefficient. Numbers closer to zero dull the mouse movement more.
-    let to_radians = Vector2::new(-1.0 / 1000.0, -1.0 / 1600.0);
+    let to_radians = Vector2::new(-1.0 / 1000.0, -1.0 / 1600.0) * client.config.mouse_sensitivity;
     let r = Vector2::new(d.x as f32 * to_radians.x, d.y as f32 * to_radians.y);
 
     update_server(protocol::ClientToServer::RotatePlayer(player_id, r));
+    client.prediction.lock().unwrap().rotate_lateral(r.x);
     view.camera.rotate_lateral(r.x);
     view.camera.rotate_vertical(r.y);
   })
@@ -0,0 +1,99 @@
+//! Getting back in touch with the server when the connection drops. Reconnecting is tried over and over, waiting
+//! longer after each failure, and each new connection resumes this client's session, so it keeps its player.
+
+use std;
+use std::sync::Mutex;
+use std::time::Duration;
+use time;
+
+use common::protocol;
+
+use client;
+use server;
+use view;
+
+/// How long to wait before the first try at reconnecting.
+const INITIAL_BACKOFF_MS: u64 = 250;
+/// The longest to wait between tries.
+const MAX_BACKOFF_MS: u64 = 30_000;
+/// A connection that lasted this long was healthy, so if it drops, the waits start over from the shortest.
+const STABLE_NS: u64 = 60_000_000_000;
+/// How often to check on the connection.
+const POLL_MS: u64 = 100;
+
+/// How long to wait before the `attempt`th try at reconnecting, counting from 0.
+pub fn backoff(attempt: u32) -> Duration {
+  let ms = INITIAL_BACKOFF_MS.saturating_mul(1 << attempt.min(16)).min(MAX_BACKOFF_MS);
+  Duration::from_millis(ms)
+}
+
+/// Sleep for about `duration`, waking early if `quit` is set. Returns whether it was.
+fn sleep_unless_quit(quit: &Mutex<bool>, duration: Duration) -> bool {
+  let poll = Duration::from_millis(POLL_MS);
+  let mut slept = Duration::from_millis(0);
+  while slept < duration {
+    if *quit.lock().unwrap() {
+      return true
+    }
+    let step = std::cmp::min(poll, duration - slept);
+    std::thread::sleep(step);
+    slept += step;
+  }
+  *quit.lock().unwrap()
+}
+
+/// Watch the connection to the server until `quit` is set, and whenever it drops, reconnect and resume.
+pub fn reconnect_thread<UpdateView>(
+  quit        : &Mutex<bool>,
+  client      : &client::T,
+  server      : &server::T,
+  listen_url  : &str,
+  update_view : &mut UpdateView,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  let mut attempt = 0;
+  let mut connected_ns = time::precise_time_ns();
+  loop {
+    if sleep_unless_quit(quit, Duration::from_millis(POLL_MS)) {
+      return
+    }
+    if server.is_connected() {
+      continue
+    }
+
+    if time::precise_time_ns() - connected_ns >= STABLE_NS {
+      attempt = 0;
+    }
+    update_view(view::update::ShowChatMessage("Lost the connection to the server. Reconnecting...".to_owned()));
+
+    loop {
+      if sleep_unless_quit(quit, backoff(attempt)) {
+        return
+      }
+      attempt += 1;
+      let resume =
+        protocol::ClientToServer::Resume {
+          client_id : client.id,
+          token     : client.session,
+          hello     : protocol::hello(listen_url),
+        };
+      if server.reconnect(&resume) {
+        break
+      }
+      debug!("Reconnection attempt {} failed; trying again in {:?}", attempt, backoff(attempt));
+    }
+
+    info!("Reconnected after {} attempts, and asked to resume our session", attempt);
+    connected_ns = time::precise_time_ns();
+  }
+}
+
+#[test]
+fn backoff_doubles_up_to_a_limit() {
+  assert_eq!(backoff(0), Duration::from_millis(INITIAL_BACKOFF_MS));
+  assert_eq!(backoff(1), Duration::from_millis(2 * INITIAL_BACKOFF_MS));
+  assert_eq!(backoff(3), Duration::from_millis(8 * INITIAL_BACKOFF_MS));
+  assert_eq!(backoff(20), Duration::from_millis(MAX_BACKOFF_MS));
+  assert_eq!(backoff(std::u32::MAX), Duration::from_millis(MAX_BACKOFF_MS));
+}
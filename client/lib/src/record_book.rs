@@ -9,7 +9,7 @@ pub struct ChunkLoad {
   pub response_time_ns  : u64,
   /// when the voxels were loaded into the client terrain cache
   pub stored_time_ns    : u64,
-  /// when the voxels were turned into mesh and enqueued into vram queues
+  /// when the chunks the voxels completed were queued to be meshed
   pub loaded_time_ns    : u64,
 }
 
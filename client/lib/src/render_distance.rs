@@ -0,0 +1,132 @@
+//! Shrink and grow how far away terrain is loaded, to hold a target frame rate.
+
+use std;
+
+use lod;
+
+/// Terrain is always loaded at least this many chunks away.
+const MIN_DISTANCE: u32 = 8;
+/// How many chunks the distance changes by at once.
+const STEP: u32 = 2;
+/// How much each frame moves the average frame time.
+const SMOOTHING: f64 = 0.05;
+/// Frames slower than this portion of the target frame time shrink the distance.
+const SHRINK_ABOVE: f64 = 1.0;
+/// Frames faster than this portion of the target frame time grow the distance. The gap between this and
+/// `SHRINK_ABOVE` keeps a distance that's just right from flipping back and forth.
+const GROW_BELOW: f64 = 0.6;
+/// Nanoseconds to wait after a change before making another, so the change has time to show up in frame times.
+const COOLDOWN_NS: u64 = 2_000_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The furthest the distance can grow to.
+  max_distance    : u32,
+  /// The LOD thresholds to use at `max_distance`. Shorter distances scale them down.
+  max_thresholds  : [u32; lod::COUNT - 1],
+  /// The target time per frame, in nanoseconds. If this is None, the distance stays at `max_distance`.
+  target_frame_ns : Option<f64>,
+
+  distance        : u32,
+  /// A running average of frame times, in nanoseconds.
+  frame_ns        : Option<f64>,
+  /// When the distance last changed.
+  last_change     : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new(max_distance: u32, max_thresholds: [u32; lod::COUNT - 1], target_fps: f32) -> T {
+  T {
+    max_distance    : max_distance,
+    max_thresholds  : max_thresholds,
+    target_frame_ns : if target_fps > 0.0 { Some(1e9 / target_fps as f64) } else { None },
+
+    distance        : max_distance,
+    frame_ns        : None,
+    last_change     : 0,
+  }
+}
+
+impl T {
+  /// How far away to load terrain, in chunks.
+  pub fn distance(&self) -> u32 {
+    self.distance
+  }
+
+  /// The chunk distances at which terrain switches to the next LOD, scaled down with the distance.
+  pub fn lod_thresholds(&self) -> [u32; lod::COUNT - 1] {
+    let mut thresholds = self.max_thresholds;
+    for threshold in &mut thresholds {
+      *threshold = (*threshold * self.distance + self.max_distance / 2) / self.max_distance;
+    }
+    thresholds
+  }
+
+  /// Count a frame that took `frame_ns` nanoseconds to draw, finishing at `now`. Returns whether the distance
+  /// changed.
+  pub fn frame(&mut self, frame_ns: u64, now: u64) -> bool {
+    let target_frame_ns =
+      match self.target_frame_ns {
+        None => return false,
+        Some(target_frame_ns) => target_frame_ns,
+      };
+
+    let average =
+      match self.frame_ns {
+        None => frame_ns as f64,
+        Some(average) => average + (frame_ns as f64 - average) * SMOOTHING,
+      };
+    self.frame_ns = Some(average);
+
+    if now < self.last_change + COOLDOWN_NS {
+      return false
+    }
+
+    let distance =
+      if average > target_frame_ns * SHRINK_ABOVE {
+        std::cmp::max(self.distance.saturating_sub(STEP), std::cmp::min(MIN_DISTANCE, self.max_distance))
+      } else if average < target_frame_ns * GROW_BELOW {
+        std::cmp::min(self.distance + STEP, self.max_distance)
+      } else {
+        self.distance
+      };
+
+    if distance == self.distance {
+      return false
+    }
+    info!("Render distance {} -> {} ({:.1}ms per frame)", self.distance, distance, average / 1e6);
+    self.distance = distance;
+    self.last_change = now;
+    true
+  }
+}
+
+#[test]
+fn slow_frames_shrink_the_distance_until_they_speed_up() {
+  let mut render_distance = new(40, [1, 15, 31, 47], 30.0);
+  let mut now = 0;
+  let mut frame = |render_distance: &mut T, ms: u64| {
+    now += 33_000_000;
+    render_distance.frame(ms * 1_000_000, now)
+  };
+
+  for _ in 0 .. 100 {
+    frame(&mut render_distance, 50);
+  }
+  let shrunk = render_distance.distance();
+  assert!(shrunk < 40);
+  assert!(shrunk >= MIN_DISTANCE);
+  assert!(render_distance.lod_thresholds()[1] < 15);
+
+  // Frame times between the two thresholds leave the distance alone.
+  for _ in 0 .. 1000 {
+    frame(&mut render_distance, 25);
+  }
+  assert_eq!(render_distance.distance(), shrunk);
+
+  for _ in 0 .. 1000 {
+    frame(&mut render_distance, 5);
+  }
+  assert_eq!(render_distance.distance(), 40);
+  assert_eq!(render_distance.lod_thresholds(), [1, 15, 31, 47]);
+}
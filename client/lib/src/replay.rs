@@ -0,0 +1,164 @@
+//! Record what the server sends, and play it back later in place of a server.
+//! A recording is a series of messages, each prefixed with when it arrived (nanoseconds since recording started,
+//! as a little-endian u64) and its length (a little-endian u32).
+
+use std;
+use std::io::{Read, Write};
+use time;
+
+use server;
+
+/// Where messages from the server come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+  /// Talk to a server as usual.
+  Live,
+  /// Talk to a server as usual, and write everything it sends to a file.
+  Record(String),
+  /// Don't talk to a server; play back a recording instead.
+  Playback(String),
+}
+
+impl Mode {
+  /// Take `--record=<file>` or `--replay=<file>` out of command-line arguments.
+  pub fn from_args(args: &mut Vec<String>) -> Mode {
+    const RECORD: &'static str = "--record=";
+    const REPLAY: &'static str = "--replay=";
+    let mut mode = Mode::Live;
+    args.retain(|arg| {
+      if arg.starts_with(RECORD) {
+        mode = Mode::Record(arg[RECORD.len() ..].to_owned());
+        false
+      } else if arg.starts_with(REPLAY) {
+        mode = Mode::Playback(arg[REPLAY.len() ..].to_owned());
+        false
+      } else {
+        true
+      }
+    });
+    mode
+  }
+}
+
+fn write_entry<W: Write>(w: &mut W, at_ns: u64, msg: &[u8]) -> std::io::Result<()> {
+  let len = msg.len() as u32;
+  // Build the whole entry first, so an interrupted client leaves at most one partial entry.
+  let mut entry = Vec::with_capacity(12 + msg.len());
+  for i in 0 .. 8 {
+    entry.push((at_ns >> (8 * i)) as u8);
+  }
+  for i in 0 .. 4 {
+    entry.push((len >> (8 * i)) as u8);
+  }
+  entry.extend_from_slice(msg);
+  w.write_all(&entry)
+}
+
+/// Read one entry. `None` at the end of the recording.
+fn read_entry<R: Read>(r: &mut R) -> Option<(u64, Vec<u8>)> {
+  let mut header = [0; 12];
+  if r.read_exact(&mut header).is_err() {
+    return None
+  }
+  let at_ns = header[.. 8].iter().rev().fold(0, |n, &b| (n << 8) | b as u64);
+  let len = header[8 ..].iter().rev().fold(0, |n, &b| (n << 8) | b as u32);
+  let mut msg = vec!(0; len as usize);
+  if r.read_exact(&mut msg).is_err() {
+    warn!("Recording ends partway through a message");
+    return None
+  }
+  Some((at_ns, msg))
+}
+
+/// Writes messages from the server to a file as they arrive.
+pub struct Recorder {
+  file  : std::fs::File,
+  start : u64,
+}
+
+impl Recorder {
+  #[allow(missing_docs)]
+  pub fn create(path: &str) -> Recorder {
+    let file = std::fs::File::create(path).unwrap_or_else(|err| panic!("Couldn't create {}: {}", path, err));
+    info!("Recording server messages to {}", path);
+    Recorder {
+      file  : file,
+      start : time::precise_time_ns(),
+    }
+  }
+
+  /// Record a serialized `ServerToClient` message.
+  pub fn record(&mut self, msg: &[u8]) {
+    let at_ns = time::precise_time_ns() - self.start;
+    if let Err(err) = write_entry(&mut self.file, at_ns, msg) {
+      warn!("Couldn't record server message: {}", err);
+    }
+  }
+}
+
+/// A stand-in for a server connection that plays back the recording at `path`,
+/// with the same timing the messages originally arrived with. Anything sent to it is dropped.
+pub fn playback(path: &str) -> server::T {
+  let file = std::fs::File::open(path).unwrap_or_else(|err| panic!("Couldn't open {}: {}", path, err));
+  info!("Playing back server messages from {}", path);
+
+  let (talk_send, talk_recv) = std::sync::mpsc::channel::<Box<[u8]>>();
+  let (listen_send, listen_recv) = std::sync::mpsc::channel();
+
+  std::thread::spawn(move || {
+    while talk_recv.recv().is_ok() {}
+  });
+
+  std::thread::spawn(move || {
+    let mut file = std::io::BufReader::new(file);
+    let start = time::precise_time_ns();
+    while let Some((at_ns, msg)) = read_entry(&mut file) {
+      let now = time::precise_time_ns() - start;
+      if at_ns > now {
+        std::thread::sleep(nanos(at_ns - now));
+      }
+      if listen_send.send(msg.into_boxed_slice()).is_err() {
+        return
+      }
+    }
+    info!("Replay finished");
+    // Hang on to the channel, so the client keeps running (and can be looked around) after the replay ends.
+    loop {
+      std::thread::park();
+    }
+  });
+
+  server::T {
+    talk   : server::SSender::new(talk_send),
+    listen : server::SReceiver::new(listen_recv),
+    link   : None,
+  }
+}
+
+fn nanos(ns: u64) -> std::time::Duration {
+  std::time::Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32)
+}
+
+#[test]
+fn recordings_play_back_in_order() {
+  let mut recording = Vec::new();
+  write_entry(&mut recording, 5, &[1, 2, 3]).unwrap();
+  write_entry(&mut recording, 1 << 40, &[]).unwrap();
+  write_entry(&mut recording, 1 << 41, &[4]).unwrap();
+  // A client killed mid-write.
+  recording.extend_from_slice(&[0; 5]);
+
+  let mut r = std::io::Cursor::new(recording);
+  assert_eq!(read_entry(&mut r), Some((5, vec!(1, 2, 3))));
+  assert_eq!(read_entry(&mut r), Some((1 << 40, vec!())));
+  assert_eq!(read_entry(&mut r), Some((1 << 41, vec!(4))));
+  assert_eq!(read_entry(&mut r), None);
+}
+
+#[test]
+fn replay_flags_are_removed_from_args() {
+  let mut args = vec!(String::from("a"), String::from("--replay=bug.rec"), String::from("b"));
+  assert_eq!(Mode::from_args(&mut args), Mode::Playback(String::from("bug.rec")));
+  assert_eq!(args, vec!(String::from("a"), String::from("b")));
+  assert_eq!(Mode::from_args(&mut args), Mode::Live);
+}
@@ -0,0 +1,151 @@
+//! Decode the entity snapshots sent by the server, and work out where entities are shown in between them.
+
+use std::collections::VecDeque;
+
+use common::movement;
+use common::protocol;
+use common::snapshot;
+
+/// How far behind the server entities are shown, in ns. This is a few snapshots' worth, so there's usually a
+/// snapshot on either side of what's shown, even when one is late or lost.
+pub const INTERPOLATION_DELAY_NS: u64 = 3 * 1_000_000_000 / movement::UPDATES_PER_SECOND;
+
+/// The snapshots received so far.
+pub struct T {
+  /// Recent snapshots, oldest first.
+  received : VecDeque<(protocol::SnapshotId, snapshot::World)>,
+  /// What's being shown, as of the last `show`.
+  shown    : Option<snapshot::World>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    received : VecDeque::new(),
+    shown    : None,
+  }
+}
+
+impl T {
+  /// What's being shown.
+  pub fn shown(&self) -> Option<&snapshot::World> {
+    self.shown.as_ref()
+  }
+
+  /// Forget the snapshots received so far, e.g. because the server's starting over on this client's snapshots.
+  /// What's shown stays until the next `show`, which changes it to the new snapshots.
+  pub fn start_over(&mut self) {
+    self.received.clear();
+  }
+
+  /// Decode a snapshot, and return what's changed since the last one received.
+  /// Returns `None` if the snapshot can't be used, because it's older than the last one or its baseline is gone.
+  pub fn receive(&mut self, snapshot: &protocol::Snapshot) -> Option<protocol::Snapshot> {
+    if self.received.back().map_or(false, |&(id, _)| id >= snapshot.id) {
+      return None
+    }
+
+    let world =
+      match snapshot.baseline {
+        None => snapshot::empty().apply(snapshot),
+        Some(baseline) => {
+          match self.received.iter().find(|&&(id, _)| id == baseline) {
+            None => {
+              debug!("Dropping snapshot {:?}, whose baseline {:?} is gone", snapshot.id, baseline);
+              return None
+            },
+            Some(&(_, ref baseline)) => baseline.apply(snapshot),
+          }
+        },
+      };
+
+    let changes =
+      match self.received.back() {
+        None => world.delta(snapshot.id, None),
+        Some(&(last_id, ref last)) => world.delta(snapshot.id, Some((last_id, last))),
+      };
+
+    self.received.push_back((snapshot.id, world));
+    while self.received.len() > snapshot::MAX_BASELINE_AGE as usize + 1 {
+      self.received.pop_front();
+    }
+
+    Some(changes)
+  }
+
+  /// The entities as they were `INTERPOLATION_DELAY_NS` before the server's time `server_ns`, or as of the last
+  /// snapshot if the server's time isn't known yet.
+  fn at(&self, server_ns: Option<u64>) -> Option<snapshot::World> {
+    let last = &self.received.back()?.1;
+    let time_ns =
+      match server_ns {
+        None => return Some(last.clone()),
+        Some(server_ns) => server_ns.saturating_sub(INTERPOLATION_DELAY_NS),
+      };
+    match self.received.iter().position(|&(_, ref world)| world.time_ns > time_ns) {
+      // Snapshots are running late, so stay on the last one until the next arrives.
+      None => Some(last.clone()),
+      Some(0) => Some(self.received[0].1.clone()),
+      Some(i) => {
+        let earlier = &self.received[i - 1].1;
+        let later = &self.received[i].1;
+        let t = (time_ns - earlier.time_ns) as f32 / (later.time_ns - earlier.time_ns) as f32;
+        Some(snapshot::interpolate(earlier, later, t))
+      },
+    }
+  }
+
+  /// Move what's shown up to the server's time `server_ns`; see `at`. Returns what's changed since the last `show`.
+  pub fn show(&mut self, server_ns: Option<u64>) -> Option<protocol::Snapshot> {
+    let world = self.at(server_ns)?;
+    let id = self.received.back()?.0;
+    let changes = world.delta(id, self.shown.as_ref().map(|shown| (id, shown)));
+    self.shown = Some(world);
+    Some(changes)
+  }
+}
+
+#[test]
+fn entities_are_shown_in_between_snapshots() {
+  use cgmath::Point3;
+  use collision::Aabb3;
+  use common::entity;
+  use common::health;
+
+  let id = entity::id::Mob::default();
+  let world = |time_ns: u64, x: f32| {
+    let mut world = snapshot::empty();
+    world.time_ns = time_ns;
+    world.mobs.insert(
+      id,
+      protocol::MobState {
+        id        : id,
+        bounds    : Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 1.0, 1.0)),
+        heading   : 0.0,
+        animation : protocol::Animation::Walk,
+        health    : health::full(health::MOB_MAX),
+      },
+    );
+    world
+  };
+
+  let mut replication = new();
+  assert!(replication.show(None).is_none());
+  let first = world(1_000, 0.0).delta(protocol::SnapshotId(0), None);
+  replication.receive(&first);
+  let second = world(2_000, 2.0).delta(protocol::SnapshotId(1), None);
+  replication.receive(&second);
+
+  // Without the server's time, the last snapshot's shown.
+  assert_eq!(replication.show(None).unwrap().mobs[0].bounds.min.x, 2.0);
+
+  let x = |replication: &mut T, server_ns| {
+    replication.show(Some(server_ns + INTERPOLATION_DELAY_NS));
+    replication.shown().unwrap().mobs[&id].bounds.min.x
+  };
+  assert_eq!(x(&mut replication, 500), 0.0);
+  assert_eq!(x(&mut replication, 1_250), 0.5);
+  // Nothing's changed since the last `show`.
+  assert!(replication.show(Some(1_250 + INTERPOLATION_DELAY_NS)).unwrap().mobs.is_empty());
+  assert_eq!(x(&mut replication, 3_000), 2.0);
+}
@@ -6,19 +6,24 @@ use std::sync::{Mutex};
 use stopwatch;
 use thread_scoped;
 
+use common::net;
 use common::protocol;
 
 use audio_loader;
 use audio_thread;
 use client;
+use config;
+use reconnect;
 use record_book;
+use replay;
 use server;
 use terrain;
 use update_thread::update_thread;
 use view::thread::view_thread;
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, server_url: &str) {
+/// Run the client until the window is closed. `replay` decides whether messages come from the server at `server_url`,
+/// or from a recording.
+pub fn run(listen_url: &str, server_url: &str, transport: net::Transport, replay: replay::Mode) {
   let view_updates0 = Mutex::new(std::collections::VecDeque::new());
   let view_updates1 = Mutex::new(std::collections::VecDeque::new());
   let audio_updates = Mutex::new(std::collections::VecDeque::new());
@@ -26,7 +31,20 @@ pub fn run(listen_url: &str, server_url: &str) {
   let quit = Mutex::new(false);
   let quit = &quit;
 
-  let server = server::new(&server_url, &listen_url);
+  let server =
+    match replay {
+      replay::Mode::Live => server::new(&server_url, &listen_url, transport),
+      replay::Mode::Record(path) => server::recorded(&server_url, &listen_url, transport, replay::Recorder::create(&path)),
+      replay::Mode::Playback(path) => Ok(replay::playback(&path)),
+    };
+  let server =
+    match server {
+      Ok(server) => server,
+      Err(err) => {
+        error!("Couldn't listen on {}: {}", listen_url, err);
+        return
+      },
+    };
 
   let client = connect_client(&listen_url, &server);
   let client = &client;
@@ -36,7 +54,7 @@ pub fn run(listen_url: &str, server_url: &str) {
       unsafe {
         thread_scoped::scoped(|| {
           while !*quit.lock().unwrap() {
-            info!("Outstanding terrain requests: {}", *client.pending_terrain_requests.lock().unwrap());
+            info!("Outstanding terrain requests: {}", client.chunk_requests.lock().unwrap().outstanding_len());
             info!("Outstanding voxel updates: {}", client.terrain.lock().unwrap().queued_update_count());
             info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
             info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
@@ -46,6 +64,22 @@ pub fn run(listen_url: &str, server_url: &str) {
       }
     };
 
+    let reconnect_thread = {
+      let server = server.clone();
+      let view_updates0 = &view_updates0;
+      unsafe {
+        thread_scoped::scoped(move || {
+          reconnect::reconnect_thread(
+            quit,
+            client,
+            &server,
+            listen_url,
+            &mut |up| { view_updates0.lock().unwrap().push_back(up) },
+          );
+        })
+      }
+    };
+
     let audio_thread = {
       let audio_updates = &audio_updates;
       unsafe {
@@ -59,7 +93,9 @@ pub fn run(listen_url: &str, server_url: &str) {
     };
 
     if (1 + 1) - 1 == 0 {
-      audio_updates.lock().unwrap().push_back(audio_thread::Message::PlayLoop(audio_loader::SoundId::Rainforest));
+      // Ambience is heard around where the player spawned.
+      let spawn = *client.player_position.lock().unwrap();
+      audio_updates.lock().unwrap().push_back(audio_thread::Message::PlayLoop(audio_loader::SoundId::Rainforest, spawn));
     }
 
     let update_thread = {
@@ -81,8 +117,8 @@ pub fn run(listen_url: &str, server_url: &str) {
             &mut |msg| {
               match msg {
                 terrain::Load::Voxels { time_requested: None, .. } => {},
-                terrain::Load::Voxels { time_requested: Some(_), .. } => {
-                  *client.pending_terrain_requests.lock().unwrap() -= 1;
+                terrain::Load::Voxels { time_requested: Some(time_requested), .. } => {
+                  client.chunk_requests.lock().unwrap().received(time_requested);
                 }
               };
               client.terrain.lock().unwrap().enqueue(msg);
@@ -118,6 +154,7 @@ pub fn run(listen_url: &str, server_url: &str) {
         &mut || { view_updates0.lock().unwrap().pop_front() },
         &mut || { view_updates1.lock().unwrap().pop_front() },
         &mut |server_update| { server.talk.tell(&server_update) },
+        &mut |up| { audio_updates.lock().unwrap().push_back(up) },
       );
 
       stopwatch::clone().print();
@@ -128,6 +165,7 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     audio_thread.join();
     monitor_thread.join();
+    reconnect_thread.join();
 
     let stopwatch = update_thread.join();
 
@@ -135,18 +173,24 @@ pub fn run(listen_url: &str, server_url: &str) {
   }
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+/// Join the server's game, and set up this client's state once it says where our player is.
+pub fn connect_client(listen_url: &str, server: &server::T) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  let config = config::load();
+  server.talk.tell(&protocol::ClientToServer::Init(protocol::hello(listen_url)));
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+      protocol::ServerToClient::Incompatible { reason, .. } => {
+        panic!("Couldn't join the server: {}", reason);
+      },
+      protocol::ServerToClient::LeaseId { client_id, seed, session, features } => {
+        info!("Joined as {:?}, with {:?}", client_id, features);
+        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id, config.name.clone()));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              return client::new(client_id, session, player_id, position, seed, config);
             },
             msg => {
               // Ignore other messages in the meantime.
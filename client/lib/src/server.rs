@@ -5,11 +5,17 @@ use std;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Sender, Receiver, TryRecvError};
 use std::sync::atomic::{Ordering, AtomicUsize};
-
-use bincode;
+use std::time::Duration;
 
 use common::protocol;
-use common::socket::{SendSocket, ReceiveSocket};
+use common::net;
+use common::wire;
+
+use replay;
+
+/// How long socket operations can block. The connection's considered lost if nothing's heard from the server
+/// in this long, since it sends something every tick.
+const SOCKET_TIMEOUT_SECS: u64 = 30;
 
 #[allow(missing_docs)]
 #[derive(Clone)]
@@ -31,7 +37,7 @@ impl SSender {
 
   #[allow(missing_docs)]
   pub fn tell(&self, msg: &protocol::ClientToServer) {
-    let msg = bincode::serialize(msg, bincode::Infinite).unwrap();
+    let msg = wire::encode(msg);
     // We aren't reading this until long after the write, so we use `Relaxed`
     self.bytes_sent.fetch_add(msg.len() as usize, Ordering::Relaxed);
     self.sender.send(msg.into_boxed_slice()).unwrap();
@@ -43,22 +49,71 @@ impl SSender {
 pub struct SReceiver (Arc<Mutex<Receiver<Box<[u8]>>>>);
 
 impl SReceiver {
+  #[allow(missing_docs)]
+  pub fn new(receiver: Receiver<Box<[u8]>>) -> SReceiver {
+    SReceiver(Arc::new(Mutex::new(receiver)))
+  }
+
   #[allow(missing_docs)]
   pub fn try(&self) -> Option<protocol::ServerToClient> {
-    match self.0.lock().unwrap().try_recv() {
-      Ok(msg) => Some(bincode::deserialize(&Vec::from(msg)).unwrap()),
-      Err(TryRecvError::Empty) => None,
-      e => {
-        e.unwrap();
-        unreachable!();
-      },
+    loop {
+      match self.0.lock().unwrap().try_recv() {
+        Ok(msg) => {
+          if let Some(msg) = decode(msg.as_ref()) {
+            return Some(msg)
+          }
+        },
+        Err(TryRecvError::Empty) => return None,
+        e => {
+          e.unwrap();
+          unreachable!();
+        },
+      }
     }
   }
 
   #[allow(missing_docs)]
   pub fn wait(&self) -> protocol::ServerToClient {
-    let msg = self.0.lock().unwrap().recv().unwrap();
-    bincode::deserialize(msg.as_ref()).unwrap()
+    loop {
+      let msg = self.0.lock().unwrap().recv().unwrap();
+      if let Some(msg) = decode(msg.as_ref()) {
+        return msg
+      }
+    }
+  }
+}
+
+/// Decode a message from the server, or skip it if it can't be.
+fn decode(msg: &[u8]) -> Option<protocol::ServerToClient> {
+  match wire::decode(msg) {
+    Ok(msg) => Some(msg),
+    Err(wire::Error::Unknown(tag)) => {
+      debug!("Skipping a kind of message we don't know, tagged {:x}", tag);
+      None
+    },
+    Err(wire::Error::Malformed) => {
+      warn!("Skipping a message that couldn't be read ({} bytes)", msg.len());
+      None
+    },
+  }
+}
+
+/// The socket to a live server, which is lost when sending on it fails or the server goes quiet, and can be
+/// connected again.
+pub struct Link {
+  server_url : String,
+  transport  : net::Transport,
+  /// None while the connection is lost. Messages sent meanwhile are dropped.
+  socket     : Mutex<Option<Box<dyn net::Sender>>>,
+}
+
+impl Link {
+  fn lose(&self, why: &str) {
+    let mut socket = self.socket.lock().unwrap();
+    if socket.is_some() {
+      warn!("Lost the connection to the server: {}", why);
+      *socket = None;
+    }
   }
 }
 
@@ -67,29 +122,91 @@ impl SReceiver {
 pub struct T {
   pub talk   : SSender,
   pub listen : SReceiver,
+  /// The connection to the server. This is None when there's no live server, e.g. when playing back a recording.
+  pub link   : Option<Arc<Link>>,
 }
 
-#[allow(missing_docs)]
+impl T {
+  /// Whether messages are getting through to the server.
+  pub fn is_connected(&self) -> bool {
+    self.link.as_ref().map_or(true, |link| link.socket.lock().unwrap().is_some())
+  }
+
+  /// Make one attempt to connect to the server again. If it works, `hello` is the first message sent over the new
+  /// connection, ahead of anything else.
+  pub fn reconnect(&self, hello: &protocol::ClientToServer) -> bool {
+    let link =
+      match self.link {
+        None => return true,
+        Some(ref link) => link,
+      };
+    let timeout = Some(Duration::from_secs(SOCKET_TIMEOUT_SECS));
+    let mut socket =
+      match link.transport.try_connect(link.server_url.as_ref(), timeout) {
+        Err(err) => {
+          debug!("Couldn't reconnect to {}: {:?}", link.server_url, err);
+          return false
+        },
+        Ok(socket) => socket,
+      };
+    let hello = wire::encode(hello);
+    if let Err(err) = socket.write(hello.as_ref()) {
+      debug!("Couldn't reconnect to {}: {:?}", link.server_url, err);
+      return false
+    }
+    *link.socket.lock().unwrap() = Some(socket);
+    true
+  }
+}
+
+/// Listen on `listen_url` for what the server sends, and connect to it in the background. Fails if `listen_url`
+/// can't be listened on.
 pub fn new(
   server_url: &str,
   listen_url: &str,
-) -> T {
+  transport: net::Transport,
+) -> std::io::Result<T> {
+  connect(server_url, listen_url, transport, None)
+}
+
+/// Like `new`, but also record everything the server sends.
+pub fn recorded(
+  server_url: &str,
+  listen_url: &str,
+  transport: net::Transport,
+  recorder: replay::Recorder,
+) -> std::io::Result<T> {
+  connect(server_url, listen_url, transport, Some(recorder))
+}
+
+fn connect(
+  server_url: &str,
+  listen_url: &str,
+  transport: net::Transport,
+  mut recorder: Option<replay::Recorder>,
+) -> std::io::Result<T> {
+  let mut listen_socket = transport.bind(listen_url, Some(Duration::from_secs(SOCKET_TIMEOUT_SECS)))?;
   let (send_send, send_recv) = std::sync::mpsc::channel();
   let (recv_send, recv_recv) = std::sync::mpsc::channel();
 
+  let link =
+    Arc::new(Link {
+      server_url : server_url.to_owned(),
+      transport  : transport,
+      socket     : Mutex::new(None),
+    });
+
   let _recv_thread ={
-    let listen_url = listen_url.to_owned();
     let recv_send = recv_send.clone();
+    let link = link.clone();
     std::thread::spawn(move || {
-      let mut listen_socket =
-        ReceiveSocket::new(
-          listen_url.clone().as_ref(),
-          Some(std::time::Duration::from_secs(30)),
-        );
       loop {
         match listen_socket.read() {
-          None => break,
+          None => link.lose("the server has gone quiet"),
           Some(msg) => {
+            if let Some(ref mut recorder) = recorder {
+              recorder.record(&msg);
+            }
             recv_send.send(msg.into_boxed_slice()).unwrap()
           },
         }
@@ -99,26 +216,34 @@ pub fn new(
 
   let _send_thread = {
     let server_url = server_url.to_owned();
+    let link = link.clone();
     std::thread::spawn(move || {
-      let mut talk_socket =
-        SendSocket::new(
-          server_url.as_ref(),
-          Some(std::time::Duration::from_secs(30)),
-        );
+      // If this doesn't work, there's no connection, which is tried again like a lost one.
+      match transport.connect(server_url.as_ref(), Some(Duration::from_secs(SOCKET_TIMEOUT_SECS))) {
+        Ok(socket) => *link.socket.lock().unwrap() = Some(socket),
+        Err(err) => warn!("Couldn't connect to {}: {:?}", server_url, err),
+      }
       loop {
         match send_recv.recv() {
           Err(_) => break,
           Ok(msg) => {
-            let msg = Vec::from(msg);
-            talk_socket.write(msg.as_ref()).unwrap();
+            let result =
+              match *link.socket.lock().unwrap() {
+                None => Ok(()),
+                Some(ref mut socket) => socket.write(msg.as_ref()),
+              };
+            if let Err(err) = result {
+              link.lose(&format!("{:?}", err));
+            }
           },
         }
       }
     })
   };
 
-  T {
+  Ok(T {
     talk: SSender::new(send_send),
-    listen: SReceiver(Arc::new(Mutex::new(recv_recv))),
-  }
+    listen: SReceiver::new(recv_recv),
+    link: Some(link),
+  })
 }
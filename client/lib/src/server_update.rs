@@ -2,25 +2,30 @@
 
 use cgmath;
 use cgmath::{Point3, EuclideanSpace, InnerSpace, ElementWise};
-use collision::{Aabb3};
+use collision::{Aabb, Aabb3};
 use rand::Rng;
 use stopwatch;
 use time;
 
 use common::color::Color4;
+use common::health;
+use common::item;
+use common::journal;
 use common::protocol;
+use common::surroundings_loader;
 
 use audio_loader;
 use audio_thread;
+use chunk;
+use chunk_requests;
 use client;
+use particles;
+use prediction;
 use terrain;
+use vertex;
 use vertex::ColoredVertex;
 use view;
 
-const TRIANGLES_PER_BOX: u32 = 12;
-const VERTICES_PER_TRIANGLE: u32 = 3;
-const TRIANGLE_VERTICES_PER_BOX: u32 = TRIANGLES_PER_BOX * VERTICES_PER_TRIANGLE;
-
 /// dispatch a server message among the subsystems
 pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrainLoad>(
   client               : &client::T,
@@ -37,72 +42,279 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
 {
   stopwatch::time("apply_server_update", move || {
     match update {
-      protocol::ServerToClient::LeaseId(_) => {
+      protocol::ServerToClient::Incompatible { reason, .. } => {
+        warn!("The server turned us away: {}", reason);
+        update_view(view::update::ShowChatMessage(reason));
+      },
+      protocol::ServerToClient::LeaseId { .. } => {
         warn!("Client ID has already been leased.");
       },
-      protocol::ServerToClient::Ping => {
-        update_server(protocol::ClientToServer::Ping(client.id));
+      protocol::ServerToClient::Pong { client_ns, server_ns } => {
+        client.clock_sync.lock().unwrap().pong(client_ns, server_ns, time::precise_time_ns());
       },
       protocol::ServerToClient::PlayerAdded(id, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
       },
-      protocol::ServerToClient::UpdatePlayer(player_id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(0.0, 0.0, 1.0, 1.0));
-        update_view(view::update::UpdatePlayer(player_id, mesh));
-
-        // We "lock" the client to client.player_id, so for updates to that player only,
-        // there is more client-specific logic.
-        if player_id != client.player_id {
-          return
+      protocol::ServerToClient::Resumed { world, position } => {
+        info!("Resumed our session in {:?}", world);
+        update_view(view::update::ShowChatMessage(String::from("Reconnected to the server.")));
+        // Anything could have been missed while we were gone, so start over on entities, and load the terrain
+        // around the player again.
+        client.replication.lock().unwrap().start_over();
+        change_world(client, update_view, world, position);
+      },
+      protocol::ServerToClient::ResumeRefused => {
+        warn!("The server refused to resume our session.");
+        update_view(
+          view::update::ShowChatMessage(String::from("The server wouldn't let this client back in. Restart to rejoin.")),
+        );
+      },
+      protocol::ServerToClient::PlayerNames(names) => {
+        let mut player_names = client.player_names.lock().unwrap();
+        for (id, name) in names {
+          if id != client.player_id {
+            update_view(view::update::SetPlayerName(id, name.clone()));
+          }
+          player_names.insert(id, name);
         }
+      },
+      protocol::ServerToClient::Snapshot(snapshot) => {
+        let changes =
+          match client.replication.lock().unwrap().receive(&snapshot) {
+            None => return,
+            Some(changes) => changes,
+          };
+        update_server(protocol::ClientToServer::AckSnapshot(client.id, snapshot.id));
 
-        let position =
-          (bounds.min.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.1, 0.5))) +
-          (bounds.max.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.9, 0.5)));
-        let position = Point3::from_vec(position);
-
-        *client.player_position.lock().unwrap() = position;
-        update_view(view::update::MoveCamera(position));
+        // Everything else is shown a little later, in `show_entities`, but this client's own health is shown now.
+        for player in changes.players.into_iter().filter(|player| player.id == client.player_id) {
+          let mut health = client.health.lock().unwrap();
+          if player.health.current < health.current {
+            update_view(view::update::FlashDamage);
+          }
+          *health = player.health;
+          update_view(view::update::SetHealth(player.health));
+        }
       },
-      protocol::ServerToClient::UpdateMob(id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
-        update_view(view::update::UpdateMob(id, mesh));
+      protocol::ServerToClient::PlayerMovement { bounds, movement, last_input } => {
+        let mut prediction = client.prediction.lock().unwrap();
+        prediction.reconcile(&client.terrain.lock().unwrap(), bounds, movement, last_input);
+        move_player(client, update_view, &prediction);
       },
-      protocol::ServerToClient::UpdateSun(fraction) => {
-        update_view(view::update::SetSun(
-          view::light::Sun {
-            progression: fraction,
-            rotation: 0.0,
+      protocol::ServerToClient::SetSky { time, rate, has_sky } => {
+        // Worlds without seasons have no sky to light the terrain.
+        client.terrain.lock().unwrap().set_sky(has_sky);
+        update_view(view::update::SetSky(
+          view::sky::Clock {
+            time     : time,
+            rate     : rate,
+            seasons  : has_sky,
+            since_ns : time::precise_time_ns(),
           }
         ));
       },
-      protocol::ServerToClient::Voxels { voxels, reason } => {
+      protocol::ServerToClient::UpdateWeather(weather) => {
+        update_view(view::update::SetWeather(weather));
+      },
+      protocol::ServerToClient::TickStats(stats) => {
+        *client.server_tick.lock().unwrap() = Some(stats);
+      },
+      protocol::ServerToClient::ShuttingDown => {
+        info!("The server is shutting down.");
+        update_view(view::update::ShowChatMessage(String::from("The server is shutting down.")));
+      },
+      protocol::ServerToClient::Kicked(reason) => {
+        let message =
+          match reason {
+            None => String::from("You were kicked from the server."),
+            Some(reason) => format!("You were kicked from the server: {}", reason),
+          };
+        info!("{}", message);
+        update_view(view::update::ShowChatMessage(message));
+      },
+      protocol::ServerToClient::PlayerLeft(id) => {
+        client.player_names.lock().unwrap().remove(&id);
+        update_view(view::update::RemovePlayer(id));
+      },
+      protocol::ServerToClient::YouDied { cause, world, position } => {
+        let message =
+          match cause {
+            health::Cause::Fall => "You fell to your death.",
+            health::Cause::Mob => "You were killed by a mob.",
+          };
+        info!("{}", message);
+        update_view(view::update::ShowChatMessage(String::from(message)));
+        update_view(view::update::FlashDamage);
+        *client.health.lock().unwrap() = health::full(health::PLAYER_MAX);
+        update_view(view::update::SetHealth(health::full(health::PLAYER_MAX)));
+        if world != *client.world.lock().unwrap() {
+          change_world(client, update_view, world, position);
+        } else {
+          let mut prediction = client.prediction.lock().unwrap();
+          *prediction = prediction::new(position);
+          *client.last_footstep.lock().unwrap() = position;
+          move_player(client, update_view, &prediction);
+        }
+      },
+      protocol::ServerToClient::ChangedWorld { world, position } => {
+        change_world(client, update_view, world, position);
+      },
+      protocol::ServerToClient::Teleported(position) => {
+        info!("Teleported to {:?}", position);
+        for time_requested_ns in client.chunk_requests.lock().unwrap().clear() {
+          update_server(
+            protocol::ClientToServer::CancelChunkRequest {
+              client_id         : client.id,
+              time_requested_ns : time_requested_ns,
+            }
+          );
+        }
+        client.terrain.lock().unwrap().unload_out_of_range(update_view, &chunk::position::of_world_position(&position));
+        start_over_at(client, update_view, position);
+      },
+      protocol::ServerToClient::Voxels { world, voxels, reason, grass_density, versions } => {
+        if world != *client.world.lock().unwrap() {
+          debug!("Dropping voxels from {:?}, which the player has left", world);
+          return
+        }
+
         let time_requested;
         match reason {
-          protocol::VoxelReason::Updated => {
-            time_requested = None;
-          },
           protocol::VoxelReason::Requested { at } => {
             time_requested = Some(at);
             debug!("Receiving a voxel request after {}ns", time::precise_time_ns() - at);
           },
         }
 
+        // Edits the voxels were loaded too early to have go back on top, in the order they were made.
+        let mut voxels =
+          match voxels.decompress() {
+            Ok(voxels) => voxels,
+            Err(err) => {
+              warn!("Dropping voxels that couldn't be decompressed: {:?}", err);
+              return
+            },
+          };
+        {
+          let mut edits = client.edits.lock().unwrap();
+          for (chunk, version) in versions {
+            voxels.extend(edits.since(&chunk, version));
+            edits.forget_through(&chunk, version);
+          }
+        }
+
         enqueue_terrain_load(
           terrain::Load::Voxels {
-            voxels       : voxels,
+            voxels         : voxels,
             time_requested : time_requested,
+            grass_density  : Some(grass_density),
           }
         );
       },
+      protocol::ServerToClient::VoxelsUpdated(voxels, versions) => {
+        let voxels =
+          match voxels.decompress() {
+            Ok(voxels) => voxels,
+            Err(err) => {
+              warn!("Dropping voxel updates that couldn't be decompressed: {:?}", err);
+              return
+            },
+          };
+        {
+          let mut edits = client.edits.lock().unwrap();
+          for (chunk, version) in versions {
+            let in_chunk =
+              voxels.iter().filter(|&&(bounds, _)| journal::chunk(&bounds) == Some(chunk)).cloned().collect();
+            edits.record_at(chunk, version, in_chunk);
+          }
+        }
+        enqueue_terrain_load(
+          terrain::Load::Voxels {
+            voxels         : voxels,
+            time_requested : None,
+            grass_density  : None,
+          }
+        );
+      },
+      protocol::ServerToClient::AddLight(id, light) => {
+        client.terrain.lock().unwrap().add_light(id, light);
+        update_view(view::update::AddLight(id, light));
+      },
+      protocol::ServerToClient::RemoveLight(id) => {
+        client.terrain.lock().unwrap().remove_light(id);
+        update_view(view::update::RemoveLight(id));
+      },
+      protocol::ServerToClient::Dug { position, material } => {
+        update_view(view::update::EmitParticles(particles::emitter::debris(position, material)));
+        update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Dig, position));
+      },
+      protocol::ServerToClient::Exploded { position, radius, material } => {
+        update_view(view::update::EmitParticles(particles::emitter::explosion(position, radius)));
+        if let Some(material) = material {
+          update_view(view::update::EmitParticles(particles::emitter::debris(position, material)));
+        }
+        update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Explosion, position));
+      },
+      protocol::ServerToClient::TrampleGrass(cell) => {
+        let position = Point3::new(cell.x as f32 + 0.5, cell.y as f32 + 1.0, cell.z as f32 + 0.5);
+        update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::GrassRustle, position));
+        update_view(view::update::TrampleGrass(cell));
+      },
+      protocol::ServerToClient::RemoveGrass { center, radius } => {
+        update_view(view::update::RemoveGrass(center, radius));
+      },
+      protocol::ServerToClient::InventorySlot { slot, stack } => {
+        update_view(view::update::SetInventorySlot(slot as usize, stack));
+      },
+      protocol::ServerToClient::Crafted { recipe, result } => {
+        let made = item::recipe(recipe).map(|recipe| item::get(recipe.output.item).name).unwrap_or("nothing");
+        let message =
+          match result {
+            Ok(()) => format!("Crafted {}.", made),
+            Err(item::CraftError::UnknownRecipe) => "There's no such recipe.".to_owned(),
+            Err(item::CraftError::MissingInputs) => format!("You don't have what it takes to make {}.", made),
+            Err(item::CraftError::NoRoom) => format!("There's no room in your inventory for {}.", made),
+          };
+        update_view(view::update::ShowChatMessage(message));
+      },
+      protocol::ServerToClient::Chat { from, text } => {
+        let name =
+          match client.player_names.lock().unwrap().get(&from) {
+            None => format!("Player {}", from.to_u32()),
+            Some(name) => name.clone(),
+          };
+        update_view(view::update::ShowChatMessage(format!("{}: {}", name, text)));
+      },
+      protocol::ServerToClient::Notice(text) => {
+        update_view(view::update::ShowChatMessage(text));
+      },
+      protocol::ServerToClient::Copied(schematic) => {
+        let schematic =
+          match schematic.decompress() {
+            Ok(schematic) => schematic,
+            Err(err) => {
+              warn!("Dropping a copied schematic that couldn't be decompressed: {:?}", err);
+              return
+            },
+          };
+        let size = schematic.size;
+        update_view(view::update::ShowChatMessage(format!("Copied {}x{}x{} voxels.", size[0], size[1], size[2])));
+        *client.clipboard.lock().unwrap() = Some(schematic);
+      },
       protocol::ServerToClient::Collision(collision_type) => {
         if let protocol::Collision::PlayerTerrain(..) = collision_type {
           let player_position = *client.player_position.lock().unwrap();
           let mut last_footstep = client.last_footstep.lock().unwrap();
           if (player_position - *last_footstep).magnitude() >= 4.0 {
             *last_footstep = player_position;
+            let feet = {
+              let bounds = client.prediction.lock().unwrap().bounds();
+              Point3::new((bounds.min.x + bounds.max.x) / 2.0, bounds.min.y, (bounds.min.z + bounds.max.z) / 2.0)
+            };
+
             let idx = client.rng.lock().unwrap().gen_range(1, 17 + 1);
-            update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Footstep(idx)));
+            update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Footstep(idx), feet));
+            update_view(view::update::EmitParticles(particles::emitter::dust(feet)));
           }
         }
       }
@@ -110,40 +322,148 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
   })
 }
 
-fn to_triangles(
-  bounds: &Aabb3<f32>,
-  c: &Color4<f32>,
-) -> [ColoredVertex; TRIANGLE_VERTICES_PER_BOX as usize] {
-  let (x1, y1, z1) = (bounds.min.x, bounds.min.y, bounds.min.z);
-  let (x2, y2, z2) = (bounds.max.x, bounds.max.y, bounds.max.z);
-
-  let vtx = |x, y, z| {
-    ColoredVertex {
-      position: Point3::new(x, y, z),
-      color: *c,
-    }
+/// Throw away everything loaded from the old world, and start over around the player's position in the new one.
+fn change_world<UpdateView>(
+  client      : &client::T,
+  update_view : &mut UpdateView,
+  world       : protocol::WorldId,
+  position    : Point3<f32>,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  info!("Moved to {:?}", world);
+  *client.world.lock().unwrap() = world;
+  client.terrain.lock().unwrap().clear(update_view);
+  *client.edits.lock().unwrap() = journal::new();
+  // The new world's lights are sent along after this.
+  update_view(view::update::ClearLights);
+  update_view(view::update::ChangeWorld(world));
+  *client.chunk_requests.lock().unwrap() = chunk_requests::new();
+  start_over_at(client, update_view, position);
+}
+
+/// Load terrain outward from the player's new position as if it had just joined, and put the player and the camera
+/// there right away.
+fn start_over_at<UpdateView>(
+  client      : &client::T,
+  update_view : &mut UpdateView,
+  position    : Point3<f32>,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  *client.surroundings_loader.lock().unwrap() = {
+    let render_distance = client.render_distance.lock().unwrap();
+    surroundings_loader::new(
+      render_distance.distance(),
+      render_distance.lod_thresholds().iter().map(|&x| x as i32).collect(),
+    )
   };
 
-  // Remember: x increases to the right, y increases up, and z becomes more
-  // negative as depth from the viewer increases.
-  [
-    // front
-    vtx(x1, y1, z2), vtx(x2, y2, z2), vtx(x1, y2, z2),
-    vtx(x1, y1, z2), vtx(x2, y1, z2), vtx(x2, y2, z2),
-    // left
-    vtx(x1, y1, z1), vtx(x1, y2, z2), vtx(x1, y2, z1),
-    vtx(x1, y1, z1), vtx(x1, y1, z2), vtx(x1, y2, z2),
-    // top
-    vtx(x1, y2, z1), vtx(x2, y2, z2), vtx(x2, y2, z1),
-    vtx(x1, y2, z1), vtx(x1, y2, z2), vtx(x2, y2, z2),
-    // back
-    vtx(x1, y1, z1), vtx(x2, y2, z1), vtx(x2, y1, z1),
-    vtx(x1, y1, z1), vtx(x1, y2, z1), vtx(x2, y2, z1),
-    // right
-    vtx(x2, y1, z1), vtx(x2, y2, z2), vtx(x2, y1, z2),
-    vtx(x2, y1, z1), vtx(x2, y2, z1), vtx(x2, y2, z2),
-    // bottom
-    vtx(x1, y1, z1), vtx(x2, y1, z2), vtx(x1, y1, z2),
-    vtx(x1, y1, z1), vtx(x2, y1, z1), vtx(x2, y1, z2),
-  ]
+  let mut prediction = client.prediction.lock().unwrap();
+  prediction.teleport(position);
+  *client.last_footstep.lock().unwrap() = position;
+  move_player(client, update_view, &prediction);
+}
+
+/// How to draw a player with the given bounds, facing `heading`.
+pub fn player_instance(bounds: &Aabb3<f32>, heading: f32) -> view::entity_buffers::Instance {
+  view::entity_buffers::Instance {
+    center       : bounds.center(),
+    size         : bounds.dim(),
+    heading      : heading,
+    color        : Color4::of_rgba(0.0, 0.0, 1.0, 1.0),
+    facing_color : Color4::of_rgba(0.9, 0.7, 0.6, 1.0),
+  }
+}
+
+/// How to draw a mob: colored by what it's doing, turned to face its heading, with its front highlighted.
+fn mob_instance(mob: &protocol::MobState) -> view::entity_buffers::Instance {
+  let color =
+    match mob.animation {
+      protocol::Animation::Idle   => Color4::of_rgba(0.6, 0.0, 0.0, 1.0),
+      protocol::Animation::Walk   => Color4::of_rgba(1.0, 0.0, 0.0, 1.0),
+      protocol::Animation::Run    => Color4::of_rgba(1.0, 0.4, 0.0, 1.0),
+      protocol::Animation::Attack => Color4::of_rgba(1.0, 0.0, 0.4, 1.0),
+    };
+  view::entity_buffers::Instance {
+    center       : mob.bounds.center(),
+    size         : mob.bounds.dim(),
+    heading      : mob.heading,
+    color        : color,
+    facing_color : Color4::of_rgba(color.r, 1.0, 1.0, color.a),
+  }
+}
+
+/// The mesh for an item: a little cube of its color, sitting on its position.
+fn item_mesh(item: &protocol::ItemState) -> [ColoredVertex; vertex::VERTICES_PER_CUBOID] {
+  let size = 0.25;
+  let low = item.position + cgmath::Vector3::new(-size / 2.0, 0.0, -size / 2.0);
+  let bounds = Aabb3::new(low, low + cgmath::Vector3::new(size, size, size));
+  let color = item::get(item.item).color;
+  ColoredVertex::cuboid(&bounds, Color4::of_rgba(color.r, color.g, color.b, 1.0))
+}
+
+/// Show this client's player where it's predicted to be, and move the camera with it.
+pub fn move_player<UpdateView>(
+  client      : &client::T,
+  update_view : &mut UpdateView,
+  prediction  : &prediction::T,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  let bounds = prediction.bounds();
+  update_view(view::update::UpdatePlayer(
+    client.player_id,
+    player_instance(&bounds, prediction.heading()),
+    prediction.animation(),
+  ));
+
+  let position =
+    (bounds.min.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.1, 0.5))) +
+    (bounds.max.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.9, 0.5)));
+  let position = Point3::from_vec(position);
+
+  *client.player_position.lock().unwrap() = position;
+  update_view(view::update::MoveCamera(position));
+  update_view(view::update::SetUnderwater(prediction.is_submerged()));
+}
+
+/// Show the other entities where they were a moment ago, by the server's clock; see `replication::T::show`.
+pub fn show_entities<UpdateView>(
+  client      : &client::T,
+  update_view : &mut UpdateView,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  let server_ns = client.clock_sync.lock().unwrap().server_ns(time::precise_time_ns());
+  let changes =
+    match client.replication.lock().unwrap().show(server_ns) {
+      None => return,
+      Some(changes) => changes,
+    };
+
+  // This client's own player is drawn where we predict it is; see `PlayerMovement`.
+  for player in changes.players.into_iter().filter(|player| player.id != client.player_id) {
+    update_view(view::update::UpdatePlayer(
+      player.id,
+      player_instance(&player.bounds, player.heading),
+      player.animation,
+    ));
+    update_view(view::update::PlaceNameplate(player.id, player.bounds.center()));
+  }
+  for id in changes.removed_players {
+    update_view(view::update::RemovePlayer(id));
+  }
+  for mob in changes.mobs {
+    update_view(view::update::UpdateMob(mob.id, mob_instance(&mob), mob.animation));
+  }
+  for id in changes.removed_mobs {
+    update_view(view::update::RemoveMob(id));
+  }
+  for item in changes.items {
+    update_view(view::update::UpdateItem(item.id, item_mesh(&item)));
+  }
+  for id in changes.removed_items {
+    update_view(view::update::RemoveItem(id));
+  }
 }
@@ -1,13 +1,21 @@
 //! Keep track of terrain load state, and store voxels cached from the server.
 
 use cgmath;
+use cgmath::EuclideanSpace;
 use collision;
+use fnv;
+use lru_cache;
 use rand;
+use rand::SeedableRng;
+use rayon;
+use rayon::prelude::*;
 use std;
 use time;
 
 use common::{fnv_set, fnv_map};
 use common::id_allocator;
+use common::protocol;
+use common::raycast;
 use common::surroundings_loader;
 use common::voxel;
 
@@ -26,29 +34,102 @@ pub enum Load {
     /// Is Some if this is a response to a request from this client; is None if the server provides
     /// these voxels because they were updated.
     time_requested : Option<u64>,
+    /// How grassy the chunk these voxels were requested for is. Is None if the server didn't say.
+    grass_density  : Option<f32>,
   },
 }
 
+/// The number of unloaded chunk meshes to hang onto, in case they're loaded again.
+const UNLOADED_CACHE_SIZE: usize = 1 << 9;
+
+/// How many chunks each meshing thread gets per batch.
+const MESH_BATCH_PER_THREAD: usize = 2;
+
+/// Everything sent to the view for a chunk.
+#[derive(Clone)]
+struct Mesh {
+  terrain : std::sync::Arc<view::chunked_terrain::T>,
+  water   : Vec<terrain_mesh::Triangle<cgmath::Point3<f32>>>,
+  /// The neighbor LODs the terrain was stitched to.
+  skirts  : terrain_mesh::Skirts,
+}
+
+impl Mesh {
+  fn ids(&self) -> terrain_mesh::Ids {
+    terrain_mesh::Ids {
+      chunk_ids: self.terrain.ids.clone(),
+      grass_ids: self.terrain.grass.ids.clone(),
+    }
+  }
+}
+
+type MeshCache =
+  lru_cache::LruCache<(chunk::position::T, lod::T), Mesh, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
 #[allow(missing_docs)]
 pub struct T {
   /// A record of all the chunks that have been loaded.
-  loaded_chunks       : fnv_map::T<chunk::position::T, (terrain_mesh::Ids, lod::T)>,
+  loaded_chunks       : fnv_map::T<chunk::position::T, (Mesh, lod::T)>,
+  /// When each loaded chunk was last loaded at a different LOD.
+  lod_changed_at      : fnv_map::T<chunk::position::T, u64>,
+  /// Meshes of recently-unloaded chunks, so moving back and forth doesn't regenerate them.
+  unloaded_chunks     : MeshCache,
   /// Map each chunk to the number of voxels inside it that we have.
   chunk_voxels_loaded : fnv_map::T<(chunk::position::T, lod::T), u32>,
+  /// The portion of each chunk's surface that should be covered in grass.
+  grass_density       : fnv_map::T<chunk::position::T, f32>,
   /// The voxels we have cached from the server.
   voxels              : voxel::tree::T,
   max_load_distance   : u32,
+  /// The distances at which LOD switches.
+  lod_thresholds      : [u32; lod::COUNT - 1],
   queue               : std::collections::VecDeque<Load>,
+  /// Loaded chunks whose neighbors' LODs may have changed, so their skirts need checking.
+  unstitched          : fnv_set::T<chunk::position::T>,
+  /// Whether this world has a sky to light the terrain.
+  sky                 : bool,
+  /// The point lights in the world, which light the terrain around them.
+  lights              : fnv_map::T<protocol::LightId, protocol::Light>,
+  /// Loaded chunks whose lighting has changed, so they need meshing again.
+  unlit               : fnv_set::T<chunk::position::T>,
+  /// Chunks with all their voxels that are waiting to be meshed, and the LOD to mesh them at.
+  unmeshed            : fnv_map::T<chunk::position::T, lod::T>,
+  /// The threads chunks are meshed on.
+  meshing_pool        : rayon::ThreadPool,
+  /// The most VRAM chunks (of `view::terrain_buffers::CHUNK_LENGTH` polygons) loaded terrain can take up.
+  vram_budget         : usize,
+  /// The VRAM chunks loaded terrain takes up.
+  vram_used           : usize,
+  /// The chunk the player was in as of the last tick. When VRAM is full, the chunks furthest from it go first.
+  center              : chunk::position::T,
 }
 
-#[allow(missing_docs)]
-pub fn new(max_load_distance: u32) -> T {
+/// Terrain that can take up `vram_budget` VRAM chunks, meshed on `meshing_threads` threads (0 for one per core).
+pub fn new(
+  max_load_distance : u32,
+  lod_thresholds    : [u32; lod::COUNT - 1],
+  vram_budget       : usize,
+  meshing_threads   : usize,
+) -> T {
   T {
     loaded_chunks       : fnv_map::new(),
+    lod_changed_at      : fnv_map::new(),
+    unloaded_chunks     : lru_cache::LruCache::with_hasher(UNLOADED_CACHE_SIZE, Default::default()),
     chunk_voxels_loaded : fnv_map::new(),
+    grass_density       : fnv_map::new(),
     voxels              : voxel::tree::new(),
     max_load_distance   : max_load_distance,
+    lod_thresholds      : lod_thresholds,
     queue               : std::collections::VecDeque::new(),
+    unstitched          : fnv_set::new(),
+    sky                 : true,
+    lights              : fnv_map::new(),
+    unlit               : fnv_set::new(),
+    unmeshed            : fnv_map::new(),
+    meshing_pool        : rayon::ThreadPoolBuilder::new().num_threads(meshing_threads).build().unwrap(),
+    vram_budget         : vram_budget,
+    vram_used           : 0,
+    center              : chunk::position::new(0, 0, 0),
   }
 }
 
@@ -60,6 +141,53 @@ impl T {
       .map(|&(_, lod)| lod)
   }
 
+  /// When a loaded chunk last changed LOD, or was first loaded.
+  pub fn lod_changed_at(&self, chunk_position: &chunk::position::T) -> Option<u64> {
+    self.lod_changed_at.get(chunk_position).cloned()
+  }
+
+  /// The position and LOD of every loaded chunk.
+  pub fn loaded_chunks(&self) -> Vec<(chunk::position::T, lod::T)> {
+    self.loaded_chunks.iter().map(|(&position, &(_, lod))| (position, lod)).collect()
+  }
+
+  /// The cached voxel at some bounds, if we have it.
+  pub fn voxel(&self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    self.voxels.get(bounds).map(|voxel| *voxel)
+  }
+
+  /// The first solid voxel (or surface) we have along a ray, e.g. the one under the crosshair.
+  pub fn target(
+    &self,
+    eye          : &cgmath::Point3<f32>,
+    forward      : &cgmath::Vector3<f32>,
+    max_distance : f32,
+  ) -> Option<raycast::Hit> {
+    raycast::cast(eye, forward, max_distance, |bounds| {
+      match self.voxel(bounds) {
+        None |
+        Some(voxel::Volume(voxel::Material::Empty)) |
+        Some(voxel::Volume(voxel::Material::Water)) => false,
+        Some(_) => true,
+      }
+    })
+  }
+
+  /// How many chunks are loaded at each LOD.
+  pub fn chunks_per_lod(&self) -> [usize; lod::COUNT] {
+    let mut counts = [0; lod::COUNT];
+    for &(_, lod) in self.loaded_chunks.values() {
+      counts[lod.0 as usize] += 1;
+    }
+    counts
+  }
+
+  /// Change how far away chunks are shown, and where LOD switches.
+  pub fn set_range(&mut self, max_load_distance: u32, lod_thresholds: [u32; lod::COUNT - 1]) {
+    self.max_load_distance = max_load_distance;
+    self.lod_thresholds = lod_thresholds;
+  }
+
   /// get the count of queued messages
   pub fn queued_update_count(&self) -> usize {
     self.queue.len()
@@ -70,6 +198,74 @@ impl T {
     self.queue.push_back(msg);
   }
 
+  /// The skirts a chunk's mesh needs at some LOD, given the LODs its neighbors are loaded at.
+  fn skirts(&self, chunk_position: &chunk::position::T, lod: lod::T) -> terrain_mesh::Skirts {
+    let mut skirts = [None; 6];
+    for (skirt, &face) in skirts.iter_mut().zip(chunk::FACES.iter()) {
+      *skirt =
+        match self.load_state(&(*chunk_position + face)) {
+          Some(neighbor_lod) if neighbor_lod > lod => Some(neighbor_lod),
+          _ => None,
+        };
+    }
+    skirts
+  }
+
+  /// A chunk has been loaded, unloaded, or changed LOD, so its neighbors' skirts may be out of date.
+  fn unstitch_neighbors(&mut self, chunk_position: &chunk::position::T) {
+    for &face in chunk::FACES.iter() {
+      self.unstitched.insert(*chunk_position + face);
+    }
+  }
+
+  /// Set whether the world has a sky. All the terrain is lit again if that changes.
+  pub fn set_sky(&mut self, sky: bool) {
+    if sky == self.sky {
+      return
+    }
+    self.sky = sky;
+    self.unlit.extend(self.loaded_chunks.keys());
+    self.unloaded_chunks.clear();
+  }
+
+  /// A light's been added or moved, so the terrain around it should be lit again.
+  pub fn add_light(&mut self, id: protocol::LightId, light: protocol::Light) {
+    if let Some(old) = self.lights.insert(id, light) {
+      self.relight_around(&old);
+    }
+    self.relight_around(&light);
+  }
+
+  #[allow(missing_docs)]
+  pub fn remove_light(&mut self, id: protocol::LightId) {
+    if let Some(light) = self.lights.remove(&id) {
+      self.relight_around(&light);
+    }
+  }
+
+  /// Mark every chunk a light might reach as needing to be lit again.
+  fn relight_around(&mut self, light: &protocol::Light) {
+    let width = chunk::WIDTH as f32;
+    let near = |chunk_position: &chunk::position::T| {
+      let low = chunk_position.as_pnt();
+      let distance = |x: f32, low: i32| {
+        let low = low as f32 * width;
+        (low - x).max(x - (low + width)).max(0.0)
+      };
+      let (dx, dy, dz) =
+        (distance(light.position.x, low.x), distance(light.position.y, low.y), distance(light.position.z, low.z));
+      dx * dx + dy * dy + dz * dz <= light.radius * light.radius
+    };
+
+    let lit: Vec<chunk::position::T> = self.loaded_chunks.keys().filter(|&&p| near(&p)).cloned().collect();
+    self.unlit.extend(lit);
+    let stale: Vec<(chunk::position::T, lod::T)> =
+      self.unloaded_chunks.iter().map(|(&key, _)| key).filter(|&(p, _)| near(&p)).collect();
+    for key in stale {
+      self.unloaded_chunks.remove(&key);
+    }
+  }
+
   fn all_voxels_loaded(
     &self,
     chunk_position: chunk::position::T,
@@ -100,20 +296,12 @@ impl T {
     UpdateView : FnMut(view::update::T),
     Rng        : rand::Rng,
   {
+    self.center = chunk::position::of_world_position(player_position);
     let start = time::precise_time_ns();
     while let Some(msg) = self.queue.pop_front() {
       match msg {
-        Load::Voxels { voxels, time_requested } => {
-          self.load_voxels(
-            terrain_allocator,
-            grass_allocator,
-            rng,
-            chunk_stats,
-            update_view,
-            player_position,
-            voxels,
-            time_requested,
-          );
+        Load::Voxels { voxels, time_requested, grass_density } => {
+          self.load_voxels(player_position, voxels, time_requested, grass_density);
         },
       }
 
@@ -121,83 +309,245 @@ impl T {
         break
       }
     }
+
+    // Re-mesh chunks whose skirts no longer match their neighbors, or whose lighting has changed.
+    let unstitched: Vec<chunk::position::T> = self.unstitched.drain().collect();
+    let unlit: Vec<chunk::position::T> = self.unlit.drain().collect();
+    let stale =
+      unstitched.into_iter().map(|chunk_position| (chunk_position, false))
+      .chain(unlit.into_iter().map(|chunk_position| (chunk_position, true)));
+    for (chunk_position, relight) in stale {
+      let (skirts, lod) =
+        match self.loaded_chunks.get(&chunk_position) {
+          None => continue,
+          Some(&(ref mesh, lod)) => (mesh.skirts, lod),
+        };
+      let restitch = skirts != self.skirts(&chunk_position, lod);
+      if !(restitch || relight) || !self.all_voxels_loaded(chunk_position, lod) {
+        continue
+      }
+      debug!("re-meshing {:?} at {:?}", chunk_position, lod);
+      // A chunk that's already waiting to be meshed at another LOD gets stitched and lit then.
+      self.unmeshed.entry(chunk_position).or_insert(lod);
+    }
+
+    // Mesh the waiting chunks a batch at a time with whatever time is left, but always at least one batch, so
+    // meshing keeps up with loading voxels.
+    loop {
+      let batch = self.next_mesh_batch();
+      if batch.is_empty() {
+        break
+      }
+      let meshes = self.mesh(terrain_allocator, grass_allocator, rng, &batch);
+      for ((chunk_position, lod), mesh) in batch.into_iter().zip(meshes.into_iter()) {
+        chunk_stats.add(mesh.terrain.polygon_count());
+        self.show_mesh(update_view, &chunk_position, lod, mesh);
+      }
+
+      if time::precise_time_ns() - start >= 1_000_000 {
+        break
+      }
+    }
+  }
+
+  /// Take the nearest chunks waiting to be meshed, as many as the meshing threads take at once.
+  fn next_mesh_batch(&mut self) -> Vec<(chunk::position::T, lod::T)> {
+    let center = self.center;
+    let mut batch: Vec<(chunk::position::T, lod::T)> =
+      self.unmeshed.iter().map(|(&chunk_position, &lod)| (chunk_position, lod)).collect();
+    batch.sort_by_key(|&(chunk_position, _)| {
+      surroundings_loader::distance_between(center.as_pnt(), chunk_position.as_pnt())
+    });
+    batch.truncate(self.meshing_pool.current_num_threads() * MESH_BATCH_PER_THREAD);
+    for &(chunk_position, _) in &batch {
+      self.unmeshed.remove(&chunk_position);
+    }
+    batch
   }
 
+  /// Mesh some chunks in parallel on the meshing threads. The meshes come back in the same order.
   #[inline(never)]
-  fn force_load_chunk<Rng, UpdateView>(
-    &mut self,
+  fn mesh<Rng>(
+    &self,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
     rng               : &mut Rng,
-    chunk_stats       : &mut chunk_stats::T,
-    update_view       : &mut UpdateView,
-    chunk_position    : &chunk::position::T,
-    lod               : lod::T,
+    chunks            : &[(chunk::position::T, lod::T)],
+  ) -> Vec<Mesh> where
+    Rng : rand::Rng,
+  {
+    let jobs: Vec<_> =
+      chunks.iter().map(|&(chunk_position, lod)| {
+        debug!("generate {:?} at {:?}", chunk_position, lod);
+        let grass_density = self.grass_density.get(&chunk_position).cloned().unwrap_or(1.0);
+        // The threads can't share `rng`, so each chunk gets its own, seeded from it.
+        let seed = [rng.next_u32(), rng.next_u32(), rng.next_u32(), rng.next_u32()];
+        (chunk_position, lod, self.skirts(&chunk_position, lod), grass_density, seed)
+      })
+      .collect();
+    let lights: Vec<protocol::Light> = self.lights.values().cloned().collect();
+    let voxels = &self.voxels;
+    let sky = self.sky;
+
+    self.meshing_pool.install(|| {
+      jobs.into_par_iter().map(|(chunk_position, lod, skirts, grass_density, seed)| {
+        let mut rng: rand::XorShiftRng = SeedableRng::from_seed(seed);
+        let mesh_chunk: view::chunked_terrain::T =
+          terrain_mesh::generate(
+            voxels,
+            &chunk_position,
+            lod,
+            &skirts,
+            grass_density,
+            sky,
+            &lights,
+            terrain_allocator,
+            grass_allocator,
+            &mut rng,
+          );
+        Mesh {
+          terrain : std::sync::Arc::new(mesh_chunk),
+          water   : terrain_mesh::generate_water(voxels, &chunk_position, lod),
+          skirts  : skirts,
+        }
+      })
+      .collect()
+    })
+  }
+
+  /// Unload the chunks furthest from the player until there's room in VRAM for `needed` more VRAM chunks of
+  /// `chunk_position`. Only chunks further away than it are unloaded; returns false if that doesn't make enough room.
+  fn make_room<UpdateView>(
+    &mut self,
+    update_view    : &mut UpdateView,
+    chunk_position : &chunk::position::T,
+    needed         : usize,
+  ) -> bool where
+    UpdateView : FnMut(view::update::T),
+  {
+    let center = self.center;
+    let distance = |position: &chunk::position::T| {
+      surroundings_loader::distance_between(center.as_pnt(), position.as_pnt())
+    };
+    let own_distance = distance(chunk_position);
+    while self.vram_used + needed > self.vram_budget {
+      let furthest =
+        self.loaded_chunks.keys()
+        .filter(|&position| position != chunk_position)
+        .max_by_key(|&position| distance(position))
+        .map(|&position| position);
+      match furthest {
+        Some(furthest) if distance(&furthest) > own_distance => {
+          debug!("Evicting {:?} to make room in VRAM", furthest);
+          self.unload(update_view, &furthest);
+        },
+        _ => return false,
+      }
+    }
+    true
+  }
+
+  /// Send a chunk's mesh to the view, replacing whatever was there.
+  /// If there's no room for it in VRAM, even after evicting further chunks, it goes in the cache instead.
+  fn show_mesh<UpdateView>(
+    &mut self,
+    update_view    : &mut UpdateView,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+    mesh           : Mesh,
   ) where
     UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
   {
-    debug!("generate {:?} at {:?}", chunk_position, lod);
-    let mesh_chunk: view::chunked_terrain::T =
-      terrain_mesh::generate(&self.voxels, chunk_stats, &chunk_position, lod, terrain_allocator, grass_allocator, rng);
+    let old_size = self.loaded_chunks.get(chunk_position).map_or(0, |&(ref mesh, _)| mesh.terrain.ids.len());
+    let new_size = mesh.terrain.ids.len();
+    // The old mesh is only taken out once the new one is in, so for a moment there has to be room for both.
+    if !self.make_room(update_view, chunk_position, new_size) {
+      debug!("No room in VRAM for {:?} at {:?}", chunk_position, lod);
+      self.unloaded_chunks.insert((*chunk_position, lod), mesh);
+      return
+    }
+    self.vram_used = self.vram_used + new_size - old_size;
 
-    let mut updates = Vec::new();
+    // A cached mesh may have been stitched to neighbors that have changed since.
+    if mesh.skirts != self.skirts(chunk_position, lod) {
+      self.unstitched.insert(*chunk_position);
+    }
 
-    let ids =
-      terrain_mesh::Ids {
-        chunk_ids: mesh_chunk.ids.clone(),
-        grass_ids: mesh_chunk.grass.ids.clone(),
-      };
+    // The new mesh goes in before the old one comes out, in the same frame, so there's never a hole where the
+    // chunk is, e.g. while it changes LOD.
+    let mut updates = Vec::new();
+    if !mesh.terrain.is_empty() {
+      updates.push(view::update::LoadMesh(mesh.terrain.clone()));
+    }
+    // New water replaces the old.
+    let has_water = !mesh.water.is_empty();
+    if has_water {
+      updates.push(view::update::AddWater(*chunk_position, mesh.water.clone()));
+    }
 
     use std::collections::hash_map::Entry::*;
-    // TODO: Rc instead of clone.
-    match self.loaded_chunks.entry(*chunk_position) {
-      Vacant(entry) => {
-        entry.insert((ids, lod));
-      },
-      Occupied(mut entry) => {
-        let (ids, _) = entry.insert((ids, lod));
-        updates.push(view::update::UnloadMesh(ids));
-      },
-    };
-
-    if !mesh_chunk.is_empty() {
-      updates.push(view::update::LoadMesh(Box::new(mesh_chunk)));
+    let lod_changed =
+      match self.loaded_chunks.entry(*chunk_position) {
+        Vacant(entry) => {
+          entry.insert((mesh, lod));
+          true
+        },
+        Occupied(mut entry) => {
+          let (old_mesh, old_lod) = entry.insert((mesh, lod));
+          updates.push(view::update::UnloadMesh(old_mesh.ids()));
+          if !has_water {
+            updates.push(view::update::RemoveWater(*chunk_position));
+          }
+          if old_lod != lod {
+            self.unloaded_chunks.insert((*chunk_position, old_lod), old_mesh);
+          }
+          old_lod != lod
+        },
+      };
+    if lod_changed {
+      self.lod_changed_at.insert(*chunk_position, time::precise_time_ns());
+      self.unstitch_neighbors(chunk_position);
     }
 
     update_view(view::update::Atomic(updates));
   }
 
-  /// try to load a chunk into VRAM.
-  /// if some voxels are missing, returns an Err of all the voxels that need to be fetched from the server.
-  pub fn load_chunk<Rng, UpdateView>(
+  /// Load a chunk from the cache of recently-unloaded chunks.
+  /// Returns false if the chunk isn't cached at this LOD.
+  pub fn load_cached_chunk<UpdateView>(
     &mut self,
-    terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
-    grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
-    chunk_stats       : &mut chunk_stats::T,
-    update_view       : &mut UpdateView,
-    chunk_position    : &chunk::position::T,
-    lod               : lod::T,
-  ) -> Result<(), Vec<voxel::bounds::T>> where
+    update_view    : &mut UpdateView,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+  ) -> bool where
     UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
   {
+    match self.unloaded_chunks.remove(&(*chunk_position, lod)) {
+      None => false,
+      Some(mesh) => {
+        debug!("{:?} at {:?} loaded from cache", chunk_position, lod);
+        // Don't let a mesh that was waiting to be made replace this one.
+        self.unmeshed.remove(chunk_position);
+        self.show_mesh(update_view, chunk_position, lod, mesh);
+        true
+      },
+    }
+  }
+
+  /// try to load a chunk into VRAM. It's meshed, and loaded, on a later `tick`.
+  /// if some voxels are missing, returns an Err of all the voxels that need to be fetched from the server.
+  pub fn load_chunk(
+    &mut self,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+  ) -> Result<(), Vec<voxel::bounds::T>> {
     let all_voxels_loaded =
       self.all_voxels_loaded(
         *chunk_position,
         lod,
       );
     if all_voxels_loaded {
-      self.force_load_chunk(
-        terrain_allocator,
-        grass_allocator,
-        rng,
-        chunk_stats,
-        update_view,
-        chunk_position,
-        lod,
-      );
+      self.unmeshed.insert(*chunk_position, lod);
       Ok(())
     } else {
       let voxel_size = 1 << lod.lg_sample_size();
@@ -262,6 +612,10 @@ impl T {
 
     for chunk_position in updated_chunk_positions(&bounds) {
       trace!("chunk_position {:?}", chunk_position);
+      // Any cached meshes of this chunk are stale now.
+      for &updated_lod in &updated_lods {
+        self.unloaded_chunks.remove(&(chunk_position, updated_lod));
+      }
       if new_voxel_loaded {
         for &updated_lod in &updated_lods {
           let chunk_voxels_loaded =
@@ -287,7 +641,7 @@ impl T {
         continue;
       }
 
-      let lod = lod::of_distance(distance as u32);
+      let lod = lod::of_distance_from(&self.lod_thresholds, distance as u32, self.load_state(&chunk_position));
       let lg_size = lod.lg_sample_size();
       if lg_size != bounds.lg_size {
         debug!(
@@ -305,20 +659,26 @@ impl T {
   }
 
   #[inline(never)]
-  fn load_voxels<Rng, UpdateView>(
+  fn load_voxels(
     &mut self,
-    terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
-    grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
-    chunk_stats       : &mut chunk_stats::T,
-    update_view       : &mut UpdateView,
     player_position   : &cgmath::Point3<f32>,
     voxel_updates     : Vec<(voxel::bounds::T, voxel::T)>,
     time_requested    : Option<u64>,
-  ) where
-    UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
-  {
+    grass_density     : Option<f32>,
+  ) {
+    if let Some(grass_density) = grass_density {
+      // Requests are for one chunk, padded on every side, so the center of the voxels is in that chunk.
+      if !voxel_updates.is_empty() {
+        let mut center = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        for &(ref bounds, _) in &voxel_updates {
+          let (low, high) = bounds.corners();
+          center = center + (low.to_vec() + high.to_vec()) / 2.0;
+        }
+        let center = cgmath::Point3::from_vec(center / voxel_updates.len() as f32);
+        self.grass_density.insert(chunk::position::of_world_position(&center), grass_density);
+      }
+    }
+
     let mut update_chunks = fnv_set::new();
     let response_time = time::precise_time_ns();
     for (bounds, voxel) in voxel_updates {
@@ -333,16 +693,7 @@ impl T {
 
     let processed_time = time::precise_time_ns();
     for (chunk, lod) in update_chunks {
-      let _ =
-        self.load_chunk(
-          terrain_allocator,
-          grass_allocator,
-          rng,
-          chunk_stats,
-          update_view,
-          &chunk,
-          lod,
-        );
+      let _ = self.load_chunk(&chunk, lod);
     }
 
     let chunk_loaded = time::precise_time_ns();
@@ -370,13 +721,61 @@ impl T {
   ) where
     UpdateView : FnMut(view::update::T),
   {
+    self.unmeshed.remove(chunk_position);
     match self.loaded_chunks.remove(chunk_position) {
       None => {},
-      Some((ids, _)) => {
-        update_view(view::update::UnloadMesh(ids));
+      Some((mesh, lod)) => {
+        self.lod_changed_at.remove(chunk_position);
+        self.vram_used -= mesh.terrain.ids.len();
+        update_view(view::update::UnloadMesh(mesh.ids()));
+        update_view(view::update::RemoveWater(*chunk_position));
+        self.unloaded_chunks.insert((*chunk_position, lod), mesh);
+        self.unstitch_neighbors(chunk_position);
       },
     }
   }
+
+  /// Unload the chunks out of range of `center`, e.g. because the player's been teleported away from them.
+  pub fn unload_out_of_range<UpdateView>(
+    &mut self,
+    update_view : &mut UpdateView,
+    center      : &chunk::position::T,
+  ) where
+    UpdateView : FnMut(view::update::T),
+  {
+    let out_of_range: Vec<chunk::position::T> =
+      self.loaded_chunks.keys()
+      .filter(|chunk| surroundings_loader::distance_between(center.as_pnt(), chunk.as_pnt()) > self.max_load_distance)
+      .cloned()
+      .collect();
+    for chunk_position in out_of_range {
+      self.unload(update_view, &chunk_position);
+    }
+  }
+
+  /// Unload every chunk and forget every voxel, e.g. because the player has moved to another world.
+  pub fn clear<UpdateView>(
+    &mut self,
+    update_view : &mut UpdateView,
+  ) where
+    UpdateView : FnMut(view::update::T),
+  {
+    for (chunk_position, (mesh, _)) in self.loaded_chunks.drain() {
+      update_view(view::update::UnloadMesh(mesh.ids()));
+      update_view(view::update::RemoveWater(chunk_position));
+    }
+    self.lod_changed_at.clear();
+    self.unloaded_chunks.clear();
+    self.chunk_voxels_loaded.clear();
+    self.grass_density.clear();
+    self.voxels = voxel::tree::new();
+    self.queue.clear();
+    self.unstitched.clear();
+    self.unmeshed.clear();
+    self.lights.clear();
+    self.unlit.clear();
+    self.vram_used = 0;
+  }
 }
 
 #[inline(never)]
@@ -1,20 +1,25 @@
 //! Data structure for a small chunk of terrain.
 
-use cgmath::{Point3, Vector3};
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
 use collision::{Aabb, Aabb3};
 use isosurface_extraction::dual_contouring;
 use num::iter::range_inclusive;
 use rand;
+use std::f32::consts::PI;
 use std::sync::Mutex;
 use stopwatch;
 
+use common::fnv_map;
 use common::id_allocator;
+use common::material;
+use common::protocol;
 use common::voxel;
 // TODO: Move the server-only parts to the server, like BLOCK_WIDTH and sample_info.
 
 use chunk;
-use chunk_stats;
 use lod;
+use mesh_simplification;
+use voxel_light;
 
 use view;
 use view::chunked_terrain;
@@ -39,6 +44,66 @@ pub fn tri<T>(v1: T, v2: T, v3: T) -> Triangle<T> {
   }
 }
 
+/// How far simplified terrain can stray from the full mesh, as a fraction of the voxel size.
+const SIMPLIFICATION_TOLERANCE: f32 = 0.125;
+
+/// How much a fully-enclosed vertex is darkened by ambient occlusion, in [0, 1].
+const MAX_OCCLUSION: f32 = 0.7;
+
+/// How exposed a vertex is to ambient light, in [0, 1], judging by how many of the voxels just
+/// outside the surface around it are solid. Creases and crevices get lower values than open ground.
+fn ambient_occlusion(
+  voxels         : &voxel::tree::T,
+  lg_sample_size : i16,
+  position       : &Point3<f32>,
+  normal         : &Vector3<f32>,
+) -> f32 {
+  let size = (1 << lg_sample_size) as f32;
+  let solidity = |p: Point3<f32>| -> f32 {
+    let bounds =
+      voxel::bounds::new(
+        (p.x / size).floor() as i32,
+        (p.y / size).floor() as i32,
+        (p.z / size).floor() as i32,
+        lg_sample_size,
+      );
+    match voxels.get(&bounds) {
+      None |
+      Some(&voxel::Volume(voxel::Material::Empty)) |
+      Some(&voxel::Volume(voxel::Material::Water)) => 0.0,
+      Some(&voxel::Volume(_)) => 1.0,
+      // Surface voxels are about half full.
+      Some(&voxel::Surface(_)) => 0.5,
+    }
+  };
+
+  let normal =
+    if normal.magnitude2() > 0.0 {
+      normal.normalize()
+    } else {
+      Vector3::new(0.0, 1.0, 0.0)
+    };
+  // Two directions along the surface.
+  let tangent = {
+    let axis =
+      if normal.x.abs() < 0.9 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+    normal.cross(axis).normalize()
+  };
+  let bitangent = normal.cross(tangent);
+
+  // Sample a step out from the surface, and around that.
+  let out = *position + normal * size;
+  let samples = [
+    out,
+    out + tangent * size,
+    out + -tangent * size,
+    out + bitangent * size,
+    out + -bitangent * size,
+  ];
+  let occlusion: f32 = samples.iter().map(|&p| solidity(p)).sum::<f32>() / samples.len() as f32;
+  1.0 - MAX_OCCLUSION * occlusion
+}
+
 /// return a vector of all the voxels of a certain size that are contained within an AABB
 pub fn voxels_in(bounds: &Aabb3<i32>, lg_size: i16) -> Vec<voxel::bounds::T> {
   let delta = bounds.max() - (bounds.min());
@@ -114,12 +179,194 @@ mod voxel_storage {
   }
 }
 
+/// The material to blend toward at each vertex of each polygon: whichever material most of the polygons sharing
+/// that vertex are made of. This way, materials fade into each other instead of meeting at hard edges.
+fn vertex_materials(polygons: &[mesh_simplification::Polygon]) -> Vec<Triangle<i32>> {
+  let key = |p: &Point3<f32>| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+  let mut counts: fnv_map::T<(u32, u32, u32), [u32; material::COUNT]> = fnv_map::new();
+  for polygon in polygons {
+    for v in &[polygon.vertices.v1, polygon.vertices.v2, polygon.vertices.v3] {
+      counts.entry(key(v)).or_insert([0; material::COUNT])[polygon.material as usize] += 1;
+    }
+  }
+
+  polygons.iter()
+    .map(|polygon| {
+      let majority = |v: &Point3<f32>| {
+        let counts = &counts[&key(v)];
+        // Ties go to the polygon's own material.
+        let mut majority = polygon.material as usize;
+        for (material, &count) in counts.iter().enumerate() {
+          if count > counts[majority] {
+            majority = material;
+          }
+        }
+        majority as i32
+      };
+      tri(majority(&polygon.vertices.v1), majority(&polygon.vertices.v2), majority(&polygon.vertices.v3))
+    })
+    .collect()
+}
+
+/// For each face of a chunk, in the order of `chunk::FACES`, the LOD of the neighbor across it, if that neighbor
+/// is coarser. The two meshes don't meet exactly along those faces, so skirts are hung there to hide the cracks.
+pub type Skirts = [Option<lod::T>; 6];
+
+/// Hang a skirt from each open edge of a mesh that lies along one of the faces of the box from `low` to `high`
+/// that `depths` has a depth for. A skirt is a strip reaching back under the surface, lit and colored like the
+/// edge it hangs from, so it fills in any gap between this mesh and a coarser neighbor's.
+fn skirts(
+  polygons : &[mesh_simplification::Polygon],
+  low      : &Point3<f32>,
+  high     : &Point3<f32>,
+  depths   : &[Option<f32>; 6],
+) -> Vec<mesh_simplification::Polygon> {
+  let key = |p: &Point3<f32>| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+  let edges_of = |polygon: &mesh_simplification::Polygon| {
+    let v = &polygon.vertices;
+    let n = &polygon.normals;
+    [((v.v1, n.v1), (v.v2, n.v2)), ((v.v2, n.v2), (v.v3, n.v3)), ((v.v3, n.v3), (v.v1, n.v1))]
+  };
+
+  // Edges inside the mesh are shared by two polygons; the open ones belong to only one.
+  let mut uses: fnv_map::T<_, u32> = fnv_map::new();
+  for polygon in polygons {
+    for &((a, _), (b, _)) in edges_of(polygon).iter() {
+      let (a, b) = (key(&a), key(&b));
+      let edge = if a < b { (a, b) } else { (b, a) };
+      *uses.entry(edge).or_insert(0) += 1;
+    }
+  }
+
+  let mut skirts = Vec::new();
+  for polygon in polygons {
+    for &((a, na), (b, nb)) in edges_of(polygon).iter() {
+      let (ka, kb) = (key(&a), key(&b));
+      let edge = if ka < kb { (ka, kb) } else { (kb, ka) };
+      if uses[&edge] != 1 {
+        continue
+      }
+
+      // The edge belongs to whichever face it's closest to.
+      let middle = Point3::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0, (a.z + b.z) / 2.0);
+      let distance = |face: usize| {
+        let axis = face / 2;
+        if face % 2 == 0 { (middle[axis] - low[axis]).abs() } else { (high[axis] - middle[axis]).abs() }
+      };
+      let face = (1 .. 6).fold(0, |closest, face| if distance(face) < distance(closest) { face } else { closest });
+      let depth =
+        match depths[face] {
+          None => continue,
+          Some(depth) => depth,
+        };
+
+      let down = |p: Point3<f32>, n: Vector3<f32>| {
+        if n.magnitude2() > 0.0 { p - n.normalize() * depth } else { p }
+      };
+      let (a_down, b_down) = (down(a, na), down(b, nb));
+      // Wind the other way from the polygon, since the skirt is on the other side of the edge.
+      skirts.push(mesh_simplification::Polygon {
+        vertices : tri(b, a, a_down),
+        normals  : tri(nb, na, na),
+        material : polygon.material,
+      });
+      skirts.push(mesh_simplification::Polygon {
+        vertices : tri(b, a_down, b_down),
+        normals  : tri(nb, na, nb),
+        material : polygon.material,
+      });
+    }
+  }
+  skirts
+}
+
+/// Extract the surface polygons of the voxels between `low` and `high`, which are in units of voxels.
+fn surface(
+  voxels         : &voxel::tree::T,
+  low            : &Point3<i32>,
+  high           : &Point3<i32>,
+  lg_sample_size : i16,
+) -> Vec<mesh_simplification::Polygon> {
+  let mut polygons = Vec::new();
+  {
+    let mut edges = |direction, low_x, high_x, low_y, high_y, low_z, high_z| {
+      for x in range_inclusive(low_x, high_x) {
+      for y in range_inclusive(low_y, high_y) {
+      for z in range_inclusive(low_z, high_z) {
+        trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
+        let edge =
+          dual_contouring::edge::T {
+            low_corner: Point3::new(x, y, z),
+            direction: direction,
+            lg_size: lg_sample_size,
+          };
+
+        let _ =
+          dual_contouring::edge::extract(
+            &mut voxel_storage::T { voxels: voxels },
+            &edge,
+            &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
+              polygons.push(mesh_simplification::Polygon {
+                vertices : tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]),
+                normals  : tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]),
+                material : polygon.material as i32,
+              });
+            }
+          );
+      }}}
+    };
+
+    edges(
+      dual_contouring::edge::Direction::X,
+      low.x, high.x - 1,
+      low.y, high.y - 1,
+      low.z, high.z - 1,
+    );
+    edges(
+      dual_contouring::edge::Direction::Y,
+      low.x, high.x - 1,
+      low.y, high.y - 1,
+      low.z, high.z - 1,
+    );
+    edges(
+      dual_contouring::edge::Direction::Z,
+      low.x, high.x - 1,
+      low.y, high.y - 1,
+      low.z, high.z - 1,
+    );
+  }
+  polygons
+}
+
+/// Simplify the surface polygons of the voxels between `low` and `high`, which are in units of voxels.
+fn simplify(
+  polygons       : &[mesh_simplification::Polygon],
+  low            : &Point3<i32>,
+  high           : &Point3<i32>,
+  lg_sample_size : i16,
+) -> Vec<mesh_simplification::Polygon> {
+  // Vertices in the outermost layer of voxels are shared with the neighboring chunks' meshes,
+  // so leave them where they are to avoid cracks.
+  let size = (1 << lg_sample_size) as f32;
+  let inner_low = Point3::new(low.x as f32 * size, low.y as f32 * size, low.z as f32 * size);
+  let inner_high =
+    Point3::new((high.x - 1) as f32 * size, (high.y - 1) as f32 * size, (high.z - 1) as f32 * size);
+  mesh_simplification::simplify(polygons, size * SIMPLIFICATION_TOLERANCE, |p| {
+    inner_low.x < p.x && p.x < inner_high.x &&
+    inner_low.y < p.y && p.y < inner_high.y &&
+    inner_low.z < p.z && p.z < inner_high.z
+  })
+}
+
 #[allow(missing_docs)]
 pub fn generate<Rng: rand::Rng>(
   voxels          : &voxel::tree::T,
-  chunk_stats     : &mut chunk_stats::T,
   chunk_position  : &chunk::position::T,
   lod             : lod::T,
+  skirts          : &Skirts,
+  grass_density   : f32,
+  sky             : bool,
+  lights          : &[protocol::Light],
   chunk_allocator : &Mutex<id_allocator::T<view::entity::id::Terrain>>,
   grass_allocator : &Mutex<id_allocator::T<view::entity::id::Grass>>,
   rng             : &mut Rng,
@@ -149,75 +396,146 @@ pub fn generate<Rng: rand::Rng>(
     trace!("low {:?}", low);
     trace!("high {:?}", high);
 
+    let mut polygons = surface(voxels, &low, &high, lg_sample_size);
+    if lod >= lod::MIN_SIMPLIFIED_LOD {
+      polygons = simplify(&polygons, &low, &high, lg_sample_size);
+    }
+
+    // Polygons from here on are skirts, which don't grow grass.
+    let surface_polygons = polygons.len();
     {
-      let mut edges = |direction, low_x, high_x, low_y, high_y, low_z, high_z| {
-        for x in range_inclusive(low_x, high_x) {
-        for y in range_inclusive(low_y, high_y) {
-        for z in range_inclusive(low_z, high_z) {
-          trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
-          let edge =
-            dual_contouring::edge::T {
-              low_corner: Point3::new(x, y, z),
-              direction: direction,
-              lg_size: lg_sample_size,
-            };
-
-          let _ =
-            dual_contouring::edge::extract(
-              &mut voxel_storage::T { voxels: voxels },
-              &edge,
-              &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
-                let vertices = tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]);
-                let normals = tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]);
-                let material = polygon.material as i32;
-
-                let grass =
-                  if polygon.material == voxel::Material::Terrain && lod <= lod::MAX_GRASS_LOD {
-                    Some(chunked_terrain::PushGrass {
-                      tex_id : rng.gen_range(0, 9),
-                      id     : grass_allocator.lock().unwrap().allocate(),
-                    })
-                  } else {
-                    None
-                  };
-
-                chunked_terrain.push(
-                  &mut *chunk_allocator.lock().unwrap(),
-                  vertices,
-                  normals,
-                  material,
-                  grass,
-                );
-              }
-            );
-        }}}
-      };
+      let size = (1 << lg_sample_size) as f32;
+      let bounds_low = Point3::new(low.x as f32 * size, low.y as f32 * size, low.z as f32 * size);
+      let bounds_high = Point3::new(high.x as f32 * size, high.y as f32 * size, high.z as f32 * size);
+      let mut depths = [None; 6];
+      for (depth, neighbor) in depths.iter_mut().zip(skirts.iter()) {
+        *depth = neighbor.map(|neighbor| (1 << neighbor.lg_sample_size()) as f32);
+      }
+      let skirts = self::skirts(&polygons, &bounds_low, &bounds_high, &depths);
+      polygons.extend(skirts);
+    }
 
-      edges(
-        dual_contouring::edge::Direction::X,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
-      );
-      edges(
-        dual_contouring::edge::Direction::Y,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
+    // Light gets through anything that isn't solid all the way through. Around the chunk, we may only have coarser
+    // voxels than ours, so fall back on those; where we have nothing at all, assume it's open.
+    let light =
+      voxel_light::propagate(
+        |p| {
+          for lg_size in lg_sample_size .. chunk::LG_WIDTH as i16 + 1 {
+            let shift = lg_size - lg_sample_size;
+            match voxels.get(&voxel::bounds::new(p.x >> shift, p.y >> shift, p.z >> shift, lg_size)) {
+              None => continue,
+              Some(&voxel::Surface(_)) |
+              Some(&voxel::Volume(voxel::Material::Empty)) |
+              Some(&voxel::Volume(voxel::Material::Water)) => return false,
+              Some(&voxel::Volume(_)) => return true,
+            }
+          }
+          false
+        },
+        lg_sample_size,
+        low,
+        1 << lg_edge_samples,
+        sky,
+        lights,
       );
-      edges(
-        dual_contouring::edge::Direction::Z,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
+
+    let vertex_materials = vertex_materials(&polygons);
+    for (i, (polygon, materials)) in polygons.into_iter().zip(vertex_materials.into_iter()).enumerate() {
+      let grass =
+        if i < surface_polygons &&
+           polygon.material == voxel::Material::Terrain as i32 &&
+           lod <= lod::MAX_GRASS_LOD &&
+           rng.gen::<f32>() < grass_density {
+          // Tufts stand in the middle of their polygons.
+          let root = (polygon.vertices.v1.to_vec() + polygon.vertices.v2.to_vec() + polygon.vertices.v3.to_vec()) / 3.0;
+          Some(chunked_terrain::PushGrass {
+            tex_id : rng.gen_range(0, 9),
+            phase  : rng.gen_range(0.0, 2.0 * PI),
+            cell   : Point3::new(root.x.floor() as i32, root.y.floor() as i32, root.z.floor() as i32),
+            id     : grass_allocator.lock().unwrap().allocate(),
+          })
+        } else {
+          None
+        };
+
+      let ambient_occlusion =
+        tri(
+          ambient_occlusion(voxels, lg_sample_size, &polygon.vertices.v1, &polygon.normals.v1),
+          ambient_occlusion(voxels, lg_sample_size, &polygon.vertices.v2, &polygon.normals.v2),
+          ambient_occlusion(voxels, lg_sample_size, &polygon.vertices.v3, &polygon.normals.v3),
+        );
+      let light =
+        tri(
+          light.at(&polygon.vertices.v1, &polygon.normals.v1),
+          light.at(&polygon.vertices.v2, &polygon.normals.v2),
+          light.at(&polygon.vertices.v3, &polygon.normals.v3),
+        );
+
+      chunked_terrain.push(
+        &mut *chunk_allocator.lock().unwrap(),
+        polygon.vertices,
+        polygon.normals,
+        ambient_occlusion,
+        light,
+        materials,
+        grass,
       );
     }
 
-    chunk_stats.add(chunked_terrain.polygon_count());
     chunked_terrain
   })
 }
 
+/// Generate a chunk's water surface: a quad on top of each water voxel that's open to the air.
+pub fn generate_water(
+  voxels         : &voxel::tree::T,
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+) -> Vec<Triangle<Point3<f32>>>
+{
+  stopwatch::time("terrain_mesh::generate_water", || {
+    let lg_edge_samples = lod.lg_edge_samples();
+    let lg_sample_size = lod.lg_sample_size();
+    let size = (1 << lg_sample_size) as f32;
+
+    let material = |x, y, z| {
+      voxels.get(&voxel::bounds::new(x, y, z, lg_sample_size)).map(|voxel| {
+        match voxel {
+          &voxel::Surface(ref voxel) => voxel.corner,
+          &voxel::Volume(material) => material,
+        }
+      })
+    };
+
+    let low = *chunk_position.as_pnt();
+    let low =
+      Point3::new(
+        low.x << lg_edge_samples,
+        low.y << lg_edge_samples,
+        low.z << lg_edge_samples,
+      );
+    let edge_samples = 1 << lg_edge_samples;
+
+    let mut triangles = Vec::new();
+    for x in low.x .. low.x + edge_samples {
+    for y in low.y .. low.y + edge_samples {
+    for z in low.z .. low.z + edge_samples {
+      if material(x, y, z) != Some(voxel::Material::Water) ||
+         material(x, y + 1, z) != Some(voxel::Material::Empty) {
+        continue
+      }
+
+      let top = (y + 1) as f32 * size;
+      let (x0, x1) = (x as f32 * size, (x + 1) as f32 * size);
+      let (z0, z1) = (z as f32 * size, (z + 1) as f32 * size);
+      triangles.push(tri(Point3::new(x0, top, z0), Point3::new(x0, top, z1), Point3::new(x1, top, z1)));
+      triangles.push(tri(Point3::new(x0, top, z0), Point3::new(x1, top, z1), Point3::new(x1, top, z0)));
+    }}}
+
+    triangles
+  })
+}
+
 /// All the information required to construct a grass tuft in vram
 #[derive(Debug, Clone)]
 pub struct Grass {
@@ -258,3 +576,92 @@ pub struct Ids {
   #[allow(missing_docs)]
   pub grass_ids : Vec<view::entity::id::Grass>,
 }
+
+#[test]
+fn skirts_hang_from_open_edges_on_coarser_faces() {
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let corner = |x, z| Point3::new(x, 0.5, z);
+  let square = [
+    mesh_simplification::Polygon {
+      vertices : tri(corner(0.0, 0.0), corner(0.0, 1.0), corner(1.0, 1.0)),
+      normals  : tri(up, up, up),
+      material : 0,
+    },
+    mesh_simplification::Polygon {
+      vertices : tri(corner(0.0, 0.0), corner(1.0, 1.0), corner(1.0, 0.0)),
+      normals  : tri(up, up, up),
+      material : 0,
+    },
+  ];
+  let low = Point3::new(0.0, 0.0, 0.0);
+  let high = Point3::new(1.0, 1.0, 1.0);
+
+  assert!(skirts(&square, &low, &high, &[None; 6]).is_empty());
+
+  // Only the +x side of the square gets a skirt, hanging straight down.
+  let skirts = skirts(&square, &low, &high, &[None, Some(0.25), None, None, None, None]);
+  assert_eq!(skirts.len(), 2);
+  for polygon in &skirts {
+    for v in &[polygon.vertices.v1, polygon.vertices.v2, polygon.vertices.v3] {
+      assert_eq!(v.x, 1.0);
+      assert!(v.y == 0.5 || v.y == 0.25);
+    }
+  }
+}
+
+#[test]
+fn generated_terrain_is_simplified_without_moving_its_edges() {
+  use terrain;
+
+  let world =
+    terrain::T::new(
+      terrain::Seed::new(0),
+      terrain::LATEST_VERSION,
+      terrain::Shape::Biomes(Default::default(), terrain::structures::load(&Default::default()).0),
+    );
+
+  // A chunk at full detail, where the ground is.
+  let lg_sample_size = lod::T(0).lg_sample_size();
+  let ground = world.surface_height(4.0, 4.0).unwrap();
+  let chunk = chunk::position::of_world_position(&Point3::new(4.0, ground, 4.0));
+  let low = *chunk.as_pnt();
+  let low = Point3::new(low.x << chunk::LG_WIDTH, low.y << chunk::LG_WIDTH, low.z << chunk::LG_WIDTH);
+  let width = chunk::WIDTH as i32;
+  let high = Point3::new(low.x + width, low.y + width, low.z + width);
+
+  let mut voxels = voxel::tree::new();
+  let around =
+    Aabb3::new(
+      Point3::new(low.x - 1, low.y - 1, low.z - 1),
+      Point3::new(high.x + 1, high.y + 1, high.z + 1),
+    );
+  for bounds in voxels_in(&around, lg_sample_size) {
+    voxels.get_mut_or_create(&bounds).data = Some(world.load(&bounds));
+  }
+
+  let polygons = surface(&voxels, &low, &high, lg_sample_size);
+  assert!(!polygons.is_empty());
+  let simplified = simplify(&polygons, &low, &high, lg_sample_size);
+  assert!(simplified.len() < polygons.len());
+
+  // Every vertex outside the chunk's interior is still there.
+  let inner_low = Point3::new(low.x as f32, low.y as f32, low.z as f32);
+  let inner_high = Point3::new((high.x - 1) as f32, (high.y - 1) as f32, (high.z - 1) as f32);
+  let interior = |p: &Point3<f32>| {
+    inner_low.x < p.x && p.x < inner_high.x &&
+    inner_low.y < p.y && p.y < inner_high.y &&
+    inner_low.z < p.z && p.z < inner_high.z
+  };
+  let vertices = |polygons: &[mesh_simplification::Polygon]| {
+    let mut vertices: Vec<[u32; 3]> =
+      polygons.iter()
+      .flat_map(|p| vec!(p.vertices.v1, p.vertices.v2, p.vertices.v3))
+      .filter(|p| !interior(p))
+      .map(|p| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()])
+      .collect();
+    vertices.sort();
+    vertices.dedup();
+    vertices
+  };
+  assert_eq!(vertices(&simplified), vertices(&polygons));
+}
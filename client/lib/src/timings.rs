@@ -0,0 +1,67 @@
+//! Running averages of how long the client's main jobs take, for the debug overlay.
+//! `stopwatch` keeps its totals per thread and only reports them on exit, so jobs timed here are also
+//! timed with `stopwatch`, under the same names.
+
+use std::sync::Mutex;
+use stopwatch;
+use time;
+
+/// How much each new sample moves an average.
+const SMOOTHING: f64 = 0.05;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Average nanoseconds per job, in the order the jobs were first timed.
+  averages: Vec<(&'static str, f64)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    averages: Vec::new(),
+  }
+}
+
+impl T {
+  /// Count one run of a job that took `ns` nanoseconds.
+  pub fn record(&mut self, name: &'static str, ns: u64) {
+    let ns = ns as f64;
+    match self.averages.iter_mut().find(|&&mut (n, _)| n == name) {
+      Some(&mut (_, ref mut average)) => {
+        *average += (ns - *average) * SMOOTHING;
+        return
+      },
+      None => {},
+    }
+    self.averages.push((name, ns));
+  }
+
+  /// Each job's name and its average time, in milliseconds.
+  pub fn averages_ms(&self) -> Vec<(&'static str, f64)> {
+    self.averages.iter().map(|&(name, ns)| (name, ns / 1_000_000.0)).collect()
+  }
+}
+
+/// Run a job under `stopwatch::time`, and record how long it took.
+pub fn time<R, F: FnOnce() -> R>(timings: &Mutex<T>, name: &'static str, f: F) -> R {
+  stopwatch::time(name, || {
+    let start = time::precise_time_ns();
+    let r = f();
+    timings.lock().unwrap().record(name, time::precise_time_ns() - start);
+    r
+  })
+}
+
+#[test]
+fn averages_follow_recent_samples() {
+  let mut timings = new();
+  timings.record("a", 1_000_000);
+  timings.record("b", 3_000_000);
+  for _ in 0 .. 200 {
+    timings.record("a", 2_000_000);
+  }
+  let averages = timings.averages_ms();
+  assert_eq!(averages[0].0, "a");
+  assert!((averages[0].1 - 2.0).abs() < 0.01);
+  assert_eq!(averages[1], ("b", 3.0));
+}
@@ -7,17 +7,21 @@ use time;
 use common::protocol;
 use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
+use common::voxel;
 
 use audio_thread;
 use chunk;
+use chunk_requests;
 use chunk_stats;
 use client;
 use lod;
-use server_update::apply_server_update;
+use server_update::{apply_server_update, move_player, show_entities};
 use terrain;
+use timings;
 use view;
 
-const MAX_OUTSTANDING_TERRAIN_REQUESTS: u32 = 1;
+/// How many chunk requests the server can be working on for this client at once.
+const MAX_OUTSTANDING_TERRAIN_REQUESTS: usize = 8;
 
 #[allow(missing_docs)]
 pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateServer, EnqueueTerrainLoad>(
@@ -45,15 +49,24 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
       break 'update_loop
     } else {
       stopwatch::time("update_iteration", || {
-        stopwatch::time("process_server_updates", || {
+        timings::time(&client.timings, "process_server_updates", || {
           process_server_updates(client, recv_server, update_view0, update_audio, update_server, enqueue_terrain_load);
         });
 
-        stopwatch::time("update_surroundings", || {
-          update_surroundings(client, &mut chunk_stats, update_view1, update_server);
+        timings::time(&client.timings, "predict_movement", || {
+          predict_movement(client, update_view0);
         });
 
-        stopwatch::time("process_voxel_updates", || {
+        timings::time(&client.timings, "show_entities", || {
+          ping(client, update_server);
+          show_entities(client, update_view0);
+        });
+
+        timings::time(&client.timings, "update_surroundings", || {
+          update_surroundings(client, update_view1, update_server);
+        });
+
+        timings::time(&client.timings, "process_voxel_updates", || {
           process_voxel_updates(client, &mut chunk_stats, update_view1);
         });
       })
@@ -64,10 +77,35 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   chunk_stats.output_to("vram_chunk_loads.out");
 }
 
+#[inline(never)]
+fn predict_movement<UpdateView>(
+  client      : &client::T,
+  update_view : &mut UpdateView,
+) where
+  UpdateView: FnMut(view::update::T),
+{
+  let mut prediction = client.prediction.lock().unwrap();
+  if prediction.tick(&client.terrain.lock().unwrap()) {
+    move_player(client, update_view, &prediction);
+  }
+}
+
+/// Measure the round trip to the server every so often, and keep track of its clock.
+fn ping<UpdateServer>(
+  client        : &client::T,
+  update_server : &mut UpdateServer,
+) where
+  UpdateServer : FnMut(protocol::ClientToServer),
+{
+  let now = time::precise_time_ns();
+  if client.clock_sync.lock().unwrap().should_ping(now) {
+    update_server(protocol::ClientToServer::Ping(client.id, now));
+  }
+}
+
 #[inline(never)]
 fn update_surroundings<UpdateView, UpdateServer>(
   client        : &client::T,
-  chunk_stats   : &mut chunk_stats::T,
   update_view   : &mut UpdateView,
   update_server : &mut UpdateServer,
 ) where
@@ -78,13 +116,20 @@ fn update_surroundings<UpdateView, UpdateServer>(
   let mut i = 0;
   let load_position = {
     let load_position = *client.load_position.lock().unwrap();
-    load_position.unwrap_or_else(|| *client.player_position.lock().unwrap())
+    let spectator_position = *client.spectator_position.lock().unwrap();
+    load_position.or(spectator_position).unwrap_or_else(|| *client.player_position.lock().unwrap())
   };
   let load_position = chunk::position::of_world_position(&load_position);
+  let (load_distance, lod_thresholds) = {
+    let render_distance = client.render_distance.lock().unwrap();
+    (render_distance.distance(), render_distance.lod_thresholds())
+  };
   let mut surroundings_loader = client.surroundings_loader.lock().unwrap();
+  surroundings_loader.set_range(load_distance, lod_thresholds.iter().map(|&x| x as i32).collect());
+  client.terrain.lock().unwrap().set_range(load_distance, lod_thresholds);
   let mut updates = surroundings_loader.updates(load_position.as_pnt()) ;
   loop {
-    if *client.pending_terrain_requests.lock().unwrap() >= MAX_OUTSTANDING_TERRAIN_REQUESTS {
+    if client.chunk_requests.lock().unwrap().queued_len() >= chunk_requests::MAX_QUEUED {
       trace!("update loop breaking");
       break;
     }
@@ -105,34 +150,44 @@ fn update_surroundings<UpdateView, UpdateServer>(
         load_position.as_pnt(),
         chunk_position.as_pnt(),
       );
+    let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
+    let new_lod = lod::of_distance_from(&lod_thresholds, distance as u32, load_state);
     match load_type {
       LoadType::Load => {
         stopwatch::time("update_thread.load_chunk", || {
           trace!("Loading distance {}", distance);
-          let new_lod = lod::of_distance(distance as u32);
-          let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
           if load_state == Some(new_lod) {
             debug!("Not re-loading {:?} at {:?}", chunk_position, new_lod);
+          } else if let Some(settled_at) = lod_settles_at(client, &chunk_position) {
+            updates.recheck_later(*chunk_position.as_pnt(), settled_at);
           } else {
-            load_or_request_chunk(client, chunk_stats, update_server, update_view, &chunk_position, new_lod);
+            load_or_queue_chunk(client, update_view, &chunk_position, new_lod);
           }
         })
       },
       LoadType::Downgrade => {
         stopwatch::time("update_thread.update_chunk", || {
-          let new_lod = lod::of_distance(distance as u32);
-          let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
           let is_downgrade = load_state.map(|lod| new_lod < lod) == Some(true);
-          if is_downgrade {
-            load_or_request_chunk(client, chunk_stats, update_server, update_view, &chunk_position, new_lod);
-          } else {
+          if !is_downgrade {
             trace!("Not updating {:?} at {:?}", chunk_position, new_lod);
+          } else if let Some(settled_at) = lod_settles_at(client, &chunk_position) {
+            updates.recheck_later(*chunk_position.as_pnt(), settled_at);
+          } else {
+            load_or_queue_chunk(client, update_view, &chunk_position, new_lod);
           }
         })
       },
       LoadType::Unload => {
         stopwatch::time("update_thread.unload", || {
           client.terrain.lock().unwrap().unload(update_view, &chunk_position);
+          if let Some(time_requested_ns) = client.chunk_requests.lock().unwrap().forget(&chunk_position) {
+            update_server(
+              protocol::ClientToServer::CancelChunkRequest {
+                client_id         : client.id,
+                time_requested_ns : time_requested_ns,
+              }
+            );
+          }
         })
       },
     };
@@ -145,44 +200,71 @@ fn update_surroundings<UpdateView, UpdateServer>(
     }
     i += 1;
   }
+
+  let forward = client.prediction.lock().unwrap().forward();
+  let fov = client.config.fov_degrees.to_radians();
+  loop {
+    if client.chunk_requests.lock().unwrap().outstanding_len() >= MAX_OUTSTANDING_TERRAIN_REQUESTS {
+      break
+    }
+    let next = client.chunk_requests.lock().unwrap().pop(load_position.as_pnt(), &forward, fov);
+    match next {
+      None => break,
+      Some((chunk_position, lod)) => {
+        // The voxels may have arrived since this was queued.
+        if let Err(voxels) = load_chunk(client, update_view, &chunk_position, lod) {
+          let time_requested_ns = time::precise_time_ns();
+          update_server(
+            protocol::ClientToServer::RequestVoxels {
+              time_requested_ns : time_requested_ns,
+              client_id         : client.id,
+              world             : *client.world.lock().unwrap(),
+              voxels            : voxels,
+            }
+          );
+          client.chunk_requests.lock().unwrap().sent(chunk_position, lod, time_requested_ns);
+        }
+      },
+    }
+  }
+}
+
+/// If a chunk changed LOD too recently to change again yet, when it can.
+fn lod_settles_at(client: &client::T, chunk_position: &chunk::position::T) -> Option<u64> {
+  client.terrain.lock().unwrap().lod_changed_at(chunk_position)
+    .map(|changed_at| changed_at + lod::MIN_DWELL_NS)
+    .filter(|&settled_at| settled_at > time::precise_time_ns())
 }
 
-fn load_or_request_chunk<UpdateServer, UpdateView>(
+/// Load a chunk from voxels we already have, or queue it to be requested from the server.
+fn load_or_queue_chunk<UpdateView>(
   client         : &client::T,
-  chunk_stats    : &mut chunk_stats::T,
-  update_server  : &mut UpdateServer,
   update_view    : &mut UpdateView,
   chunk_position : &chunk::position::T,
   lod            : lod::T,
 ) where
-  UpdateServer: FnMut(protocol::ClientToServer),
+  UpdateView: FnMut(view::update::T),
+{
+  if load_chunk(client, update_view, chunk_position, lod).is_err() {
+    client.chunk_requests.lock().unwrap().enqueue(*chunk_position, lod);
+  }
+}
+
+/// Load a chunk from voxels we already have. If some are missing, returns them.
+fn load_chunk<UpdateView>(
+  client         : &client::T,
+  update_view    : &mut UpdateView,
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+) -> Result<(), Vec<voxel::bounds::T>> where
   UpdateView: FnMut(view::update::T),
 {
   let mut terrain = client.terrain.lock().unwrap();
-  let rng = &mut *client.rng.lock().unwrap();
-  let r =
-    terrain.load_chunk(
-      &client.terrain_allocator,
-      &client.grass_allocator,
-      &mut *rng,
-      chunk_stats,
-      update_view,
-      chunk_position,
-      lod,
-    );
-  match r {
-    Ok(()) => {},
-    Err(voxels) => {
-      update_server(
-        protocol::ClientToServer::RequestVoxels {
-          time_requested_ns : time::precise_time_ns(),
-          client_id       : client.id,
-          voxels          : voxels,
-        }
-      );
-      *client.pending_terrain_requests.lock().unwrap() += 1;
-    },
+  if terrain.load_cached_chunk(update_view, chunk_position, lod) {
+    return Ok(())
   }
+
+  terrain.load_chunk(chunk_position, lod)
 }
 
 #[inline(never)]
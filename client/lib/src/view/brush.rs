@@ -0,0 +1,91 @@
+//! The brush picked for editing terrain directly, instead of digging and placing.
+
+use common::protocol;
+
+/// The smallest brush radius.
+const MIN_RADIUS: f32 = 1.0;
+/// The largest brush radius. The server won't make brushes bigger than this anyway.
+const MAX_RADIUS: f32 = 16.0;
+/// How much the smoothing and flattening brushes do per use.
+const STRENGTH: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Shape {
+  Sphere,
+  Cube,
+  Cylinder,
+  Smooth,
+  Flatten,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Is None if no brush is picked.
+  shape  : Option<Shape>,
+  radius : f32,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    shape  : None,
+    radius : 4.0,
+  }
+}
+
+impl T {
+  /// Pick the next brush shape, going back to no brush after the last one.
+  pub fn next_shape(&mut self) {
+    self.shape =
+      match self.shape {
+        None                  => Some(Shape::Sphere),
+        Some(Shape::Sphere)   => Some(Shape::Cube),
+        Some(Shape::Cube)     => Some(Shape::Cylinder),
+        Some(Shape::Cylinder) => Some(Shape::Smooth),
+        Some(Shape::Smooth)   => Some(Shape::Flatten),
+        Some(Shape::Flatten)  => None,
+      };
+  }
+
+  /// Double or halve the radius, within limits.
+  pub fn scale(&mut self, grow: bool) {
+    let radius = if grow { self.radius * 2.0 } else { self.radius / 2.0 };
+    self.radius = radius.max(MIN_RADIUS).min(MAX_RADIUS);
+  }
+
+  /// The brush to send to the server, if one is picked.
+  pub fn brush(&self) -> Option<protocol::Brush> {
+    let radius = self.radius;
+    self.shape.map(|shape| {
+      match shape {
+        Shape::Sphere   => protocol::Brush::Sphere { radius: radius },
+        Shape::Cube     => protocol::Brush::Cube { radius: radius },
+        Shape::Cylinder => protocol::Brush::Cylinder { radius: radius },
+        Shape::Smooth   => protocol::Brush::Smooth { radius: radius, strength: STRENGTH },
+        Shape::Flatten  => protocol::Brush::Flatten { radius: radius, strength: STRENGTH },
+      }
+    })
+  }
+
+  /// A description for the HUD, if a brush is picked.
+  pub fn label(&self) -> Option<String> {
+    self.shape.map(|shape| format!("{:?} brush, radius {}", shape, self.radius))
+  }
+}
+
+#[test]
+fn shapes_cycle_and_radius_is_clamped() {
+  let mut brush = new();
+  assert!(brush.brush().is_none());
+  brush.next_shape();
+  assert_eq!(brush.label(), Some(String::from("Sphere brush, radius 4")));
+  for _ in 0 .. 10 {
+    brush.scale(true);
+  }
+  assert_eq!(brush.radius, MAX_RADIUS);
+  for _ in 0 .. 5 {
+    brush.next_shape();
+  }
+  assert!(brush.brush().is_none());
+}
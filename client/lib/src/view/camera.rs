@@ -74,6 +74,18 @@ impl T {
     self.rotate(&Vector3::new(0.0, 1.0, 0.0), r);
   }
 
+  /// The direction the camera is looking in.
+  pub fn forward(&self) -> Vector3<f32> {
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let right =
+      Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
+        * Vector3::new(1.0, 0.0, 0.0);
+    let transform =
+      Matrix3::from_axis_angle(right, cgmath::Rad(self.vertical_rotation))
+        * Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation));
+    transform * Vector3::new(0.0, 0.0, -1.0)
+  }
+
   /// Changes the camera pitch by `r` radians. Positive is up.
   /// Angles that "flip around" (i.e. looking too far up or down)
   /// are sliently rejected.
@@ -0,0 +1,173 @@
+//! Chat messages and the chat entry line, drawn on the HUD as a texture per line.
+
+use cgmath::{Point3, Vector2};
+use gl;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::TextureUnit;
+use yaglw::vertex_buffer::GLArray;
+
+use vertex::TextureVertex;
+use view;
+use view::fontloader::FontLoader;
+use view::ttf;
+
+/// The number of received messages to show.
+const MAX_MESSAGES: usize = 8;
+/// Longer lines are cut off.
+pub const MAX_LINE_LENGTH: usize = 120;
+
+/// Distance from the edge of the window, in pixels.
+const MARGIN: f32 = 8.0;
+
+/// A rendered line of text, and the quad to draw it on.
+struct Line<'a> {
+  text : ttf::Text<'a>,
+  quad : GLArray<'a, TextureVertex>,
+}
+
+/// Recent chat messages, and the message being typed.
+pub struct T<'a> {
+  messages : std::collections::VecDeque<String>,
+  /// The message being typed, if the player is typing one.
+  draft    : Option<String>,
+  /// The currently-displayed lines. This is None if the text has changed since they were rendered.
+  lines    : Option<Vec<Line<'a>>>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a>() -> T<'a> {
+  T {
+    messages : std::collections::VecDeque::new(),
+    draft    : None,
+    lines    : None,
+  }
+}
+
+fn truncated(s: &str) -> String {
+  s.chars().take(MAX_LINE_LENGTH).collect()
+}
+
+impl<'a> T<'a> {
+  /// Show a received message.
+  pub fn push_message(&mut self, message: &str) {
+    if self.messages.len() >= MAX_MESSAGES {
+      self.messages.pop_front();
+    }
+    self.messages.push_back(truncated(message));
+    self.lines = None;
+  }
+
+  /// Start typing a new message.
+  pub fn start_typing(&mut self) {
+    self.draft = Some(String::new());
+    self.lines = None;
+  }
+
+  /// Append to the message being typed.
+  pub fn type_text(&mut self, text: &str) {
+    if let Some(ref mut draft) = self.draft {
+      draft.push_str(text);
+      *draft = truncated(draft);
+    }
+    self.lines = None;
+  }
+
+  /// Delete the last character of the message being typed.
+  pub fn backspace(&mut self) {
+    if let Some(ref mut draft) = self.draft {
+      draft.pop();
+    }
+    self.lines = None;
+  }
+
+  /// Stop typing, and return the typed message if it's worth sending.
+  pub fn finish_typing(&mut self) -> Option<String> {
+    self.lines = None;
+    self.draft.take()
+      .map(|draft| draft.trim().to_owned())
+      .and_then(|draft| if draft.is_empty() { None } else { Some(draft) })
+  }
+
+  fn render_lines<'b>(
+    &self,
+    gl: &'b mut GLContext,
+    shader: &view::shaders::texture::T<'a>,
+    fonts: &FontLoader,
+    window_size: &Vector2<i32>,
+  ) -> Vec<Line<'a>> where
+    'a: 'b,
+  {
+    let mut lines: Vec<String> = self.messages.iter().cloned().collect();
+    if let Some(ref draft) = self.draft {
+      lines.push(format!("> {}_", draft));
+    }
+
+    // The HUD spans [-1, 1] vertically, and proportionally wider horizontally.
+    let units_per_pixel = 2.0 / window_size.y as f32;
+    let aspect = window_size.x as f32 / window_size.y as f32;
+    let left = -aspect + MARGIN * units_per_pixel;
+    let bottom = -1.0 + MARGIN * units_per_pixel;
+    let line_height = fonts.mono.height() as f32 * units_per_pixel;
+
+    let mut rendered = Vec::new();
+    for (row, line) in lines.iter().rev().enumerate() {
+      // Empty lines take up room, but there's nothing to render.
+      if line.is_empty() {
+        continue
+      }
+      let text = fonts.mono.white(gl, line);
+      let x0 = left;
+      let y0 = bottom + row as f32 * line_height;
+      let x1 = x0 + text.size.x as f32 * units_per_pixel;
+      let y1 = y0 + text.size.y as f32 * units_per_pixel;
+      let vtx = |x, y, u, v| {
+        TextureVertex {
+          world_position   : Point3::new(x, y, 0.0),
+          texture_position : Vector2::new(u, v),
+        }
+      };
+      // The texture's rows are stored top-down, so v = 0 is the top of the line.
+      let quad = [
+        vtx(x0, y0, 0.0, 1.0), vtx(x1, y1, 1.0, 0.0), vtx(x0, y1, 0.0, 0.0),
+        vtx(x0, y0, 0.0, 1.0), vtx(x1, y0, 1.0, 1.0), vtx(x1, y1, 1.0, 0.0),
+      ];
+      let quad = view::build_hud_quads(gl, shader, &quad);
+      rendered.push(Line { text: text, quad: quad });
+    }
+    rendered
+  }
+
+  /// Draw the chat text.
+  pub fn draw<'b>(
+    &mut self,
+    gl: &'b mut GLContext,
+    shader: &mut view::shaders::texture::T<'a>,
+    fonts: &FontLoader,
+    texture_unit: &TextureUnit,
+    window_size: &Vector2<i32>,
+  ) where
+    'a: 'b,
+  {
+    if self.lines.is_none() {
+      let lines = self.render_lines(gl, shader, fonts, window_size);
+      self.lines = Some(lines);
+    }
+
+    shader.shader.use_shader(gl);
+    unsafe {
+      gl::ActiveTexture(texture_unit.gl_id());
+      gl::Disable(gl::DEPTH_TEST);
+    }
+    for line in self.lines.as_ref().unwrap() {
+      unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, line.text.texture.handle.gl_id);
+      }
+      line.quad.bind(gl);
+      line.quad.draw(gl);
+    }
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+    }
+  }
+}
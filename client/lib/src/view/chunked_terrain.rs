@@ -1,7 +1,7 @@
 //! Terrain allocated in vram-sized chunks.
 
 use gl::types::*;
-use cgmath::{Point3, Vector3};
+use cgmath::{Point3, Vector2, Vector3};
 use terrain_mesh;
 
 use common::id_allocator;
@@ -16,6 +16,10 @@ use super::entity;
 pub struct Grass {
   /// subtexture indices
   pub tex_ids : Vec<u32>,
+  /// where each tuft starts in its swaying cycle
+  pub phases : Vec<f32>,
+  /// the voxel each tuft grows in, which is how the server refers to it
+  pub cells : Vec<Point3<i32>>,
   #[allow(missing_docs)]
   pub ids : Vec<entity::id::Grass>,
   /// id of the vram terrain chunk for each polygon that the grass tufts rest on
@@ -28,6 +32,8 @@ impl Grass {
   fn empty() -> Self {
     Grass {
       tex_ids           : Vec::new(),
+      phases            : Vec::new(),
+      cells             : Vec::new(),
       ids               : Vec::new(),
       polygon_chunk_ids : Vec::new(),
       polygon_offsets   : Vec::new(),
@@ -49,8 +55,12 @@ pub struct T {
   pub vertex_coordinates: Vec<Chunk<terrain_mesh::Triangle<Point3<f32>>>>,
   /// Vertex normals. These should be normalized!
   pub normals: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<Vector3<f32>>>>,
-  /// Material IDs for each triangle.
-  pub materials: Vec<terrain_buffers::Chunk<i32>>,
+  /// How exposed each vertex is to ambient light, in [0, 1].
+  pub ambient_occlusion: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<f32>>>,
+  /// How much sky light and torch light reach each vertex, in [0, 1].
+  pub light: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<Vector2<f32>>>>,
+  /// The material each vertex blends toward.
+  pub materials: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<i32>>>,
   /// per-chunk ids
   pub ids: Vec<entity::id::Terrain>,
   pub grass : Grass,
@@ -63,6 +73,10 @@ pub struct T {
 pub struct PushGrass {
   #[allow(missing_docs)]
   pub tex_id : u32,
+  /// where this tuft starts in its swaying cycle, in radians
+  pub phase  : f32,
+  /// the voxel this tuft grows in
+  pub cell   : Point3<i32>,
   #[allow(missing_docs)]
   pub id     : entity::id::Grass,
 }
@@ -90,11 +104,13 @@ impl T {
   #[allow(missing_docs)]
   pub fn push(
     &mut self,
-    id_allocator : &mut id_allocator::T<entity::id::Terrain>,
-    vertices     : terrain_mesh::Triangle<Point3<GLfloat>>,
-    normals      : terrain_mesh::Triangle<Vector3<GLfloat>>,
-    material     : GLint,
-    grass        : Option<PushGrass>,
+    id_allocator      : &mut id_allocator::T<entity::id::Terrain>,
+    vertices          : terrain_mesh::Triangle<Point3<GLfloat>>,
+    normals           : terrain_mesh::Triangle<Vector3<GLfloat>>,
+    ambient_occlusion : terrain_mesh::Triangle<GLfloat>,
+    light             : terrain_mesh::Triangle<Vector2<GLfloat>>,
+    materials         : terrain_mesh::Triangle<GLint>,
+    grass             : Option<PushGrass>,
   ) {
     // After this block executes, then it is unconditionally true that we write to the last chunk in every `Vec` at this index.
     // We only allocate a new chunk when we know we will actually write data to it, to avoid conceptual ambiguity between the
@@ -104,7 +120,10 @@ impl T {
       self.vertex_coordinates.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
       let zero = Vector3::new(0.0, 0.0, 0.0);
       self.normals.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
-      self.materials.push(terrain_buffers::Chunk([0; terrain_buffers::CHUNK_LENGTH]));
+      self.ambient_occlusion.push(terrain_buffers::Chunk([terrain_mesh::tri(1.0, 1.0, 1.0); terrain_buffers::CHUNK_LENGTH]));
+      let zero = Vector2::new(0.0, 0.0);
+      self.light.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
+      self.materials.push(terrain_buffers::Chunk([terrain_mesh::tri(0, 0, 0); terrain_buffers::CHUNK_LENGTH]));
       let id = id_allocator.allocate();
       self.ids.push(id);
     }
@@ -113,12 +132,16 @@ impl T {
 
     self.vertex_coordinates.last_mut().unwrap().0[self.next_idx_inside_chunks] = vertices;
     self.normals.last_mut().unwrap().0[self.next_idx_inside_chunks] = normals;
-    self.materials.last_mut().unwrap().0[self.next_idx_inside_chunks] = material;
+    self.ambient_occlusion.last_mut().unwrap().0[self.next_idx_inside_chunks] = ambient_occlusion;
+    self.light.last_mut().unwrap().0[self.next_idx_inside_chunks] = light;
+    self.materials.last_mut().unwrap().0[self.next_idx_inside_chunks] = materials;
 
     grass.map(|grass| {
       self.grass.polygon_chunk_ids.push(chunk_id);
       self.grass.polygon_offsets.push(index::of_u32(self.next_idx_inside_chunks as u32));
       self.grass.tex_ids.push(grass.tex_id);
+      self.grass.phases.push(grass.phase);
+      self.grass.cells.push(grass.cell);
       self.grass.ids.push(grass.id);
     });
 
@@ -131,6 +154,8 @@ pub fn empty() -> T {
   T {
     vertex_coordinates     : Vec::new(),
     normals                : Vec::new(),
+    ambient_occlusion      : Vec::new(),
+    light                  : Vec::new(),
     materials              : Vec::new(),
     ids                    : Vec::new(),
     grass                  : Grass::empty(),
@@ -0,0 +1,268 @@
+//! Clustered forward shading: the view frustum is cut into a grid of clusters (screen tiles, each split into
+//! slices by depth), and every cluster gets a list of the point lights that might reach it. Fragments then only
+//! loop over their own cluster's lights, instead of every light in the world.
+//! The clusters are filled on the CPU each frame, since compute shaders need a newer GL than the view asks for,
+//! and read by the shaders from buffer textures.
+
+use cgmath;
+use cgmath::{Matrix4, Vector4};
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::texture::{BufferTexture, TextureUnit};
+
+use common::id_allocator;
+use common::protocol;
+
+use view::camera;
+use view::light;
+
+/// The number of tiles across the screen.
+pub const TILES_X: usize = 16;
+/// The number of tiles up the screen.
+pub const TILES_Y: usize = 9;
+/// The number of slices each tile is cut into by depth. Slices get exponentially deeper further away.
+pub const SLICES: usize = 24;
+#[allow(missing_docs)]
+pub const CLUSTERS: usize = TILES_X * TILES_Y * SLICES;
+/// Lights past this many in one cluster are left out of it.
+pub const MAX_LIGHTS_PER_CLUSTER: usize = 32;
+
+/// The slice a view depth falls in, between the near and far clip planes.
+pub fn slice(depth: f32, near: f32, far: f32) -> usize {
+  if depth <= near {
+    return 0
+  }
+  let slice = (depth / near).ln() / (far / near).ln() * SLICES as f32;
+  std::cmp::min(slice as usize, SLICES - 1)
+}
+
+/// The tile, across or up, that a coordinate in [-1, 1] falls in.
+fn tile(ndc: f32, tiles: usize) -> usize {
+  let tile = ((ndc + 1.0) / 2.0 * tiles as f32).max(0.0) as usize;
+  std::cmp::min(tile, tiles - 1)
+}
+
+/// Which lights reach each cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+  /// The offset into `indices` of each cluster's lights, and how many there are, by cluster index.
+  pub cells   : Vec<[u32; 2]>,
+  /// Indices into the list of lights the grid was made from.
+  pub indices : Vec<u32>,
+}
+
+#[allow(missing_docs)]
+pub fn index(x: usize, y: usize, slice: usize) -> usize {
+  (slice * TILES_Y + y) * TILES_X + x
+}
+
+/// Sort lights into clusters, for a camera with the given projection.
+/// Each light goes in every cluster its bounding box might cover on screen, which is a little generous.
+pub fn assign(projection: &Matrix4<f32>, near: f32, far: f32, lights: &[protocol::Light]) -> Grid {
+  let mut lists = vec!(Vec::new(); CLUSTERS);
+  for (i, light) in lights.iter().enumerate() {
+    let p = light.position;
+    let r = light.radius;
+    // For a perspective projection, w is the depth in front of the camera.
+    let depth = (projection * Vector4::new(p.x, p.y, p.z, 1.0)).w;
+    if depth + r <= near || depth - r >= far {
+      continue
+    }
+
+    let mut low = (1.0f32, 1.0f32);
+    let mut high = (-1.0f32, -1.0f32);
+    let mut behind = false;
+    for &dx in &[-r, r] {
+    for &dy in &[-r, r] {
+    for &dz in &[-r, r] {
+      let clip = projection * Vector4::new(p.x + dx, p.y + dy, p.z + dz, 1.0);
+      if clip.w <= near {
+        // Corners behind the camera project to nonsense, so assume the light could be anywhere on screen.
+        behind = true;
+        continue
+      }
+      let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+      low = (low.0.min(x), low.1.min(y));
+      high = (high.0.max(x), high.1.max(y));
+    }}}
+    if behind {
+      low = (-1.0, -1.0);
+      high = (1.0, 1.0);
+    }
+    if high.0 < -1.0 || high.1 < -1.0 || low.0 > 1.0 || low.1 > 1.0 {
+      continue
+    }
+
+    for s in slice(depth - r, near, far) .. slice(depth + r, near, far) + 1 {
+    for y in tile(low.1, TILES_Y) .. tile(high.1, TILES_Y) + 1 {
+    for x in tile(low.0, TILES_X) .. tile(high.0, TILES_X) + 1 {
+      let list = &mut lists[index(x, y, s)];
+      if list.len() < MAX_LIGHTS_PER_CLUSTER {
+        list.push(i as u32);
+      }
+    }}}
+  }
+
+  let mut grid =
+    Grid {
+      cells   : Vec::with_capacity(CLUSTERS),
+      indices : Vec::new(),
+    };
+  for list in lists {
+    grid.cells.push([grid.indices.len() as u32, list.len() as u32]);
+    grid.indices.extend(list);
+  }
+  grid
+}
+
+/// The clusters in VRAM.
+pub struct T<'a> {
+  cells         : BufferTexture<'a, [GLuint; 2]>,
+  indices       : BufferTexture<'a, GLuint>,
+  /// Two texels per light: its position and radius, then its color.
+  lights        : BufferTexture<'a, [GLfloat; 4]>,
+  cells_unit    : TextureUnit,
+  indices_unit  : TextureUnit,
+  lights_unit   : TextureUnit,
+}
+
+/// Fill a buffer texture with `capacity` copies of `empty`, so it can be overwritten in place.
+fn fill<'a, X: Copy>(gl: &mut GLContext, texture: &mut BufferTexture<'a, X>, empty: X, capacity: usize) {
+  texture.buffer.byte_buffer.bind(gl);
+  let success = texture.buffer.push(gl, &vec!(empty; capacity));
+  assert!(success);
+}
+
+/// Replace the start of a buffer texture's contents.
+fn overwrite<'a, X>(gl: &mut GLContext, texture: &mut BufferTexture<'a, X>, data: &[X]) {
+  if data.is_empty() {
+    return
+  }
+  texture.buffer.byte_buffer.bind(gl);
+  unsafe {
+    texture.buffer.byte_buffer.update(gl, 0, data.as_ptr() as *const u8, std::mem::size_of::<X>() * data.len());
+  }
+}
+
+fn bind_unit(unit: &TextureUnit, id: GLuint) {
+  unsafe {
+    gl::ActiveTexture(unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_BUFFER, id);
+  }
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(gl: &'b mut GLContext, texture_unit_alloc: &mut id_allocator::T<TextureUnit>) -> T<'a> where
+  'a: 'b,
+{
+  let mut cells = BufferTexture::new(gl, gl::RG32UI, CLUSTERS);
+  let mut indices = BufferTexture::new(gl, gl::R32UI, CLUSTERS * MAX_LIGHTS_PER_CLUSTER);
+  let mut lights = BufferTexture::new(gl, gl::RGBA32F, 2 * protocol::MAX_LIGHTS);
+  fill(gl, &mut cells, [0; 2], CLUSTERS);
+  fill(gl, &mut indices, 0, CLUSTERS * MAX_LIGHTS_PER_CLUSTER);
+  fill(gl, &mut lights, [0.0; 4], 2 * protocol::MAX_LIGHTS);
+
+  let t =
+    T {
+      cells         : cells,
+      indices       : indices,
+      lights        : lights,
+      cells_unit    : texture_unit_alloc.allocate(),
+      indices_unit  : texture_unit_alloc.allocate(),
+      lights_unit   : texture_unit_alloc.allocate(),
+    };
+  bind_unit(&t.cells_unit, t.cells.handle.gl_id);
+  bind_unit(&t.indices_unit, t.indices.handle.gl_id);
+  bind_unit(&t.lights_unit, t.lights.handle.gl_id);
+  t
+}
+
+impl<'a> T<'a> {
+  /// Sort the lights into clusters for this frame's camera.
+  pub fn update(
+    &mut self,
+    gl     : &mut GLContext,
+    camera : &camera::T,
+    near   : f32,
+    far    : f32,
+    lights : &light::PointLights,
+  ) {
+    let lights: Vec<protocol::Light> = lights.values().take(protocol::MAX_LIGHTS).cloned().collect();
+    let grid = assign(&camera.projection_matrix(), near, far, &lights);
+
+    let texels: Vec<[GLfloat; 4]> =
+      lights.iter()
+      .flat_map(|light| {
+        let p = light.position;
+        let c = light.color;
+        vec!([p.x, p.y, p.z, light.radius], [c.r, c.g, c.b, 0.0])
+      })
+      .collect();
+    overwrite(gl, &mut self.cells, &grid.cells);
+    overwrite(gl, &mut self.indices, &grid.indices);
+    overwrite(gl, &mut self.lights, &texels);
+  }
+
+  /// Point a shader at the clusters, and have it use them instead of its forward lights.
+  pub fn bind(&self, gl: &mut GLContext, shader: &mut Shader, window_size: &cgmath::Vector2<i32>) {
+    let clustered = shader.get_uniform_location("clustered_lighting");
+    let counts = shader.get_uniform_location("cluster_counts");
+    let viewport = shader.get_uniform_location("viewport_size");
+    let cells = shader.get_uniform_location("cluster_cells");
+    let indices = shader.get_uniform_location("cluster_light_indices");
+    let lights = shader.get_uniform_location("cluster_lights");
+    shader.use_shader(gl);
+    unsafe {
+      gl::Uniform1i(clustered, 1);
+      gl::Uniform3i(counts, TILES_X as GLint, TILES_Y as GLint, SLICES as GLint);
+      gl::Uniform2f(viewport, window_size.x as GLfloat, window_size.y as GLfloat);
+      gl::Uniform1i(cells, self.cells_unit.glsl_id as GLint);
+      gl::Uniform1i(indices, self.indices_unit.glsl_id as GLint);
+      gl::Uniform1i(lights, self.lights_unit.glsl_id as GLint);
+    }
+  }
+}
+
+#[cfg(test)]
+fn light(x: f32, y: f32, z: f32, radius: f32) -> protocol::Light {
+  protocol::Light {
+    position : cgmath::Point3::new(x, y, z),
+    color    : ::common::color::Color3::of_rgb(1.0, 1.0, 1.0),
+    radius   : radius,
+  }
+}
+
+#[test]
+fn slices_cover_the_frustum() {
+  assert_eq!(slice(0.0, 0.1, 1000.0), 0);
+  assert_eq!(slice(0.2, 0.1, 1000.0), 1);
+  assert_eq!(slice(999.0, 0.1, 1000.0), SLICES - 1);
+  assert_eq!(slice(5000.0, 0.1, 1000.0), SLICES - 1);
+}
+
+#[test]
+fn lights_go_in_the_clusters_they_reach() {
+  // Looking down -z.
+  let projection = cgmath::perspective(cgmath::Deg(90.0), 16.0 / 9.0, 0.1, 1000.0);
+  let ahead = light(0.0, 0.0, -10.0, 1.0);
+  let behind = light(0.0, 0.0, 10.0, 1.0);
+  let around = light(0.0, 0.0, 0.0, 2.0);
+  let grid = assign(&projection, 0.1, 1000.0, &[ahead, behind, around]);
+
+  let lights_in = |x, y, s| {
+    let cell = grid.cells[index(x, y, s)];
+    grid.indices[cell[0] as usize .. (cell[0] + cell[1]) as usize].to_vec()
+  };
+  let s = slice(10.0, 0.1, 1000.0);
+  // The light ahead is in the middle of the screen, at its depth, but not in the corners or much further off.
+  assert_eq!(lights_in(TILES_X / 2, TILES_Y / 2, s), vec!(0));
+  assert!(!lights_in(0, 0, s).contains(&0));
+  assert!(!lights_in(TILES_X / 2, TILES_Y / 2, SLICES - 1).contains(&0));
+  // The light behind the camera is nowhere, and the one around it is in every tile up close.
+  assert!(grid.indices.iter().all(|&i| i != 1));
+  assert_eq!(lights_in(0, 0, 0), vec!(2));
+  assert_eq!(lights_in(TILES_X - 1, TILES_Y - 1, 0), vec!(2));
+}
@@ -0,0 +1,105 @@
+//! The crafting panel: a list of recipes, drawn on the HUD while the player's crafting.
+
+use cgmath::{Point2, Vector2};
+use yaglw::gl_context::GLContext;
+use yaglw::texture::TextureUnit;
+use yaglw::vertex_buffer::GLArray;
+
+use common::item;
+
+use vertex::TextureVertex;
+use view;
+use view::hotbar;
+use view::ttf;
+
+/// The left edge of the panel, in HUD coordinates.
+const LEFT: f32 = -0.5;
+/// The top of the panel, in HUD coordinates.
+const TOP: f32 = 0.6;
+
+/// The recipe list, and which recipe is picked.
+pub struct T<'a> {
+  selected : item::RecipeId,
+  /// Quads for the recipe list. This is None if the list has changed since it was built.
+  quads    : Option<GLArray<'a, TextureVertex>>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a>() -> T<'a> {
+  T {
+    selected : 0,
+    quads    : None,
+  }
+}
+
+impl<'a> T<'a> {
+  #[allow(missing_docs)]
+  pub fn selected(&self) -> item::RecipeId {
+    self.selected
+  }
+
+  /// Pick the next recipe down the list (or up it, if `down` is false), wrapping around at the ends.
+  pub fn move_selection(&mut self, down: bool) {
+    let count = item::recipes().len() as item::RecipeId;
+    self.selected =
+      if down {
+        (self.selected + 1) % count
+      } else {
+        (self.selected + count - 1) % count
+      };
+    self.quads = None;
+  }
+
+  /// Rebuild the list next time it's drawn, e.g. because the inventory changed.
+  pub fn refresh(&mut self) {
+    self.quads = None;
+  }
+
+  fn build_quads<'b>(
+    &self,
+    gl: &'b mut GLContext,
+    shader: &view::shaders::texture::T<'a>,
+    glyphs: &ttf::Glyphs<'a>,
+    hotbar: &hotbar::T<'a>,
+    window_size: &Vector2<i32>,
+  ) -> GLArray<'a, TextureVertex> where
+    'a: 'b,
+  {
+    let mut lines = vec!("Crafting".to_owned());
+    for (id, recipe) in item::recipes().iter().enumerate() {
+      let marker = if id as item::RecipeId == self.selected { ">" } else { " " };
+      let missing = recipe.inputs.iter().any(|input| hotbar.count(input.item) < input.count);
+      let note = if missing { " (missing items)" } else { "" };
+      lines.push(format!("{} {}{}", marker, recipe.describe(), note));
+    }
+
+    let line_height = glyphs.size(window_size).y;
+    let mut vertices = Vec::new();
+    for (row, line) in lines.iter().enumerate() {
+      let origin = Point2::new(LEFT, TOP - (row + 1) as f32 * line_height);
+      glyphs.push_line(&mut vertices, window_size, origin, line);
+    }
+
+    glyphs.build(gl, shader, &vertices)
+  }
+
+  /// Draw the recipe list.
+  pub fn draw<'b>(
+    &mut self,
+    gl: &'b mut GLContext,
+    shader: &mut view::shaders::texture::T<'a>,
+    glyphs: &ttf::Glyphs<'a>,
+    hotbar: &hotbar::T<'a>,
+    texture_unit: &TextureUnit,
+    window_size: &Vector2<i32>,
+  ) where
+    'a: 'b,
+  {
+    if self.quads.is_none() {
+      let quads = self.build_quads(gl, shader, glyphs, hotbar, window_size);
+      self.quads = Some(quads);
+    }
+
+    glyphs.draw(gl, shader, texture_unit, self.quads.as_ref().unwrap());
+  }
+}
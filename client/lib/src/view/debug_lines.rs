@@ -0,0 +1,212 @@
+//! Lines drawn over the world while the debug overlay is up: loaded chunks colored by LOD, players' and mobs'
+//! bounding boxes, and the view frustum frozen at some point in time.
+
+use cgmath::{Matrix4, Point3, Vector4, SquareMatrix};
+use collision::Aabb3;
+use gl;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::vertex_buffer;
+
+use common::color::Color4;
+
+use lod;
+use vertex::ColoredVertex;
+
+/// The most line vertices drawn in a frame.
+const MAX_VERTICES: usize = 1 << 18;
+/// Each box is drawn as its 12 edges.
+const VERTICES_PER_BOX: usize = 24;
+
+/// The color of a chunk's outline, by its LOD.
+const LOD_COLORS: [[f32; 3]; lod::COUNT] = [
+  [1.0, 0.0, 0.0],
+  [1.0, 0.6, 0.0],
+  [1.0, 1.0, 0.0],
+  [0.0, 1.0, 0.0],
+  [0.0, 0.4, 1.0],
+];
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// Whether to outline every loaded chunk, colored by LOD.
+  pub show_chunks    : bool,
+  /// Whether to outline players' and mobs' bounding boxes.
+  pub show_bounds    : bool,
+  /// The camera's projection matrix when the frustum was frozen, if it is.
+  pub frozen_frustum : Option<Matrix4<f32>>,
+
+  vertices           : Vec<ColoredVertex>,
+  gl_array           : vertex_buffer::ArrayHandle<'a>,
+  buffer             : vertex_buffer::GLBuffer<'a, ColoredVertex>,
+  /// How many vertices are in `buffer`.
+  uploaded           : usize,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b:'a>(
+  gl: &'a mut GLContext,
+  shader: &Shader<'a>,
+) -> T<'b>
+{
+  let gl_array = vertex_buffer::ArrayHandle::new(gl);
+  let buffer = vertex_buffer::GLBuffer::new(gl, MAX_VERTICES);
+
+  unsafe {
+    gl::BindVertexArray(gl_array.gl_id);
+  }
+  shader.use_shader(gl);
+  buffer.byte_buffer.bind(gl);
+  let attrib_span =
+    vertex_buffer::VertexAttribData::apply(
+      &[
+        vertex_buffer::VertexAttribData { name: "position", size: 3, unit: vertex_buffer::GLType::Float, divisor: 0 },
+        vertex_buffer::VertexAttribData { name: "in_color", size: 4, unit: vertex_buffer::GLType::Float, divisor: 0 },
+      ],
+      gl,
+      shader,
+    );
+  assert!(attrib_span == std::mem::size_of::<ColoredVertex>() as u32);
+
+  T {
+    show_chunks    : false,
+    show_bounds    : false,
+    frozen_frustum : None,
+
+    vertices       : Vec::new(),
+    gl_array       : gl_array,
+    buffer         : buffer,
+    uploaded       : 0,
+  }
+}
+
+/// The color to outline a chunk at some LOD.
+pub fn lod_color(lod: lod::T) -> Color4<f32> {
+  let c = LOD_COLORS[lod.0 as usize];
+  Color4::of_rgba(c[0], c[1], c[2], 1.0)
+}
+
+/// The corners of a box. Bit i of a corner's index picks max over min on axis i, like in the outline shader.
+fn box_corners(bounds: &Aabb3<f32>) -> [Point3<f32>; 8] {
+  let corner = |i: usize| {
+    Point3::new(
+      if i & 1 == 0 { bounds.min.x } else { bounds.max.x },
+      if i & 2 == 0 { bounds.min.y } else { bounds.max.y },
+      if i & 4 == 0 { bounds.min.z } else { bounds.max.z },
+    )
+  };
+  [corner(0), corner(1), corner(2), corner(3), corner(4), corner(5), corner(6), corner(7)]
+}
+
+/// The ends of each of the 12 edges of a box-like shape, given its corners in `box_corners` order.
+fn edges(corners: &[Point3<f32>; 8], color: Color4<f32>) -> [ColoredVertex; VERTICES_PER_BOX] {
+  let corner = |i: usize| ColoredVertex { position: corners[i], color: color };
+  [
+    corner(0), corner(1), corner(2), corner(3), corner(4), corner(5), corner(6), corner(7),
+    corner(0), corner(2), corner(1), corner(3), corner(4), corner(6), corner(5), corner(7),
+    corner(0), corner(4), corner(1), corner(5), corner(2), corner(6), corner(3), corner(7),
+  ]
+}
+
+/// The world-space corners of the volume a projection matrix sees, in `box_corners` order.
+/// Returns `None` if the matrix can't be inverted.
+pub fn frustum_corners(projection: &Matrix4<f32>) -> Option<[Point3<f32>; 8]> {
+  let inverse =
+    match projection.invert() {
+      None => return None,
+      Some(inverse) => inverse,
+    };
+  let corner = |i: usize| {
+    let clip =
+      Vector4::new(
+        if i & 1 == 0 { -1.0 } else { 1.0 },
+        if i & 2 == 0 { -1.0 } else { 1.0 },
+        if i & 4 == 0 { -1.0 } else { 1.0 },
+        1.0,
+      );
+    let world = inverse * clip;
+    Point3::new(world.x / world.w, world.y / world.w, world.z / world.w)
+  };
+  Some([corner(0), corner(1), corner(2), corner(3), corner(4), corner(5), corner(6), corner(7)])
+}
+
+impl<'a> T<'a> {
+  /// Whether anything's turned on to draw.
+  pub fn is_active(&self) -> bool {
+    self.show_chunks || self.show_bounds || self.frozen_frustum.is_some()
+  }
+
+  /// Start over with no lines.
+  pub fn clear(&mut self) {
+    self.vertices.clear();
+  }
+
+  /// Outline a box. Returns false (and doesn't) if there's no room for more lines this frame.
+  pub fn push_box(&mut self, bounds: &Aabb3<f32>, color: Color4<f32>) -> bool {
+    if self.vertices.len() + VERTICES_PER_BOX > MAX_VERTICES {
+      return false
+    }
+    self.vertices.extend_from_slice(&edges(&box_corners(bounds), color));
+    true
+  }
+
+  /// Outline the frozen frustum, if there is one.
+  pub fn push_frozen_frustum(&mut self) {
+    let corners =
+      match self.frozen_frustum.and_then(|frustum| frustum_corners(&frustum)) {
+        None => return,
+        Some(corners) => corners,
+      };
+    if self.vertices.len() + VERTICES_PER_BOX <= MAX_VERTICES {
+      self.vertices.extend_from_slice(&edges(&corners, Color4::of_rgba(1.0, 1.0, 1.0, 1.0)));
+    }
+  }
+
+  /// Copy this frame's lines to VRAM.
+  pub fn upload(&mut self, gl: &mut GLContext) {
+    self.uploaded = self.vertices.len();
+    if self.vertices.is_empty() {
+      return
+    }
+    self.buffer.byte_buffer.bind(gl);
+    unsafe {
+      self.buffer.byte_buffer.update(
+        gl,
+        0,
+        self.vertices.as_ptr() as *const u8,
+        std::mem::size_of::<ColoredVertex>() * self.vertices.len(),
+      );
+    }
+  }
+
+  /// Draw the lines.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, _gl: &mut GLContext) {
+    if self.uploaded == 0 {
+      return
+    }
+    unsafe {
+      gl::BindVertexArray(self.gl_array.gl_id);
+      gl::DrawArrays(gl::LINES, 0, self.uploaded as i32);
+    }
+  }
+}
+
+#[test]
+fn frustum_corners_undo_the_projection() {
+  use cgmath;
+
+  let projection =
+    cgmath::perspective(cgmath::Rad(1.0), 1.5, 0.1, 100.0) *
+    Matrix4::from_translation(cgmath::Vector3::new(-3.0, -4.0, -5.0));
+  let corners = frustum_corners(&projection).unwrap();
+  for (i, corner) in corners.iter().enumerate() {
+    let clip = projection * corner.to_homogeneous();
+    let ndc = clip.truncate() / clip.w;
+    let expected = |bit| if i & bit == 0 { -1.0 } else { 1.0 };
+    assert!((ndc.x - expected(1)).abs() < 0.01);
+    assert!((ndc.y - expected(2)).abs() < 0.01);
+    assert!((ndc.z - expected(4)).abs() < 0.01);
+  }
+}
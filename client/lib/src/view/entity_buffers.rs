@@ -0,0 +1,262 @@
+//! Data structures and functions to load/unload/maintain mob and player data in VRAM.
+//! Every entity of a kind shares one rigged mesh, and is drawn as an instance of it, so a whole kind of entity takes
+//! one draw call, and moving one only means rewriting its instance. Each instance's bones are posed on the CPU every
+//! frame, and their skinning matrices are read from a buffer texture by the vertex shader.
+
+use cgmath::{Matrix4, Point3, Vector3};
+use gl;
+use gl::types::*;
+use std;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use time;
+use yaglw;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::{BufferTexture, TextureUnit};
+use yaglw::vertex_buffer;
+
+use common::color::Color4;
+use common::fnv_map;
+use common::protocol;
+
+use animation;
+use vertex;
+use vertex::ColoredVertex;
+
+/// The vertices of a cuboid's right (+x) face.
+const FACING_VERTICES: std::ops::Range<usize> = 24 .. 30;
+
+/// A vertex of the shared mesh, in its rig's rest pose.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Vertex {
+  position : Point3<f32>,
+  /// The index of the bone this vertex moves with.
+  bone     : f32,
+  /// 1 on the face turned toward the heading, 0 elsewhere.
+  facing   : f32,
+}
+
+/// Where and how to draw one entity.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Instance {
+  #[allow(missing_docs)]
+  pub center       : Point3<f32>,
+  #[allow(missing_docs)]
+  pub size         : Vector3<f32>,
+  /// An angle from +x toward +z.
+  pub heading      : f32,
+  #[allow(missing_docs)]
+  pub color        : Color4<f32>,
+  /// The color of the face turned toward the heading.
+  pub facing_color : Color4<f32>,
+}
+
+/// This data structure keeps track of one kind of entity in VRAM.
+pub struct T<'a, Id> {
+  id_to_index : fnv_map::T<Id, usize>,
+  index_to_id : Vec<Id>,
+  /// What each entity is doing, and when it started, by index.
+  animations  : Vec<(protocol::Animation, u64)>,
+
+  rig         : animation::T,
+  vertices    : usize,
+
+  gl_array    : yaglw::vertex_buffer::ArrayHandle<'a>,
+  /// Never touched again once it's filled, but it has to outlive `gl_array`, which draws from it.
+  #[allow(dead_code)]
+  mesh        : yaglw::vertex_buffer::GLBuffer<'a, Vertex>,
+  instances   : yaglw::vertex_buffer::GLBuffer<'a, Instance>,
+  /// Each instance's skinning matrices, one bone after another.
+  bones       : BufferTexture<'a, Matrix4<GLfloat>>,
+  bones_unit  : TextureUnit,
+}
+
+/// A box for each of a rig's bones.
+fn mesh(rig: &animation::T) -> Vec<Vertex> {
+  let mut mesh = Vec::with_capacity(rig.bones.len() * vertex::VERTICES_PER_CUBOID);
+  for (i, bone) in rig.bones.iter().enumerate() {
+    let triangles = ColoredVertex::cuboid(&bone.bounds, Color4::of_rgba(1.0, 1.0, 1.0, 1.0));
+    for (j, triangle) in triangles.iter().enumerate() {
+      let facing = bone.facing && FACING_VERTICES.start <= j && j < FACING_VERTICES.end;
+      mesh.push(Vertex {
+        position : triangle.position,
+        bone     : i as f32,
+        facing   : if facing { 1.0 } else { 0.0 },
+      });
+    }
+  }
+  mesh
+}
+
+/// Buffers with room for `capacity` entities, all drawn with the same rig.
+/// The skinning matrices are bound to `bones_unit` for good.
+pub fn new<'a, 'b:'a, Id: Copy + Eq + Hash>(
+  gl: &'a mut GLContext,
+  shader: &yaglw::shader::Shader<'a>,
+  bones_unit: TextureUnit,
+  rig: animation::T,
+  capacity: usize,
+) -> T<'b, Id>
+{
+  let mesh = mesh(&rig);
+  let gl_array = vertex_buffer::ArrayHandle::new(gl);
+  let mut mesh_buffer = vertex_buffer::GLBuffer::new(gl, mesh.len());
+  let instances = vertex_buffer::GLBuffer::new(gl, capacity);
+
+  let bone_count = capacity * rig.bones.len();
+  let mut bones = BufferTexture::new(gl, gl::RGBA32F, bone_count);
+  bones.buffer.byte_buffer.bind(gl);
+  let success = bones.buffer.push(gl, &vec!(Matrix4::from_scale(1.0); bone_count));
+  assert!(success);
+  unsafe {
+    gl::ActiveTexture(bones_unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_BUFFER, bones.handle.gl_id);
+  }
+
+  unsafe {
+    gl::BindVertexArray(gl_array.gl_id);
+  }
+  shader.use_shader(gl);
+
+  mesh_buffer.byte_buffer.bind(gl);
+  let success = mesh_buffer.push(gl, &mesh);
+  assert!(success);
+  let attrib_span =
+    vertex_buffer::VertexAttribData::apply(
+      &[
+        vertex_buffer::VertexAttribData { name: "position", size: 3, unit: vertex_buffer::GLType::Float, divisor: 0 },
+        vertex_buffer::VertexAttribData { name: "bone", size: 1, unit: vertex_buffer::GLType::Float, divisor: 0 },
+        vertex_buffer::VertexAttribData { name: "facing", size: 1, unit: vertex_buffer::GLType::Float, divisor: 0 },
+      ],
+      gl,
+      shader,
+    );
+  assert!(attrib_span == std::mem::size_of::<Vertex>() as u32);
+
+  instances.byte_buffer.bind(gl);
+  let attrib_span =
+    vertex_buffer::VertexAttribData::apply(
+      &[
+        vertex_buffer::VertexAttribData { name: "center", size: 3, unit: vertex_buffer::GLType::Float, divisor: 1 },
+        vertex_buffer::VertexAttribData { name: "size", size: 3, unit: vertex_buffer::GLType::Float, divisor: 1 },
+        vertex_buffer::VertexAttribData { name: "heading", size: 1, unit: vertex_buffer::GLType::Float, divisor: 1 },
+        vertex_buffer::VertexAttribData { name: "in_color", size: 4, unit: vertex_buffer::GLType::Float, divisor: 1 },
+        vertex_buffer::VertexAttribData {
+          name: "facing_color",
+          size: 4,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+      ],
+      gl,
+      shader,
+    );
+  assert!(attrib_span == std::mem::size_of::<Instance>() as u32);
+
+  T {
+    id_to_index : fnv_map::new(),
+    index_to_id : Vec::new(),
+    animations  : Vec::new(),
+
+    rig         : rig,
+    vertices    : mesh.len(),
+
+    gl_array    : gl_array,
+    mesh        : mesh_buffer,
+    instances   : instances,
+    bones       : bones,
+    bones_unit  : bones_unit,
+  }
+}
+
+impl<'a, Id: Copy + Eq + Hash> T<'a, Id> {
+  /// Add a single entity into VRAM and return true.
+  /// If the ID is already loaded, replace the existing instance and return false.
+  /// The entity's animation starts over if it's changed.
+  pub fn insert(
+    &mut self,
+    gl: &mut GLContext,
+    id: Id,
+    instance: &Instance,
+    animation: protocol::Animation,
+  ) -> bool {
+    match self.id_to_index.entry(id) {
+      Entry::Vacant(entry) => {
+        entry.insert(self.index_to_id.len());
+        self.index_to_id.push(id);
+        self.animations.push((animation, time::precise_time_ns()));
+
+        self.instances.byte_buffer.bind(gl);
+        assert!(self.instances.push(gl, &[*instance]));
+        true
+      },
+      Entry::Occupied(entry) => {
+        let idx = *entry.get();
+        if self.animations[idx].0 != animation {
+          self.animations[idx] = (animation, time::precise_time_ns());
+        }
+        self.instances.byte_buffer.bind(gl);
+        self.instances.update(gl, idx, &[*instance]);
+        false
+      },
+    }
+  }
+
+  /// Remove an entity from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, id: Id) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    self.index_to_id.swap_remove(idx);
+    self.animations.swap_remove(idx);
+    if idx < self.index_to_id.len() {
+      self.id_to_index.insert(self.index_to_id[idx], idx);
+    }
+
+    self.instances.byte_buffer.bind(gl);
+    self.instances.swap_remove(gl, idx, 1);
+  }
+
+  /// Pose every entity as of `now`, in ns.
+  pub fn animate(&mut self, gl: &mut GLContext, now: u64) {
+    if self.index_to_id.is_empty() {
+      return
+    }
+
+    let mut bones = Vec::with_capacity(self.animations.len() * self.rig.bones.len());
+    for &(animation, start) in &self.animations {
+      let seconds = now.saturating_sub(start) as f32 / 1e9;
+      bones.extend(self.rig.pose(animation, seconds));
+    }
+
+    self.bones.buffer.byte_buffer.bind(gl);
+    unsafe {
+      self.bones.buffer.byte_buffer.update(
+        gl,
+        0,
+        bones.as_ptr() as *const u8,
+        std::mem::size_of::<Matrix4<GLfloat>>() * bones.len(),
+      );
+    }
+  }
+
+  /// Draw all the entities.
+  /// N.B. This expects `shader` to be bound already; only this kind of entity's bones are set in it.
+  pub fn draw(&self, _gl: &mut GLContext, shader: &mut yaglw::shader::Shader) {
+    if self.index_to_id.is_empty() {
+      return
+    }
+    let bones = shader.get_uniform_location("bones");
+    let bones_per_instance = shader.get_uniform_location("bones_per_instance");
+    unsafe {
+      gl::Uniform1i(bones, self.bones_unit.glsl_id as GLint);
+      gl::Uniform1i(bones_per_instance, self.rig.bones.len() as GLint);
+      gl::BindVertexArray(self.gl_array.gl_id);
+      gl::DrawArraysInstanced(gl::TRIANGLES, 0, self.vertices as i32, self.index_to_id.len() as i32);
+    }
+  }
+}
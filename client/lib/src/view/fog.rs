@@ -0,0 +1,35 @@
+//! Distance fog, and the sunlight it scatters.
+
+use common::color::Color3;
+use gl;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+#[derive(Debug, Clone)]
+/// How thick the fog is, and what color.
+pub struct T {
+  /// The fog's color before it's lit. The sun tints it further, especially looking toward the sun.
+  pub color   : Color3<f32>,
+  /// How quickly things fade into the fog, per unit of distance. Zero turns fog off.
+  pub density : f32,
+}
+
+impl Default for T {
+  fn default() -> Self {
+    T {
+      color   : Color3::of_rgb(0.8, 0.85, 0.95),
+      density : 1.0 / 768.0,
+    }
+  }
+}
+
+/// Sets the `fog` struct in some shader.
+pub fn set_fog(shader: &mut Shader, gl: &mut GLContext, fog: &T) {
+  let color_uniform = shader.get_uniform_location("fog.color");
+  let density_uniform = shader.get_uniform_location("fog.density");
+  shader.use_shader(gl);
+  unsafe {
+    gl::Uniform3f(color_uniform, fog.color.r, fog.color.g, fog.color.b);
+    gl::Uniform1f(density_uniform, fog.density);
+  }
+}
@@ -1,7 +1,11 @@
 //! Font loading data structure and functions.
 
 use std::path::Path;
-use ttf;
+
+use view::ttf;
+
+/// The point size HUD text is drawn at.
+const POINT_SIZE: u32 = 16;
 
 #[allow(missing_docs)]
 pub struct FontLoader {
@@ -13,8 +17,8 @@ impl FontLoader {
   #[allow(missing_docs)]
   pub fn new() -> FontLoader {
     FontLoader {
-      sans : ttf::Font::new(&Path::new("fonts/Open_Sans/OpenSans-Regular.ttf"), 11),
-      mono : ttf::Font::new(&Path::new("fonts/Ubuntu_Mono/UbuntuMono-Regular.ttf"), 11),
+      sans : ttf::Font::new(&Path::new("fonts/Open_Sans/OpenSans-Regular.ttf"), POINT_SIZE),
+      mono : ttf::Font::new(&Path::new("fonts/Ubuntu_Mono/UbuntuMono-Regular.ttf"), POINT_SIZE),
     }
   }
 }
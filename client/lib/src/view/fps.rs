@@ -0,0 +1,45 @@
+//! Count how many frames are being drawn per second.
+
+use std::collections::VecDeque;
+
+/// Frames are counted over this many nanoseconds.
+const WINDOW_NS: u64 = 1_000_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// When each frame in the last `WINDOW_NS` was drawn, oldest first.
+  frames: VecDeque<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    frames: VecDeque::new(),
+  }
+}
+
+impl T {
+  /// Count a frame drawn at `now`, in nanoseconds.
+  pub fn frame(&mut self, now: u64) {
+    self.frames.push_back(now);
+    while self.frames.front().map_or(false, |&then| then + WINDOW_NS <= now) {
+      self.frames.pop_front();
+    }
+  }
+
+  /// The number of frames drawn in the last second.
+  pub fn per_second(&self) -> usize {
+    self.frames.len()
+  }
+}
+
+#[test]
+fn old_frames_are_forgotten() {
+  let mut fps = new();
+  for i in 0 .. 60 {
+    fps.frame(i * WINDOW_NS / 30);
+  }
+  assert_eq!(fps.per_second(), 30);
+  fps.frame(10 * WINDOW_NS);
+  assert_eq!(fps.per_second(), 1);
+}
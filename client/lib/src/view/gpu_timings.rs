@@ -0,0 +1,135 @@
+//! GPU timer queries around the main render passes, averaged for the debug overlay, to tell frames that are waiting on
+//! the GPU from ones that are waiting on the CPU. Query results come back a few frames late, so each pass has a query
+//! per frame in flight, and reading them never stalls.
+
+use gl;
+use gl::types::*;
+use yaglw::gl_context::GLContext;
+
+use timings;
+
+/// How many frames' queries can be waiting on the GPU at once.
+const FRAMES_IN_FLIGHT: usize = 3;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+  Backdrop,
+  Terrain,
+  /// Mobs, players and items.
+  Mobs,
+  Grass,
+  Hud,
+}
+
+const PASSES: [Pass; 5] = [Pass::Backdrop, Pass::Terrain, Pass::Mobs, Pass::Grass, Pass::Hud];
+
+impl Pass {
+  #[allow(missing_docs)]
+  pub fn name(self) -> &'static str {
+    match self {
+      Pass::Backdrop => "backdrop",
+      Pass::Terrain  => "terrain",
+      Pass::Mobs     => "mobs",
+      Pass::Grass    => "grass",
+      Pass::Hud      => "hud",
+    }
+  }
+}
+
+struct Query {
+  id      : GLuint,
+  /// Whether the query has been run, and its result not read yet.
+  pending : bool,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Indexed by frame in flight, then by pass.
+  queries  : Vec<Vec<Query>>,
+  /// The frame in flight being drawn.
+  frame    : usize,
+  averages : timings::T,
+}
+
+#[allow(missing_docs)]
+pub fn new(_gl: &GLContext) -> T {
+  let queries =
+    (0 .. FRAMES_IN_FLIGHT).map(|_| {
+      PASSES.iter().map(|_| {
+        let mut id = 0;
+        unsafe {
+          gl::GenQueries(1, &mut id);
+        }
+        Query {
+          id      : id,
+          pending : false,
+        }
+      })
+      .collect()
+    })
+    .collect();
+  T {
+    queries  : queries,
+    frame    : 0,
+    averages : timings::new(),
+  }
+}
+
+impl T {
+  /// Start timing a new frame, and collect the times from the last frame that used the same queries.
+  pub fn begin_frame(&mut self) {
+    self.frame = (self.frame + 1) % FRAMES_IN_FLIGHT;
+    for (pass, query) in PASSES.iter().zip(self.queries[self.frame].iter_mut()) {
+      if !query.pending {
+        continue
+      }
+      query.pending = false;
+      let mut available = 0;
+      unsafe {
+        gl::GetQueryObjectuiv(query.id, gl::QUERY_RESULT_AVAILABLE, &mut available);
+      }
+      // If the GPU is this far behind, skip the sample rather than wait for it.
+      if available == 0 {
+        continue
+      }
+      let mut ns: GLuint64 = 0;
+      unsafe {
+        gl::GetQueryObjectui64v(query.id, gl::QUERY_RESULT, &mut ns);
+      }
+      self.averages.record(pass.name(), ns);
+    }
+  }
+
+  /// Start timing a pass. Passes can't overlap.
+  pub fn begin(&mut self, pass: Pass) {
+    let query = &self.queries[self.frame][pass as usize];
+    unsafe {
+      gl::BeginQuery(gl::TIME_ELAPSED, query.id);
+    }
+  }
+
+  /// Stop timing a pass.
+  pub fn end(&mut self, pass: Pass) {
+    let query = &mut self.queries[self.frame][pass as usize];
+    unsafe {
+      gl::EndQuery(gl::TIME_ELAPSED);
+    }
+    query.pending = true;
+  }
+
+  /// Each pass's name and its average GPU time, in milliseconds.
+  pub fn averages_ms(&self) -> Vec<(&'static str, f64)> {
+    self.averages.averages_ms()
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    for query in self.queries.iter().flat_map(|queries| queries.iter()) {
+      unsafe {
+        gl::DeleteQueries(1, &query.id);
+      }
+    }
+  }
+}
@@ -1,8 +1,9 @@
 //! Each tuft of grass in the grass buffer is associated with a terrain polygon. It is associated using
 //! an index into the VRAM buffer of terrain polygons.
+//! Tufts are also known by the voxel they grow in, which is how the server says which ones were trampled.
 
 use cgmath;
-use cgmath::{Point3, EuclideanSpace};
+use cgmath::{Point3, EuclideanSpace, InnerSpace};
 use gl;
 use std;
 use std::f32;
@@ -10,14 +11,17 @@ use yaglw;
 use yaglw::gl_context::GLContext;
 
 use common::fnv_map;
+use common::fnv_set;
 
 use super::entity;
 use super::terrain_buffers;
 
 // VRAM bytes
 const BYTE_BUDGET: usize = 64_000_000;
-const TUFT_COST: usize = 8;
+const TUFT_COST: usize = 16;
 const TUFT_BUDGET: usize = BYTE_BUDGET / TUFT_COST;
+/// How long a trampled tuft takes to stand back up, in ns. This matches the server.
+const SPRING_BACK_NS: u64 = 3_000_000_000;
 
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -27,6 +31,10 @@ pub struct Entry {
   pub polygon_idx : u32,
   /// Id of which grass texture to use for a given tuft
   pub tex_id      : u32,
+  /// Where this tuft starts in its swaying cycle, in radians
+  pub phase       : f32,
+  /// How flat the tuft's been trampled, in [0, 1]. This has to stay last; see `spring_back`.
+  pub bend        : f32,
 }
 
 /// Struct for loading/unloading/maintaining terrain data in VRAM.
@@ -37,6 +45,13 @@ pub struct T<'a> {
   to_polygon_idx: fnv_map::T<entity::id::Grass, u32>,
   of_polygon_idx: fnv_map::T<u32, entity::id::Grass>,
 
+  /// The voxel each tuft grows in.
+  cells: fnv_map::T<entity::id::Grass, Point3<i32>>,
+  /// The tufts growing in each voxel.
+  in_cell: fnv_map::T<Point3<i32>, fnv_set::T<entity::id::Grass>>,
+  /// When each tuft that's still standing back up was trampled.
+  trampled: fnv_map::T<entity::id::Grass, u64>,
+
   gl_array: yaglw::vertex_buffer::ArrayHandle<'a>,
   _instance_vertices: yaglw::vertex_buffer::GLBuffer<'a, Vertex>,
   per_tuft: yaglw::vertex_buffer::GLBuffer<'a, Entry>,
@@ -155,6 +170,18 @@ pub fn new<'a, 'b:'a>(
           unit: vertex_buffer::GLType::UInt,
           divisor: 1,
         },
+        vertex_buffer::VertexAttribData {
+          name: "phase",
+          size: 1,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+        vertex_buffer::VertexAttribData {
+          name: "bend",
+          size: 1,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
       ],
       gl,
       shader,
@@ -168,6 +195,10 @@ pub fn new<'a, 'b:'a>(
     to_polygon_idx: fnv_map::new(),
     of_polygon_idx: fnv_map::new(),
 
+    cells: fnv_map::new(),
+    in_cell: fnv_map::new(),
+    trampled: fnv_map::new(),
+
     gl_array: gl_array,
     _instance_vertices: instance_vertices,
     per_tuft: per_tuft,
@@ -181,8 +212,10 @@ impl<'a> T<'a> {
     gl: &mut GLContext,
     grass: &[Entry],
     grass_ids: &[entity::id::Grass],
+    cells: &[Point3<i32>],
   ) {
     assert!(grass.len() == grass_ids.len());
+    assert!(grass.len() == cells.len());
 
     self.per_tuft.byte_buffer.bind(gl);
     let success: bool = self.per_tuft.push(gl, grass);
@@ -203,13 +236,25 @@ impl<'a> T<'a> {
       let previous = self.of_polygon_idx.insert(grass.polygon_idx, *id);
       assert!(previous.is_none());
     }
+
+    for (id, cell) in grass_ids.iter().zip(cells.iter()) {
+      self.cells.insert(*id, *cell);
+      self.in_cell.entry(*cell).or_insert_with(fnv_set::new).insert(*id);
+    }
   }
 
   // TODO: Make this take many ids as a parameter, to reduce `bind`s.
-  // Note: `id` must be present in the buffers.
-  /// Remove some entity from VRAM.
+  /// Remove some entity from VRAM. Stale and unknown ids are ignored.
   pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity::id::Grass) {
-    let idx = (*self).id_to_index[&id];
+    let idx =
+      match self.id_to_index.get(&id) {
+        None => {
+          // Dug-out tufts are removed before the rest of their chunk.
+          debug!("Not removing {:?}, which isn't in VRAM", id);
+          return
+        },
+        Some(&idx) => idx,
+      };
     let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
     self.index_to_id.swap_remove(idx);
     self.id_to_index.remove(&id);
@@ -224,6 +269,65 @@ impl<'a> T<'a> {
     let polygon_idx = self.to_polygon_idx.remove(&id).unwrap();
     self.of_polygon_idx.remove(&polygon_idx).unwrap();
     debug!("Swap-remove {:?} {:?} with {:?}", id, polygon_idx, swapped_id);
+
+    let cell = self.cells.remove(&id).unwrap();
+    let now_empty = {
+      let in_cell = self.in_cell.get_mut(&cell).unwrap();
+      in_cell.remove(&id);
+      in_cell.is_empty()
+    };
+    if now_empty {
+      self.in_cell.remove(&cell);
+    }
+    self.trampled.remove(&id);
+  }
+
+  /// Flatten the tufts growing in a voxel. They spring back up over the next few seconds.
+  pub fn trample(&mut self, cell: &Point3<i32>, now: u64) {
+    if let Some(in_cell) = self.in_cell.get(cell) {
+      for id in in_cell {
+        self.trampled.insert(*id, now);
+      }
+    }
+  }
+
+  /// The tufts growing in voxels whose centers are within `radius` of `center`.
+  pub fn within(&self, center: &Point3<f32>, radius: f32) -> Vec<entity::id::Grass> {
+    let mut ids = Vec::new();
+    for (cell, in_cell) in &self.in_cell {
+      let voxel_center = Point3::new(cell.x as f32 + 0.5, cell.y as f32 + 0.5, cell.z as f32 + 0.5);
+      if (voxel_center - *center).magnitude2() <= radius * radius {
+        ids.extend(in_cell.iter().cloned());
+      }
+    }
+    ids
+  }
+
+  /// Stand trampled tufts back up a bit more, as of `now`.
+  pub fn spring_back(&mut self, gl: &mut GLContext, now: u64) {
+    if self.trampled.is_empty() {
+      return
+    }
+    self.per_tuft.byte_buffer.bind(gl);
+    let mut upright = Vec::new();
+    for (id, &then) in &self.trampled {
+      let elapsed = now.saturating_sub(then);
+      let bend =
+        if elapsed >= SPRING_BACK_NS {
+          upright.push(*id);
+          0.0
+        } else {
+          1.0 - elapsed as f32 / SPRING_BACK_NS as f32
+        };
+      // `bend` is the last field of the tuft's `Entry`.
+      let offset = std::mem::size_of::<Entry>() * (self.id_to_index[id] + 1) - std::mem::size_of::<f32>();
+      unsafe {
+        self.per_tuft.byte_buffer.update(gl, offset, &bend as *const f32 as *const u8, std::mem::size_of::<f32>());
+      }
+    }
+    for id in upright {
+      self.trampled.remove(&id);
+    }
   }
 
   /// Update the index of the underlying polygon that a grass tuft is associated with.
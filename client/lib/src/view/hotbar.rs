@@ -0,0 +1,129 @@
+//! The player's inventory, drawn as a row of slots along the bottom of the HUD.
+
+use cgmath::{Point2, Vector2};
+use yaglw::gl_context::GLContext;
+use yaglw::texture::TextureUnit;
+use yaglw::vertex_buffer::GLArray;
+
+use common::color::Color4;
+use common::item;
+use common::protocol;
+use common::voxel;
+
+use vertex::{ColoredVertex, TextureVertex};
+use view;
+use view::ttf;
+
+/// The width of a slot, in HUD coordinates.
+const SLOT_WIDTH: f32 = 0.12;
+/// The space between slots, in HUD coordinates.
+const SLOT_SPACING: f32 = 0.02;
+/// The height of the bottom of the slots, in HUD coordinates.
+const BOTTOM: f32 = -0.95;
+
+/// The color an item's slot is filled with.
+fn item_color(item: item::Id) -> Color4<f32> {
+  let color = item::get(item).color;
+  Color4::of_rgba(color.r, color.g, color.b, 1.0)
+}
+
+/// The lower-left corner of a slot.
+fn slot_corner(slot: usize) -> Point2<f32> {
+  let width = protocol::INVENTORY_SLOTS as f32 * (SLOT_WIDTH + SLOT_SPACING) - SLOT_SPACING;
+  Point2::new(-width / 2.0 + slot as f32 * (SLOT_WIDTH + SLOT_SPACING), BOTTOM)
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  slots    : [Option<protocol::ItemStack>; protocol::INVENTORY_SLOTS],
+  selected : usize,
+  /// Item counts. This is None if the counts have changed since they were built.
+  labels   : Option<GLArray<'a, TextureVertex>>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a>() -> T<'a> {
+  T {
+    slots    : [None; protocol::INVENTORY_SLOTS],
+    selected : 0,
+    labels   : None,
+  }
+}
+
+impl<'a> T<'a> {
+  #[allow(missing_docs)]
+  pub fn set_slot(&mut self, slot: usize, stack: Option<protocol::ItemStack>) {
+    if slot < self.slots.len() {
+      self.slots[slot] = stack;
+      self.labels = None;
+    }
+  }
+
+  /// Highlight a slot. Out-of-range slots are ignored.
+  pub fn select(&mut self, slot: usize) {
+    if slot < self.slots.len() {
+      self.selected = slot;
+    }
+  }
+
+  /// The material the item in the selected slot places, if there's any.
+  pub fn selected_material(&self) -> Option<voxel::Material> {
+    self.slots[self.selected].and_then(|stack| item::get(stack.item).places)
+  }
+
+  /// How many of an item there are, across every slot.
+  pub fn count(&self, item: item::Id) -> u32 {
+    self.slots.iter()
+      .filter_map(|slot| *slot)
+      .filter(|stack| stack.item == item)
+      .map(|stack| stack.count)
+      .sum()
+  }
+
+  /// The slot frames and their contents, to be drawn with the rest of the HUD triangles.
+  pub fn triangles(&self) -> Vec<ColoredVertex> {
+    let frame_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.4);
+    let selected_color = Color4::of_rgba(1.0, 1.0, 1.0, 0.6);
+    let inset = SLOT_WIDTH / 8.0;
+
+    let mut triangles = Vec::new();
+    for (i, slot) in self.slots.iter().enumerate() {
+      let min = slot_corner(i);
+      let max = Point2::new(min.x + SLOT_WIDTH, min.y + SLOT_WIDTH);
+      let color = if i == self.selected { selected_color } else { frame_color };
+      triangles.extend(ColoredVertex::square(min, max, color).iter().cloned());
+
+      if let Some(stack) = *slot {
+        let min = Point2::new(min.x + inset, min.y + inset);
+        let max = Point2::new(max.x - inset, max.y - inset);
+        triangles.extend(ColoredVertex::square(min, max, item_color(stack.item)).iter().cloned());
+      }
+    }
+    triangles
+  }
+
+  /// Draw the item counts.
+  pub fn draw_labels<'b>(
+    &mut self,
+    gl: &'b mut GLContext,
+    shader: &mut view::shaders::texture::T<'a>,
+    glyphs: &ttf::Glyphs<'a>,
+    texture_unit: &TextureUnit,
+    window_size: &Vector2<i32>,
+  ) where
+    'a: 'b,
+  {
+    if self.labels.is_none() {
+      let mut vertices = Vec::new();
+      for (i, slot) in self.slots.iter().enumerate() {
+        if let Some(stack) = *slot {
+          glyphs.push_line(&mut vertices, window_size, slot_corner(i), &stack.count.to_string());
+        }
+      }
+      let labels = glyphs.build(gl, shader, &vertices);
+      self.labels = Some(labels);
+    }
+
+    glyphs.draw(gl, shader, texture_unit, self.labels.as_ref().unwrap());
+  }
+}
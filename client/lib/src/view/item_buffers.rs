@@ -0,0 +1,106 @@
+//! Data structures and functions to load/unload/maintain item data in VRAM.
+
+use std::collections::hash_map::Entry;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+use yaglw::gl_context::GLContext;
+
+use common::entity;
+use common::fnv_map;
+
+use vertex::ColoredVertex;
+use view;
+
+/// Number of vertices in an item mesh.
+pub const VERTICES_PER_ITEM: usize = 36;
+
+/// The most items that can be shown at once. Any more aren't drawn.
+const MAX_ITEMS: usize = 1024;
+
+/// This data structure keeps tracks of item data in VRAM.
+pub struct T<'a> {
+  id_to_index: fnv_map::T<entity::id::Item, usize>,
+  index_to_id: Vec<entity::id::Item>,
+
+  triangles: GLArray<'a, ColoredVertex>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::mob::T<'a>,
+) -> T<'a> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, MAX_ITEMS * VERTICES_PER_ITEM);
+  T {
+    id_to_index: fnv_map::new(),
+    index_to_id: Vec::new(),
+
+    triangles: GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    ),
+  }
+}
+
+impl<'a> T<'a> {
+  /// Add a single item into VRAM and return true.
+  /// If the item ID is already loaded, replace the existing item and return false.
+  /// If there's no room for another item, it's not added, and false is returned.
+  pub fn insert(
+    &mut self,
+    gl: &mut GLContext,
+    id: entity::id::Item,
+    triangles: &[ColoredVertex; VERTICES_PER_ITEM],
+  ) -> bool {
+    match self.id_to_index.entry(id) {
+      Entry::Vacant(entry) => {
+        if self.index_to_id.len() >= MAX_ITEMS {
+          return false
+        }
+        entry.insert(self.index_to_id.len());
+        self.index_to_id.push(id);
+
+        self.triangles.buffer.byte_buffer.bind(gl);
+        assert!(self.triangles.push(gl, triangles));
+        true
+      },
+      Entry::Occupied(entry) => {
+        let idx = *entry.get();
+        self.triangles.buffer.byte_buffer.bind(gl);
+        self.triangles.buffer.update(gl, idx * VERTICES_PER_ITEM, triangles);
+        false
+      },
+    }
+  }
+
+  /// Remove an item from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity::id::Item) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    self.index_to_id.swap_remove(idx);
+    if idx < self.index_to_id.len() {
+      self.id_to_index.insert(self.index_to_id[idx], idx);
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.swap_remove(gl, idx * VERTICES_PER_ITEM, VERTICES_PER_ITEM);
+  }
+
+  /// Draw all the items.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, gl: &mut GLContext) {
+    self.triangles.bind(gl);
+    self.triangles.draw(gl);
+  }
+}
@@ -1,12 +1,21 @@
 //! Data structures and functions for dealing with lighting.
 
-use cgmath::Vector3;
+use cgmath::{MetricSpace, Point3, Vector3};
 use common::color::Color3;
+use common::fnv_map;
+use common::protocol;
 use gl;
+use gl::types::*;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw::shader::Shader;
 
+/// The point lights in the player's world, e.g. torches. There are never more than `protocol::MAX_LIGHTS`.
+pub type PointLights = fnv_map::T<protocol::LightId, protocol::Light>;
+
+/// The most point lights shaders are given without clustered lighting. The nearest ones are picked.
+pub const FORWARD_LIGHTS: usize = 16;
+
 #[derive(Debug, Clone)]
 /// Colored sun data structure.
 pub struct Sun {
@@ -14,6 +23,8 @@ pub struct Sun {
   pub progression : f32,
   /// The rotation of the sun's path about the y axis.
   pub rotation: f32,
+  /// How far the sun's path is tilted away from passing straight overhead, in radians.
+  pub tilt: f32,
 }
 
 impl Sun {
@@ -26,7 +37,8 @@ impl Sun {
   /// A vector pointing toward the sun.
   pub fn direction(&self) -> Vector3<f32> {
     let (s, c) = self.sin_cos();
-    Vector3::new(c, s, 0.0)
+    let (tilt_s, tilt_c) = self.tilt.sin_cos();
+    Vector3::new(c, s * tilt_c, s * tilt_s)
   }
 
   /// The intensity of direct lighting from the sun.
@@ -52,25 +64,37 @@ impl Sun {
   }
 }
 
-/// Sets the `sun` struct in some shader.
-pub fn set_sun(shader: &mut Shader, gl: &mut GLContext, sun: &Sun) {
-  let sun_direction_uniform = shader.get_uniform_location("sun.direction");
-  let sun_intensity_uniform = shader.get_uniform_location("sun.intensity");
-  shader.use_shader(gl);
-  unsafe {
-    let d = sun.direction();
-    gl::Uniform3f(sun_direction_uniform, d.x, d.y, d.z);
-    let i = sun.intensity();
-    gl::Uniform3f(sun_intensity_uniform, i.r, i.g, i.b);
+/// Sets the `point_light_*` uniforms in some shader, to the lights nearest `eye`.
+pub fn set_point_lights(shader: &mut Shader, gl: &mut GLContext, lights: &PointLights, eye: &Point3<f32>) {
+  let mut nearest: Vec<&protocol::Light> = lights.values().collect();
+  nearest.sort_by(|a, b| {
+    let a = a.position.distance2(*eye);
+    let b = b.position.distance2(*eye);
+    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+  });
+  nearest.truncate(FORWARD_LIGHTS);
+
+  let count = nearest.len();
+  let mut positions: Vec<GLfloat> = Vec::with_capacity(3 * count);
+  let mut colors: Vec<GLfloat> = Vec::with_capacity(3 * count);
+  let mut radii: Vec<GLfloat> = Vec::with_capacity(count);
+  for light in nearest {
+    positions.extend_from_slice(&[light.position.x, light.position.y, light.position.z]);
+    colors.extend_from_slice(&[light.color.r, light.color.g, light.color.b]);
+    radii.push(light.radius);
   }
-}
 
-/// Sets the `ambient_light` uniform in some shader.
-pub fn set_ambient_light(shader: &mut Shader, gl: &mut GLContext, sun: &Sun) {
-  let ambient_light_uniform = shader.get_uniform_location("ambient_light");
+  let count_uniform = shader.get_uniform_location("point_light_count");
+  let positions_uniform = shader.get_uniform_location("point_light_positions");
+  let colors_uniform = shader.get_uniform_location("point_light_colors");
+  let radii_uniform = shader.get_uniform_location("point_light_radii");
   shader.use_shader(gl);
   unsafe {
-    let a = sun.ambient_intensity();
-    gl::Uniform3f(ambient_light_uniform, a.r, a.g, a.b);
+    gl::Uniform1i(count_uniform, count as GLint);
+    if count > 0 {
+      gl::Uniform3fv(positions_uniform, count as GLint, positions.as_ptr());
+      gl::Uniform3fv(colors_uniform, count as GLint, colors.as_ptr());
+      gl::Uniform1fv(radii_uniform, count as GLint, radii.as_ptr());
+    }
   }
 }
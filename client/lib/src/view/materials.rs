@@ -0,0 +1,205 @@
+//! Albedo and normal maps for every terrain material, as texture arrays for the terrain shader to splat.
+//! The textures are generated from the material registry, so there's nothing to load from disk.
+//! Layer `2*m` of each array is material `m` seen from above, and layer `2*m + 1` is it seen from the side.
+
+use gl;
+use gl::types::*;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::texture::TextureUnit;
+
+use common::color::Color3;
+use common::id_allocator;
+use common::material;
+use common::voxel;
+
+/// The width and height of each layer, in texels.
+const SIZE: usize = 128;
+/// The number of layers in each array.
+const LAYERS: usize = 2 * material::COUNT;
+
+#[allow(missing_docs)]
+pub struct T {
+  albedo  : GLuint,
+  normals : GLuint,
+}
+
+/// A hash of a lattice point, in [0, 1].
+fn lattice(seed: u32, x: usize, y: usize) -> f32 {
+  let mut h = seed.wrapping_mul(0x9E3779B1) ^ (x as u32).wrapping_mul(0x85EBCA6B) ^ (y as u32).wrapping_mul(0xC2B2AE35);
+  h ^= h >> 15;
+  h = h.wrapping_mul(0x2C1B3C6D);
+  h ^= h >> 12;
+  (h & 0xFFFF) as f32 / 0xFFFF as f32
+}
+
+/// Value noise with `cells` lattice cells across, which wraps around at the edges of the texture.
+fn value_noise(seed: u32, cells: usize, x: usize, y: usize) -> f32 {
+  let cell_size = SIZE / cells;
+  let (cx, cy) = (x / cell_size, y / cell_size);
+  let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+  let fx = smooth((x % cell_size) as f32 / cell_size as f32);
+  let fy = smooth((y % cell_size) as f32 / cell_size as f32);
+  let corner = |dx, dy| lattice(seed, (cx + dx) % cells, (cy + dy) % cells);
+  let bottom = corner(0, 0) * (1.0 - fx) + corner(1, 0) * fx;
+  let top = corner(0, 1) * (1.0 - fx) + corner(1, 1) * fx;
+  bottom * (1.0 - fy) + top * fy
+}
+
+/// A tiling height map in [0, 1], made of a few octaves of value noise.
+fn height_map(seed: u32) -> Vec<f32> {
+  let octaves = [(4, 1.0), (8, 0.5), (16, 0.25), (32, 0.125)];
+  let total: f32 = octaves.iter().map(|&(_, amp)| amp).sum();
+  let mut heights = Vec::with_capacity(SIZE * SIZE);
+  for y in 0 .. SIZE {
+  for x in 0 .. SIZE {
+    let h: f32 = octaves.iter().map(|&(cells, amp)| amp * value_noise(seed, cells, x, y)).sum();
+    heights.push(h / total);
+  }}
+  heights
+}
+
+fn push_color(texels: &mut Vec<u8>, c: Color3<f32>) {
+  for &v in &[c.r, c.g, c.b] {
+    texels.push((v.max(0.0).min(1.0) * 255.0).round() as u8);
+  }
+}
+
+/// One layer of the albedo array, as RGB texels.
+fn albedo(heights: &[f32], palette: &material::Palette) -> Vec<u8> {
+  let mut texels = Vec::with_capacity(3 * heights.len());
+  for &h in heights {
+    let mix = |a: f32, b: f32| a + (b - a) * h;
+    push_color(
+      &mut texels,
+      Color3::of_rgb(
+        mix(palette.dark.r, palette.light.r),
+        mix(palette.dark.g, palette.light.g),
+        mix(palette.dark.b, palette.light.b),
+      ),
+    );
+  }
+  texels
+}
+
+/// One layer of the normal map array, as tangent-space normals packed into RGB texels.
+fn normals(heights: &[f32], bumpiness: f32) -> Vec<u8> {
+  // How steep the steepest bumps are.
+  let strength = 8.0 * bumpiness;
+  let h = |x: usize, y: usize| heights[(y % SIZE) * SIZE + (x % SIZE)];
+  let mut texels = Vec::with_capacity(3 * heights.len());
+  for y in 0 .. SIZE {
+  for x in 0 .. SIZE {
+    let dx = h(x + 1, y) - h(x + SIZE - 1, y);
+    let dy = h(x, y + 1) - h(x, y + SIZE - 1);
+    let (nx, ny, nz) = (-dx * strength, -dy * strength, 1.0);
+    let len = (nx * nx + ny * ny + nz * nz).sqrt();
+    push_color(
+      &mut texels,
+      Color3::of_rgb(
+        nx / len * 0.5 + 0.5,
+        ny / len * 0.5 + 0.5,
+        nz / len * 0.5 + 0.5,
+      ),
+    );
+  }}
+  texels
+}
+
+fn upload_array(texels: &[u8]) -> GLuint {
+  assert_eq!(texels.len(), 3 * SIZE * SIZE * LAYERS);
+  let mut texture = 0;
+  unsafe {
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+    gl::TexImage3D(
+      gl::TEXTURE_2D_ARRAY, 0, gl::RGB8 as GLint,
+      SIZE as GLsizei, SIZE as GLsizei, LAYERS as GLsizei, 0,
+      gl::RGB, gl::UNSIGNED_BYTE, texels.as_ptr() as *const _,
+    );
+    gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+  }
+  texture
+}
+
+/// Generate every material's textures, and bind them (and the other per-material uniforms) in `shader`.
+pub fn new(
+  gl                 : &mut GLContext,
+  texture_unit_alloc : &mut id_allocator::T<TextureUnit>,
+  shader             : &mut Shader,
+) -> T {
+  let mut albedo_texels = Vec::with_capacity(3 * SIZE * SIZE * LAYERS);
+  let mut normal_texels = Vec::with_capacity(3 * SIZE * SIZE * LAYERS);
+  for (i, material) in material::all().iter().enumerate() {
+    for (j, palette) in [material.top, material.side].iter().enumerate() {
+      let heights = height_map((2 * i + j) as u32);
+      albedo_texels.extend(albedo(&heights, palette));
+      normal_texels.extend(normals(&heights, material.bumpiness));
+    }
+  }
+
+  let albedo_unit = texture_unit_alloc.allocate();
+  let normals_unit = texture_unit_alloc.allocate();
+  let t =
+    unsafe {
+      gl::ActiveTexture(albedo_unit.gl_id());
+      let albedo = upload_array(&albedo_texels);
+      gl::ActiveTexture(normals_unit.gl_id());
+      let normals = upload_array(&normal_texels);
+      T {
+        albedo  : albedo,
+        normals : normals,
+      }
+    };
+
+  let scales: Vec<GLfloat> = material::all().iter().map(|material| material.texture_scale).collect();
+  let shininess: Vec<GLfloat> = material::all().iter().map(|material| material.shininess).collect();
+  let emission: Vec<GLfloat> = material::all().iter().map(|material| material.emission).collect();
+
+  let albedo_uniform = shader.get_uniform_location("material_albedo");
+  let normals_uniform = shader.get_uniform_location("material_normals");
+  let scales_uniform = shader.get_uniform_location("material_scales");
+  let shininess_uniform = shader.get_uniform_location("material_shininess");
+  let emission_uniform = shader.get_uniform_location("material_emission");
+  let snow_uniform = shader.get_uniform_location("snow_material");
+  let leaves_uniform = shader.get_uniform_location("leaves_material");
+  shader.use_shader(gl);
+  unsafe {
+    gl::Uniform1i(albedo_uniform, albedo_unit.glsl_id as GLint);
+    gl::Uniform1i(normals_uniform, normals_unit.glsl_id as GLint);
+    gl::Uniform1fv(scales_uniform, material::COUNT as GLint, scales.as_ptr());
+    gl::Uniform1fv(shininess_uniform, material::COUNT as GLint, shininess.as_ptr());
+    gl::Uniform1fv(emission_uniform, material::COUNT as GLint, emission.as_ptr());
+    gl::Uniform1i(snow_uniform, voxel::Material::Snow as GLint);
+    gl::Uniform1i(leaves_uniform, voxel::Material::Leaves as GLint);
+  }
+
+  t
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteTextures(1, &self.albedo);
+      gl::DeleteTextures(1, &self.normals);
+    }
+  }
+}
+
+#[test]
+fn textures_tile() {
+  let heights = height_map(3);
+  for &h in &heights {
+    assert!(0.0 <= h && h <= 1.0);
+  }
+  // The last column blends into the first, just as neighboring columns do.
+  let step = |x0: usize, x1: usize| {
+    (0 .. SIZE).map(|y| (heights[y * SIZE + x0] - heights[y * SIZE + x1]).abs()).fold(0.0, f32::max)
+  };
+  assert!(step(SIZE - 1, 0) < 0.1);
+  assert!(step(0, 1) < 0.1);
+}
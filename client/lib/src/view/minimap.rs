@@ -0,0 +1,363 @@
+//! A top-down map of the terrain the player has seen, drawn in a corner of the HUD with markers for the player and
+//! everything around it, or across most of the screen as a full map. Both are drawn from the same texture, which
+//! is painted as terrain chunks load.
+
+use cgmath::{Point2, Point3, Vector2, Vector3, EuclideanSpace, InnerSpace};
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::{Texture2D, TextureUnit};
+
+use common::color::{Color3, Color4};
+use common::fnv_map;
+use common::material;
+
+use terrain_mesh;
+use vertex::{ColoredVertex, TextureVertex};
+use view;
+use view::chunked_terrain;
+use view::entity;
+use view::shaders;
+use view::terrain_buffers;
+
+/// The width and height of the map texture, in texels.
+pub const SIZE: usize = 256;
+/// The width of the column of the world each texel shows.
+pub const CELL_SIZE: f32 = 2.0;
+/// How far the minimap can see from its center to its edge, from most zoomed in to least.
+const ZOOMS: [f32; 4] = [32.0, 64.0, 128.0, 256.0];
+/// The width of the minimap, in HUD units.
+const MINIMAP_SIZE: f32 = 0.5;
+/// The width of the full map, in HUD units.
+const FULL_MAP_SIZE: f32 = 1.8;
+/// The gap between the minimap and the edges of the window, in HUD units.
+const MARGIN: f32 = 0.04;
+/// The width of the markers for mobs and other players, in HUD units.
+const MARKER_SIZE: f32 = 0.015;
+/// The color of water seen from above.
+const WATER_COLOR: Color3<f32> = Color3 { r: 0.2, g: 0.35, b: 0.7 };
+
+/// Where the top-down sunlight that shades the map comes from: the north-west, so slopes facing it are lighter.
+fn light_direction() -> Vector3<f32> {
+  Vector3::new(-1.0, 2.0, -1.0).normalize()
+}
+
+fn center(triangle: &terrain_mesh::Triangle<Point3<f32>>) -> Point3<f32> {
+  Point3::from_vec((triangle.v1.to_vec() + triangle.v2.to_vec() + triangle.v3.to_vec()) / 3.0)
+}
+
+/// The index of the texel a column of the world is shown in. Columns `SIZE` texels apart share a texel.
+fn wrap(i: i32) -> usize {
+  let size = SIZE as i32;
+  (((i % size) + size) % size) as usize
+}
+
+/// The colors and heights of the terrain from above, wrapped around a square `SIZE` texels across. The raster
+/// never has to scroll: whichever of the columns sharing a texel was painted last is the one it shows, and the
+/// player is always closer to that one than to the others.
+pub struct Raster {
+  /// The column each texel shows, and the height of the surface painted into it.
+  columns    : Vec<Option<(i32, i32, f32)>>,
+  /// RGBA texels, row by row along z.
+  pub texels : Vec<u8>,
+}
+
+#[allow(missing_docs)]
+pub fn raster() -> Raster {
+  Raster {
+    columns : vec!(None; SIZE * SIZE),
+    texels  : vec!(0; 4 * SIZE * SIZE),
+  }
+}
+
+impl Raster {
+  /// Paint a surface at some height into the column of the world it's in, unless there's already a higher
+  /// surface there. Returns whether anything changed.
+  pub fn paint(&mut self, position: Point3<f32>, color: Color3<f32>) -> bool {
+    let column = ((position.x / CELL_SIZE).floor() as i32, (position.z / CELL_SIZE).floor() as i32);
+    let i = wrap(column.1) * SIZE + wrap(column.0);
+    match self.columns[i] {
+      Some((x, z, height)) if (x, z) == column && height > position.y => return false,
+      _ => {},
+    }
+    self.columns[i] = Some((column.0, column.1, position.y));
+    let texel = &mut self.texels[4 * i .. 4 * i + 4];
+    for (t, &c) in texel.iter_mut().zip(&[color.r, color.g, color.b, 1.0]) {
+      *t = (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    }
+    true
+  }
+
+  /// Forget everything that's been painted.
+  pub fn clear(&mut self) {
+    *self = raster();
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  raster         : Raster,
+  /// Whether the raster's changed since it was last uploaded.
+  dirty          : bool,
+  texture        : Texture2D<'a>,
+  mobs           : fnv_map::T<entity::id::Mob, Point3<f32>>,
+  players        : fnv_map::T<entity::id::Player, Point3<f32>>,
+  /// An index into `ZOOMS`.
+  zoom           : usize,
+  /// Show the full map in the middle of the screen, instead of the minimap in the corner.
+  pub fullscreen : bool,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b:'a>(gl: &'a GLContext) -> T<'b> {
+  let texture = Texture2D::new(gl);
+  unsafe {
+    gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
+    gl::TexImage2D(
+      gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, SIZE as GLint, SIZE as GLint, 0,
+      gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null(),
+    );
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+    // The raster wraps around, so the texture does too.
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+  }
+
+  T {
+    raster     : raster(),
+    dirty      : true,
+    texture    : texture,
+    mobs       : fnv_map::new(),
+    players    : fnv_map::new(),
+    zoom       : 1,
+    fullscreen : false,
+  }
+}
+
+/// Upload some textured quads for the texture shader.
+impl<'a> T<'a> {
+  /// Paint the upward-facing parts of some newly loaded terrain.
+  pub fn add_terrain(&mut self, mesh: &chunked_terrain::T) {
+    for i in 0 .. mesh.polygon_count() {
+      let (chunk, j) = (i / terrain_buffers::CHUNK_LENGTH, i % terrain_buffers::CHUNK_LENGTH);
+      let vertices = &mesh.vertex_coordinates[chunk].0[j];
+      let normals = &mesh.normals[chunk].0[j];
+      let normal = (normals.v1 + normals.v2 + normals.v3) / 3.0;
+      if normal.y <= 0.0 {
+        continue
+      }
+      let material = mesh.materials[chunk].0[j].v1 as usize;
+      if material >= material::COUNT {
+        continue
+      }
+      let palette = &material::all()[material].top;
+      let shade = 0.5 + 0.5 * normal.normalize().dot(light_direction()).max(0.0);
+      let color =
+        Color3::of_rgb(
+          shade * (palette.dark.r + palette.light.r) / 2.0,
+          shade * (palette.dark.g + palette.light.g) / 2.0,
+          shade * (palette.dark.b + palette.light.b) / 2.0,
+        );
+      self.dirty |= self.raster.paint(center(vertices), color);
+    }
+  }
+
+  /// Paint a chunk's water surface.
+  pub fn add_water(&mut self, triangles: &[terrain_mesh::Triangle<Point3<f32>>]) {
+    for triangle in triangles {
+      self.dirty |= self.raster.paint(center(triangle), WATER_COLOR);
+    }
+  }
+
+  /// Forget the map and everything on it, e.g. when moving to another world.
+  pub fn clear(&mut self) {
+    self.raster.clear();
+    self.dirty = true;
+    self.mobs.clear();
+    self.players.clear();
+  }
+
+  #[allow(missing_docs)]
+  pub fn place_mob(&mut self, id: entity::id::Mob, position: Point3<f32>) {
+    self.mobs.insert(id, position);
+  }
+
+  #[allow(missing_docs)]
+  pub fn remove_mob(&mut self, id: entity::id::Mob) {
+    self.mobs.remove(&id);
+  }
+
+  #[allow(missing_docs)]
+  pub fn place_player(&mut self, id: entity::id::Player, position: Point3<f32>) {
+    self.players.insert(id, position);
+  }
+
+  #[allow(missing_docs)]
+  pub fn remove_player(&mut self, id: entity::id::Player) {
+    self.players.remove(&id);
+  }
+
+  /// Show less of the map, bigger, or more of it, smaller.
+  pub fn zoom(&mut self, closer: bool) {
+    if closer {
+      self.zoom = self.zoom.saturating_sub(1);
+    } else {
+      self.zoom = std::cmp::min(self.zoom + 1, ZOOMS.len() - 1);
+    }
+  }
+
+  /// The zoom, as how far the minimap sees from its center to its edge.
+  pub fn range(&self) -> f32 {
+    ZOOMS[self.zoom]
+  }
+
+  /// Draw the minimap (or the full map) centered on `eye`, with an arrow pointing along `forward`.
+  pub fn draw<'b>(
+    &mut self,
+    gl             : &'b mut GLContext,
+    texture_shader : &mut shaders::texture::T<'a>,
+    color_shader   : &mut shaders::color::T<'a>,
+    texture_unit   : &TextureUnit,
+    eye            : &Point3<f32>,
+    forward        : &Vector3<f32>,
+    window_size    : &Vector2<i32>,
+  ) where
+    'a: 'b,
+  {
+    if self.dirty {
+      unsafe {
+        gl::ActiveTexture(texture_unit.gl_id());
+        gl::BindTexture(gl::TEXTURE_2D, self.texture.handle.gl_id);
+        gl::TexSubImage2D(
+          gl::TEXTURE_2D, 0, 0, 0, SIZE as GLint, SIZE as GLint,
+          gl::RGBA, gl::UNSIGNED_BYTE, self.raster.texels.as_ptr() as *const _,
+        );
+      }
+      self.dirty = false;
+    }
+
+    // The HUD spans [-1, 1] vertically, and proportionally wider horizontally.
+    let aspect = window_size.x as f32 / window_size.y as f32;
+    let (center, size, range) =
+      if self.fullscreen {
+        (Point2::new(0.0, 0.0), FULL_MAP_SIZE, SIZE as f32 * CELL_SIZE / 2.0)
+      } else {
+        let half = MINIMAP_SIZE / 2.0;
+        (Point2::new(aspect - MARGIN - half, -1.0 + MARGIN + half), MINIMAP_SIZE, self.range())
+      };
+    let half = size / 2.0;
+    let min = Point2::new(center.x - half, center.y - half);
+    let max = Point2::new(center.x + half, center.y + half);
+    // North (-z) is up the screen.
+    let to_hud = |p: &Point3<f32>| {
+      let offset = Vector2::new(p.x - eye.x, eye.z - p.z) / range;
+      if offset.x.abs() > 1.0 || offset.y.abs() > 1.0 {
+        None
+      } else {
+        Some(center + offset * half)
+      }
+    };
+
+    let mut background: Vec<_> =
+      ColoredVertex::square(
+        Point2::new(min.x - 0.01, min.y - 0.01),
+        Point2::new(max.x + 0.01, max.y + 0.01),
+        Color4::of_rgba(0.0, 0.0, 0.0, 0.6),
+      ).iter().cloned().collect();
+
+    let world_size = SIZE as f32 * CELL_SIZE;
+    let us = Vector2::new(eye.x - range, eye.x + range) / world_size;
+    let vs = Vector2::new(eye.z - range, eye.z + range) / world_size;
+    let vtx = |x, y, u, v| {
+      TextureVertex {
+        world_position   : Point3::new(x, y, 0.0),
+        texture_position : Vector2::new(u, v),
+      }
+    };
+    let map = [
+      vtx(min.x, min.y, us.x, vs.y), vtx(max.x, max.y, us.y, vs.x), vtx(min.x, max.y, us.x, vs.x),
+      vtx(min.x, min.y, us.x, vs.y), vtx(max.x, min.y, us.y, vs.y), vtx(max.x, max.y, us.y, vs.x),
+    ];
+
+    let mut markers = Vec::new();
+    let marker = |markers: &mut Vec<ColoredVertex>, p: Point2<f32>, color| {
+      let r = MARKER_SIZE / 2.0;
+      markers.extend(ColoredVertex::square(Point2::new(p.x - r, p.y - r), Point2::new(p.x + r, p.y + r), color).iter());
+    };
+    for position in self.mobs.values() {
+      if let Some(p) = to_hud(position) {
+        marker(&mut markers, p, Color4::of_rgba(0.9, 0.2, 0.1, 1.0));
+      }
+    }
+    for position in self.players.values() {
+      if let Some(p) = to_hud(position) {
+        marker(&mut markers, p, Color4::of_rgba(1.0, 1.0, 1.0, 1.0));
+      }
+    }
+    {
+      // An arrow for this player, pointing the way the camera's facing.
+      let heading = Vector2::new(forward.x, -forward.z);
+      let heading = if heading.magnitude2() > 0.0 { heading.normalize() } else { Vector2::new(0.0, 1.0) };
+      let side = Vector2::new(heading.y, -heading.x);
+      let color = Color4::of_rgba(1.0, 0.9, 0.2, 1.0);
+      let vtx = |p: Point2<f32>| ColoredVertex { position: Point3::new(p.x, p.y, 0.0), color: color };
+      markers.push(vtx(center + heading * 2.0 * MARKER_SIZE));
+      markers.push(vtx(center - heading * MARKER_SIZE - side * MARKER_SIZE));
+      markers.push(vtx(center - heading * MARKER_SIZE + side * MARKER_SIZE));
+    }
+    background.extend(markers.iter().cloned());
+
+    unsafe {
+      gl::Disable(gl::DEPTH_TEST);
+    }
+
+    // The map goes between the background and the markers, so draw the background's triangles on their own.
+    color_shader.shader.use_shader(gl);
+    let triangles = view::build_hud_triangles(gl, color_shader, &background);
+    triangles.bind(gl);
+    unsafe {
+      gl::DrawArrays(gl::TRIANGLES, 0, 6);
+    }
+
+    texture_shader.shader.use_shader(gl);
+    unsafe {
+      gl::ActiveTexture(texture_unit.gl_id());
+      gl::BindTexture(gl::TEXTURE_2D, self.texture.handle.gl_id);
+    }
+    let quad = view::build_hud_quads(gl, texture_shader, &map);
+    quad.bind(gl);
+    quad.draw(gl);
+
+    color_shader.shader.use_shader(gl);
+    triangles.bind(gl);
+    unsafe {
+      gl::DrawArrays(gl::TRIANGLES, 6, markers.len() as GLsizei);
+      gl::Enable(gl::DEPTH_TEST);
+    }
+  }
+}
+
+#[test]
+fn higher_surfaces_and_newer_columns_win() {
+  let mut raster = raster();
+  let grass = Color3::of_rgb(0.0, 1.0, 0.0);
+  let stone = Color3::of_rgb(0.5, 0.5, 0.5);
+  let texel = |raster: &Raster, x: usize, z: usize| raster.texels[4 * (z * SIZE + x) + 1];
+
+  assert!(raster.paint(Point3::new(1.0, 10.0, 1.0), grass));
+  assert_eq!(texel(&raster, 0, 0), 255);
+  // A cave floor under the grass doesn't cover it up, but a ledge above it does.
+  assert!(!raster.paint(Point3::new(1.5, 2.0, 0.5), stone));
+  assert!(raster.paint(Point3::new(0.5, 12.0, 1.5), stone));
+  assert_eq!(texel(&raster, 0, 0), 128);
+
+  // A column a whole raster away shares the texel, and takes it over whatever its height.
+  let far = SIZE as f32 * CELL_SIZE;
+  assert!(raster.paint(Point3::new(1.0 + far, -50.0, 1.0 - far), grass));
+  assert_eq!(texel(&raster, 0, 0), 255);
+  // Negative coordinates wrap around too.
+  assert!(raster.paint(Point3::new(-1.0, 0.0, -1.0), grass));
+  assert_eq!(texel(&raster, SIZE - 1, SIZE - 1), 255);
+}
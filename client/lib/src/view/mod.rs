@@ -1,17 +1,44 @@
 //! The state associated with perceiving the world state.
 
+pub mod brush;
 mod camera;
+pub mod chat;
 pub mod chunked_terrain;
+pub mod clusters;
+pub mod crafting;
+pub mod debug_lines;
 mod grass_buffers;
 pub mod entity;
+pub mod entity_buffers;
+pub mod fog;
+pub mod fontloader;
+pub mod fps;
+pub mod gpu_timings;
+pub mod hotbar;
+mod item_buffers;
 pub mod light;
-mod mob_buffers;
-mod player_buffers;
+pub mod materials;
+pub mod minimap;
+pub mod nameplates;
+pub mod post_process;
 mod render;
+pub mod screen_effects;
+pub mod screenshot;
+pub mod selection;
 pub mod shaders;
+pub mod shadow;
+pub mod sky;
+pub mod spectator;
 pub mod terrain_buffers;
+pub mod third_person;
 pub mod thread;
+pub mod ttf;
 pub mod update;
+pub mod update_queue;
+mod upload_ring;
+mod water_buffers;
+pub mod waypoints;
+pub mod weather;
 
 pub use self::render::render;
 
@@ -20,24 +47,37 @@ use gl;
 use gl::types::*;
 use image;
 use image::GenericImage;
+use sdl2::keyboard::Keycode;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData};
 use yaglw::texture::{TextureUnit};
 
+use common::fnv_map;
+use common::health;
 use common::id_allocator;
-use vertex::{ColoredVertex};
+use common::voxel;
 
-/// FOV in radians
-pub const FOV: f32 = std::f32::consts::FRAC_PI_3;
+use animation;
+use particles;
+use vertex::{ColoredVertex, TextureVertex};
 
 const VERTICES_PER_TRIANGLE: usize = 3;
+const MAX_HUD_TRIANGLES: usize = 64;
+/// The most mobs that can be drawn at once.
+const MAX_MOBS: usize = 1 << 12;
+/// The most players that can be drawn at once.
+const MAX_PLAYERS: usize = 1 << 8;
 
 #[allow(missing_docs)]
 pub enum InputMode {
   Camera,
   Sun,
+  /// Keys are typed into the chat line.
+  Chat,
+  /// The crafting panel is open, and the look up and down keys pick recipes.
+  Crafting,
 }
 
 /// The state associated with perceiving the world state.
@@ -53,28 +93,100 @@ pub struct T<'a> {
   pub misc_texture_unit: TextureUnit,
   /// The OpenGL buffers for terrain render data
   pub terrain_buffers: terrain_buffers::T<'a>,
+  /// The textures for each terrain material
+  pub materials: materials::T,
   /// The OpenGL buffers for grass render data
   pub grass_buffers: grass_buffers::T<'a>,
   /// The OpenGL texture to sample for grass
   pub grass_texture: yaglw::texture::Texture2D<'a>,
   /// OpenGL buffers for mob render data
-  pub mob_buffers: mob_buffers::T<'a>,
+  pub mob_buffers: entity_buffers::T<'a, entity::id::Mob>,
   /// OpenGL buffers for player render data
-  pub player_buffers: player_buffers::T<'a>,
+  pub player_buffers: entity_buffers::T<'a, entity::id::Player>,
+  /// OpenGL buffers for item render data
+  pub item_buffers: item_buffers::T<'a>,
   /// Hud triangles for non-text.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  /// Glyphs for drawing HUD text.
+  pub glyphs: ttf::Glyphs<'a>,
+  /// Fonts for HUD text that doesn't change often.
+  pub fonts: fontloader::FontLoader,
+  /// Chat messages on the HUD.
+  pub chat: chat::T<'a>,
+  /// The player's inventory slots on the HUD.
+  pub hotbar: hotbar::T<'a>,
+  /// The recipe list, shown while crafting.
+  pub crafting: crafting::T<'a>,
+  /// Other players' names, floating above them.
+  pub nameplates: nameplates::T,
+  /// The map of the terrain around the player, in a corner of the HUD.
+  pub minimap: minimap::T<'a>,
+  /// The player's waypoints, on the compass and as beacons in the world.
+  pub waypoints: waypoints::T<'a>,
+  /// The player's health, shown on the HUD.
+  pub health: health::T,
+  /// The brush picked for editing terrain, if any.
+  pub brush: brush::T,
+  /// The region picked for copying.
+  pub selection: selection::T,
+  /// Updates from the client that haven't been applied yet.
+  pub updates: update_queue::T,
+  /// Shadow maps for the sun.
+  pub shadow: shadow::T,
+  /// Where the scene is drawn, and how it gets onto the window.
+  pub post_process: post_process::T,
+  /// OpenGL buffers for water surfaces
+  pub water_buffers: water_buffers::T<'a>,
+  /// Particles in flight.
+  pub particles: particles::T<'a>,
+  /// The voxel under the crosshair, if any.
+  pub target: Option<voxel::bounds::T>,
 
   #[allow(missing_docs)]
-  pub sun: light::Sun,
+  pub sky: sky::T,
+  /// What moves the sun along, once the server's said where it is.
+  pub sky_clock: Option<sky::Clock>,
+  /// Torches and the like, placed by the server.
+  pub point_lights: light::PointLights,
+  /// The point lights sorted by where they shine, if clustered lighting is on.
+  pub clusters: Option<clusters::T<'a>>,
+  #[allow(missing_docs)]
+  pub fog: fog::T,
+  /// The wind's direction in the xz plane. Its length is the wind's strength.
+  pub wind: cgmath::Vector2<f32>,
+  /// Rain, snow, and how wet the terrain is.
+  pub weather: weather::T,
   #[allow(missing_docs)]
   pub camera: camera::T,
+  /// Where the player sees from. The camera is here too, unless it's in third person or spectating.
+  pub eye: cgmath::Point3<f32>,
+  /// Puts the camera behind the player, when it's on.
+  pub third_person: third_person::T,
   #[allow(missing_docs)]
   pub window_size: cgmath::Vector2<i32>,
+  /// The vertical field of view, in radians.
+  pub fov: f32,
   /// Whether to render HUD elements
   pub show_hud: bool,
+  /// Counts frames, to show the frame rate on the HUD.
+  pub fps: fps::T,
+  /// How long the GPU spends on each render pass.
+  pub gpu_timings: gpu_timings::T,
+  /// Whether to show the debug overlay.
+  pub show_debug: bool,
+  /// The text of the debug overlay, refreshed by the view thread while it's shown.
+  pub debug_stats: String,
+  /// Boxes and lines drawn while the debug overlay is shown.
+  pub debug_lines: debug_lines::T<'a>,
+  /// Saves frames to disk on request.
+  pub screenshots: screenshot::T,
 
   /// Whether to render HUD elements
   pub input_mode: InputMode,
+  /// The keys pressed outside of chat and not released yet.
+  pub held_keys: std::collections::HashSet<Keycode>,
+  /// Flies the camera around on its own, when it's detached from the player.
+  pub spectator: spectator::T,
 
   /// Distance to near clip plane
   pub near_clip: f32,
@@ -118,12 +230,15 @@ fn load_grass_texture<'a, 'b:'a>(
 pub fn new<'a>(
   mut gl: GLContext,
   window_size: cgmath::Vector2<i32>,
+  fov: f32,
+  terrain_byte_budget: usize,
+  clustered_lighting: bool,
 ) -> T<'a> {
   let mut texture_unit_alloc = id_allocator::new();
 
   let mut shaders = shaders::new(&mut gl, window_size);
 
-  let terrain_buffers = terrain_buffers::new(&mut gl);
+  let terrain_buffers = terrain_buffers::new(&mut gl, terrain_byte_budget);
   terrain_buffers.bind_vertex_positions(
     &mut gl,
     &mut texture_unit_alloc,
@@ -134,41 +249,81 @@ pub fn new<'a>(
     &mut texture_unit_alloc,
     &mut shaders.terrain_shader.shader,
   );
+  terrain_buffers.bind_ambient_occlusion(
+    &mut gl,
+    &mut texture_unit_alloc,
+    &mut shaders.terrain_shader.shader,
+  );
+  terrain_buffers.bind_light(
+    &mut gl,
+    &mut texture_unit_alloc,
+    &mut shaders.terrain_shader.shader,
+  );
   terrain_buffers.bind_materials(
     &mut gl,
     &mut texture_unit_alloc,
     &mut shaders.terrain_shader.shader,
   );
+  let materials =
+    materials::new(
+      &mut gl,
+      &mut texture_unit_alloc,
+      &mut shaders.terrain_shader.shader,
+    );
   terrain_buffers.bind_vertex_positions(
     &mut gl,
     &mut texture_unit_alloc,
     &mut shaders.grass_billboard.shader,
   );
+  terrain_buffers.bind_vertex_positions(
+    &mut gl,
+    &mut texture_unit_alloc,
+    &mut shaders.terrain_shadow.shader,
+  );
   terrain_buffers.bind_normals(
     &mut gl,
     &mut texture_unit_alloc,
     &mut shaders.grass_billboard.shader,
   );
 
-  let mob_buffers = mob_buffers::new(&mut gl, &shaders.mob_shader);
-  let player_buffers = player_buffers::new(&mut gl, &shaders.mob_shader);
-
-  let buffer = GLBuffer::new(&mut gl, 16 * VERTICES_PER_TRIANGLE);
-  let hud_triangles = {
-    GLArray::new(
+  let load_rig = |path| animation::load(path).unwrap_or_else(|err| panic!("{}", err));
+  let mob_buffers =
+    entity_buffers::new(
       &mut gl,
-      &shaders.hud_color_shader.shader,
-      &[
-        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
-        VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
-      ],
-      DrawMode::Triangles,
-      buffer,
-    )
-  };
+      &shaders.entity.shader,
+      texture_unit_alloc.allocate(),
+      load_rig(animation::MOB_RIG),
+      MAX_MOBS,
+    );
+  let player_buffers =
+    entity_buffers::new(
+      &mut gl,
+      &shaders.entity.shader,
+      texture_unit_alloc.allocate(),
+      load_rig(animation::PLAYER_RIG),
+      MAX_PLAYERS,
+    );
+  let item_buffers = item_buffers::new(&mut gl, &shaders.mob_shader);
+
+  let hud_triangles = new_hud_triangles(&mut gl, &shaders.hud_color_shader);
+
+  let particles = particles::new(&mut gl, &shaders.particle.shader);
+  let waypoints = waypoints::new(&mut gl, &shaders.beacon.shader);
+  let debug_lines = debug_lines::new(&mut gl, &shaders.debug_lines.shader);
+
+  let shadow = shadow::new(shadow::Quality::Medium, texture_unit_alloc.allocate());
 
   let misc_texture_unit = texture_unit_alloc.allocate();
 
+  let post_process = post_process::new(window_size, texture_unit_alloc.allocate(), texture_unit_alloc.allocate());
+
+  let clusters =
+    if clustered_lighting {
+      Some(clusters::new(&mut gl, &mut texture_unit_alloc))
+    } else {
+      None
+    };
+
   unsafe {
     gl::FrontFace(gl::CCW);
     gl::CullFace(gl::BACK);
@@ -208,6 +363,11 @@ pub fn new<'a>(
 
   let empty_gl_array = yaglw::vertex_buffer::ArrayHandle::new(&gl);
 
+  let fonts = fontloader::FontLoader::new();
+  let glyphs = fonts.mono.glyphs(&gl);
+  let minimap = minimap::new(&gl);
+  let gpu_timings = gpu_timings::new(&gl);
+
   let near_clip = 0.1;
   let far_clip = 2048.0;
 
@@ -216,19 +376,39 @@ pub fn new<'a>(
     shaders: shaders,
 
     terrain_buffers: terrain_buffers,
+    materials: materials,
     grass_buffers: grass_buffers,
     grass_texture: grass_texture,
     mob_buffers: mob_buffers,
     player_buffers: player_buffers,
+    item_buffers: item_buffers,
     hud_triangles: hud_triangles,
+    glyphs: glyphs,
+    fonts: fonts,
+    chat: chat::new(),
+    hotbar: hotbar::new(),
+    crafting: crafting::new(),
+    nameplates: nameplates::new(),
+    minimap: minimap,
+    waypoints: waypoints,
+    health: health::full(health::PLAYER_MAX),
+    brush: brush::new(),
+    selection: selection::new(),
+    updates: update_queue::new(),
+    shadow: shadow,
+    post_process: post_process,
+    water_buffers: water_buffers::new(),
+    particles: particles,
+    target: None,
 
     empty_gl_array: empty_gl_array,
     misc_texture_unit: misc_texture_unit,
 
     window_size: window_size,
+    fov: fov,
 
     camera: {
-      let fovy = cgmath::Rad(FOV);
+      let fovy = cgmath::Rad(fov);
       let aspect = window_size.x as f32 / window_size.y as f32;
       let mut camera = camera::unit();
       // Initialize the projection matrix.
@@ -238,16 +418,104 @@ pub fn new<'a>(
       camera
     },
 
-    sun:
-      light::Sun {
-        progression: 0.0,
-        rotation: 0.0,
+    eye: cgmath::Point3::new(0.0, 0.0, 0.0),
+    third_person: third_person::new(),
+
+    sky:
+      sky::T {
+        time_of_day : 0.0,
+        rotation    : 0.0,
+        season      : None,
       },
+    sky_clock: None,
+    point_lights: fnv_map::new(),
+    clusters: clusters,
+    fog: Default::default(),
+    wind: cgmath::Vector2::new(0.4, 0.15),
+    weather: weather::new(),
 
     show_hud: true,
+    fps: fps::new(),
+    gpu_timings: gpu_timings,
+    show_debug: false,
+    debug_stats: String::new(),
+    debug_lines: debug_lines,
+    screenshots: screenshot::new(),
     input_mode: InputMode::Camera,
+    held_keys: std::collections::HashSet::new(),
+    spectator: spectator::new(),
 
     near_clip: near_clip,
     far_clip: far_clip,
   }
 }
+
+/// Make an empty array for HUD triangles.
+pub fn new_hud_triangles<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &shaders::color::T<'a>,
+) -> GLArray<'a, ColoredVertex> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, MAX_HUD_TRIANGLES * VERTICES_PER_TRIANGLE);
+  GLArray::new(
+    gl,
+    &shader.shader,
+    &[
+      VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+      VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+    ],
+    DrawMode::Triangles,
+    buffer,
+  )
+}
+
+/// Upload some HUD triangles that change every frame, e.g. ones that follow the camera.
+pub fn build_hud_triangles<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &shaders::color::T<'a>,
+  vertices: &[ColoredVertex],
+) -> GLArray<'a, ColoredVertex> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, vertices.len());
+  let mut triangles =
+    GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    );
+  triangles.buffer.byte_buffer.bind(gl);
+  triangles.push(gl, vertices);
+  triangles
+}
+
+/// Upload some textured HUD triangles.
+pub fn build_hud_quads<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &shaders::texture::T<'a>,
+  vertices: &[TextureVertex],
+) -> GLArray<'a, TextureVertex> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, vertices.len());
+  let mut quads =
+    GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "texture_position", size: 2, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    );
+  quads.buffer.byte_buffer.bind(gl);
+  quads.push(gl, vertices);
+  quads
+}
@@ -0,0 +1,105 @@
+//! Other players' names, drawn as text floating above their heads.
+//! The text is laid out on the HUD at wherever each head lands on screen, so it always faces the camera.
+
+use cgmath::{Point2, Point3, Vector2, Vector3, InnerSpace};
+use yaglw::gl_context::GLContext;
+use yaglw::texture::TextureUnit;
+
+use common::fnv_map;
+
+use view;
+use view::camera;
+use view::entity;
+use view::ttf;
+
+/// How far above a player's center its name floats.
+const HEIGHT_ABOVE_CENTER: f32 = 1.5;
+/// Names further away than this aren't drawn.
+const MAX_DISTANCE: f32 = 64.0;
+/// Names closer than this are drawn at the font's own size; further ones shrink with distance.
+const FULL_SIZE_DISTANCE: f32 = 8.0;
+/// Names don't shrink past this portion of the font's own size.
+const MIN_SCALE: f32 = 0.5;
+
+#[allow(missing_docs)]
+pub struct T {
+  names     : fnv_map::T<entity::id::Player, String>,
+  /// Where each name floats, for the players being shown.
+  positions : fnv_map::T<entity::id::Player, Point3<f32>>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    names     : fnv_map::new(),
+    positions : fnv_map::new(),
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn set_name(&mut self, id: entity::id::Player, name: String) {
+    self.names.insert(id, name);
+  }
+
+  /// Float a player's name above its center.
+  pub fn place(&mut self, id: entity::id::Player, center: Point3<f32>) {
+    self.positions.insert(id, center + Vector3::new(0.0, HEIGHT_ABOVE_CENTER, 0.0));
+  }
+
+  /// Stop showing a player's name. The name's remembered, in case the player comes back into view.
+  pub fn remove(&mut self, id: entity::id::Player) {
+    self.positions.remove(&id);
+  }
+
+  /// Draw every name in front of the camera.
+  pub fn draw<'a, 'b>(
+    &self,
+    gl: &'b mut GLContext,
+    shader: &mut view::shaders::texture::T<'a>,
+    glyphs: &ttf::Glyphs<'a>,
+    texture_unit: &TextureUnit,
+    camera: &camera::T,
+    window_size: &Vector2<i32>,
+  ) where
+    'a: 'b,
+  {
+    let projection = camera.projection_matrix();
+    let aspect = window_size.x as f32 / window_size.y as f32;
+    let glyph_size = glyphs.size(window_size);
+
+    let mut vertices = Vec::new();
+    for (id, position) in &self.positions {
+      let name =
+        match self.names.get(id) {
+          None => continue,
+          Some(name) => name,
+        };
+      let distance = (position - camera.position).magnitude();
+      if distance > MAX_DISTANCE {
+        continue
+      }
+      let clip = projection * position.to_homogeneous();
+      if clip.w <= 0.0 {
+        continue
+      }
+      let screen = Point2::new(clip.x / clip.w, clip.y / clip.w);
+      if screen.x.abs() > 1.0 || screen.y.abs() > 1.0 {
+        continue
+      }
+
+      let scale = (FULL_SIZE_DISTANCE / distance).min(1.0).max(MIN_SCALE);
+      let height = glyph_size.y * scale;
+      let width = glyph_size.x * scale * name.chars().count() as f32;
+      // The HUD spans [-1, 1] vertically, and proportionally wider horizontally.
+      let origin = Point2::new(screen.x * aspect - width / 2.0, screen.y);
+      glyphs.push_text(&mut vertices, origin, height, name);
+    }
+
+    if vertices.is_empty() {
+      return
+    }
+    let quads = glyphs.build(gl, shader, &vertices);
+    glyphs.draw(gl, shader, texture_unit, &quads);
+  }
+}
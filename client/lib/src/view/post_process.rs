@@ -0,0 +1,281 @@
+//! The scene is drawn into an off-screen HDR framebuffer, and then run through a chain of full-screen passes on
+//! its way to the window: bloom, tonemapping, screen effects, and FXAA. Bloom, tonemapping and FXAA can be turned
+//! on and off, and the screen effects pass only runs while there's an effect to show.
+
+use cgmath;
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::texture::TextureUnit;
+
+use view::screen_effects;
+use view::shaders;
+
+/// How far past white a pixel has to be to bloom.
+const BLOOM_THRESHOLD: f32 = 1.0;
+/// How much of the bloom is added back onto the scene.
+const BLOOM_STRENGTH: f32 = 0.6;
+/// Each pass blurs horizontally and then vertically. More passes spread the bloom further.
+const BLUR_PASSES: usize = 3;
+
+/// A framebuffer that renders into a texture.
+struct Target {
+  framebuffer : GLuint,
+  texture     : GLuint,
+}
+
+/// Make a target of some size, with a depth buffer if one's given.
+/// N.B. This binds the new texture to the active texture unit.
+fn target(size: cgmath::Vector2<i32>, internal_format: GLenum, depth: Option<GLuint>) -> Target {
+  let mut framebuffer = 0;
+  let mut texture = 0;
+  unsafe {
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(
+      gl::TEXTURE_2D,
+      0,
+      internal_format as GLint,
+      size.x,
+      size.y,
+      0,
+      gl::RGBA,
+      gl::FLOAT,
+      std::ptr::null(),
+    );
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+
+    gl::GenFramebuffers(1, &mut framebuffer);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+    if let Some(depth) = depth {
+      gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth);
+    }
+    let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+    if status != gl::FRAMEBUFFER_COMPLETE {
+      warn!("Post-processing framebuffer incomplete: 0x{:x}", status);
+    }
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+  }
+
+  Target {
+    framebuffer : framebuffer,
+    texture     : texture,
+  }
+}
+
+impl Drop for Target {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteFramebuffers(1, &self.framebuffer);
+      gl::DeleteTextures(1, &self.texture);
+    }
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  #[allow(missing_docs)]
+  pub tonemapping : bool,
+  #[allow(missing_docs)]
+  pub bloom       : bool,
+  #[allow(missing_docs)]
+  pub fxaa        : bool,
+  #[allow(missing_docs)]
+  pub effects     : screen_effects::T,
+
+  size          : cgmath::Vector2<i32>,
+  depth         : GLuint,
+  /// What the scene is drawn into, in HDR.
+  scene         : Target,
+  /// Bloom is blurred back and forth between these, at half resolution.
+  bloom_targets : [Target; 2],
+  /// The passes after tonemapping draw back and forth between these, until the last one draws onto the window.
+  ldr           : [Target; 2],
+  scene_unit    : TextureUnit,
+  bloom_unit    : TextureUnit,
+}
+
+#[allow(missing_docs)]
+pub fn new(size: cgmath::Vector2<i32>, scene_unit: TextureUnit, bloom_unit: TextureUnit) -> T {
+  let mut depth = 0;
+  unsafe {
+    gl::GenRenderbuffers(1, &mut depth);
+    gl::BindRenderbuffer(gl::RENDERBUFFER, depth);
+    gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, size.x, size.y);
+    gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+    // Creating the targets binds their textures, so keep them out of every other unit.
+    gl::ActiveTexture(scene_unit.gl_id());
+  }
+
+  T {
+    tonemapping   : true,
+    bloom         : true,
+    fxaa          : true,
+    effects       : screen_effects::new(),
+
+    size          : size,
+    depth         : depth,
+    scene         : target(size, gl::RGBA16F, Some(depth)),
+    bloom_targets : [target(half(size), gl::RGBA16F, None), target(half(size), gl::RGBA16F, None)],
+    ldr           : [target(size, gl::RGBA8, None), target(size, gl::RGBA8, None)],
+    scene_unit    : scene_unit,
+    bloom_unit    : bloom_unit,
+  }
+}
+
+/// The size of the bloom targets.
+fn half(size: cgmath::Vector2<i32>) -> cgmath::Vector2<i32> {
+  cgmath::Vector2::new(std::cmp::max(size.x / 2, 1), std::cmp::max(size.y / 2, 1))
+}
+
+fn bind_texture(unit: &TextureUnit, texture: GLuint) {
+  unsafe {
+    gl::ActiveTexture(unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+  }
+}
+
+fn set_sampler(shader: &mut Shader, name: &str, unit: &TextureUnit) {
+  unsafe {
+    let uniform = shader.get_uniform_location(name);
+    gl::Uniform1i(uniform, unit.glsl_id as GLint);
+  }
+}
+
+fn draw_fullscreen() {
+  unsafe {
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+  }
+}
+
+impl T {
+  /// Direct rendering into the HDR scene target.
+  pub fn begin(&self) {
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, self.scene.framebuffer);
+    }
+  }
+
+  /// Direct a pass's output into LDR target `next`, if there are passes left to read it, or else onto the window.
+  fn bind_output(&self, passes_left: usize, next: usize) {
+    let framebuffer = if passes_left > 0 { self.ldr[next].framebuffer } else { 0 };
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    }
+  }
+
+  /// Run the scene through the enabled passes and onto the window, leaving the window bound with its depth buffer
+  /// cleared, so the HUD can be drawn over it. `now` is in ns.
+  pub fn finish(&self, gl: &mut GLContext, shaders: &mut shaders::post_process::T, empty_array: GLuint, now: u64) {
+    unsafe {
+      gl::Disable(gl::DEPTH_TEST);
+      gl::Disable(gl::BLEND);
+      gl::BindVertexArray(empty_array);
+    }
+
+    if self.bloom {
+      self.draw_bloom(gl, shaders);
+    }
+
+    let effects = self.effects.is_active(now);
+    let mut passes_left = effects as usize + self.fxaa as usize;
+    let mut next = 0;
+
+    unsafe {
+      gl::Viewport(0, 0, self.size.x, self.size.y);
+    }
+    self.bind_output(passes_left, next);
+    bind_texture(&self.scene_unit, self.scene.texture);
+    bind_texture(&self.bloom_unit, self.bloom_targets[0].texture);
+    let shader = &mut shaders.composite;
+    shader.use_shader(gl);
+    set_sampler(shader, "scene", &self.scene_unit);
+    set_sampler(shader, "bloom", &self.bloom_unit);
+    unsafe {
+      gl::Uniform1i(shader.get_uniform_location("bloom_enabled"), self.bloom as GLint);
+      gl::Uniform1f(shader.get_uniform_location("bloom_strength"), BLOOM_STRENGTH);
+      gl::Uniform1i(shader.get_uniform_location("tonemapping_enabled"), self.tonemapping as GLint);
+    }
+    draw_fullscreen();
+
+    if effects {
+      passes_left -= 1;
+      bind_texture(&self.scene_unit, self.ldr[next].texture);
+      next = 1 - next;
+      self.bind_output(passes_left, next);
+      let shader = &mut shaders.effects;
+      self.effects.set_uniforms(shader, gl, now);
+      set_sampler(shader, "source", &self.scene_unit);
+      draw_fullscreen();
+    }
+
+    // FXAA is always the last pass.
+    if self.fxaa {
+      bind_texture(&self.scene_unit, self.ldr[next].texture);
+      unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+      }
+      let shader = &mut shaders.fxaa;
+      shader.use_shader(gl);
+      set_sampler(shader, "source", &self.scene_unit);
+      unsafe {
+        gl::Uniform2f(shader.get_uniform_location("texel_size"), 1.0 / self.size.x as f32, 1.0 / self.size.y as f32);
+      }
+      draw_fullscreen();
+    }
+
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+      gl::Enable(gl::BLEND);
+      gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+  }
+
+  /// Pick out the bright parts of the scene and blur them, into the first bloom target.
+  fn draw_bloom(&self, gl: &mut GLContext, shaders: &mut shaders::post_process::T) {
+    let half = half(self.size);
+    unsafe {
+      gl::Viewport(0, 0, half.x, half.y);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, self.bloom_targets[0].framebuffer);
+    }
+    bind_texture(&self.scene_unit, self.scene.texture);
+    {
+      let shader = &mut shaders.bright_pass;
+      shader.use_shader(gl);
+      set_sampler(shader, "scene", &self.scene_unit);
+      unsafe {
+        gl::Uniform1f(shader.get_uniform_location("threshold"), BLOOM_THRESHOLD);
+      }
+    }
+    draw_fullscreen();
+
+    let shader = &mut shaders.blur;
+    shader.use_shader(gl);
+    set_sampler(shader, "source", &self.bloom_unit);
+    let texel_step = shader.get_uniform_location("texel_step");
+    for _ in 0 .. BLUR_PASSES {
+      for &(from, to, step) in &[(0, 1, (1.0 / half.x as f32, 0.0)), (1, 0, (0.0, 1.0 / half.y as f32))] {
+        bind_texture(&self.bloom_unit, self.bloom_targets[from].texture);
+        unsafe {
+          gl::BindFramebuffer(gl::FRAMEBUFFER, self.bloom_targets[to].framebuffer);
+          gl::Uniform2f(texel_step, step.0, step.1);
+        }
+        draw_fullscreen();
+      }
+    }
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteRenderbuffers(1, &self.depth);
+    }
+  }
+}
@@ -6,26 +6,89 @@ use time;
 use yaglw;
 
 use view;
-use view::camera::{set_camera};
-use view::light::{set_sun, set_ambient_light};
+use view::gpu_timings::Pass;
+use view::clusters;
+use view::fog::{set_fog};
+use view::light::{set_point_lights};
+use view::shadow::{set_shadows};
+use view::sky::{set_sky, set_season};
 
-fn set_eye_position(shader: &mut yaglw::shader::Shader, camera: &view::camera::T) {
-  unsafe {
-    let uniform = shader.get_uniform_location("eye_position");
-    let ptr = &camera.position as *const _ as *const _;
-    gl::Uniform3fv(uniform, 1, ptr);
+/// Point a shader at the clustered lights if they're on, or else give it the point lights nearest the camera.
+fn set_lights(
+  shader      : &mut yaglw::shader::Shader,
+  gl          : &mut yaglw::gl_context::GLContext,
+  lights      : &view::light::PointLights,
+  clusters    : &Option<clusters::T>,
+  camera      : &view::camera::T,
+  window_size : &cgmath::Vector2<i32>,
+) {
+  match *clusters {
+    None => set_point_lights(shader, gl, lights, &camera.position),
+    Some(ref clusters) => clusters.bind(gl, shader, window_size),
   }
 }
 
-fn set_clip(shader: &mut yaglw::shader::Shader, near: f32, far: f32) {
+fn set_depth_only(shader: &mut yaglw::shader::Shader, depth_only: bool) {
   unsafe {
-    let uniform = shader.get_uniform_location("near_clip");
-    gl::Uniform1f(uniform, near);
-    let uniform = shader.get_uniform_location("far_clip");
-    gl::Uniform1f(uniform, far);
+    let uniform = shader.get_uniform_location("depth_only");
+    gl::Uniform1i(uniform, depth_only as i32);
   }
 }
 
+/// Distance from the edge of the window to HUD text, in pixels.
+const HUD_MARGIN: f32 = 8.0;
+
+/// Show the frame rate, the camera's position, the picked brush, the selection and the flying speed in the top-left
+/// corner.
+fn draw_stats(rndr: &mut view::T) {
+  let p = rndr.camera.position;
+  let mut stats = format!("{} fps\n{:.1}, {:.1}, {:.1}", rndr.fps.per_second(), p.x, p.y, p.z);
+  let labels = rndr.brush.label().into_iter().chain(rndr.selection.label()).chain(rndr.spectator.label());
+  for label in labels {
+    stats.push_str("\n");
+    stats.push_str(&label);
+  }
+
+  // The HUD spans [-1, 1] vertically, and proportionally wider horizontally.
+  let units_per_pixel = 2.0 / rndr.window_size.y as f32;
+  let aspect = rndr.window_size.x as f32 / rndr.window_size.y as f32;
+  let height = rndr.glyphs.size(&rndr.window_size).y;
+  let origin =
+    cgmath::Point2::new(
+      -aspect + HUD_MARGIN * units_per_pixel,
+      1.0 - HUD_MARGIN * units_per_pixel - height,
+    );
+  rndr.glyphs.draw_text(
+    &mut rndr.gl,
+    &mut rndr.shaders.texture_shader,
+    &rndr.misc_texture_unit,
+    origin,
+    height,
+    &stats,
+  );
+}
+
+/// Show the debug overlay in the top-right corner.
+fn draw_debug(rndr: &mut view::T) {
+  let units_per_pixel = 2.0 / rndr.window_size.y as f32;
+  let aspect = rndr.window_size.x as f32 / rndr.window_size.y as f32;
+  let size = rndr.glyphs.size(&rndr.window_size);
+  let columns = rndr.debug_stats.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+  let origin =
+    cgmath::Point2::new(
+      aspect - HUD_MARGIN * units_per_pixel - columns as f32 * size.x,
+      1.0 - HUD_MARGIN * units_per_pixel - size.y,
+    );
+  rndr.glyphs.draw_text(
+    &mut rndr.gl,
+    &mut rndr.shaders.texture_shader,
+    &rndr.misc_texture_unit,
+    origin,
+    size.y,
+    &rndr.debug_stats,
+  );
+}
+
 fn draw_backdrop(
   rndr: &mut view::T,
 ) {
@@ -35,23 +98,9 @@ fn draw_backdrop(
     gl::BindVertexArray(rndr.empty_gl_array.gl_id);
   }
 
-  set_sun(&mut rndr.shaders.sky.shader, &mut rndr.gl, &rndr.sun);
-  set_eye_position(&mut rndr.shaders.sky.shader, &rndr.camera);
+  set_sky(&mut rndr.shaders.sky.shader, &mut rndr.gl, &rndr.sky);
 
   unsafe {
-    let time_ms_uniform = rndr.shaders.sky.shader.get_uniform_location("time_ms");
-    gl::Uniform1f(time_ms_uniform, (time::precise_time_ns() / 1_000_000) as f32);
-
-    let projection_uniform = rndr.shaders.sky.shader.get_uniform_location("projection_matrix");
-    let projection_matrix = rndr.camera.projection_matrix();
-    let ptr = &projection_matrix as *const _ as *const _;
-    gl::UniformMatrix4fv(projection_uniform, 1, 0, ptr);
-
-    let window_size_uniform = rndr.shaders.sky.shader.get_uniform_location("window_size");
-    let window_size = cgmath::Vector2::new(rndr.window_size.x as f32, rndr.window_size.y as f32);
-    let ptr = &window_size as *const _ as *const _;
-    gl::Uniform2fv(window_size_uniform, 1, ptr);
-
     gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
 
     gl::Clear(gl::DEPTH_BUFFER_BIT);
@@ -63,14 +112,19 @@ fn draw_grass_billboards(
 ) {
   rndr.shaders.grass_billboard.shader.use_shader(&mut rndr.gl);
   unsafe {
-    let time_ms_uniform = rndr.shaders.grass_billboard.shader.get_uniform_location("time_ms");
-    gl::Uniform1f(time_ms_uniform, (time::precise_time_ns() / 1_000_000) as f32);
-  }
-  set_ambient_light(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.sun);
-  set_camera(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.camera);
-  set_clip(&mut rndr.shaders.grass_billboard.shader, rndr.near_clip, rndr.far_clip);
-  set_eye_position(&mut rndr.shaders.grass_billboard.shader, &rndr.camera);
-  set_sun(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.sun);
+    let wind_uniform = rndr.shaders.grass_billboard.shader.get_uniform_location("wind");
+    gl::Uniform2f(wind_uniform, rndr.wind.x, rndr.wind.y);
+  }
+  set_fog(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.fog);
+  set_season(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.sky);
+  set_lights(
+    &mut rndr.shaders.grass_billboard.shader,
+    &mut rndr.gl,
+    &rndr.point_lights,
+    &rndr.clusters,
+    &rndr.camera,
+    &rndr.window_size,
+  );
   let alpha_threshold_uniform =
     rndr.shaders.grass_billboard.shader.get_uniform_location("alpha_threshold");
   unsafe {
@@ -79,41 +133,308 @@ fn draw_grass_billboards(
     gl::ActiveTexture(rndr.misc_texture_unit.gl_id());
     gl::BindTexture(gl::TEXTURE_2D, rndr.grass_texture.handle.gl_id);
   }
+  rndr.grass_buffers.spring_back(&mut rndr.gl, time::precise_time_ns());
   rndr.grass_buffers.draw(&mut rndr.gl);
 }
 
+fn draw_shadows(
+  rndr: &mut view::T,
+) {
+  let aspect = rndr.window_size.x as f32 / rndr.window_size.y as f32;
+  rndr.shadow.update_cascades(&rndr.camera, &rndr.sky.sun(), rndr.fov, aspect, rndr.near_clip);
+  if rndr.shadow.cascade_count() == 0 {
+    return
+  }
+
+  rndr.shadow.begin();
+  for i in 0 .. rndr.shadow.cascade_count() {
+    rndr.shadow.bind_cascade(&mut rndr.shaders.terrain_shadow.shader, &mut rndr.gl, i);
+    rndr.terrain_buffers.draw(&mut rndr.gl);
+  }
+  rndr.shadow.end(&rndr.window_size);
+}
+
+fn draw_water(
+  rndr: &mut view::T,
+) {
+  rndr.shaders.water.shader.use_shader(&mut rndr.gl);
+  set_fog(&mut rndr.shaders.water.shader, &mut rndr.gl, &rndr.fog);
+  let cull_face;
+  unsafe {
+    cull_face = gl::IsEnabled(gl::CULL_FACE);
+    // Water is visible from below, and shouldn't hide anything behind it.
+    gl::Disable(gl::CULL_FACE);
+    gl::DepthMask(gl::FALSE);
+  }
+  rndr.water_buffers.draw(&mut rndr.gl);
+  unsafe {
+    gl::DepthMask(gl::TRUE);
+    if cull_face == gl::TRUE {
+      gl::Enable(gl::CULL_FACE);
+    }
+  }
+}
+
+fn draw_particles(
+  rndr: &mut view::T,
+) {
+  rndr.shaders.particle.shader.use_shader(&mut rndr.gl);
+  unsafe {
+    gl::Disable(gl::CULL_FACE);
+    // Particles are see-through, so they shouldn't hide each other.
+    gl::DepthMask(gl::FALSE);
+  }
+  rndr.particles.draw(&mut rndr.gl);
+  unsafe {
+    gl::DepthMask(gl::TRUE);
+  }
+}
+
+fn draw_beacons(
+  rndr: &mut view::T,
+) {
+  rndr.waypoints.update(&mut rndr.gl);
+  rndr.shaders.beacon.shader.use_shader(&mut rndr.gl);
+  unsafe {
+    gl::Disable(gl::CULL_FACE);
+    // Beacons are see-through, and shouldn't hide what's behind them.
+    gl::DepthMask(gl::FALSE);
+  }
+  rndr.waypoints.draw_beacons(&mut rndr.gl);
+  unsafe {
+    gl::DepthMask(gl::TRUE);
+  }
+}
+
+/// Outline the voxel under the crosshair, and the selected region.
+fn draw_target(
+  rndr: &mut view::T,
+) {
+  if let Some((low, high)) = rndr.selection.region() {
+    let low = cgmath::Point3::new(low.x as f32, low.y as f32, low.z as f32);
+    let high = cgmath::Point3::new(high.x as f32, high.y as f32, high.z as f32);
+    draw_outline(rndr, &low, &high, [1.0, 1.0, 1.0, 0.8]);
+  }
+  if let Some(bounds) = rndr.target {
+    let (low, high) = bounds.corners();
+    draw_outline(rndr, &low, &high, [0.0, 0.0, 0.0, 0.6]);
+  }
+}
+
+/// Outline a box in the world.
+fn draw_outline(
+  rndr  : &mut view::T,
+  low   : &cgmath::Point3<f32>,
+  high  : &cgmath::Point3<f32>,
+  color : [f32; 4],
+) {
+  // Push the outline out a little, so it isn't hidden by the surface inside it.
+  let margin = 0.01;
+
+  rndr.shaders.outline.shader.use_shader(&mut rndr.gl);
+  unsafe {
+    let box_min = rndr.shaders.outline.shader.get_uniform_location("box_min");
+    gl::Uniform3f(box_min, low.x - margin, low.y - margin, low.z - margin);
+    let box_max = rndr.shaders.outline.shader.get_uniform_location("box_max");
+    gl::Uniform3f(box_max, high.x + margin, high.y + margin, high.z + margin);
+    let uniform = rndr.shaders.outline.shader.get_uniform_location("color");
+    gl::Uniform4f(uniform, color[0], color[1], color[2], color[3]);
+
+    gl::BindVertexArray(rndr.empty_gl_array.gl_id);
+    gl::DrawArrays(gl::LINES, 0, 24);
+  }
+}
+
+/// Draw the debug boxes and lines, without lighting or fog.
+fn draw_debug_lines(
+  rndr: &mut view::T,
+) {
+  rndr.shaders.debug_lines.shader.use_shader(&mut rndr.gl);
+  rndr.debug_lines.draw(&mut rndr.gl);
+}
+
 #[allow(missing_docs)]
 pub fn render(
   rndr: &mut view::T,
 ) {
+  unsafe {
+    gl::Enable(gl::CULL_FACE);
+  }
+  rndr.gpu_timings.begin_frame();
+
+  rndr.shaders.blocks.update(
+    &rndr.camera,
+    rndr.near_clip,
+    rndr.far_clip,
+    &rndr.sky.sun(),
+    &rndr.window_size,
+    time::precise_time_ns(),
+  );
+
+  draw_shadows(rndr);
+
+  rndr.post_process.begin();
   rndr.gl.clear_buffer();
 
+  rndr.gpu_timings.begin(Pass::Backdrop);
   draw_backdrop(rndr);
+  rndr.gpu_timings.end(Pass::Backdrop);
 
-  unsafe {
-    gl::Enable(gl::CULL_FACE);
+  if let Some(ref mut clusters) = rndr.clusters {
+    clusters.update(&mut rndr.gl, &rndr.camera, rndr.near_clip, rndr.far_clip, &rndr.point_lights);
   }
 
   // draw the world
+  rndr.gpu_timings.begin(Pass::Terrain);
   rndr.shaders.terrain_shader.shader.use_shader(&mut rndr.gl);
-  set_ambient_light(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
-  set_camera(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.camera);
-  set_clip(&mut rndr.shaders.terrain_shader.shader, rndr.near_clip, rndr.far_clip);
-  set_eye_position(&mut rndr.shaders.terrain_shader.shader, &rndr.camera);
-  set_sun(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
-  rndr.terrain_buffers.draw(&mut rndr.gl);
+  set_fog(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.fog);
+  set_season(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sky);
+  set_lights(
+    &mut rndr.shaders.terrain_shader.shader,
+    &mut rndr.gl,
+    &rndr.point_lights,
+    &rndr.clusters,
+    &rndr.camera,
+    &rndr.window_size,
+  );
+  set_shadows(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.shadow);
+  unsafe {
+    let wetness_uniform = rndr.shaders.terrain_shader.shader.get_uniform_location("wetness");
+    gl::Uniform1f(wetness_uniform, rndr.weather.wetness());
+  }
+  if rndr.clusters.is_some() {
+    // Fill the depth buffer first, so the lights are only looped over once per pixel.
+    rndr.shaders.terrain_shader.shader.use_shader(&mut rndr.gl);
+    set_depth_only(&mut rndr.shaders.terrain_shader.shader, true);
+    unsafe {
+      gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+    }
+    rndr.terrain_buffers.draw_unoccluded(&mut rndr.gl);
+    set_depth_only(&mut rndr.shaders.terrain_shader.shader, false);
+    unsafe {
+      gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+      gl::DepthFunc(gl::LEQUAL);
+      gl::DepthMask(gl::FALSE);
+    }
+    rndr.terrain_buffers.draw_unoccluded(&mut rndr.gl);
+    unsafe {
+      gl::DepthFunc(gl::LESS);
+      gl::DepthMask(gl::TRUE);
+    }
+  } else {
+    rndr.terrain_buffers.draw_unoccluded(&mut rndr.gl);
+  }
+  rndr.gpu_timings.end(Pass::Terrain);
 
+  // Test terrain against the depth buffer before anything else is drawn into it.
+  rndr.terrain_buffers.test_occlusion(&mut rndr.gl, &mut rndr.shaders.occlusion_box.shader, &rndr.camera.position);
+
+  rndr.gpu_timings.begin(Pass::Mobs);
   rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
-  set_camera(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.camera);
-  set_clip(&mut rndr.shaders.mob_shader.shader, rndr.near_clip, rndr.far_clip);
-  rndr.mob_buffers.draw(&mut rndr.gl);
-  rndr.player_buffers.draw(&mut rndr.gl);
+  set_fog(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.fog);
+  set_shadows(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.shadow);
+  rndr.item_buffers.draw(&mut rndr.gl);
+
+  rndr.shaders.entity.shader.use_shader(&mut rndr.gl);
+  set_fog(&mut rndr.shaders.entity.shader, &mut rndr.gl, &rndr.fog);
+  set_shadows(&mut rndr.shaders.entity.shader, &mut rndr.gl, &rndr.shadow);
+  set_lights(
+    &mut rndr.shaders.entity.shader,
+    &mut rndr.gl,
+    &rndr.point_lights,
+    &rndr.clusters,
+    &rndr.camera,
+    &rndr.window_size,
+  );
+  rndr.mob_buffers.draw(&mut rndr.gl, &mut rndr.shaders.entity.shader);
+  rndr.player_buffers.draw(&mut rndr.gl, &mut rndr.shaders.entity.shader);
+  rndr.gpu_timings.end(Pass::Mobs);
 
+  draw_target(rndr);
+  if rndr.show_debug {
+    draw_debug_lines(rndr);
+  }
+  rndr.gpu_timings.begin(Pass::Grass);
   draw_grass_billboards(rndr);
+  rndr.gpu_timings.end(Pass::Grass);
+  draw_particles(rndr);
+  draw_beacons(rndr);
+  draw_water(rndr);
+
+  rndr.post_process.finish(
+    &mut rndr.gl,
+    &mut rndr.shaders.post_process,
+    rndr.empty_gl_array.gl_id,
+    time::precise_time_ns(),
+  );
 
   if rndr.show_hud {
+    rndr.gpu_timings.begin(Pass::Hud);
+    rndr.nameplates.draw(
+      &mut rndr.gl,
+      &mut rndr.shaders.texture_shader,
+      &rndr.glyphs,
+      &rndr.misc_texture_unit,
+      &rndr.camera,
+      &rndr.window_size,
+    );
+
     rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
     rndr.hud_triangles.bind(&mut rndr.gl);
     rndr.hud_triangles.draw(&mut rndr.gl);
+
+    let forward = rndr.camera.forward();
+    rndr.minimap.draw(
+      &mut rndr.gl,
+      &mut rndr.shaders.texture_shader,
+      &mut rndr.shaders.hud_color_shader,
+      &rndr.misc_texture_unit,
+      &rndr.eye,
+      &forward,
+      &rndr.window_size,
+    );
+    rndr.waypoints.draw_compass(
+      &mut rndr.gl,
+      &mut rndr.shaders.texture_shader,
+      &mut rndr.shaders.hud_color_shader,
+      &rndr.glyphs,
+      &rndr.misc_texture_unit,
+      &rndr.eye,
+      &forward,
+      &rndr.window_size,
+    );
+
+    draw_stats(rndr);
+    rndr.hotbar.draw_labels(
+      &mut rndr.gl,
+      &mut rndr.shaders.texture_shader,
+      &rndr.glyphs,
+      &rndr.misc_texture_unit,
+      &rndr.window_size,
+    );
+    rndr.chat.draw(
+      &mut rndr.gl,
+      &mut rndr.shaders.texture_shader,
+      &rndr.fonts,
+      &rndr.misc_texture_unit,
+      &rndr.window_size,
+    );
+    if let view::InputMode::Crafting = rndr.input_mode {
+      rndr.crafting.draw(
+        &mut rndr.gl,
+        &mut rndr.shaders.texture_shader,
+        &rndr.glyphs,
+        &rndr.hotbar,
+        &rndr.misc_texture_unit,
+        &rndr.window_size,
+      );
+    }
+    rndr.gpu_timings.end(Pass::Hud);
   }
+
+  if rndr.show_debug {
+    draw_debug(rndr);
+  }
+
+  rndr.terrain_buffers.end_frame(&mut rndr.gl);
 }
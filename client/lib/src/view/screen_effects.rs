@@ -0,0 +1,80 @@
+//! Full-screen effects that say something about the player: a tint, vignette and wobble while the camera's
+//! underwater, and a red flash when the player's hurt. These are drawn as a pass in `post_process`.
+
+use gl;
+use gl::types::*;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+/// How long the damage flash takes to fade out, in ns.
+const DAMAGE_FLASH_NS: u64 = 400_000_000;
+
+#[allow(missing_docs)]
+pub struct T {
+  underwater : bool,
+  /// When the player was last hurt, in ns.
+  hurt_at    : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    underwater : false,
+    hurt_at    : None,
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn set_underwater(&mut self, underwater: bool) {
+    self.underwater = underwater;
+  }
+
+  /// Start flashing the screen red, as of `now`, in ns.
+  pub fn flash_damage(&mut self, now: u64) {
+    self.hurt_at = Some(now);
+  }
+
+  /// How strongly the screen flashes red at `now`, in [0, 1]. It fades out over `DAMAGE_FLASH_NS`.
+  pub fn damage(&self, now: u64) -> f32 {
+    match self.hurt_at {
+      None => 0.0,
+      Some(hurt_at) => {
+        let elapsed = now.saturating_sub(hurt_at);
+        if elapsed >= DAMAGE_FLASH_NS {
+          0.0
+        } else {
+          1.0 - elapsed as f32 / DAMAGE_FLASH_NS as f32
+        }
+      },
+    }
+  }
+
+  /// Whether there's anything to draw at `now`.
+  pub fn is_active(&self, now: u64) -> bool {
+    self.underwater || self.damage(now) > 0.0
+  }
+
+  /// Set the effects' uniforms in the screen effects shader.
+  pub fn set_uniforms(&self, shader: &mut Shader, gl: &mut GLContext, now: u64) {
+    let underwater = shader.get_uniform_location("underwater");
+    let damage = shader.get_uniform_location("damage");
+    shader.use_shader(gl);
+    unsafe {
+      gl::Uniform1i(underwater, self.underwater as GLint);
+      gl::Uniform1f(damage, self.damage(now));
+    }
+  }
+}
+
+#[test]
+fn damage_flashes_fade_out() {
+  let mut effects = new();
+  assert!(!effects.is_active(0));
+  effects.flash_damage(1_000);
+  assert_eq!(effects.damage(1_000), 1.0);
+  assert_eq!(effects.damage(1_000 + DAMAGE_FLASH_NS / 2), 0.5);
+  assert!(!effects.is_active(1_000 + DAMAGE_FLASH_NS));
+  effects.set_underwater(true);
+  assert!(effects.is_active(1_000 + DAMAGE_FLASH_NS));
+}
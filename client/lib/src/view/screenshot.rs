@@ -0,0 +1,103 @@
+//! Save what's on screen to PNG files, once or at intervals for timelapses.
+
+use cgmath::Vector2;
+use gl;
+use image;
+use std;
+use time;
+
+use common::interval_timer::IntervalTimer;
+
+/// Where screenshots are saved, relative to the working directory.
+pub const DIRECTORY: &'static str = "screenshots";
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Whether to save the next frame.
+  requested : bool,
+  /// When the next timelapse frame is due, if a timelapse is running.
+  timelapse : Option<IntervalTimer>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    requested : false,
+    timelapse : None,
+  }
+}
+
+impl T {
+  /// Save the next frame that's drawn.
+  pub fn request(&mut self) {
+    self.requested = true;
+  }
+
+  /// Start saving a frame every `interval_ns`, or stop if that's already happening.
+  pub fn toggle_timelapse(&mut self, interval_ns: u64, now: u64) {
+    self.timelapse =
+      match self.timelapse {
+        None => Some(IntervalTimer::new(interval_ns, now)),
+        Some(_) => None,
+      };
+    info!("Timelapse {}", if self.timelapse.is_some() { "started" } else { "stopped" });
+  }
+
+  /// Save the frame that was just drawn, if one's due. This has to happen before the buffers are swapped.
+  pub fn after_render(&mut self, window_size: &Vector2<i32>, now: u64) {
+    let timelapse_due = self.timelapse.as_mut().map_or(false, |timer| timer.update(now) > 0);
+    if !self.requested && !timelapse_due {
+      return
+    }
+    self.requested = false;
+
+    let width = window_size.x as u32;
+    let height = window_size.y as u32;
+    let pixels = read_pixels(width, height);
+    let path = format!("{}/{}.png", DIRECTORY, timestamp());
+
+    // Encoding takes long enough to drop frames, so do it elsewhere.
+    std::thread::spawn(move || {
+      let saved =
+        std::fs::create_dir_all(DIRECTORY)
+        .and_then(|()| image::save_buffer(&path, &pixels, width, height, image::ColorType::RGB(8)));
+      match saved {
+        Ok(()) => info!("Saved screenshot {}", path),
+        Err(err) => warn!("Couldn't save screenshot {}: {}", path, err),
+      }
+    });
+  }
+}
+
+/// Read the default framebuffer's back buffer, as rows of RGB pixels from the top down.
+fn read_pixels(width: u32, height: u32) -> Vec<u8> {
+  let row_len = 3 * width as usize;
+  let mut pixels = vec!(0; row_len * height as usize);
+  unsafe {
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    gl::ReadBuffer(gl::BACK);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+      0, 0, width as i32, height as i32,
+      gl::RGB, gl::UNSIGNED_BYTE,
+      pixels.as_mut_ptr() as *mut _,
+    );
+  }
+
+  // GL rows go from the bottom up.
+  let mut flipped = Vec::with_capacity(pixels.len());
+  for row in pixels.chunks(row_len).rev() {
+    flipped.extend_from_slice(row);
+  }
+  flipped
+}
+
+/// A file name for something saved now, e.g. "2017-06-04_13-37-00.123".
+fn timestamp() -> String {
+  let now = time::now();
+  format!(
+    "{}.{:03}",
+    time::strftime("%Y-%m-%d_%H-%M-%S", &now).unwrap(),
+    now.tm_nsec / 1_000_000,
+  )
+}
@@ -0,0 +1,65 @@
+//! The region of terrain picked for copying, between two corner voxels.
+
+use cgmath::{Point3, Vector3};
+
+#[allow(missing_docs)]
+pub struct T {
+  corners : [Option<Point3<i32>>; 2],
+  /// Which corner gets marked next.
+  next    : usize,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    corners : [None, None],
+    next    : 0,
+  }
+}
+
+impl T {
+  /// Put one of the corners at a voxel, taking turns between them.
+  pub fn mark(&mut self, voxel: Point3<i32>) {
+    self.corners[self.next] = Some(voxel);
+    self.next = 1 - self.next;
+  }
+
+  /// The selected voxels, from the low corner up to (but not including) the high one, once both corners are marked.
+  pub fn region(&self) -> Option<(Point3<i32>, Point3<i32>)> {
+    match (self.corners[0], self.corners[1]) {
+      (Some(a), Some(b)) => {
+        let low = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let high = Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)) + Vector3::new(1, 1, 1);
+        Some((low, high))
+      },
+      _ => None,
+    }
+  }
+
+  /// A description for the HUD, if any corners are marked.
+  pub fn label(&self) -> Option<String> {
+    match self.region() {
+      Some((low, high)) => {
+        let size = high - low;
+        Some(format!("Selected {}x{}x{}", size.x, size.y, size.z))
+      },
+      None if self.corners.iter().any(|corner| corner.is_some()) => Some(String::from("Selected one corner")),
+      None => None,
+    }
+  }
+}
+
+#[test]
+fn corners_take_turns() {
+  let mut selection = new();
+  assert_eq!(selection.label(), None);
+  selection.mark(Point3::new(4, 0, -2));
+  assert_eq!(selection.region(), None);
+  assert_eq!(selection.label(), Some(String::from("Selected one corner")));
+  selection.mark(Point3::new(1, 2, 3));
+  assert_eq!(selection.region(), Some((Point3::new(1, 0, -2), Point3::new(5, 3, 4))));
+  assert_eq!(selection.label(), Some(String::from("Selected 4x3x6")));
+  // The first corner moves next.
+  selection.mark(Point3::new(1, 2, 3));
+  assert_eq!(selection.region(), Some((Point3::new(1, 2, 3), Point3::new(2, 3, 4))));
+}
@@ -0,0 +1,19 @@
+//! Draw waypoint beacons as columns of light, turned to face the camera.
+
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+use view::shaders;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  #[allow(missing_docs)]
+  pub shader: Shader<'a>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(gl: &'b GLContext) -> T<'a> where 'a: 'b {
+  T {
+    shader: shaders::shader_from_prefix(gl, "beacon")
+  }
+}
@@ -0,0 +1,252 @@
+//! Uniforms that every shader shares, like the camera and the sun, kept in uniform buffers. They're uploaded once per
+//! frame, instead of being looked up and set in each shader that uses them.
+//!
+//! Shaders declare the blocks by including `camera.glsl`, `sun.glsl` and `window.glsl`. When a shader's loaded, the
+//! blocks it declares are checked against the layouts here, so a mismatch is caught then instead of drawing garbage.
+
+use cgmath::{Matrix4, Point3, Vector2, Vector3};
+use gl;
+use gl::types::*;
+use std;
+
+use view::camera;
+use view::light;
+
+/// The camera the world is drawn from.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Camera {
+  projection_matrix : Matrix4<f32>,
+  eye_position      : Point3<f32>,
+  near_clip         : f32,
+  far_clip          : f32,
+  padding           : [f32; 3],
+}
+
+/// Direct and ambient light from the sun. Each vec3 takes up 16 bytes.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Sun {
+  direction : Vector3<f32>,
+  padding0  : f32,
+  intensity : Vector3<f32>,
+  padding1  : f32,
+  ambient   : Vector3<f32>,
+  padding2  : f32,
+}
+
+/// The window being drawn into, and when.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Window {
+  window_size : Vector2<f32>,
+  time_ms     : f32,
+  padding     : f32,
+}
+
+/// A block as GLSL declares it: its name, the binding point it's read from, its size in bytes, and the name, type
+/// and byte offset of each member, in std140 layout.
+struct Layout {
+  name    : &'static str,
+  binding : GLuint,
+  size    : usize,
+  members : &'static [(&'static str, GLenum, usize)],
+}
+
+const CAMERA: Layout =
+  Layout {
+    name    : "Camera",
+    binding : 0,
+    size    : 96,
+    members : &[
+      ("projection_matrix", gl::FLOAT_MAT4, 0),
+      ("eye_position", gl::FLOAT_VEC3, 64),
+      ("near_clip", gl::FLOAT, 76),
+      ("far_clip", gl::FLOAT, 80),
+    ],
+  };
+
+const SUN: Layout =
+  Layout {
+    name    : "Sun",
+    binding : 1,
+    size    : 48,
+    members : &[
+      ("Sun.direction", gl::FLOAT_VEC3, 0),
+      ("Sun.intensity", gl::FLOAT_VEC3, 16),
+      ("Sun.ambient", gl::FLOAT_VEC3, 32),
+    ],
+  };
+
+const WINDOW: Layout =
+  Layout {
+    name    : "Window",
+    binding : 2,
+    size    : 16,
+    members : &[
+      ("window_size", gl::FLOAT_VEC2, 0),
+      ("time_ms", gl::FLOAT, 8),
+    ],
+  };
+
+const LAYOUTS: [&'static Layout; 3] = [&CAMERA, &SUN, &WINDOW];
+
+/// Point a program's blocks at their binding points, after checking they're laid out as expected. Blocks the
+/// program doesn't declare are skipped.
+pub fn bind(program: GLuint) -> Result<(), String> {
+  for layout in LAYOUTS.iter() {
+    unsafe {
+      let name = std::ffi::CString::new(layout.name).unwrap();
+      let index = gl::GetUniformBlockIndex(program, name.as_ptr());
+      if index == gl::INVALID_INDEX {
+        continue
+      }
+
+      let mut size = 0;
+      gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut size);
+      if size as usize > layout.size {
+        return Err(format!("The {} block is {} bytes, but should be {}", layout.name, size, layout.size))
+      }
+
+      for &(member, expected_type, expected_offset) in layout.members {
+        let member_name = std::ffi::CString::new(member).unwrap();
+        let mut member_index = gl::INVALID_INDEX;
+        gl::GetUniformIndices(program, 1, &member_name.as_ptr(), &mut member_index);
+        if member_index == gl::INVALID_INDEX {
+          return Err(format!("The {} block is missing {}", layout.name, member))
+        }
+        let (mut member_type, mut offset) = (0, 0);
+        gl::GetActiveUniformsiv(program, 1, &member_index, gl::UNIFORM_TYPE, &mut member_type);
+        gl::GetActiveUniformsiv(program, 1, &member_index, gl::UNIFORM_OFFSET, &mut offset);
+        if member_type as GLenum != expected_type || offset as usize != expected_offset {
+          return Err(
+            format!(
+              "{} in the {} block is type 0x{:x} at byte {}, but should be type 0x{:x} at byte {}",
+              member, layout.name, member_type, offset, expected_type, expected_offset,
+            )
+          )
+        }
+      }
+
+      gl::UniformBlockBinding(program, index, layout.binding);
+    }
+  }
+  Ok(())
+}
+
+/// The uniform buffers behind the shared blocks.
+pub struct T {
+  camera : GLuint,
+  sun    : GLuint,
+  window : GLuint,
+}
+
+fn buffer(layout: &Layout) -> GLuint {
+  let mut buffer = 0;
+  unsafe {
+    gl::GenBuffers(1, &mut buffer);
+    gl::BindBuffer(gl::UNIFORM_BUFFER, buffer);
+    gl::BufferData(gl::UNIFORM_BUFFER, layout.size as GLsizeiptr, std::ptr::null(), gl::DYNAMIC_DRAW);
+    gl::BindBufferBase(gl::UNIFORM_BUFFER, layout.binding, buffer);
+  }
+  buffer
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    camera : buffer(&CAMERA),
+    sun    : buffer(&SUN),
+    window : buffer(&WINDOW),
+  }
+}
+
+fn upload<Block>(buffer: GLuint, block: &Block) {
+  unsafe {
+    gl::BindBuffer(gl::UNIFORM_BUFFER, buffer);
+    gl::BufferSubData(
+      gl::UNIFORM_BUFFER,
+      0,
+      std::mem::size_of::<Block>() as GLsizeiptr,
+      block as *const Block as *const _,
+    );
+  }
+}
+
+impl T {
+  /// Upload this frame's values.
+  pub fn update(
+    &self,
+    camera      : &camera::T,
+    near_clip   : f32,
+    far_clip    : f32,
+    sun         : &light::Sun,
+    window_size : &Vector2<i32>,
+    now         : u64,
+  ) {
+    upload(
+      self.camera,
+      &Camera {
+        projection_matrix : camera.projection_matrix(),
+        eye_position      : camera.position,
+        near_clip         : near_clip,
+        far_clip          : far_clip,
+        padding           : [0.0; 3],
+      },
+    );
+
+    let intensity = sun.intensity();
+    let ambient = sun.ambient_intensity();
+    upload(
+      self.sun,
+      &Sun {
+        direction : sun.direction(),
+        padding0  : 0.0,
+        intensity : Vector3::new(intensity.r, intensity.g, intensity.b),
+        padding1  : 0.0,
+        ambient   : Vector3::new(ambient.r, ambient.g, ambient.b),
+        padding2  : 0.0,
+      },
+    );
+
+    upload(
+      self.window,
+      &Window {
+        window_size : Vector2::new(window_size.x as f32, window_size.y as f32),
+        time_ms     : (now / 1_000_000) as f32,
+        padding     : 0.0,
+      },
+    );
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteBuffers(1, &self.camera);
+      gl::DeleteBuffers(1, &self.sun);
+      gl::DeleteBuffers(1, &self.window);
+    }
+  }
+}
+
+#[test]
+fn blocks_match_their_layouts() {
+  assert_eq!(std::mem::size_of::<Camera>(), CAMERA.size);
+  assert_eq!(std::mem::size_of::<Sun>(), SUN.size);
+  assert_eq!(std::mem::size_of::<Window>(), WINDOW.size);
+
+  let camera: Camera = unsafe { std::mem::zeroed() };
+  let base = &camera as *const _ as usize;
+  assert_eq!(&camera.eye_position as *const _ as usize - base, 64);
+  assert_eq!(&camera.near_clip as *const _ as usize - base, 76);
+  assert_eq!(&camera.far_clip as *const _ as usize - base, 80);
+
+  let sun: Sun = unsafe { std::mem::zeroed() };
+  let base = &sun as *const _ as usize;
+  assert_eq!(&sun.intensity as *const _ as usize - base, 16);
+  assert_eq!(&sun.ambient as *const _ as usize - base, 32);
+
+  let window: Window = unsafe { std::mem::zeroed() };
+  assert_eq!(&window.time_ms as *const _ as usize - &window as *const _ as usize, 8);
+}
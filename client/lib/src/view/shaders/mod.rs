@@ -1,56 +1,79 @@
 //! This module contains the game's custom shader structs.
 
+pub mod beacon;
+pub mod blocks;
 pub mod color;
+pub mod debug_lines;
+pub mod entity;
 pub mod grass_billboard;
+pub mod mob;
+pub mod occlusion_box;
+pub mod outline;
+pub mod particle;
+pub mod post_process;
+pub mod reload;
 pub mod sky;
 pub mod terrain;
+pub mod terrain_shadow;
 pub mod texture;
+pub mod water;
 
 use cgmath;
 use cgmath::{Vector2};
 use gl;
+use gl::types::GLenum;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw;
 
 use view::camera;
 
-/// Load a shader from a filename prefix.
-/// e.g. shader_from_prefix("foo") will load a vertex shader from shaders/foo.vs.glsl and fragment shader
+/// Read the sources of a shader, by running them through m4.
+/// e.g. read_sources("foo") will read a vertex shader from shaders/foo.vs.glsl and fragment shader
 /// from shaders/foo.fs.glsl.
-pub fn shader_from_prefix<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str) -> yaglw::shader::Shader<'b> {
+pub fn read_sources(prefix: &'static str) -> Result<Vec<(GLenum, String)>, String> {
   let read_preprocessed_shader =
-    |name| {
-      String::from_utf8(
+    |name: String| {
+      let output =
         std::process::Command::new("m4")
-        .arg(name)
+        .arg(&name)
         .current_dir(std::path::Path::new("shaders/"))
         .output()
-        .unwrap()
-        .stdout
-      ).unwrap()
+        .map_err(|err| format!("Couldn't run m4 on {}: {}", name, err))?;
+      if !output.status.success() {
+        return Err(format!("m4 failed on {}: {}", name, String::from_utf8_lossy(&output.stderr)))
+      }
+      String::from_utf8(output.stdout).map_err(|err| format!("{} isn't UTF-8: {}", name, err))
     };
-  let vs = read_preprocessed_shader(format!("{}.vs.glsl", prefix));
-  let fs = read_preprocessed_shader(format!("{}.fs.glsl", prefix));
+  let vs = read_preprocessed_shader(format!("{}.vs.glsl", prefix))?;
+  let fs = read_preprocessed_shader(format!("{}.fs.glsl", prefix))?;
   debug!("loaded {} vertex shader:", prefix);
   debug!("{}", vs);
   debug!("loaded {} fragment shader:", prefix);
   debug!("{}", fs);
-  let components =
-    vec!(
-      (gl::VERTEX_SHADER, vs),
-      (gl::FRAGMENT_SHADER, fs),
-    );
+  Ok(vec!(
+    (gl::VERTEX_SHADER, vs),
+    (gl::FRAGMENT_SHADER, fs),
+  ))
+}
+
+/// Load a shader from a filename prefix, as in `read_sources`.
+pub fn shader_from_prefix<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str) -> yaglw::shader::Shader<'b> {
+  let components = read_sources(prefix).unwrap();
   yaglw::shader::Shader::new(gl, components.into_iter())
 }
 
 /// The game's custom shader structs.
 pub struct T<'a> {
   #[allow(missing_docs)]
-  pub mob_shader: self::color::T<'a>,
+  pub mob_shader: self::mob::T<'a>,
+  #[allow(missing_docs)]
+  pub entity: self::entity::T<'a>,
   #[allow(missing_docs)]
   pub terrain_shader: self::terrain::T<'a>,
   #[allow(missing_docs)]
+  pub terrain_shadow: self::terrain_shadow::T<'a>,
+  #[allow(missing_docs)]
   pub texture_shader: self::texture::T<'a>,
   #[allow(missing_docs)]
   pub grass_billboard: self::grass_billboard::T<'a>,
@@ -58,16 +81,43 @@ pub struct T<'a> {
   pub hud_color_shader: self::color::T<'a>,
   #[allow(missing_docs)]
   pub sky: self::sky::T<'a>,
+  #[allow(missing_docs)]
+  pub water: self::water::T<'a>,
+  #[allow(missing_docs)]
+  pub occlusion_box: self::occlusion_box::T<'a>,
+  #[allow(missing_docs)]
+  pub outline: self::outline::T<'a>,
+  #[allow(missing_docs)]
+  pub particle: self::particle::T<'a>,
+  #[allow(missing_docs)]
+  pub beacon: self::beacon::T<'a>,
+  #[allow(missing_docs)]
+  pub debug_lines: self::debug_lines::T<'a>,
+  #[allow(missing_docs)]
+  pub post_process: self::post_process::T<'a>,
+  /// The uniforms every shader shares.
+  pub blocks: self::blocks::T,
+  /// Watches the sources, to recompile them when they change.
+  sources: self::reload::Sources,
 }
 
 #[allow(missing_docs)]
 pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> where 'a: 'b {
   let terrain_shader       = self::terrain::new(gl);
-  let mob_shader           = self::color::new(gl);
+  let terrain_shadow       = self::terrain_shadow::new(gl);
+  let mob_shader           = self::mob::new(gl);
+  let entity               = self::entity::new(gl);
   let mut hud_color_shader = self::color::new(gl);
-  let texture_shader       = self::texture::new(gl);
+  let mut texture_shader   = self::texture::new(gl);
   let grass_billboard      = self::grass_billboard::new(gl);
   let sky                  = self::sky::new(gl);
+  let water                = self::water::new(gl);
+  let occlusion_box        = self::occlusion_box::new(gl);
+  let outline              = self::outline::new(gl);
+  let particle             = self::particle::new(gl);
+  let beacon               = self::beacon::new(gl);
+  let debug_lines          = self::debug_lines::new(gl);
+  let post_process         = self::post_process::new(gl);
 
   let hud_camera = {
     let mut c = camera::unit();
@@ -82,18 +132,89 @@ pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> wh
     gl,
     &hud_camera,
   );
+  camera::set_camera(
+    &mut texture_shader.shader,
+    gl,
+    &hud_camera,
+  );
+
+  let mut shaders =
+    T {
+      mob_shader: mob_shader,
+      entity: entity,
+      terrain_shader: terrain_shader,
+      terrain_shadow: terrain_shadow,
+      texture_shader: texture_shader,
+      grass_billboard: grass_billboard,
+      hud_color_shader: hud_color_shader,
+      sky: sky,
+      water: water,
+      occlusion_box: occlusion_box,
+      outline: outline,
+      particle: particle,
+      beacon: beacon,
+      debug_lines: debug_lines,
+      post_process: post_process,
+      blocks: self::blocks::new(),
+      sources: self::reload::watch(),
+    };
+
+  for (prefix, shader) in shaders.programs() {
+    if let Err(err) = self::blocks::bind(shader.handle.gl_id) {
+      panic!("The {} shader's uniform blocks are wrong: {}", prefix, err);
+    }
+  }
 
   match gl.get_error() {
     gl::NO_ERROR => {},
     err => warn!("OpenGL error 0x{:x}", err),
   }
 
-  T {
-    mob_shader: mob_shader,
-    terrain_shader: terrain_shader,
-    texture_shader: texture_shader,
-    grass_billboard: grass_billboard,
-    hud_color_shader: hud_color_shader,
-    sky: sky,
+  shaders
+}
+
+impl<'a> T<'a> {
+  /// Every program, with the prefix of the sources it's compiled from.
+  fn programs(&mut self) -> Vec<(&'static str, &mut yaglw::shader::Shader<'a>)> {
+    vec!(
+      ("mob", &mut self.mob_shader.shader),
+      ("entity", &mut self.entity.shader),
+      ("terrain", &mut self.terrain_shader.shader),
+      ("terrain_shadow", &mut self.terrain_shadow.shader),
+      ("texture", &mut self.texture_shader.shader),
+      ("grass_billboard", &mut self.grass_billboard.shader),
+      ("color", &mut self.hud_color_shader.shader),
+      ("sky", &mut self.sky.shader),
+      ("water", &mut self.water.shader),
+      ("occlusion_box", &mut self.occlusion_box.shader),
+      ("outline", &mut self.outline.shader),
+      ("particle", &mut self.particle.shader),
+      ("beacon", &mut self.beacon.shader),
+      ("debug_lines", &mut self.debug_lines.shader),
+      ("bright_pass", &mut self.post_process.bright_pass),
+      ("blur", &mut self.post_process.blur),
+      ("composite", &mut self.post_process.composite),
+      ("screen_effects", &mut self.post_process.effects),
+      ("fxaa", &mut self.post_process.fxaa),
+    )
+  }
+
+  /// Recompile the shaders if their sources have changed. Shaders that no longer compile are left as they were.
+  pub fn reload_changed(&mut self, gl: &mut GLContext, now: u64) {
+    if !self.sources.changed(now) {
+      return
+    }
+    info!("Shader sources changed; recompiling.");
+
+    for (prefix, shader) in self.programs() {
+      if let Err(err) = self::reload::reload(gl, prefix, shader) {
+        warn!("Couldn't recompile the {} shader, so it's unchanged: {}", prefix, err);
+      }
+    }
+
+    match gl.get_error() {
+      gl::NO_ERROR => {},
+      err => warn!("OpenGL error 0x{:x}", err),
+    }
   }
 }
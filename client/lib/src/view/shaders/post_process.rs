@@ -0,0 +1,31 @@
+//! Full-screen passes that turn the rendered scene into the final frame.
+
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+use view::shaders;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// Keep only what's bright enough to bloom.
+  pub bright_pass : Shader<'a>,
+  /// One direction of a separable gaussian blur.
+  pub blur        : Shader<'a>,
+  /// Add the bloom and tonemap.
+  pub composite   : Shader<'a>,
+  /// Underwater and damage effects.
+  pub effects     : Shader<'a>,
+  #[allow(missing_docs)]
+  pub fxaa        : Shader<'a>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(gl: &'b GLContext) -> T<'a> where 'a: 'b {
+  T {
+    bright_pass : shaders::shader_from_prefix(gl, "bright_pass"),
+    blur        : shaders::shader_from_prefix(gl, "blur"),
+    composite   : shaders::shader_from_prefix(gl, "composite"),
+    effects     : shaders::shader_from_prefix(gl, "screen_effects"),
+    fxaa        : shaders::shader_from_prefix(gl, "fxaa"),
+  }
+}
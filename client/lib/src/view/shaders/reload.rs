@@ -0,0 +1,216 @@
+//! Recompile shaders while the game runs, when their sources change. A shader that doesn't compile any more keeps
+//! running its old program, so a typo doesn't take the game down.
+//!
+//! The new program is given the old one's attribute locations, so vertex arrays set up for it still work, and a copy
+//! of the old one's uniforms, so uniforms that are only set once (like which texture unit to sample) aren't lost.
+//! Its uniform blocks are checked and bound like any newly-loaded shader's.
+//! Adding new vertex inputs still needs a restart.
+
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+use view::shaders;
+
+/// Where shader sources are read from.
+const DIRECTORY: &'static str = "shaders/";
+/// How often to look for changed sources, in nanoseconds.
+const CHECK_INTERVAL: u64 = 500_000_000;
+/// The longest uniform or attribute name copied across.
+const MAX_NAME_LENGTH: usize = 256;
+
+/// Watches the shader sources for changes.
+pub struct Sources {
+  /// When the most recently changed source was changed.
+  modified   : Option<std::time::SystemTime>,
+  next_check : u64,
+}
+
+#[allow(missing_docs)]
+pub fn watch() -> Sources {
+  Sources {
+    modified   : newest_source(),
+    next_check : 0,
+  }
+}
+
+/// When the most recently changed file in the shader directory was changed.
+fn newest_source() -> Option<std::time::SystemTime> {
+  std::fs::read_dir(DIRECTORY).ok()?
+  .filter_map(|entry| entry.ok())
+  .filter_map(|entry| entry.metadata().and_then(|metadata| metadata.modified()).ok())
+  .max()
+}
+
+impl Sources {
+  /// Whether any source has changed since the last time this returned true. Only looks every `CHECK_INTERVAL`.
+  pub fn changed(&mut self, now: u64) -> bool {
+    if now < self.next_check {
+      return false
+    }
+    self.next_check = now + CHECK_INTERVAL;
+
+    let modified = newest_source();
+    if modified == self.modified {
+      return false
+    }
+    self.modified = modified;
+    true
+  }
+}
+
+unsafe fn shader_log(shader: GLuint) -> String {
+  let mut length = 0;
+  gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut length);
+  let mut log = vec!(0u8; std::cmp::max(length, 1) as usize);
+  gl::GetShaderInfoLog(shader, log.len() as GLsizei, std::ptr::null_mut(), log.as_mut_ptr() as *mut GLchar);
+  String::from_utf8_lossy(&log).trim_end_matches('\0').to_owned()
+}
+
+unsafe fn program_log(program: GLuint) -> String {
+  let mut length = 0;
+  gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut length);
+  let mut log = vec!(0u8; std::cmp::max(length, 1) as usize);
+  gl::GetProgramInfoLog(program, log.len() as GLsizei, std::ptr::null_mut(), log.as_mut_ptr() as *mut GLchar);
+  String::from_utf8_lossy(&log).trim_end_matches('\0').to_owned()
+}
+
+unsafe fn link(program: GLuint) -> Result<(), String> {
+  gl::LinkProgram(program);
+  let mut status = gl::FALSE as GLint;
+  gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+  if status == gl::TRUE as GLint {
+    Ok(())
+  } else {
+    Err(program_log(program))
+  }
+}
+
+/// Compile and link some sources in a throwaway program, to find any errors before they'd panic in `Shader::new`.
+fn check(components: &[(GLenum, String)]) -> Result<(), String> {
+  let mut sources = Vec::new();
+  for &(kind, ref source) in components {
+    sources.push((kind, std::ffi::CString::new(source.as_bytes()).map_err(|err| err.to_string())?));
+  }
+
+  unsafe {
+    let program = gl::CreateProgram();
+    let mut shaders = Vec::new();
+    let mut result = Ok(());
+    for (kind, source) in sources {
+      let shader = gl::CreateShader(kind);
+      shaders.push(shader);
+      gl::ShaderSource(shader, 1, &source.as_ptr(), std::ptr::null());
+      gl::CompileShader(shader);
+      let mut status = gl::FALSE as GLint;
+      gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+      if status != gl::TRUE as GLint {
+        result = Err(shader_log(shader));
+        break
+      }
+      gl::AttachShader(program, shader);
+    }
+    if result.is_ok() {
+      result = link(program);
+    }
+    for shader in shaders {
+      gl::DeleteShader(shader);
+    }
+    gl::DeleteProgram(program);
+    result
+  }
+}
+
+fn name_of(bytes: &[u8], length: GLsizei) -> String {
+  String::from_utf8_lossy(&bytes[.. length as usize]).into_owned()
+}
+
+/// Give `to` the attribute locations `from` has, and link it again.
+unsafe fn copy_attribute_locations(from: GLuint, to: GLuint) -> Result<(), String> {
+  let mut count = 0;
+  gl::GetProgramiv(from, gl::ACTIVE_ATTRIBUTES, &mut count);
+  for i in 0 .. count as GLuint {
+    let mut name = [0u8; MAX_NAME_LENGTH];
+    let (mut length, mut size, mut kind) = (0, 0, 0);
+    gl::GetActiveAttrib(
+      from, i, name.len() as GLsizei, &mut length, &mut size, &mut kind, name.as_mut_ptr() as *mut GLchar,
+    );
+    let name = std::ffi::CString::new(name_of(&name, length)).unwrap();
+    let location = gl::GetAttribLocation(from, name.as_ptr());
+    if location >= 0 {
+      gl::BindAttribLocation(to, location as GLuint, name.as_ptr());
+    }
+  }
+  link(to)
+}
+
+/// Set every uniform in `to` that `from` also has to `from`'s value. `to` must be the current program.
+unsafe fn copy_uniforms(from: GLuint, to: GLuint) {
+  let mut count = 0;
+  gl::GetProgramiv(from, gl::ACTIVE_UNIFORMS, &mut count);
+  for i in 0 .. count as GLuint {
+    let mut name = [0u8; MAX_NAME_LENGTH];
+    let (mut length, mut size, mut kind) = (0, 0, 0);
+    gl::GetActiveUniform(
+      from, i, name.len() as GLsizei, &mut length, &mut size, &mut kind, name.as_mut_ptr() as *mut GLchar,
+    );
+    let name = name_of(&name, length);
+    // Arrays are listed once, as their first element.
+    let base = name.trim_end_matches("[0]");
+    for element in 0 .. size {
+      let name = if size > 1 { format!("{}[{}]", base, element) } else { name.clone() };
+      let name = std::ffi::CString::new(name).unwrap();
+      let (old, new) = (gl::GetUniformLocation(from, name.as_ptr()), gl::GetUniformLocation(to, name.as_ptr()));
+      // Uniforms in blocks don't have locations.
+      if old < 0 || new < 0 {
+        continue
+      }
+      let mut floats = [0.0f32; 16];
+      let mut ints = [0i32; 4];
+      match kind {
+        gl::FLOAT | gl::FLOAT_VEC2 | gl::FLOAT_VEC3 | gl::FLOAT_VEC4 | gl::FLOAT_MAT3 | gl::FLOAT_MAT4 =>
+          gl::GetUniformfv(from, old, floats.as_mut_ptr()),
+        _ => gl::GetUniformiv(from, old, ints.as_mut_ptr()),
+      }
+      match kind {
+        gl::FLOAT => gl::Uniform1fv(new, 1, floats.as_ptr()),
+        gl::FLOAT_VEC2 => gl::Uniform2fv(new, 1, floats.as_ptr()),
+        gl::FLOAT_VEC3 => gl::Uniform3fv(new, 1, floats.as_ptr()),
+        gl::FLOAT_VEC4 => gl::Uniform4fv(new, 1, floats.as_ptr()),
+        gl::FLOAT_MAT3 => gl::UniformMatrix3fv(new, 1, gl::FALSE, floats.as_ptr()),
+        gl::FLOAT_MAT4 => gl::UniformMatrix4fv(new, 1, gl::FALSE, floats.as_ptr()),
+        gl::INT | gl::BOOL => gl::Uniform1iv(new, 1, ints.as_ptr()),
+        gl::INT_VEC2 | gl::BOOL_VEC2 => gl::Uniform2iv(new, 1, ints.as_ptr()),
+        gl::INT_VEC3 | gl::BOOL_VEC3 => gl::Uniform3iv(new, 1, ints.as_ptr()),
+        gl::INT_VEC4 | gl::BOOL_VEC4 => gl::Uniform4iv(new, 1, ints.as_ptr()),
+        // Samplers are set as ints.
+        _ => gl::Uniform1iv(new, 1, ints.as_ptr()),
+      }
+    }
+  }
+}
+
+/// Compile a shader from its sources again, and swap it in for the old one. If it doesn't compile, the old one's
+/// left as it is.
+pub fn reload<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str, shader: &mut Shader<'b>) -> Result<(), String> {
+  let components = shaders::read_sources(prefix)?;
+  check(&components)?;
+
+  let new = Shader::new(gl, components.into_iter());
+  unsafe {
+    copy_attribute_locations(shader.handle.gl_id, new.handle.gl_id)?;
+    shaders::blocks::bind(new.handle.gl_id)?;
+
+    let mut current = 0;
+    gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut current);
+    gl::UseProgram(new.handle.gl_id);
+    copy_uniforms(shader.handle.gl_id, new.handle.gl_id);
+    // Leave the program that the GLContext thinks is in use in use.
+    gl::UseProgram(current as GLuint);
+  }
+
+  *shader = new;
+  Ok(())
+}
@@ -0,0 +1,294 @@
+//! Cascaded shadow maps for the sun.
+
+use cgmath;
+use cgmath::{Matrix4, Point3, Vector3, Vector4, EuclideanSpace, InnerSpace, SquareMatrix};
+use gl;
+use gl::types::*;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+use yaglw::texture::TextureUnit;
+
+use view::camera;
+use view::light;
+
+/// The most cascades any quality level uses. This must match the array sizes in `shadow.glsl`.
+pub const MAX_CASCADES: usize = 4;
+
+/// Shadows are only rendered this far from the camera.
+const SHADOW_DISTANCE: f32 = 256.0;
+/// How far outside a cascade's bounds to look for things that cast shadows into it.
+const CASTER_MARGIN: f32 = 128.0;
+/// Blend between logarithmic (1.0) and uniform (0.0) cascade splits.
+const SPLIT_LAMBDA: f32 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Quality {
+  Off,
+  Low,
+  Medium,
+  High,
+}
+
+impl Quality {
+  /// The number of cascades to split the view frustum into.
+  pub fn cascade_count(self) -> usize {
+    match self {
+      Quality::Off    => 0,
+      Quality::Low    => 2,
+      Quality::Medium => 3,
+      Quality::High   => MAX_CASCADES,
+    }
+  }
+
+  /// Width and height of each cascade's depth texture.
+  pub fn resolution(self) -> i32 {
+    match self {
+      Quality::Off    => 0,
+      Quality::Low    => 1024,
+      Quality::Medium => 2048,
+      Quality::High   => 2048,
+    }
+  }
+
+  /// The next quality level, wrapping around.
+  pub fn next(self) -> Quality {
+    match self {
+      Quality::Off    => Quality::Low,
+      Quality::Low    => Quality::Medium,
+      Quality::Medium => Quality::High,
+      Quality::High   => Quality::Off,
+    }
+  }
+}
+
+/// Shadow map render targets, and the transforms used to render and sample them.
+pub struct T {
+  quality       : Quality,
+  framebuffer   : GLuint,
+  depth_texture : GLuint,
+  texture_unit  : TextureUnit,
+
+  /// World-to-light-clip-space transforms, one per active cascade.
+  matrices     : Vec<Matrix4<GLfloat>>,
+  /// The far end of each active cascade, as a depth from the camera.
+  cascade_ends : Vec<GLfloat>,
+}
+
+#[allow(missing_docs)]
+pub fn new(quality: Quality, texture_unit: TextureUnit) -> T {
+  let mut framebuffer = 0;
+  let mut depth_texture = 0;
+  unsafe {
+    gl::GenFramebuffers(1, &mut framebuffer);
+    gl::GenTextures(1, &mut depth_texture);
+  }
+
+  let mut shadow =
+    T {
+      quality       : Quality::Off,
+      framebuffer   : framebuffer,
+      depth_texture : depth_texture,
+      texture_unit  : texture_unit,
+      matrices      : Vec::with_capacity(MAX_CASCADES),
+      cascade_ends  : Vec::with_capacity(MAX_CASCADES),
+    };
+  shadow.set_quality(quality);
+  shadow
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn quality(&self) -> Quality {
+    self.quality
+  }
+
+  /// Change the quality level, reallocating the depth textures.
+  pub fn set_quality(&mut self, quality: Quality) {
+    self.quality = quality;
+    self.matrices.clear();
+    self.cascade_ends.clear();
+
+    if quality == Quality::Off {
+      return
+    }
+
+    let resolution = quality.resolution();
+    let border = [1.0 as GLfloat; 4];
+    unsafe {
+      gl::ActiveTexture(self.texture_unit.gl_id());
+      gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.depth_texture);
+      gl::TexImage3D(
+        gl::TEXTURE_2D_ARRAY,
+        0,
+        gl::DEPTH_COMPONENT24 as GLint,
+        resolution,
+        resolution,
+        quality.cascade_count() as GLint,
+        0,
+        gl::DEPTH_COMPONENT,
+        gl::FLOAT,
+        std::ptr::null(),
+      );
+      gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+      gl::TexParameterfv(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+      gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+
+      gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+      gl::DrawBuffer(gl::NONE);
+      gl::ReadBuffer(gl::NONE);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+  }
+
+  /// The number of cascades that should be rendered this frame.
+  pub fn cascade_count(&self) -> usize {
+    self.matrices.len()
+  }
+
+  /// Fit the cascades to the camera's view frustum.
+  /// If the sun is below the horizon, no cascades are active.
+  pub fn update_cascades(
+    &mut self,
+    camera : &camera::T,
+    sun    : &light::Sun,
+    fov    : f32,
+    aspect : f32,
+    near   : f32,
+  ) {
+    self.matrices.clear();
+    self.cascade_ends.clear();
+
+    let count = self.quality.cascade_count();
+    let sun_direction = sun.direction();
+    if count == 0 || sun_direction.y <= 0.0 {
+      return
+    }
+
+    let view_to_world = (camera.rotation * camera.translation).invert().unwrap();
+    let tan_y = (fov / 2.0).tan();
+    let tan_x = tan_y * aspect;
+    let resolution = self.quality.resolution() as f32;
+
+    let mut cascade_near = near;
+    for i in 0 .. count {
+      let cascade_far = split_distance(i + 1, count, near, SHADOW_DISTANCE);
+
+      // Find a bounding sphere for this slice of the view frustum.
+      let mut corners = Vec::with_capacity(8);
+      for &d in &[cascade_near, cascade_far] {
+        for &(sx, sy) in &[(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+          let p = view_to_world * Vector4::new(sx * tan_x * d, sy * tan_y * d, -d, 1.0);
+          corners.push(Point3::new(p.x, p.y, p.z));
+        }
+      }
+      let center =
+        corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, c| sum + c.to_vec()) / corners.len() as f32;
+      let center = Point3::from_vec(center);
+      // Round up, so the projection doesn't change size as the camera rotates.
+      let radius =
+        corners.iter().map(|&c| (c - center).magnitude()).fold(0.0, f32::max).ceil();
+
+      let light_view =
+        Matrix4::look_at(
+          center + sun_direction * (radius + CASTER_MARGIN),
+          center,
+          Vector3::new(0.0, 0.0, 1.0),
+        );
+
+      // Snap the projection to whole texels, so shadow edges don't shimmer as the camera moves.
+      let texels_per_unit = resolution / (2.0 * radius);
+      let origin = light_view * Vector4::new(0.0, 0.0, 0.0, 1.0);
+      let snap =
+        Matrix4::from_translation(
+          Vector3::new(
+            (origin.x * texels_per_unit).round() / texels_per_unit - origin.x,
+            (origin.y * texels_per_unit).round() / texels_per_unit - origin.y,
+            0.0,
+          )
+        );
+
+      let projection =
+        cgmath::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + CASTER_MARGIN);
+
+      self.matrices.push(projection * snap * light_view);
+      self.cascade_ends.push(cascade_far);
+      cascade_near = cascade_far;
+    }
+  }
+
+  /// Start rendering into the shadow map's framebuffer.
+  pub fn begin(&self) {
+    let resolution = self.quality.resolution();
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+      gl::Viewport(0, 0, resolution, resolution);
+      // Render back faces, to keep lit surfaces from shadowing themselves.
+      gl::CullFace(gl::FRONT);
+    }
+  }
+
+  /// Direct rendering into cascade `i`, and set the transform in `shader`.
+  pub fn bind_cascade(&self, shader: &mut Shader, gl: &mut GLContext, i: usize) {
+    let light_matrix = shader.get_uniform_location("light_matrix");
+    shader.use_shader(gl);
+    unsafe {
+      gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.depth_texture, 0, i as GLint);
+      gl::Clear(gl::DEPTH_BUFFER_BIT);
+      let ptr = &self.matrices[i] as *const _ as *const _;
+      gl::UniformMatrix4fv(light_matrix, 1, 0, ptr);
+    }
+  }
+
+  /// Return to rendering into the window.
+  pub fn end(&self, window_size: &cgmath::Vector2<i32>) {
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+      gl::Viewport(0, 0, window_size.x, window_size.y);
+      gl::CullFace(gl::BACK);
+    }
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteFramebuffers(1, &self.framebuffer);
+      gl::DeleteTextures(1, &self.depth_texture);
+    }
+  }
+}
+
+/// The far end of the `i`th cascade.
+fn split_distance(i: usize, count: usize, near: f32, far: f32) -> f32 {
+  let f = i as f32 / count as f32;
+  let logarithmic = near * (far / near).powf(f);
+  let uniform = near + (far - near) * f;
+  SPLIT_LAMBDA * logarithmic + (1.0 - SPLIT_LAMBDA) * uniform
+}
+
+/// Sets the shadow-sampling uniforms in some shader (see `shadow.glsl`).
+pub fn set_shadows(shader: &mut Shader, gl: &mut GLContext, shadow: &T) {
+  let shadow_map = shader.get_uniform_location("shadow_map");
+  let cascade_count = shader.get_uniform_location("shadow_cascade_count");
+  let matrices = shader.get_uniform_location("shadow_matrices");
+  let cascade_ends = shader.get_uniform_location("shadow_cascade_ends");
+  shader.use_shader(gl);
+  unsafe {
+    gl::ActiveTexture(shadow.texture_unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_2D_ARRAY, shadow.depth_texture);
+    gl::Uniform1i(shadow_map, shadow.texture_unit.glsl_id as GLint);
+
+    let count = shadow.cascade_count() as GLint;
+    gl::Uniform1i(cascade_count, count);
+    if count > 0 {
+      gl::UniformMatrix4fv(matrices, count, 0, shadow.matrices.as_ptr() as *const _);
+      gl::Uniform1fv(cascade_ends, count, shadow.cascade_ends.as_ptr());
+    }
+  }
+}
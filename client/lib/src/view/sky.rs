@@ -0,0 +1,113 @@
+//! The sky: time of day and year, and the sun, moon, stars and seasonal changes that follow from them.
+
+use cgmath::Vector3;
+use common::color::Color3;
+use common::season;
+use gl;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::shader::Shader;
+
+use view::light;
+
+/// The season when plants are at their greenest.
+const MIDSUMMER: f32 = 0.25;
+
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct T {
+  /// How far through the day it is, in [0, 1). The sun rises at 0 and sets at 0.5.
+  pub time_of_day : f32,
+  /// The rotation of the sun's and moon's paths about the y axis.
+  pub rotation    : f32,
+  /// How far through the year it is, in [0, 1), or `None` in a world without seasons; see `common::season`.
+  pub season      : Option<f32>,
+}
+
+/// Where the sun is and how fast it's moving, as of the last time the server said, so the sky can move along on its
+/// own in between.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+  /// How many days had gone by at `since_ns`; see `season::at`.
+  pub time     : f64,
+  /// How fast `time` goes by, in days per second.
+  pub rate     : f64,
+  /// Whether the world has seasons.
+  pub seasons  : bool,
+  /// When the server said where the sun is, by our clock.
+  pub since_ns : u64,
+}
+
+impl Clock {
+  /// The sky at `now_ns`, with the sun's path rotated by `rotation`.
+  pub fn sky(&self, now_ns: u64, rotation: f32) -> T {
+    let time = self.time + self.rate * now_ns.saturating_sub(self.since_ns) as f64 / 1e9;
+    let (time_of_day, season) = season::at(time);
+    T {
+      time_of_day : time_of_day,
+      rotation    : rotation,
+      season      : if self.seasons { Some(season) } else { None },
+    }
+  }
+}
+
+impl T {
+  /// The sun, as a light source.
+  pub fn sun(&self) -> light::Sun {
+    light::Sun {
+      progression : self.time_of_day,
+      rotation    : self.rotation,
+      tilt        : season::sun_tilt(self.season.unwrap_or(0.0)),
+    }
+  }
+
+  /// A vector pointing toward the moon, which is always opposite the sun.
+  pub fn moon_direction(&self) -> Vector3<f32> {
+    -self.sun().direction()
+  }
+
+  /// The moon's color. It fades out as it sets.
+  pub fn moon_intensity(&self) -> Color3<f32> {
+    let height = f32::max(0.0, self.moon_direction().y);
+    let brightness = f32::min(1.0, 4.0 * height);
+    Color3::of_rgb(0.6 * brightness, 0.65 * brightness, 0.8 * brightness)
+  }
+
+  /// How visible the stars are, in [0, 1]. They fade in as the sun sets.
+  pub fn star_intensity(&self) -> f32 {
+    let sun_height = self.sun().direction().y;
+    f32::max(0.0, f32::min(1.0, (0.1 - sun_height) / 0.2))
+  }
+}
+
+/// Sets the `season` and `snow_line` uniforms in some shader.
+pub fn set_season(shader: &mut Shader, gl: &mut GLContext, sky: &T) {
+  let season_uniform = shader.get_uniform_location("season");
+  let snow_line_uniform = shader.get_uniform_location("snow_line");
+  // Without seasons, plants stay green and snow never settles.
+  let (season, snow_line) =
+    match sky.season {
+      None => (MIDSUMMER, std::f32::INFINITY),
+      Some(fraction) => (fraction, season::snow_line(fraction)),
+    };
+  shader.use_shader(gl);
+  unsafe {
+    gl::Uniform1f(season_uniform, season);
+    gl::Uniform1f(snow_line_uniform, snow_line);
+  }
+}
+
+/// Sets the `moon` struct and `star_intensity` uniform in some shader.
+pub fn set_sky(shader: &mut Shader, gl: &mut GLContext, sky: &T) {
+  let moon_direction_uniform = shader.get_uniform_location("moon.direction");
+  let moon_intensity_uniform = shader.get_uniform_location("moon.intensity");
+  let star_intensity_uniform = shader.get_uniform_location("star_intensity");
+  shader.use_shader(gl);
+  unsafe {
+    let d = sky.moon_direction();
+    gl::Uniform3f(moon_direction_uniform, d.x, d.y, d.z);
+    let i = sky.moon_intensity();
+    gl::Uniform3f(moon_intensity_uniform, i.r, i.g, i.b);
+    gl::Uniform1f(star_intensity_uniform, sky.star_intensity());
+  }
+}
@@ -0,0 +1,131 @@
+//! Free flight for the camera, for looking around the terrain without moving the player.
+//! While it's on, the movement keys fly the camera instead of walking, and the player stays where it is.
+
+use cgmath::{Vector3, InnerSpace};
+
+/// The slowest flying speed, in units per second.
+const MIN_SPEED: f32 = 1.0;
+/// The fastest flying speed, in units per second.
+const MAX_SPEED: f32 = 1024.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  active         : bool,
+  /// The movement keys held down, as a walking direction: x is right and z is back.
+  /// This is tracked in either mode, so switching modes can hand the held keys over.
+  walk           : Vector3<f32>,
+  ascending      : bool,
+  descending     : bool,
+  /// In units per second.
+  speed          : f32,
+  last_update_ns : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    active         : false,
+    walk           : Vector3::new(0.0, 0.0, 0.0),
+    ascending      : false,
+    descending     : false,
+    speed          : 16.0,
+    last_update_ns : None,
+  }
+}
+
+impl T {
+  /// Whether the camera is detached from the player.
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+
+  /// Detach the camera from the player, or reattach it.
+  pub fn toggle(&mut self) {
+    self.active = !self.active;
+    self.last_update_ns = None;
+  }
+
+  /// The movement keys currently held, as a walking direction.
+  pub fn walk(&self) -> Vector3<f32> {
+    self.walk
+  }
+
+  /// A movement key was pressed (or released, with the direction negated).
+  pub fn push(&mut self, direction: Vector3<f32>) {
+    self.walk = self.walk + direction;
+  }
+
+  /// Whether the jump key is held.
+  pub fn is_ascending(&self) -> bool {
+    self.ascending
+  }
+
+  #[allow(missing_docs)]
+  pub fn set_ascending(&mut self, ascending: bool) {
+    self.ascending = ascending;
+  }
+
+  #[allow(missing_docs)]
+  pub fn set_descending(&mut self, descending: bool) {
+    self.descending = descending;
+  }
+
+  /// Double or halve the flying speed, within limits.
+  pub fn scale_speed(&mut self, faster: bool) {
+    let speed = if faster { self.speed * 2.0 } else { self.speed / 2.0 };
+    self.speed = speed.max(MIN_SPEED).min(MAX_SPEED);
+  }
+
+  /// How far the camera has flown since the last update, given the direction it's looking in.
+  pub fn update(&mut self, now_ns: u64, forward: Vector3<f32>) -> Vector3<f32> {
+    let dt =
+      match self.last_update_ns {
+        None => 0.0,
+        Some(last) => (now_ns - last) as f32 / 1e9,
+      };
+    self.last_update_ns = Some(now_ns);
+
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    // Looking straight up or down, there's no telling which way is right.
+    let right = forward.cross(up);
+    let right = if right.magnitude2() > 0.0 { right.normalize() } else { right };
+    let vertical = if self.ascending { 1.0 } else { 0.0 } - if self.descending { 1.0 } else { 0.0 };
+    let direction = right * self.walk.x - forward * self.walk.z + up * vertical;
+    if direction.magnitude2() == 0.0 {
+      return direction
+    }
+    direction.normalize() * self.speed * dt
+  }
+
+  /// A description for the HUD, if the camera is detached.
+  pub fn label(&self) -> Option<String> {
+    if self.active {
+      Some(format!("Spectating, {} units/s", self.speed))
+    } else {
+      None
+    }
+  }
+}
+
+#[test]
+fn flies_where_its_looking_at_its_speed() {
+  let mut spectator = new();
+  spectator.toggle();
+  let forward = Vector3::new(0.0, 0.0, -1.0);
+
+  spectator.push(Vector3::new(0.0, 0.0, -1.0));
+  assert_eq!(spectator.update(0, forward), Vector3::new(0.0, 0.0, 0.0));
+  let d = spectator.update(500_000_000, forward);
+  assert!((d - Vector3::new(0.0, 0.0, -8.0)).magnitude() < 0.001);
+
+  spectator.push(Vector3::new(0.0, 0.0, 1.0));
+  spectator.set_ascending(true);
+  spectator.scale_speed(true);
+  let d = spectator.update(1_000_000_000, forward);
+  assert!((d - Vector3::new(0.0, 16.0, 0.0)).magnitude() < 0.001);
+
+  for _ in 0 .. 20 {
+    spectator.scale_speed(false);
+  }
+  assert_eq!(spectator.speed, MIN_SPEED);
+}
@@ -2,7 +2,8 @@
 
 use gl;
 use gl::types::*;
-use cgmath::{Point3, Vector3};
+use cgmath::{Point3, Vector2, Vector3};
+use collision::{Aabb, Aabb3};
 use std;
 use yaglw;
 use yaglw::gl_context::GLContext;
@@ -16,22 +17,36 @@ use common::id_allocator;
 use terrain_mesh::Triangle;
 
 use super::entity;
+use super::upload_ring;
 
 #[cfg(test)]
 use std::mem;
 
 const VERTICES_PER_TRIANGLE: usize = 3;
+/// The number of vertices used to draw an occlusion-testing box.
+const VERTICES_PER_BOX: GLint = 36;
+/// Chunks whose bounds, grown by this much, contain the eye are always drawn,
+/// since the near plane might clip their boxes away.
+const EYE_MARGIN: f32 = 1.0;
 
-/// Maximum number of bytes to be used in VRAM
-pub const BYTE_BUDGET: usize = 64_000_000;
-const POLYGON_COST: usize = 100;
-/// Maximum number of polygons to be used in VRAM
-pub const POLYGON_BUDGET: usize = BYTE_BUDGET / POLYGON_COST;
+/// Roughly how many bytes of VRAM a polygon takes up.
+const POLYGON_COST: usize = 120;
 
 /// Number of elements in a chunk in vram.
 pub const CHUNK_LENGTH: usize = 1 << 5;
-/// The number of polygons loaded contiguously into VRAM.
-const CHUNK_BUDGET: usize = POLYGON_BUDGET / CHUNK_LENGTH;
+
+/// Maximum number of polygons to be used in VRAM, given a budget in bytes.
+pub fn polygon_budget(byte_budget: usize) -> usize {
+  byte_budget / POLYGON_COST
+}
+
+/// Maximum number of chunks to be used in VRAM, given a budget in bytes.
+pub fn chunk_budget(byte_budget: usize) -> usize {
+  polygon_budget(byte_budget) / CHUNK_LENGTH
+}
+/// How many bytes of chunks can be streamed into VRAM each frame without waiting on the GPU.
+/// Chunks past this are uploaded the slow way.
+const UPLOAD_BYTES_PER_FRAME: usize = 1 << 22;
 /// Instead of storing individual vertices, normals, etc. in VRAM, store them in chunks.
 /// This makes it much faster to unload things.
 pub struct Chunk<V>(pub [V; CHUNK_LENGTH]);
@@ -43,6 +58,57 @@ impl<V> Chunk<V> {
   }
 }
 
+/// Whether a chunk is hidden behind other terrain, according to the last occlusion query that finished.
+struct Occlusion {
+  /// The bounds of the chunk's polygons.
+  bounds   : Aabb3<f32>,
+  query    : GLuint,
+  /// Whether `query` has been issued and its result not yet read.
+  pending  : bool,
+  occluded : bool,
+}
+
+fn bounds(vertices: &Chunk<Triangle<Point3<GLfloat>>>) -> Aabb3<f32> {
+  let mut bounds: Option<Aabb3<f32>> = None;
+  for triangle in vertices.0.iter() {
+    // Unused slots at the end of a chunk are zeroed.
+    if triangle.v1 == triangle.v2 && triangle.v2 == triangle.v3 {
+      continue
+    }
+    for &v in &[triangle.v1, triangle.v2, triangle.v3] {
+      bounds = Some(bounds.map_or(Aabb3::new(v, v), |bounds| bounds.grow(v)));
+    }
+  }
+  let origin = Point3::new(0.0, 0.0, 0.0);
+  bounds.unwrap_or(Aabb3::new(origin, origin))
+}
+
+/// Append a chunk to the end of a buffer, through the upload ring if there's room in it this frame.
+fn push_chunk<'a, V>(
+  gl          : &mut GLContext,
+  upload_ring : &mut Option<upload_ring::T>,
+  buffer      : &mut BufferTexture<'a, Chunk<V>>,
+  chunk       : &Chunk<V>,
+) {
+  let bytes = std::mem::size_of::<Chunk<V>>();
+  if let Some(ref mut upload_ring) = *upload_ring {
+    let byte_buffer = &mut buffer.buffer.byte_buffer;
+    let uploaded =
+      unsafe {
+        upload_ring.upload(gl, byte_buffer.handle.gl_id, byte_buffer.length, chunk as *const _ as *const u8, bytes)
+      };
+    if uploaded {
+      byte_buffer.length += bytes;
+      return
+    }
+  }
+
+  let chunk = unsafe { std::slice::from_raw_parts(chunk.as_ptr() as *const _, 1) };
+  buffer.buffer.byte_buffer.bind(gl);
+  let success = buffer.buffer.push(gl, chunk);
+  assert!(success);
+}
+
 /// Struct for loading/unloading/maintaining terrain data in VRAM.
 pub struct T<'a> {
   id_to_index: fnv_map::T<entity::id::Terrain, usize>,
@@ -51,12 +117,22 @@ pub struct T<'a> {
   // TODO: Use yaglw's ArrayHandle.
   empty_array: GLuint,
   length: u32,
+  /// The number of chunks that fit in the buffers.
+  capacity: usize,
 
   // Per-triangle buffers
 
   vertex_positions: BufferTexture<'a, Chunk<Triangle<Point3<GLfloat>>>>,
   normals: BufferTexture<'a, Chunk<Triangle<Vector3<GLfloat>>>>,
-  materials: BufferTexture<'a, Chunk<GLint>>,
+  ambient_occlusion: BufferTexture<'a, Chunk<Triangle<GLfloat>>>,
+  light: BufferTexture<'a, Chunk<Triangle<Vector2<GLfloat>>>>,
+  materials: BufferTexture<'a, Chunk<Triangle<GLint>>>,
+
+  /// Per-chunk occlusion state, in the same order as the chunks in VRAM.
+  occlusion: Vec<Occlusion>,
+
+  /// Streams chunks in without stalling, if persistently mapped buffers are supported.
+  upload_ring: Option<upload_ring::T>,
 }
 
 /// Phantom type for this buffer.
@@ -80,12 +156,14 @@ fn correct_size() {
   assert!(mem::size_of::<Vector3<GLfloat>>() == 3 * mem::size_of::<GLfloat>());
 }
 
-#[allow(missing_docs)]
+/// Buffers with room for `byte_budget` bytes' worth of terrain.
 pub fn new<'a, 'b>(
   gl: &'b mut GLContext,
+  byte_budget: usize,
 ) -> T<'a> where
   'a: 'b,
 {
+  let capacity = chunk_budget(byte_budget);
   T {
     id_to_index: fnv_map::new(),
     index_to_id: Vec::new(),
@@ -95,9 +173,20 @@ pub fn new<'a, 'b>(
       empty_array
     },
     length: 0,
-    vertex_positions: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    normals: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    materials: BufferTexture::new(gl, gl::R32UI, CHUNK_BUDGET),
+    capacity: capacity,
+    vertex_positions: BufferTexture::new(gl, gl::R32F, capacity),
+    normals: BufferTexture::new(gl, gl::R32F, capacity),
+    ambient_occlusion: BufferTexture::new(gl, gl::R32F, capacity),
+    light: BufferTexture::new(gl, gl::R32F, capacity),
+    materials: BufferTexture::new(gl, gl::R32UI, capacity),
+    occlusion: Vec::new(),
+    upload_ring: {
+      let upload_ring = upload_ring::new(gl, UPLOAD_BYTES_PER_FRAME);
+      if upload_ring.is_none() {
+        info!("Persistently mapped buffers aren't supported; terrain will be uploaded synchronously.");
+      }
+      upload_ring
+    },
   }
 }
 
@@ -150,6 +239,28 @@ impl<'a> T<'a> {
     self.bind(texture_unit_alloc, shader, "normals", self.normals.handle.gl_id);
   }
 
+  #[allow(missing_docs)]
+  pub fn bind_ambient_occlusion(
+    &self,
+    gl: &mut GLContext,
+    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
+    shader: &mut yaglw::shader::Shader,
+  ) {
+    shader.use_shader(gl);
+    self.bind(texture_unit_alloc, shader, "ambient_occlusion", self.ambient_occlusion.handle.gl_id);
+  }
+
+  #[allow(missing_docs)]
+  pub fn bind_light(
+    &self,
+    gl: &mut GLContext,
+    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
+    shader: &mut yaglw::shader::Shader,
+  ) {
+    shader.use_shader(gl);
+    self.bind(texture_unit_alloc, shader, "light", self.light.handle.gl_id);
+  }
+
   #[allow(missing_docs)]
   pub fn bind_materials(
     &self,
@@ -164,47 +275,63 @@ impl<'a> T<'a> {
   /// Add a series of entites into VRAM.
   pub fn push(
     &mut self,
-    gl        : &mut GLContext,
-    chunk_id  : entity::id::Terrain,
-    vertices  : &Chunk<Triangle<Point3<GLfloat>>>,
-    normals   : &Chunk<Triangle<Vector3<GLfloat>>>,
-    materials : &Chunk<GLint>,
+    gl                : &mut GLContext,
+    chunk_id          : entity::id::Terrain,
+    vertices          : &Chunk<Triangle<Point3<GLfloat>>>,
+    normals           : &Chunk<Triangle<Vector3<GLfloat>>>,
+    ambient_occlusion : &Chunk<Triangle<GLfloat>>,
+    light             : &Chunk<Triangle<Vector2<GLfloat>>>,
+    materials         : &Chunk<Triangle<GLint>>,
   ) {
     debug!("Insert {:?}", chunk_id);
+    if self.id_to_index.contains_key(&chunk_id) {
+      warn!("Ignoring {:?}, which is already in VRAM", chunk_id);
+      return
+    }
 
-    let vertices  = unsafe { std::slice::from_raw_parts(vertices.as_ptr()  as *const _, 1) };
-    let normals   = unsafe { std::slice::from_raw_parts(normals.as_ptr()   as *const _, 1) };
-    let materials = unsafe { std::slice::from_raw_parts(materials.as_ptr() as *const _, 1) };
-
-    self.vertex_positions.buffer.byte_buffer.bind(gl);
-    let success = self.vertex_positions.buffer.push(gl, vertices);
-    assert!(success);
-
-    self.normals.buffer.byte_buffer.bind(gl);
-    let success = self.normals.buffer.push(gl, normals);
-    assert!(success);
-
-    let previous = self.id_to_index.insert(chunk_id, self.index_to_id.len());
-    assert!(previous.is_none());
+    let mut query = 0;
+    unsafe {
+      gl::GenQueries(1, &mut query);
+    }
+    self.occlusion.push(Occlusion {
+      bounds   : bounds(vertices),
+      query    : query,
+      pending  : false,
+      occluded : false,
+    });
+
+    // The terrain module evicts chunks to stay within the budget, so this should always fit.
+    assert!((self.length as usize) < self.capacity, "Terrain VRAM budget exceeded");
+    push_chunk(gl, &mut self.upload_ring, &mut self.vertex_positions, vertices);
+    push_chunk(gl, &mut self.upload_ring, &mut self.normals, normals);
+    push_chunk(gl, &mut self.upload_ring, &mut self.ambient_occlusion, ambient_occlusion);
+    push_chunk(gl, &mut self.upload_ring, &mut self.light, light);
+    push_chunk(gl, &mut self.upload_ring, &mut self.materials, materials);
+
+    self.id_to_index.insert(chunk_id, self.index_to_id.len());
     self.index_to_id.push(chunk_id);
     assert_eq!(self.id_to_index.len(), self.index_to_id.len());
 
-    self.materials.buffer.byte_buffer.bind(gl);
-    let success = self.materials.buffer.push(gl, materials);
-    assert!(success);
-
     self.length += 1;
   }
 
   /// Remove some entity from VRAM.
   /// Returns the swapped ID and its VRAM index, if any.
+  /// Stale and unknown ids are ignored, rather than removing whichever chunk is in their place.
   pub fn swap_remove(
     &mut self,
     gl: &mut GLContext,
     id: entity::id::Terrain,
   ) -> Option<(ChunkIndex, ChunkIndex)>
   {
-    let idx = self.id_to_index[&id];
+    let idx =
+      match self.id_to_index.get(&id) {
+        None => {
+          warn!("Not removing {:?}, which isn't in VRAM", id);
+          return None
+        },
+        Some(&idx) => idx,
+      };
     let swapped_idx = self.index_to_id.len() - 1;
     let swapped_id = self.index_to_id[swapped_idx];
     self.index_to_id.swap_remove(idx);
@@ -228,12 +355,40 @@ impl<'a> T<'a> {
     self.normals.buffer.byte_buffer.bind(gl);
     self.normals.buffer.swap_remove(gl, idx, 1);
 
+    self.ambient_occlusion.buffer.byte_buffer.bind(gl);
+    self.ambient_occlusion.buffer.swap_remove(gl, idx, 1);
+
+    self.light.buffer.byte_buffer.bind(gl);
+    self.light.buffer.swap_remove(gl, idx, 1);
+
     self.materials.buffer.byte_buffer.bind(gl);
     self.materials.buffer.swap_remove(gl, idx, 1);
 
+    let occlusion = self.occlusion.swap_remove(idx);
+    unsafe {
+      gl::DeleteQueries(1, &occlusion.query);
+    }
+
     r
   }
 
+  /// Let the GPU finish this frame's streamed uploads, and start on the next frame's.
+  pub fn end_frame(&mut self, gl: &mut GLContext) {
+    if let Some(ref mut upload_ring) = self.upload_ring {
+      upload_ring.end_frame(gl);
+    }
+  }
+
+  /// The number of chunks in VRAM.
+  pub fn len(&self) -> usize {
+    self.length as usize
+  }
+
+  /// The number of chunks that fit in VRAM.
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
   /// Draw the terrain.
   pub fn draw(&self, _gl: &mut GLContext) {
     unsafe {
@@ -241,4 +396,91 @@ impl<'a> T<'a> {
       gl::DrawArrays(gl::TRIANGLES, 0, (self.length * CHUNK_LENGTH as u32 * VERTICES_PER_TRIANGLE as u32) as GLint);
     }
   }
+
+  /// Draw the terrain, skipping chunks that were hidden the last time `test_occlusion` heard back about them.
+  pub fn draw_unoccluded(&self, _gl: &mut GLContext) {
+    let vertices_per_chunk = (CHUNK_LENGTH * VERTICES_PER_TRIANGLE) as GLint;
+    unsafe {
+      gl::BindVertexArray(self.empty_array);
+    }
+
+    // Draw each run of consecutive visible chunks at once.
+    let mut run_start = None;
+    for i in 0 .. self.occlusion.len() + 1 {
+      let visible = i < self.occlusion.len() && !self.occlusion[i].occluded;
+      match (visible, run_start) {
+        (true, None) => run_start = Some(i),
+        (false, Some(start)) => {
+          unsafe {
+            gl::DrawArrays(gl::TRIANGLES, start as GLint * vertices_per_chunk, (i - start) as GLint * vertices_per_chunk);
+          }
+          run_start = None;
+        },
+        _ => {},
+      }
+    }
+  }
+
+  /// Read back any finished occlusion queries, and issue new ones for each chunk's bounds against the current depth
+  /// buffer. Results are picked up on later calls once the GPU has them, so this never waits.
+  pub fn test_occlusion(
+    &mut self,
+    gl: &mut GLContext,
+    shader: &mut yaglw::shader::Shader,
+    eye: &Point3<f32>,
+  ) {
+    shader.use_shader(gl);
+    let box_min = shader.get_uniform_location("box_min");
+    let box_max = shader.get_uniform_location("box_max");
+    unsafe {
+      gl::BindVertexArray(self.empty_array);
+      gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+      gl::DepthMask(gl::FALSE);
+      // The eye might be inside a box, looking at its back faces.
+      gl::Disable(gl::CULL_FACE);
+    }
+
+    for occlusion in &mut self.occlusion {
+      if occlusion.pending {
+        let mut available = 0;
+        unsafe {
+          gl::GetQueryObjectuiv(occlusion.query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+        }
+        if available == 0 {
+          continue
+        }
+        let mut samples_passed = 0;
+        unsafe {
+          gl::GetQueryObjectuiv(occlusion.query, gl::QUERY_RESULT, &mut samples_passed);
+        }
+        occlusion.pending = false;
+        occlusion.occluded = samples_passed == 0;
+      }
+
+      let (low, high) = (occlusion.bounds.min(), occlusion.bounds.max());
+      let near_eye =
+        low.x - EYE_MARGIN <= eye.x && eye.x <= high.x + EYE_MARGIN &&
+        low.y - EYE_MARGIN <= eye.y && eye.y <= high.y + EYE_MARGIN &&
+        low.z - EYE_MARGIN <= eye.z && eye.z <= high.z + EYE_MARGIN;
+      if near_eye {
+        occlusion.occluded = false;
+        continue
+      }
+
+      unsafe {
+        gl::Uniform3f(box_min, low.x, low.y, low.z);
+        gl::Uniform3f(box_max, high.x, high.y, high.z);
+        gl::BeginQuery(gl::ANY_SAMPLES_PASSED, occlusion.query);
+        gl::DrawArrays(gl::TRIANGLES, 0, VERTICES_PER_BOX);
+        gl::EndQuery(gl::ANY_SAMPLES_PASSED);
+      }
+      occlusion.pending = true;
+    }
+
+    unsafe {
+      gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+      gl::DepthMask(gl::TRUE);
+      gl::Enable(gl::CULL_FACE);
+    }
+  }
 }
@@ -0,0 +1,67 @@
+//! A camera that follows the player from behind, pulled in closer whenever terrain gets in the way.
+
+use cgmath::{Point3, Vector3};
+
+/// How far in front of terrain the camera stops, so the near clip plane doesn't cut into it.
+const TERRAIN_MARGIN: f32 = 0.25;
+
+#[allow(missing_docs)]
+pub struct T {
+  active   : bool,
+  /// How far behind the player the camera is.
+  distance : f32,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    active   : false,
+    distance : 0.0,
+  }
+}
+
+impl T {
+  /// Whether the camera is behind the player, rather than in their eyes.
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+
+  #[allow(missing_docs)]
+  pub fn toggle(&mut self) {
+    self.active = !self.active;
+    self.distance = 0.0;
+  }
+
+  /// Back the camera up to `max_distance` behind the player, or to just in front of the first terrain behind them,
+  /// `obstruction` away.
+  pub fn pull_in(&mut self, max_distance: f32, obstruction: Option<f32>) {
+    self.distance =
+      match obstruction {
+        None => max_distance,
+        Some(obstruction) => (obstruction - TERRAIN_MARGIN).max(0.0).min(max_distance),
+      };
+  }
+
+  /// Where the camera goes, for a player looking along `forward` from `eye`.
+  pub fn camera_position(&self, eye: &Point3<f32>, forward: &Vector3<f32>) -> Point3<f32> {
+    eye - forward * self.distance
+  }
+}
+
+#[test]
+fn terrain_pulls_the_camera_in() {
+  let mut third_person = new();
+  third_person.toggle();
+  let eye = Point3::new(0.0, 10.0, 0.0);
+  let forward = Vector3::new(1.0, 0.0, 0.0);
+
+  third_person.pull_in(6.0, None);
+  assert_eq!(third_person.camera_position(&eye, &forward), Point3::new(-6.0, 10.0, 0.0));
+
+  third_person.pull_in(6.0, Some(2.25));
+  assert_eq!(third_person.camera_position(&eye, &forward), Point3::new(-2.0, 10.0, 0.0));
+
+  // Terrain right behind the player leaves the camera in their eyes, not in front of them.
+  third_person.pull_in(6.0, Some(0.0));
+  assert_eq!(third_person.camera_position(&eye, &forward), eye);
+}
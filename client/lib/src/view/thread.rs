@@ -1,6 +1,7 @@
 //! This module defines the main function for the view/render/event thread.
 
-use cgmath::{Vector2};
+use cgmath::{Point3, Vector2, Vector3};
+use collision::Aabb3;
 use gl;
 use sdl2;
 use sdl2::event::{Event, WindowEvent};
@@ -10,19 +11,32 @@ use stopwatch;
 use time;
 use yaglw::gl_context::GLContext;
 
+use common::color::Color4;
 use common::interval_timer::IntervalTimer;
 use common::protocol;
 
+use audio_thread;
+use chunk;
 use client;
 use hud::make_hud;
+use lod;
 use process_event::process_event;
+use terrain;
+use timings;
 use view;
 
 use super::update;
+use super::update_queue;
 
 #[allow(missing_docs)]
 pub const FRAMES_PER_SECOND: u64 = 30;
 
+/// How long each frame can spend applying updates from the client, past the first one.
+const UPDATE_BUDGET_NS: u64 = 1_000_000;
+
+/// How far away voxels can be targeted from.
+const TARGET_DISTANCE: f32 = 64.0;
+
 #[allow(missing_docs)]
 pub const GL_MAJOR_VERSION: u8 = 3;
 #[allow(missing_docs)]
@@ -33,16 +47,113 @@ enum ViewIteration {
   Continue,
 }
 
+/// The text of the debug overlay.
+fn debug_stats(client: &client::T, terrain: &terrain::T, view: &view::T) -> String {
+  let mut stats = String::new();
+  for (name, ms) in client.timings.lock().unwrap().averages_ms() {
+    stats.push_str(&format!("{}: {:.2}ms\n", name, ms));
+  }
+  let gpu_timings = view.gpu_timings.averages_ms();
+  if !gpu_timings.is_empty() {
+    stats.push_str("gpu:");
+    for (name, ms) in gpu_timings {
+      stats.push_str(&format!(" {} {:.2}ms", name, ms));
+    }
+    stats.push_str("\n");
+  }
+
+  if let Some(tick) = *client.server_tick.lock().unwrap() {
+    stats.push_str(&format!(
+      "server tick: {:.2}ms ({:.2}ms max) of {:.2}ms, {} dropped\n",
+      tick.average_ms, tick.max_ms, tick.step_ms, tick.dropped,
+    ));
+  }
+  if let Some(rtt_ns) = client.clock_sync.lock().unwrap().rtt_ns() {
+    stats.push_str(&format!("ping: {:.1}ms\n", rtt_ns as f64 / 1e6));
+  }
+  stats.push_str(&format!("seed: {}\n", client.seed));
+  stats.push_str(&format!("render distance: {}\n", client.render_distance.lock().unwrap().distance()));
+  stats.push_str("chunks by LOD:");
+  for (lod, count) in lod::ALL.iter().zip(terrain.chunks_per_lod().iter()) {
+    stats.push_str(&format!(" {}:{}", lod.0, count));
+  }
+  stats.push_str("\n");
+
+  stats.push_str(&format!("terrain VRAM: {}/{} chunks\n", view.terrain_buffers.len(), view.terrain_buffers.capacity()));
+
+  let chunk_requests = client.chunk_requests.lock().unwrap();
+  stats.push_str(&format!(
+    "chunk requests: {} outstanding, {} queued\nvoxel updates queued: {}\nview updates queued: {}",
+    chunk_requests.outstanding_len(),
+    chunk_requests.queued_len(),
+    terrain.queued_update_count(),
+    view.updates.len(),
+  ));
+
+  let keys = &client.config.keys;
+  let on_off = |on| if on { "on" } else { "off" };
+  stats.push_str(&format!(
+    "\nchunk boxes ({}): {}, entity boxes ({}): {}, frustum ({}): {}",
+    keys.debug_chunks.name(), on_off(view.debug_lines.show_chunks),
+    keys.debug_bounds.name(), on_off(view.debug_lines.show_bounds),
+    keys.freeze_frustum.name(), if view.debug_lines.frozen_frustum.is_some() { "frozen" } else { "off" },
+  ));
+  stats.push_str(&format!(
+    "\ntonemapping ({}): {}, bloom ({}): {}, fxaa ({}): {}",
+    keys.tonemapping.name(), on_off(view.post_process.tonemapping),
+    keys.bloom.name(), on_off(view.post_process.bloom),
+    keys.fxaa.name(), on_off(view.post_process.fxaa),
+  ));
+  stats
+}
+
+/// Outline this client's player, and the other players and mobs it knows about.
+fn push_entity_bounds(client: &client::T, view: &mut view::T) {
+  let color = Color4::of_rgba(1.0, 0.0, 1.0, 1.0);
+  view.debug_lines.push_box(&client.prediction.lock().unwrap().bounds(), color);
+  if let Some(world) = client.replication.lock().unwrap().shown() {
+    for player in world.players.values().filter(|player| player.id != client.player_id) {
+      view.debug_lines.push_box(&player.bounds, color);
+    }
+    for mob in world.mobs.values() {
+      view.debug_lines.push_box(&mob.bounds, color);
+    }
+  }
+}
+
+/// Outline the loaded chunks, colored by LOD.
+fn push_chunk_bounds(terrain: &terrain::T, view: &mut view::T) {
+  // There can be more chunks than there's room to draw, so the nearest go first.
+  let camera = *chunk::position::of_world_position(&view.camera.position).as_pnt();
+  let mut chunks = terrain.loaded_chunks();
+  chunks.sort_by_key(|&(position, _)| {
+    let d = position.as_pnt() - camera;
+    d.x * d.x + d.y * d.y + d.z * d.z
+  });
+
+  let width = chunk::WIDTH as f32;
+  for (position, lod) in chunks {
+    let p = position.as_pnt();
+    let low = Point3::new(p.x as f32 * width, p.y as f32 * width, p.z as f32 * width);
+    let bounds = Aabb3::new(low, low + Vector3::new(width, width, width));
+    if !view.debug_lines.push_box(&bounds, view::debug_lines::lod_color(lod)) {
+      break
+    }
+  }
+}
+
 #[allow(missing_docs)]
-pub fn view_thread<Recv0, Recv1, UpdateServer>(
+pub fn view_thread<Recv0, Recv1, UpdateServer, UpdateAudio>(
   client: &client::T,
   recv0: &mut Recv0,
   recv1: &mut Recv1,
   update_server: &mut UpdateServer,
+  update_audio: &mut UpdateAudio,
 ) where
   Recv0: FnMut() -> Option<update::T>,
   Recv1: FnMut() -> Option<update::T>,
   UpdateServer: FnMut(protocol::ClientToServer),
+  UpdateAudio: FnMut(audio_thread::Message),
 {
   let sdl = sdl2::init().unwrap();
   let sdl_event = sdl.event().unwrap();
@@ -52,11 +163,10 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
   gl_attr.set_context_profile(video::GLProfile::Core);
   gl_attr.set_context_version(GL_MAJOR_VERSION, GL_MINOR_VERSION);
 
-  // Open the window as fullscreen at the current resolution.
   let mut window =
     video.window(
       "Playform",
-      800, 600,
+      client.config.window_size[0], client.config.window_size[1],
     );
   let window = window.opengl();
   let window = window.build().unwrap();
@@ -68,6 +178,10 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
 
   let _sdl_gl_context = window.gl_create_context().unwrap();
 
+  if !video.gl_set_swap_interval(if client.config.vsync { 1 } else { 0 }) {
+    warn!("Couldn't set vsync to {}: {}", client.config.vsync, sdl2::get_error());
+  }
+
   // Load the OpenGL function pointers.
   gl::load_with(|s| video.gl_get_proc_address(s) as *const _ );
 
@@ -82,7 +196,14 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     Vector2::new(w as i32, h as i32)
   };
 
-  let mut view = view::new(gl, window_size);
+  let mut view =
+    view::new(
+      gl,
+      window_size,
+      client.config.fov(),
+      client.config.vram_budget(),
+      client.config.clustered_lighting,
+    );
 
   sdl.mouse().set_relative_mouse_mode(true);
 
@@ -132,30 +253,93 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
           sdl.mouse().warp_mouse_in_window(&window, window_size.x / 2, window_size.y / 2);
         }
 
-        stopwatch::time("apply_updates", || {
-          let start = time::precise_time_ns();
-          loop {
-            if let Some(update) = recv0() {
-              update::apply_client_to_view(&mut view, update);
-            } else if let Some(update) = recv1() {
-              update::apply_client_to_view(&mut view, update);
-            } else {
-              info!("Out of view updates");
-              break
-            }
-
-            if time::precise_time_ns() - start >= 1_000_000 {
-              break
-            }
+        timings::time(&client.timings, "apply_updates", || {
+          while let Some(update) = recv0() {
+            view.updates.push(update);
+          }
+          while let Some(update) = recv1() {
+            view.updates.push(update);
           }
+          update_queue::apply(&mut view, UPDATE_BUDGET_NS);
         });
 
         let renders = render_timer.update(time::precise_time_ns());
         if renders > 0 {
-          stopwatch::time("render", || {
+          if view.spectator.is_active() {
+            let position = view.camera.position + view.spectator.update(time::precise_time_ns(), view.camera.forward());
+            view.camera.translate_to(position);
+            *client.spectator_position.lock().unwrap() = Some(position);
+          } else if view.third_person.is_active() {
+            let forward = view.camera.forward();
+            // Like the target, this can wait a frame if the terrain is busy, and keep the last frame's distance.
+            if let Ok(terrain) = client.terrain.try_lock() {
+              let distance = client.config.third_person_distance;
+              let obstruction = terrain.target(&view.eye, &-forward, distance).map(|hit| hit.distance);
+              view.third_person.pull_in(distance, obstruction);
+            }
+            let position = view.third_person.camera_position(&view.eye, &forward);
+            view.camera.translate_to(position);
+          }
+          // Sounds are heard from the camera.
+          update_audio(audio_thread::Message::MoveListener {
+            position : view.camera.position,
+            forward  : view.camera.forward(),
+          });
+          // While the sun's being moved by hand, it stays where it's put.
+          if let Some(clock) = view.sky_clock {
+            match view.input_mode {
+              view::InputMode::Sun => {},
+              _ => view.sky = clock.sky(time::precise_time_ns(), view.sky.rotation),
+            }
+          }
+          timings::time(&client.timings, "particles", || {
+            let now = time::precise_time_ns();
+            view.weather.update(now, &view.camera.position, &mut view.particles, &mut view.wind);
+            view.particles.update(&mut view.gl, now);
+          });
+          timings::time(&client.timings, "animation", || {
+            let now = time::precise_time_ns();
+            view.mob_buffers.animate(&mut view.gl, now);
+            view.player_buffers.animate(&mut view.gl, now);
+          });
+          timings::time(&client.timings, "target", || {
+            // Don't hold up rendering while terrain is being loaded; keep the old target for a frame.
+            if let Ok(terrain) = client.terrain.try_lock() {
+              let forward = view.camera.forward();
+              // The player targets from their eyes, even when the camera is behind them.
+              let eye = if view.spectator.is_active() { view.camera.position } else { view.eye };
+              view.target =
+                terrain.target(&eye, &forward, TARGET_DISTANCE)
+                .map(|hit| hit.bounds);
+            }
+          });
+          if view.show_debug {
+            view.debug_lines.clear();
+            view.debug_lines.push_frozen_frustum();
+            // Entities go before the terrain is locked: the update thread locks the prediction, then the terrain.
+            if view.debug_lines.show_bounds {
+              push_entity_bounds(client, &mut view);
+            }
+            // Like the target, this can wait a frame if the terrain is busy.
+            if let Ok(terrain) = client.terrain.try_lock() {
+              view.debug_stats = debug_stats(client, &terrain, &view);
+              if view.debug_lines.show_chunks {
+                push_chunk_bounds(&terrain, &mut view);
+              }
+            }
+            view.debug_lines.upload(&mut view.gl);
+          }
+          view.shaders.reload_changed(&mut view.gl, time::precise_time_ns());
+          timings::time(&client.timings, "render", || {
+            let start = time::precise_time_ns();
             view::render::render(&mut view);
+            view.screenshots.after_render(&view.window_size, time::precise_time_ns());
+            view.fps.frame(time::precise_time_ns());
             // swap buffers
             window.gl_swap_window();
+            // Swapping waits on the GPU once it falls behind, so this counts GPU time too.
+            let now = time::precise_time_ns();
+            client.render_distance.lock().unwrap().frame(now - start, now);
           });
         }
 
@@ -1,16 +1,32 @@
 //! Module for creating text textures.
 
+use cgmath::{Point2, Point3, Vector2};
+use gl;
+use gl::types::*;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+use sdl2::surface::Surface;
+use sdl2::sys::SDL_Color;
+use std;
 use std::ffi::CString;
 use std::path::Path;
-use sdl2_sys::pixels::{SDL_Color,SDL_PIXELFORMAT_ARGB8888};
-use sdl2_sys::surface::SDL_Surface;
-use sdl2_sys::surface;
-use gl;
 
 use common::color::Color4;
 
 use yaglw::gl_context::GLContext;
-use yaglw::texture::Texture2D;
+use yaglw::texture::{Texture2D, TextureUnit};
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+
+use vertex::TextureVertex;
+use view;
+
+/// The range of characters in a glyph atlas. Anything else is drawn as `?`.
+const FIRST_GLYPH: char = ' ';
+const LAST_GLYPH: char = '~';
+
+const VERTICES_PER_GLYPH: usize = 6;
 
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
@@ -18,8 +34,7 @@ use yaglw::texture::Texture2D;
 pub mod ffi {
   extern crate libc;
 
-  use sdl2_sys::pixels::SDL_Color;
-  use sdl2_sys::surface::SDL_Surface;
+  use sdl2::sys::{SDL_Color, SDL_Surface};
 
   pub use self::libc::{c_int, c_char, c_void, c_long};
 
@@ -77,6 +92,22 @@ pub struct Font {
   p: *mut ffi::TTF_Font
 }
 
+/// A row of monospace glyphs, rendered into a texture, for drawing text that changes often.
+pub struct Glyphs<'a> {
+  texture     : Texture2D<'a>,
+  /// The size of each glyph, in pixels.
+  glyph_size  : Vector2<u32>,
+  glyph_count : u32,
+}
+
+/// A line of text rendered into a texture.
+pub struct Text<'a> {
+  #[allow(missing_docs)]
+  pub texture : Texture2D<'a>,
+  /// In pixels.
+  pub size    : Vector2<u32>,
+}
+
 fn ensure_init() {
   unsafe {
     if ffi::TTF_WasInit() == 0 {
@@ -99,13 +130,12 @@ impl Font {
     Font { p: p }
   }
 
-  /// Color is rgba
-  pub fn render<'a, 'b:'a>(
-    &self,
-    gl: &'a GLContext,
-    txt: &str,
-    color: Color4<u8>,
-  ) -> Texture2D<'b> {
+  /// The height of a line of text, in pixels.
+  pub fn height(&self) -> u32 {
+    unsafe { ffi::TTF_FontHeight(self.p as *const ffi::c_void) as u32 }
+  }
+
+  fn render_surface(&self, txt: &str, color: Color4<u8>) -> Surface<'static> {
     let sdl_color = SDL_Color {
       r: color.r,
       g: color.g,
@@ -120,47 +150,252 @@ impl Font {
         ffi::TTF_RenderUTF8_Blended(self.p as *const ffi::c_void, ptr, sdl_color)
       }
     };
+    assert!(!surface_ptr.is_null(), "Cannot render text.");
+    let surface = unsafe { Surface::from_ll(surface_ptr) };
+    assert_eq!(surface.pixel_format_enum(), PixelFormatEnum::ARGB8888);
+    surface
+  }
 
-    let tex = unsafe {
-      surface_ptr.as_ref().expect("Cannot render text.")
-    };
+  /// Color is rgba. `txt` can't be empty.
+  pub fn render<'a, 'b:'a>(
+    &self,
+    gl: &'a GLContext,
+    txt: &str,
+    color: Color4<u8>,
+  ) -> Text<'b> {
+    let surface = self.render_surface(txt, color);
 
-    unsafe {
-      assert_eq!((*tex.format).format, SDL_PIXELFORMAT_ARGB8888);
+    let texture = Texture2D::new(gl);
+    let (w, h, pitch) = (surface.width(), surface.height(), surface.pitch());
+    surface.with_lock(|pixels| {
+      unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, (pitch / 4) as GLint);
+        gl::TexImage2D(
+          gl::TEXTURE_2D, 0, gl::RGBA as GLint, w as GLint, h as GLint, 0,
+          gl::BGRA, gl::UNSIGNED_INT_8_8_8_8_REV, pixels.as_ptr() as *const _,
+        );
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+      }
+    });
+
+    Text {
+      texture : texture,
+      size    : Vector2::new(w, h),
+    }
+  }
+
+  /// Render this font's printable ASCII characters, in white, for drawing with `Glyphs`. Only makes sense for a
+  /// monospace font.
+  pub fn glyphs<'a, 'b:'a>(&self, gl: &'a GLContext) -> Glyphs<'b> {
+    let white = Color4::of_rgba(0xFF, 0xFF, 0xFF, 0xFF);
+    let mut glyphs = Vec::new();
+    for c in FIRST_GLYPH as u8 .. LAST_GLYPH as u8 + 1 {
+      let mut glyph = self.render_surface(&(c as char).to_string(), white);
+      // Copy the glyph's alpha as-is, instead of blending it onto the (transparent) atlas.
+      glyph.set_blend_mode(BlendMode::None).unwrap();
+      glyphs.push(glyph);
+    }
+
+    let glyph_size =
+      Vector2::new(
+        glyphs.iter().map(|glyph| glyph.width()).max().unwrap(),
+        self.height(),
+      );
+    let glyph_count = glyphs.len() as u32;
+
+    let mut atlas = Surface::new(glyph_size.x * glyph_count, glyph_size.y, PixelFormatEnum::ARGB8888).unwrap();
+    for (i, glyph) in glyphs.iter().enumerate() {
+      let dest = Rect::new(i as i32 * glyph_size.x as i32, 0, glyph.width(), glyph.height());
+      glyph.blit(None, &mut atlas, dest).unwrap();
     }
 
     let texture = Texture2D::new(gl);
-    unsafe {
-      gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
-      gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, tex.w, tex.h, 0, gl::BGRA, gl::UNSIGNED_INT_8_8_8_8_REV, tex.pixels as *const _);
-      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    let (w, h, pitch) = (atlas.width(), atlas.height(), atlas.pitch());
+    atlas.with_lock(|pixels| {
+      unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, (pitch / 4) as GLint);
+        gl::TexImage2D(
+          gl::TEXTURE_2D, 0, gl::RGBA as GLint, w as GLint, h as GLint, 0,
+          gl::BGRA, gl::UNSIGNED_INT_8_8_8_8_REV, pixels.as_ptr() as *const _,
+        );
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+      }
+    });
 
-      surface::SDL_FreeSurface(surface_ptr as *mut SDL_Surface);
+    Glyphs {
+      texture     : texture,
+      glyph_size  : glyph_size,
+      glyph_count : glyph_count,
     }
+  }
 
-    texture
+  /// Color the text white.
+  pub fn white<'a>(&self, gl: &'a GLContext, txt: &str) -> Text<'a> {
+    self.render(gl, txt, Color4::of_rgba(0xFF, 0xFF, 0xFF, 0xFF))
   }
 
   /// Color the text red.
   #[allow(dead_code)]
-  pub fn red<'a>(&self, gl: &'a GLContext, txt: &str) -> Texture2D<'a> {
+  pub fn red<'a>(&self, gl: &'a GLContext, txt: &str) -> Text<'a> {
     self.render(gl, txt, Color4::of_rgba(0xFF, 0x00, 0x00, 0xFF))
   }
 
   /// dark black #333
   #[allow(dead_code)]
-  pub fn dark<'a>(&self, gl: &'a GLContext, txt: &str) -> Texture2D<'a> {
+  pub fn dark<'a>(&self, gl: &'a GLContext, txt: &str) -> Text<'a> {
     self.render(gl, txt, Color4::of_rgba(0x33, 0x33, 0x33, 0xFF))
   }
 
   /// light black #555
   #[allow(dead_code)]
-  pub fn light<'a>(&self, gl: &'a GLContext, txt: &str) -> Texture2D<'a> {
+  pub fn light<'a>(&self, gl: &'a GLContext, txt: &str) -> Text<'a> {
     self.render(gl, txt, Color4::of_rgba(0x55, 0x55, 0x55, 0xFF))
   }
 }
 
+impl<'a> Glyphs<'a> {
+  /// The size of a glyph in HUD coordinates, which span [-1, 1] vertically.
+  pub fn size(&self, window_size: &Vector2<i32>) -> Vector2<f32> {
+    let units_per_pixel = 2.0 / window_size.y as f32;
+    Vector2::new(
+      self.glyph_size.x as f32 * units_per_pixel,
+      self.glyph_size.y as f32 * units_per_pixel,
+    )
+  }
+
+  /// Append the quads for a line of text, at the font's own size, with its bottom-left corner at `origin` in
+  /// HUD coordinates.
+  pub fn push_line(
+    &self,
+    vertices    : &mut Vec<TextureVertex>,
+    window_size : &Vector2<i32>,
+    origin      : Point2<f32>,
+    line        : &str,
+  ) {
+    let height = self.size(window_size).y;
+    self.push_text(vertices, origin, height, line);
+  }
+
+  /// Append the quads for some text, with glyphs `height` HUD units tall. The bottom-left corner of the first
+  /// line is at `origin`, and any later lines go below it.
+  pub fn push_text(
+    &self,
+    vertices : &mut Vec<TextureVertex>,
+    origin   : Point2<f32>,
+    height   : f32,
+    text     : &str,
+  ) {
+    let size = Vector2::new(height * self.glyph_size.x as f32 / self.glyph_size.y as f32, height);
+    let tex_w = 1.0 / self.glyph_count as f32;
+
+    let vtx = |x, y, u, v| {
+      TextureVertex {
+        world_position   : Point3::new(x, y, 0.0),
+        texture_position : Vector2::new(u, v),
+      }
+    };
+
+    for (row, line) in text.lines().enumerate() {
+      let y0 = origin.y - row as f32 * size.y;
+      let y1 = y0 + size.y;
+      for (col, c) in line.chars().enumerate() {
+        let c = if FIRST_GLYPH <= c && c <= LAST_GLYPH { c } else { '?' };
+        if c == ' ' {
+          continue
+        }
+        let x0 = origin.x + col as f32 * size.x;
+        let x1 = x0 + size.x;
+        let u0 = (c as u32 - FIRST_GLYPH as u32) as f32 * tex_w;
+        let u1 = u0 + tex_w;
+        // The atlas's rows are stored top-down, so v = 0 is the top of the glyph.
+        vertices.push(vtx(x0, y0, u0, 1.0));
+        vertices.push(vtx(x1, y1, u1, 0.0));
+        vertices.push(vtx(x0, y1, u0, 0.0));
+        vertices.push(vtx(x0, y0, u0, 1.0));
+        vertices.push(vtx(x1, y0, u1, 1.0));
+        vertices.push(vtx(x1, y1, u1, 0.0));
+      }
+    }
+  }
+
+  /// Upload some glyph quads made by `push_line`.
+  pub fn build<'b>(
+    &self,
+    gl: &'b mut GLContext,
+    shader: &view::shaders::texture::T<'a>,
+    vertices: &[TextureVertex],
+  ) -> GLArray<'a, TextureVertex> where
+    'a: 'b,
+  {
+    let capacity = std::cmp::max(vertices.len(), VERTICES_PER_GLYPH);
+    let buffer = GLBuffer::new(gl, capacity);
+    let mut quads =
+      GLArray::new(
+        gl,
+        &shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+          VertexAttribData { name: "texture_position", size: 2, unit: GLType::Float, divisor: 0 },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      );
+    quads.buffer.byte_buffer.bind(gl);
+    quads.push(gl, vertices);
+    quads
+  }
+
+  /// Draw glyph quads made by `build`.
+  pub fn draw<'b>(
+    &self,
+    gl: &'b mut GLContext,
+    shader: &mut view::shaders::texture::T<'a>,
+    texture_unit: &TextureUnit,
+    quads: &GLArray<'a, TextureVertex>,
+  ) where
+    'a: 'b,
+  {
+    shader.shader.use_shader(gl);
+    unsafe {
+      gl::ActiveTexture(texture_unit.gl_id());
+      gl::BindTexture(gl::TEXTURE_2D, self.texture.handle.gl_id);
+      gl::Disable(gl::DEPTH_TEST);
+    }
+    quads.bind(gl);
+    quads.draw(gl);
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+    }
+  }
+
+  /// Draw some text right away, as laid out by `push_text`. This builds new quads every time it's called, so
+  /// it's meant for text that changes every frame anyway; cache the output of `build` for anything else.
+  pub fn draw_text<'b>(
+    &self,
+    gl           : &'b mut GLContext,
+    shader       : &mut view::shaders::texture::T<'a>,
+    texture_unit : &TextureUnit,
+    origin       : Point2<f32>,
+    height       : f32,
+    text         : &str,
+  ) where
+    'a: 'b,
+  {
+    let mut vertices = Vec::new();
+    self.push_text(&mut vertices, origin, height, text);
+    let quads = self.build(gl, shader, &vertices);
+    self.draw(gl, shader, texture_unit, &quads);
+  }
+}
+
 impl Drop for Font {
   fn drop(&mut self) {
     unsafe { ffi::TTF_CloseFont(self.p) }
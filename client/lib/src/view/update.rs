@@ -1,38 +1,94 @@
 //! Define the updates passed from the client to the view.
 
 use cgmath::Point3;
+use gl::types::*;
+use std;
 use stopwatch;
+use time;
 
+use chunk;
+use hud;
+use particles;
 use terrain_mesh;
 use vertex::ColoredVertex;
 use view;
 
+use common::health;
 use common::index;
+use common::protocol;
 
 use super::chunked_terrain;
 use super::entity;
-use super::light;
-use super::mob_buffers::VERTICES_PER_MOB;
-use super::player_buffers::VERTICES_PER_PLAYER;
+use super::fog;
+use super::sky;
+use super::shadow;
+use super::entity_buffers;
+use super::item_buffers::VERTICES_PER_ITEM;
 
 /// Messages from the client to the view.
 pub enum T {
   /// Set the camera location.
   MoveCamera(Point3<f32>),
 
-  /// Update a player mesh.
-  UpdatePlayer(entity::id::Player, [ColoredVertex; VERTICES_PER_PLAYER]),
-  /// Update a mob mesh.
-  UpdateMob(entity::id::Mob, [ColoredVertex; VERTICES_PER_MOB]),
+  /// Show a player, or move it.
+  UpdatePlayer(entity::id::Player, entity_buffers::Instance, protocol::Animation),
+  /// Stop showing a player.
+  RemovePlayer(entity::id::Player),
+  /// Learn a player's name.
+  SetPlayerName(entity::id::Player, String),
+  /// Float a player's name above a point, usually the player's center.
+  PlaceNameplate(entity::id::Player, Point3<f32>),
+  /// Show a mob, or move it.
+  UpdateMob(entity::id::Mob, entity_buffers::Instance, protocol::Animation),
+  /// Stop showing a mob.
+  RemoveMob(entity::id::Mob),
+  /// Update an item mesh.
+  UpdateItem(entity::id::Item, [ColoredVertex; VERTICES_PER_ITEM]),
+  /// Stop showing an item.
+  RemoveItem(entity::id::Item),
 
-  /// Update the sun.
-  SetSun(light::Sun),
+  /// Move the sun, and set how fast it goes from here.
+  SetSky(sky::Clock),
+  /// Change the shadow map quality.
+  SetShadowQuality(shadow::Quality),
+  /// Place a point light, or move it.
+  AddLight(protocol::LightId, protocol::Light),
+  /// Take out a point light.
+  RemoveLight(protocol::LightId),
+  /// Take out every point light, e.g. on leaving a world.
+  ClearLights,
 
   /// Add a terrain chunk to the view.
-  LoadMesh (Box<chunked_terrain::T>),
+  LoadMesh (std::sync::Arc<chunked_terrain::T>),
   /// Remove a terrain entity.
   UnloadMesh(terrain_mesh::Ids),
-  /// Treat a series of updates as an atomic operation.
+  /// Flatten the grass growing in a voxel for a while.
+  TrampleGrass(Point3<i32>),
+  /// Remove the grass growing in voxels whose centers are within a radius of a point.
+  RemoveGrass(Point3<f32>, f32),
+  /// Add (or replace) a chunk's water surface.
+  AddWater(chunk::position::T, Vec<terrain_mesh::Triangle<Point3<GLfloat>>>),
+  /// Remove a chunk's water surface.
+  RemoveWater(chunk::position::T),
+  /// Show a chat message.
+  ShowChatMessage(String),
+  /// Change what's in an inventory slot.
+  SetInventorySlot(usize, Option<protocol::ItemStack>),
+  /// Change the player's health on the HUD.
+  SetHealth(health::T),
+  /// Start a burst of particles.
+  EmitParticles(particles::Emitter),
+  /// Change the fog's color and density.
+  SetFog(fog::T),
+  /// Tint and wobble the screen (or stop) because the camera's underwater.
+  SetUnderwater(bool),
+  /// Flash the screen red because the player was hurt.
+  FlashDamage,
+  /// Change what's falling from the sky, and how the wind blows.
+  SetWeather(protocol::Weather),
+  /// Forget the minimap, and show another world's waypoints.
+  ChangeWorld(protocol::WorldId),
+  /// Treat a series of updates as an atomic operation: they're applied in order, all in the same frame.
   Atomic(Vec<T>),
 }
 
@@ -42,51 +98,101 @@ pub use self::T::*;
 pub fn apply_client_to_view(view: &mut view::T, up: T) {
   match up {
     T::MoveCamera(position) => {
-      view.camera.translate_to(position);
+      view.eye = position;
+      // A detached camera goes its own way, and a third-person one is placed every frame.
+      if !view.spectator.is_active() && !view.third_person.is_active() {
+        view.camera.translate_to(position);
+      }
     },
-    T::UpdateMob(id, triangles) => {
-      view.mob_buffers.insert(&mut view.gl, id, &triangles);
+    T::UpdateMob(id, instance, animation) => {
+      view.mob_buffers.insert(&mut view.gl, id, &instance, animation);
+      view.minimap.place_mob(id, instance.center);
     },
-    T::UpdatePlayer(id, triangles) => {
-      view.player_buffers.insert(&mut view.gl, id, &triangles);
+    T::UpdatePlayer(id, instance, animation) => {
+      view.player_buffers.insert(&mut view.gl, id, &instance, animation);
+      view.minimap.place_player(id, instance.center);
     },
-    T::SetSun(sun) => {
-      match view.input_mode {
-        view::InputMode::Sun => {},
-        _ => {
-          view.sun = sun;
-        },
-      }
+    T::RemovePlayer(id) => {
+      view.player_buffers.swap_remove(&mut view.gl, id);
+      view.nameplates.remove(id);
+      view.minimap.remove_player(id);
+    },
+    T::SetPlayerName(id, name) => {
+      view.nameplates.set_name(id, name);
+    },
+    T::PlaceNameplate(id, position) => {
+      view.nameplates.place(id, position);
+    },
+    T::RemoveMob(id) => {
+      view.mob_buffers.swap_remove(&mut view.gl, id);
+      view.minimap.remove_mob(id);
+    },
+    T::UpdateItem(id, triangles) => {
+      view.item_buffers.insert(&mut view.gl, id, &triangles);
+    },
+    T::RemoveItem(id) => {
+      view.item_buffers.swap_remove(&mut view.gl, id);
+    },
+    T::SetSky(clock) => {
+      view.sky_clock = Some(clock);
+    },
+    T::SetShadowQuality(quality) => {
+      view.shadow.set_quality(quality);
+    },
+    T::AddLight(id, light) => {
+      view.point_lights.insert(id, light);
+    },
+    T::RemoveLight(id) => {
+      view.point_lights.remove(&id);
+    },
+    T::ClearLights => {
+      view.point_lights.clear();
     },
     T::LoadMesh(mesh) => {
       stopwatch::time("add_chunk", move || {
-        let mesh = *mesh;
         for i in 0 .. mesh.chunk_count() {
           view.terrain_buffers.push(
             &mut view.gl,
             mesh.ids[i],
             &mesh.vertex_coordinates[i],
             &mesh.normals[i],
+            &mesh.ambient_occlusion[i],
+            &mesh.light[i],
             &mesh.materials[i],
           );
         }
+        view.minimap.add_terrain(&mesh);
         let mut grass_entries = Vec::with_capacity(mesh.grass.len());
+        let mut grass_ids = Vec::with_capacity(mesh.grass.len());
+        let mut grass_cells = Vec::with_capacity(mesh.grass.len());
         for i in 0 .. mesh.grass.len() {
           let chunk_id = mesh.grass.polygon_chunk_ids[i];
           let polygon_offset = mesh.grass.polygon_offsets[i];
-          let chunk_idx = view.terrain_buffers.lookup_opengl_index(chunk_id).unwrap();
+          let chunk_idx =
+            match view.terrain_buffers.lookup_opengl_index(chunk_id) {
+              None => {
+                warn!("Skipping grass {:?} on {:?}, which isn't in VRAM", mesh.grass.ids[i], chunk_id);
+                continue
+              },
+              Some(idx) => idx,
+            };
           let polygon_idx = chunk_idx.subindex(polygon_offset);
           grass_entries.push(
             view::grass_buffers::Entry {
               polygon_idx : polygon_idx.to_u32(),
               tex_id      : mesh.grass.tex_ids[i],
+              phase       : mesh.grass.phases[i],
+              bend        : 0.0,
             }
           );
+          grass_ids.push(mesh.grass.ids[i]);
+          grass_cells.push(mesh.grass.cells[i]);
         }
         view.grass_buffers.push(
           &mut view.gl,
           grass_entries.as_ref(),
-          mesh.grass.ids.as_ref(),
+          grass_ids.as_ref(),
+          grass_cells.as_ref(),
         );
       })
     },
@@ -110,6 +216,54 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
         }
       }
     },
+    T::TrampleGrass(cell) => {
+      view.grass_buffers.trample(&cell, time::precise_time_ns());
+    },
+    T::RemoveGrass(center, radius) => {
+      for id in view.grass_buffers.within(&center, radius) {
+        view.grass_buffers.swap_remove(&mut view.gl, id);
+      }
+    },
+    T::AddWater(position, triangles) => {
+      view.water_buffers.insert(&mut view.gl, &view.shaders.water, position, &triangles);
+      view.minimap.add_water(&triangles);
+    },
+    T::RemoveWater(position) => {
+      view.water_buffers.remove(&position);
+    },
+    T::ShowChatMessage(message) => {
+      view.chat.push_message(&message);
+    },
+    T::SetInventorySlot(slot, stack) => {
+      view.hotbar.set_slot(slot, stack);
+      view.crafting.refresh();
+      hud::make_hud(view);
+    },
+    T::SetHealth(health) => {
+      if view.health != health {
+        view.health = health;
+        hud::make_hud(view);
+      }
+    },
+    T::EmitParticles(emitter) => {
+      view.particles.emit(&emitter);
+    },
+    T::SetFog(fog) => {
+      view.fog = fog;
+    },
+    T::SetUnderwater(underwater) => {
+      view.post_process.effects.set_underwater(underwater);
+    },
+    T::FlashDamage => {
+      view.post_process.effects.flash_damage(time::precise_time_ns());
+    },
+    T::SetWeather(weather) => {
+      view.weather.set(weather);
+    },
+    T::ChangeWorld(world) => {
+      view.minimap.clear();
+      view.waypoints.set_world(world);
+    },
     T::Atomic(updates) => {
       for up in updates {
         apply_client_to_view(view, up);
@@ -0,0 +1,115 @@
+//! Updates from the client waiting to be applied to the view. Each frame has a time budget for them, spent on the
+//! most urgent first, so a burst of terrain (e.g. after teleporting) is spread over several frames instead of
+//! stalling one.
+
+use std::collections::VecDeque;
+use time;
+
+use view;
+
+use super::update;
+
+/// How urgent an update is. Updates of the same priority are applied in the order they were sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+  /// The camera and the sun, which the whole frame is drawn from.
+  Camera,
+  /// Everything that isn't terrain.
+  Normal,
+  /// Terrain meshes, and anything that has to stay in order with them.
+  Terrain,
+}
+
+const COUNT: usize = 3;
+
+/// How urgent an update is.
+pub fn priority(update: &update::T) -> Priority {
+  match *update {
+    update::MoveCamera(_) | update::SetSky(_) => Priority::Camera,
+    update::LoadMesh(_)
+    | update::UnloadMesh(_)
+    | update::TrampleGrass(_)
+    | update::RemoveGrass(_, _)
+    | update::AddWater(_, _)
+    | update::RemoveWater(_)
+    // Changing worlds clears the minimap, so it has to come after the old world's terrain and before the new one's.
+    | update::ChangeWorld(_)
+    | update::Atomic(_) => Priority::Terrain,
+    _ => Priority::Normal,
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Indexed by priority.
+  queues : [VecDeque<update::T>; COUNT],
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    queues : [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn push(&mut self, update: update::T) {
+    self.queues[priority(&update) as usize].push_back(update);
+  }
+
+  /// The most urgent update, if there are any at least as urgent as `min_priority`.
+  pub fn pop(&mut self, min_priority: Priority) -> Option<update::T> {
+    self.queues[.. min_priority as usize + 1].iter_mut().filter_map(|queue| queue.pop_front()).next()
+  }
+
+  /// How many updates are waiting.
+  pub fn len(&self) -> usize {
+    self.queues.iter().map(|queue| queue.len()).sum()
+  }
+}
+
+/// Apply the view's queued updates, most urgent first, until `budget_ns` is spent. Camera updates are always all
+/// applied, and so is at least one other, so the queue keeps moving however slow the updates are.
+pub fn apply(view: &mut view::T, budget_ns: u64) {
+  let start = time::precise_time_ns();
+  let mut applied = 0;
+  loop {
+    let min_priority =
+      if applied > 0 && time::precise_time_ns() - start >= budget_ns {
+        Priority::Camera
+      } else {
+        Priority::Terrain
+      };
+    match view.updates.pop(min_priority) {
+      None => break,
+      Some(up) => update::apply_client_to_view(view, up),
+    }
+    applied += 1;
+  }
+}
+
+#[test]
+fn urgent_updates_go_first() {
+  use cgmath::Point3;
+  use chunk;
+
+  let mut queue = new();
+  queue.push(update::RemoveWater(chunk::position::new(0, 0, 0)));
+  queue.push(update::FlashDamage);
+  queue.push(update::MoveCamera(Point3::new(1.0, 2.0, 3.0)));
+  queue.push(update::RemoveWater(chunk::position::new(1, 0, 0)));
+  assert_eq!(queue.len(), 4);
+
+  assert_eq!(queue.pop(Priority::Camera).map(|up| priority(&up)), Some(Priority::Camera));
+  assert!(queue.pop(Priority::Camera).is_none());
+  assert_eq!(queue.pop(Priority::Terrain).map(|up| priority(&up)), Some(Priority::Normal));
+  match (queue.pop(Priority::Terrain), queue.pop(Priority::Terrain)) {
+    (Some(update::RemoveWater(first)), Some(update::RemoveWater(second))) => {
+      assert_eq!(first, chunk::position::new(0, 0, 0));
+      assert_eq!(second, chunk::position::new(1, 0, 0));
+    },
+    _ => panic!("terrain updates should come last, in order"),
+  }
+  assert_eq!(queue.len(), 0);
+}
@@ -0,0 +1,129 @@
+//! Streaming data into VRAM without waiting on the GPU.
+//! A persistently mapped buffer (GL_ARB_buffer_storage) is split into a section for each frame in flight. Uploads
+//! are written into the current frame's section and copied into their destinations on the GPU's side, and each
+//! section is fenced off at the end of its frame, so it's only waited on if the GPU is a whole ring behind.
+
+use gl;
+use gl::types::*;
+use std;
+use std::ffi::CStr;
+use yaglw::gl_context::GLContext;
+
+/// The number of frames whose uploads can be in flight at once.
+const SECTIONS: usize = 3;
+/// How long to wait on a fence before checking it again, in nanoseconds.
+const FENCE_WAIT_NS: GLuint64 = 1_000_000;
+
+/// Whether the GL context supports an extension.
+pub fn has_extension(name: &str) -> bool {
+  let mut count = 0;
+  unsafe {
+    gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+  }
+  (0 .. count as GLuint).any(|i| {
+    let extension = unsafe { gl::GetStringi(gl::EXTENSIONS, i) };
+    !extension.is_null() && unsafe { CStr::from_ptr(extension as *const _) }.to_bytes() == name.as_bytes()
+  })
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  buffer        : GLuint,
+  /// The start of the buffer, which stays mapped for as long as the ring lives.
+  mapping       : *mut u8,
+  section_bytes : usize,
+  /// The section this frame's uploads go in.
+  section       : usize,
+  /// How much of the current section has been used.
+  used          : usize,
+  /// Signaled once the GPU is done with each section's uploads, if it's been used.
+  fences        : [Option<GLsync>; SECTIONS],
+}
+
+/// A ring that can take `section_bytes` of uploads a frame.
+/// Returns None if persistently mapped buffers aren't supported.
+pub fn new(_gl: &mut GLContext, section_bytes: usize) -> Option<T> {
+  if !has_extension("GL_ARB_buffer_storage") {
+    return None
+  }
+
+  let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+  let bytes = (SECTIONS * section_bytes) as GLsizeiptr;
+  let mut buffer = 0;
+  let mapping =
+    unsafe {
+      gl::GenBuffers(1, &mut buffer);
+      gl::BindBuffer(gl::COPY_READ_BUFFER, buffer);
+      gl::BufferStorage(gl::COPY_READ_BUFFER, bytes, std::ptr::null(), flags);
+      gl::MapBufferRange(gl::COPY_READ_BUFFER, 0, bytes, flags) as *mut u8
+    };
+  if mapping.is_null() {
+    warn!("Couldn't map an upload buffer");
+    unsafe {
+      gl::DeleteBuffers(1, &buffer);
+    }
+    return None
+  }
+
+  Some(T {
+    buffer        : buffer,
+    mapping       : mapping,
+    section_bytes : section_bytes,
+    section       : 0,
+    used          : 0,
+    fences        : [None; SECTIONS],
+  })
+}
+
+impl T {
+  /// Copy `bytes` bytes from `data` into the buffer `destination`, `offset` bytes in.
+  /// Returns false, having done nothing, if this frame's section is full.
+  pub unsafe fn upload(
+    &mut self,
+    _gl         : &mut GLContext,
+    destination : GLuint,
+    offset      : usize,
+    data        : *const u8,
+    bytes       : usize,
+  ) -> bool {
+    if self.used + bytes > self.section_bytes {
+      return false
+    }
+
+    let source = self.section * self.section_bytes + self.used;
+    std::ptr::copy_nonoverlapping(data, self.mapping.offset(source as isize), bytes);
+    gl::BindBuffer(gl::COPY_READ_BUFFER, self.buffer);
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, destination);
+    gl::CopyBufferSubData(
+      gl::COPY_READ_BUFFER,
+      gl::COPY_WRITE_BUFFER,
+      source as GLintptr,
+      offset as GLintptr,
+      bytes as GLsizeiptr,
+    );
+    self.used += bytes;
+    true
+  }
+
+  /// Fence off this frame's uploads, and move on to the next section, once the GPU is done with it.
+  pub fn end_frame(&mut self, _gl: &mut GLContext) {
+    if self.used > 0 {
+      self.fences[self.section] = Some(unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) });
+    }
+    self.section = (self.section + 1) % SECTIONS;
+    self.used = 0;
+
+    if let Some(fence) = self.fences[self.section].take() {
+      unsafe {
+        loop {
+          let result = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, FENCE_WAIT_NS);
+          if result != gl::TIMEOUT_EXPIRED {
+            break
+          }
+          debug!("Waiting for the GPU to finish with upload section {}", self.section);
+        }
+        gl::DeleteSync(fence);
+      }
+    }
+  }
+}
@@ -0,0 +1,75 @@
+//! Data structures and functions to load/unload/maintain water surfaces in VRAM.
+
+use cgmath::Point3;
+use gl::types::*;
+use yaglw::gl_context::GLContext;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+
+use common::fnv_map;
+
+use chunk;
+use terrain_mesh;
+use view;
+
+/// The water surface meshes of each chunk. Water is sparse, so each chunk gets its own buffer.
+pub struct T<'a> {
+  meshes: fnv_map::T<chunk::position::T, GLArray<'a, Point3<GLfloat>>>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a>() -> T<'a> {
+  T {
+    meshes: fnv_map::new(),
+  }
+}
+
+impl<'a> T<'a> {
+  /// Load a chunk's water surface into VRAM, replacing any existing one.
+  pub fn insert<'b>(
+    &mut self,
+    gl: &'b mut GLContext,
+    shader: &view::shaders::water::T<'a>,
+    position: chunk::position::T,
+    triangles: &[terrain_mesh::Triangle<Point3<GLfloat>>],
+  ) where
+    'a: 'b,
+  {
+    let mut vertices = Vec::with_capacity(3 * triangles.len());
+    for triangle in triangles {
+      vertices.push(triangle.v1);
+      vertices.push(triangle.v2);
+      vertices.push(triangle.v3);
+    }
+
+    let buffer = GLBuffer::new(gl, vertices.len());
+    let mut mesh =
+      GLArray::new(
+        gl,
+        &shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      );
+    mesh.buffer.byte_buffer.bind(gl);
+    mesh.push(gl, &vertices);
+
+    self.meshes.insert(position, mesh);
+  }
+
+  /// Remove a chunk's water surface from VRAM, if it has one.
+  pub fn remove(&mut self, position: &chunk::position::T) {
+    self.meshes.remove(position);
+  }
+
+  /// Draw all the water.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&self, gl: &mut GLContext) {
+    for mesh in self.meshes.values() {
+      mesh.bind(gl);
+      mesh.draw(gl);
+    }
+  }
+}
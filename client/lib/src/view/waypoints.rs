@@ -0,0 +1,321 @@
+//! The player's waypoints, as the view shows them: a compass strip along the top of the HUD, with each waypoint's
+//! name and distance at its bearing, and a column of light standing on each one out in the world.
+
+use cgmath::{Point2, Point3, Vector2, Vector3, InnerSpace};
+use gl;
+use std;
+use yaglw;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::TextureUnit;
+
+use common::color::Color4;
+use common::protocol;
+
+use vertex::ColoredVertex;
+use view;
+use view::shaders;
+use view::ttf;
+use waypoint;
+
+/// The most beacons drawn at once.
+const MAX_BEACONS: usize = 64;
+/// Each beacon is drawn as two triangles.
+const VERTICES_PER_BEACON: i32 = 6;
+/// How high a beacon reaches above its waypoint.
+const BEACON_HEIGHT: f32 = 96.0;
+/// Beacons are brighter than white, so they bloom.
+const BEACON_COLOR: Color4<f32> = Color4 { r: 2.0, g: 1.6, b: 0.6, a: 0.5 };
+/// The color of waypoints on the compass.
+const MARKER_COLOR: Color4<f32> = Color4 { r: 1.0, g: 0.8, b: 0.3, a: 1.0 };
+
+/// The middle of the compass strip, in HUD units.
+const COMPASS_CENTER: Point2<f32> = Point2 { x: 0.0, y: 0.9 };
+const COMPASS_HALF_WIDTH: f32 = 0.6;
+const COMPASS_HEIGHT: f32 = 0.06;
+/// How far either side of straight ahead the compass shows, in radians.
+const COMPASS_HALF_SPAN: f32 = std::f32::consts::FRAC_PI_2;
+/// The compass points, and their bearings in eighths of a turn.
+const COMPASS_POINTS: [(&'static str, u32); 4] = [("N", 0), ("E", 2), ("S", 4), ("W", 6)];
+
+/// The per-instance data of a beacon in VRAM.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Instance {
+  position : Point3<f32>,
+  height   : f32,
+  color    : Color4<f32>,
+}
+
+/// How far clockwise from north (-z) a direction points, in radians.
+pub fn bearing(direction: &Vector3<f32>) -> f32 {
+  direction.x.atan2(-direction.z)
+}
+
+/// How far clockwise from `heading` a bearing is, in [-pi, pi).
+fn relative_bearing(bearing: f32, heading: f32) -> f32 {
+  let turn = 2.0 * std::f32::consts::PI;
+  let relative = (bearing - heading) % turn;
+  if relative < -std::f32::consts::PI {
+    relative + turn
+  } else if relative >= std::f32::consts::PI {
+    relative - turn
+  } else {
+    relative
+  }
+}
+
+/// Where a relative bearing falls on the compass strip, clamped to its ends.
+fn compass_x(relative_bearing: f32) -> f32 {
+  let x = relative_bearing / COMPASS_HALF_SPAN;
+  COMPASS_CENTER.x + COMPASS_HALF_WIDTH * x.max(-1.0).min(1.0)
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  saved     : waypoint::T,
+  /// The world the player's in. Only its waypoints are shown.
+  world     : protocol::WorldId,
+  /// Whether the beacons need uploading again.
+  dirty     : bool,
+  beacons   : usize,
+
+  gl_array  : yaglw::vertex_buffer::ArrayHandle<'a>,
+  instances : yaglw::vertex_buffer::GLBuffer<'a, Instance>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b:'a>(
+  gl: &'a mut GLContext,
+  shader: &yaglw::shader::Shader<'a>,
+) -> T<'b>
+{
+  use yaglw::vertex_buffer;
+
+  let gl_array = vertex_buffer::ArrayHandle::new(gl);
+  let instances = vertex_buffer::GLBuffer::new(gl, MAX_BEACONS);
+
+  unsafe {
+    gl::BindVertexArray(gl_array.gl_id);
+  }
+  shader.use_shader(gl);
+  instances.byte_buffer.bind(gl);
+  let attrib_span =
+    vertex_buffer::VertexAttribData::apply(
+      &[
+        vertex_buffer::VertexAttribData {
+          name: "position",
+          size: 3,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+        vertex_buffer::VertexAttribData {
+          name: "height",
+          size: 1,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+        vertex_buffer::VertexAttribData {
+          name: "in_color",
+          size: 4,
+          unit: vertex_buffer::GLType::Float,
+          divisor: 1,
+        },
+      ],
+      gl,
+      shader,
+    );
+  assert!(attrib_span == std::mem::size_of::<Instance>() as u32);
+
+  T {
+    saved     : waypoint::load(),
+    world     : protocol::WorldId(0),
+    dirty     : true,
+    beacons   : 0,
+
+    gl_array  : gl_array,
+    instances : instances,
+  }
+}
+
+impl<'a> T<'a> {
+  /// Show the waypoints in another world.
+  pub fn set_world(&mut self, world: protocol::WorldId) {
+    self.world = world;
+    self.dirty = true;
+  }
+
+  /// Put a waypoint where the player is, or move the one with that name here, and save the waypoints.
+  pub fn place(&mut self, name: &str, position: Point3<f32>) {
+    self.saved.place(name, self.world, position);
+    self.saved.save();
+    self.dirty = true;
+  }
+
+  /// Remove a waypoint, and save the waypoints. Returns whether there was one to remove.
+  pub fn remove(&mut self, name: &str) -> bool {
+    let removed = self.saved.remove(name, self.world);
+    if removed {
+      self.saved.save();
+      self.dirty = true;
+    }
+    removed
+  }
+
+  /// List this world's waypoints, with how far away each one is from `eye`.
+  pub fn describe(&self, eye: &Point3<f32>) -> String {
+    let waypoints = self.saved.in_world(self.world);
+    if waypoints.is_empty() {
+      return String::from("No waypoints here. Add one with /waypoint add <name>.")
+    }
+    let descriptions: Vec<String> =
+      waypoints.iter()
+      .map(|waypoint| format!("{} ({}m)", waypoint.name, (waypoint.position() - eye).magnitude().round()))
+      .collect();
+    format!("Waypoints: {}", descriptions.join(", "))
+  }
+
+  /// Upload the beacons, if they've changed.
+  pub fn update(&mut self, gl: &mut GLContext) {
+    if !self.dirty {
+      return
+    }
+    self.dirty = false;
+
+    let instances: Vec<Instance> =
+      self.saved.in_world(self.world).iter()
+      .take(MAX_BEACONS)
+      .map(|waypoint| {
+        Instance {
+          position : waypoint.position(),
+          height   : BEACON_HEIGHT,
+          color    : BEACON_COLOR,
+        }
+      })
+      .collect();
+    self.beacons = instances.len();
+    if instances.is_empty() {
+      return
+    }
+
+    self.instances.byte_buffer.bind(gl);
+    unsafe {
+      self.instances.byte_buffer.update(
+        gl,
+        0,
+        instances.as_ptr() as *const u8,
+        std::mem::size_of::<Instance>() * instances.len(),
+      );
+    }
+  }
+
+  /// Draw the beacons.
+  /// N.B. This does not bind any shaders.
+  pub fn draw_beacons(&self, _gl: &mut GLContext) {
+    if self.beacons == 0 {
+      return
+    }
+    unsafe {
+      gl::BindVertexArray(self.gl_array.gl_id);
+      gl::DrawArraysInstanced(gl::TRIANGLES, 0, VERTICES_PER_BEACON, self.beacons as i32);
+    }
+  }
+
+  /// Draw the compass strip for a player at `eye` facing `forward`.
+  pub fn draw_compass<'b>(
+    &self,
+    gl             : &'b mut GLContext,
+    texture_shader : &mut shaders::texture::T<'a>,
+    color_shader   : &mut shaders::color::T<'a>,
+    glyphs         : &ttf::Glyphs<'a>,
+    texture_unit   : &TextureUnit,
+    eye            : &Point3<f32>,
+    forward        : &Vector3<f32>,
+    window_size    : &Vector2<i32>,
+  ) where
+    'a: 'b,
+  {
+    let heading = bearing(forward);
+    let glyph_size = glyphs.size(window_size);
+    let top = COMPASS_CENTER.y + COMPASS_HEIGHT / 2.0;
+    let bottom = COMPASS_CENTER.y - COMPASS_HEIGHT / 2.0;
+
+    let mut triangles = Vec::new();
+    triangles.extend(
+      ColoredVertex::square(
+        Point2::new(COMPASS_CENTER.x - COMPASS_HALF_WIDTH, bottom),
+        Point2::new(COMPASS_CENTER.x + COMPASS_HALF_WIDTH, top),
+        Color4::of_rgba(0.0, 0.0, 0.0, 0.5),
+      ).iter()
+    );
+    let tick = |triangles: &mut Vec<ColoredVertex>, x: f32, height: f32, color| {
+      triangles.extend(
+        ColoredVertex::square(Point2::new(x - 0.002, bottom), Point2::new(x + 0.002, bottom + height), color).iter()
+      );
+    };
+    // Straight ahead.
+    tick(&mut triangles, COMPASS_CENTER.x, COMPASS_HEIGHT, Color4::of_rgba(1.0, 1.0, 1.0, 1.0));
+
+    let mut text = Vec::new();
+    for eighth in 0 .. 8 {
+      let relative = relative_bearing(eighth as f32 * std::f32::consts::FRAC_PI_4, heading);
+      if relative.abs() > COMPASS_HALF_SPAN {
+        continue
+      }
+      let x = compass_x(relative);
+      match COMPASS_POINTS.iter().find(|&&(_, e)| e == eighth) {
+        None => tick(&mut triangles, x, COMPASS_HEIGHT / 3.0, Color4::of_rgba(0.8, 0.8, 0.8, 0.8)),
+        Some(&(name, _)) =>
+          glyphs.push_text(
+            &mut text,
+            Point2::new(x - glyph_size.x / 2.0, COMPASS_CENTER.y - glyph_size.y / 2.0),
+            glyph_size.y,
+            name,
+          ),
+      }
+    }
+
+    for waypoint in self.saved.in_world(self.world) {
+      let to_waypoint = waypoint.position() - eye;
+      let x = compass_x(relative_bearing(bearing(&to_waypoint), heading));
+      let r = COMPASS_HEIGHT / 4.0;
+      triangles.extend(
+        ColoredVertex::square(Point2::new(x - r, top - 2.0 * r), Point2::new(x + r, top), MARKER_COLOR).iter()
+      );
+      let label = format!("{} {}m", waypoint.name, to_waypoint.magnitude().round());
+      let width = glyph_size.x * label.chars().count() as f32;
+      glyphs.push_text(&mut text, Point2::new(x - width / 2.0, bottom - glyph_size.y - 0.01), glyph_size.y, &label);
+    }
+
+    unsafe {
+      gl::Disable(gl::DEPTH_TEST);
+    }
+    color_shader.shader.use_shader(gl);
+    let triangles = view::build_hud_triangles(gl, color_shader, &triangles);
+    triangles.bind(gl);
+    triangles.draw(gl);
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+    }
+
+    if !text.is_empty() {
+      let quads = glyphs.build(gl, texture_shader, &text);
+      glyphs.draw(gl, texture_shader, texture_unit, &quads);
+    }
+  }
+}
+
+#[test]
+fn bearings_are_clockwise_from_north() {
+  let north = Vector3::new(0.0, 0.0, -1.0);
+  let east = Vector3::new(1.0, 0.0, 0.0);
+  assert_eq!(bearing(&north), 0.0);
+  assert!((bearing(&east) - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+  // Facing west, north is a quarter turn to the right, and east is behind.
+  let west = bearing(&-east);
+  assert!((relative_bearing(bearing(&north), west) - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+  assert!((relative_bearing(bearing(&east), west).abs() - std::f32::consts::PI).abs() < 1e-6);
+  // Anything behind is pinned to an end of the compass.
+  assert_eq!(compass_x(3.0), COMPASS_CENTER.x + COMPASS_HALF_WIDTH);
+  assert_eq!(compass_x(0.0), COMPASS_CENTER.x);
+}
@@ -0,0 +1,136 @@
+//! The weather the server says we're in, as the view sees it: rain or snow falling around the camera, terrain that
+//! gets darker the longer it rains on it, and the wind the grass leans in.
+
+use cgmath::{Point3, Vector2, Vector3};
+
+use common::protocol;
+
+use particles;
+
+/// The width of the square around the camera that rain and snow fall over.
+const FALL_WIDTH: f32 = 40.0;
+/// Raindrops given off per second, at full intensity.
+const RAIN_PER_SECOND: f32 = 1200.0;
+/// Snowflakes given off per second, at full intensity.
+const SNOW_PER_SECOND: f32 = 300.0;
+/// How fast rain and snow are blown sideways, in units per second, per unit of wind.
+const WIND_DRIFT: f32 = 8.0;
+/// How long it takes the view's wind to get most of the way to a new wind, in seconds.
+const WIND_EASE_SECONDS: f32 = 5.0;
+/// How long it takes a downpour to soak the terrain, in seconds.
+const SOAK_SECONDS: f32 = 20.0;
+/// How long it takes soaked terrain to dry out, in seconds.
+const DRY_SECONDS: f32 = 60.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  weather     : protocol::Weather,
+  /// How wet the terrain is, in [0, 1].
+  wetness     : f32,
+  /// Particles owed from previous frames, since a frame's worth is rarely a whole burst.
+  owed        : f32,
+  /// When the weather was last updated, in ns.
+  last_update : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    weather     :
+      protocol::Weather {
+        precipitation : protocol::Precipitation::None,
+        intensity     : 0.0,
+        wind          : Vector2::new(0.4, 0.15),
+      },
+    wetness     : 0.0,
+    owed        : 0.0,
+    last_update : None,
+  }
+}
+
+/// How wet the terrain is after `dt` more seconds of some weather.
+fn soak(wetness: f32, weather: &protocol::Weather, dt: f32) -> f32 {
+  let wetness =
+    if weather.precipitation == protocol::Precipitation::Rain {
+      wetness + weather.intensity * dt / SOAK_SECONDS
+    } else {
+      wetness - dt / DRY_SECONDS
+    };
+  wetness.max(0.0).min(1.0)
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn set(&mut self, weather: protocol::Weather) {
+    self.weather = weather;
+  }
+
+  /// How wet the terrain is, in [0, 1].
+  pub fn wetness(&self) -> f32 {
+    self.wetness
+  }
+
+  /// Move the weather along to time `now` (in ns): let rain and snow fall around `camera`, soak or dry the
+  /// terrain, and ease `wind` towards the weather's wind.
+  pub fn update(
+    &mut self,
+    now       : u64,
+    camera    : &Point3<f32>,
+    particles : &mut particles::T,
+    wind      : &mut Vector2<f32>,
+  ) {
+    let dt = self.last_update.map_or(0.0, |last| (now - last) as f32 / 1e9);
+    self.last_update = Some(now);
+
+    self.wetness = soak(self.wetness, &self.weather, dt);
+    let ease = f32::min(1.0, dt / WIND_EASE_SECONDS);
+    *wind = *wind + (self.weather.wind - *wind) * ease;
+
+    let (emitter, per_second) =
+      match self.weather.precipitation {
+        protocol::Precipitation::None => {
+          self.owed = 0.0;
+          return
+        },
+        protocol::Precipitation::Rain => (particles::emitter::rain(*camera, FALL_WIDTH), RAIN_PER_SECOND),
+        protocol::Precipitation::Snow => (particles::emitter::snow(*camera, FALL_WIDTH), SNOW_PER_SECOND),
+      };
+
+    self.owed += per_second * self.weather.intensity * dt;
+    let bursts = (self.owed / emitter.count as f32) as u32;
+    if bursts == 0 {
+      return
+    }
+    self.owed -= (bursts * emitter.count) as f32;
+
+    // Start high enough, and far enough upwind, that the particles are around the camera halfway through their life.
+    let drift = Vector3::new(wind.x, 0.0, wind.y) * WIND_DRIFT;
+    let fall = -emitter.velocity.y * emitter.lifetime;
+    let emitter =
+      particles::Emitter {
+        position : *camera + Vector3::new(0.0, fall / 2.0, 0.0) - drift * emitter.lifetime / 2.0,
+        velocity : emitter.velocity + drift,
+        .. emitter
+      };
+    for _ in 0 .. bursts {
+      particles.emit(&emitter);
+    }
+  }
+}
+
+#[test]
+fn rain_soaks_the_terrain_and_it_dries_out() {
+  let rain =
+    protocol::Weather {
+      precipitation : protocol::Precipitation::Rain,
+      intensity     : 1.0,
+      wind          : Vector2::new(0.0, 0.0),
+    };
+  let snow = protocol::Weather { precipitation: protocol::Precipitation::Snow, .. rain };
+
+  let wet = soak(0.0, &rain, SOAK_SECONDS / 2.0);
+  assert!(wet > 0.4 && wet < 0.6);
+  assert_eq!(soak(wet, &rain, SOAK_SECONDS), 1.0);
+  assert!(soak(1.0, &snow, 1.0) < 1.0);
+  assert_eq!(soak(1.0, &snow, DRY_SECONDS), 0.0);
+}
@@ -0,0 +1,172 @@
+//! Light carried through the air from voxel to voxel. Sky light falls straight down every column that's open to the
+//! sky and spreads sideways under overhangs and into cave mouths; torch light spreads out from each point light.
+//! Both fade by a level for every voxel they travel, and neither gets through solid terrain, so caves are dark
+//! unless something lights them, and torches don't shine through walls.
+//!
+//! Light is worked out per chunk, over the chunk's voxels and a margin around it so light from just outside gets in.
+
+use cgmath::{Point3, Vector2, Vector3, InnerSpace};
+use std;
+
+use common::protocol;
+
+/// The level of full sky light, and the most a torch can have.
+pub const MAX_LEVEL: u8 = 15;
+/// How many voxels around a chunk light is propagated through, on each side.
+const MARGIN: i32 = 8;
+/// How far above a chunk (in voxels) to look for a roof, before deciding a column is open to the sky.
+const SKY_SCAN: i32 = 32;
+
+/// Light levels over a chunk and its margin.
+pub struct Grid {
+  /// The lowest voxel in the grid.
+  low   : Point3<i32>,
+  /// The number of voxels along each edge of the grid.
+  edge  : i32,
+  /// The width of a voxel.
+  size  : f32,
+  sky   : Vec<u8>,
+  torch : Vec<u8>,
+}
+
+impl Grid {
+  fn index(&self, x: i32, y: i32, z: i32) -> usize {
+    ((y * self.edge + z) * self.edge + x) as usize
+  }
+
+  /// How much sky light and torch light (in that order, in [0, 1]) fall on a vertex with some normal.
+  /// The light's read from the voxel just off the surface, clamped into the grid.
+  pub fn at(&self, position: &Point3<f32>, normal: &Vector3<f32>) -> Vector2<f32> {
+    let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::new(0.0, 1.0, 0.0) };
+    let p = *position + normal * (self.size / 2.0);
+    let cell = |x: f32, low: i32| std::cmp::max(0, std::cmp::min(self.edge - 1, (x / self.size).floor() as i32 - low));
+    let i = self.index(cell(p.x, self.low.x), cell(p.y, self.low.y), cell(p.z, self.low.z));
+    Vector2::new(self.sky[i] as f32 / MAX_LEVEL as f32, self.torch[i] as f32 / MAX_LEVEL as f32)
+  }
+}
+
+/// Spread light out from the cells in `queue`, losing a level with each step.
+fn spread(edge: i32, solid: &[bool], levels: &mut [u8], mut queue: std::collections::VecDeque<(i32, i32, i32)>) {
+  let index = |x: i32, y: i32, z: i32| ((y * edge + z) * edge + x) as usize;
+  while let Some((x, y, z)) = queue.pop_front() {
+    let level = levels[index(x, y, z)];
+    if level <= 1 {
+      continue
+    }
+    let neighbors = [(x - 1, y, z), (x + 1, y, z), (x, y - 1, z), (x, y + 1, z), (x, y, z - 1), (x, y, z + 1)];
+    for &(x, y, z) in &neighbors {
+      if x < 0 || y < 0 || z < 0 || x >= edge || y >= edge || z >= edge {
+        continue
+      }
+      let i = index(x, y, z);
+      if !solid[i] && levels[i] < level - 1 {
+        levels[i] = level - 1;
+        queue.push_back((x, y, z));
+      }
+    }
+  }
+}
+
+/// Work out the light over a chunk whose lowest voxel is `chunk_low`, `edge_samples` voxels of
+/// `2^lg_sample_size` across. `is_solid` says whether the voxel at some coordinates blocks light; `sky` is whether
+/// the world has a sky at all.
+pub fn propagate<IsSolid>(
+  is_solid       : IsSolid,
+  lg_sample_size : i16,
+  chunk_low      : Point3<i32>,
+  edge_samples   : i32,
+  sky            : bool,
+  lights         : &[protocol::Light],
+) -> Grid where
+  IsSolid : Fn(Point3<i32>) -> bool,
+{
+  let edge = edge_samples + 2 * MARGIN;
+  let cells = (edge * edge * edge) as usize;
+  let mut grid =
+    Grid {
+      low   : chunk_low + Vector3::new(-MARGIN, -MARGIN, -MARGIN),
+      edge  : edge,
+      size  : (1 << lg_sample_size) as f32,
+      sky   : vec!(0; cells),
+      torch : vec!(0; cells),
+    };
+  let low = grid.low;
+
+  let mut solid = vec!(false; cells);
+  for y in 0 .. edge {
+  for z in 0 .. edge {
+  for x in 0 .. edge {
+    solid[grid.index(x, y, z)] = is_solid(Point3::new(low.x + x, low.y + y, low.z + z));
+  }}}
+
+  if sky {
+    let mut queue = std::collections::VecDeque::new();
+    for z in 0 .. edge {
+    for x in 0 .. edge {
+      let roofed = (0 .. SKY_SCAN).any(|dy| is_solid(Point3::new(low.x + x, low.y + edge + dy, low.z + z)));
+      if roofed {
+        continue
+      }
+      // Sky light falls straight down without fading, until it hits something.
+      for y in (0 .. edge).rev() {
+        let i = grid.index(x, y, z);
+        if solid[i] {
+          break
+        }
+        grid.sky[i] = MAX_LEVEL;
+        queue.push_back((x, y, z));
+      }
+    }}
+    spread(edge, &solid, &mut grid.sky, queue);
+  }
+
+  let mut queue = std::collections::VecDeque::new();
+  for light in lights {
+    let cell = |x: f32, low: i32| (x / grid.size).floor() as i32 - low;
+    let (x, y, z) = (cell(light.position.x, low.x), cell(light.position.y, low.y), cell(light.position.z, low.z));
+    if x < 0 || y < 0 || z < 0 || x >= edge || y >= edge || z >= edge {
+      continue
+    }
+    let i = grid.index(x, y, z);
+    let level = std::cmp::min(MAX_LEVEL as i32, (light.radius / grid.size).ceil() as i32) as u8;
+    if !solid[i] && grid.torch[i] < level {
+      grid.torch[i] = level;
+      queue.push_back((x, y, z));
+    }
+  }
+  spread(edge, &solid, &mut grid.torch, queue);
+
+  grid
+}
+
+#[test]
+fn caves_are_dark_unless_lit() {
+  use common::color::Color3;
+
+  // Solid ground below y = 0, with a sealed cave from y = -6 to -2 and x = 0 to 5.
+  let is_solid = |p: Point3<i32>| {
+    let cave = -6 <= p.y && p.y < -2 && 0 <= p.x && p.x < 6 && 0 <= p.z && p.z < 6;
+    p.y < 0 && !cave
+  };
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  let grid = propagate(&is_solid, 0, Point3::new(0, -8, 0), 8, true, &[]);
+  assert_eq!(grid.at(&Point3::new(3.0, 0.0, 3.0), &up).x, 1.0);
+  assert_eq!(grid.at(&Point3::new(3.0, -6.0, 3.0), &up).x, 0.0);
+
+  // A torch in the cave lights it, but none of its light gets out.
+  let torch =
+    protocol::Light {
+      position : Point3::new(3.5, -5.5, 3.5),
+      color    : Color3::of_rgb(1.0, 0.6, 0.2),
+      radius   : 8.0,
+    };
+  let grid = propagate(&is_solid, 0, Point3::new(0, -8, 0), 8, true, &[torch]);
+  let near = grid.at(&Point3::new(3.0, -6.0, 3.0), &up).y;
+  let far = grid.at(&Point3::new(0.0, -6.0, 0.0), &up).y;
+  assert!(near > far && far > 0.0);
+  assert_eq!(grid.at(&Point3::new(3.0, 0.0, 3.0), &up).y, 0.0);
+
+  // Without a sky, nothing is sky-lit.
+  let grid = propagate(&is_solid, 0, Point3::new(0, -8, 0), 8, false, &[]);
+  assert_eq!(grid.at(&Point3::new(3.0, 0.0, 3.0), &up).x, 0.0);
+}
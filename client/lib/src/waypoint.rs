@@ -0,0 +1,176 @@
+//! Named places the player wants to find again. Each one belongs to a world, and they're all saved to a TOML file
+//! so they're still there next session.
+
+use cgmath::Point3;
+use std;
+use std::io::{Read, Write};
+use toml;
+
+use common::protocol;
+
+/// Where waypoints are saved, relative to the working directory.
+pub const PATH: &'static str = "waypoints.toml";
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+  pub name     : String,
+  /// The `WorldId` of the world it's in.
+  pub world    : u32,
+  pub position : [f32; 3],
+}
+
+impl Waypoint {
+  #[allow(missing_docs)]
+  pub fn position(&self) -> Point3<f32> {
+    Point3::new(self.position[0], self.position[1], self.position[2])
+  }
+}
+
+/// What's in the waypoints file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct File {
+  #[serde(default)]
+  waypoint : Vec<Waypoint>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  waypoints : Vec<Waypoint>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    waypoints : Vec::new(),
+  }
+}
+
+fn parse(contents: &str) -> Result<T, toml::de::Error> {
+  let file: File = toml::from_str(contents)?;
+  Ok(T { waypoints: file.waypoint })
+}
+
+/// Read the saved waypoints. If the file is missing or broken, there aren't any.
+pub fn load() -> T {
+  let mut contents = String::new();
+  if let Err(err) = std::fs::File::open(PATH).and_then(|mut file| file.read_to_string(&mut contents)) {
+    info!("Couldn't read {}: {}. Starting without waypoints.", PATH, err);
+    return new()
+  }
+
+  match parse(&contents) {
+    Ok(waypoints) => waypoints,
+    Err(err) => {
+      warn!("Couldn't parse {}: {}. Starting without waypoints.", PATH, err);
+      new()
+    },
+  }
+}
+
+impl T {
+  /// Write every waypoint out to `PATH`.
+  pub fn save(&self) {
+    let file = File { waypoint: self.waypoints.clone() };
+    let saved =
+      toml::to_string(&file)
+      .map_err(|err| err.to_string())
+      .and_then(|contents| {
+        std::fs::File::create(PATH)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|err| err.to_string())
+      });
+    if let Err(err) = saved {
+      warn!("Couldn't save {}: {}", PATH, err);
+    }
+  }
+
+  /// Put a waypoint somewhere, moving the one with the same name in the same world if there is one.
+  pub fn place(&mut self, name: &str, world: protocol::WorldId, position: Point3<f32>) {
+    self.remove(name, world);
+    self.waypoints.push(
+      Waypoint {
+        name     : String::from(name),
+        world    : world.0,
+        position : [position.x, position.y, position.z],
+      }
+    );
+  }
+
+  /// Remove a waypoint by name. Returns whether there was one to remove.
+  pub fn remove(&mut self, name: &str, world: protocol::WorldId) -> bool {
+    let len = self.waypoints.len();
+    self.waypoints.retain(|waypoint| waypoint.name != name || waypoint.world != world.0);
+    self.waypoints.len() < len
+  }
+
+  /// The waypoints in one world.
+  pub fn in_world(&self, world: protocol::WorldId) -> Vec<&Waypoint> {
+    self.waypoints.iter().filter(|waypoint| waypoint.world == world.0).collect()
+  }
+}
+
+/// Something to do with waypoints, typed into chat.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  /// Put a waypoint where the player is.
+  Place(String),
+  #[allow(missing_docs)]
+  Remove(String),
+  /// List the waypoints in this world, in chat.
+  List,
+}
+
+/// How to use the waypoint commands.
+pub const USAGE: &'static str = "Usage: /waypoint add <name>, /waypoint remove <name> or /waypoint list";
+
+/// Parse a chat message as a waypoint command. Returns `None` if the message isn't one, so it can go to the
+/// server as usual, and `Some(Err(..))` if it's a waypoint command that doesn't make sense.
+pub fn parse_command(text: &str) -> Option<Result<Command, String>> {
+  let mut words = text.trim().splitn(3, ' ');
+  if words.next() != Some("/waypoint") {
+    return None
+  }
+  let name = |name: Option<&str>| {
+    match name.map(|name| name.trim()) {
+      None | Some("") => Err(String::from(USAGE)),
+      Some(name) => Ok(String::from(name)),
+    }
+  };
+  let command =
+    match words.next() {
+      Some("add") => name(words.next()).map(Command::Place),
+      Some("remove") => name(words.next()).map(Command::Remove),
+      Some("list") => Ok(Command::List),
+      _ => Err(String::from(USAGE)),
+    };
+  Some(command)
+}
+
+#[test]
+fn waypoints_round_trip_through_the_file() {
+  let mut waypoints = new();
+  waypoints.place("home", protocol::WorldId(0), Point3::new(1.0, 2.0, 3.0));
+  waypoints.place("cave", protocol::WorldId(1), Point3::new(4.0, 5.0, 6.0));
+  waypoints.place("home", protocol::WorldId(0), Point3::new(7.0, 8.0, 9.0));
+  assert_eq!(waypoints.in_world(protocol::WorldId(0)).len(), 1);
+
+  let contents = toml::to_string(&File { waypoint: waypoints.waypoints.clone() }).unwrap();
+  let loaded = parse(&contents).unwrap();
+  assert_eq!(loaded.waypoints, waypoints.waypoints);
+  assert_eq!(loaded.in_world(protocol::WorldId(0))[0].position(), Point3::new(7.0, 8.0, 9.0));
+  assert!(parse("").unwrap().waypoints.is_empty());
+
+  assert!(waypoints.remove("home", protocol::WorldId(0)));
+  assert!(!waypoints.remove("cave", protocol::WorldId(0)));
+}
+
+#[test]
+fn waypoint_commands_are_parsed() {
+  assert_eq!(parse_command("hello"), None);
+  assert_eq!(parse_command("/waypoint add old mill"), Some(Ok(Command::Place(String::from("old mill")))));
+  assert_eq!(parse_command("/waypoint remove home"), Some(Ok(Command::Remove(String::from("home")))));
+  assert_eq!(parse_command("/waypoint list"), Some(Ok(Command::List)));
+  assert!(parse_command("/waypoint add").unwrap().is_err());
+  assert!(parse_command("/waypoint").unwrap().is_err());
+}
@@ -0,0 +1,12 @@
+#version 330 core
+
+in vec4 color;
+in vec2 corner;
+
+out vec4 frag_color;
+
+void main() {
+  // Brightest along the middle and at the bottom, fading out sideways and upwards.
+  float fade = (1.0 - abs(corner.x)) * (1.0 - corner.y);
+  frag_color = vec4(color.rgb, color.a * fade);
+}
@@ -0,0 +1,35 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+in vec3 position;
+in float height;
+in vec4 in_color;
+
+out vec4 color;
+out vec2 corner;
+
+const vec2 corners[6] = vec2[6](
+  vec2(-1, 0), vec2(1, 0), vec2(1, 1),
+  vec2(-1, 0), vec2(1, 1), vec2(-1, 1)
+);
+
+// How wide a beacon is, at any distance.
+const float width = 1.0;
+
+void main() {
+  // Stand the quad up from `position`, turned around the vertical to face the eye.
+  vec3 to_eye = eye_position - position;
+  to_eye.y = 0;
+  vec3 right = vec3(1, 0, 0);
+  if (dot(to_eye, to_eye) > 0) {
+    right = normalize(cross(vec3(0, 1, 0), to_eye));
+  }
+
+  corner = corners[gl_VertexID];
+  vec3 world_position = position + width / 2.0 * corner.x * right + vec3(0, height * corner.y, 0);
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
+  color = in_color;
+}
@@ -0,0 +1,21 @@
+#version 330 core
+
+uniform sampler2D source;
+// One texel along the direction to blur in, in texture coordinates.
+uniform vec2 texel_step;
+
+in vec2 uv;
+out vec4 frag_color;
+
+// A 9-tap gaussian, in 5 samples by reading between texels and letting linear filtering do the weighting.
+const float offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+  vec3 c = texture(source, uv).rgb * weights[0];
+  for (int i = 1; i < 3; ++i) {
+    c += texture(source, uv + texel_step * offsets[i]).rgb * weights[i];
+    c += texture(source, uv - texel_step * offsets[i]).rgb * weights[i];
+  }
+  frag_color = vec4(c, 1);
+}
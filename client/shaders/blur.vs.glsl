@@ -0,0 +1 @@
+include(fullscreen.glsl)
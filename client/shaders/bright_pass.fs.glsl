@@ -0,0 +1,15 @@
+#version 330 core
+
+uniform sampler2D scene;
+// How bright a pixel has to be to bloom.
+uniform float threshold;
+
+in vec2 uv;
+out vec4 frag_color;
+
+void main() {
+  vec3 c = texture(scene, uv).rgb;
+  // Keep only the part of each pixel past the threshold, so the edges of bright spots fade in.
+  float brightness = max(c.r, max(c.g, c.b));
+  frag_color = vec4(c * max(brightness - threshold, 0) / max(brightness, 0.0001), 1);
+}
@@ -0,0 +1,7 @@
+// The camera the world is drawn from. Laid out to match client/lib/src/view/shaders/blocks.rs.
+layout(std140) uniform Camera {
+  mat4 projection_matrix;
+  vec3 eye_position;
+  float near_clip;
+  float far_clip;
+};
@@ -0,0 +1,27 @@
+#version 330 core
+
+uniform sampler2D scene;
+uniform sampler2D bloom;
+uniform bool bloom_enabled;
+uniform float bloom_strength;
+uniform bool tonemapping_enabled;
+
+in vec2 uv;
+out vec4 frag_color;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+// https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+vec3 aces(vec3 x) {
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0, 1);
+}
+
+void main() {
+  vec3 c = texture(scene, uv).rgb;
+  if (bloom_enabled) {
+    c += bloom_strength * texture(bloom, uv).rgb;
+  }
+  if (tonemapping_enabled) {
+    c = aces(c);
+  }
+  frag_color = vec4(clamp(c, 0, 1), 1);
+}
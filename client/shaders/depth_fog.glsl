@@ -1,7 +1,27 @@
-float fog_density(float distance) {
-  return 1 - exp(-distance / 768);
+uniform struct Fog {
+  // The fog's color, before it's lit by the sun.
+  vec3 color;
+  // How quickly things fade into the fog with distance.
+  float density;
+} fog;
+
+// How much of something `distance` away in `view_direction` is hidden by fog.
+// Lines of sight toward the horizon stay low, in the thickest air, so they're fogged the most.
+float atmospheric_fog(vec3 view_direction, float distance) {
+  if (fog.density <= 0) {
+    return 0;
+  }
+  float thinning = 1 + 4 * abs(view_direction.y);
+  return 1 - exp(-fog.density * distance / thinning);
+}
+
+// The color of the fog looking in `view_direction`. Sunlight scatters toward the eye, so the fog
+// is brighter, and takes on the sun's color, when looking toward the sun.
+vec4 scattered_fog_color(vec3 view_direction, vec3 sun_direction, vec3 sun_intensity) {
+  float toward_sun = pow(max(dot(view_direction, sun_direction), 0), 8);
+  return vec4(mix(fog.color * sun_intensity, sun_intensity, toward_sun), 1);
 }
 
-vec4 apply_fog(vec4 base_color, vec4 fog_color, float distance) {
-  return mix(base_color, fog_color, fog_density(distance));
+vec4 apply_fog(vec4 base_color, vec4 fog_color, vec3 view_direction, float distance) {
+  return mix(base_color, fog_color, atmospheric_fog(view_direction, distance));
 }
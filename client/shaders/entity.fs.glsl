@@ -0,0 +1,23 @@
+#version 330 core
+
+include(camera.glsl)
+include(sun.glsl)
+include(shadow.glsl)
+include(depth_fog.glsl)
+include(point_lights.glsl)
+
+in vec4 color;
+in vec3 world_position;
+out vec4 frag_color;
+
+void main() {
+  // Entities are flat-shaded boxes, so each face's normal is the same as its triangles'.
+  vec3 normal = normalize(cross(dFdx(world_position), dFdy(world_position)));
+  float visibility = sun_visibility(world_position, gl_FragCoord.z / gl_FragCoord.w);
+  vec3 lit = color.rgb * (mix(0.6, 1.0, visibility) + point_lighting(world_position, normal));
+
+  vec3 view_direction = normalize(world_position - eye_position);
+  vec4 fog_color = scattered_fog_color(view_direction, sun.direction, sun.intensity);
+  frag_color =
+    apply_fog(vec4(lit, color.a), vec4(fog_color.rgb, color.a), view_direction, gl_FragCoord.z / gl_FragCoord.w);
+}
@@ -0,0 +1,46 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+// Each instance's skinning matrices, one after another, each as four columns.
+uniform samplerBuffer bones;
+uniform int bones_per_instance;
+
+// The shared mesh, in its rig's rest pose, inside a unit box around the origin.
+in vec3 position;
+// The bone this vertex moves with.
+in float bone;
+// 1 on the face turned toward the heading.
+in float facing;
+
+// Per instance.
+in vec3 center;
+in vec3 size;
+// An angle from +x toward +z.
+in float heading;
+in vec4 in_color;
+in vec4 facing_color;
+
+out vec4 color;
+out vec3 world_position;
+
+mat4 skinning_matrix() {
+  int i = 4 * (gl_InstanceID * bones_per_instance + int(bone));
+  return mat4(
+    texelFetch(bones, i),
+    texelFetch(bones, i + 1),
+    texelFetch(bones, i + 2),
+    texelFetch(bones, i + 3)
+  );
+}
+
+void main() {
+  vec3 d = (skinning_matrix() * vec4(position, 1.0)).xyz * size;
+  float s = sin(heading);
+  float c = cos(heading);
+  world_position = center + vec3(d.x * c - d.z * s, d.y, d.x * s + d.z * c);
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
+  color = facing > 0.5 ? facing_color : in_color;
+}
@@ -0,0 +1,10 @@
+#version 330 core
+
+// Texture coordinates across the screen, from (0, 0) at the bottom left to (1, 1) at the top right.
+out vec2 uv;
+
+// One triangle, big enough to cover the screen. Draw it with 3 vertices and no attributes.
+void main() {
+  uv = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+  gl_Position = vec4(uv * 2 - 1, 0, 1);
+}
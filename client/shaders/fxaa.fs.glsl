@@ -0,0 +1,50 @@
+#version 330 core
+
+// A tonemapped image to smooth the edges in.
+uniform sampler2D source;
+// The size of a pixel, in texture coordinates.
+uniform vec2 texel_size;
+
+in vec2 uv;
+out vec4 frag_color;
+
+// Based on Timothy Lottes' FXAA, in its simplest form: find the direction of the edge through each pixel from the
+// luma of its corners, and blur along it.
+const float reduce_min = 1.0 / 128.0;
+const float reduce_mul = 1.0 / 8.0;
+// The furthest along an edge to blur, in pixels.
+const float span_max = 8.0;
+
+float luma(vec3 c) {
+  return dot(c, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+  vec3 m = texture(source, uv).rgb;
+  float luma_m = luma(m);
+  float luma_nw = luma(texture(source, uv + vec2(-1, -1) * texel_size).rgb);
+  float luma_ne = luma(texture(source, uv + vec2( 1, -1) * texel_size).rgb);
+  float luma_sw = luma(texture(source, uv + vec2(-1,  1) * texel_size).rgb);
+  float luma_se = luma(texture(source, uv + vec2( 1,  1) * texel_size).rgb);
+  float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+  float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+  vec2 direction = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+  float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+  float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+  direction = clamp(direction * scale, vec2(-span_max), vec2(span_max)) * texel_size;
+
+  vec3 near =
+    0.5 * (
+      texture(source, uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+      texture(source, uv + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+  vec3 far =
+    0.5 * near + 0.25 * (
+      texture(source, uv - direction * 0.5).rgb +
+      texture(source, uv + direction * 0.5).rgb
+    );
+  // If the wider blur reaches past what's around the pixel, it's crossed another edge, so stick with the narrow one.
+  float luma_far = luma(far);
+  frag_color = vec4(luma_far < luma_min || luma_far > luma_max ? near : far, 1);
+}
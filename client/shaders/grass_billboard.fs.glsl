@@ -1,21 +1,18 @@
 #version 330 core
 
+include(camera.glsl)
+include(sun.glsl)
 include(depth_fog.glsl)
+include(point_lights.glsl)
+include(season.glsl)
 include(world_fragment.glsl)
 
-uniform struct Sun {
-  vec3 direction;
-  vec3 intensity;
-} sun;
-
-uniform vec3 ambient_light;
-uniform vec3 eye_position;
-
 uniform sampler2D texture_in;
 uniform float alpha_threshold;
 
 in vec2 vs_texture_position;
 in vec3 vs_normal;
+in vec3 vs_world_position;
 in float vs_tex_id;
 
 out vec4 frag_color;
@@ -31,14 +28,15 @@ void main() {
   if (c.a < alpha_threshold) {
     discard;
   }
-  vec3 world_position = vec3(gl_FragCoord.xy * gl_FragCoord.w, gl_FragCoord.w);
-  vec4 fog_color = vec4(sun.intensity, 1);
+  c.rgb *= foliage_tint();
+  vec3 view_direction = normalize(vs_world_position - eye_position);
+  vec4 fog_color = scattered_fog_color(view_direction, sun.direction, sun.intensity);
   frag_color =
     world_fragment(
       sun.direction,
       sun.intensity,
-      normalize(world_position - eye_position),
-      ambient_light,
+      view_direction,
+      sun.ambient + point_lighting(vs_world_position, vs_normal),
       c,
       1.0 / 0.0,
       vs_normal,
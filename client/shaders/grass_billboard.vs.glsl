@@ -3,11 +3,11 @@
 include(noise.glsl)
 include(adjust_depth_precision.glsl)
 
-uniform float near_clip;
-uniform float far_clip;
-uniform mat4 projection_matrix;
-uniform vec3 eye_position;
-uniform float time_ms;
+include(camera.glsl)
+include(window.glsl)
+
+// The wind's direction in the xz plane. Its length is the wind's strength.
+uniform vec2 wind;
 
 uniform samplerBuffer positions;
 uniform samplerBuffer normals;
@@ -17,9 +17,13 @@ in vec3 vertex_position;
 in vec3 model_translation;
 in int polygon_id;
 in uint tex_id;
+in float phase;
+// How flat the tuft's been trampled, in [0, 1].
+in float bend;
 
 out vec2 vs_texture_position;
 out vec3 vs_normal;
+out vec3 vs_world_position;
 out float vs_tex_id;
 
 mat3 between(vec3 v1, vec3 v2) {
@@ -102,18 +106,22 @@ void main() {
 
   mat4 shear = shearTo(inverse(rotate_normal) * vec3(0, 1, 0));
 
-  mat4 noise_shear;
+  mat4 wind_shear;
   {
-    vec3 billboard_seed = root / vec3(1 << 3, 1, 1 << 3);
-    float azimuth = 3.14 * cnoise(billboard_seed + vec3(122, -1, 14.5) + vec3(0, time_ms / 2000, 0));
-    float altitude = 3.14/2 - 3.14/4 * (cnoise(billboard_seed + vec3(-18.11, 101.1, 44.5) + vec3(0, time_ms / 2000, 0)) + 1) / 2.0;
-    vec3 v =
-      vec3(
-        cos(altitude) * cos(azimuth),
-        sin(altitude),
-        cos(altitude) * sin(azimuth)
-      );
-    noise_shear = shearTo(v);
+    float wind_strength = length(wind);
+    vec2 wind_direction = wind / max(wind_strength, 0.0001);
+    float t = time_ms / 1000;
+    // Gusts roll across the field in the direction of the wind.
+    vec2 gust_seed = root.xz / 32 - wind_direction * t / 4;
+    float gust = (cnoise(vec3(gust_seed.x, 17.3, gust_seed.y)) + 1) / 2;
+    // Each tuft also sways back and forth on its own.
+    float sway = sin(2 * t + phase);
+    float lean = wind_strength * (gust + 0.25 * sway);
+    // Trampled tufts are pushed over, each its own way, and barely move in the wind.
+    lean *= 1 - bend;
+    vec2 pushed = 3 * bend * vec2(cos(phase), sin(phase));
+    vec3 v = normalize(vec3(wind_direction.x * lean + pushed.x, 1, wind_direction.y * lean + pushed.y));
+    wind_shear = shearTo(v);
   }
 
   // this is duplicated in the terrain shader
@@ -127,14 +135,14 @@ void main() {
   float max_side = max(max(side_length[0], side_length[1]), side_length[2]);
   float min_side = min(min(side_length[0], side_length[1]), side_length[2]);
   float side_scale = (min_side + max_side) / 2.0;
-  scale[1].y = grassiness * 0.8;
+  scale[1].y = grassiness * 0.8 * (1 - 0.6 * bend);
   scale[0].x = grassiness * side_scale * 1.0;
   scale[2].z = scale[0].x;
 
   mat4 model_translation_mat = mat4(1.0);
   model_translation_mat[3].xyz = model_translation;
 
-  mat4 shear_mat = shear * noise_shear;
+  mat4 shear_mat = shear * wind_shear;
   mat4 to_world_mat = translation * rotation * model_translation_mat;
 
   vec4 scaled = scale * vec4(vertex_position, 1);
@@ -142,14 +150,10 @@ void main() {
   float length_ratio = length(vec3(scaled)) / length(vec3(sheared));
   sheared *= vec4(vec3(length_ratio), 1);
 
-  gl_Position =
-    adjust_depth_precision(
-      near_clip,
-      far_clip,
-      projection_matrix *
-      to_world_mat *
-      sheared
-    );
+  vec4 world_position = to_world_mat * sheared;
+  vs_world_position = world_position.xyz;
+
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * world_position);
 
   vs_normal = normal;
 }
@@ -0,0 +1,19 @@
+#version 330 core
+
+include(camera.glsl)
+include(sun.glsl)
+include(shadow.glsl)
+include(depth_fog.glsl)
+
+in vec4 color;
+in vec3 world_position;
+out vec4 frag_color;
+
+void main() {
+  float visibility = sun_visibility(world_position, gl_FragCoord.z / gl_FragCoord.w);
+  vec4 lit = vec4(color.rgb * mix(0.6, 1.0, visibility), color.a);
+
+  vec3 view_direction = normalize(world_position - eye_position);
+  vec4 fog_color = scattered_fog_color(view_direction, sun.direction, sun.intensity);
+  frag_color = apply_fog(lit, vec4(fog_color.rgb, color.a), view_direction, gl_FragCoord.z / gl_FragCoord.w);
+}
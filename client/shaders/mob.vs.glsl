@@ -0,0 +1,17 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+in vec3 position;
+in vec4 in_color;
+
+out vec4 color;
+out vec3 world_position;
+
+void main() {
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(position, 1.0));
+  color = in_color;
+  world_position = position;
+}
@@ -0,0 +1,5 @@
+#version 330 core
+
+// Nothing is written; only whether any fragments pass the depth test matters.
+void main() {
+}
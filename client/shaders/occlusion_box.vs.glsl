@@ -0,0 +1,25 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+uniform vec3 box_min;
+uniform vec3 box_max;
+
+// The corners of each of the box's 12 triangles. Bit i of a corner picks box_max over box_min on axis i.
+const int corners[36] = int[36](
+  0, 2, 3, 0, 3, 1,
+  4, 5, 7, 4, 7, 6,
+  0, 4, 6, 0, 6, 2,
+  1, 3, 7, 1, 7, 5,
+  0, 1, 5, 0, 5, 4,
+  2, 6, 7, 2, 7, 3
+);
+
+void main() {
+  int corner = corners[gl_VertexID];
+  vec3 world_position =
+    mix(box_min, box_max, vec3(float(corner & 1), float((corner >> 1) & 1), float((corner >> 2) & 1)));
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
+}
@@ -0,0 +1,22 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+uniform vec3 box_min;
+uniform vec3 box_max;
+
+// The ends of each of the box's 12 edges. Bit i of a corner picks box_max over box_min on axis i.
+const int corners[24] = int[24](
+  0, 1, 2, 3, 4, 5, 6, 7,
+  0, 2, 1, 3, 4, 6, 5, 7,
+  0, 4, 1, 5, 2, 6, 3, 7
+);
+
+void main() {
+  int corner = corners[gl_VertexID];
+  vec3 world_position =
+    mix(box_min, box_max, vec3(float(corner & 1), float((corner >> 1) & 1), float((corner >> 2) & 1)));
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
+}
@@ -0,0 +1,14 @@
+#version 330 core
+
+in vec4 color;
+in vec2 corner;
+
+out vec4 frag_color;
+
+void main() {
+  // Round off the quad's corners.
+  if (dot(corner, corner) > 1.0) {
+    discard;
+  }
+  frag_color = color;
+}
@@ -0,0 +1,29 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+in vec3 position;
+in float size;
+in vec4 in_color;
+
+out vec4 color;
+out vec2 corner;
+
+const vec2 corners[6] = vec2[6](
+  vec2(-1, -1), vec2(1, -1), vec2(1, 1),
+  vec2(-1, -1), vec2(1, 1), vec2(-1, 1)
+);
+
+void main() {
+  // Turn the quad to face the eye.
+  vec3 to_eye = normalize(eye_position - position);
+  vec3 right = normalize(cross(vec3(0, 1, 0), to_eye));
+  vec3 up = cross(to_eye, right);
+
+  corner = corners[gl_VertexID];
+  vec3 world_position = position + size / 2.0 * (corner.x * right + corner.y * up);
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
+  color = in_color;
+}
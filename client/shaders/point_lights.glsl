@@ -0,0 +1,62 @@
+// Needs camera.glsl included first, for the clip distances.
+// Torches and the like. Only the first point_light_count of each array are set.
+// The arrays' size is FORWARD_LIGHTS in client/lib/src/view/light.rs.
+uniform int point_light_count;
+uniform vec3 point_light_positions[16];
+uniform vec3 point_light_colors[16];
+uniform float point_light_radii[16];
+
+// With clustered lighting, the lights are read from buffer textures instead; see client/lib/src/view/clusters.rs.
+uniform bool clustered_lighting;
+// Tiles across, tiles up, and depth slices.
+uniform ivec3 cluster_counts;
+uniform vec2 viewport_size;
+// The offset into cluster_light_indices of each cluster's lights, and how many there are.
+uniform usamplerBuffer cluster_cells;
+uniform usamplerBuffer cluster_light_indices;
+// Two texels per light: its position and radius, then its color.
+uniform samplerBuffer cluster_lights;
+
+// The light falling on a surface from one point light. It fades out to nothing at the light's radius.
+vec3 point_light(vec3 position, vec3 normal, vec3 light_position, vec3 light_color, float radius) {
+  vec3 to_light = light_position - position;
+  float distance = length(to_light);
+  if (distance >= radius) {
+    return vec3(0);
+  }
+  float falloff = 1 - distance / radius;
+  float diffuse = clamp(dot(normal, to_light / max(distance, 0.0001)), 0, 1);
+  return light_color * diffuse * falloff * falloff;
+}
+
+// The index of the cluster this fragment is in. Slices get exponentially deeper further away.
+int cluster() {
+  ivec2 tile = ivec2(gl_FragCoord.xy / viewport_size * vec2(cluster_counts.xy));
+  tile = clamp(tile, ivec2(0), cluster_counts.xy - 1);
+  float depth = 1.0 / gl_FragCoord.w;
+  int slice = 0;
+  if (depth > near_clip) {
+    slice = int(log(depth / near_clip) / log(far_clip / near_clip) * float(cluster_counts.z));
+    slice = clamp(slice, 0, cluster_counts.z - 1);
+  }
+  return (slice * cluster_counts.y + tile.y) * cluster_counts.x + tile.x;
+}
+
+// The light falling on a surface from every point light.
+vec3 point_lighting(vec3 position, vec3 normal) {
+  vec3 total = vec3(0);
+  if (clustered_lighting) {
+    uvec2 cell = texelFetch(cluster_cells, cluster()).rg;
+    for (uint i = 0u; i < cell.y; ++i) {
+      int light = int(texelFetch(cluster_light_indices, int(cell.x + i)).r);
+      vec4 position_radius = texelFetch(cluster_lights, 2 * light);
+      vec3 color = texelFetch(cluster_lights, 2 * light + 1).rgb;
+      total += point_light(position, normal, position_radius.xyz, color, position_radius.w);
+    }
+  } else {
+    for (int i = 0; i < point_light_count; ++i) {
+      total += point_light(position, normal, point_light_positions[i], point_light_colors[i], point_light_radii[i]);
+    }
+  }
+  return total;
+}
@@ -0,0 +1,33 @@
+#version 330 core
+
+include(window.glsl)
+
+uniform sampler2D source;
+uniform bool underwater;
+// How strongly to flash red because the player was hurt, in [0, 1].
+uniform float damage;
+
+in vec2 uv;
+out vec4 frag_color;
+
+const vec3 water_color = vec3(0.05, 0.25, 0.35);
+const vec3 blood_color = vec3(0.8, 0.0, 0.0);
+
+void main() {
+  vec2 p = uv;
+  if (underwater) {
+    // Wobble the view, like looking through moving water.
+    float t = time_ms / 1000;
+    p += 0.004 * vec2(sin(uv.y * 40 + t * 2.0), cos(uv.x * 30 + t * 1.7));
+  }
+  vec3 c = texture(source, clamp(p, vec2(0), vec2(1))).rgb;
+
+  // 0 in the middle of the screen, and 1 in the corners.
+  float edge = length(uv - 0.5) * sqrt(2.0);
+  if (underwater) {
+    c = mix(c, water_color, 0.5);
+    c *= 1 - 0.5 * edge * edge;
+  }
+  c = mix(c, blood_color, damage * mix(0.25, 0.7, edge * edge));
+  frag_color = vec4(c, 1);
+}
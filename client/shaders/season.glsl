@@ -0,0 +1,21 @@
+// The seasons; see common/season.rs.
+// How far through the year it is, in [0, 1). Spring starts at 0, summer at 0.25, autumn at 0.5 and winter at 0.75.
+uniform float season;
+// The height above which snow settles on the ground.
+uniform float snow_line;
+
+// What plants are tinted by at this time of year: fresh in spring, full green in summer, turning in autumn, and
+// dry in winter.
+vec3 foliage_tint() {
+  vec3 ramp[5] =
+    vec3[5](
+      vec3(0.9, 1.1, 0.8),
+      vec3(1.0, 1.0, 1.0),
+      vec3(1.4, 0.8, 0.45),
+      vec3(0.9, 0.85, 0.7),
+      vec3(0.9, 1.1, 0.8)
+    );
+  float t = fract(season) * 4;
+  int i = int(t);
+  return mix(ramp[i], ramp[i + 1], fract(t));
+}
@@ -0,0 +1,29 @@
+// The array sizes here must match view::shadow::MAX_CASCADES.
+uniform sampler2DArrayShadow shadow_map;
+uniform int shadow_cascade_count;
+uniform mat4 shadow_matrices[4];
+uniform float shadow_cascade_ends[4];
+
+// The portion of direct sunlight that reaches a point, in [0, 1].
+float sun_visibility(vec3 world_position, float view_depth) {
+  for (int i = 0; i < shadow_cascade_count; ++i) {
+    if (view_depth < shadow_cascade_ends[i]) {
+      vec4 p = shadow_matrices[i] * vec4(world_position, 1);
+      p.xyz = p.xyz / p.w * 0.5 + 0.5;
+      // Farther cascades cover more area per texel, so they need more bias.
+      float bias = 0.0005 * (i + 1);
+
+      // Average a few samples to soften the edges.
+      vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+      float lit = 0;
+      for (int x = -1; x <= 1; x += 2) {
+        for (int y = -1; y <= 1; y += 2) {
+          vec2 offset = 0.5 * vec2(x, y) * texel;
+          lit += texture(shadow_map, vec4(p.xy + offset, i, p.z - bias));
+        }
+      }
+      return lit / 4;
+    }
+  }
+  return 1;
+}
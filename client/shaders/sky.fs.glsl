@@ -2,20 +2,21 @@
 
 include(depth_fog.glsl)
 include(noise.glsl)
+include(camera.glsl)
+include(sun.glsl)
+include(window.glsl)
 
-uniform vec2 window_size;
-
-uniform struct Sun {
+uniform struct Moon {
   vec3 direction;
   vec3 intensity;
-} sun;
-
-const float sun_angular_radius = 3.14/32;
+} moon;
 
-uniform mat4 projection_matrix;
-uniform vec3 eye_position;
+// How visible the stars are, in [0, 1].
+uniform float star_intensity;
 
-uniform float time_ms;
+const float sun_angular_radius = 3.14/32;
+const float moon_angular_radius = 3.14/64;
+const vec3 night_color = vec3(0.01, 0.01, 0.04);
 
 out vec4 frag_color;
 
@@ -39,8 +40,31 @@ float cloud_density(vec3 seed) {
   return d;
 }
 
+// A cheap hash of a grid cell, in [0, 1).
+float hash(vec3 cell) {
+  return fract(sin(dot(cell, vec3(12.9898, 78.233, 37.719))) * 43758.5453);
+}
+
+float stars(vec3 direction) {
+  // The stars turn with the sun, which moves through the xy plane.
+  vec2 s = normalize(sun.direction.xy);
+  direction = vec3(dot(direction.xy, s), dot(direction.xy, vec2(-s.y, s.x)), direction.z);
+
+  const float density = 0.997;
+  vec3 p = direction * 300;
+  vec3 cell = floor(p);
+  float h = hash(cell);
+  if (h < density) {
+    return 0;
+  }
+  float brightness = (h - density) / (1 - density);
+  float twinkle = 0.75 + 0.25 * sin(time_ms / 300 + 100 * h);
+  return brightness * twinkle * (1 - smoothstep(0.1, 0.5, length(p - cell - 0.5)));
+}
+
 void main() {
   vec3 direction = pixel_direction(gl_FragCoord.xy);
+  float daylight = 1 - star_intensity;
 
   const int HEIGHTS = 2;
   float heights[HEIGHTS] = float[](150, 1000);
@@ -56,7 +80,8 @@ void main() {
     } else {
       vec3 seed = (eye_position + dist * direction + offsets[i]) / 1000 * vec3(1, 4, 1);
 
-      float depth_alpha = fog_density(dist / 16);
+      // Clouds fade out into the distance.
+      float depth_alpha = 1 - exp(-dist / (16 * 768));
 
       float density = cloud_density(seed);
 
@@ -69,6 +94,7 @@ void main() {
 
       float lightness = pow(max(density - cloud_density(seed + 10 * sun.direction), 0), 1.0) * (1 - density);
       vec3 cloud_color = vec3(mix(0.4, 1, lightness));
+      cloud_color *= mix(night_color + moon.intensity / 4, vec3(1), daylight);
       c += alpha * cloud_alpha * cloud_color;
       alpha *= (1 - cloud_alpha);
     }
@@ -76,7 +102,17 @@ void main() {
 
   float sunniness = exp(64 * (dot(sun.direction, direction) - cos(sun_angular_radius)));
   vec3 infinity_color = mix(sun.intensity, vec3(1), sunniness);
+  infinity_color = mix(night_color, infinity_color, daylight);
+
+  float mooniness = min(exp(256 * (dot(moon.direction, direction) - cos(moon_angular_radius))), 1);
+  infinity_color += mooniness * moon.intensity;
+  infinity_color += star_intensity * (1 - mooniness) * vec3(stars(direction));
+
   c += alpha * infinity_color;
+  c = min(c, vec3(1));
+
+  // The sky's drawn into an HDR target, so the sun's disc can be brighter than white, which makes it bloom.
+  c += alpha * daylight * 8 * max(sunniness - 1, 0) * sun.intensity;
 
-  frag_color = min(vec4(c, 1), vec4(1));
+  frag_color = vec4(c, 1);
 }
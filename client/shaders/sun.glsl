@@ -0,0 +1,6 @@
+// Direct and ambient light from the sun. Laid out to match client/lib/src/view/shaders/blocks.rs.
+layout(std140) uniform Sun {
+  vec3 direction;
+  vec3 intensity;
+  vec3 ambient;
+} sun;
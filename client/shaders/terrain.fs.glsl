@@ -1,97 +1,148 @@
 #version 330 core
 
-uniform struct Sun {
-  vec3 direction;
-  vec3 intensity;
-} sun;
+include(camera.glsl)
+include(sun.glsl)
 
-uniform vec3 ambient_light;
-uniform vec3 eye_position;
-
-uniform samplerBuffer positions;
+// Layer 2*m of each array is material m seen from above, and layer 2*m + 1 is it seen from the side.
+uniform sampler2DArray material_albedo;
+uniform sampler2DArray material_normals;
+// World units spanned by each material's textures.
+uniform float material_scales[9];
+uniform float material_shininess[9];
+// How brightly each material glows on its own.
+uniform float material_emission[9];
+// The materials tinted and laid down by the seasons.
+uniform int snow_material;
+uniform int leaves_material;
+// Set for the depth prepass, which only fills the depth buffer.
+uniform bool depth_only;
+// How long it's been raining, in [0, 1]. Wet ground is darker and shinier.
+uniform float wetness;
 
 in vec3 world_position;
 in vec3 vs_normal;
-flat in int material;
+// How exposed this point is to ambient light, in [0, 1]. Lower in creases.
+in float vs_ambient_occlusion;
+// How much sky light and torch light have found their way here through the air, in [0, 1].
+// Caves get no sky light, and torches don't light anything on the far side of a wall.
+in vec2 vs_light;
+flat in ivec3 corner_materials;
+in vec3 material_weights;
 
 out vec4 frag_color;
 
 include(depth_fog.glsl)
-include(world_fragment.glsl)
 include(noise.glsl)
-include(grass.glsl)
-include(dirt.glsl)
-include(bark.glsl)
-include(leaves.glsl)
-include(stone.glsl)
+include(season.glsl)
+include(shadow.glsl)
+include(point_lights.glsl)
+include(world_fragment.glsl)
 
-// http://www.neilmendoza.com/glsl-rotation-about-an-arbitrary-axis/
-mat3 rotationMatrix(vec3 axis, float angle)
-{
-    axis = normalize(axis);
-    float s = sin(angle);
-    float c = cos(angle);
-    float oc = 1.0 - c;
+// How much the projection along each axis contributes, for a surface with some normal.
+vec3 triplanar_weights(vec3 normal) {
+  vec3 w = pow(abs(normal), vec3(4));
+  return w / (w.x + w.y + w.z);
+}
 
-    return mat3(oc * axis.x * axis.x + c,           oc * axis.x * axis.y - axis.z * s,  oc * axis.z * axis.x + axis.y * s,
-                oc * axis.x * axis.y + axis.z * s,  oc * axis.y * axis.y + c,           oc * axis.y * axis.z - axis.x * s,
-                oc * axis.z * axis.x - axis.y * s,  oc * axis.y * axis.z + axis.x * s,  oc * axis.z * axis.z + c         );
+// The layer to use for the projection along the y axis. Overhangs get the side texture too.
+float top_layer(int material, vec3 normal) {
+  return normal.y > 0 ? 2*material : 2*material + 1;
 }
 
-vec3 bump_map(float shallowness, float frequency, vec3 v) {
-  vec3 seed = frequency * world_position + vec3(0x123411);
-  float p0 = cnoise(seed);
-  float d = 0.1;
-  float px = cnoise(seed + vec3(d, 0, 0));
-  float py = cnoise(seed + vec3(0, d, 0));
-  float pz = cnoise(seed + vec3(0, 0, d));
-  vec3 r = normalize(vec3(px, py, pz) - vec3(p0));
+vec3 splat_albedo(int material, vec3 axes, vec3 normal) {
+  float s = 1.0 / material_scales[material];
+  float side = 2*material + 1;
+  vec3 x = texture(material_albedo, vec3(world_position.zy * s, side)).rgb;
+  vec3 y = texture(material_albedo, vec3(world_position.xz * s, top_layer(material, normal))).rgb;
+  vec3 z = texture(material_albedo, vec3(world_position.xy * s, side)).rgb;
+  return x*axes.x + y*axes.y + z*axes.z;
+}
 
-  vec3 axis = cross(vec3(0, 1, 0), r);
-  float c = dot(vec3(0, 1, 0), r);
-  return rotationMatrix(axis, acos(c) / shallowness) * v;
+// Perturb the surface normal by the material's normal maps, using a "UDN" blend of each projection.
+vec3 splat_normal(int material, vec3 axes, vec3 normal) {
+  float s = 1.0 / material_scales[material];
+  float side = 2*material + 1;
+  vec3 x = texture(material_normals, vec3(world_position.zy * s, side)).xyz * 2 - 1;
+  vec3 y = texture(material_normals, vec3(world_position.xz * s, top_layer(material, normal))).xyz * 2 - 1;
+  vec3 z = texture(material_normals, vec3(world_position.xy * s, side)).xyz * 2 - 1;
+  x = vec3(x.xy + normal.zy, normal.x);
+  y = vec3(y.xy + normal.xz, normal.y);
+  z = vec3(z.xy + normal.xy, normal.z);
+  return normalize(x.zyx*axes.x + y.xzy*axes.y + z.xyz*axes.z);
 }
 
 void main() {
-  vec4 base_color;
+  if (depth_only) {
+    return;
+  }
+  vec3 surface_normal = normalize(vs_normal);
+  vec3 axes = triplanar_weights(surface_normal);
 
-  vec3 normal = vs_normal;
-  float shininess = 100000000;
+  vec3 albedo = vec3(0);
+  vec3 normal = vec3(0);
+  // Shininess spans orders of magnitude, so blend it logarithmically.
+  float log_shininess = 0;
+  float emission = 0;
+  for (int i = 0; i < 3; ++i) {
+    float w = material_weights[i];
+    if (w <= 0) {
+      continue;
+    }
+    int material = corner_materials[i];
+    vec3 splatted = splat_albedo(material, axes, surface_normal);
+    if (material == leaves_material) {
+      splatted *= foliage_tint();
+    }
+    albedo += w * splatted;
+    normal += w * splat_normal(material, axes, surface_normal);
+    log_shininess += w * log(material_shininess[material]);
+    emission += w * material_emission[material];
+  }
 
-  if (material == 1) {
-    // this is duplicated in the grass billboard shader
-    float grassiness =
-      (cnoise(world_position / 32) + 1) / 2 *
-      dot(normal, vec3(0, 1, 0)) *
-      1.5;
-    grassiness = clamp(grassiness, 0, 1);
-    base_color = vec4(mix(dirt(world_position), grass(world_position), grassiness), 1);
-  } else if (material == 2) {
-    base_color = vec4(bark(world_position), 1);
-  } else if (material == 3) {
-    base_color = vec4(leaves(world_position), 1);
-  } else if (material == 4) {
-    base_color = vec4(stone(world_position), 1);
-    normal = bump_map(4, 2, normal);
-  } else if (material == 5) {
-    base_color = vec4(0, 0, 0, 1);
-    shininess = 40;
-  } else {
-    base_color = vec4(0.5, 0, 0.5, 0.5);
-    shininess = 1;
+  // Snow settles on flat ground above the snow line, which moves with the seasons. It's drawn over whatever the
+  // ground is made of, so the terrain itself doesn't have to change.
+  // Nothing settles more than 10 below the snow line, which also covers there being no snow line at all.
+  float snow = 0;
+  if (world_position.y > snow_line - 10) {
+    float snow_height = snow_line + 6 * cnoise(world_position / 16);
+    snow =
+      smoothstep(snow_height - 4, snow_height + 4, world_position.y) *
+      smoothstep(0.5, 0.8, surface_normal.y);
+  }
+  if (snow > 0) {
+    albedo = mix(albedo, splat_albedo(snow_material, axes, surface_normal), snow);
+    log_shininess = mix(log_shininess, log(material_shininess[snow_material]), snow);
   }
 
-  vec4 fog_color = vec4(sun.intensity, 1);
+  normal = normalize(normal);
+  float shininess = exp(log_shininess);
+  // Rain pools on the ground more than it sticks to walls.
+  float wet = wetness * clamp(surface_normal.y, 0, 1);
+  albedo *= mix(1.0, 0.55, wet);
+  shininess = mix(shininess, max(shininess, 40), wet);
+  vec4 base_color = vec4(albedo, 1);
+
+  float view_depth = gl_FragCoord.z / gl_FragCoord.w;
+  vec3 sun_intensity = sun.intensity * sun_visibility(world_position, view_depth);
+  // Creases are also partly shaded from the sun by the terrain around them.
+  sun_intensity *= mix(0.6, 1.0, vs_ambient_occlusion);
+  sun_intensity *= vs_light.x;
+  // Caves aren't quite pitch black, so they can still be found.
+  vec3 ambient = sun.ambient * vs_ambient_occlusion * mix(0.15, 1.0, vs_light.x);
+  vec3 torch_light = point_lighting(world_position, normal) * smoothstep(0.0, 0.2, vs_light.y);
+
+  vec3 view_direction = normalize(world_position - eye_position);
+  vec4 fog_color = scattered_fog_color(view_direction, sun.direction, sun.intensity);
   frag_color =
     world_fragment(
       sun.direction,
-      sun.intensity,
-      normalize(world_position - eye_position),
-      ambient_light,
+      sun_intensity,
+      view_direction,
+      ambient + torch_light + vec3(emission),
       base_color,
       shininess,
       normal,
       fog_color,
-      gl_FragCoord.z / gl_FragCoord.w
+      view_depth
     );
 }
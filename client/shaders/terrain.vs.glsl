@@ -2,17 +2,22 @@
 
 include(adjust_depth_precision.glsl)
 
-uniform float near_clip;
-uniform float far_clip;
-uniform mat4 projection_matrix;
+include(camera.glsl)
 
 uniform samplerBuffer positions;
 uniform samplerBuffer normals;
+uniform samplerBuffer ambient_occlusion;
+uniform samplerBuffer light;
 uniform isamplerBuffer materials;
 
 out vec3 world_position;
 out vec3 vs_normal;
-flat out int material;
+out float vs_ambient_occlusion;
+// How much sky light and torch light reach this point, in [0, 1].
+out vec2 vs_light;
+// The materials the triangle's corners blend toward, and how close this point is to each corner.
+flat out ivec3 corner_materials;
+out vec3 material_weights;
 
 void main() {
   // Mutiply by 3 because there are 3 components for each normal vector.
@@ -26,9 +31,19 @@ void main() {
   vs_normal.y = texelFetch(normals, normal_id + 1).r;
   vs_normal.z = texelFetch(normals, normal_id + 2).r;
 
-  int face_id = gl_VertexID / 3;
+  vs_ambient_occlusion = texelFetch(ambient_occlusion, gl_VertexID).r;
 
-  material = texelFetch(materials, face_id).r;
+  int light_id = gl_VertexID * 2;
+  vs_light.x = texelFetch(light, light_id + 0).r;
+  vs_light.y = texelFetch(light, light_id + 1).r;
+
+  // Every vertex needs all three of its triangle's materials, since they're blended across the triangle.
+  int first_vertex = gl_VertexID - gl_VertexID % 3;
+  corner_materials.x = texelFetch(materials, first_vertex + 0).r;
+  corner_materials.y = texelFetch(materials, first_vertex + 1).r;
+  corner_materials.z = texelFetch(materials, first_vertex + 2).r;
+  material_weights = vec3(0);
+  material_weights[gl_VertexID % 3] = 1;
 
   gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
 }
@@ -0,0 +1,16 @@
+#version 330 core
+
+uniform mat4 light_matrix;
+
+uniform samplerBuffer positions;
+
+void main() {
+  // Multiply by 3 because there are 3 components for each position.
+  int position_id = gl_VertexID * 3;
+  vec3 world_position;
+  world_position.x = texelFetch(positions, position_id + 0).r;
+  world_position.y = texelFetch(positions, position_id + 1).r;
+  world_position.z = texelFetch(positions, position_id + 2).r;
+
+  gl_Position = light_matrix * vec4(world_position, 1.0);
+}
@@ -0,0 +1,47 @@
+#version 330 core
+
+include(camera.glsl)
+include(sun.glsl)
+include(window.glsl)
+
+in vec3 world_position;
+
+out vec4 frag_color;
+
+include(depth_fog.glsl)
+include(noise.glsl)
+
+// A normal map made of two layers of noise, scrolling in different directions.
+vec3 water_normal() {
+  float t = time_ms / 1000;
+  vec2 p1 = world_position.xz / 4 + vec2(t * 0.3, t * 0.1);
+  vec2 p2 = world_position.xz / 9 - vec2(t * 0.05, t * 0.2);
+  float d = 0.1;
+  float h1 = cnoise(vec3(p1, t * 0.2));
+  float h2 = cnoise(vec3(p2, t * 0.1));
+  float dx = (cnoise(vec3(p1 + vec2(d, 0), t * 0.2)) - h1) + (cnoise(vec3(p2 + vec2(d, 0), t * 0.1)) - h2);
+  float dz = (cnoise(vec3(p1 + vec2(0, d), t * 0.2)) - h1) + (cnoise(vec3(p2 + vec2(0, d), t * 0.1)) - h2);
+  return normalize(vec3(-dx, 8 * d, -dz));
+}
+
+void main() {
+  vec3 normal = water_normal();
+  vec3 view_direction = normalize(world_position - eye_position);
+
+  // Schlick's approximation: water reflects more at grazing angles.
+  float cos_theta = clamp(dot(-view_direction, normal), 0, 1);
+  float fresnel = 0.02 + 0.98 * pow(1 - cos_theta, 5);
+
+  vec3 deep_color = vec3(0.02, 0.12, 0.2) * (sun.ambient + sun.intensity * max(sun.direction.y, 0));
+  vec3 sky_color = sun.intensity;
+  vec3 color = mix(deep_color, sky_color, fresnel);
+
+  vec3 reflected = reflect(view_direction, normal);
+  float specular = pow(clamp(dot(reflected, sun.direction), 0, 1), 200);
+  color += specular * sun.intensity;
+
+  float alpha = mix(0.6, 0.95, fresnel);
+
+  vec4 fog_color = scattered_fog_color(view_direction, sun.direction, sun.intensity);
+  frag_color = apply_fog(vec4(color, alpha), fog_color, view_direction, gl_FragCoord.z / gl_FragCoord.w);
+}
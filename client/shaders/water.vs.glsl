@@ -0,0 +1,14 @@
+#version 330 core
+
+include(adjust_depth_precision.glsl)
+
+include(camera.glsl)
+
+in vec3 position;
+
+out vec3 world_position;
+
+void main() {
+  world_position = position;
+  gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(position, 1.0));
+}
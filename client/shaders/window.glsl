@@ -0,0 +1,5 @@
+// The window being drawn into, and when. Laid out to match client/lib/src/view/shaders/blocks.rs.
+layout(std140) uniform Window {
+  vec2 window_size;
+  float time_ms;
+};
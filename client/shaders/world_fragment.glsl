@@ -18,5 +18,5 @@ vec4 world_fragment(
   specular = pow(specular, shininess);
 
   vec4 with_light = diffuse*vec4(intensity, 1)*material_color + specular*vec4(1) + vec4(ambient_light, 1)*material_color;
-  return apply_fog(with_light, fog_color, frag_distance);
+  return apply_fog(with_light, fog_color, view_direction, frag_distance);
 }
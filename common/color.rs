@@ -1,6 +1,6 @@
 //! Color structs
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 /// A color with RGB channels.
 pub struct Color3<T> {
   /// Red
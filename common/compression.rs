@@ -0,0 +1,134 @@
+//! Compression for large protocol payloads, e.g. terrain.
+
+use bincode;
+use lz4;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Ways of compressing a payload.
+pub enum Scheme {
+  /// Send the serialized bytes as-is.
+  None,
+  /// LZ4 block compression.
+  Lz4,
+}
+
+/// The schemes this build understands, most preferred first.
+pub const SUPPORTED: &'static [Scheme] = &[Scheme::Lz4, Scheme::None];
+
+/// The most bytes a payload may claim to decompress to. Bigger claims are refused instead of allocated.
+pub const MAX_UNCOMPRESSED_LEN: u32 = 64 << 20;
+
+/// Why a payload couldn't be decompressed.
+#[derive(Debug)]
+pub enum Error {
+  /// The payload claims to decompress to more than `MAX_UNCOMPRESSED_LEN` bytes.
+  TooLarge(u32),
+  /// The compressed bytes are corrupt.
+  Corrupt(std::io::Error),
+  /// The decompressed bytes aren't a serialized value of the expected type.
+  Malformed(bincode::Error),
+}
+
+/// Choose the most preferred scheme out of the ones the other end understands.
+pub fn negotiate(offered: &[Scheme]) -> Scheme {
+  for scheme in SUPPORTED {
+    if offered.contains(scheme) {
+      return *scheme
+    }
+  }
+  Scheme::None
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A serialized and possibly compressed `V`.
+pub struct T<V> {
+  scheme           : Scheme,
+  uncompressed_len : u32,
+  bytes            : Vec<u8>,
+  phantom          : PhantomData<V>,
+}
+
+/// Serialize and compress a value.
+pub fn compress<V>(scheme: Scheme, value: &V) -> T<V> where V: Serialize {
+  let serialized = bincode::serialize(value, bincode::Infinite).unwrap();
+  let bytes =
+    match scheme {
+      Scheme::None => serialized.clone(),
+      Scheme::Lz4 => lz4::block::compress(&serialized, None, false).unwrap(),
+    };
+  T {
+    scheme           : scheme,
+    uncompressed_len : serialized.len() as u32,
+    bytes            : bytes,
+    phantom          : PhantomData,
+  }
+}
+
+impl<V> T<V> where V: DeserializeOwned {
+  /// Decompress and deserialize the value. The payload came off the wire, so it may be corrupt or hostile.
+  pub fn decompress(&self) -> Result<V, Error> {
+    if self.uncompressed_len > MAX_UNCOMPRESSED_LEN {
+      return Err(Error::TooLarge(self.uncompressed_len))
+    }
+    let limit = bincode::Bounded(self.uncompressed_len as u64);
+    match self.scheme {
+      Scheme::None => bincode::deserialize_from(&mut &self.bytes[..], limit).map_err(Error::Malformed),
+      Scheme::Lz4 => {
+        let serialized =
+          lz4::block::decompress(&self.bytes, Some(self.uncompressed_len as i32)).map_err(Error::Corrupt)?;
+        bincode::deserialize_from(&mut &serialized[..], limit).map_err(Error::Malformed)
+      },
+    }
+  }
+}
+
+impl<V> T<V> {
+  /// The number of bytes the payload takes up on the wire.
+  pub fn compressed_len(&self) -> usize {
+    self.bytes.len()
+  }
+
+  /// The number of bytes the payload would take up without compression.
+  pub fn uncompressed_len(&self) -> usize {
+    self.uncompressed_len as usize
+  }
+}
+
+#[test]
+fn round_trip() {
+  let value: Vec<u32> = (0 .. 1000).map(|i| i / 10).collect();
+  for &scheme in SUPPORTED {
+    let compressed = compress(scheme, &value);
+    assert_eq!(compressed.decompress().unwrap(), value);
+  }
+}
+
+#[test]
+fn oversized_payloads_are_refused() {
+  let mut compressed = compress(Scheme::Lz4, &vec!(0u8; 16));
+  compressed.uncompressed_len = MAX_UNCOMPRESSED_LEN + 1;
+  match compressed.decompress() {
+    Err(Error::TooLarge(len)) => assert_eq!(len, MAX_UNCOMPRESSED_LEN + 1),
+    result => panic!("expected TooLarge, got {:?}", result),
+  }
+}
+
+#[test]
+fn corrupt_payloads_are_errors() {
+  let mut compressed = compress(Scheme::Lz4, &(0 .. 1000u32).collect::<Vec<_>>());
+  for byte in &mut compressed.bytes {
+    *byte = !*byte;
+  }
+  assert!(compressed.decompress().is_err());
+}
+
+#[test]
+fn negotiate_prefers_lz4() {
+  assert_eq!(negotiate(&[Scheme::None, Scheme::Lz4]), Scheme::Lz4);
+  assert_eq!(negotiate(&[Scheme::None]), Scheme::None);
+  assert_eq!(negotiate(&[]), Scheme::None);
+}
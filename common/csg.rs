@@ -0,0 +1,212 @@
+//! Fields that combine other fields, to go with `voxel_data`'s `intersection`. They're re-exported in
+//! `voxel::field`. Densities are positive inside a field.
+
+use cgmath::{Vector3, InnerSpace};
+#[cfg(test)]
+use cgmath::Point3;
+
+#[cfg(test)]
+use voxel::field;
+
+/// How much of `a` to blend into `b` where they meet, in a smooth combination `k` wide.
+fn blend(a: f32, b: f32, k: f32) -> f32 {
+  (0.5 + 0.5 * (a - b) / k).max(0.0).min(1.0)
+}
+
+/// Everything inside either of two fields.
+pub mod union {
+  use cgmath::{Point3, Vector3};
+
+  use voxel::field;
+
+  #[allow(missing_docs)]
+  pub struct T<A, B> {
+    pub a : A,
+    pub b : B,
+  }
+
+  #[allow(missing_docs)]
+  pub fn new<A, B>(a: A, b: B) -> T<A, B> {
+    T {
+      a : a,
+      b : b,
+    }
+  }
+
+  impl<A: field::T, B: field::T> field::T for T<A, B> {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      self.a.density(p).max(self.b.density(p))
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      if self.a.density(p) >= self.b.density(p) {
+        self.a.normal(p)
+      } else {
+        self.b.normal(p)
+      }
+    }
+  }
+}
+
+/// Everything inside one field, but not another.
+pub mod difference {
+  use cgmath::{Point3, Vector3};
+
+  use voxel::field;
+
+  #[allow(missing_docs)]
+  pub struct T<A, B> {
+    /// The field to cut from.
+    pub a : A,
+    /// The field to cut out.
+    pub b : B,
+  }
+
+  #[allow(missing_docs)]
+  pub fn new<A, B>(a: A, b: B) -> T<A, B> {
+    T {
+      a : a,
+      b : b,
+    }
+  }
+
+  impl<A: field::T, B: field::T> field::T for T<A, B> {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      self.a.density(p).min(-self.b.density(p))
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      // Where the cut-out field is the boundary, its surface faces the other way.
+      if self.a.density(p) <= -self.b.density(p) {
+        self.a.normal(p)
+      } else {
+        -self.b.normal(p)
+      }
+    }
+  }
+}
+
+/// Like `union`, but filleted where the fields meet.
+pub mod smooth_union {
+  use cgmath::{Point3, Vector3};
+
+  use voxel::field;
+
+  #[allow(missing_docs)]
+  pub struct T<A, B> {
+    pub a : A,
+    pub b : B,
+    /// How wide the fillet is.
+    pub k : f32,
+  }
+
+  #[allow(missing_docs)]
+  pub fn new<A, B>(a: A, b: B, k: f32) -> T<A, B> {
+    T {
+      a : a,
+      b : b,
+      k : k,
+    }
+  }
+
+  impl<A: field::T, B: field::T> field::T for T<A, B> {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let (a, b) = (self.a.density(p), self.b.density(p));
+      let h = super::blend(a, b, self.k);
+      b + (a - b) * h + self.k * h * (1.0 - h)
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      let h = super::blend(self.a.density(p), self.b.density(p), self.k);
+      super::mix_normals(self.a.normal(p), self.b.normal(p), h)
+    }
+  }
+}
+
+/// Like `intersection`, but rounded where the fields meet. This is a smooth minimum of the two densities.
+pub mod smooth_intersection {
+  use cgmath::{Point3, Vector3};
+
+  use voxel::field;
+
+  #[allow(missing_docs)]
+  pub struct T<A, B> {
+    pub a : A,
+    pub b : B,
+    /// How wide the rounding is.
+    pub k : f32,
+  }
+
+  #[allow(missing_docs)]
+  pub fn new<A, B>(a: A, b: B, k: f32) -> T<A, B> {
+    T {
+      a : a,
+      b : b,
+      k : k,
+    }
+  }
+
+  impl<A: field::T, B: field::T> field::T for T<A, B> {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let (a, b) = (self.a.density(p), self.b.density(p));
+      let h = super::blend(b, a, self.k);
+      b + (a - b) * h - self.k * h * (1.0 - h)
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      let h = super::blend(self.b.density(p), self.a.density(p), self.k);
+      super::mix_normals(self.a.normal(p), self.b.normal(p), h)
+    }
+  }
+}
+
+/// Blend from `b`'s normal (at 0) to `a`'s (at 1).
+fn mix_normals(a: Vector3<f32>, b: Vector3<f32>, h: f32) -> Vector3<f32> {
+  let n = b + (a - b) * h;
+  if n.magnitude2() == 0.0 { a } else { n.normalize() }
+}
+
+#[cfg(test)]
+/// The half-space below y = `height`.
+struct Floor {
+  height : f32,
+}
+
+#[cfg(test)]
+impl field::T for Floor {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.height - p.y
+  }
+
+  fn normal(&mut self, _: &Point3<f32>) -> Vector3<f32> {
+    Vector3::new(0.0, 1.0, 0.0)
+  }
+}
+
+#[test]
+fn combinations_follow_the_nearer_surface() {
+  use voxel::field::T;
+
+  let p = Point3::new(0.0, 1.0, 0.0);
+
+  let mut union = union::new(Floor { height: 0.0 }, Floor { height: 2.0 });
+  assert_eq!(union.density(&p), 1.0);
+
+  let mut difference = difference::new(Floor { height: 2.0 }, Floor { height: 0.0 });
+  assert_eq!(difference.density(&p), 1.0);
+  assert_eq!(difference.density(&Point3::new(0.0, -1.0, 0.0)), -1.0);
+  assert_eq!(difference.normal(&Point3::new(0.0, 0.1, 0.0)), Vector3::new(0.0, -1.0, 0.0));
+
+  // Far from where the fields meet, the smooth versions match the sharp ones.
+  let mut smooth_union = smooth_union::new(Floor { height: 0.0 }, Floor { height: 8.0 }, 1.0);
+  assert_eq!(smooth_union.density(&p), 7.0);
+  let mut smooth_intersection = smooth_intersection::new(Floor { height: 0.0 }, Floor { height: 8.0 }, 1.0);
+  assert_eq!(smooth_intersection.density(&p), -1.0);
+
+  // Where they're equal, the smooth union bulges out and the smooth intersection shrinks in.
+  let mut smooth_union = smooth_union::new(Floor { height: 1.0 }, Floor { height: 1.0 }, 1.0);
+  assert_eq!(smooth_union.density(&p), 0.25);
+  let mut smooth_intersection = smooth_intersection::new(Floor { height: 1.0 }, Floor { height: 1.0 }, 1.0);
+  assert_eq!(smooth_intersection.density(&p), -0.25);
+  assert_eq!(smooth_intersection.normal(&p), Vector3::new(0.0, 1.0, 0.0));
+}
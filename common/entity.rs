@@ -1,37 +1,67 @@
 //! Uniquely identify entities
 
 /// Phantom types to use with `id`.
-mod types {
+#[allow(missing_docs)]
+pub mod types {
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
   pub struct Player;
 
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
   pub struct Mob;
+
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+  pub struct Item;
 }
 
 #[allow(missing_docs)]
 pub mod id {
   use std;
 
-  #[allow(missing_docs)]
+  use id_allocator;
+
+  /// An id is a number, plus the generation of that number. When an id is freed and its number reused, the new id is
+  /// a generation on, so anything still holding the old one can tell it's stale instead of mistaking the new entity
+  /// for its own.
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
   pub struct T<U> {
-    units: std::marker::PhantomData<U>,
-    value: u32,
+    units      : std::marker::PhantomData<U>,
+    value      : u32,
+    generation : u32,
   }
 
   fn of_u32<U>(value: u32) -> T<U> {
     T {
-      value: value,
-      units: std::marker::PhantomData,
+      value      : value,
+      generation : 0,
+      units      : std::marker::PhantomData,
     }
   }
 
   impl<U> T<U> {
-    #[allow(missing_docs)]
+    /// The id's number, regardless of generation.
     pub fn to_u32(self) -> u32 {
       self.value
     }
+
+    #[allow(missing_docs)]
+    pub fn generation(self) -> u32 {
+      self.generation
+    }
+
+    /// The same number, a generation on.
+    pub fn next_generation(self) -> T<U> {
+      T {
+        value      : self.value,
+        generation : self.generation.wrapping_add(1),
+        units      : std::marker::PhantomData,
+      }
+    }
+  }
+
+  impl<U> id_allocator::Reusable for T<U> {
+    fn reused(self) -> T<U> {
+      self.next_generation()
+    }
   }
 
   impl<U> std::default::Default for T<U> {
@@ -43,10 +73,15 @@ pub mod id {
   impl<U> std::ops::Add<u32> for T<U> {
     type Output = T<U>;
     fn add(self, rhs: u32) -> T<U> {
-      of_u32(self.value + rhs)
+      T {
+        value      : self.value + rhs,
+        generation : self.generation,
+        units      : std::marker::PhantomData,
+      }
     }
   }
 
   pub type Player = T<super::types::Player>;
   pub type Mob = T<super::types::Mob>;
+  pub type Item = T<super::types::Item>;
 }
@@ -0,0 +1,76 @@
+//! Hit points, and what takes them away.
+
+/// How many hit points players have when unhurt.
+pub const PLAYER_MAX: u32 = 20;
+/// How many hit points mobs have when unhurt.
+pub const MOB_MAX: u32 = 10;
+
+/// Landing slower than this, in units per update, doesn't hurt. This is about a 4-unit drop.
+const SAFE_FALL_SPEED: f32 = 0.9;
+/// Hit points lost per unit per update of landing speed past `SAFE_FALL_SPEED`.
+const FALL_DAMAGE_PER_SPEED: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub struct T {
+  pub current : u32,
+  pub max     : u32,
+}
+
+/// Unhurt, with `max` hit points.
+pub fn full(max: u32) -> T {
+  T {
+    current : max,
+    max     : max,
+  }
+}
+
+impl T {
+  /// Lose up to `amount` hit points. Returns true if this was the blow that killed.
+  pub fn damage(&mut self, amount: u32) -> bool {
+    let was_alive = !self.is_dead();
+    self.current = self.current.saturating_sub(amount);
+    was_alive && self.is_dead()
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_dead(&self) -> bool {
+    self.current == 0
+  }
+
+  /// The portion of hit points left, in [0, 1].
+  pub fn fraction(&self) -> f32 {
+    if self.max == 0 {
+      return 0.0
+    }
+    self.current as f32 / self.max as f32
+  }
+}
+
+/// What killed someone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cause {
+  /// Hitting the ground too hard.
+  Fall,
+  /// A mob's attack.
+  Mob,
+}
+
+/// The damage from landing on the ground at `speed` units per update.
+pub fn fall_damage(speed: f32) -> u32 {
+  ((speed - SAFE_FALL_SPEED) * FALL_DAMAGE_PER_SPEED).max(0.0).ceil() as u32
+}
+
+#[test]
+fn damage_kills_once() {
+  let mut health = full(PLAYER_MAX);
+  assert_eq!(fall_damage(SAFE_FALL_SPEED), 0);
+  assert!(fall_damage(2.0) > 0);
+
+  assert!(!health.damage(PLAYER_MAX - 1));
+  assert_eq!(health.fraction(), 1.0 / PLAYER_MAX as f32);
+  assert!(health.damage(5));
+  assert_eq!(health.current, 0);
+  assert!(!health.damage(5));
+  assert!(health.is_dead());
+}
@@ -3,9 +3,17 @@
 use std::default::Default;
 use std::ops::Add;
 
+/// Ids that can be handed out again once they're freed, as long as the new id can be told apart from the old one.
+pub trait Reusable {
+  /// The id to hand out next, in place of this freed one.
+  fn reused(self) -> Self;
+}
+
 /// Data structure to produce unique IDs.
 pub struct T<Id> {
   next: Id,
+  /// Freed ids, already changed to be told apart from what they were.
+  free: Vec<Id>,
 }
 
 impl<Id> T<Id> where
@@ -13,17 +21,47 @@ impl<Id> T<Id> where
 {
   /// Produce an Id that hasn't been produced yet by this object.
   pub fn allocate(&mut self) -> Id {
+    if let Some(id) = self.free.pop() {
+      return id
+    }
     let ret = self.next.clone();
     self.next = self.next.clone() + 1;
     ret
   }
 }
 
+impl<Id> T<Id> where
+  Id : Reusable,
+{
+  /// Hand `id` out again, as a new id that doesn't compare equal to it.
+  pub fn free(&mut self, id: Id) {
+    self.free.push(id.reused());
+  }
+}
+
 #[allow(missing_docs)]
 pub fn new<Id>() -> T<Id> where
   Id : Default
 {
   T {
-    next: Default::default()
+    next: Default::default(),
+    free: Vec::new(),
   }
 }
+
+#[test]
+fn freed_ids_come_back_a_generation_on() {
+  use entity;
+
+  let mut allocator: T<entity::id::Player> = new();
+  let a = allocator.allocate();
+  let b = allocator.allocate();
+  assert!(a != b);
+
+  allocator.free(a);
+  let c = allocator.allocate();
+  assert!(c != a);
+  assert_eq!(c.to_u32(), a.to_u32());
+  assert_eq!(c.generation(), a.generation() + 1);
+  assert_eq!(allocator.allocate().to_u32(), b.to_u32() + 1);
+}
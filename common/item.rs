@@ -0,0 +1,162 @@
+//! Everything that can be carried in an inventory, and the recipes that turn some items into others.
+//! Client and server share these tables, so an item's or recipe's id means the same thing on both ends.
+
+use color::Color3;
+use voxel::Material;
+
+/// The number of items.
+pub const COUNT: usize = 11;
+
+/// Identifies an item.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Id {
+  Dirt,
+  Bark,
+  Leaves,
+  Stone,
+  Marble,
+  Sand,
+  Snow,
+  Sticks,
+  Snowball,
+  Spear,
+  Bomb,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct T {
+  pub name   : &'static str,
+  /// How the item's drawn on the HUD, and when it's dropped.
+  pub color  : Color3<f32>,
+  /// The terrain material the item places, if it can be placed.
+  pub places : Option<Material>,
+  /// The hit points the item takes from a mob it's thrown at.
+  pub damage : u32,
+  /// The radius of the explosion the item sets off when it's thrown and hits something, or 0 if it doesn't.
+  pub blast  : f32,
+}
+
+/// Indexed by `Id`.
+static ALL: [T; COUNT] = [
+  T { name: "dirt"    , color: Color3 { r: 0.3, g: 0.5, b: 0.2 }  , places: Some(Material::Terrain), damage: 2, blast: 0.0 },
+  T { name: "bark"    , color: Color3 { r: 0.4, g: 0.3, b: 0.2 }  , places: Some(Material::Bark)   , damage: 2, blast: 0.0 },
+  T { name: "leaves"  , color: Color3 { r: 0.2, g: 0.6, b: 0.1 }  , places: Some(Material::Leaves) , damage: 1, blast: 0.0 },
+  T { name: "stone"   , color: Color3 { r: 0.5, g: 0.5, b: 0.5 }  , places: Some(Material::Stone)  , damage: 4, blast: 0.0 },
+  T { name: "marble"  , color: Color3 { r: 0.0, g: 0.0, b: 0.0 }  , places: Some(Material::Marble) , damage: 4, blast: 0.0 },
+  T { name: "sand"    , color: Color3 { r: 0.8, g: 0.7, b: 0.5 }  , places: Some(Material::Sand)   , damage: 1, blast: 0.0 },
+  T { name: "snow"    , color: Color3 { r: 0.9, g: 0.9, b: 1.0 }  , places: Some(Material::Snow)   , damage: 1, blast: 0.0 },
+  T { name: "sticks"  , color: Color3 { r: 0.6, g: 0.45, b: 0.25 }, places: None                   , damage: 2, blast: 0.0 },
+  T { name: "snowball", color: Color3 { r: 1.0, g: 1.0, b: 1.0 }  , places: None                   , damage: 1, blast: 0.0 },
+  T { name: "spear"   , color: Color3 { r: 0.7, g: 0.7, b: 0.75 } , places: None                   , damage: 8, blast: 0.0 },
+  T { name: "bomb"    , color: Color3 { r: 0.2, g: 0.2, b: 0.2 }  , places: None                   , damage: 2, blast: 3.0 },
+];
+
+/// Look up an item.
+pub fn get(id: Id) -> &'static T {
+  &ALL[id as usize]
+}
+
+/// The item digging out some material gives, if any.
+pub fn of_material(material: Material) -> Option<Id> {
+  match material {
+    Material::Empty | Material::Water => None,
+    Material::Terrain => Some(Id::Dirt),
+    Material::Bark    => Some(Id::Bark),
+    Material::Leaves  => Some(Id::Leaves),
+    Material::Stone   => Some(Id::Stone),
+    Material::Marble  => Some(Id::Marble),
+    Material::Sand    => Some(Id::Sand),
+    Material::Snow    => Some(Id::Snow),
+  }
+}
+
+/// Identifies a recipe, by its index in `recipes()`.
+pub type RecipeId = u32;
+
+/// Some number of one item.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Amount {
+  pub item  : Id,
+  pub count : u32,
+}
+
+/// A way to turn some items into another.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipe {
+  pub inputs : &'static [Amount],
+  pub output : Amount,
+}
+
+static RECIPES: [Recipe; 6] = [
+  Recipe {
+    inputs : &[Amount { item: Id::Bark, count: 1 }],
+    output : Amount { item: Id::Sticks, count: 4 },
+  },
+  Recipe {
+    inputs : &[Amount { item: Id::Sticks, count: 2 }, Amount { item: Id::Stone, count: 1 }],
+    output : Amount { item: Id::Spear, count: 1 },
+  },
+  Recipe {
+    inputs : &[Amount { item: Id::Snow, count: 1 }],
+    output : Amount { item: Id::Snowball, count: 4 },
+  },
+  Recipe {
+    inputs : &[Amount { item: Id::Stone, count: 4 }, Amount { item: Id::Sand, count: 1 }],
+    output : Amount { item: Id::Marble, count: 2 },
+  },
+  Recipe {
+    inputs : &[Amount { item: Id::Leaves, count: 4 }],
+    output : Amount { item: Id::Dirt, count: 1 },
+  },
+  Recipe {
+    inputs : &[Amount { item: Id::Sand, count: 4 }, Amount { item: Id::Sticks, count: 1 }],
+    output : Amount { item: Id::Bomb, count: 1 },
+  },
+];
+
+/// Every recipe, in order of their ids.
+pub fn recipes() -> &'static [Recipe] {
+  &RECIPES
+}
+
+/// Look up a recipe.
+pub fn recipe(id: RecipeId) -> Option<&'static Recipe> {
+  RECIPES.get(id as usize)
+}
+
+impl Recipe {
+  /// A one-line description, e.g. "4 sticks from 1 bark".
+  pub fn describe(&self) -> String {
+    let inputs: Vec<String> =
+      self.inputs.iter()
+      .map(|input| format!("{} {}", input.count, get(input.item).name))
+      .collect();
+    format!("{} {} from {}", self.output.count, get(self.output.item).name, inputs.join(" and "))
+  }
+}
+
+/// Why a crafting request was turned down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CraftError {
+  /// There's no recipe with that id.
+  UnknownRecipe,
+  /// The player doesn't have everything the recipe takes.
+  MissingInputs,
+  /// The player's inventory has no room for what the recipe makes.
+  NoRoom,
+}
+
+#[test]
+fn items_are_in_id_order() {
+  assert_eq!(get(Id::Bomb).name, "bomb");
+  assert_eq!(Id::Bomb as usize + 1, COUNT);
+  assert_eq!(get(of_material(Material::Terrain).unwrap()).places, Some(Material::Terrain));
+  for recipe in recipes() {
+    assert!(recipe.inputs.iter().all(|input| input.count > 0 && input.item != recipe.output.item));
+  }
+  assert_eq!(recipe(0).unwrap().describe(), "4 sticks from 1 bark");
+}
@@ -0,0 +1,163 @@
+//! A journal of terrain edits, so edits aren't lost to voxels that were loaded before them but arrive after.
+//! The terrain's split into chunks, the same size as the client's, and each chunk has a version that goes up every
+//! time it's edited. Voxels sent in answer to a request carry their chunks' versions as of when they were loaded, and
+//! edits carry the versions they bring their chunks to, so the edits a late answer is missing can be put back on top.
+//! An edit sets voxels outright, so putting it back on top of voxels that already have it changes nothing.
+//!
+//! Only voxels no bigger than a chunk are versioned. Coarser ones cover several chunks, which could each be at a
+//! different version, so they're left out: they don't count toward any chunk's version, and edits to them aren't
+//! recorded or put back.
+
+use cgmath::Point3;
+use std::collections::VecDeque;
+
+use fnv_map;
+use voxel;
+
+/// lg of the width of a chunk, in voxels.
+const LG_CHUNK_WIDTH: i16 = 3;
+/// How many of its most recent edits each chunk keeps.
+const MAX_EDITS: usize = 16;
+
+/// The chunk a voxel's in, unless it's bigger than a chunk.
+pub fn chunk(bounds: &voxel::bounds::T) -> Option<Point3<i32>> {
+  let lg_size = bounds.lg_size - LG_CHUNK_WIDTH;
+  if lg_size > 0 {
+    return None
+  }
+  Some(Point3::new(bounds.x >> -lg_size, bounds.y >> -lg_size, bounds.z >> -lg_size))
+}
+
+struct Chunk {
+  version : u32,
+  /// The most recent edits, oldest first, with the versions they brought the chunk to.
+  edits   : VecDeque<(u32, Vec<(voxel::bounds::T, voxel::T)>)>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  chunks : fnv_map::T<Point3<i32>, Chunk>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    chunks : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// A chunk's version. Chunks that have never been edited are at 0.
+  pub fn version(&self, chunk: &Point3<i32>) -> u32 {
+    self.chunks.get(chunk).map_or(0, |chunk| chunk.version)
+  }
+
+  /// The versions of the chunks some voxels are in. Voxels bigger than a chunk are skipped.
+  pub fn versions(&self, voxels: &[voxel::bounds::T]) -> Vec<(Point3<i32>, u32)> {
+    let mut chunks: Vec<_> = voxels.iter().filter_map(chunk).collect();
+    chunks.sort_by_key(|p| (p.x, p.y, p.z));
+    chunks.dedup();
+    chunks.into_iter().map(|chunk| (chunk, self.version(&chunk))).collect()
+  }
+
+  /// Record an edit at the version it brought its chunk to, which is older than the chunk's version if an edit that
+  /// came after it has already been recorded.
+  pub fn record_at(&mut self, chunk: Point3<i32>, version: u32, voxels: Vec<(voxel::bounds::T, voxel::T)>) {
+    let chunk = self.chunks.entry(chunk).or_insert_with(|| Chunk { version: 0, edits: VecDeque::new() });
+    chunk.version = chunk.version.max(version);
+    let i = chunk.edits.iter().position(|&(v, _)| v > version).unwrap_or(chunk.edits.len());
+    chunk.edits.insert(i, (version, voxels));
+    while chunk.edits.len() > MAX_EDITS {
+      chunk.edits.pop_front();
+    }
+  }
+
+  /// Record an edit, and return the versions it brought each chunk it touched to. Voxels bigger than a chunk are
+  /// skipped.
+  pub fn record(&mut self, voxels: &[(voxel::bounds::T, voxel::T)]) -> Vec<(Point3<i32>, u32)> {
+    let mut by_chunk: fnv_map::T<Point3<i32>, Vec<_>> = fnv_map::new();
+    for &(bounds, voxel) in voxels {
+      if let Some(chunk) = chunk(&bounds) {
+        by_chunk.entry(chunk).or_insert_with(Vec::new).push((bounds, voxel));
+      }
+    }
+    let mut versions = Vec::with_capacity(by_chunk.len());
+    for (chunk, voxels) in by_chunk {
+      let version = self.version(&chunk) + 1;
+      self.record_at(chunk, version, voxels);
+      versions.push((chunk, version));
+    }
+    versions
+  }
+
+  /// The recorded edits to a chunk since `version`, oldest first.
+  pub fn since(&self, chunk: &Point3<i32>, version: u32) -> Vec<(voxel::bounds::T, voxel::T)> {
+    match self.chunks.get(chunk) {
+      None => Vec::new(),
+      Some(chunk) => {
+        chunk.edits.iter()
+        .filter(|&&(v, _)| v > version)
+        .flat_map(|&(_, ref voxels)| voxels.iter().cloned())
+        .collect()
+      },
+    }
+  }
+
+  /// Whether every edit to a chunk since `version` is still recorded.
+  pub fn has_all_since(&self, chunk: &Point3<i32>, version: u32) -> bool {
+    match self.chunks.get(chunk) {
+      None => true,
+      Some(chunk) => {
+        // Versions go up by one per edit, so any missing version is an edit that's been forgotten.
+        let recorded = chunk.edits.iter().filter(|&&(v, _)| v > version).count();
+        recorded as u32 >= chunk.version.saturating_sub(version)
+      },
+    }
+  }
+
+  /// Forget a chunk's edits up to `version`, e.g. because voxels at that version have arrived and superseded them.
+  pub fn forget_through(&mut self, chunk: &Point3<i32>, version: u32) {
+    if let Some(chunk) = self.chunks.get_mut(chunk) {
+      chunk.edits.retain(|&(v, _)| v > version);
+    }
+  }
+}
+
+#[test]
+fn late_voxels_get_newer_edits_on_top() {
+  let stone = voxel::Volume(voxel::Material::Stone);
+  let empty = voxel::Volume(voxel::Material::Empty);
+  let a = voxel::bounds::new(1, 2, 3, 0);
+  let b = voxel::bounds::new(9, 2, 3, 0);
+  let coarse = voxel::bounds::new(-1, 2, 3, 4);
+  assert_eq!(chunk(&a), Some(Point3::new(0, 0, 0)));
+  assert_eq!(chunk(&b), Some(Point3::new(1, 0, 0)));
+  assert_eq!(chunk(&voxel::bounds::new(-1, 2, 3, 3)), Some(Point3::new(-1, 2, 3)));
+  assert_eq!(chunk(&coarse), None);
+  let (a_chunk, b_chunk) = (chunk(&a).unwrap(), chunk(&b).unwrap());
+
+  let mut server = new();
+  let mut client = new();
+  // Voxels in `a`'s chunk are loaded before it's dug out, but arrive after.
+  let loaded = server.versions(&[a, b, coarse]);
+  assert_eq!(loaded, vec!((a_chunk, 0), (b_chunk, 0)));
+  // Coarse voxels aren't versioned.
+  assert_eq!(server.record(&[(coarse, stone)]), vec!());
+  for (chunk, version) in server.record(&[(a, empty)]) {
+    assert_eq!(version, 1);
+    client.record_at(chunk, version, vec!((a, empty)));
+  }
+  assert_eq!(client.since(&a_chunk, 0), vec!((a, empty)));
+  assert_eq!(client.since(&b_chunk, 0), vec!());
+  // Once voxels with the edit arrive, it's not needed anymore.
+  client.forget_through(&a_chunk, 1);
+  assert_eq!(client.since(&a_chunk, 0), vec!());
+
+  // A chunk that's been edited too often since can't be brought up to date.
+  for _ in 0 .. MAX_EDITS + 1 {
+    server.record(&[(a, stone)]);
+  }
+  assert!(!server.has_all_since(&a_chunk, 0));
+  assert!(server.has_all_since(&a_chunk, 2));
+  assert_eq!(server.since(&a_chunk, 2).len(), MAX_EDITS);
+}
@@ -0,0 +1,82 @@
+//! What each terrain material is like, shared by world generation and rendering.
+
+use color::Color3;
+use voxel::Material;
+
+/// The number of materials, including `Empty`.
+pub const COUNT: usize = 9;
+
+/// A specular exponent high enough that there's no visible highlight.
+pub const MATTE: f32 = 100000000.0;
+
+/// The range of colors a material's texture is made from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+  #[allow(missing_docs)]
+  pub dark  : Color3<f32>,
+  #[allow(missing_docs)]
+  pub light : Color3<f32>,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct T {
+  pub name          : &'static str,
+  /// Whether the material has a surface of its own. Water is meshed separately, so terrain surfaces are
+  /// generated against it.
+  pub solid         : bool,
+  /// The colors of faces that face up.
+  pub top           : Palette,
+  /// The colors of faces that face sideways or down, e.g. the dirt under grass.
+  pub side          : Palette,
+  /// How many world units the material's texture spans before repeating.
+  pub texture_scale : f32,
+  /// How rough the surface looks, in [0, 1].
+  pub bumpiness     : f32,
+  /// Specular exponent; see `MATTE`.
+  pub shininess     : f32,
+  /// How brightly the material glows on its own, e.g. for glowing ore, in [0, 1].
+  pub emission      : f32,
+  /// The material found underneath a surface of this one.
+  pub subsurface    : Material,
+}
+
+const GRASS : Palette = Palette { dark: Color3 { r: 0.1, g: 0.4, b: 0.0 }, light: Color3 { r: 0.3, g: 0.6, b: 0.0 } };
+const DIRT  : Palette = Palette { dark: Color3 { r: 0.4, g: 0.3, b: 0.1 }, light: Color3 { r: 0.7, g: 0.6, b: 0.4 } };
+const STONE : Palette = Palette { dark: Color3 { r: 0.2, g: 0.2, b: 0.2 }, light: Color3 { r: 0.4, g: 0.4, b: 0.4 } };
+const MARBLE: Palette = Palette { dark: Color3 { r: 0.0, g: 0.0, b: 0.0 }, light: Color3 { r: 0.05, g: 0.05, b: 0.05 } };
+const SAND  : Palette = Palette { dark: Color3 { r: 0.7, g: 0.6, b: 0.35 }, light: Color3 { r: 0.9, g: 0.8, b: 0.55 } };
+const SNOW  : Palette = Palette { dark: Color3 { r: 0.8, g: 0.85, b: 0.9 }, light: Color3 { r: 0.95, g: 0.97, b: 1.0 } };
+const WATER : Palette = Palette { dark: Color3 { r: 0.05, g: 0.2, b: 0.3 }, light: Color3 { r: 0.1, g: 0.3, b: 0.4 } };
+const NONE  : Palette = Palette { dark: Color3 { r: 0.5, g: 0.0, b: 0.5 }, light: Color3 { r: 0.5, g: 0.0, b: 0.5 } };
+
+/// Indexed by `Material`.
+static ALL: [T; COUNT] = [
+  T { name: "empty"  , solid: false, top: NONE  , side: NONE  , texture_scale: 1.0 , bumpiness: 0.0, shininess: MATTE, emission: 0.0, subsurface: Material::Empty   },
+  T { name: "terrain", solid: true , top: GRASS , side: DIRT  , texture_scale: 8.0 , bumpiness: 0.3, shininess: MATTE, emission: 0.0, subsurface: Material::Terrain },
+  T { name: "bark"   , solid: true , top: DIRT  , side: DIRT  , texture_scale: 4.0 , bumpiness: 0.8, shininess: MATTE, emission: 0.0, subsurface: Material::Bark    },
+  T { name: "leaves" , solid: true , top: GRASS , side: GRASS , texture_scale: 2.0 , bumpiness: 0.5, shininess: MATTE, emission: 0.0, subsurface: Material::Leaves  },
+  T { name: "stone"  , solid: true , top: STONE , side: STONE , texture_scale: 16.0, bumpiness: 1.0, shininess: MATTE, emission: 0.0, subsurface: Material::Stone   },
+  T { name: "marble" , solid: true , top: MARBLE, side: MARBLE, texture_scale: 8.0 , bumpiness: 0.1, shininess: 40.0 , emission: 0.0, subsurface: Material::Marble  },
+  T { name: "sand"   , solid: true , top: SAND  , side: SAND  , texture_scale: 4.0 , bumpiness: 0.2, shininess: MATTE, emission: 0.0, subsurface: Material::Sand    },
+  T { name: "snow"   , solid: true , top: SNOW  , side: SNOW  , texture_scale: 8.0 , bumpiness: 0.1, shininess: 20.0 , emission: 0.0, subsurface: Material::Stone   },
+  T { name: "water"  , solid: false, top: WATER , side: WATER , texture_scale: 8.0 , bumpiness: 0.0, shininess: MATTE, emission: 0.0, subsurface: Material::Water   },
+];
+
+/// Look up a material.
+pub fn get(material: Material) -> &'static T {
+  &ALL[material as usize]
+}
+
+/// Every material, in order of their ids.
+pub fn all() -> &'static [T; COUNT] {
+  &ALL
+}
+
+#[test]
+fn materials_are_in_id_order() {
+  assert_eq!(get(Material::Water).name, "water");
+  assert_eq!(Material::Water as usize + 1, COUNT);
+  assert!(get(Material::Snow).solid);
+  assert!(!get(Material::Empty).solid);
+}
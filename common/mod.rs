@@ -3,13 +3,19 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+extern crate bincode;
 extern crate cgmath;
 extern crate collision;
 extern crate fnv;
 extern crate isosurface_extraction;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
+extern crate lz4;
 extern crate nanomsg;
 extern crate num;
+#[macro_use]
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -19,15 +25,29 @@ extern crate voxel_data;
 
 pub mod closure_series;
 pub mod color;
+pub mod compression;
+pub mod csg;
 pub mod cube_shell;
 pub mod entity;
 pub mod fnv_map;
 pub mod fnv_set;
+pub mod health;
 pub mod id_allocator;
 pub mod index;
 pub mod interval_timer;
+pub mod item;
+pub mod journal;
+pub mod material;
+pub mod movement;
+pub mod net;
 pub mod protocol;
 pub mod range_abs;
+pub mod raycast;
+pub mod schematic;
+pub mod season;
+pub mod snapshot;
 pub mod socket;
 pub mod surroundings_loader;
+pub mod transform;
 pub mod voxel;
+pub mod wire;
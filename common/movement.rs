@@ -0,0 +1,364 @@
+//! Player movement rules. The server runs these to move players, and clients run them too, to
+//! predict where their own player is before the server says so.
+
+use cgmath;
+use cgmath::{Matrix3, Vector3, ElementWise, InnerSpace};
+use collision::Aabb3;
+use std::f32::consts::PI;
+
+use protocol;
+use voxel;
+
+/// How many times per second players (and the rest of the world) are moved.
+pub const UPDATES_PER_SECOND: u64 = 30;
+
+/// The size of a player's bounding box.
+pub const PLAYER_SIZE: Vector3<f32> = Vector3 { x: 1.0, y: 2.0, z: 1.0 };
+
+/// The direction, around the y axis, that new players face.
+pub const SPAWN_LATERAL_ROTATION: f32 = PI / 2.0;
+
+const GRAVITY: f32 = 0.1;
+/// The extra upward acceleration while trying to jump.
+const JUMP_ACCEL: f32 = 0.3;
+const MAX_JUMP_FUEL: u32 = 4;
+const MAX_STEP_HEIGHT: f32 = 1.0;
+/// How much each walk input changes walking acceleration by.
+const WALK_ACCEL: f32 = 0.1;
+/// The fastest anything moves, in units per update. This is a little more than the speed of a long fall, so it only
+/// ever stops movement that's gone wrong.
+const MAX_SPEED: f32 = 10.0;
+
+/// The upward acceleration on a fully submerged body. This is a little more than gravity, so players float.
+const BUOYANCY: f32 = 0.12;
+/// The upward acceleration while trying to jump underwater, in place of `JUMP_ACCEL`.
+const SWIM_ACCEL: f32 = 0.05;
+/// The portion of its speed a fully submerged body loses each update.
+const WATER_DRAG: f32 = 0.2;
+/// How much walking acceleration a fully submerged body keeps.
+const SWIM_WALK_FACTOR: f32 = 0.5;
+/// How far up the player's body their eyes are, as a portion of its height.
+const EYE_HEIGHT: f32 = 0.9;
+/// Bodies moving sideways slower than this (per update) look like they're standing still.
+const STILL_SPEED: f32 = 0.01;
+
+/// Identifies a movement input, so the server can say which inputs it has applied.
+/// Each client numbers its inputs upward from 0.
+pub type InputId = u32;
+
+/// Something the movement rules can move around.
+pub trait Body {
+  /// What the body can bump into.
+  type Obstacle;
+
+  #[allow(missing_docs)]
+  fn bounds(&self) -> Aabb3<f32>;
+
+  /// Move by `shift`, unless that would overlap something.
+  /// If it would, stay put and return the bounds of what's in the way.
+  fn translate(&mut self, shift: Vector3<f32>) -> Option<(Aabb3<f32>, Self::Obstacle)>;
+
+  /// How much of the body is underwater, in [0, 1]. See `submersion`.
+  fn submersion(&self) -> f32;
+}
+
+/// How much of `bounds` is underwater, in [0, 1], going by the column of voxels through its center.
+pub fn submersion<IsWater>(bounds: &Aabb3<f32>, mut is_water: IsWater) -> f32 where
+  IsWater: FnMut(&voxel::bounds::T) -> bool,
+{
+  let height = bounds.max.y - bounds.min.y;
+  if height <= 0.0 {
+    return 0.0
+  }
+
+  let x = ((bounds.min.x + bounds.max.x) / 2.0).floor() as i32;
+  let z = ((bounds.min.z + bounds.max.z) / 2.0).floor() as i32;
+  let mut underwater = 0.0;
+  for y in bounds.min.y.floor() as i32 .. bounds.max.y.ceil() as i32 {
+    if is_water(&voxel::bounds::new(x, y, z, 0)) {
+      let low = f32::max(bounds.min.y, y as f32);
+      let high = f32::min(bounds.max.y, (y + 1) as f32);
+      underwater += high - low;
+    }
+  }
+  underwater / height
+}
+
+/// Whether a walk input is one a client could really send: a step of at most one along x and z, for pressing or
+/// releasing a key, and none up or down, since players walk along the ground.
+pub fn is_valid_walk(da: &Vector3<f32>) -> bool {
+  da.x.is_finite() && da.z.is_finite() && da.x.abs() <= 1.0 && da.z.abs() <= 1.0 && da.y == 0.0
+}
+
+/// A player's movement state, apart from where they are.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct T {
+  /// Units are world coordinates per update.
+  pub speed      : Vector3<f32>,
+  /// Units are world coordinates.
+  pub accel      : Vector3<f32>,
+  /// x/z units are relative to player facing.
+  pub walk_accel : Vector3<f32>,
+  /// This is depleted as we jump and replenished as we stand.
+  pub jump_fuel  : u32,
+  /// Are we currently trying to jump? (e.g. holding the key).
+  pub is_jumping : bool,
+  /// How much of the body was underwater at the last update, in [0, 1].
+  pub submersion : f32,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    speed      : Vector3::new(0.0, 0.0, 0.0),
+    accel      : Vector3::new(0.0, -GRAVITY, 0.0),
+    walk_accel : Vector3::new(0.0, 0.0, 0.0),
+    jump_fuel  : 0,
+    is_jumping : false,
+    submersion : 0.0,
+  }
+}
+
+/// The direction a player with the given `lateral_rotation` faces, as an angle from +x toward +z.
+pub fn heading(lateral_rotation: f32) -> f32 {
+  // Unrotated players face -z.
+  (-lateral_rotation.cos()).atan2(-lateral_rotation.sin())
+}
+
+impl T {
+  /// What a player moving like this is visibly doing.
+  pub fn animation(&self) -> protocol::Animation {
+    if self.speed.x.hypot(self.speed.z) < STILL_SPEED {
+      protocol::Animation::Idle
+    } else {
+      protocol::Animation::Walk
+    }
+  }
+
+  /// Whether the player's eyes are underwater.
+  pub fn is_submerged(&self) -> bool {
+    self.submersion >= EYE_HEIGHT
+  }
+
+  /// Changes the walking acceleration by the given `da`. However many inputs pile up, players walk no faster than
+  /// holding keys down lets them.
+  pub fn walk(&mut self, da: Vector3<f32>) {
+    self.walk_accel += da * WALK_ACCEL;
+    self.walk_accel.x = self.walk_accel.x.max(-WALK_ACCEL).min(WALK_ACCEL);
+    self.walk_accel.z = self.walk_accel.z.max(-WALK_ACCEL).min(WALK_ACCEL);
+  }
+
+  #[allow(missing_docs)]
+  pub fn start_jump(&mut self) {
+    if !self.is_jumping {
+      self.is_jumping = true;
+      self.accel.y += JUMP_ACCEL;
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn stop_jump(&mut self) {
+    if self.is_jumping {
+      self.is_jumping = false;
+      self.accel.y -= JUMP_ACCEL;
+    }
+  }
+
+  /// Translates a body by a vector.
+  /// If the body collides with something with a small height jump, the body will shift upward.
+  /// Returns the amount actually moved by.
+  fn translate<B: Body>(
+    &mut self,
+    body: &mut B,
+    requested_shift: Vector3<f32>,
+    obstacles: &mut Vec<B::Obstacle>,
+  ) -> Vector3<f32> {
+    let requested_min_y = body.bounds().min.y + requested_shift.y;
+
+    let mut shift = requested_shift;
+    let mut moved = Vector3::new(0.0, 0.0, 0.0);
+    let mut collided = false;
+    loop {
+      match body.translate(shift) {
+        None => {
+          moved = shift;
+          break
+        },
+        Some((obstacle_bounds, obstacle)) => {
+          obstacles.push(obstacle);
+          collided = true;
+
+          // Step to the top of whatever we hit.
+          let step_height = obstacle_bounds.max.y - requested_min_y;
+          assert!(step_height > 0.0);
+
+          if step_height > MAX_STEP_HEIGHT {
+            // Step is too big; we just ran into something.
+            break
+          }
+
+          shift += Vector3::new(0.0, step_height, 0.0);
+        },
+      }
+    }
+
+    if collided {
+      if requested_shift.y < 0.0 {
+        self.jump_fuel = MAX_JUMP_FUEL;
+      }
+
+      self.speed.y -= requested_shift.y;
+    } else {
+      if requested_shift.y < 0.0 {
+        self.jump_fuel = 0;
+      }
+    }
+
+    moved
+  }
+
+  /// Move a body forward by one update. `lateral_rotation` is the direction the player faces,
+  /// around the y axis. Returns the total shift, and everything the body ran into.
+  /// Underwater, bodies float, are slowed down, and swim (without running out of jump) instead of jumping.
+  pub fn update<B: Body>(&mut self, body: &mut B, lateral_rotation: f32) -> (Vector3<f32>, Vec<B::Obstacle>) {
+    self.submersion = body.submersion();
+
+    if self.is_jumping {
+      if self.submersion > 0.0 {
+        // Swimming up doesn't use up any jump.
+      } else if self.jump_fuel > 0 {
+        self.jump_fuel -= 1;
+      } else {
+        self.stop_jump();
+      }
+    }
+
+    if self.speed.magnitude2() > MAX_SPEED * MAX_SPEED {
+      self.speed = self.speed.normalize_to(MAX_SPEED);
+    }
+
+    let delta_p = self.speed;
+    let mut moved = Vector3::new(0.0, 0.0, 0.0);
+    let mut obstacles = Vec::new();
+    if delta_p.x != 0.0 {
+      moved += self.translate(body, Vector3::new(delta_p.x, 0.0, 0.0), &mut obstacles);
+    }
+    if delta_p.y != 0.0 {
+      moved += self.translate(body, Vector3::new(0.0, delta_p.y, 0.0), &mut obstacles);
+    }
+    if delta_p.z != 0.0 {
+      moved += self.translate(body, Vector3::new(0.0, 0.0, delta_p.z), &mut obstacles);
+    }
+
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let walk_v =
+        Matrix3::from_axis_angle(y_axis, cgmath::Rad(lateral_rotation))
+        * self.walk_accel;
+    let mut accel = self.accel;
+    if self.submersion > 0.0 {
+      accel.y += BUOYANCY * self.submersion;
+      if self.is_jumping {
+        accel.y -= JUMP_ACCEL - SWIM_ACCEL;
+      }
+    }
+    self.speed += walk_v * (1.0 - (1.0 - SWIM_WALK_FACTOR) * self.submersion);
+    self.speed += accel;
+    // friction
+    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
+    self.speed *= 1.0 - WATER_DRAG * self.submersion;
+
+    (moved, obstacles)
+  }
+}
+
+#[cfg(test)]
+struct Floor {
+  bounds: Aabb3<f32>,
+  /// Voxels below this are water.
+  water_level: i32,
+}
+
+#[cfg(test)]
+impl Body for Floor {
+  type Obstacle = ();
+
+  fn bounds(&self) -> Aabb3<f32> {
+    self.bounds
+  }
+
+  fn translate(&mut self, shift: Vector3<f32>) -> Option<(Aabb3<f32>, ())> {
+    let new_bounds = Aabb3::new(self.bounds.min + shift, self.bounds.max + shift);
+    if new_bounds.min.y < 0.0 {
+      Some((Aabb3::new(cgmath::Point3::new(-1000.0, -1.0, -1000.0), cgmath::Point3::new(1000.0, 0.0, 1000.0)), ()))
+    } else {
+      self.bounds = new_bounds;
+      None
+    }
+  }
+
+  fn submersion(&self) -> f32 {
+    let water_level = self.water_level;
+    submersion(&self.bounds, |bounds| bounds.y < water_level)
+  }
+}
+
+#[test]
+fn falls_and_lands() {
+  let mut body = Floor { bounds: Aabb3::new(cgmath::Point3::new(0.0, 4.0, 0.0), cgmath::Point3::new(1.0, 6.0, 1.0)), water_level: 0 };
+  let mut movement = new();
+  let mut landed = false;
+  for _ in 0 .. 100 {
+    let (_, obstacles) = movement.update(&mut body, 0.0);
+    landed = landed || !obstacles.is_empty();
+  }
+  assert!(landed);
+  assert!(0.0 <= body.bounds.min.y && body.bounds.min.y < 1.0);
+  assert_eq!(movement.jump_fuel, MAX_JUMP_FUEL);
+}
+
+#[test]
+fn floats_in_water() {
+  let mut body = Floor { bounds: Aabb3::new(cgmath::Point3::new(0.0, 0.0, 0.0), cgmath::Point3::new(1.0, 2.0, 1.0)), water_level: 8 };
+  let mut movement = new();
+  for _ in 0 .. 300 {
+    movement.update(&mut body, 0.0);
+  }
+  assert!(body.bounds.min.y > 5.0);
+  assert!(0.5 < movement.submersion && movement.submersion < 1.0);
+
+  // Swimming up pokes the player's head out.
+  movement.start_jump();
+  for _ in 0 .. 30 {
+    movement.update(&mut body, 0.0);
+  }
+  assert!(movement.is_jumping);
+  assert!(!movement.is_submerged());
+}
+
+#[test]
+fn walking_can_not_be_sped_up() {
+  assert!(is_valid_walk(&Vector3::new(1.0, 0.0, -1.0)));
+  assert!(!is_valid_walk(&Vector3::new(0.0, 1.0, 0.0)));
+  assert!(!is_valid_walk(&Vector3::new(4.0, 0.0, 0.0)));
+  assert!(!is_valid_walk(&Vector3::new(::std::f32::NAN, 0.0, 0.0)));
+
+  // Pressing a key over and over without letting go doesn't add up.
+  let mut movement = new();
+  for _ in 0 .. 10 {
+    movement.walk(Vector3::new(0.0, 0.0, -1.0));
+  }
+  movement.walk(Vector3::new(0.0, 0.0, 1.0));
+  assert_eq!(movement.walk_accel, Vector3::new(0.0, 0.0, 0.0));
+
+  // Nothing moves faster than `MAX_SPEED`.
+  let mut body = Floor { bounds: Aabb3::new(cgmath::Point3::new(0.0, 4.0, 0.0), cgmath::Point3::new(1.0, 6.0, 1.0)), water_level: 0 };
+  movement.speed = Vector3::new(100.0, 0.0, 0.0);
+  let (moved, _) = movement.update(&mut body, 0.0);
+  assert!(moved.magnitude() <= MAX_SPEED);
+}
+
+#[test]
+fn unrotated_players_face_negative_z() {
+  assert!((heading(0.0) + PI / 2.0).abs() < 1e-6);
+  assert!((heading(PI / 2.0).abs() - PI).abs() < 1e-6);
+}
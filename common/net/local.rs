@@ -0,0 +1,125 @@
+//! Messages passed over channels within one process, so a client and server running together
+//! don't need any sockets. URLs are arbitrary names.
+
+use std;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use super::Result;
+
+lazy_static! {
+  /// The receivers bound in this process, by name.
+  static ref LISTENERS: Mutex<HashMap<String, mpsc::Sender<Vec<u8>>>> = Mutex::new(HashMap::new());
+}
+
+/// How long to keep looking for a receiver before giving up, since it may still be starting up.
+const CONNECT_BUDGET_MS: u64 = 5000;
+/// How long to wait between looking for a receiver.
+const CONNECT_RETRY_MS: u64 = 100;
+
+/// A connection to a local `Receiver`.
+pub struct Sender {
+  messages: mpsc::Sender<Vec<u8>>,
+}
+
+impl Sender {
+  /// Connect, trying again for a while if nothing's listening yet.
+  pub fn new(name: &str) -> std::io::Result<Sender> {
+    Sender::within(name, Duration::from_millis(CONNECT_BUDGET_MS))
+  }
+
+  /// Connect, trying again until `budget` is spent.
+  pub fn within(name: &str, budget: Duration) -> std::io::Result<Sender> {
+    let deadline = Instant::now() + budget;
+    loop {
+      let err =
+        match Sender::try_new(name) {
+          Ok(sender) => return Ok(sender),
+          Err(err) => err,
+        };
+      if Instant::now() + Duration::from_millis(CONNECT_RETRY_MS) >= deadline {
+        return Err(err)
+      }
+      std::thread::sleep(Duration::from_millis(CONNECT_RETRY_MS));
+    }
+  }
+
+  /// Connect if something's listening on `name` right now.
+  pub fn try_new(name: &str) -> std::io::Result<Sender> {
+    match LISTENERS.lock().unwrap().get(name) {
+      None => Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "nothing is listening locally")),
+      Some(messages) =>
+        Ok(Sender {
+          messages: messages.clone(),
+        }),
+    }
+  }
+}
+
+impl super::Sender for Sender {
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+    self.messages.send(msg.to_vec())
+      .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "local receiver was closed"))
+  }
+}
+
+/// Receives the messages sent to a name.
+pub struct Receiver {
+  name     : String,
+  messages : mpsc::Receiver<Vec<u8>>,
+  timeout  : Option<Duration>,
+}
+
+impl Receiver {
+  /// Start listening under `name`, replacing anything that was listening there before.
+  pub fn new(name: &str, timeout: Option<Duration>) -> Receiver {
+    let (send, recv) = mpsc::channel();
+    LISTENERS.lock().unwrap().insert(name.to_owned(), send);
+    Receiver {
+      name     : name.to_owned(),
+      messages : recv,
+      timeout  : timeout,
+    }
+  }
+}
+
+impl Drop for Receiver {
+  fn drop(&mut self) {
+    LISTENERS.lock().unwrap().remove(&self.name);
+  }
+}
+
+impl super::Receiver for Receiver {
+  fn read(&mut self) -> Option<Vec<u8>> {
+    match self.timeout {
+      None => self.messages.recv().ok(),
+      Some(timeout) => self.messages.recv_timeout(timeout).ok(),
+    }
+  }
+
+  fn try_read(&mut self) -> Result<Vec<u8>> {
+    match self.messages.try_recv() {
+      Ok(msg) => Result::Success(msg),
+      Err(mpsc::TryRecvError::Empty) => Result::Empty,
+      Err(mpsc::TryRecvError::Disconnected) => Result::Terminating,
+    }
+  }
+}
+
+#[test]
+fn senders_reach_the_receiver_by_name() {
+  use super::{Sender as S, Receiver as R};
+
+  let mut receiver = Receiver::new("senders_reach_the_receiver_by_name", None);
+  let mut a = Sender::new("senders_reach_the_receiver_by_name");
+  let mut b = Sender::new("senders_reach_the_receiver_by_name");
+  a.write(b"a").unwrap();
+  b.write(b"b").unwrap();
+  assert_eq!(receiver.read(), Some(b"a".to_vec()));
+  match receiver.try_read() {
+    Result::Success(msg) => assert_eq!(msg, b"b".to_vec()),
+    _ => panic!("expected a message"),
+  }
+}
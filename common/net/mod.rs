@@ -0,0 +1,184 @@
+//! Transports for the one-way message streams between client and server. Every transport moves
+//! whole messages: a receiver is bound to a URL, and any number of senders connect to it.
+
+pub mod local;
+pub mod tcp;
+
+use std;
+use std::time::Duration;
+
+use socket;
+
+pub use socket::Result;
+
+/// The sending end of a message stream.
+pub trait Sender: std::marker::Send {
+  /// Block until we can send a message.
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()>;
+}
+
+/// The receiving end of a message stream.
+pub trait Receiver: std::marker::Send {
+  /// Block until a message arrives. `None` if the receive times out or the stream is closed.
+  fn read(&mut self) -> Option<Vec<u8>>;
+  /// Try to read a message without blocking.
+  fn try_read(&mut self) -> Result<Vec<u8>>;
+}
+
+impl Sender for socket::SendSocket {
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+    socket::SendSocket::write(self, msg)
+  }
+}
+
+impl Receiver for socket::ReceiveSocket {
+  fn read(&mut self) -> Option<Vec<u8>> {
+    socket::ReceiveSocket::read(self)
+  }
+
+  fn try_read(&mut self) -> Result<Vec<u8>> {
+    socket::ReceiveSocket::try_read(self)
+  }
+}
+
+/// Which kind of connection to carry messages over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+  /// nanomsg push/pull sockets, with URLs like "ipc:///tmp/server.ipc" or "tcp://127.0.0.1:5555".
+  Nanomsg,
+  /// Plain TCP streams, with addresses like "127.0.0.1:5555".
+  Tcp,
+  /// Channels within this process, with any name as a URL. Only usable when client and server run together.
+  Local,
+}
+
+impl Transport {
+  /// Parse a transport's name, as given on the command line.
+  pub fn parse(name: &str) -> Option<Transport> {
+    match name {
+      "nanomsg" => Some(Transport::Nanomsg),
+      "tcp"     => Some(Transport::Tcp),
+      "local"   => Some(Transport::Local),
+      _         => None,
+    }
+  }
+
+  /// The URL the server listens on if none is given.
+  pub fn default_server_url(self) -> &'static str {
+    match self {
+      Transport::Nanomsg => "ipc:///tmp/server.ipc",
+      Transport::Tcp     => "127.0.0.1:28711",
+      Transport::Local   => "server",
+    }
+  }
+
+  /// The URL a client listens on if none is given.
+  pub fn default_client_url(self) -> &'static str {
+    match self {
+      Transport::Nanomsg => "ipc:///tmp/client.ipc",
+      Transport::Tcp     => "127.0.0.1:28712",
+      Transport::Local   => "client",
+    }
+  }
+
+  /// Connect to a receiver listening at `url`, waiting a while for it if it's still starting up.
+  pub fn connect(self, url: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Sender>> {
+    match self {
+      // nanomsg connects in the background, so there's nothing to fail yet.
+      Transport::Nanomsg => Ok(Box::new(socket::SendSocket::new(url, timeout))),
+      Transport::Tcp     => tcp::Sender::new(url, timeout).map(|s| Box::new(s) as Box<dyn Sender>),
+      Transport::Local   => local::Sender::new(url).map(|s| Box::new(s) as Box<dyn Sender>),
+    }
+  }
+
+  /// Connect to a receiver listening at `url`, trying again until `budget` is spent.
+  pub fn connect_within(
+    self,
+    url     : &str,
+    budget  : Duration,
+    timeout : Option<Duration>,
+  ) -> std::io::Result<Box<dyn Sender>> {
+    match self {
+      // nanomsg connects in the background, so there's nothing to fail yet.
+      Transport::Nanomsg => Ok(Box::new(socket::SendSocket::new(url, timeout))),
+      Transport::Tcp     => tcp::Sender::within(url, budget, timeout).map(|s| Box::new(s) as Box<dyn Sender>),
+      Transport::Local   => local::Sender::within(url, budget).map(|s| Box::new(s) as Box<dyn Sender>),
+    }
+  }
+
+  /// Make one attempt to connect to a receiver listening at `url`, e.g. to reconnect to a server that may be down.
+  pub fn try_connect(self, url: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Sender>> {
+    match self {
+      // nanomsg connects in the background, so there's nothing to fail yet.
+      Transport::Nanomsg => Ok(Box::new(socket::SendSocket::new(url, timeout))),
+      Transport::Tcp     => tcp::Sender::try_new(url, timeout).map(|s| Box::new(s) as Box<dyn Sender>),
+      Transport::Local   => local::Sender::try_new(url).map(|s| Box::new(s) as Box<dyn Sender>),
+    }
+  }
+
+  /// Listen for messages sent to `url`.
+  pub fn bind(self, url: &str, timeout: Option<Duration>) -> std::io::Result<Box<dyn Receiver>> {
+    match self {
+      Transport::Nanomsg => Ok(Box::new(socket::ReceiveSocket::new(url, timeout))),
+      Transport::Tcp     => tcp::Receiver::new(url, timeout).map(|r| Box::new(r) as Box<dyn Receiver>),
+      Transport::Local   => Ok(Box::new(local::Receiver::new(url, timeout))),
+    }
+  }
+
+  /// An address for the `n`th of several endpoints sharing a base address, e.g. clients running in the same process.
+  /// Port numbers are counted up from; anything else gets a suffix. The 0th endpoint keeps `url` as it is.
+  pub fn nth_url(self, url: &str, n: u32) -> String {
+    if n == 0 {
+      return url.to_owned()
+    }
+    let has_port =
+      match self {
+        Transport::Nanomsg => url.starts_with("tcp://"),
+        Transport::Tcp     => true,
+        Transport::Local   => false,
+      };
+    if has_port {
+      if let Some(colon) = url.rfind(':') {
+        if let Ok(port) = url[colon + 1 ..].parse::<u32>() {
+          return format!("{}:{}", &url[.. colon], port + n)
+        }
+      }
+    }
+    format!("{}-{}", url, n)
+  }
+
+  /// Pull a `--transport=<name>` flag out of a list of command-line arguments, if there is one.
+  pub fn from_args(args: &mut Vec<String>) -> Option<Transport> {
+    const FLAG: &'static str = "--transport=";
+    let mut transport = None;
+    args.retain(|arg| {
+      if arg.starts_with(FLAG) {
+        let name = &arg[FLAG.len() ..];
+        transport = Some(Transport::parse(name).unwrap_or_else(|| panic!("Unknown transport {:?}", name)));
+        false
+      } else {
+        true
+      }
+    });
+    transport
+  }
+}
+
+#[test]
+fn transport_flag_is_removed_from_args() {
+  let mut args = vec!("a".to_owned(), "--transport=tcp".to_owned(), "b".to_owned());
+  assert_eq!(Transport::from_args(&mut args), Some(Transport::Tcp));
+  assert_eq!(args, vec!("a".to_owned(), "b".to_owned()));
+
+  let mut args = vec!("a".to_owned());
+  assert_eq!(Transport::from_args(&mut args), None);
+}
+
+#[test]
+fn nth_urls_are_distinct() {
+  assert_eq!(Transport::Tcp.nth_url("127.0.0.1:28712", 0), "127.0.0.1:28712");
+  assert_eq!(Transport::Tcp.nth_url("127.0.0.1:28712", 3), "127.0.0.1:28715");
+  assert_eq!(Transport::Nanomsg.nth_url("tcp://127.0.0.1:5555", 1), "tcp://127.0.0.1:5556");
+  assert_eq!(Transport::Nanomsg.nth_url("ipc:///tmp/client.ipc", 2), "ipc:///tmp/client.ipc-2");
+  assert_eq!(Transport::Local.nth_url("client", 1), "client-1");
+}
@@ -0,0 +1,293 @@
+//! Messages over plain TCP streams. Each message is framed with its length, as a little-endian u32.
+
+use std;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use super::Result;
+
+/// How long to keep trying to connect before giving up, since the receiver may still be starting up.
+const CONNECT_BUDGET_MS: u64 = 5000;
+/// How long to wait between connection attempts.
+const CONNECT_RETRY_MS: u64 = 100;
+/// The longest message that can be sent. A longer frame means the stream is garbled (or hostile), so it's closed.
+pub const MAX_MESSAGE_LEN: u32 = 64 << 20;
+/// How long a receiver's listening thread waits between checks for new connections, and for being dropped.
+const ACCEPT_POLL_MS: u64 = 50;
+
+fn too_long(len: usize) -> std::io::Error {
+  std::io::Error::new(
+    std::io::ErrorKind::InvalidData,
+    format!("a message of {} bytes is longer than the limit of {}", len, MAX_MESSAGE_LEN),
+  )
+}
+
+fn write_message<W: Write>(w: &mut W, msg: &[u8]) -> std::io::Result<()> {
+  if msg.len() > MAX_MESSAGE_LEN as usize {
+    return Err(too_long(msg.len()))
+  }
+  let len = msg.len() as u32;
+  let header = [len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+  w.write_all(&header)?;
+  w.write_all(msg)
+}
+
+/// Read one message. Fails with `UnexpectedEof` once the stream is closed.
+fn read_message<R: Read>(r: &mut R) -> std::io::Result<Vec<u8>> {
+  let mut header = [0; 4];
+  r.read_exact(&mut header)?;
+  let len =
+    (header[0] as u32) |
+    (header[1] as u32) << 8 |
+    (header[2] as u32) << 16 |
+    (header[3] as u32) << 24;
+  if len > MAX_MESSAGE_LEN {
+    return Err(too_long(len as usize))
+  }
+  let mut msg = vec!(0; len as usize);
+  r.read_exact(&mut msg)?;
+  Ok(msg)
+}
+
+/// A connection to a TCP `Receiver`.
+pub struct Sender {
+  stream: TcpStream,
+}
+
+impl Sender {
+  /// Connect, trying again for a while if nothing's listening yet.
+  pub fn new(address: &str, timeout: Option<Duration>) -> std::io::Result<Sender> {
+    Sender::within(address, Duration::from_millis(CONNECT_BUDGET_MS), timeout)
+  }
+
+  /// Connect, trying again until `budget` is spent. No one attempt outlasts what's left of it.
+  pub fn within(address: &str, budget: Duration, timeout: Option<Duration>) -> std::io::Result<Sender> {
+    let deadline = Instant::now() + budget;
+    loop {
+      let now = Instant::now();
+      let left = if deadline > now { deadline - now } else { Duration::from_millis(1) };
+      let err =
+        match Sender::connect(address, left, timeout) {
+          Ok(sender) => return Ok(sender),
+          Err(err) => err,
+        };
+      if Instant::now() + Duration::from_millis(CONNECT_RETRY_MS) >= deadline {
+        return Err(err)
+      }
+      std::thread::sleep(Duration::from_millis(CONNECT_RETRY_MS));
+    }
+  }
+
+  /// Make one attempt to connect to any of `address`'s addresses, taking at most `limit` for each.
+  fn connect(address: &str, limit: Duration, timeout: Option<Duration>) -> std::io::Result<Sender> {
+    let mut last_err =
+      std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} has no addresses", address));
+    for address in address.to_socket_addrs()? {
+      match TcpStream::connect_timeout(&address, limit) {
+        Ok(stream) => return Sender::of_stream(stream, timeout),
+        Err(err) => last_err = err,
+      }
+    }
+    Err(last_err)
+  }
+
+  /// Make one attempt to connect.
+  pub fn try_new(address: &str, timeout: Option<Duration>) -> std::io::Result<Sender> {
+    Sender::of_stream(TcpStream::connect(address)?, timeout)
+  }
+
+  fn of_stream(stream: TcpStream, timeout: Option<Duration>) -> std::io::Result<Sender> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(timeout)?;
+    Ok(Sender {
+      stream: stream,
+    })
+  }
+}
+
+impl super::Sender for Sender {
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+    write_message(&mut self.stream, msg)
+  }
+}
+
+/// Accepts TCP connections, and receives the messages sent over all of them. Dropping it closes the connections and
+/// stops its threads.
+pub struct Receiver {
+  messages : mpsc::Receiver<Vec<u8>>,
+  timeout  : Option<Duration>,
+  stop     : Arc<AtomicBool>,
+  accept   : Option<std::thread::JoinHandle<()>>,
+}
+
+impl Receiver {
+  #[allow(missing_docs)]
+  pub fn new(address: &str, timeout: Option<Duration>) -> std::io::Result<Receiver> {
+    let listener = TcpListener::bind(address)?;
+    // Accept without blocking, so the listening thread notices when it's time to stop.
+    listener.set_nonblocking(true)?;
+    let (send, recv) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let accept = {
+      let stop = stop.clone();
+      std::thread::spawn(move || accept(listener, send, &stop))
+    };
+
+    Ok(Receiver {
+      messages : recv,
+      timeout  : timeout,
+      stop     : stop,
+      accept   : Some(accept),
+    })
+  }
+}
+
+impl Drop for Receiver {
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::SeqCst);
+    if let Some(accept) = self.accept.take() {
+      let _ = accept.join();
+    }
+  }
+}
+
+/// A connection being read on its own thread.
+struct Connection {
+  /// For shutting the connection down from the listening thread.
+  stream : TcpStream,
+  done   : Arc<AtomicBool>,
+  reader : std::thread::JoinHandle<()>,
+}
+
+/// Accept connections until `stop` is set, reading each on its own thread, then close them all.
+fn accept(listener: TcpListener, send: mpsc::Sender<Vec<u8>>, stop: &AtomicBool) {
+  let mut connections: Vec<Connection> = Vec::new();
+  while !stop.load(Ordering::SeqCst) {
+    let (finished, open): (Vec<_>, Vec<_>) =
+      connections.into_iter().partition(|connection| connection.done.load(Ordering::SeqCst));
+    connections = open;
+    for connection in finished {
+      let _ = connection.reader.join();
+    }
+
+    let stream =
+      match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+          std::thread::sleep(Duration::from_millis(ACCEPT_POLL_MS));
+          continue
+        },
+        Err(err) => {
+          warn!("Error accepting a connection: {:?}", err);
+          continue
+        },
+      };
+    let handle =
+      match stream.set_nonblocking(false).and_then(|()| stream.try_clone()) {
+        Ok(handle) => handle,
+        Err(err) => {
+          warn!("Error setting up a connection: {:?}", err);
+          continue
+        },
+      };
+    let send = send.clone();
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+      let done = done.clone();
+      std::thread::spawn(move || {
+        read_stream(stream, &send);
+        done.store(true, Ordering::SeqCst);
+      })
+    };
+    connections.push(Connection { stream: handle, done: done, reader: reader });
+  }
+
+  for connection in connections {
+    let _ = connection.stream.shutdown(Shutdown::Both);
+    let _ = connection.reader.join();
+  }
+}
+
+/// Pass on the messages from a stream until it closes, breaks, or sends a frame that's too long.
+fn read_stream(mut stream: TcpStream, send: &mpsc::Sender<Vec<u8>>) {
+  loop {
+    match read_message(&mut stream) {
+      Ok(msg) => {
+        if send.send(msg).is_err() {
+          break
+        }
+      },
+      Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+      Err(err) => {
+        warn!("Closing a connection: {}", err);
+        break
+      },
+    }
+  }
+  let _ = stream.shutdown(Shutdown::Both);
+}
+
+impl super::Receiver for Receiver {
+  fn read(&mut self) -> Option<Vec<u8>> {
+    match self.timeout {
+      None => self.messages.recv().ok(),
+      Some(timeout) => self.messages.recv_timeout(timeout).ok(),
+    }
+  }
+
+  fn try_read(&mut self) -> Result<Vec<u8>> {
+    match self.messages.try_recv() {
+      Ok(msg) => Result::Success(msg),
+      Err(mpsc::TryRecvError::Empty) => Result::Empty,
+      Err(mpsc::TryRecvError::Disconnected) => Result::Terminating,
+    }
+  }
+}
+
+#[test]
+fn messages_survive_framing() {
+  let mut buffer = Vec::new();
+  write_message(&mut buffer, b"hello").unwrap();
+  write_message(&mut buffer, b"").unwrap();
+  write_message(&mut buffer, &[7; 300]).unwrap();
+
+  let mut r = std::io::Cursor::new(buffer);
+  assert_eq!(read_message(&mut r).unwrap(), b"hello".to_vec());
+  assert_eq!(read_message(&mut r).unwrap(), Vec::new());
+  assert_eq!(read_message(&mut r).unwrap(), vec!(7; 300));
+  assert_eq!(read_message(&mut r).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn overlong_frames_are_errors() {
+  let len = MAX_MESSAGE_LEN + 1;
+  let header = vec!(len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8);
+  let mut r = std::io::Cursor::new(header);
+  assert_eq!(read_message(&mut r).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn dropped_receivers_stop_listening() {
+  use super::Receiver as R;
+  use super::Sender as S;
+
+  let address = "127.0.0.1:28799";
+  let mut receiver = Receiver::new(address, Some(Duration::from_secs(5))).unwrap();
+  let mut sender = Sender::try_new(address, None).unwrap();
+  sender.write(b"hello").unwrap();
+  assert_eq!(receiver.read(), Some(b"hello".to_vec()));
+
+  std::mem::drop(receiver);
+  assert!(Sender::try_new(address, None).is_err());
+}
+
+#[test]
+fn connecting_gives_up_within_its_budget() {
+  let start = Instant::now();
+  assert!(Sender::within("127.0.0.1:1", Duration::from_millis(500), None).is_err());
+  assert!(start.elapsed() < Duration::from_secs(2));
+}
@@ -1,12 +1,20 @@
 //! Defines the messages passed between client and server.
 
+use bincode;
 use cgmath::{Vector2, Vector3, Point3};
 use collision::{Aabb3};
 use std::default::Default;
 use std::ops::Add;
 
+use color::Color3;
+use compression;
 use entity;
+use health;
+use item;
+use movement;
+use schematic;
 use voxel;
+use wire;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 /// Unique client ID.
@@ -27,36 +35,326 @@ impl Add<u32> for ClientId {
   }
 }
 
+/// The version of the protocol this build speaks. Bump this whenever messages change in a way other builds can't
+/// read; clients and servers only talk to the same version.
+pub const VERSION: u32 = 8;
+
+/// Optional parts of the protocol. A client offers the ones it supports when it connects, and the connection
+/// uses the ones the server supports too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Feature {
+  /// Snapshots are sent as changes from one the client has acknowledged. Without this, every snapshot is whole.
+  DeltaSnapshots,
+}
+
+/// The features this build supports.
+pub const FEATURES: &'static [Feature] = &[Feature::DeltaSnapshots];
+
+/// The features out of `offered` that this build supports too.
+pub fn negotiate_features(offered: &[Feature]) -> Vec<Feature> {
+  FEATURES.iter().cloned().filter(|feature| offered.contains(feature)).collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How a client introduces itself when it connects.
+pub struct Hello {
+  /// The `VERSION` the client speaks. This and `client_url` have to stay first, so servers can still read them from
+  /// clients of other versions.
+  pub version     : u32,
+  /// The "return address".
+  pub client_url  : String,
+  /// The payload compression schemes the client understands.
+  pub compression : Vec<compression::Scheme>,
+  /// The optional features the client supports.
+  pub features    : Vec<Feature>,
+}
+
+/// This build's `Hello`, for a client listening on `client_url`.
+pub fn hello(client_url: &str) -> Hello {
+  Hello {
+    version     : VERSION,
+    client_url  : client_url.to_owned(),
+    compression : compression::SUPPORTED.to_vec(),
+    features    : FEATURES.to_vec(),
+  }
+}
+
+/// Read the version and return address from a message, if it's an `Init`. Unlike decoding the whole message,
+/// this works on `Init`s from any version.
+pub fn peek_init(msg: &[u8]) -> Option<(u32, String)> {
+  if wire::peek_tag(msg) != Some(wire::tag("Init")) {
+    return None
+  }
+  let (_, version, client_url): (u32, u32, String) = bincode::deserialize(msg).ok()?;
+  Some((version, client_url))
+}
+
+/// A secret the server gives each client when it connects. A client that loses its connection sends it back to
+/// pick up where it left off, with the same client ID and player.
+pub type SessionToken = u64;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+/// Identifies one of the snapshots sent to a client. Later snapshots have larger IDs.
+pub struct SnapshotId(pub u32);
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+/// Identifies one of the worlds a server hosts. Players join in `WorldId(0)`.
+pub struct WorldId(pub u32);
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+/// Identifies a point light the server has placed.
+pub struct LightId(u32);
+
+impl Default for LightId {
+  fn default() -> LightId {
+    LightId(0)
+  }
+}
+
+impl Add<u32> for LightId {
+  type Output = LightId;
+
+  fn add(self, rhs: u32) -> LightId {
+    let LightId(i) = self;
+    LightId(i + rhs)
+  }
+}
+
+impl LightId {
+  #[allow(missing_docs)]
+  pub fn to_u32(self) -> u32 {
+    self.0
+  }
+}
+
+/// The most point lights a world can have.
+pub const MAX_LIGHTS: usize = 256;
+
+/// What's falling from the sky.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precipitation {
+  None,
+  Rain,
+  Snow,
+}
+
+/// The weather where a player is.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Weather {
+  #[allow(missing_docs)]
+  pub precipitation : Precipitation,
+  /// How hard it's raining or snowing, in [0, 1].
+  pub intensity     : f32,
+  /// The wind's direction in the xz plane. Its length is the wind's strength.
+  pub wind          : Vector2<f32>,
+}
+
+/// Light shining out from a point, e.g. a torch.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+  #[allow(missing_docs)]
+  pub position : Point3<f32>,
+  /// The light's color and brightness right next to it. It fades out with distance.
+  pub color    : Color3<f32>,
+  /// Nothing further away than this is lit.
+  pub radius   : f32,
+}
+
+/// The number of slots in a player's inventory.
+pub const INVENTORY_SLOTS: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Some amount of one item, in an inventory slot.
+pub struct ItemStack {
+  #[allow(missing_docs)]
+  pub item  : item::Id,
+  #[allow(missing_docs)]
+  pub count : u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// The shape of a voxel modification.
+pub enum Brush {
+  /// A sphere of the given radius.
+  Sphere {
+    /// The sphere's radius.
+    radius: f32,
+  },
+  /// An axis-aligned cube.
+  Cube {
+    /// The distance from the cube's center to each face.
+    radius: f32,
+  },
+  /// An upright cylinder, as tall as it is wide.
+  Cylinder {
+    #[allow(missing_docs)]
+    radius: f32,
+  },
+  /// Blur the terrain within a sphere. Adding fills in dips; removing digs out bumps.
+  Smooth {
+    #[allow(missing_docs)]
+    radius: f32,
+    /// How far to blur, in [0, 1].
+    strength: f32,
+  },
+  /// Level the terrain within an upright cylinder to the height of its center. Adding fills in below that
+  /// height; removing digs out above it.
+  Flatten {
+    #[allow(missing_docs)]
+    radius: f32,
+    /// How deep to level, as a portion of the radius, in [0, 1].
+    strength: f32,
+  },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// What a voxel modification does to the voxels under the brush.
+pub enum BrushAction {
+  /// Fill with the given material.
+  Add(voxel::Material),
+  /// Dig out.
+  Remove,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the client sends to the server.
 pub enum ClientToServer {
-  /// Notify the server that the client exists, and provide a "return address".
-  Init(String),
-  /// Ping
-  Ping(ClientId),
-  /// Ask the server to create a new player.
-  AddPlayer(ClientId),
+  /// Notify the server that the client exists, and say how to talk to it. This has to keep its name and fields; see
+  /// `Hello`.
+  Init(Hello),
+  /// Take up a session again over a new connection, after the old one dropped. This is sent instead of `Init`.
+  Resume {
+    #[allow(missing_docs)]
+    client_id : ClientId,
+    /// The token the server sent with this client's `LeaseId`.
+    token     : SessionToken,
+    /// How to talk to the client now, e.g. its new "return address".
+    hello     : Hello,
+  },
+  /// Measure the round trip to the server, and how far apart the clocks are, with the client's time in ns.
+  Ping(ClientId, u64),
+  /// Ask the server to create a new player, with the name to show above it.
+  AddPlayer(ClientId, String),
   /// Add a vector the player's acceleration.
-  Walk(entity::id::Player, Vector3<f32>),
+  Walk(entity::id::Player, Vector3<f32>, movement::InputId),
   /// Rotate the player by some amount.
   RotatePlayer(entity::id::Player, Vector2<f32>),
   /// [Try to] start a jump for the player.
-  StartJump(entity::id::Player),
+  StartJump(entity::id::Player, movement::InputId),
   /// [Try to] stop a jump for the player.
-  StopJump(entity::id::Player),
+  StopJump(entity::id::Player, movement::InputId),
   /// Ask the server to send a block of terrain.
   RequestVoxels {
     /// The time, in nanoseconds, when the voxels were requested.
     time_requested_ns : u64,
     /// The ID of the requesting client.
     client_id       : ClientId,
+    /// The world to fetch the voxels from.
+    world           : WorldId,
     /// The bounds of the voxels to fetch.
     voxels          : Vec<voxel::bounds::T>,
   },
+  /// Don't bother with a `RequestVoxels` that hasn't been served yet; the client no longer needs it.
+  CancelChunkRequest {
+    #[allow(missing_docs)]
+    client_id         : ClientId,
+    /// The `time_requested_ns` of the request to cancel.
+    time_requested_ns : u64,
+  },
   /// Brush-remove where the player's looking.
   Add(entity::id::Player),
   /// Brush-add at where the player's looking.
   Remove(entity::id::Player),
+  /// Say something to everyone.
+  Chat(entity::id::Player, String),
+  /// Choose which inventory slot to place from.
+  SelectItem(entity::id::Player, u32),
+  /// Place some of the selected item where the player's looking, if it can be placed.
+  Place(entity::id::Player),
+  /// Drop one of the selected item on the ground in front of the player.
+  DropItem(entity::id::Player),
+  /// Throw one of the selected item where the player's looking.
+  Throw(entity::id::Player),
+  /// Turn some of the player's items into others, following a recipe.
+  Craft(entity::id::Player, item::RecipeId),
+  /// Tell the server a snapshot arrived, so later snapshots can be sent as changes against it.
+  AckSnapshot(ClientId, SnapshotId),
+  /// Teleport the player to one of the server's named points.
+  RequestTeleport(entity::id::Player, String),
+  /// Apply a brush to the terrain.
+  ModifyVoxels {
+    /// The player applying the brush, who can undo it.
+    player   : entity::id::Player,
+    /// The world to modify.
+    world    : WorldId,
+    /// The shape of the modification.
+    brush    : Brush,
+    /// Where to center the brush.
+    position : Point3<f32>,
+    /// Whether to add or remove material.
+    action   : BrushAction,
+  },
+  /// Undo the player's most recent brush edit.
+  Undo(entity::id::Player),
+  /// Redo the player's most recently undone brush edit.
+  Redo(entity::id::Player),
+  /// Copy the voxels from `low` up to (but not including) `high` into the client's clipboard. The server sends them
+  /// back as `Copied`.
+  Copy {
+    #[allow(missing_docs)]
+    player : entity::id::Player,
+    #[allow(missing_docs)]
+    world  : WorldId,
+    #[allow(missing_docs)]
+    low    : Point3<i32>,
+    #[allow(missing_docs)]
+    high   : Point3<i32>,
+  },
+  /// Paste a schematic into the terrain with its low corner at `position`, like a brush the player can undo.
+  Paste {
+    #[allow(missing_docs)]
+    player    : entity::id::Player,
+    #[allow(missing_docs)]
+    world     : WorldId,
+    #[allow(missing_docs)]
+    position  : Point3<i32>,
+    #[allow(missing_docs)]
+    schematic : schematic::T,
+  },
+}
+
+impl ClientToServer {
+  /// The name of the message's variant, e.g. for counting messages by type.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      ClientToServer::Init(..) => "Init",
+      ClientToServer::Resume { .. } => "Resume",
+      ClientToServer::Ping(..) => "Ping",
+      ClientToServer::AddPlayer(..) => "AddPlayer",
+      ClientToServer::Walk(..) => "Walk",
+      ClientToServer::RotatePlayer(..) => "RotatePlayer",
+      ClientToServer::StartJump(..) => "StartJump",
+      ClientToServer::StopJump(..) => "StopJump",
+      ClientToServer::RequestVoxels { .. } => "RequestVoxels",
+      ClientToServer::CancelChunkRequest { .. } => "CancelChunkRequest",
+      ClientToServer::Add(..) => "Add",
+      ClientToServer::Remove(..) => "Remove",
+      ClientToServer::Chat(..) => "Chat",
+      ClientToServer::SelectItem(..) => "SelectItem",
+      ClientToServer::Place(..) => "Place",
+      ClientToServer::DropItem(..) => "DropItem",
+      ClientToServer::Throw(..) => "Throw",
+      ClientToServer::Craft(..) => "Craft",
+      ClientToServer::AckSnapshot(..) => "AckSnapshot",
+      ClientToServer::RequestTeleport(..) => "RequestTeleport",
+      ClientToServer::ModifyVoxels { .. } => "ModifyVoxels",
+      ClientToServer::Undo(..) => "Undo",
+      ClientToServer::Redo(..) => "Redo",
+      ClientToServer::Copy { .. } => "Copy",
+      ClientToServer::Paste { .. } => "Paste",
+    }
+  }
 }
 
 /// Why a block is being sent to a client.
@@ -67,8 +365,107 @@ pub enum VoxelReason {
     /// The time, in nanoseconds, when the voxels were requested.
     at: u64,
   },
-  /// The block has been updated.
-  Updated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// What a player or mob is visibly doing.
+#[allow(missing_docs)]
+pub enum Animation {
+  Idle,
+  Walk,
+  Run,
+  Attack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// What a client needs to know to show a player.
+pub struct PlayerState {
+  #[allow(missing_docs)]
+  pub id        : entity::id::Player,
+  /// The player's bounding box.
+  pub bounds    : Aabb3<f32>,
+  /// The direction the player is facing, as an angle from +x toward +z.
+  pub heading   : f32,
+  /// What the player is visibly doing.
+  pub animation : Animation,
+  #[allow(missing_docs)]
+  pub health    : health::T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// What a client needs to know to show a mob.
+pub struct MobState {
+  #[allow(missing_docs)]
+  pub id        : entity::id::Mob,
+  /// The mob's bounding box.
+  pub bounds    : Aabb3<f32>,
+  /// The direction the mob is facing, as an angle from +x toward +z.
+  pub heading   : f32,
+  /// What the mob is visibly doing.
+  pub animation : Animation,
+  #[allow(missing_docs)]
+  pub health    : health::T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// What sort of thing an item entity is, so clients know how to show it.
+pub enum EntityKind {
+  /// Lying around (or falling), waiting to be picked up.
+  DroppedItem,
+  /// Thrown, and still flying. Projectiles become dropped items once they come to rest.
+  Projectile,
+  /// A piece of terrain that broke off, and is falling. It turns back into terrain where it lands.
+  Debris,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// What a client needs to know to show an item.
+pub struct ItemState {
+  #[allow(missing_docs)]
+  pub id       : entity::id::Item,
+  #[allow(missing_docs)]
+  pub kind     : EntityKind,
+  /// Which item it is.
+  pub item     : item::Id,
+  #[allow(missing_docs)]
+  pub position : Point3<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The entities in the world, as changes against an earlier snapshot the client acknowledged.
+pub struct Snapshot {
+  #[allow(missing_docs)]
+  pub id              : SnapshotId,
+  /// The snapshot these are changes against. If this is `None`, the snapshot is complete: anything not in it
+  /// doesn't exist.
+  pub baseline        : Option<SnapshotId>,
+  /// The server's time when the snapshot was taken, in ns.
+  pub time_ns         : u64,
+  /// Players that are new or have changed since the baseline.
+  pub players         : Vec<PlayerState>,
+  /// Players that have been removed since the baseline.
+  pub removed_players : Vec<entity::id::Player>,
+  /// Mobs that are new or have changed since the baseline.
+  pub mobs            : Vec<MobState>,
+  /// Mobs that have been removed since the baseline.
+  pub removed_mobs    : Vec<entity::id::Mob>,
+  /// Items that are new or have changed since the baseline.
+  pub items           : Vec<ItemState>,
+  /// Items that have been removed since the baseline.
+  pub removed_items   : Vec<entity::id::Item>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// How long the server's ticks are taking.
+pub struct TickStats {
+  /// How often the world's stepped, as the time between steps.
+  pub step_ms    : f32,
+  /// The average time a tick takes.
+  pub average_ms : f32,
+  /// The longest tick in the last second.
+  pub max_ms     : f32,
+  /// Steps skipped in the last second, because the server fell too far behind to catch up.
+  pub dropped    : u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,28 +479,251 @@ pub enum Collision {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the server sends to the client.
 pub enum ServerToClient {
-  /// Provide the client a unique id to tag its messages.
-  LeaseId(ClientId),
-  /// Ping
-  Ping,
+  /// Turn a client away, e.g. because it speaks another protocol version. This has to keep its name and fields, so
+  /// clients of any version can read it.
+  Incompatible {
+    /// The `VERSION` the server speaks.
+    server_version : u32,
+    /// Why the client was turned away, to show to the player.
+    reason         : String,
+  },
+  /// Welcome a client that's just connected.
+  LeaseId {
+    /// A unique id to tag the client's messages with.
+    client_id : ClientId,
+    /// The seed the world was generated from.
+    seed      : u32,
+    /// The token to resume the session with if the connection drops.
+    session   : SessionToken,
+    /// The optional features both ends support, which this connection uses.
+    features  : Vec<Feature>,
+  },
+  /// Answer a `Ping`.
+  Pong {
+    /// The time the `Ping` carried.
+    client_ns : u64,
+    /// The server's time when it answered, in ns.
+    server_ns : u64,
+  },
 
   /// Complete an AddPlayer request.
   PlayerAdded(entity::id::Player, Point3<f32>),
+  /// Complete a Resume request. The client's player is as it was, in this world and at this position.
+  Resumed {
+    #[allow(missing_docs)]
+    world    : WorldId,
+    #[allow(missing_docs)]
+    position : Point3<f32>,
+  },
+  /// Turn down a Resume request, e.g. because the client was kicked or the server has restarted since.
+  ResumeRefused,
+  /// Some players' names. Everyone's sent these when a player joins, and new clients get the names of everyone
+  /// already playing.
+  PlayerNames(Vec<(entity::id::Player, String)>),
 
-  /// Update a player's position.
-  UpdatePlayer(entity::id::Player, Aabb3<f32>),
-  /// Update the client's view of a mob with a given mesh.
-  UpdateMob(entity::id::Mob, Aabb3<f32>),
-  /// The sun as a [0, 1) portion of its cycle.
-  UpdateSun(f32),
+  /// The players, mobs and items in this client's player's world.
+  Snapshot(Snapshot),
+  /// Where this client's own player really is, so the client can correct its predictions.
+  /// This is sent every update, along with the snapshot.
+  PlayerMovement {
+    #[allow(missing_docs)]
+    bounds     : Aabb3<f32>,
+    #[allow(missing_docs)]
+    movement   : movement::T,
+    /// The most recent movement input the server has applied.
+    last_input : Option<movement::InputId>,
+  },
+  /// Where the sun is, and how fast it's moving. Clients move it along themselves, so this is only sent when a player
+  /// joins, when the sun's moved by hand, and when a player moves between worlds with and without a sky.
+  SetSky {
+    /// How many days have gone by, counting the part of today that has; see `season::at`.
+    time    : f64,
+    /// How fast `time` goes by, in days per second.
+    rate    : f64,
+    /// Whether the player's world has a sky, and seasons.
+    has_sky : bool,
+  },
+  /// The weather where this client's player is changed, or the player arrived somewhere.
+  UpdateWeather(Weather),
+  /// How long the server's ticks are taking. This is sent about once a second.
+  TickStats(TickStats),
+  /// The server is saving and shutting down, and won't send anything more.
+  ShuttingDown,
+  /// An admin kicked this client, for the reason given if there is one. The server won't send anything more, and
+  /// won't resume the session.
+  Kicked(Option<String>),
+  /// A player left the game, e.g. because its client was kicked. It won't be in snapshots anymore.
+  PlayerLeft(entity::id::Player),
+  /// This client's player died, and has respawned somewhere else, with full health.
+  YouDied {
+    #[allow(missing_docs)]
+    cause    : health::Cause,
+    /// The world the player respawned in. If it's not the one the player was in, everything the client knows
+    /// about the old one should be thrown away, like for `ChangedWorld`.
+    world    : WorldId,
+    /// Where the player respawned.
+    position : Point3<f32>,
+  },
+  /// This client's player was teleported somewhere else in the same world. Terrain the client asked for around
+  /// where it was isn't needed anymore.
+  Teleported(Point3<f32>),
+  /// This client's player moved to another world. Everything the client knows about the old one should be
+  /// thrown away.
+  ChangedWorld {
+    #[allow(missing_docs)]
+    world    : WorldId,
+    /// Where the player is in the new world.
+    position : Point3<f32>,
+  },
 
   /// Provide a block of terrain to a client.
   Voxels {
+    /// The world the voxels are from. The client may have moved to another since asking for them.
+    world  : WorldId,
     /// The voxels requested, and their associated bounds.
-    voxels : Vec<(voxel::bounds::T, voxel::T)>,
+    voxels : compression::T<Vec<(voxel::bounds::T, voxel::T)>>,
     /// The reason the voxels are being sent.
     reason : VoxelReason,
+    /// The portion of the grassy ground in the requested region that should grow grass, in [0, 1].
+    grass_density : f32,
+    /// The versions of the chunks the voxels are in, as of when they were loaded. See `journal`.
+    versions : Vec<(Point3<i32>, u32)>,
+  },
+  /// One of this client's player's inventory slots changed.
+  InventorySlot {
+    #[allow(missing_docs)]
+    slot  : u32,
+    /// What's in the slot now.
+    stack : Option<ItemStack>,
+  },
+  /// The answer to a `Craft` request. Any inventory slots that changed are sent separately.
+  Crafted {
+    #[allow(missing_docs)]
+    recipe : item::RecipeId,
+    #[allow(missing_docs)]
+    result : Result<(), item::CraftError>,
+  },
+  /// Some terrain was dug out.
+  Dug {
+    /// The center of the hole.
+    position : Point3<f32>,
+    /// The material most of the hole was made of.
+    material : voxel::Material,
+  },
+  /// Someone walked through the grass growing in this voxel, which should rustle and be trampled flat for a while.
+  TrampleGrass(Point3<i32>),
+  /// The grass growing in voxels whose centers are within `radius` of `center` was dug out.
+  RemoveGrass {
+    #[allow(missing_docs)]
+    center : Point3<f32>,
+    #[allow(missing_docs)]
+    radius : f32,
   },
+  /// Something blew up.
+  Exploded {
+    #[allow(missing_docs)]
+    position : Point3<f32>,
+    #[allow(missing_docs)]
+    radius   : f32,
+    /// The material most of the crater was made of, if it broke any terrain.
+    material : Option<voxel::Material>,
+  },
+  /// Some voxels were modified, e.g. by a brush, bringing the chunks they're in to these versions. See `journal`.
+  VoxelsUpdated(compression::T<Vec<(voxel::bounds::T, voxel::T)>>, Vec<(Point3<i32>, u32)>),
+  /// A point light was placed in this client's player's world, or was already there when the player arrived.
+  AddLight(LightId, Light),
+  /// A point light was taken out of this client's player's world.
+  RemoveLight(LightId),
   /// A collision happened.
   Collision(Collision),
+  /// Someone said something.
+  Chat {
+    /// The player who said it.
+    from : entity::id::Player,
+    /// What they said.
+    text : String,
+  },
+  /// Something the server itself wants players to read, e.g. from a script.
+  Notice(String),
+  /// The voxels a `Copy` asked for, to put in the client's clipboard.
+  Copied(compression::T<schematic::T>),
+}
+
+lazy_static! {
+  static ref CLIENT_TO_SERVER_TAGS: Vec<u32> = wire::tags::<ClientToServer>();
+  static ref SERVER_TO_CLIENT_TAGS: Vec<u32> = wire::tags::<ServerToClient>();
+}
+
+impl wire::Message for ClientToServer {
+  fn tags() -> &'static [u32] {
+    &CLIENT_TO_SERVER_TAGS
+  }
+}
+
+impl wire::Message for ServerToClient {
+  fn tags() -> &'static [u32] {
+    &SERVER_TO_CLIENT_TAGS
+  }
+}
+
+impl ServerToClient {
+  /// The name of the message's variant, e.g. for counting messages by type.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      ServerToClient::Incompatible { .. } => "Incompatible",
+      ServerToClient::LeaseId { .. } => "LeaseId",
+      ServerToClient::Pong { .. } => "Pong",
+      ServerToClient::PlayerAdded(..) => "PlayerAdded",
+      ServerToClient::Resumed { .. } => "Resumed",
+      ServerToClient::ResumeRefused => "ResumeRefused",
+      ServerToClient::PlayerNames(..) => "PlayerNames",
+      ServerToClient::Snapshot(..) => "Snapshot",
+      ServerToClient::PlayerMovement { .. } => "PlayerMovement",
+      ServerToClient::SetSky { .. } => "SetSky",
+      ServerToClient::UpdateWeather(..) => "UpdateWeather",
+      ServerToClient::TickStats(..) => "TickStats",
+      ServerToClient::ShuttingDown => "ShuttingDown",
+      ServerToClient::Kicked(..) => "Kicked",
+      ServerToClient::PlayerLeft(..) => "PlayerLeft",
+      ServerToClient::YouDied { .. } => "YouDied",
+      ServerToClient::ChangedWorld { .. } => "ChangedWorld",
+      ServerToClient::Teleported(..) => "Teleported",
+      ServerToClient::Voxels { .. } => "Voxels",
+      ServerToClient::InventorySlot { .. } => "InventorySlot",
+      ServerToClient::Crafted { .. } => "Crafted",
+      ServerToClient::Dug { .. } => "Dug",
+      ServerToClient::TrampleGrass(..) => "TrampleGrass",
+      ServerToClient::RemoveGrass { .. } => "RemoveGrass",
+      ServerToClient::Exploded { .. } => "Exploded",
+      ServerToClient::VoxelsUpdated(..) => "VoxelsUpdated",
+      ServerToClient::AddLight(..) => "AddLight",
+      ServerToClient::RemoveLight(..) => "RemoveLight",
+      ServerToClient::Collision(..) => "Collision",
+      ServerToClient::Chat { .. } => "Chat",
+      ServerToClient::Notice(..) => "Notice",
+      ServerToClient::Copied(..) => "Copied",
+    }
+  }
+}
+
+#[test]
+fn init_can_be_peeked_at() {
+  let msg = wire::encode(&ClientToServer::Init(hello("client")));
+  assert_eq!(peek_init(&msg), Some((VERSION, "client".to_owned())));
+
+  let msg = wire::encode(&ClientToServer::Ping(ClientId(3), 0));
+  assert_eq!(peek_init(&msg), None);
+}
+
+#[test]
+fn tags_are_unique() {
+  use wire::Message;
+
+  for tags in &[ClientToServer::tags(), ServerToClient::tags()] {
+    let mut sorted = tags.to_vec();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), tags.len());
+  }
+  assert_eq!(ClientToServer::tags()[0], wire::tag("Init"));
 }
@@ -0,0 +1,118 @@
+//! Walk a ray through the grid of smallest voxels, visiting each voxel it passes through in order
+//! (a 3D DDA). Voxels are looked up as the ray goes, so they can live in whatever chunks the caller has.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use std;
+
+use voxel;
+
+/// Where a ray stopped.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+  /// The voxel that was hit.
+  pub bounds   : voxel::bounds::T,
+  /// The normal of the face the ray entered the voxel through. Zero if the ray started inside it.
+  pub normal   : Vector3<i32>,
+  /// How far along the ray the voxel was entered.
+  pub distance : f32,
+}
+
+/// Find the first voxel along a ray, within `max_distance`, that `is_solid`.
+pub fn cast<IsSolid>(
+  origin       : &Point3<f32>,
+  direction    : &Vector3<f32>,
+  max_distance : f32,
+  mut is_solid : IsSolid,
+) -> Option<Hit> where
+  IsSolid: FnMut(&voxel::bounds::T) -> bool,
+{
+  if direction.magnitude2() == 0.0 {
+    return None
+  }
+  let direction = direction.normalize();
+
+  let mut cell = Point3::new(origin.x.floor() as i32, origin.y.floor() as i32, origin.z.floor() as i32);
+  // Which way the ray steps on each axis.
+  let mut step = Vector3::new(0, 0, 0);
+  // How far along the ray the next voxel boundary is on each axis.
+  let mut next_boundary = Vector3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+  // How far along the ray it is between voxel boundaries on each axis.
+  let mut boundary_spacing = Vector3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY);
+  for axis in 0 .. 3 {
+    let d = direction[axis];
+    if d > 0.0 {
+      step[axis] = 1;
+      next_boundary[axis] = (cell[axis] as f32 + 1.0 - origin[axis]) / d;
+      boundary_spacing[axis] = 1.0 / d;
+    } else if d < 0.0 {
+      step[axis] = -1;
+      next_boundary[axis] = (origin[axis] - cell[axis] as f32) / -d;
+      boundary_spacing[axis] = -1.0 / d;
+    }
+  }
+
+  let mut normal = Vector3::new(0, 0, 0);
+  let mut distance = 0.0;
+  loop {
+    let bounds = voxel::bounds::new(cell.x, cell.y, cell.z, 0);
+    if is_solid(&bounds) {
+      return Some(Hit {
+        bounds   : bounds,
+        normal   : normal,
+        distance : distance,
+      })
+    }
+
+    let axis =
+      if next_boundary.x <= next_boundary.y && next_boundary.x <= next_boundary.z {
+        0
+      } else if next_boundary.y <= next_boundary.z {
+        1
+      } else {
+        2
+      };
+
+    distance = next_boundary[axis];
+    if distance > max_distance {
+      return None
+    }
+
+    cell[axis] += step[axis];
+    next_boundary[axis] += boundary_spacing[axis];
+    normal = Vector3::new(0, 0, 0);
+    normal[axis] = -step[axis];
+  }
+}
+
+#[test]
+fn hits_the_floor() {
+  let hit =
+    cast(
+      &Point3::new(0.5, 3.5, 0.5),
+      &Vector3::new(0.0, -1.0, 0.0),
+      16.0,
+      |bounds| bounds.y < 0,
+    ).unwrap();
+  assert_eq!((hit.bounds.x, hit.bounds.y, hit.bounds.z), (0, -1, 0));
+  assert_eq!(hit.normal, Vector3::new(0, 1, 0));
+  assert_eq!(hit.distance, 3.5);
+}
+
+#[test]
+fn diagonal_rays_visit_every_voxel_they_touch() {
+  let mut visited = Vec::new();
+  let hit =
+    cast(
+      &Point3::new(0.5, 0.5, 0.25),
+      &Vector3::new(1.0, 0.0, 1.0),
+      16.0,
+      |bounds| { visited.push((bounds.x, bounds.z)); bounds.x >= 2 },
+    ).unwrap();
+  assert_eq!(visited, vec!((0, 0), (1, 0), (1, 1), (2, 1)));
+  assert_eq!(hit.normal, Vector3::new(-1, 0, 0));
+}
+
+#[test]
+fn gives_up_past_max_distance() {
+  assert!(cast(&Point3::new(0.5, 0.5, 0.5), &Vector3::new(1.0, 0.0, 0.0), 4.0, |bounds| bounds.x >= 8).is_none());
+}
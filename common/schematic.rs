@@ -0,0 +1,90 @@
+//! Schematics: blocks of voxels copied out of the terrain, to paste somewhere else or share as files.
+//! Schematic files are the same as the prefab blobs servers can scatter over their worlds.
+
+use bincode;
+use cgmath::Vector3;
+use std;
+
+use voxel;
+
+/// Schematics bigger than this along any axis can't be copied, pasted or loaded.
+pub const MAX_SIZE: u32 = 64;
+
+/// A block of voxels, one material per unit cube: x varies fastest, then y, then z.
+/// Files of these are bincode-serialized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct T {
+  #[allow(missing_docs)]
+  pub size      : [u32; 3],
+  /// Empty cells are dug out when the schematic's placed, so rooms come out hollow.
+  pub materials : Vec<voxel::Material>,
+}
+
+impl T {
+  /// The material in a cell, counting from the low corner. Everything outside the schematic is empty.
+  pub fn get(&self, x: i32, y: i32, z: i32) -> voxel::Material {
+    if x < 0 || y < 0 || z < 0 || x >= self.size[0] as i32 || y >= self.size[1] as i32 || z >= self.size[2] as i32 {
+      return voxel::Material::Empty
+    }
+    let (x, y, z) = (x as usize, y as usize, z as usize);
+    self.materials[x + self.size[0] as usize * (y + self.size[1] as usize * z)]
+  }
+
+  #[allow(missing_docs)]
+  pub fn extent(&self) -> Vector3<f32> {
+    Vector3::new(self.size[0] as f32, self.size[1] as f32, self.size[2] as f32)
+  }
+
+  /// Make sure the schematic has a material for every cell, and isn't too big, e.g. before placing one from a file
+  /// or a client.
+  pub fn check(&self) -> Result<(), String> {
+    let cells = self.size[0] as usize * self.size[1] as usize * self.size[2] as usize;
+    if self.size.iter().any(|&s| s > MAX_SIZE) {
+      return Err(format!("{:?} is bigger than {} along some axis", self.size, MAX_SIZE))
+    }
+    if self.materials.len() != cells {
+      return Err(format!("{:?} should have {} cells, but has {}", self.size, cells, self.materials.len()))
+    }
+    Ok(())
+  }
+
+  /// Write the schematic to a file.
+  pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+    let mut file = std::fs::File::create(path).map_err(|err| format!("Couldn't write {}: {}", path.display(), err))?;
+    bincode::serialize_into(&mut file, self, bincode::Infinite)
+    .map_err(|err| format!("Couldn't write {}: {:?}", path.display(), err))
+  }
+}
+
+/// Read a schematic file.
+pub fn load(path: &std::path::Path) -> Result<T, String> {
+  let mut file = std::fs::File::open(path).map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+  let schematic: T =
+    bincode::deserialize_from(&mut file, bincode::Infinite)
+    .map_err(|err| format!("Couldn't parse {}: {:?}", path.display(), err))?;
+  schematic.check().map_err(|err| format!("{}: {}", path.display(), err))?;
+  Ok(schematic)
+}
+
+#[test]
+fn schematics_round_trip_through_files() {
+  let schematic =
+    T {
+      size      : [2, 1, 2],
+      materials : vec!(voxel::Material::Stone, voxel::Material::Empty, voxel::Material::Empty, voxel::Material::Sand),
+    };
+  assert_eq!(schematic.get(1, 0, 1), voxel::Material::Sand);
+  assert_eq!(schematic.get(2, 0, 1), voxel::Material::Empty);
+
+  let path = std::env::temp_dir().join(format!("playform-schematic-{}", std::process::id()));
+  schematic.save(&path).unwrap();
+  let loaded = load(&path);
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!(loaded, Ok(schematic.clone()));
+
+  let mut short = schematic.clone();
+  short.materials.pop();
+  assert!(short.check().is_err());
+  let big = T { size: [MAX_SIZE + 1, 1, 1], materials: vec!(voxel::Material::Stone; MAX_SIZE as usize + 1) };
+  assert!(big.check().is_err());
+}
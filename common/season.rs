@@ -0,0 +1,98 @@
+//! The seasons. A year lasts `DAYS_PER_YEAR` days, and over it days get longer and shorter, the sun's path rises
+//! and falls, and the snow line creeps up and down the mountains. The season is given as how far through the year
+//! it is, in [0, 1): spring starts at 0, midsummer is at 0.25, autumn starts at 0.5 and midwinter is at 0.75.
+
+use std;
+
+/// The number of days in a year.
+pub const DAYS_PER_YEAR: u32 = 16;
+
+/// How far the sun's path is tilted away from overhead on the first day of spring, in radians.
+const LATITUDE: f32 = 0.4;
+/// How far the sun's path moves from `LATITUDE` by midsummer and midwinter, in radians.
+const MAX_DECLINATION: f32 = 0.4;
+
+/// The portion of the day the sun is up, on the first day of spring.
+const MEAN_DAYLIGHT: f32 = 0.6;
+/// How much longer the days are at midsummer, and shorter at midwinter.
+const DAYLIGHT_SWING: f32 = 0.12;
+
+/// How high the snow line is on the first day of spring.
+const MEAN_SNOW_LINE: f32 = 64.0;
+/// How much higher the snow line is at midsummer, and lower at midwinter.
+const SNOW_LINE_SWING: f32 = 44.0;
+
+/// How summery it is, in [-1, 1]. It peaks at midsummer and bottoms out at midwinter.
+pub fn warmth(season: f32) -> f32 {
+  (season * 2.0 * std::f32::consts::PI).sin()
+}
+
+/// The portion of the day the sun is up.
+pub fn daylight(season: f32) -> f32 {
+  MEAN_DAYLIGHT + DAYLIGHT_SWING * warmth(season)
+}
+
+/// Where the sun is along its path, given how far through the day it is (both in [0, 1)). The sun rises at 0 and
+/// sets at 0.5, however long the day is.
+pub fn time_of_day(fraction: f32, season: f32) -> f32 {
+  let daylight = daylight(season);
+  if fraction < daylight {
+    0.5 * fraction / daylight
+  } else {
+    0.5 + 0.5 * (fraction - daylight) / (1.0 - daylight)
+  }
+}
+
+/// Where the sun is along its path and how far through the year it is, `days` days after the first day of the
+/// first spring.
+pub fn at(days: f64) -> (f32, f32) {
+  let season = ((days % DAYS_PER_YEAR as f64) / DAYS_PER_YEAR as f64) as f32;
+  (time_of_day(days.fract() as f32, season), season)
+}
+
+/// How far through the day it is, given where the sun is along its path. This undoes `time_of_day`.
+pub fn clock(time_of_day: f32, season: f32) -> f32 {
+  let daylight = daylight(season);
+  if time_of_day < 0.5 {
+    2.0 * time_of_day * daylight
+  } else {
+    daylight + 2.0 * (time_of_day - 0.5) * (1.0 - daylight)
+  }
+}
+
+/// How far the sun's path is tilted away from passing straight overhead, in radians.
+pub fn sun_tilt(season: f32) -> f32 {
+  LATITUDE - MAX_DECLINATION * warmth(season)
+}
+
+/// The height above which snow settles on the ground.
+pub fn snow_line(season: f32) -> f32 {
+  MEAN_SNOW_LINE + SNOW_LINE_SWING * warmth(season)
+}
+
+#[test]
+fn summer_days_are_long_and_high() {
+  let midsummer = 0.25;
+  let midwinter = 0.75;
+  assert!(daylight(midsummer) > daylight(midwinter));
+  assert!(sun_tilt(midsummer) < sun_tilt(midwinter));
+  assert!(snow_line(midsummer) > snow_line(midwinter));
+
+  for &season in &[0.0, midsummer, midwinter] {
+    assert_eq!(time_of_day(0.0, season), 0.0);
+    assert!((time_of_day(daylight(season), season) - 0.5).abs() < 1e-6);
+    assert!(time_of_day(0.99, season) < 1.0);
+    for &fraction in &[0.1, 0.5, 0.9] {
+      assert!((clock(time_of_day(fraction, season), season) - fraction).abs() < 1e-6);
+    }
+  }
+  // Halfway through the day, the summer sun is still up, and the winter sun has set.
+  assert!(time_of_day(0.5, midsummer) < 0.5);
+  assert!(time_of_day(0.5, midwinter) > 0.5);
+
+  // Midsummer's sunrise and afternoon, a year on.
+  let (sunrise, season) = at(DAYS_PER_YEAR as f64 * 1.25);
+  assert_eq!(sunrise, 0.0);
+  assert!((season - midsummer).abs() < 1e-6);
+  assert!(at(DAYS_PER_YEAR as f64 * 1.25 + 0.5).0 < 0.5);
+}
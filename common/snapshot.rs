@@ -0,0 +1,248 @@
+//! The entities in the world at one point in time, and the changes between two such points.
+//! The server sends clients snapshots as changes against one they've already received.
+//! Clients show entities a little in the past, in between the two snapshots either side, so they move smoothly.
+
+use cgmath::Point3;
+use collision::Aabb3;
+use std::f32::consts::PI;
+
+use entity;
+use fnv_map;
+use protocol;
+
+/// Servers abandon baselines older than this many snapshots, and send a full snapshot instead.
+/// Clients keep at least this many snapshots around, so they can always decode what they're sent.
+pub const MAX_BASELINE_AGE: u32 = 64;
+
+/// Every entity a client can see.
+#[derive(Debug, Clone)]
+pub struct World {
+  /// The server's time when the world was captured, in ns.
+  pub time_ns : u64,
+  #[allow(missing_docs)]
+  pub players : fnv_map::T<entity::id::Player, protocol::PlayerState>,
+  #[allow(missing_docs)]
+  pub mobs    : fnv_map::T<entity::id::Mob, protocol::MobState>,
+  #[allow(missing_docs)]
+  pub items   : fnv_map::T<entity::id::Item, protocol::ItemState>,
+}
+
+#[allow(missing_docs)]
+pub fn empty() -> World {
+  World {
+    time_ns : 0,
+    players : fnv_map::new(),
+    mobs    : fnv_map::new(),
+    items   : fnv_map::new(),
+  }
+}
+
+impl World {
+  /// Describe this world as changes against `baseline`, or in full if there's no baseline.
+  pub fn delta(&self, id: protocol::SnapshotId, baseline: Option<(protocol::SnapshotId, &World)>) -> protocol::Snapshot {
+    let empty = empty();
+    let (baseline_id, baseline) =
+      match baseline {
+        None => (None, &empty),
+        Some((baseline_id, baseline)) => (Some(baseline_id), baseline),
+      };
+
+    protocol::Snapshot {
+      id              : id,
+      baseline        : baseline_id,
+      time_ns         : self.time_ns,
+      players         :
+        self.players.values()
+        .filter(|player| baseline.players.get(&player.id) != Some(player))
+        .cloned()
+        .collect(),
+      removed_players :
+        baseline.players.keys()
+        .filter(|id| !self.players.contains_key(id))
+        .cloned()
+        .collect(),
+      mobs            :
+        self.mobs.values()
+        .filter(|mob| baseline.mobs.get(&mob.id) != Some(mob))
+        .cloned()
+        .collect(),
+      removed_mobs    :
+        baseline.mobs.keys()
+        .filter(|id| !self.mobs.contains_key(id))
+        .cloned()
+        .collect(),
+      items           :
+        self.items.values()
+        .filter(|item| baseline.items.get(&item.id) != Some(item))
+        .cloned()
+        .collect(),
+      removed_items   :
+        baseline.items.keys()
+        .filter(|id| !self.items.contains_key(id))
+        .cloned()
+        .collect(),
+    }
+  }
+
+  /// The world after applying a snapshot's changes to this one, which should be the snapshot's baseline.
+  pub fn apply(&self, snapshot: &protocol::Snapshot) -> World {
+    let mut world = self.clone();
+    world.time_ns = snapshot.time_ns;
+    for id in &snapshot.removed_players {
+      world.players.remove(id);
+    }
+    for player in &snapshot.players {
+      world.players.insert(player.id, *player);
+    }
+    for id in &snapshot.removed_mobs {
+      world.mobs.remove(id);
+    }
+    for mob in &snapshot.mobs {
+      world.mobs.insert(mob.id, *mob);
+    }
+    for id in &snapshot.removed_items {
+      world.items.remove(id);
+    }
+    for item in &snapshot.items {
+      world.items.insert(item.id, *item);
+    }
+    world
+  }
+}
+
+fn lerp_point(a: &Point3<f32>, b: &Point3<f32>, t: f32) -> Point3<f32> {
+  a + (b - a) * t
+}
+
+fn lerp_bounds(a: &Aabb3<f32>, b: &Aabb3<f32>, t: f32) -> Aabb3<f32> {
+  Aabb3::new(lerp_point(&a.min, &b.min, t), lerp_point(&a.max, &b.max, t))
+}
+
+/// Turn the shorter way around.
+fn lerp_heading(a: f32, b: f32, t: f32) -> f32 {
+  let mut turn = (b - a) % (2.0 * PI);
+  if turn > PI {
+    turn -= 2.0 * PI;
+  } else if turn < -PI {
+    turn += 2.0 * PI;
+  }
+  a + turn * t
+}
+
+/// The world `t` of the way from `earlier` to `later`, where `t` is in [0, 1]. Entities are where `later` has them,
+/// except that those in both have moved only part of the way there.
+pub fn interpolate(earlier: &World, later: &World, t: f32) -> World {
+  let mut world = later.clone();
+  world.time_ns = earlier.time_ns + ((later.time_ns - earlier.time_ns) as f64 * t as f64) as u64;
+  for (id, player) in &mut world.players {
+    if let Some(before) = earlier.players.get(id) {
+      player.bounds = lerp_bounds(&before.bounds, &player.bounds, t);
+      player.heading = lerp_heading(before.heading, player.heading, t);
+    }
+  }
+  for (id, mob) in &mut world.mobs {
+    if let Some(before) = earlier.mobs.get(id) {
+      mob.bounds = lerp_bounds(&before.bounds, &mob.bounds, t);
+      mob.heading = lerp_heading(before.heading, mob.heading, t);
+    }
+  }
+  for (id, item) in &mut world.items {
+    if let Some(before) = earlier.items.get(id) {
+      item.position = lerp_point(&before.position, &item.position, t);
+    }
+  }
+  world
+}
+
+#[test]
+fn deltas_reproduce_the_world() {
+  use health;
+
+  let player = |id: u32, x: f32| {
+    protocol::PlayerState {
+      id        : entity::id::Player::default() + id,
+      bounds    : Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 2.0, 1.0)),
+      heading   : 0.0,
+      animation : protocol::Animation::Idle,
+      health    : health::full(health::PLAYER_MAX),
+    }
+  };
+
+  let mut before = empty();
+  before.players.insert(player(0, 0.0).id, player(0, 0.0));
+  before.players.insert(player(1, 0.0).id, player(1, 0.0));
+
+  // Player 0 moves, player 1 leaves and player 2 joins.
+  let mut after = empty();
+  after.players.insert(player(0, 3.0).id, player(0, 3.0));
+  after.players.insert(player(2, 5.0).id, player(2, 5.0));
+
+  let delta = after.delta(protocol::SnapshotId(2), Some((protocol::SnapshotId(1), &before)));
+  assert_eq!(delta.baseline, Some(protocol::SnapshotId(1)));
+  assert_eq!(delta.players.len(), 2);
+  assert_eq!(delta.removed_players, vec!(player(1, 0.0).id));
+
+  let decoded = before.apply(&delta);
+  assert_eq!(decoded.players, after.players);
+
+  let full = after.delta(protocol::SnapshotId(2), None);
+  assert_eq!(empty().apply(&full).players, after.players);
+}
+
+#[test]
+fn health_changes_are_sent_even_when_nothing_moves() {
+  use health;
+
+  let player =
+    protocol::PlayerState {
+      id        : entity::id::Player::default(),
+      bounds    : Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0)),
+      heading   : 0.0,
+      animation : protocol::Animation::Idle,
+      health    : health::full(health::PLAYER_MAX),
+    };
+  let mut before = empty();
+  before.players.insert(player.id, player);
+
+  let mut after = before.clone();
+  after.players.get_mut(&player.id).unwrap().health.damage(3);
+
+  let delta = after.delta(protocol::SnapshotId(2), Some((protocol::SnapshotId(1), &before)));
+  assert_eq!(delta.players.len(), 1);
+  assert_eq!(before.apply(&delta).players[&player.id].health.current, health::PLAYER_MAX - 3);
+  assert!(before.delta(protocol::SnapshotId(2), Some((protocol::SnapshotId(1), &before))).players.is_empty());
+}
+
+#[test]
+fn interpolation_moves_part_way() {
+  use health;
+
+  let player = |id: u32, x: f32, heading: f32| {
+    protocol::PlayerState {
+      id        : entity::id::Player::default() + id,
+      bounds    : Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 2.0, 1.0)),
+      heading   : heading,
+      animation : protocol::Animation::Walk,
+      health    : health::full(health::PLAYER_MAX),
+    }
+  };
+
+  let mut earlier = empty();
+  earlier.time_ns = 100;
+  earlier.players.insert(player(0, 0.0, 0.9 * PI).id, player(0, 0.0, 0.9 * PI));
+  earlier.players.insert(player(1, 0.0, 0.0).id, player(1, 0.0, 0.0));
+  let mut later = empty();
+  later.time_ns = 200;
+  later.players.insert(player(0, 4.0, -0.9 * PI).id, player(0, 4.0, -0.9 * PI));
+  later.players.insert(player(2, 8.0, 0.0).id, player(2, 8.0, 0.0));
+
+  let world = interpolate(&earlier, &later, 0.25);
+  assert_eq!(world.time_ns, 125);
+  let moved = world.players[&player(0, 0.0, 0.0).id];
+  assert!((moved.bounds.min.x - 1.0).abs() < 1e-6);
+  // Across the wraparound, not all the way back around.
+  assert!((moved.heading - 0.95 * PI).abs() < 1e-5);
+  // Players only in one snapshot are where the later one has them.
+  assert!(!world.players.contains_key(&player(1, 0.0, 0.0).id));
+  assert_eq!(world.players[&player(2, 0.0, 0.0).id], player(2, 8.0, 0.0));
+}
@@ -6,6 +6,7 @@ use cgmath::Point3;
 use std::cmp::max;
 use std::collections::VecDeque;
 use stopwatch;
+use time;
 
 use cube_shell::{cube_diff, cube_shell};
 
@@ -33,6 +34,8 @@ pub struct T {
   to_load: Option<Box<dyn Iterator<Item=Point3<i32>> + Send>>,
 
   to_recheck: VecDeque<Point3<i32>>,
+  /// Points to check again once it's time, soonest first.
+  deferred: VecDeque<(Point3<i32>, u64)>,
   // The distances to the switches between LODs.
   lod_thresholds: Vec<i32>,
 }
@@ -49,6 +52,7 @@ pub fn new(
     max_load_distance: max_load_distance,
 
     to_recheck: VecDeque::new(),
+    deferred: VecDeque::new(),
     lod_thresholds: lod_thresholds,
   }
 }
@@ -80,6 +84,24 @@ impl T {
       position: *position,
     }
   }
+
+  /// Change how far away to load, and where LOD switches. Everything in range is loaded again from the center
+  /// out, and anything that's now out of range is unloaded.
+  pub fn set_range(&mut self, max_load_distance: u32, lod_thresholds: Vec<i32>) {
+    if max_load_distance == self.max_load_distance && lod_thresholds == self.lod_thresholds {
+      return
+    }
+
+    if let Some(position) = self.last_position {
+      for radius in max_load_distance .. self.max_load_distance {
+        self.to_recheck.extend(cube_shell(&position, radius as i32).into_iter());
+      }
+      self.to_load = Some(surroundings_iter(position, max_load_distance as i32));
+    }
+
+    self.max_load_distance = max_load_distance;
+    self.lod_thresholds = lod_thresholds;
+  }
 }
 
 /// Iterator for the updates from a T.
@@ -88,6 +110,16 @@ pub struct Updates<'a> {
   position: Point3<i32>,
 }
 
+impl<'a> Updates<'a> {
+  /// Load a point again at `time_ns` (or unload it, if it's out of range by then), e.g. because its LOD changed too
+  /// recently to change again now.
+  pub fn recheck_later(&mut self, position: Point3<i32>, time_ns: u64) {
+    let deferred = &mut self.loader.deferred;
+    let i = deferred.iter().position(|&(_, t)| t > time_ns).unwrap_or(deferred.len());
+    deferred.insert(i, (position, time_ns));
+  }
+}
+
 /// Find the minimum cube shell radius it would take from one point to intersect the other.
 pub fn distance_between(p1: &Point3<i32>, p2: &Point3<i32>) -> u32 {
   let dx = (p1.x - p2.x).abs();
@@ -108,6 +140,14 @@ impl<'a> Iterator for Updates<'a> {
         } else {
           Some((position, LoadType::Downgrade))
         }
+      } else if self.loader.deferred.front().map_or(false, |&(_, t)| t <= time::precise_time_ns()) {
+        let (position, _) = self.loader.deferred.pop_front().unwrap();
+        let distance = distance_between(&self.position, &position);
+        if distance > self.loader.max_load_distance {
+          Some((position, LoadType::Unload))
+        } else {
+          Some((position, LoadType::Load))
+        }
       } else {
         self.loader.to_load.as_mut().unwrap().next()
           .map(|position| (position, LoadType::Load))
@@ -0,0 +1,118 @@
+//! Fields that move other fields around, to go with `voxel_data`'s `translation`. They're re-exported in
+//! `voxel::field`.
+
+/// A field turned around the origin. Unlike `voxel_data`'s, this takes any rotation, e.g. a `Quaternion` as well
+/// as a `Basis3`.
+pub mod rotation {
+  use cgmath::{Point3, Vector3, Rotation};
+
+  use voxel::field;
+
+  #[allow(missing_docs)]
+  pub struct T<R, Field> {
+    pub rotation : R,
+    pub field    : Field,
+  }
+
+  impl<R: Rotation<Point3<f32>>, Field: field::T> field::T for T<R, Field> {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let p = self.rotation.invert().rotate_point(*p);
+      self.field.density(&p)
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      let p = self.rotation.invert().rotate_point(*p);
+      let normal = self.field.normal(&p);
+      self.rotation.rotate_vector(normal)
+    }
+  }
+}
+
+/// A field stretched away from the origin, by a different amount along each axis if need be.
+pub mod scale {
+  use cgmath::{Point3, Vector3, InnerSpace};
+
+  use voxel::field;
+
+  #[allow(missing_docs)]
+  pub struct T<Field> {
+    /// How much to stretch along each axis. These should all be positive.
+    pub scale : Vector3<f32>,
+    pub field : Field,
+  }
+
+  /// Stretch a field the same amount along every axis.
+  pub fn uniform<Field>(scale: f32, field: Field) -> T<Field> {
+    T {
+      scale : Vector3::new(scale, scale, scale),
+      field : field,
+    }
+  }
+
+  impl<Field: field::T> field::T for T<Field> {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let p = Point3::new(p.x / self.scale.x, p.y / self.scale.y, p.z / self.scale.z);
+      // Densities change with distance, so they're stretched too. Using the smallest stretch means a density
+      // never claims the surface is further away than it is.
+      let scale = self.scale.x.min(self.scale.y).min(self.scale.z);
+      self.field.density(&p) * scale
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      let p = Point3::new(p.x / self.scale.x, p.y / self.scale.y, p.z / self.scale.z);
+      let normal = self.field.normal(&p);
+      // Normals don't stretch with the field; they go through the inverse transpose, which for a scale is the
+      // inverse scale.
+      Vector3::new(normal.x / self.scale.x, normal.y / self.scale.y, normal.z / self.scale.z).normalize()
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3, InnerSpace, EuclideanSpace};
+
+  use voxel::field;
+
+  /// A sphere of radius 1 at the origin.
+  pub struct Ball;
+
+  impl field::T for Ball {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      1.0 - p.to_vec().magnitude()
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      p.to_vec().normalize()
+    }
+  }
+}
+
+#[test]
+fn stretched_and_turned_balls() {
+  use cgmath::{Point3, Vector3, Quaternion, Rotation3, Rad, InnerSpace};
+  use voxel::field::T;
+
+  let close = |a: Vector3<f32>, b: Vector3<f32>| (a - b).magnitude() < 1e-5;
+
+  let mut ball = scale::uniform(2.0, test::Ball);
+  assert_eq!(ball.density(&Point3::new(0.0, 1.0, 0.0)), 1.0);
+  assert_eq!(ball.density(&Point3::new(0.0, 3.0, 0.0)), -1.0);
+
+  let mut egg = scale::T { scale: Vector3::new(2.0, 1.0, 1.0), field: test::Ball };
+  assert!(egg.density(&Point3::new(1.5, 0.0, 0.0)) > 0.0);
+  assert!(egg.density(&Point3::new(0.0, 1.5, 0.0)) < 0.0);
+  // On the surface of the egg, the normal leans towards its flatter sides.
+  let p = Point3::new(2.0 * 0.5f32.sqrt(), 0.5f32.sqrt(), 0.0);
+  assert!(egg.density(&p).abs() < 1e-5);
+  assert!(close(egg.normal(&p), Vector3::new(1.0, 2.0, 0.0).normalize()));
+
+  let mut turned = rotation::T {
+    rotation : Quaternion::from_angle_z(Rad(::std::f32::consts::PI / 2.0)),
+    field    : egg,
+  };
+  assert!(turned.density(&Point3::new(0.0, 1.5, 0.0)) > 0.0);
+  assert!(turned.density(&Point3::new(1.5, 0.0, 0.0)) < 0.0);
+  let p = Point3::new(-0.5f32.sqrt(), 2.0 * 0.5f32.sqrt(), 0.0);
+  assert!(close(turned.normal(&p), Vector3::new(-2.0, 1.0, 0.0).normalize()));
+}
@@ -3,6 +3,8 @@
 use isosurface_extraction;
 use voxel_data;
 
+use material;
+
 pub use voxel_data::bounds;
 pub use voxel_data::impls::surface_vertex::T::*;
 pub use voxel_data::impls::surface_vertex::of_field;
@@ -21,6 +23,9 @@ pub enum Material {
   Leaves = 3,
   Stone = 4,
   Marble = 5,
+  Sand = 6,
+  Snow = 7,
+  Water = 8,
 }
 
 #[allow(missing_docs)]
@@ -44,6 +49,8 @@ pub mod brush {
 #[allow(missing_docs)]
 pub mod field {
   pub use voxel_data::field::*;
+  pub use csg::{union, difference, smooth_union, smooth_intersection};
+  pub use transform::{rotation, scale};
 }
 
 #[allow(missing_docs)]
@@ -53,6 +60,6 @@ pub mod mosaic {
 
 impl isosurface_extraction::dual_contouring::material::T for Material {
   fn is_opaque(&self) -> bool {
-    *self != Material::Empty
+    material::get(*self).solid
   }
 }
@@ -0,0 +1,160 @@
+//! How protocol messages are encoded on the wire.
+//! bincode tags each enum variant with its position in the declaration, so a message added anywhere but the end
+//! would change the meaning of every message after it, and a message a peer doesn't know can't be told apart from
+//! garbage. Instead, messages are tagged with a hash of their variant's name, so variants can be added (or moved)
+//! without changing any other message's tag, and peers can skip messages with tags they don't know.
+//! Renaming a variant changes its tag, so only do that for a message that's meant to be a different one.
+
+use bincode;
+use fnv::FnvHasher;
+use serde::{Deserializer, Serialize};
+use serde::de;
+use serde::de::{DeserializeOwned, Visitor};
+use std::hash::Hasher;
+
+/// A kind of message sent over the wire. These are the protocol's enums.
+pub trait Message: Serialize + DeserializeOwned {
+  /// Each variant's tag, in the order the variants are declared. See `tags`.
+  fn tags() -> &'static [u32];
+}
+
+/// Why a message couldn't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+  /// The message's tag isn't one this build knows, e.g. because it was added in a later version.
+  /// The message can be skipped.
+  Unknown(u32),
+  /// The message is cut off, or its contents aren't what its tag says.
+  Malformed,
+}
+
+/// The tag of the variant called `name`.
+pub fn tag(name: &str) -> u32 {
+  let mut hasher = FnvHasher::default();
+  hasher.write(name.as_bytes());
+  hasher.finish() as u32
+}
+
+/// A deserializer that doesn't deserialize anything, but notes the variant names serde hands it for an enum.
+struct VariantNames(Option<&'static [&'static str]>);
+
+impl<'de, 'a> Deserializer<'de> for &'a mut VariantNames {
+  type Error = de::value::Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+    Err(de::Error::custom("only enums have variant names"))
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    variants: &'static [&'static str],
+    _visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    self.0 = Some(variants);
+    Err(de::Error::custom("only the variant names were wanted"))
+  }
+
+  forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option unit unit_struct
+    newtype_struct seq tuple tuple_struct map struct identifier ignored_any
+  }
+}
+
+/// The tags of an enum's variants, in the order they're declared.
+pub fn tags<M: DeserializeOwned>() -> Vec<u32> {
+  let mut names = VariantNames(None);
+  // This always fails; it's only run for the names it turns up.
+  let _ = M::deserialize(&mut names);
+  names.0.expect("Messages must be enums").iter().map(|name| tag(name)).collect()
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32) |
+  (bytes[1] as u32) << 8 |
+  (bytes[2] as u32) << 16 |
+  (bytes[3] as u32) << 24
+}
+
+fn write_u32(bytes: &mut [u8], x: u32) {
+  bytes[0] = x as u8;
+  bytes[1] = (x >> 8) as u8;
+  bytes[2] = (x >> 16) as u8;
+  bytes[3] = (x >> 24) as u8;
+}
+
+/// Encode a message: its tag, and then its fields as bincode would write them.
+pub fn encode<M: Message>(msg: &M) -> Vec<u8> {
+  // bincode starts an enum with its variant's index, which is swapped for the variant's tag.
+  let mut bytes = bincode::serialize(msg, bincode::Infinite).unwrap();
+  let index = read_u32(&bytes) as usize;
+  write_u32(&mut bytes, M::tags()[index]);
+  bytes
+}
+
+/// The tag a message was encoded with.
+pub fn peek_tag(bytes: &[u8]) -> Option<u32> {
+  if bytes.len() < 4 {
+    None
+  } else {
+    Some(read_u32(bytes))
+  }
+}
+
+/// Decode a message encoded by `encode`, even by another build.
+pub fn decode<M: Message>(bytes: &[u8]) -> Result<M, Error> {
+  let tag = peek_tag(bytes).ok_or(Error::Malformed)?;
+  let index =
+    match M::tags().iter().position(|&t| t == tag) {
+      None => return Err(Error::Unknown(tag)),
+      Some(index) => index,
+    };
+  let mut bytes = bytes.to_vec();
+  write_u32(&mut bytes, index as u32);
+  bincode::deserialize(&bytes).map_err(|_| Error::Malformed)
+}
+
+#[cfg(test)]
+mod versions {
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  pub enum Old {
+    Ping(u32),
+    Chat { text: String },
+  }
+
+  /// `Old`, with a variant added at the front and the others swapped around.
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  pub enum New {
+    Wave,
+    Chat { text: String },
+    Ping(u32),
+  }
+
+  lazy_static! {
+    static ref OLD_TAGS: Vec<u32> = super::tags::<Old>();
+    static ref NEW_TAGS: Vec<u32> = super::tags::<New>();
+  }
+
+  impl super::Message for Old {
+    fn tags() -> &'static [u32] {
+      &OLD_TAGS
+    }
+  }
+
+  impl super::Message for New {
+    fn tags() -> &'static [u32] {
+      &NEW_TAGS
+    }
+  }
+}
+
+#[test]
+fn messages_survive_added_variants() {
+  use self::versions::{Old, New};
+
+  let chat = encode(&Old::Chat { text: "hi".to_owned() });
+  assert_eq!(decode::<New>(&chat), Ok(New::Chat { text: "hi".to_owned() }));
+  assert_eq!(decode::<Old>(&encode(&New::Ping(3))), Ok(Old::Ping(3)));
+  assert_eq!(decode::<Old>(&encode(&New::Wave)), Err(Error::Unknown(tag("Wave"))));
+  assert_eq!(decode::<Old>(&chat[.. 6]), Err(Error::Malformed));
+}
@@ -3,54 +3,95 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+extern crate ctrlc;
 extern crate env_logger;
 extern crate nanomsg;
 #[macro_use]
 extern crate log;
-extern crate thread_scoped;
 
 extern crate server_lib;
 
 use std::borrow::Borrow;
 use std::env;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
-  args.next().unwrap();
-  let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  let transport = server_lib::Transport::from_args(&mut args).unwrap_or(server_lib::Transport::Nanomsg);
+  let seed = server_lib::config::seed_from_args(&mut args);
+  let mut args = args.into_iter();
+  let listen_url = args.next().unwrap_or_else(|| String::from(transport.default_server_url()));
+  let terrain_workers =
+    args.next()
+    .map(|n| n.parse().expect("terrain worker count must be a number"))
+    .unwrap_or(server_lib::DEFAULT_TERRAIN_WORKERS);
+  let admin_address = args.next();
   assert!(args.next().is_none());
 
-  info!("Listening on {}.", listen_url);
+  info!("Listening on {} over {:?}.", listen_url, transport);
+  info!("Generating terrain on {} threads.", terrain_workers);
 
-  let quit_signal = Mutex::new(false);
+  let quit_signal = Arc::new(Mutex::new(false));
+  let console = Arc::new(server_lib::console::new_queue());
 
-  let _quit_thread =
-    unsafe {
-      let quit_signal = &quit_signal;
-      thread_scoped::scoped(move || {
-        wait_for_quit();
-        *quit_signal.lock().unwrap() = true;
-        // Close all sockets.
-        nanomsg::Socket::terminate();
-      })
-    };
+  {
+    let quit_signal = quit_signal.clone();
+    ctrlc::set_handler(move || {
+      println!("Interrupted; saving and quitting");
+      *quit_signal.lock().unwrap() = true;
+    }).expect("Couldn't handle interrupts");
+  }
+
+  // This isn't joined: after an interrupt, it's still waiting on stdin.
+  {
+    let quit_signal = quit_signal.clone();
+    let console = console.clone();
+    std::thread::spawn(move || {
+      wait_for_quit(&console);
+      *quit_signal.lock().unwrap() = true;
+    });
+  }
+
+  let result =
+    server_lib::run(
+      listen_url.borrow(),
+      transport,
+      terrain_workers,
+      seed,
+      admin_address.as_ref().map(|address| address.as_str()),
+      &console,
+      plugins(),
+      &quit_signal,
+    );
+  if let Err(err) = result {
+    error!("Couldn't listen on {}: {}", listen_url, err);
+  }
+
+  // Close all sockets.
+  nanomsg::Socket::terminate();
+}
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+/// The plugins this server runs. Plugin crates linked into the server are registered here, with
+/// `Registry::register`.
+fn plugins() -> server_lib::plugin::Registry {
+  let mut plugins = server_lib::plugin::new();
+  plugins.register(Box::new(server_lib::script::new()));
+  plugins
 }
 
-fn wait_for_quit() {
+/// Pass console commands from stdin to the server, until "quit".
+fn wait_for_quit(console: &server_lib::console::Queue) {
   loop {
     let mut line = String::new();
     std::io::stdin().read_line(&mut line).unwrap();
 
-    if line == "quit\n" {
+    if line.trim() == "quit" {
       println!("Quitting");
       return
     } else {
-      println!("Unrecognized command: {:?}", line);
+      server_lib::console::submit(console, &line, Box::new(|output| println!("{}", output)));
     }
   }
 }
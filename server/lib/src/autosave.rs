@@ -0,0 +1,171 @@
+//! Saving everything the server would lose in a crash. Terrain is kept by each world's `persistence` store;
+//! this flushes those, and saves players, mobs, items and teleport points alongside them.
+
+use bincode;
+use cgmath::{Point3, Vector3};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std;
+
+use common;
+use common::fnv_map;
+use common::protocol;
+
+use entity;
+use item;
+use mob;
+use persistence;
+use server;
+use teleport;
+
+/// Where players, mobs and items are saved.
+const PATH: &'static str = "world/entities.bin";
+/// Where teleport points are saved.
+const TELEPORTS_PATH: &'static str = "world/teleports.bin";
+
+/// Where a player was when the server last saved.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Player {
+  #[allow(missing_docs)]
+  pub world    : protocol::WorldId,
+  #[allow(missing_docs)]
+  pub position : Point3<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Mob {
+  world       : protocol::WorldId,
+  position    : Point3<f32>,
+  temperament : mob::ai::Temperament,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Item {
+  world    : protocol::WorldId,
+  kind     : protocol::EntityKind,
+  item     : common::item::Id,
+  position : Point3<f32>,
+  velocity : Vector3<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entities {
+  players : Vec<(entity::id::Player, Player)>,
+  mobs    : Vec<Mob>,
+  items   : Vec<Item>,
+}
+
+fn capture(server: &server::T) -> Entities {
+  // Players that haven't come back since the last restart are kept, so they don't lose their place.
+  let mut players: fnv_map::T<_, _> =
+    server.saved_players.lock().unwrap().iter().map(|(&id, &player)| (id, player)).collect();
+  for (&id, player) in server.players.lock().unwrap().iter() {
+    players.insert(id, Player { world: player.world, position: player.position });
+  }
+
+  Entities {
+    players : players.into_iter().collect(),
+    mobs    :
+      server.mobs.lock().unwrap().values()
+      .map(|mob| Mob { world: mob.world, position: mob.position, temperament: mob.ai.temperament })
+      .collect(),
+    items   :
+      server.items.lock().unwrap().values()
+      .map(|item| {
+        Item {
+          world    : item.world,
+          kind     : item.kind,
+          item     : item.item,
+          position : item.body.position,
+          velocity : item.body.velocity,
+        }
+      })
+      .collect(),
+  }
+}
+
+/// Write all modified terrain, the state of every player, mob and item, and the teleport points, to disk.
+pub fn save(server: &server::T) {
+  for world in &server.worlds {
+    persistence::flush(&world.terrain_loader.store);
+  }
+
+  let entities = capture(server);
+  match write(std::path::Path::new(PATH), &entities) {
+    Ok(()) => debug!("Saved {} players, {} mobs and {} items", entities.players.len(), entities.mobs.len(), entities.items.len()),
+    Err(err) => warn!("Error saving entities to {}: {:?}", PATH, err),
+  }
+
+  let teleports: Vec<(String, teleport::Point)> =
+    server.teleports.lock().unwrap().iter().map(|(name, &point)| (name.clone(), point)).collect();
+  if let Err(err) = write(std::path::Path::new(TELEPORTS_PATH), &teleports) {
+    warn!("Error saving teleport points to {}: {:?}", TELEPORTS_PATH, err);
+  }
+}
+
+/// Read something saved with `write`. Returns `None` if it hasn't been saved, or can't be read.
+fn read<T: DeserializeOwned>(path: &str) -> Option<T> {
+  let mut file =
+    match std::fs::File::open(path) {
+      Err(err) => {
+        if err.kind() != std::io::ErrorKind::NotFound {
+          warn!("Error opening {}: {:?}", path, err);
+        }
+        return None
+      },
+      Ok(file) => file,
+    };
+
+  match bincode::deserialize_from(&mut file, bincode::Infinite) {
+    Ok(value) => Some(value),
+    Err(err) => {
+      warn!("Error loading {}: {:?}", path, err);
+      None
+    },
+  }
+}
+
+/// Bring back the teleport points, and the mobs and items from the last save, and remember where its players were
+/// until they join. Returns false if there were no entities saved.
+pub fn restore(server: &server::T) -> bool {
+  // Anything in a world this server no longer has is dropped.
+  let exists = |world: protocol::WorldId| (world.0 as usize) < server.worlds.len();
+
+  if let Some(teleports) = read::<Vec<(String, teleport::Point)>>(TELEPORTS_PATH) {
+    let mut points = server.teleports.lock().unwrap();
+    for (name, point) in teleports.into_iter().filter(|&(_, point)| exists(point.world)) {
+      points.set(&name, point);
+    }
+  }
+
+  let entities: Entities =
+    match read(PATH) {
+      None => return false,
+      Some(entities) => entities,
+    };
+
+  info!("Restoring {} mobs and {} items", entities.mobs.len(), entities.items.len());
+  server.saved_players.lock().unwrap().extend(
+    entities.players.into_iter().filter(|&(_, player)| exists(player.world))
+  );
+  for mob in entities.mobs.into_iter().filter(|mob| exists(mob.world)) {
+    mob::add(server, mob.world, mob.position - mob::SIZE / 2.0, mob.temperament);
+  }
+  for item in entities.items.into_iter().filter(|item| exists(item.world)) {
+    item::spawn(server, item.world, item.kind, item.item, None, item.position, item.velocity);
+  }
+  true
+}
+
+fn write<T: Serialize>(path: &std::path::Path, value: &T) -> std::io::Result<()> {
+  std::fs::create_dir_all(path.parent().unwrap())?;
+
+  // Write to a temporary file first so a crash mid-write can't corrupt the last save.
+  let tmp_path = path.with_extension("bin.tmp");
+  {
+    let mut file = std::fs::File::create(&tmp_path)?;
+    bincode::serialize_into(&mut file, value, bincode::Infinite)
+      .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err)))?;
+  }
+  std::fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,459 @@
+//! Brushes for editing terrain: shapes filled with (or dug out of) the terrain, and tools that reshape
+//! what's already there.
+
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+use collision::{Aabb3};
+
+use common::material;
+use common::protocol;
+use common::schematic;
+use common::voxel;
+use voxel_data;
+
+use terrain_loader;
+
+/// Brushes bigger than this are shrunk to it.
+const MAX_RADIUS: f32 = 16.0;
+
+/// Brushes can't be used further than this from the origin along any axis, so their bounds fit in an `i32`
+/// (and voxel positions are still exact as `f32`s).
+const MAX_COORDINATE: i32 = 1 << 24;
+
+#[allow(missing_docs)]
+pub type T = voxel_data::brush::T<Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>>;
+
+/// An axis-aligned cube, positive inside.
+struct Cube {
+  radius : f32,
+}
+
+impl voxel::field::T for Cube {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.radius - p.x.abs().max(p.y.abs()).max(p.z.abs())
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    let (x, y, z) = (p.x.abs(), p.y.abs(), p.z.abs());
+    if x >= y && x >= z {
+      Vector3::new(p.x.signum(), 0.0, 0.0)
+    } else if y >= z {
+      Vector3::new(0.0, p.y.signum(), 0.0)
+    } else {
+      Vector3::new(0.0, 0.0, p.z.signum())
+    }
+  }
+}
+
+/// An upright cylinder, positive inside.
+struct Cylinder {
+  radius      : f32,
+  half_height : f32,
+}
+
+impl voxel::field::T for Cylinder {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    let across = self.radius - (p.x * p.x + p.z * p.z).sqrt();
+    let along = self.half_height - p.y.abs();
+    across.min(along)
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    let across = self.radius - (p.x * p.x + p.z * p.z).sqrt();
+    let along = self.half_height - p.y.abs();
+    if along < across || (p.x == 0.0 && p.z == 0.0) {
+      Vector3::new(0.0, p.y.signum(), 0.0)
+    } else {
+      Vector3::new(p.x, 0.0, p.z).normalize()
+    }
+  }
+}
+
+/// Fill the voxels where some field centered on `center` is positive. `extent` bounds the field in each
+/// direction.
+fn shape<Field>(
+  center   : &Point3<f32>,
+  extent   : Vector3<f32>,
+  field    : Field,
+  material : voxel::Material,
+) -> T where
+  Field: voxel::field::T + Send + 'static,
+{
+  let center = *center;
+  let mosaic =
+    voxel_data::mosaic::solid::T {
+      field: voxel_data::field::translation::T {
+        translation: center.to_vec(),
+        field: field,
+      },
+      material: material,
+    };
+  let extent = extent + Vector3::new(1.0, 1.0, 1.0);
+  voxel_data::brush::T {
+    bounds:
+      Aabb3::new(
+        {
+          let low = center + -extent;
+          Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
+        },
+        {
+          let high = center + extent;
+          Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
+        },
+      ),
+    mosaic: Box::new(mosaic) as Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size: 0,
+  }
+}
+
+#[allow(missing_docs)]
+pub fn sphere(center: &Point3<f32>, radius: f32, material: voxel::Material) -> T {
+  let field = voxel_data::field::sphere::T { radius: radius };
+  shape(center, Vector3::new(radius, radius, radius), field, material)
+}
+
+/// A cube `radius` from its center to each face.
+pub fn cube(center: &Point3<f32>, radius: f32, material: voxel::Material) -> T {
+  shape(center, Vector3::new(radius, radius, radius), Cube { radius: radius }, material)
+}
+
+/// An upright cylinder `radius` wide and `2 * half_height` tall.
+pub fn cylinder(center: &Point3<f32>, radius: f32, half_height: f32, material: voxel::Material) -> T {
+  let field = Cylinder { radius: radius, half_height: half_height };
+  shape(center, Vector3::new(radius, half_height, radius), field, material)
+}
+
+/// Level the terrain within `radius` of `center` to `center`'s height, to a depth of `strength * radius`:
+/// fill the slab below it with some material, or dig out the slab above it.
+pub fn flatten(center: &Point3<f32>, radius: f32, strength: f32, fill: Option<voxel::Material>) -> T {
+  let half_height = strength * radius / 2.0;
+  match fill {
+    Some(material) => cylinder(&(*center + Vector3::new(0.0, -half_height, 0.0)), radius, half_height, material),
+    None => cylinder(&(*center + Vector3::new(0.0, half_height, 0.0)), radius, half_height, voxel::Material::Empty),
+  }
+}
+
+/// How solid the voxels in a box around a smoothed sphere (or carved-out shape, or pasted schematic) should be, and
+/// which of them change.
+struct Smooth {
+  /// The voxel at the low corner of the grid.
+  low      : Point3<i32>,
+  width    : i32,
+  /// How solid each voxel should be, in [0, 1].
+  solidity : Vec<f32>,
+  /// What each voxel becomes, if it changes.
+  changes  : Vec<Option<voxel::Material>>,
+}
+
+impl Smooth {
+  fn solidity(&self, x: i32, y: i32, z: i32) -> f32 {
+    let clamp = |v: i32| v.max(0).min(self.width - 1);
+    let (x, y, z) = (clamp(x), clamp(y), clamp(z));
+    self.solidity[((x * self.width + y) * self.width + z) as usize]
+  }
+}
+
+impl voxel::field::T for Smooth {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    // Interpolate between voxel centers.
+    let p = *p - Vector3::new(self.low.x as f32 + 0.5, self.low.y as f32 + 0.5, self.low.z as f32 + 0.5);
+    let (x, y, z) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (fx, fy, fz) = (p.x - x, p.y - y, p.z - z);
+    let (x, y, z) = (x as i32, y as i32, z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let along_z = |x, y| lerp(self.solidity(x, y, z), self.solidity(x, y, z + 1), fz);
+    let along_y = |x| lerp(along_z(x, y), along_z(x, y + 1), fy);
+    lerp(along_y(x), along_y(x + 1), fx) - 0.5
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    let delta = 0.01;
+
+    macro_rules! differential(($d:ident) => {{
+      let high: f32 = {
+        let mut p = *p;
+        p.$d += delta;
+        voxel::field::T::density(self, &p)
+      };
+      let low: f32 = {
+        let mut p = *p;
+        p.$d -= delta;
+        voxel::field::T::density(self, &p)
+      };
+      high - low
+    }});
+
+    let v = Vector3::new(differential!(x), differential!(y), differential!(z));
+    // Negate because we're leaving the volume when density is decreasing.
+    -v.normalize()
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for Smooth {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let x = p.x.floor() as i32 - self.low.x;
+    let y = p.y.floor() as i32 - self.low.y;
+    let z = p.z.floor() as i32 - self.low.z;
+    if x < 0 || y < 0 || z < 0 || x >= self.width || y >= self.width || z >= self.width {
+      return None
+    }
+    self.changes[((x * self.width + y) * self.width + z) as usize]
+  }
+}
+
+/// Check that a brush centered here has bounds we can work with.
+fn check_center(center: &Point3<f32>) -> Result<(), String> {
+  let max = MAX_COORDINATE as f32;
+  if [center.x, center.y, center.z].iter().all(|c| c.is_finite() && c.abs() <= max) {
+    Ok(())
+  } else {
+    Err(format!("{:?} isn't within {} of the origin", center, MAX_COORDINATE))
+  }
+}
+
+/// Blur the terrain within `radius` of `center`, by `strength` in [0, 1]. Bumps are dug out if there's no
+/// fill material; otherwise, dips are filled in with it.
+pub fn smooth(
+  terrain_loader : &terrain_loader::T,
+  center         : &Point3<f32>,
+  radius         : f32,
+  strength       : f32,
+  fill           : Option<voxel::Material>,
+) -> Result<T, String> {
+  check_center(center)?;
+  let radius = radius.max(0.0).min(MAX_RADIUS);
+  let r = radius.ceil() as i32 + 1;
+  let low = Point3::new(center.x.floor() as i32 - r, center.y.floor() as i32 - r, center.z.floor() as i32 - r);
+  let width = 2 * r + 1;
+  let index = |x: i32, y: i32, z: i32| ((x * width + y) * width + z) as usize;
+
+  let mut solid = Vec::with_capacity((width * width * width) as usize);
+  for x in 0 .. width {
+  for y in 0 .. width {
+  for z in 0 .. width {
+    let material = material_at(terrain_loader, &(low + Vector3::new(x, y, z)));
+    solid.push(material::get(material).solid);
+  }}}
+
+  let mut solidity = Vec::with_capacity(solid.len());
+  let mut changes = Vec::with_capacity(solid.len());
+  for x in 0 .. width {
+  for y in 0 .. width {
+  for z in 0 .. width {
+    let was = if solid[index(x, y, z)] { 1.0 } else { 0.0 };
+
+    let mut total = 0.0;
+    let mut count = 0.0;
+    for dx in -1 .. 2 {
+    for dy in -1 .. 2 {
+    for dz in -1 .. 2 {
+      let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+      if 0 <= nx && nx < width && 0 <= ny && ny < width && 0 <= nz && nz < width {
+        total += if solid[index(nx, ny, nz)] { 1.0 } else { 0.0 };
+        count += 1.0;
+      }
+    }}}
+
+    let voxel_center = Point3::new((low.x + x) as f32 + 0.5, (low.y + y) as f32 + 0.5, (low.z + z) as f32 + 0.5);
+    let in_sphere = (voxel_center - *center).magnitude2() <= radius * radius;
+    let is = if in_sphere { was + (total / count - was) * strength } else { was };
+    solidity.push(is);
+
+    changes.push(
+      match fill {
+        Some(material) if was == 0.0 && is >= 0.5 => Some(material),
+        None if was == 1.0 && is < 0.5 => Some(voxel::Material::Empty),
+        _ => None,
+      }
+    );
+  }}}
+
+  let mosaic =
+    Smooth {
+      low      : low,
+      width    : width,
+      solidity : solidity,
+      changes  : changes,
+    };
+  Ok(voxel_data::brush::T {
+    bounds      : Aabb3::new(low, low + Vector3::new(width, width, width)),
+    mosaic      : Box::new(mosaic) as Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size : 0,
+  })
+}
+
+/// Dig out exactly these voxels, e.g. a piece of terrain that's breaking off. There's nothing to dig out if
+/// `voxels` is empty.
+pub fn carve(terrain_loader: &terrain_loader::T, voxels: &[Point3<i32>]) -> Option<T> {
+  let first = match voxels.first() {
+    None => return None,
+    Some(&first) => first,
+  };
+  let mut low = first;
+  let mut high = first;
+  for p in voxels {
+    low = Point3::new(low.x.min(p.x), low.y.min(p.y), low.z.min(p.z));
+    high = Point3::new(high.x.max(p.x), high.y.max(p.y), high.z.max(p.z));
+  }
+  // Leave a voxel around the edges, so the surfaces there are placed with what's next to them.
+  let low = low + Vector3::new(-1, -1, -1);
+  let width = (high.x - low.x).max(high.y - low.y).max(high.z - low.z) + 2;
+  let index = |p: &Point3<i32>| (((p.x - low.x) * width + p.y - low.y) * width + p.z - low.z) as usize;
+
+  let size = (width * width * width) as usize;
+  let mut changes = vec!(None; size);
+  for p in voxels {
+    changes[index(p)] = Some(voxel::Material::Empty);
+  }
+
+  let mut solidity = Vec::with_capacity(size);
+  for x in 0 .. width {
+  for y in 0 .. width {
+  for z in 0 .. width {
+    let p = low + Vector3::new(x, y, z);
+    let solid = changes[index(&p)].is_none() && material::get(material_at(terrain_loader, &p)).solid;
+    solidity.push(if solid { 1.0 } else { 0.0 });
+  }}}
+
+  let mosaic =
+    Smooth {
+      low      : low,
+      width    : width,
+      solidity : solidity,
+      changes  : changes,
+    };
+  Some(voxel_data::brush::T {
+    bounds      : Aabb3::new(low, low + Vector3::new(width, width, width)),
+    mosaic      : Box::new(mosaic) as Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size : 0,
+  })
+}
+
+/// Place a schematic with its low corner at `low`, replacing everything in its box, empty cells included.
+/// The schematic should already have been `check`ed.
+pub fn paste(terrain_loader: &terrain_loader::T, low: &Point3<i32>, schematic: &schematic::T) -> Result<T, String> {
+  let width = schematic.size.iter().cloned().max().unwrap() as i32 + 2;
+  if [low.x, low.y, low.z].iter().any(|c| c.abs() > MAX_COORDINATE) {
+    return Err(format!("{:?} isn't within {} of the origin", low, MAX_COORDINATE))
+  }
+  // Leave a voxel around the edges, so the surfaces there are placed with what's next to them.
+  let corner = *low + Vector3::new(-1, -1, -1);
+  let inside = |x: i32, y: i32, z: i32| {
+    0 <= x && x < schematic.size[0] as i32 &&
+    0 <= y && y < schematic.size[1] as i32 &&
+    0 <= z && z < schematic.size[2] as i32
+  };
+
+  let size = (width * width * width) as usize;
+  let mut solidity = Vec::with_capacity(size);
+  let mut changes = Vec::with_capacity(size);
+  for x in 0 .. width {
+  for y in 0 .. width {
+  for z in 0 .. width {
+    let material =
+      if inside(x - 1, y - 1, z - 1) {
+        let material = schematic.get(x - 1, y - 1, z - 1);
+        changes.push(Some(material));
+        material
+      } else {
+        changes.push(None);
+        material_at(terrain_loader, &(corner + Vector3::new(x, y, z)))
+      };
+    solidity.push(if material::get(material).solid { 1.0 } else { 0.0 });
+  }}}
+
+  let mosaic =
+    Smooth {
+      low      : corner,
+      width    : width,
+      solidity : solidity,
+      changes  : changes,
+    };
+  Ok(voxel_data::brush::T {
+    bounds      : Aabb3::new(corner, corner + Vector3::new(width, width, width)),
+    mosaic      : Box::new(mosaic) as Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size : 0,
+  })
+}
+
+/// The voxels from `low` up to (but not including) `high`, to paste somewhere else.
+pub fn copy(terrain_loader: &terrain_loader::T, low: &Point3<i32>, high: &Point3<i32>) -> schematic::T {
+  let size = [(high.x - low.x).max(0) as u32, (high.y - low.y).max(0) as u32, (high.z - low.z).max(0) as u32];
+  let mut materials = Vec::with_capacity(size[0] as usize * size[1] as usize * size[2] as usize);
+  for z in low.z .. high.z {
+  for y in low.y .. high.y {
+  for x in low.x .. high.x {
+    materials.push(material_at(terrain_loader, &Point3::new(x, y, z)));
+  }}}
+  schematic::T {
+    size      : size,
+    materials : materials,
+  }
+}
+
+/// The material of a voxel, or of its corner if it's on a surface.
+fn material_at(terrain_loader: &terrain_loader::T, p: &Point3<i32>) -> voxel::Material {
+  match terrain_loader.load_voxel(&voxel::bounds::new(p.x, p.y, p.z, 0)) {
+    voxel::Volume(material) => material,
+    voxel::Surface(voxel) => voxel.corner,
+  }
+}
+
+/// Make the brush a client asked for.
+pub fn of_protocol(
+  terrain_loader : &terrain_loader::T,
+  brush          : protocol::Brush,
+  center         : &Point3<f32>,
+  action         : protocol::BrushAction,
+) -> Result<T, String> {
+  check_center(center)?;
+  let fill =
+    match action {
+      protocol::BrushAction::Add(material) => Some(material),
+      protocol::BrushAction::Remove => None,
+    };
+  let material = fill.unwrap_or(voxel::Material::Empty);
+  let clamp_radius = |radius: f32| radius.max(0.0).min(MAX_RADIUS);
+  let clamp_strength = |strength: f32| strength.max(0.0).min(1.0);
+
+  let brush =
+    match brush {
+      protocol::Brush::Sphere { radius } => sphere(center, clamp_radius(radius), material),
+      protocol::Brush::Cube { radius } => cube(center, clamp_radius(radius), material),
+      protocol::Brush::Cylinder { radius } => {
+        let radius = clamp_radius(radius);
+        cylinder(center, radius, radius, material)
+      },
+      protocol::Brush::Smooth { radius, strength } =>
+        return smooth(terrain_loader, center, radius, clamp_strength(strength), fill),
+      protocol::Brush::Flatten { radius, strength } =>
+        flatten(center, clamp_radius(radius), clamp_strength(strength), fill),
+    };
+  Ok(brush)
+}
+
+#[test]
+fn shapes_are_positive_inside() {
+  use common::voxel::field::T as Field;
+
+  let mut cube = Cube { radius: 2.0 };
+  assert!(cube.density(&Point3::new(1.9, -1.9, 0.0)) > 0.0);
+  assert!(cube.density(&Point3::new(2.1, 0.0, 0.0)) < 0.0);
+  assert_eq!(cube.normal(&Point3::new(0.5, -3.0, 1.0)), Vector3::new(0.0, -1.0, 0.0));
+
+  let mut cylinder = Cylinder { radius: 2.0, half_height: 1.0 };
+  assert!(cylinder.density(&Point3::new(1.0, 0.5, 1.0)) > 0.0);
+  assert!(cylinder.density(&Point3::new(1.5, 0.0, 1.5)) < 0.0);
+  assert!(cylinder.density(&Point3::new(0.0, 1.5, 0.0)) < 0.0);
+  assert_eq!(cylinder.normal(&Point3::new(0.0, 0.9, 0.0)), Vector3::new(0.0, 1.0, 0.0));
+  assert_eq!(cylinder.normal(&Point3::new(1.9, 0.0, 0.0)), Vector3::new(1.0, 0.0, 0.0));
+}
+
+#[test]
+fn centers_out_of_range_are_rejected() {
+  use std::f32::{INFINITY, NAN};
+
+  assert!(check_center(&Point3::new(1.5, -20.0, 1000.0)).is_ok());
+  assert!(check_center(&Point3::new(NAN, 0.0, 0.0)).is_err());
+  assert!(check_center(&Point3::new(0.0, INFINITY, 0.0)).is_err());
+  assert!(check_center(&Point3::new(0.0, 0.0, -3.0e9)).is_err());
+}
@@ -1,141 +1,507 @@
-use cgmath::{Point3, Vector3, EuclideanSpace};
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
 use collision::{Aabb3};
 use rand;
+use rand::Rng;
 use rand::distributions::IndependentSample;
-use std::convert::AsRef;
-use std::f32::consts::PI;
+use std;
+use std::io;
 use std::ops::DerefMut;
+use std::sync::mpsc;
 use std::time::Duration;
 use stopwatch;
 
+use common;
+use common::compression;
+use common::movement;
+use common::net;
 use common::protocol;
-use common::socket::SendSocket;
+use common::schematic;
 use common::voxel;
 
+use brush;
 use entity;
+use interest;
+use inventory;
+use item;
 use player;
+use plugin;
+use replication;
 use server;
 use server::Client;
+use teleport;
 use terrain;
 use voxel_data;
 use update_gaia;
-use update_gaia::LoadDestination;
+use world;
+
+/// Longer chat messages are cut off.
+const MAX_CHAT_LENGTH: usize = 256;
+/// Longer player names are cut off.
+const MAX_NAME_LENGTH: usize = 24;
+
+/// The radius of the sphere removed by digging.
+const DIG_RADIUS: f32 = 8.0;
+/// The radius of the sphere added by placing one item.
+const PLACE_RADIUS: f32 = 2.0;
+/// How many dug-out voxels make one item. This is about the volume of a placed sphere.
+const VOXELS_PER_ITEM: u32 = 32;
+/// How fast dropped items are tossed, in units per update.
+const DROP_SPEED: f32 = 0.2;
+/// How fast thrown items fly, in units per update.
+const THROW_SPEED: f32 = 1.5;
 
 fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
   (bounds.min + bounds.max.to_vec()) * 0.5
 }
 
+/// How long to keep trying to connect back to a client.
+const CONNECT_BUDGET_SECS: u64 = 3;
+/// How long a send to a client can block.
+const SEND_TIMEOUT_SECS: u64 = 30;
+/// The most connections back to clients that can be opening at once. Past this, new clients are dropped until some
+/// of them finish, so a flood of hellos can't tie up a thread each.
+const MAX_CONNECTING: usize = 16;
+
+/// A connection back to a client that's being opened on its own thread, so a client that's slow to listen doesn't
+/// hold up everyone else's messages.
+pub struct Connecting {
+  url     : String,
+  socket  : mpsc::Receiver<io::Result<Box<dyn net::Sender>>>,
+  purpose : Purpose,
+}
+
+/// What to do with a connection once it's open.
+enum Purpose {
+  /// Tell the client why it can't join, and hang up.
+  TurnAway(protocol::Hello, protocol::ServerToClient),
+  Init(protocol::Hello),
+  Resume {
+    client_id : protocol::ClientId,
+    token     : protocol::SessionToken,
+    hello     : protocol::Hello,
+  },
+}
+
+/// Start connecting back to a client at `url`. The connection's finished by `finish_connections`.
+/// Nothing's done if `url` is already being connected to, or too many other clients are.
+fn connect_later(server: &server::T, transport: net::Transport, url: &str, purpose: Purpose) {
+  let mut connecting = server.connecting.lock().unwrap();
+  if connecting.iter().any(|connection| connection.url == url) {
+    debug!("Already connecting to {}", url);
+    return
+  }
+  if connecting.len() >= MAX_CONNECTING {
+    warn!("Dropping {}, since {} other clients are still being connected to", url, connecting.len());
+    return
+  }
+
+  let (send, recv) = mpsc::channel();
+  let thread_url = url.to_owned();
+  std::thread::spawn(move || {
+    let socket =
+      transport.connect_within(
+        &thread_url,
+        Duration::from_secs(CONNECT_BUDGET_SECS),
+        Some(Duration::from_secs(SEND_TIMEOUT_SECS)),
+      );
+    // If the server's gone by now, nobody needs the socket.
+    let _ = send.send(socket);
+  });
+  connecting.push(
+    Connecting {
+      url     : url.to_owned(),
+      socket  : recv,
+      purpose : purpose,
+    }
+  );
+}
+
+/// Finish whatever's waiting on connections that have opened since the last call. Clients that couldn't be
+/// connected to are dropped.
+pub fn finish_connections(server: &server::T) {
+  let finished: Vec<_>;
+  {
+    let mut connecting = server.connecting.lock().unwrap();
+    let mut pending = Vec::new();
+    let mut done = Vec::new();
+    for connection in connecting.drain(..) {
+      let socket =
+        match connection.socket.try_recv() {
+          Err(mpsc::TryRecvError::Empty) => {
+            pending.push(connection);
+            continue
+          },
+          Err(mpsc::TryRecvError::Disconnected) =>
+            Err(io::Error::new(io::ErrorKind::Other, "the connecting thread died")),
+          Ok(socket) => socket,
+        };
+      done.push((connection.url, socket, connection.purpose));
+    }
+    *connecting = pending;
+    finished = done;
+  }
+
+  for (url, socket, purpose) in finished {
+    match socket {
+      Err(err) => warn!("Dropping {}, which couldn't be connected to: {}", url, err),
+      Ok(socket) => connected(server, socket, purpose),
+    }
+  }
+}
+
+/// Carry on with whatever a connection was opened for.
+fn connected(server: &server::T, socket: Box<dyn net::Sender>, purpose: Purpose) {
+  match purpose {
+    Purpose::TurnAway(hello, msg) => {
+      new_client(server, &hello, 0, socket).send(msg);
+    },
+    Purpose::Init(hello) => {
+      let session = server.rng.lock().unwrap().gen();
+      let mut client = new_client(server, &hello, session, socket);
+
+      let client_id = server.client_allocator.lock().unwrap().allocate();
+      client.send(
+        protocol::ServerToClient::LeaseId {
+          client_id : client_id,
+          seed      : server.seed,
+          session   : session,
+          features  : client.features.clone(),
+        }
+      );
+
+      server.clients.lock().unwrap().insert(client_id, client);
+    },
+    Purpose::Resume { client_id, token, hello } => {
+      let mut client = new_client(server, &hello, token, socket);
+
+      let player =
+        match server.clients.lock().unwrap().get(&client_id) {
+          Some(old) if old.session == token => old.player,
+          _ => None,
+        };
+      let resumed =
+        player.and_then(|player_id| {
+          server.players.lock().unwrap().get(&player_id)
+            .map(|player| (player_id, player.world, player.position))
+        });
+      let (player_id, world, position) =
+        match resumed {
+          None => {
+            warn!("Refusing to resume {:?}, which is gone or has another token", client_id);
+            client.send(protocol::ServerToClient::ResumeRefused);
+            return
+          },
+          Some(resumed) => resumed,
+        };
+
+      // The new connection replaces the old one. The client starts over on snapshots and terrain.
+      client.player = Some(player_id);
+      client.interest.enter(world, position);
+      client.send(protocol::ServerToClient::Resumed { world: world, position: position });
+      client.send_lights(server.world(world));
+      let names: Vec<_> =
+        server.players.lock().unwrap().values()
+        .map(|player| (player.entity_id, player.name.clone()))
+        .collect();
+      client.send(protocol::ServerToClient::PlayerNames(names));
+
+      server.clients.lock().unwrap().insert(client_id, client);
+    },
+  }
+}
+
+/// A client that introduced itself with `hello`, not yet controlling any player.
+fn new_client(
+  server: &server::T,
+  hello: &protocol::Hello,
+  session: protocol::SessionToken,
+  socket: Box<dyn net::Sender>,
+) -> Client {
+  Client {
+    socket: socket,
+    compression: compression::negotiate(&hello.compression),
+    player: None,
+    replication: replication::new(),
+    interest: interest::new(),
+    metrics: server.metrics.clone(),
+    session: session,
+    features: protocol::negotiate_features(&hello.features),
+    weather: None,
+    sky: None,
+  }
+}
+
+/// Tell a client that speaks protocol `version` why it can't join, if it can't. Returns whether it was turned away.
+fn turn_away(server: &server::T, transport: net::Transport, version: u32, client_url: &str) -> bool {
+  if version == protocol::VERSION {
+    return false
+  }
+  info!("Turning away {}, which speaks protocol version {}", client_url, version);
+  let hello =
+    protocol::Hello {
+      version     : version,
+      client_url  : client_url.to_owned(),
+      compression : Vec::new(),
+      features    : Vec::new(),
+    };
+  let msg =
+    protocol::ServerToClient::Incompatible {
+      server_version : protocol::VERSION,
+      reason         :
+        format!(
+          "The server speaks protocol version {}, but this client speaks version {}. Use a matching build.",
+          protocol::VERSION,
+          version,
+        ),
+    };
+  connect_later(server, transport, client_url, Purpose::TurnAway(hello, msg));
+  true
+}
+
+/// Deal with a message that couldn't be read. Clients speaking other versions are turned away; anything else is
+/// dropped.
+pub fn reject_unreadable(server: &server::T, transport: net::Transport, msg: &[u8]) {
+  if let Some((version, client_url)) = protocol::peek_init(msg) {
+    if turn_away(server, transport, version, &client_url) {
+      return
+    }
+  }
+  warn!("Dropping a message that couldn't be read ({} bytes)", msg.len());
+}
+
+fn player_world(server: &server::T, player_id: entity::id::Player) -> protocol::WorldId {
+  server.players.lock().unwrap().get(&player_id).unwrap().world
+}
+
 fn cast(
   server: &server::T,
   player_id: entity::id::Player,
 ) -> Option<voxel::bounds::T> {
   let ray;
+  let world;
   {
     let players = server.players.lock().unwrap();
     let player = players.get(&player_id).unwrap();
     ray = player.forward_ray();
+    world = player.world;
   }
 
-  server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
+  server.world(world).terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
     &ray,
     &mut |bounds, voxel| {
       match voxel {
-        &voxel::Volume(voxel::Material::Empty) => None,
+        &voxel::Volume(voxel::Material::Empty) |
+        &voxel::Volume(voxel::Material::Water) => None,
         _ => Some(bounds),
       }
     }
   )
 }
 
+/// Whether a message names a world this server doesn't have.
+fn names_unknown_world(server: &server::T, update: &protocol::ClientToServer) -> bool {
+  match *update {
+    protocol::ClientToServer::RequestVoxels { world, .. } |
+    protocol::ClientToServer::ModifyVoxels { world, .. } |
+    protocol::ClientToServer::Copy { world, .. } |
+    protocol::ClientToServer::Paste { world, .. } =>
+      world.0 as usize >= server.worlds.len(),
+    _ => false,
+  }
+}
+
+/// Whether a message comes from a client or player that no longer exists, e.g. because it was kicked.
+fn is_orphaned(server: &server::T, update: &protocol::ClientToServer) -> bool {
+  let player_id =
+    match *update {
+      protocol::ClientToServer::Walk(player_id, _, _) |
+      protocol::ClientToServer::RotatePlayer(player_id, _) |
+      protocol::ClientToServer::StartJump(player_id, _) |
+      protocol::ClientToServer::StopJump(player_id, _) |
+      protocol::ClientToServer::Add(player_id) |
+      protocol::ClientToServer::Remove(player_id) |
+      protocol::ClientToServer::Chat(player_id, _) |
+      protocol::ClientToServer::SelectItem(player_id, _) |
+      protocol::ClientToServer::Place(player_id) |
+      protocol::ClientToServer::DropItem(player_id) |
+      protocol::ClientToServer::Throw(player_id) |
+      protocol::ClientToServer::Craft(player_id, _) |
+      protocol::ClientToServer::RequestTeleport(player_id, _) |
+      protocol::ClientToServer::ModifyVoxels { player: player_id, .. } |
+      protocol::ClientToServer::Undo(player_id) |
+      protocol::ClientToServer::Redo(player_id) |
+      protocol::ClientToServer::Copy { player: player_id, .. } |
+      protocol::ClientToServer::Paste { player: player_id, .. } =>
+        player_id,
+      protocol::ClientToServer::Ping(client_id, _) |
+      protocol::ClientToServer::AddPlayer(client_id, _) |
+      protocol::ClientToServer::AckSnapshot(client_id, _) |
+      protocol::ClientToServer::RequestVoxels { client_id, .. } |
+      protocol::ClientToServer::CancelChunkRequest { client_id, .. } =>
+        return !server.clients.lock().unwrap().contains_key(&client_id),
+      protocol::ClientToServer::Init(..) |
+      protocol::ClientToServer::Resume { .. } =>
+        return false,
+    };
+  !server.players.lock().unwrap().contains_key(&player_id)
+}
+
 pub fn apply_client_update<UpdateGaia>(
   server: &server::T,
+  transport: net::Transport,
   update_gaia: &mut UpdateGaia,
   update: protocol::ClientToServer,
 ) where
   UpdateGaia: FnMut(update_gaia::Message),
 {
+  if is_orphaned(server, &update) {
+    debug!("Dropping message from a removed client or player: {:?}", update);
+    return
+  }
+  if names_unknown_world(server, &update) {
+    warn!("Dropping message about a world that doesn't exist: {:?}", update);
+    return
+  }
+
   stopwatch::time("apply_client_update", move || {
     match update {
-      protocol::ClientToServer::Init(client_url) => {
-        info!("Sending to {}.", client_url);
-
-        let mut client =
-          Client {
-            socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
-          };
-
-        let client_id = server.client_allocator.lock().unwrap().allocate();
-        client.send(protocol::ServerToClient::LeaseId(client_id));
-
-        server.clients.lock().unwrap().insert(client_id, client);
+      protocol::ClientToServer::Init(hello) => {
+        if turn_away(server, transport, hello.version, &hello.client_url) {
+          return
+        }
+        info!("Sending to {}.", hello.client_url);
+        let url = hello.client_url.clone();
+        connect_later(server, transport, &url, Purpose::Init(hello));
+      },
+      protocol::ClientToServer::Resume { client_id, token, hello } => {
+        if turn_away(server, transport, hello.version, &hello.client_url) {
+          return
+        }
+        info!("{:?} is resuming its session, sending to {}.", client_id, hello.client_url);
+        let url = hello.client_url.clone();
+        connect_later(server, transport, &url, Purpose::Resume { client_id: client_id, token: token, hello: hello });
       },
-      protocol::ClientToServer::Ping(client_id) => {
+      protocol::ClientToServer::Ping(client_id, client_ns) => {
         server.clients.lock().unwrap()
           .get_mut(&client_id)
           .unwrap()
-          .send(protocol::ServerToClient::Ping);
+          .send(protocol::ServerToClient::Pong { client_ns: client_ns, server_ns: server.clock.now_ns() });
+      },
+      protocol::ClientToServer::AckSnapshot(client_id, snapshot_id) => {
+        let mut clients = server.clients.lock().unwrap();
+        let client = clients.get_mut(&client_id).unwrap();
+        // Without an acknowledged baseline, every snapshot is sent whole.
+        if client.features.contains(&protocol::Feature::DeltaSnapshots) {
+          client.replication.ack(snapshot_id);
+        }
       },
-      protocol::ClientToServer::AddPlayer(client_id) => {
+      protocol::ClientToServer::AddPlayer(client_id, name) => {
+        let entity_id = server.players.lock().unwrap().allocate();
         let mut player =
           player::new(
-            server.player_allocator.lock().unwrap().allocate(),
+            entity_id,
             server.misc_allocator.lock().unwrap().allocate(),
+            clean_name(&name, entity_id),
             &server.owner_allocator,
           );
 
-        // TODO: shift upward until outside terrain
-        let min = Point3::new(0.0, 64.0, 4.0);
-        let max = min + (&Vector3::new(1.0, 2.0, 1.0));
-        let bounds = Aabb3::new(min, max);
-        server.physics.lock().unwrap().insert_misc(player.physics_id, &bounds);
+        // Players from the last save pick up where they left off.
+        let saved = server.saved_players.lock().unwrap().remove(&player.entity_id);
+        let start =
+          match saved {
+            None => server.teleports.lock().unwrap().spawn(),
+            Some(saved) => teleport::Point { world: saved.world, position: saved.position },
+          };
+        player.world = start.world;
+        let half_size = movement::PLAYER_SIZE / 2.0;
+        let bounds = Aabb3::new(start.position + -half_size, start.position + half_size);
+        server.world(player.world).physics.lock().unwrap().insert_misc(player.physics_id, &bounds);
 
         player.position = center(&bounds);
-        player.rotate_lateral(PI / 2.0);
+        player.rotate_lateral(movement::SPAWN_LATERAL_ROTATION);
 
         let id = player.entity_id;
+        let world = player.world;
         let pos = player.position;
+        let name = player.name.clone();
+        info!("{} joined as {:?}", name, id);
 
-        server.players.lock().unwrap().insert(id, player);
+        let names: Vec<_>;
+        {
+          let mut players = server.players.lock().unwrap();
+          players.insert(id, player);
+          names = players.values().map(|player| (player.entity_id, player.name.clone())).collect();
+        }
 
-        let mut clients = server.clients.lock().unwrap();
-        let client = clients.get_mut(&client_id).unwrap();
-        client.send(
-          protocol::ServerToClient::PlayerAdded(id, pos)
-        );
+        {
+          let mut clients = server.clients.lock().unwrap();
+          for (&other_id, client) in clients.iter_mut() {
+            if other_id != client_id {
+              client.send(protocol::ServerToClient::PlayerNames(vec!((id, name.clone()))));
+            }
+          }
+          let client = clients.get_mut(&client_id).unwrap();
+          client.player = Some(id);
+          client.send(
+            protocol::ServerToClient::PlayerAdded(id, pos)
+          );
+          client.send(protocol::ServerToClient::PlayerNames(names));
+          if world != world::OVERWORLD {
+            client.interest.enter(world, pos);
+            client.send(protocol::ServerToClient::ChangedWorld { world: world, position: pos });
+          }
+          client.send_lights(server.world(world));
+        }
+        server.plugins.player_joined(server, id);
       },
-      protocol::ClientToServer::StartJump(player_id) => {
+      protocol::ClientToServer::StartJump(player_id, input) => {
         let mut players = server.players.lock().unwrap();
         let player = players.get_mut(&player_id).unwrap();
-        if !player.is_jumping {
-          player.is_jumping = true;
-          // this 0.3 is duplicated in a few places
-          player.accel.y = player.accel.y + 0.3;
-        }
+        player.input(input, |movement| movement.start_jump());
       },
-      protocol::ClientToServer::StopJump(player_id) => {
+      protocol::ClientToServer::StopJump(player_id, input) => {
         let mut players = server.players.lock().unwrap();
         let player = players.get_mut(&player_id).unwrap();
-        if player.is_jumping {
-          player.is_jumping = false;
-          // this 0.3 is duplicated in a few places
-          player.accel.y = player.accel.y - 0.3;
-        }
+        player.input(input, |movement| movement.stop_jump());
       },
-      protocol::ClientToServer::Walk(player_id, v) => {
+      protocol::ClientToServer::Walk(player_id, v, input) => {
         let mut players = server.players.lock().unwrap();
         let player = players.get_mut(&player_id).unwrap();
-        player.walk(v);
+        if movement::is_valid_walk(&v) {
+          player.input(input, |movement| movement.walk(v));
+        } else {
+          warn!("Ignoring {:?} walking by {:?}", player_id, v);
+          // The input's still acknowledged, so the client drops it from its prediction, and snaps back to where we
+          // say the player is with the next movement update.
+          player.input(input, |_| {});
+        }
       },
       protocol::ClientToServer::RotatePlayer(player_id, v) => {
+        if !v.x.is_finite() || !v.y.is_finite() {
+          warn!("Ignoring {:?} rotating by {:?}", player_id, v);
+          return
+        }
         let mut players = server.players.lock().unwrap();
         let player = players.get_mut(&player_id).unwrap();
         player.rotate_lateral(v.x);
         player.rotate_vertical(v.y);
       },
-      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels } => {
-        update_gaia(update_gaia::Message::Load(time_requested_ns, voxels, LoadDestination::Client(client_id)));
+      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, world, voxels } => {
+        let player = server.clients.lock().unwrap().get(&client_id).and_then(|client| client.player);
+        let requester =
+          player.and_then(|player| {
+            server.players.lock().unwrap().get(&player)
+              .and_then(|player| if player.world == world { Some(player.position) } else { None })
+          });
+        server.voxel_requests.push(time_requested_ns, client_id, world, voxels, requester);
+      },
+      protocol::ClientToServer::CancelChunkRequest { client_id, time_requested_ns } => {
+        if !server.voxel_requests.cancel(client_id, time_requested_ns) {
+          trace!("Request from {:?} at {} was already served", client_id, time_requested_ns);
+        }
       },
       protocol::ClientToServer::Add(player_id) => {
+        let world = player_world(server, player_id);
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
@@ -194,47 +560,325 @@ pub fn apply_client_update<UpdateGaia>(
               min_lg_size: 0,
             };
 
-          update_gaia(update_gaia::Message::Brush(brush));
+          if may_edit(server, Some(player_id), world, &brush.bounds) {
+            update_gaia(update_gaia::Message::Brush(world, brush, Some(player_id)));
+          }
         });
       },
       protocol::ClientToServer::Remove(player_id) => {
+        let world = player_world(server, player_id);
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
           debug!("remove bounds {:?}", bounds);
           let center = bounds.center();
-          let r = 8.0;
-          let sphere =
-            voxel_data::mosaic::solid::T {
-              field: voxel_data::field::translation::T {
-                translation: center.to_vec(),
-                field: voxel_data::field::sphere::T {
-                  radius: r,
-                },
-              },
-              material: voxel::Material::Empty,
-            };
-          let r = sphere.field.field.radius + 1.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = sphere.field.translation + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = sphere.field.translation + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(sphere) as Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
-            };
-          let brush: voxel_data::brush::T<Box<dyn voxel_data::mosaic::T<voxel::Material> + Send>> = brush;
-          update_gaia(update_gaia::Message::Brush(brush));
+          let brush = brush::sphere(&center, DIG_RADIUS, voxel::Material::Empty);
+          if !may_edit(server, Some(player_id), world, &brush.bounds) {
+            return
+          }
+          let dug = materials_in_sphere(server, world, &center, DIG_RADIUS);
+          // Digging fills the inventory, so it can't be undone.
+          update_gaia(update_gaia::Message::Brush(world, brush, None));
+          server.world(world).grass.lock().unwrap().remove(&center, DIG_RADIUS);
+
+          let mostly = dug.iter().max_by_key(|&&(_, voxel_count)| voxel_count).map(|&(material, _)| material);
+          for (_, client) in server.clients.lock().unwrap().iter_mut() {
+            if client.interest.sees(world, &center) {
+              // The grass goes with the ground it grew in, without waiting for the new terrain.
+              client.send(protocol::ServerToClient::RemoveGrass { center: center, radius: DIG_RADIUS });
+              if let Some(material) = mostly {
+                client.send(protocol::ServerToClient::Dug { position: center, material: material });
+              }
+            }
+          }
+
+          let mut changed = Vec::new();
+          {
+            let mut players = server.players.lock().unwrap();
+            let player = players.get_mut(&player_id).unwrap();
+            for (material, voxel_count) in dug {
+              let items = voxel_count / VOXELS_PER_ITEM;
+              match common::item::of_material(material) {
+                Some(item) if items > 0 => changed.extend(player.inventory.add(item, items)),
+                _ => {},
+              }
+            }
+          }
+          send_inventory_slots(server, player_id, changed);
         });
       },
+      protocol::ClientToServer::SelectItem(player_id, slot) => {
+        let mut players = server.players.lock().unwrap();
+        let player = players.get_mut(&player_id).unwrap();
+        player.inventory.select(slot as usize);
+      },
+      protocol::ClientToServer::Place(player_id) => {
+        let world = player_world(server, player_id);
+        let bounds = cast(server, player_id);
+
+        bounds.map(|bounds| {
+          // Only items that place some material get used up.
+          let material =
+            server.players.lock().unwrap().get(&player_id).unwrap()
+            .inventory.selected_item().and_then(|item| common::item::get(item).places);
+
+          material.map(|material| {
+            let brush = brush::sphere(&bounds.center(), PLACE_RADIUS, material);
+            if !may_edit(server, Some(player_id), world, &brush.bounds) {
+              return
+            }
+            let slot;
+            {
+              let mut players = server.players.lock().unwrap();
+              let player = players.get_mut(&player_id).unwrap();
+              slot = player.inventory.selected();
+              player.inventory.take_selected();
+            }
+            update_gaia(update_gaia::Message::Brush(world, brush, None));
+            send_inventory_slots(server, player_id, vec!(slot));
+          });
+        });
+      },
+      protocol::ClientToServer::DropItem(player_id) => {
+        throw(server, player_id, protocol::EntityKind::DroppedItem, DROP_SPEED);
+      },
+      protocol::ClientToServer::Throw(player_id) => {
+        throw(server, player_id, protocol::EntityKind::Projectile, THROW_SPEED);
+      },
+      protocol::ClientToServer::Craft(player_id, recipe_id) => {
+        let result =
+          match common::item::recipe(recipe_id) {
+            None => Err(common::item::CraftError::UnknownRecipe),
+            Some(recipe) => {
+              let mut players = server.players.lock().unwrap();
+              let player = players.get_mut(&player_id).unwrap();
+              player.inventory.craft(recipe)
+            },
+          };
+        let result =
+          match result {
+            Ok(changed) => {
+              send_inventory_slots(server, player_id, changed);
+              Ok(())
+            },
+            Err(err) => Err(err),
+          };
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          if client.player == Some(player_id) {
+            client.send(protocol::ServerToClient::Crafted { recipe: recipe_id, result: result });
+          }
+        }
+      },
+      protocol::ClientToServer::Chat(player_id, text) => {
+        let text: String = text.chars().take(MAX_CHAT_LENGTH).collect();
+        let text =
+          match server.plugins.chat(server, player_id, text) {
+            None => return,
+            Some(text) => text,
+          };
+        info!("{}: {}", server.players.lock().unwrap().get(&player_id).unwrap().name, text);
+        let mut clients = server.clients.lock().unwrap();
+        for (_, client) in clients.iter_mut() {
+          client.send(protocol::ServerToClient::Chat { from: player_id, text: text.clone() });
+        }
+      },
+      protocol::ClientToServer::RequestTeleport(player_id, name) => {
+        let point = server.teleports.lock().unwrap().get(&name);
+        match point {
+          Some(point) => {
+            info!("{:?} teleported to {}", player_id, name);
+            teleport::teleport(server, player_id, point);
+          },
+          None => {
+            let message = {
+              let teleports = server.teleports.lock().unwrap();
+              let names = teleports.names();
+              if names.is_empty() {
+                String::from("There are no teleport points.")
+              } else {
+                format!("There's no teleport point called {}. Try {}.", name, names.join(", "))
+              }
+            };
+            server.notify(player_id, &message);
+          },
+        }
+      },
+      protocol::ClientToServer::ModifyVoxels { player, world, brush, position, action } => {
+        let brush =
+          match brush::of_protocol(&server.world(world).terrain_loader, brush, &position, action) {
+            Ok(brush) => brush,
+            Err(err) => {
+              warn!("Dropping a brush stroke that doesn't make sense from {:?}: {}", player, err);
+              return
+            },
+          };
+        if may_edit(server, Some(player), world, &brush.bounds) {
+          update_gaia(update_gaia::Message::Brush(world, brush, Some(player)));
+        }
+      },
+      protocol::ClientToServer::Undo(player_id) => {
+        update_gaia(update_gaia::Message::Undo(player_id));
+      },
+      protocol::ClientToServer::Redo(player_id) => {
+        update_gaia(update_gaia::Message::Redo(player_id));
+      },
+      protocol::ClientToServer::Copy { player, world, low, high } => {
+        let max = schematic::MAX_SIZE as i32;
+        let fits = |low: i32, high: i32| high.checked_sub(low).map_or(false, |size| 0 < size && size <= max);
+        if !fits(low.x, high.x) || !fits(low.y, high.y) || !fits(low.z, high.z) {
+          server.notify(player, &format!("Only regions up to {} voxels along each side can be copied.", max));
+          return
+        }
+        let copied = brush::copy(&server.world(world).terrain_loader, &low, &high);
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          if client.player == Some(player) {
+            client.send(protocol::ServerToClient::Copied(compression::compress(client.compression, &copied)));
+          }
+        }
+      },
+      protocol::ClientToServer::Paste { player, world, position, schematic } => {
+        if let Err(err) = schematic.check() {
+          warn!("Dropping a paste that doesn't make sense from {:?}: {}", player, err);
+          return
+        }
+        let brush =
+          match brush::paste(&server.world(world).terrain_loader, &position, &schematic) {
+            Ok(brush) => brush,
+            Err(err) => {
+              warn!("Dropping a paste that doesn't make sense from {:?}: {}", player, err);
+              return
+            },
+          };
+        if may_edit(server, Some(player), world, &brush.bounds) {
+          update_gaia(update_gaia::Message::Brush(world, brush, Some(player)));
+        }
+      },
     };
   })
 }
+
+/// Whether the plugins allow some terrain to be changed.
+fn may_edit(
+  server : &server::T,
+  player : Option<entity::id::Player>,
+  world  : protocol::WorldId,
+  bounds : &Aabb3<i32>,
+) -> bool {
+  let edit =
+    plugin::VoxelEdit {
+      world  : world,
+      bounds : *bounds,
+      player : player,
+    };
+  server.plugins.allows_edit(server, &edit)
+}
+
+/// A player's name, trimmed of control characters and cut to length. Players who leave it blank get one
+/// made from their id.
+fn clean_name(name: &str, id: entity::id::Player) -> String {
+  let name: String =
+    name.chars()
+    .filter(|c| !c.is_control())
+    .take(MAX_NAME_LENGTH)
+    .collect();
+  let name = name.trim();
+  if name.is_empty() {
+    format!("Player {}", id.to_u32())
+  } else {
+    name.to_owned()
+  }
+}
+
+/// Count the voxels of each (solid) material in a sphere.
+pub fn materials_in_sphere(
+  server: &server::T,
+  world: protocol::WorldId,
+  center: &Point3<f32>,
+  radius: f32,
+) -> Vec<(voxel::Material, u32)> {
+  let terrain = &server.world(world).terrain_loader.terrain;
+  let mut counts: Vec<(voxel::Material, u32)> = Vec::new();
+  let low = *center + -Vector3::new(radius, radius, radius);
+  let high = *center + Vector3::new(radius, radius, radius);
+  for x in low.x.floor() as i32 .. high.x.ceil() as i32 {
+  for y in low.y.floor() as i32 .. high.y.ceil() as i32 {
+  for z in low.z.floor() as i32 .. high.z.ceil() as i32 {
+    let voxel_center = Point3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
+    if (voxel_center - *center).magnitude2() > radius * radius {
+      continue
+    }
+
+    let material =
+      match terrain.load(&voxel::bounds::new(x, y, z, 0)) {
+        voxel::Volume(material) => material,
+        voxel::Surface(voxel) => voxel.corner,
+      };
+    match material {
+      voxel::Material::Empty | voxel::Material::Water => continue,
+      _ => {},
+    }
+
+    if let Some(entry) = counts.iter_mut().find(|entry| entry.0 == material) {
+      entry.1 += 1;
+      continue
+    }
+    counts.push((material, 1));
+  }}}
+  counts
+}
+
+/// Send one of the player's selected items flying the way they're looking, at `speed` units per update.
+fn throw(
+  server: &server::T,
+  player_id: entity::id::Player,
+  kind: protocol::EntityKind,
+  speed: f32,
+) {
+  let slot;
+  let item;
+  let world;
+  let position;
+  let forward;
+  {
+    let mut players = server.players.lock().unwrap();
+    let player = players.get_mut(&player_id).unwrap();
+    world = player.world;
+    slot = player.inventory.selected();
+    item = player.inventory.take_selected();
+    position = player.position;
+    forward = player.forward();
+  }
+
+  item.map(|item| {
+    item::spawn(server, world, kind, item, Some(player_id), position + forward, forward * speed);
+    send_inventory_slots(server, player_id, vec!(slot));
+  });
+}
+
+/// Tell a player's client about some of their inventory slots.
+pub fn send_inventory_slots(
+  server: &server::T,
+  player_id: entity::id::Player,
+  slots: Vec<usize>,
+) {
+  if slots.is_empty() {
+    return
+  }
+
+  let stacks: Vec<_>;
+  {
+    let players = server.players.lock().unwrap();
+    let inventory: &inventory::T = &players.get(&player_id).unwrap().inventory;
+    stacks = slots.into_iter().map(|slot| (slot, inventory.slot(slot))).collect();
+  }
+
+  let mut clients = server.clients.lock().unwrap();
+  for (_, client) in clients.iter_mut() {
+    if client.player != Some(player_id) {
+      continue
+    }
+    for &(slot, stack) in &stacks {
+      client.send(protocol::ServerToClient::InventorySlot { slot: slot as u32, stack: stack });
+    }
+  }
+}
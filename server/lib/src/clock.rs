@@ -0,0 +1,56 @@
+//! Where the simulation gets the time from.
+//! Normally that's the wall clock. In deterministic mode it's a logical clock, which only moves when the world is
+//! stepped, so a run never depends on how fast the machine is or when things happened to arrive.
+
+use std::sync::Mutex;
+use time;
+
+#[allow(missing_docs)]
+pub enum T {
+  Wall,
+  /// Nanoseconds of simulated time.
+  Logical(Mutex<u64>),
+}
+
+/// A logical clock starting at zero if `deterministic`, otherwise the wall clock.
+pub fn new(deterministic: bool) -> T {
+  if deterministic {
+    T::Logical(Mutex::new(0))
+  } else {
+    T::Wall
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn now_ns(&self) -> u64 {
+    match *self {
+      T::Wall => time::precise_time_ns(),
+      T::Logical(ref now) => *now.lock().unwrap(),
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_logical(&self) -> bool {
+    match *self {
+      T::Wall => false,
+      T::Logical(_) => true,
+    }
+  }
+
+  /// Let `ns` pass on a logical clock. The wall clock keeps its own time, so this does nothing to it.
+  pub fn step(&self, ns: u64) {
+    if let T::Logical(ref now) = *self {
+      *now.lock().unwrap() += ns;
+    }
+  }
+}
+
+#[test]
+fn logical_time_only_passes_in_steps() {
+  let clock = new(true);
+  assert_eq!(clock.now_ns(), 0);
+  clock.step(16_000_000);
+  clock.step(16_000_000);
+  assert_eq!(clock.now_ns(), 32_000_000);
+}
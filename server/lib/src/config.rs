@@ -0,0 +1,97 @@
+//! Server settings, read from a TOML file at startup.
+
+use std;
+use std::io::Read;
+use toml;
+
+use mob;
+use terrain;
+
+/// Where the settings are read from, relative to the working directory.
+pub const PATH: &'static str = "server.toml";
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct T {
+  /// The world generated from a seed is always the same.
+  pub seed            : u32,
+  /// Simulate in lockstep on a logical clock, so the same inputs always lead to the same world. The world only moves
+  /// when admins `step` it, and what clients send is applied just before the next step.
+  pub deterministic   : bool,
+  /// How caves are carved out of generated terrain.
+  pub caves           : terrain::carver::Params,
+  /// Which structures are built on generated terrain, and how often.
+  pub structures      : terrain::structures::Params,
+  /// An image to shape the terrain with, instead of generating it.
+  pub heightmap       : terrain::heightmap::Params,
+  /// Which mobs spawn where, and how many.
+  pub spawning        : mob::spawning::Params,
+  /// Where to serve metrics over HTTP, in the Prometheus text format, e.g. "127.0.0.1:9100". Metrics aren't
+  /// served if this is left out.
+  pub metrics_address : Option<String>,
+  /// A password admin connections have to send as their first line. Without one, admin commands are only taken
+  /// over loopback addresses.
+  pub admin_secret    : Option<String>,
+  /// How many of their brush edits each player can undo. Leaving this out allows `history::DEFAULT_LENGTH`, and 0
+  /// turns undo off.
+  pub undo_history    : Option<usize>,
+}
+
+fn parse(contents: &str) -> Result<T, toml::de::Error> {
+  toml::from_str(contents)
+}
+
+/// Read the settings file. Anything missing from it takes its default value.
+/// If the file is missing or broken, all the defaults are used.
+pub fn load() -> T {
+  let mut contents = String::new();
+  if let Err(err) = std::fs::File::open(PATH).and_then(|mut file| file.read_to_string(&mut contents)) {
+    info!("Couldn't read {}: {}. Using the default settings.", PATH, err);
+    return T::default()
+  }
+
+  match parse(&contents) {
+    Ok(config) => config,
+    Err(err) => {
+      warn!("Couldn't parse {}: {}. Using the default settings.", PATH, err);
+      T::default()
+    },
+  }
+}
+
+/// Pull a `--seed=<n>` flag out of a list of command-line arguments, if there is one.
+/// It overrides the seed in the settings file.
+pub fn seed_from_args(args: &mut Vec<String>) -> Option<u32> {
+  const FLAG: &'static str = "--seed=";
+  let mut seed = None;
+  args.retain(|arg| {
+    if arg.starts_with(FLAG) {
+      let n = &arg[FLAG.len() ..];
+      seed = Some(n.parse().unwrap_or_else(|_| panic!("Seed must be a number, not {:?}", n)));
+      false
+    } else {
+      true
+    }
+  });
+  seed
+}
+
+#[test]
+fn missing_settings_are_defaulted() {
+  let config = parse("[caves]\nthreshold = 0.9\n").unwrap();
+  assert_eq!(config.caves.threshold, 0.9);
+  assert!(config.caves.enabled);
+  assert_eq!(config.caves.octaves, terrain::carver::Params::default().octaves);
+  assert!(config.heightmap.path.is_none());
+  assert_eq!(config.seed, 0);
+  assert!(!config.deterministic);
+}
+
+#[test]
+fn seed_flag_is_removed_from_args() {
+  let mut args = vec!("a".to_owned(), "--seed=42".to_owned());
+  assert_eq!(seed_from_args(&mut args), Some(42));
+  assert_eq!(args, vec!("a".to_owned()));
+  assert_eq!(seed_from_args(&mut args), None);
+}
@@ -0,0 +1,220 @@
+//! A TCP socket that takes console commands, one per line, and writes back their output. It only listens on
+//! loopback addresses, unless the server has an `admin_secret`, which connections then have to send first.
+
+use std;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use console;
+
+/// How long to wait between checks for new connections and input.
+const POLL_INTERVAL_MS: u64 = 50;
+/// Connections that send a longer line than this are dropped.
+const MAX_LINE_BYTES: usize = 4 << 10;
+/// Connections that leave more replies than this unread are dropped.
+const MAX_UNSENT_BYTES: usize = 1 << 20;
+
+/// Where to listen, given an address or just a port, which means the loopback address.
+fn socket_address(address: &str) -> String {
+  match address.parse::<u16>() {
+    Ok(port) => format!("127.0.0.1:{}", port),
+    Err(_) => address.to_owned(),
+  }
+}
+
+/// Compare a line to the secret, in time that doesn't depend on how much of it matches.
+fn is_secret(line: &str, secret: &str) -> bool {
+  let (line, secret) = (line.trim().as_bytes(), secret.as_bytes());
+  line.len() == secret.len() && line.iter().zip(secret.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+struct Connection {
+  stream     : TcpStream,
+  /// Input that hasn't made up a whole line yet.
+  pending    : Vec<u8>,
+  /// Replies that haven't been written yet, because the socket wasn't ready for them. Commands' replies are added from
+  /// wherever they run, and written out here.
+  unsent     : Arc<Mutex<Vec<u8>>>,
+  /// Whether the connection has sent the secret, or doesn't need to.
+  authorized : bool,
+}
+
+/// A newly accepted connection, which is authorized if there's no secret to send.
+fn connection(stream: TcpStream, authorized: bool) -> Connection {
+  Connection {
+    stream     : stream,
+    pending    : Vec::new(),
+    unsent     : Arc::new(Mutex::new(Vec::new())),
+    authorized : authorized,
+  }
+}
+
+impl Connection {
+  /// Queue up any complete lines, and write out as many replies as the socket will take. Returns false once the
+  /// connection is closed, or should be.
+  fn poll(&mut self, secret: Option<&str>, queue: &console::Queue) -> bool {
+    let mut buffer = [0; 1 << 10];
+    loop {
+      match self.stream.read(&mut buffer) {
+        Ok(0) => return false,
+        Ok(n) => {
+          self.pending.extend_from_slice(&buffer[.. n]);
+          // Leave the rest for later, once the lines so far have been taken out.
+          if self.pending.len() > MAX_LINE_BYTES {
+            break
+          }
+        },
+        Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+        Err(err) => {
+          warn!("Error reading from admin connection: {:?}", err);
+          return false
+        },
+      }
+    }
+
+    while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+      if end > MAX_LINE_BYTES {
+        break
+      }
+      let line: Vec<u8> = self.pending.drain(.. end + 1).collect();
+      let line = String::from_utf8_lossy(&line).into_owned();
+      if !self.authorized {
+        self.authorized = secret.map_or(true, |secret| is_secret(&line, secret));
+        if !self.authorized {
+          reply(&self.unsent, "Wrong secret");
+          self.flush();
+          warn!("Closing an admin connection that sent the wrong secret");
+          return false
+        }
+        continue
+      }
+      let unsent = self.unsent.clone();
+      console::submit(queue, &line, Box::new(move |output| reply(&unsent, &output)));
+    }
+    if self.pending.len() > MAX_LINE_BYTES {
+      warn!("Closing an admin connection that sent a line longer than {} bytes", MAX_LINE_BYTES);
+      return false
+    }
+
+    self.flush()
+  }
+
+  /// Write out as many replies as the socket will take without blocking. Returns false if the connection should be
+  /// closed.
+  fn flush(&mut self) -> bool {
+    let mut unsent = self.unsent.lock().unwrap();
+    while !unsent.is_empty() {
+      match self.stream.write(&unsent) {
+        Ok(0) => return false,
+        Ok(n) => { unsent.drain(.. n); },
+        Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+        Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {},
+        Err(err) => {
+          warn!("Error replying to admin connection: {:?}", err);
+          return false
+        },
+      }
+    }
+    if unsent.len() > MAX_UNSENT_BYTES {
+      warn!("Closing an admin connection that left more than {} bytes of replies unread", MAX_UNSENT_BYTES);
+      return false
+    }
+    true
+  }
+}
+
+/// Add a line to a connection's unsent replies.
+fn reply(unsent: &Mutex<Vec<u8>>, output: &str) {
+  let mut unsent = unsent.lock().unwrap();
+  unsent.extend_from_slice(output.as_bytes());
+  unsent.push(b'\n');
+}
+
+/// Accept admin connections on `address` (or on loopback, if it's just a port) until `quit_signal` is set. If there's
+/// a `secret`, connections have to send it before any commands.
+pub fn listen(address: &str, secret: Option<&str>, queue: &console::Queue, quit_signal: &Mutex<bool>) {
+  let address = socket_address(address);
+  let listener =
+    match TcpListener::bind(&address) {
+      Ok(listener) => listener,
+      Err(err) => {
+        warn!("Couldn't listen for admin connections on {}: {:?}", address, err);
+        return
+      },
+    };
+  let loopback = listener.local_addr().map(|address| address.ip().is_loopback()).unwrap_or(false);
+  if !loopback && secret.is_none() {
+    warn!("Not taking admin commands on {}, which isn't a loopback address, without an admin_secret.", address);
+    return
+  }
+  listener.set_nonblocking(true).unwrap();
+  info!("Listening for admin connections on {}.", address);
+
+  let mut connections = Vec::new();
+  while !*quit_signal.lock().unwrap() {
+    loop {
+      match listener.accept() {
+        Ok((stream, peer)) => {
+          info!("Admin connection from {}", peer);
+          stream.set_nonblocking(true).unwrap();
+          connections.push(connection(stream, secret.is_none()));
+        },
+        Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+        Err(err) => {
+          warn!("Error accepting admin connection: {:?}", err);
+          break
+        },
+      }
+    }
+
+    connections =
+      connections.into_iter()
+      .filter_map(|mut connection| if connection.poll(secret, queue) { Some(connection) } else { None })
+      .collect();
+    std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+  }
+}
+
+#[test]
+fn ports_alone_mean_loopback() {
+  assert_eq!(socket_address("7777"), "127.0.0.1:7777");
+  assert_eq!(socket_address("0.0.0.0:7777"), "0.0.0.0:7777");
+}
+
+#[test]
+fn secrets_must_match_exactly() {
+  assert!(is_secret("hunter2\n", "hunter2"));
+  assert!(!is_secret("hunter", "hunter2"));
+  assert!(!is_secret("hunter3", "hunter2"));
+}
+
+#[cfg(test)]
+fn connected() -> (Connection, TcpStream) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+  let (stream, _) = listener.accept().unwrap();
+  stream.set_nonblocking(true).unwrap();
+  (connection(stream, true), client)
+}
+
+#[test]
+fn long_lines_close_the_connection() {
+  let queue = console::new_queue();
+  let (mut connection, mut client) = connected();
+  client.write_all(&vec!(b'x'; MAX_LINE_BYTES + 1)).unwrap();
+  std::thread::sleep(std::time::Duration::from_millis(100));
+  assert!(!connection.poll(None, &queue));
+}
+
+#[test]
+fn replies_are_written_when_polled() {
+  let queue = console::new_queue();
+  let (mut connection, mut client) = connected();
+  reply(&connection.unsent, "done");
+  assert!(connection.poll(None, &queue));
+  assert!(connection.unsent.lock().unwrap().is_empty());
+  let mut received = [0; 5];
+  client.read_exact(&mut received).unwrap();
+  assert_eq!(&received, b"done\n");
+}
@@ -0,0 +1,481 @@
+//! Admin commands, typed into the server's stdin or sent over the admin socket.
+//! Commands are queued and run on the gaia thread, alongside terrain updates.
+
+pub mod admin;
+
+use cgmath::{Point3, Vector3};
+use collision::Aabb3;
+use std;
+use std::sync::Mutex;
+
+use common::color::Color3;
+use common::protocol;
+use common::voxel;
+
+use entity;
+use explosion;
+use server;
+use teleport;
+use world;
+
+/// lg of the width of a chunk, in voxels. This matches the client's chunks.
+const LG_CHUNK_WIDTH: i32 = 3;
+/// The coarsest voxels that clients load, which `regenerate` has to replace too.
+const MAX_LG_SIZE: i16 = 3;
+
+const HELP: &'static str = "\
+Commands:
+  help                      show this message
+  stats                     list clients and players, and show what the server is busy with and how long ticks take
+  kick <client> [reason]    disconnect a client and remove its player, telling it why if a reason is given
+  teleport <player> <x> <y> <z> [world]
+                            move a player, to another world if one is named (overworld or caves)
+  teleport <player> <point> move a player to a named teleport point
+  point <name> <x> <y> <z> [world]
+                            set a named teleport point, which players can go to; the one named spawn is where
+                            players join and respawn
+  unpoint <name>            take a teleport point out
+  points                    list the teleport points
+  time <fraction>           set the time of day, as a fraction of a day in [0, 1)
+  season <fraction>         skip to some time of year, as a fraction of a year in [0, 1) starting with spring
+  regenerate <x> <y> <z> [world]
+                            throw away changes to a chunk and generate it again
+  light <x> <y> <z> <r> <g> <b> <radius> [world]
+                            place a point light, with a color in [0, 1] (or brighter) that fades out by the radius
+  unlight <light>           take a point light out
+  explode <x> <y> <z> <radius> [world]
+                            blow a hole in the terrain, knocking back anything nearby
+  step [n]                  (deterministic mode only) simulate the next n steps, or one
+  hash                      show a hash of the world's state, to compare deterministic runs by
+  quit                      (stdin only) shut down the server";
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  Help,
+  Stats,
+  /// Kick a client, with an optional reason to show it.
+  Kick(protocol::ClientId, Option<String>),
+  Teleport(entity::id::Player, Point3<f32>),
+  MoveToWorld(entity::id::Player, protocol::WorldId, Point3<f32>),
+  TeleportToPoint(entity::id::Player, String),
+  SetPoint(String, teleport::Point),
+  RemovePoint(String),
+  ListPoints,
+  SetTimeOfDay(f32),
+  SetSeason(f32),
+  /// Regenerate the chunk at these chunk coordinates.
+  Regenerate(protocol::WorldId, Point3<i32>),
+  AddLight(protocol::WorldId, protocol::Light),
+  RemoveLight(protocol::LightId),
+  Explode(protocol::WorldId, Point3<f32>, f32),
+  /// Simulate some steps, in deterministic mode.
+  Step(u64),
+  Hash,
+}
+
+/// A command, and where to send its output.
+pub struct Request {
+  #[allow(missing_docs)]
+  pub command : Command,
+  #[allow(missing_docs)]
+  pub reply   : Box<dyn FnMut(String) + Send>,
+}
+
+/// Requests waiting to be run.
+pub type Queue = Mutex<std::collections::VecDeque<Request>>;
+
+#[allow(missing_docs)]
+pub fn new_queue() -> Queue {
+  Mutex::new(std::collections::VecDeque::new())
+}
+
+fn number<N: std::str::FromStr>(word: &str) -> Result<N, String> {
+  word.parse().map_err(|_| format!("Not a number: {:?}", word))
+}
+
+fn world_named(word: &str) -> Result<protocol::WorldId, String> {
+  world::parse(word).ok_or_else(|| format!("No world named {:?}", word))
+}
+
+fn light(x: &str, y: &str, z: &str, r: &str, g: &str, b: &str, radius: &str) -> Result<protocol::Light, String> {
+  let radius: f32 = number(radius)?;
+  if radius <= 0.0 {
+    return Err(format!("A light's radius should be positive, not {}", radius))
+  }
+  Ok(protocol::Light {
+    position : Point3::new(number(x)?, number(y)?, number(z)?),
+    color    : Color3::of_rgb(number(r)?, number(g)?, number(b)?),
+    radius   : radius,
+  })
+}
+
+fn explode(world: protocol::WorldId, x: &str, y: &str, z: &str, radius: &str) -> Result<Command, String> {
+  let radius: f32 = number(radius)?;
+  if !(0.0 < radius && radius <= explosion::MAX_RADIUS) {
+    return Err(format!("An explosion's radius should be more than 0 and at most {}, not {}", explosion::MAX_RADIUS, radius))
+  }
+  Ok(Command::Explode(world, Point3::new(number(x)?, number(y)?, number(z)?), radius))
+}
+
+/// Parse a line of input into a command.
+pub fn parse(line: &str) -> Result<Command, String> {
+  let words: Vec<&str> = line.split_whitespace().collect();
+  // A kick's reason is the rest of the line.
+  if words.len() > 2 && words[0] == "kick" {
+    return Ok(Command::Kick(protocol::ClientId::default() + number(words[1])?, Some(words[2 ..].join(" "))))
+  }
+  match words.as_slice() {
+    ["help"] => Ok(Command::Help),
+    ["stats"] => Ok(Command::Stats),
+    ["kick", client] =>
+      Ok(Command::Kick(protocol::ClientId::default() + number(client)?, None)),
+    ["teleport", player, x, y, z] =>
+      Ok(Command::Teleport(
+        entity::id::Player::default() + number(player)?,
+        Point3::new(number(x)?, number(y)?, number(z)?),
+      )),
+    ["teleport", player, x, y, z, world] =>
+      Ok(Command::MoveToWorld(
+        entity::id::Player::default() + number(player)?,
+        world_named(world)?,
+        Point3::new(number(x)?, number(y)?, number(z)?),
+      )),
+    ["teleport", player, point] =>
+      Ok(Command::TeleportToPoint(entity::id::Player::default() + number(player)?, String::from(*point))),
+    ["point", name, x, y, z] =>
+      Ok(Command::SetPoint(
+        String::from(*name),
+        teleport::Point { world: world::OVERWORLD, position: Point3::new(number(x)?, number(y)?, number(z)?) },
+      )),
+    ["point", name, x, y, z, world] =>
+      Ok(Command::SetPoint(
+        String::from(*name),
+        teleport::Point { world: world_named(world)?, position: Point3::new(number(x)?, number(y)?, number(z)?) },
+      )),
+    ["unpoint", name] => Ok(Command::RemovePoint(String::from(*name))),
+    ["points"] => Ok(Command::ListPoints),
+    ["time", fraction] => {
+      let fraction: f32 = number(fraction)?;
+      if 0.0 <= fraction && fraction < 1.0 {
+        Ok(Command::SetTimeOfDay(fraction))
+      } else {
+        Err(format!("The time of day should be in [0, 1), not {}", fraction))
+      }
+    },
+    ["season", fraction] => {
+      let fraction: f32 = number(fraction)?;
+      if 0.0 <= fraction && fraction < 1.0 {
+        Ok(Command::SetSeason(fraction))
+      } else {
+        Err(format!("The season should be in [0, 1), not {}", fraction))
+      }
+    },
+    ["regenerate", x, y, z] =>
+      Ok(Command::Regenerate(world::OVERWORLD, Point3::new(number(x)?, number(y)?, number(z)?))),
+    ["regenerate", x, y, z, world] =>
+      Ok(Command::Regenerate(world_named(world)?, Point3::new(number(x)?, number(y)?, number(z)?))),
+    ["light", x, y, z, r, g, b, radius] =>
+      Ok(Command::AddLight(world::OVERWORLD, light(x, y, z, r, g, b, radius)?)),
+    ["light", x, y, z, r, g, b, radius, world] =>
+      Ok(Command::AddLight(world_named(world)?, light(x, y, z, r, g, b, radius)?)),
+    ["unlight", id] =>
+      Ok(Command::RemoveLight(protocol::LightId::default() + number(id)?)),
+    ["explode", x, y, z, radius] => explode(world::OVERWORLD, x, y, z, radius),
+    ["explode", x, y, z, radius, world] => explode(world_named(world)?, x, y, z, radius),
+    ["step"] => Ok(Command::Step(1)),
+    ["step", n] => Ok(Command::Step(number(n)?)),
+    ["hash"] => Ok(Command::Hash),
+    _ => Err(format!("Unrecognized command: {:?}. Try \"help\".", line.trim())),
+  }
+}
+
+/// Parse a line and queue it up to be run. Parse errors are sent straight to `reply`.
+pub fn submit(queue: &Queue, line: &str, mut reply: Box<dyn FnMut(String) + Send>) {
+  if line.trim().is_empty() {
+    return
+  }
+  match parse(line) {
+    Ok(command) => {
+      queue.lock().unwrap().push_back(Request { command: command, reply: reply });
+    },
+    Err(err) => reply(err),
+  }
+}
+
+#[allow(missing_docs)]
+pub fn apply(server: &server::T, request: Request) {
+  let Request { command, mut reply } = request;
+  info!("Console command: {:?}", command);
+  let output =
+    match command {
+      Command::Help => HELP.to_owned(),
+      Command::Stats => stats(server),
+      Command::Kick(client_id, reason) => kick(server, client_id, reason),
+      Command::Teleport(player_id, position) => teleport(server, current_player(server, player_id), None, position),
+      Command::MoveToWorld(player_id, world, position) =>
+        teleport(server, current_player(server, player_id), Some(world), position),
+      Command::TeleportToPoint(player_id, name) => {
+        let point = server.teleports.lock().unwrap().get(&name);
+        match point {
+          None => format!("No teleport point called {}", name),
+          Some(point) => teleport(server, current_player(server, player_id), Some(point.world), point.position),
+        }
+      },
+      Command::SetPoint(name, point) => {
+        server.teleports.lock().unwrap().set(&name, point);
+        format!("Set {} to {:?} in the {}", name, point.position, world::name(point.world))
+      },
+      Command::RemovePoint(name) => {
+        match server.teleports.lock().unwrap().remove(&name) {
+          None => format!("No teleport point called {}", name),
+          Some(_) => format!("Took out {}", name),
+        }
+      },
+      Command::ListPoints => list_points(server),
+      Command::SetTimeOfDay(fraction) => {
+        server.sun.lock().unwrap().set_time_of_day(fraction);
+        format!("Set the time of day to {}", fraction)
+      },
+      Command::SetSeason(fraction) => {
+        server.sun.lock().unwrap().set_season(fraction);
+        format!("Set the season to {}", fraction)
+      },
+      Command::Regenerate(world, chunk) => regenerate(server, world, chunk),
+      Command::AddLight(world, light) => add_light(server, world, light),
+      Command::RemoveLight(id) => remove_light(server, id),
+      Command::Explode(world, center, radius) => {
+        explosion::explode(server, world, center, radius, true);
+        format!("Set off an explosion at {:?} in the {}", center, world::name(world))
+      },
+      Command::Step(steps) => step(server, steps),
+      Command::Hash => format!("{:016x}", server.state_hash()),
+    };
+  reply(output);
+}
+
+fn stats(server: &server::T) -> String {
+  let mut lines = Vec::new();
+  {
+    let clients = server.clients.lock().unwrap();
+    let players = server.players.lock().unwrap();
+    lines.push(format!("{} clients, {} players, {} mobs", clients.len(), players.len(), server.mobs.lock().unwrap().len()));
+    for (client_id, client) in clients.iter() {
+      let player =
+        client.player
+        .and_then(|id| players.get(&id).map(|player| (id, player.world, player.position)))
+        .map_or_else(
+          || String::from("no player"),
+          |(id, w, p)| format!("player {} at ({:.1}, {:.1}, {:.1}) in the {}", id.to_u32(), p.x, p.y, p.z, world::name(w)),
+        );
+      lines.push(format!("  {:?}: {}", client_id, player));
+    }
+  }
+  lines.push(format!("{} outstanding voxel requests", server.voxel_requests.len()));
+
+  let clock = server.tick.lock().unwrap();
+  let tick = clock.stats();
+  lines.push(format!(
+    "ticks take {:.2}ms on average of their {:.2}ms step, {:.2}ms at most lately; {} steps dropped lately",
+    tick.average_ms, tick.step_ms, tick.max_ms, tick.dropped,
+  ));
+  for (name, ms) in clock.systems_ms() {
+    lines.push(format!("  {}: {:.2}ms", name, ms));
+  }
+  lines.join("\n")
+}
+
+fn kick(server: &server::T, client_id: protocol::ClientId, reason: Option<String>) -> String {
+  let mut client =
+    match server.clients.lock().unwrap().remove(&client_id) {
+      None => return format!("No client {:?}", client_id),
+      Some(client) => client,
+    };
+  client.send(protocol::ServerToClient::Kicked(reason));
+
+  if let Some(player_id) = client.player {
+    if let Some(player) = server.players.lock().unwrap().remove(&player_id) {
+      server.world(player.world).physics.lock().unwrap().remove_misc(player.physics_id);
+    }
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::PlayerLeft(player_id));
+    }
+  }
+
+  format!("Kicked {:?}", client_id)
+}
+
+/// Admins name players by number, which means whichever player has that number now, whatever its generation.
+fn current_player(server: &server::T, player_id: entity::id::Player) -> entity::id::Player {
+  server.players.lock().unwrap().current(player_id.to_u32()).unwrap_or(player_id)
+}
+
+/// Move a player, to another world if one's given.
+fn teleport(
+  server    : &server::T,
+  player_id : entity::id::Player,
+  world     : Option<protocol::WorldId>,
+  position  : Point3<f32>,
+) -> String {
+  let world =
+    match (world, server.players.lock().unwrap().get(&player_id)) {
+      (_, None) => return format!("No player {}", player_id.to_u32()),
+      (Some(world), Some(_)) => world,
+      (None, Some(player)) => player.world,
+    };
+  teleport::teleport(server, player_id, teleport::Point { world: world, position: position });
+  format!("Moved player {} to {:?} in the {}", player_id.to_u32(), position, world::name(world))
+}
+
+fn list_points(server: &server::T) -> String {
+  let teleports = server.teleports.lock().unwrap();
+  let lines: Vec<String> =
+    teleports.iter()
+    .map(|(name, point)| format!("{}: {:?} in the {}", name, point.position, world::name(point.world)))
+    .collect();
+  if lines.is_empty() {
+    String::from("No teleport points")
+  } else {
+    lines.join("\n")
+  }
+}
+
+fn regenerate(server: &server::T, world_id: protocol::WorldId, chunk: Point3<i32>) -> String {
+  let world = server.world(world_id);
+  let chunk_width = 1 << LG_CHUNK_WIDTH;
+  let low = Point3::new(chunk.x * chunk_width, chunk.y * chunk_width, chunk.z * chunk_width);
+  world.mob_navigation.lock().unwrap().invalidate(&Aabb3::new(low, low + Vector3::new(chunk_width, chunk_width, chunk_width)));
+
+  let mut updates = Vec::new();
+  for lg_size in 0 .. MAX_LG_SIZE + 1 {
+    let width = chunk_width >> lg_size;
+    for x in 0 .. width {
+    for y in 0 .. width {
+    for z in 0 .. width {
+      let bounds =
+        voxel::bounds::new(
+          (low.x >> lg_size) + x,
+          (low.y >> lg_size) + y,
+          (low.z >> lg_size) + z,
+          lg_size,
+        );
+      let voxel = world.terrain_loader.terrain.regenerate(&bounds);
+      updates.push((bounds, voxel));
+    }}}
+  }
+
+  {
+    let mut store = world.terrain_loader.store.lock().unwrap();
+    for &(bounds, voxel) in &updates {
+      store.insert(&bounds, voxel);
+    }
+  }
+  let versions = world.journal.lock().unwrap().record(&updates);
+
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send_voxels(world_id, &updates, &versions);
+  }
+
+  format!("Regenerated {} voxels in chunk {:?}", updates.len(), chunk)
+}
+
+/// Send a message to every client whose player is in `world`.
+fn send_to_world(server: &server::T, world: protocol::WorldId, msg: protocol::ServerToClient) {
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    if client.player.is_some() && client.interest.world() == world {
+      client.send(msg.clone());
+    }
+  }
+}
+
+fn add_light(server: &server::T, world: protocol::WorldId, light: protocol::Light) -> String {
+  let id;
+  {
+    let mut lights = server.world(world).lights.lock().unwrap();
+    if lights.len() >= protocol::MAX_LIGHTS {
+      return format!("The {} already has {} lights, which is as many as it can", world::name(world), lights.len())
+    }
+    id = server.light_allocator.lock().unwrap().allocate();
+    lights.insert(id, light);
+  }
+  send_to_world(server, world, protocol::ServerToClient::AddLight(id, light));
+  format!("Placed light {} at {:?} in the {}", id.to_u32(), light.position, world::name(world))
+}
+
+fn remove_light(server: &server::T, id: protocol::LightId) -> String {
+  for world in &server.worlds {
+    if world.lights.lock().unwrap().remove(&id).is_some() {
+      send_to_world(server, world.id, protocol::ServerToClient::RemoveLight(id));
+      return format!("Took out light {}", id.to_u32())
+    }
+  }
+  format!("No light {}", id.to_u32())
+}
+
+fn step(server: &server::T, steps: u64) -> String {
+  if !server.clock.is_logical() {
+    return "The world steps by itself unless the server's deterministic (see server.toml)".to_owned()
+  }
+  server.tick.lock().unwrap().queue(steps);
+  format!("Queued {} steps", steps)
+}
+
+#[test]
+fn parse_commands() {
+  assert_eq!(parse("stats\n"), Ok(Command::Stats));
+  assert_eq!(parse("  kick 3"), Ok(Command::Kick(protocol::ClientId::default() + 3, None)));
+  assert_eq!(
+    parse("kick 3 no  griefing"),
+    Ok(Command::Kick(protocol::ClientId::default() + 3, Some(String::from("no griefing")))),
+  );
+  assert_eq!(
+    parse("teleport 1 0 64.5 -2"),
+    Ok(Command::Teleport(entity::id::Player::default() + 1, Point3::new(0.0, 64.5, -2.0))),
+  );
+  assert_eq!(parse("regenerate -1 2 0"), Ok(Command::Regenerate(world::OVERWORLD, Point3::new(-1, 2, 0))));
+  assert_eq!(
+    parse("teleport 1 0 -20 0 caves"),
+    Ok(Command::MoveToWorld(entity::id::Player::default() + 1, world::CAVES, Point3::new(0.0, -20.0, 0.0))),
+  );
+  assert_eq!(
+    parse("light 1 2 3 1 0.5 0 8 caves"),
+    Ok(Command::AddLight(
+      world::CAVES,
+      protocol::Light {
+        position : Point3::new(1.0, 2.0, 3.0),
+        color    : Color3::of_rgb(1.0, 0.5, 0.0),
+        radius   : 8.0,
+      },
+    )),
+  );
+  assert_eq!(
+    parse("teleport 2 spawn"),
+    Ok(Command::TeleportToPoint(entity::id::Player::default() + 2, String::from("spawn"))),
+  );
+  assert_eq!(
+    parse("point camp 1 -40 2 caves"),
+    Ok(Command::SetPoint(
+      String::from("camp"),
+      teleport::Point { world: world::CAVES, position: Point3::new(1.0, -40.0, 2.0) },
+    )),
+  );
+  assert_eq!(parse("unlight 4"), Ok(Command::RemoveLight(protocol::LightId::default() + 4)));
+  assert_eq!(parse("explode 0 10.5 -3 4 caves"), Ok(Command::Explode(world::CAVES, Point3::new(0.0, 10.5, -3.0), 4.0)));
+  assert!(parse("explode 0 0 0 0").is_err());
+  assert_eq!(parse("season 0.75"), Ok(Command::SetSeason(0.75)));
+  assert_eq!(parse("step"), Ok(Command::Step(1)));
+  assert_eq!(parse("step 60"), Ok(Command::Step(60)));
+  assert_eq!(parse("hash"), Ok(Command::Hash));
+}
+
+#[test]
+fn parse_errors() {
+  assert!(parse("kick").is_err());
+  assert!(parse("kick me").is_err());
+  assert!(parse("time 1.5").is_err());
+  assert!(parse("season -0.5").is_err());
+  assert!(parse("light 0 0 0 1 1 1 -2").is_err());
+  assert!(parse("teleport 1 0 0 0 nether").is_err());
+  assert!(parse("step -1").is_err());
+  assert!(parse("dance").is_err());
+}
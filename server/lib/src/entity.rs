@@ -1,4 +1,7 @@
-mod types {
+#[allow(missing_docs)]
+pub mod types {
+  pub use common::entity::types::*;
+
   #[allow(missing_docs)]
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
   pub struct Terrain;
@@ -0,0 +1,94 @@
+//! Explosions: a sphere of terrain blown out, and everything near it thrown clear.
+//! Whatever the blast leaves hanging falls as debris, like after any other edit.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use common::movement;
+use common::protocol;
+use common::voxel;
+
+use brush;
+use client_recv_thread::materials_in_sphere;
+use server;
+use update_gaia;
+
+/// The biggest explosion scripts and admins can set off.
+pub const MAX_RADIUS: f32 = 16.0;
+/// How far from the center things are knocked back, as a multiple of the radius.
+const REACH: f32 = 2.0;
+/// How fast things right at the center are knocked back, in units per update per unit of radius.
+const KNOCKBACK: f32 = 0.25;
+/// How long knocked-back mobs stop steering for, in updates.
+const STUN_TICKS: u32 = movement::UPDATES_PER_SECOND as u32 / 2;
+
+/// The speed an explosion gives something at `position`: away from the center, and less the further out it is.
+pub fn impulse(center: &Point3<f32>, radius: f32, position: &Point3<f32>) -> Vector3<f32> {
+  let reach = radius * REACH;
+  let away = position - center;
+  let distance = away.magnitude();
+  if distance >= reach {
+    return Vector3::new(0.0, 0.0, 0.0)
+  }
+  let away = if distance > 0.0 { away / distance } else { Vector3::new(0.0, 1.0, 0.0) };
+  // Everything's thrown upward some, so it clears the ground instead of scraping along it.
+  let direction = (away + Vector3::new(0.0, 0.5, 0.0)).normalize();
+  direction * KNOCKBACK * radius * (1.0 - distance / reach)
+}
+
+/// Set off an explosion, which blows out a sphere of terrain if `breaks_terrain`.
+pub fn explode(
+  server         : &server::T,
+  world          : protocol::WorldId,
+  center         : Point3<f32>,
+  radius         : f32,
+  breaks_terrain : bool,
+) {
+  let mut material = None;
+  if breaks_terrain {
+    let blown = materials_in_sphere(server, world, &center, radius);
+    material = blown.iter().max_by_key(|&&(_, voxel_count)| voxel_count).map(|&(material, _)| material);
+    let brush = brush::sphere(&center, radius, voxel::Material::Empty);
+    update_gaia::update_gaia(server, update_gaia::Message::Brush(world, brush, None));
+    server.world(world).grass.lock().unwrap().remove(&center, radius);
+  }
+
+  for mob in server.mobs.lock().unwrap().values_mut().filter(|mob| mob.world == world) {
+    let push = impulse(&center, radius, &mob.position);
+    if push != Vector3::new(0.0, 0.0, 0.0) {
+      mob.speed += push;
+      mob.ai.stun(STUN_TICKS);
+    }
+  }
+  for player in server.players.lock().unwrap().values_mut().filter(|player| player.world == world) {
+    player.movement.speed += impulse(&center, radius, &player.position);
+  }
+  for item in server.items.lock().unwrap().values_mut().filter(|item| item.world == world) {
+    let push = impulse(&center, radius, &item.body.position);
+    if push != Vector3::new(0.0, 0.0, 0.0) {
+      item.body.velocity += push;
+      item.body.at_rest = false;
+    }
+  }
+
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    if client.interest.sees(world, &center) {
+      if breaks_terrain {
+        client.send(protocol::ServerToClient::RemoveGrass { center: center, radius: radius });
+      }
+      client.send(protocol::ServerToClient::Exploded { position: center, radius: radius, material: material });
+    }
+  }
+}
+
+#[test]
+fn knockback_falls_off() {
+  let center = Point3::new(0.0, 10.0, 0.0);
+  let near = impulse(&center, 2.0, &Point3::new(1.0, 10.0, 0.0));
+  let far = impulse(&center, 2.0, &Point3::new(3.0, 10.0, 0.0));
+  assert!(near.x > far.x && far.x > 0.0);
+  assert!(near.y > 0.0);
+  assert_eq!(impulse(&center, 2.0, &Point3::new(0.0, 10.0, -4.0)), Vector3::new(0.0, 0.0, 0.0));
+  // Something right at the center goes straight up.
+  let up = impulse(&center, 2.0, &center);
+  assert!(up.x == 0.0 && up.z == 0.0 && up.y > 0.0);
+}
@@ -0,0 +1,98 @@
+//! Grass, as far as the server cares about it: which tufts have been trampled lately, per chunk.
+//! Clients grow and draw the grass themselves. Tufts are only told apart by the voxel they grow in, which is all
+//! the server and clients need to agree on.
+
+use cgmath::{Point3, InnerSpace};
+
+use common::fnv_map;
+use common::voxel;
+
+use terrain;
+
+/// lg of the width of a chunk, in voxels. This matches the client's chunks.
+const LG_CHUNK_WIDTH: i32 = 3;
+/// How long a trampled tuft takes to stand back up, in ns. Clients spring it back over the same time.
+const SPRING_BACK_NS: u64 = 3_000_000_000;
+
+/// The voxel a tuft of grass under a point grows in.
+pub fn cell(p: &Point3<f32>) -> Point3<i32> {
+  Point3::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32)
+}
+
+fn chunk(cell: &Point3<i32>) -> Point3<i32> {
+  Point3::new(cell.x >> LG_CHUNK_WIDTH, cell.y >> LG_CHUNK_WIDTH, cell.z >> LG_CHUNK_WIDTH)
+}
+
+/// Whether grass can grow in a voxel: it's the surface of some soil, somewhere grassy.
+pub fn grows_in(terrain: &terrain::T, cell: &Point3<i32>) -> bool {
+  let material =
+    match terrain.load(&voxel::bounds::new(cell.x, cell.y, cell.z, 0)) {
+      voxel::Surface(voxel) => voxel.corner,
+      voxel::Volume(_) => return false,
+    };
+  material == voxel::Material::Terrain && terrain.grass_density(cell.x as f32, cell.z as f32) > 0.0
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// When each trampled tuft was last trampled, by chunk.
+  chunks : fnv_map::T<Point3<i32>, fnv_map::T<Point3<i32>, u64>>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    chunks : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Trample the tuft in `cell`. Returns whether it was standing up, i.e. whether this should rustle.
+  pub fn trample(&mut self, cell: Point3<i32>, now: u64) -> bool {
+    let trampled = self.chunks.entry(chunk(&cell)).or_insert_with(fnv_map::new);
+    match trampled.insert(cell, now) {
+      None => true,
+      Some(then) => now.saturating_sub(then) >= SPRING_BACK_NS,
+    }
+  }
+
+  /// Forget tufts that have stood back up.
+  pub fn spring_back(&mut self, now: u64) {
+    for trampled in self.chunks.values_mut() {
+      trampled.retain(|_, &mut then| now.saturating_sub(then) < SPRING_BACK_NS);
+    }
+    self.chunks.retain(|_, trampled| !trampled.is_empty());
+  }
+
+  /// Forget the tufts growing in voxels whose centers are within `radius` of `center`, e.g. because they were dug
+  /// out.
+  pub fn remove(&mut self, center: &Point3<f32>, radius: f32) {
+    for trampled in self.chunks.values_mut() {
+      trampled.retain(|cell, _| {
+        let voxel_center = Point3::new(cell.x as f32 + 0.5, cell.y as f32 + 0.5, cell.z as f32 + 0.5);
+        (voxel_center - *center).magnitude2() > radius * radius
+      });
+    }
+    self.chunks.retain(|_, trampled| !trampled.is_empty());
+  }
+}
+
+#[test]
+fn trampled_grass_springs_back() {
+  let mut grass = new();
+  let a = Point3::new(3, 10, -9);
+  let b = Point3::new(-20, 10, 9);
+  assert!(grass.trample(a, 0));
+  assert!(grass.trample(b, SPRING_BACK_NS / 2));
+  // Still flat, so walking through again doesn't rustle.
+  assert!(!grass.trample(a, SPRING_BACK_NS / 4));
+
+  grass.spring_back(SPRING_BACK_NS / 4 + SPRING_BACK_NS);
+  assert!(grass.trample(a, SPRING_BACK_NS / 4 + SPRING_BACK_NS));
+  assert!(!grass.trample(b, SPRING_BACK_NS / 4 + SPRING_BACK_NS));
+
+  // Digging `b` out forgets it was trampled.
+  grass.remove(&Point3::new(-19.5, 10.5, 9.5), 2.0);
+  assert!(grass.trample(b, SPRING_BACK_NS / 4 + SPRING_BACK_NS));
+  assert!(!grass.trample(a, SPRING_BACK_NS / 4 + SPRING_BACK_NS));
+}
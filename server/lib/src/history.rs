@@ -0,0 +1,112 @@
+//! Each player's recent brush edits, so they can be undone and redone. An edit is kept as the voxels it changed,
+//! before and after, so the edit that undoes it is just the same voxels set back the way they were. Undoing puts
+//! them back over anything that's been done to them since.
+
+use collision::Aabb3;
+use std::collections::VecDeque;
+
+use common::protocol;
+use common::voxel;
+
+/// How many edits each player can undo, unless the config says otherwise.
+pub const DEFAULT_LENGTH: usize = 32;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct Edit {
+  pub world  : protocol::WorldId,
+  /// Every voxel the edit changed is in here.
+  pub bounds : Aabb3<i32>,
+  /// The voxels the edit changed, as they were before it.
+  pub before : Vec<(voxel::bounds::T, voxel::T)>,
+  /// The same voxels, as the edit left them.
+  pub after  : Vec<(voxel::bounds::T, voxel::T)>,
+}
+
+impl Edit {
+  /// The edit that undoes this one.
+  pub fn inverse(self) -> Edit {
+    Edit {
+      world  : self.world,
+      bounds : self.bounds,
+      before : self.after,
+      after  : self.before,
+    }
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Oldest first.
+  done   : VecDeque<Edit>,
+  /// Most recently undone last.
+  undone : Vec<Edit>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    done   : VecDeque::new(),
+    undone : Vec::new(),
+  }
+}
+
+impl T {
+  /// Remember a new edit, forgetting the oldest ones past `max_length`. Edits that were undone can't be redone
+  /// after this.
+  pub fn push(&mut self, edit: Edit, max_length: usize) {
+    self.undone.clear();
+    self.done.push_back(edit);
+    while self.done.len() > max_length {
+      self.done.pop_front();
+    }
+  }
+
+  /// Take back the most recent edit. Returns the edit that undoes it.
+  pub fn undo(&mut self) -> Option<Edit> {
+    self.done.pop_back().map(|edit| {
+      self.undone.push(edit.clone());
+      edit.inverse()
+    })
+  }
+
+  /// Make the most recently undone edit again.
+  pub fn redo(&mut self) -> Option<Edit> {
+    self.undone.pop().map(|edit| {
+      self.done.push_back(edit.clone());
+      edit
+    })
+  }
+}
+
+#[test]
+fn undo_then_redo() {
+  use cgmath::Point3;
+
+  let at = voxel::bounds::new(0, 0, 0, 0);
+  let edit = |from, to| {
+    Edit {
+      world  : protocol::WorldId(0),
+      bounds : Aabb3::new(Point3::new(0, 0, 0), Point3::new(1, 1, 1)),
+      before : vec!((at, voxel::Volume(from))),
+      after  : vec!((at, voxel::Volume(to))),
+    }
+  };
+  let dig = edit(voxel::Material::Stone, voxel::Material::Empty);
+  let fill = edit(voxel::Material::Empty, voxel::Material::Terrain);
+
+  let mut history = new();
+  assert!(history.undo().is_none());
+  history.push(dig, 1);
+  history.push(fill, 1);
+  // Only the fill is remembered, and undoing it empties the voxel again.
+  assert_eq!(history.undo().unwrap().after, vec!((at, voxel::Volume(voxel::Material::Empty))));
+  assert!(history.undo().is_none());
+  assert_eq!(history.redo().unwrap().after, vec!((at, voxel::Volume(voxel::Material::Terrain))));
+  assert!(history.redo().is_none());
+
+  // A new edit can't be redone past.
+  history.undo();
+  history.push(edit(voxel::Material::Empty, voxel::Material::Stone), 1);
+  assert!(history.redo().is_none());
+}
@@ -0,0 +1,163 @@
+//! What each client cares about: the part of the world around its player. Entity and voxel changes out of a
+//! client's range, or in other worlds, aren't sent to it.
+
+use cgmath::Point3;
+
+use common::fnv_set;
+use common::protocol;
+use common::snapshot;
+use common::voxel;
+
+use world;
+
+/// Entities further than this from a client's player, along any axis, aren't sent to it.
+const ENTITY_RANGE: f32 = 256.0;
+/// Voxel changes further than this from a client's player, along any axis, aren't sent to it right away.
+/// Clients keep terrain loaded much further out than they can see entities, so this is larger.
+const VOXEL_RANGE: f32 = 1024.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The world the client's player is in.
+  world    : protocol::WorldId,
+  /// Where the client's player was last seen. Until it's been seen, everything is in range.
+  center   : Option<Point3<f32>>,
+  /// Voxels that changed while they were out of range. Clients may still have the old voxels cached, so these
+  /// are sent once they come back into range.
+  withheld : fnv_set::T<voxel::bounds::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    world    : world::OVERWORLD,
+    center   : None,
+    withheld : fnv_set::new(),
+  }
+}
+
+fn within(center: &Point3<f32>, point: &Point3<f32>, range: f32) -> bool {
+  (point.x - center.x).abs() <= range &&
+  (point.y - center.y).abs() <= range &&
+  (point.z - center.z).abs() <= range
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn world(&self) -> protocol::WorldId {
+    self.world
+  }
+
+  /// The client's player moved to another world. The client throws away what it knew about the old one, so
+  /// nothing needs to be withheld anymore.
+  pub fn enter(&mut self, world: protocol::WorldId, center: Point3<f32>) {
+    self.world = world;
+    self.center = Some(center);
+    self.withheld.clear();
+  }
+
+  /// The client's player is now at `center`.
+  pub fn move_to(&mut self, center: Point3<f32>) {
+    self.center = Some(center);
+  }
+
+  /// Whether an entity at some position in some world is in range.
+  pub fn sees(&self, world: protocol::WorldId, position: &Point3<f32>) -> bool {
+    world == self.world && self.center.map_or(true, |center| within(&center, position, ENTITY_RANGE))
+  }
+
+  fn sees_position(&self, position: &Point3<f32>) -> bool {
+    self.center.map_or(true, |center| within(&center, position, ENTITY_RANGE))
+  }
+
+  fn sees_voxel(&self, bounds: &voxel::bounds::T) -> bool {
+    self.center.map_or(true, |center| within(&center, &bounds.center(), VOXEL_RANGE))
+  }
+
+  /// The entities in range, out of all those in the client's world.
+  pub fn filter(&self, world: &snapshot::World) -> snapshot::World {
+    let mut filtered = snapshot::empty();
+    filtered.time_ns = world.time_ns;
+    filtered.players.extend(
+      world.players.iter()
+      .filter(|&(_, player)| self.sees_position(&player.bounds.min))
+      .map(|(&id, player)| (id, player.clone()))
+    );
+    filtered.mobs.extend(
+      world.mobs.iter()
+      .filter(|&(_, mob)| self.sees_position(&mob.bounds.min))
+      .map(|(&id, mob)| (id, mob.clone()))
+    );
+    filtered.items.extend(
+      world.items.iter()
+      .filter(|&(_, item)| self.sees_position(&item.position))
+      .map(|(&id, item)| (id, item.clone()))
+    );
+    filtered
+  }
+
+  /// Some voxels changed. Returns the ones to send now; the rest are remembered until they're in range.
+  pub fn voxels_updated(&mut self, updates: &[(voxel::bounds::T, voxel::T)]) -> Vec<(voxel::bounds::T, voxel::T)> {
+    let mut in_range = Vec::new();
+    for &(bounds, voxel) in updates {
+      if self.sees_voxel(&bounds) {
+        self.withheld.remove(&bounds);
+        in_range.push((bounds, voxel));
+      } else {
+        self.withheld.insert(bounds);
+      }
+    }
+    in_range
+  }
+
+  /// Forget, and return, the withheld voxels that are now in range.
+  pub fn take_withheld_in_range(&mut self) -> Vec<voxel::bounds::T> {
+    if self.withheld.is_empty() {
+      return Vec::new()
+    }
+    let in_range: Vec<_> = self.withheld.iter().filter(|bounds| self.sees_voxel(bounds)).cloned().collect();
+    for bounds in &in_range {
+      self.withheld.remove(bounds);
+    }
+    in_range
+  }
+}
+
+#[test]
+fn far_away_changes_wait_until_theyre_in_range() {
+  let mut interest = new();
+  let near = voxel::bounds::new(0, 0, 0, 0);
+  let far = voxel::bounds::new(4096, 0, 0, 0);
+  let updates = [(near, voxel::Volume(voxel::Material::Stone)), (far, voxel::Volume(voxel::Material::Stone))];
+
+  // Until the player has been seen, everything is in range.
+  assert_eq!(interest.voxels_updated(&updates).len(), 2);
+  assert!(interest.sees(world::OVERWORLD, &Point3::new(1e6, 0.0, 0.0)));
+
+  interest.move_to(Point3::new(0.0, 0.0, 0.0));
+  let sent = interest.voxels_updated(&updates);
+  assert_eq!(sent.len(), 1);
+  assert_eq!(sent[0].0, near);
+  assert!(interest.take_withheld_in_range().is_empty());
+  assert!(!interest.sees(world::OVERWORLD, &Point3::new(4096.0, 0.0, 0.0)));
+
+  interest.move_to(Point3::new(4000.0, 0.0, 0.0));
+  assert_eq!(interest.take_withheld_in_range(), vec!(far));
+  assert!(interest.take_withheld_in_range().is_empty());
+}
+
+#[test]
+fn other_worlds_are_out_of_range() {
+  let mut interest = new();
+  let here = Point3::new(0.0, 0.0, 0.0);
+  interest.move_to(here);
+  assert!(!interest.sees(world::CAVES, &here));
+
+  // Overworld voxels withheld from far away don't get sent once the player is near them in the caves.
+  interest.voxels_updated(&[(voxel::bounds::new(4096, 0, 0, 0), voxel::Volume(voxel::Material::Stone))]);
+  let there = Point3::new(4000.0, 0.0, 0.0);
+  interest.enter(world::CAVES, there);
+  assert!(interest.sees(world::CAVES, &there));
+  assert!(!interest.sees(world::OVERWORLD, &there));
+  assert!(interest.take_withheld_in_range().is_empty());
+}
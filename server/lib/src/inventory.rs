@@ -0,0 +1,227 @@
+//! What a player is carrying.
+
+use std;
+
+use common::item;
+use common::protocol;
+
+/// The most of one item that fits in a slot.
+pub const MAX_STACK: u32 = 64;
+
+#[allow(missing_docs)]
+pub struct T {
+  slots    : [Option<protocol::ItemStack>; protocol::INVENTORY_SLOTS],
+  selected : usize,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    slots    : [None; protocol::INVENTORY_SLOTS],
+    selected : 0,
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn slot(&self, slot: usize) -> Option<protocol::ItemStack> {
+    self.slots[slot]
+  }
+
+  #[allow(missing_docs)]
+  pub fn selected(&self) -> usize {
+    self.selected
+  }
+
+  /// The item in the selected slot, if there's any.
+  pub fn selected_item(&self) -> Option<item::Id> {
+    self.slots[self.selected].map(|stack| stack.item)
+  }
+
+  /// How many of an item there are, across every slot.
+  pub fn count(&self, item: item::Id) -> u32 {
+    self.slots.iter()
+      .filter_map(|slot| *slot)
+      .filter(|stack| stack.item == item)
+      .map(|stack| stack.count)
+      .sum()
+  }
+
+  /// How many more of an item fit.
+  fn room_for(&self, item: item::Id) -> u32 {
+    self.slots.iter()
+      .map(|slot| {
+        match *slot {
+          None => MAX_STACK,
+          Some(stack) if stack.item == item => MAX_STACK - stack.count,
+          Some(_) => 0,
+        }
+      })
+      .sum()
+  }
+
+  /// Choose the slot that `take_selected` takes from. Out-of-range slots are ignored.
+  pub fn select(&mut self, slot: usize) {
+    if slot < self.slots.len() {
+      self.selected = slot;
+    }
+  }
+
+  /// Add some of an item, topping up existing stacks first. Whatever doesn't fit is dropped.
+  /// Returns the indices of the slots that changed.
+  pub fn add(&mut self, item: item::Id, mut count: u32) -> Vec<usize> {
+    let mut changed = Vec::new();
+
+    for (i, slot) in self.slots.iter_mut().enumerate() {
+      if count == 0 {
+        break
+      }
+      if let Some(ref mut stack) = *slot {
+        if stack.item == item && stack.count < MAX_STACK {
+          let n = std::cmp::min(count, MAX_STACK - stack.count);
+          stack.count += n;
+          count -= n;
+          changed.push(i);
+        }
+      }
+    }
+
+    for (i, slot) in self.slots.iter_mut().enumerate() {
+      if count == 0 {
+        break
+      }
+      if slot.is_none() {
+        let n = std::cmp::min(count, MAX_STACK);
+        *slot = Some(protocol::ItemStack { item: item, count: n });
+        count -= n;
+        changed.push(i);
+      }
+    }
+
+    changed
+  }
+
+  /// Take one of whatever's in the selected slot. Returns the item taken, if any.
+  pub fn take_selected(&mut self) -> Option<item::Id> {
+    let slot = &mut self.slots[self.selected];
+    let item =
+      match *slot {
+        None => return None,
+        Some(ref mut stack) => {
+          stack.count -= 1;
+          stack.item
+        },
+      };
+    if slot.map_or(false, |stack| stack.count == 0) {
+      *slot = None;
+    }
+    Some(item)
+  }
+
+  /// Take some of an item, emptying the last stacks first. There should be at least `count` of it.
+  /// Returns the indices of the slots that changed.
+  fn remove(&mut self, item: item::Id, mut count: u32) -> Vec<usize> {
+    let mut changed = Vec::new();
+    for (i, slot) in self.slots.iter_mut().enumerate().rev() {
+      if count == 0 {
+        break
+      }
+      let left =
+        match *slot {
+          Some(ref mut stack) if stack.item == item => {
+            let n = std::cmp::min(count, stack.count);
+            stack.count -= n;
+            count -= n;
+            changed.push(i);
+            stack.count
+          },
+          _ => continue,
+        };
+      if left == 0 {
+        *slot = None;
+      }
+    }
+    changed
+  }
+
+  /// Follow a recipe: take its inputs and add its output. If the recipe can't be followed, nothing changes.
+  /// Returns the indices of the slots that changed.
+  pub fn craft(&mut self, recipe: &item::Recipe) -> Result<Vec<usize>, item::CraftError> {
+    if recipe.inputs.iter().any(|input| self.count(input.item) < input.count) {
+      return Err(item::CraftError::MissingInputs)
+    }
+
+    let mut after =
+      T {
+        slots    : self.slots,
+        selected : self.selected,
+      };
+    let mut changed = Vec::new();
+    for input in recipe.inputs {
+      changed.extend(after.remove(input.item, input.count));
+    }
+    if after.room_for(recipe.output.item) < recipe.output.count {
+      return Err(item::CraftError::NoRoom)
+    }
+    changed.extend(after.add(recipe.output.item, recipe.output.count));
+
+    *self = after;
+    changed.sort();
+    changed.dedup();
+    Ok(changed)
+  }
+}
+
+#[test]
+fn add_fills_existing_stacks_first() {
+  let mut inventory = new();
+  assert_eq!(inventory.add(item::Id::Stone, MAX_STACK - 1), vec!(0));
+  assert_eq!(inventory.add(item::Id::Sand, 1), vec!(1));
+  assert_eq!(inventory.add(item::Id::Stone, 3), vec!(0, 2));
+  assert_eq!(inventory.slot(0).unwrap().count, MAX_STACK);
+  assert_eq!(inventory.slot(2).unwrap().count, 2);
+}
+
+#[test]
+fn add_drops_overflow() {
+  let mut inventory = new();
+  let capacity = MAX_STACK * protocol::INVENTORY_SLOTS as u32;
+  assert_eq!(inventory.add(item::Id::Stone, capacity + 10).len(), protocol::INVENTORY_SLOTS);
+  assert!(inventory.add(item::Id::Stone, 1).is_empty());
+}
+
+#[test]
+fn take_selected_empties_slot() {
+  let mut inventory = new();
+  inventory.add(item::Id::Snow, 1);
+  inventory.add(item::Id::Sand, 2);
+  assert_eq!(inventory.take_selected(), Some(item::Id::Snow));
+  assert_eq!(inventory.slot(0), None);
+  assert_eq!(inventory.take_selected(), None);
+  inventory.select(1);
+  assert_eq!(inventory.take_selected(), Some(item::Id::Sand));
+  assert_eq!(inventory.slot(1).unwrap().count, 1);
+}
+
+#[test]
+fn craft_takes_inputs_or_nothing() {
+  let recipe = item::recipes().iter().find(|recipe| recipe.output.item == item::Id::Spear).unwrap();
+  let mut inventory = new();
+  inventory.add(item::Id::Stone, 1);
+  inventory.add(item::Id::Sticks, 1);
+  assert_eq!(inventory.craft(recipe), Err(item::CraftError::MissingInputs));
+  assert_eq!(inventory.count(item::Id::Stone), 1);
+
+  inventory.add(item::Id::Sticks, 2);
+  assert_eq!(inventory.craft(recipe), Ok(vec!(0, 1)));
+  assert_eq!(inventory.slot(0).unwrap(), protocol::ItemStack { item: item::Id::Spear, count: 1 });
+  assert_eq!(inventory.count(item::Id::Sticks), 1);
+
+  // Leftover stone and sticks keep their slots, so the spear has nowhere to go.
+  let mut full = new();
+  full.add(item::Id::Dirt, MAX_STACK * (protocol::INVENTORY_SLOTS as u32 - 2));
+  full.add(item::Id::Stone, 2);
+  full.add(item::Id::Sticks, 3);
+  assert_eq!(full.craft(recipe), Err(item::CraftError::NoRoom));
+  assert_eq!(full.count(item::Id::Sticks), 3);
+}
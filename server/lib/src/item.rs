@@ -0,0 +1,335 @@
+//! Dropped items, thrown projectiles and falling debris: points that fall, bounce off the terrain and come to rest.
+//! Thrown items with a blast radius explode when they hit something.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use common;
+use common::fnv_set;
+use common::material;
+use common::movement;
+use common::protocol;
+use common::raycast;
+use common::voxel;
+
+use brush;
+use client_recv_thread::send_inventory_slots;
+use entity;
+use explosion;
+use mob;
+use plugin;
+use server;
+use update_gaia;
+
+/// Downward acceleration, in units per update per update. This matches players'.
+const GRAVITY: f32 = 0.1;
+/// Bodies sliding slower than this after bouncing off the ground stop, as long as they wouldn't rise much.
+const REST_SPEED: f32 = 0.05;
+/// The portion of along-surface speed kept through a bounce.
+const FRICTION: f32 = 0.6;
+/// Bodies are kept this far off surfaces, so they don't start the next update inside the terrain.
+const SKIN: f32 = 0.01;
+/// How many surfaces a body can bounce off in one update.
+const MAX_BOUNCES: usize = 4;
+
+/// Items disappear after this many updates.
+const LIFETIME: u32 = 5 * 60 * movement::UPDATES_PER_SECOND as u32;
+/// Dropped items can't be picked up for this many updates, so whoever dropped them doesn't grab them right back.
+const PICKUP_DELAY: u32 = movement::UPDATES_PER_SECOND as u32;
+/// How close a player's center has to be to an item to pick it up.
+const PICKUP_RADIUS: f32 = 2.0;
+/// Items that fall this far have fallen out of the world.
+const MIN_HEIGHT: f32 = -512.0;
+/// How far above where it landed debris can be piled up; any more is lost.
+const MAX_PILE: u32 = 8;
+
+/// A point with some velocity, which collides with the voxel field.
+#[derive(Debug, Clone, Copy)]
+pub struct Body {
+  pub position    : Point3<f32>,
+  /// In units per update.
+  pub velocity    : Vector3<f32>,
+  /// The portion of into-surface speed a bounce sends back out.
+  pub restitution : f32,
+  /// Resting bodies don't move until the ground under them goes away.
+  pub at_rest     : bool,
+}
+
+/// Reflect the part of `v` going into a surface, and slow the part going along it.
+fn bounce(v: Vector3<f32>, normal: Vector3<f32>, restitution: f32) -> Vector3<f32> {
+  let into = normal * v.dot(normal);
+  let along = v - into;
+  along * FRICTION - into * restitution
+}
+
+impl Body {
+  /// Move the body through one update, bouncing it off whatever `is_solid`. Returns whether it hit anything.
+  pub fn step<IsSolid>(&mut self, mut is_solid: IsSolid) -> bool where
+    IsSolid: FnMut(&voxel::bounds::T) -> bool,
+  {
+    if self.at_rest {
+      let below = self.position + Vector3::new(0.0, -2.0 * SKIN, 0.0);
+      if is_solid(&voxel::bounds::new(below.x.floor() as i32, below.y.floor() as i32, below.z.floor() as i32, 0)) {
+        return false
+      }
+      self.at_rest = false;
+    }
+
+    self.velocity.y -= GRAVITY;
+
+    let mut hit_anything = false;
+    let mut remaining = self.velocity;
+    for _ in 0 .. MAX_BOUNCES {
+      let distance = remaining.magnitude();
+      if distance == 0.0 {
+        break
+      }
+
+      let hit =
+        match raycast::cast(&self.position, &remaining, distance, &mut is_solid) {
+          None => {
+            self.position += remaining;
+            break
+          },
+          Some(hit) => hit,
+        };
+      hit_anything = true;
+
+      if hit.normal == Vector3::new(0, 0, 0) {
+        // This started inside the terrain, e.g. because it was thrown into a wall. Pop it out the top.
+        self.position.y = (hit.bounds.y + 1) as f32 + SKIN;
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+        break
+      }
+
+      let normal = Vector3::new(hit.normal.x as f32, hit.normal.y as f32, hit.normal.z as f32);
+      self.position += remaining * (hit.distance / distance) + normal * SKIN;
+      remaining = bounce(remaining * (1.0 - hit.distance / distance), normal, self.restitution);
+      self.velocity = bounce(self.velocity, normal, self.restitution);
+
+      let rebound = self.velocity.dot(normal);
+      let slide = (self.velocity - normal * rebound).magnitude();
+      if normal.y > 0.0 && rebound < GRAVITY && slide < REST_SPEED {
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+        self.at_rest = true;
+        break
+      }
+    }
+
+    hit_anything
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub world     : protocol::WorldId,
+  pub entity_id : entity::id::Item,
+  pub kind      : protocol::EntityKind,
+  pub item      : common::item::Id,
+  /// Whoever threw this, if anyone.
+  pub thrower   : Option<entity::id::Player>,
+  pub body      : Body,
+  /// How many updates this has existed for.
+  age           : u32,
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn state(&self) -> protocol::ItemState {
+    protocol::ItemState {
+      id       : self.entity_id,
+      kind     : self.kind,
+      item     : self.item,
+      position : self.body.position,
+    }
+  }
+}
+
+/// Add an item to the world.
+pub fn spawn(
+  server   : &server::T,
+  world    : protocol::WorldId,
+  kind     : protocol::EntityKind,
+  item     : common::item::Id,
+  thrower  : Option<entity::id::Player>,
+  position : Point3<f32>,
+  velocity : Vector3<f32>,
+) -> entity::id::Item {
+  let restitution =
+    match kind {
+      protocol::EntityKind::DroppedItem => 0.3,
+      protocol::EntityKind::Projectile  => 0.2,
+      protocol::EntityKind::Debris      => 0.1,
+    };
+  let mut items = server.items.lock().unwrap();
+  let id = items.allocate();
+  items.insert(
+    id,
+    T {
+      world     : world,
+      entity_id : id,
+      kind      : kind,
+      item      : item,
+      thrower   : thrower,
+      body      :
+        Body {
+          position    : position,
+          velocity    : velocity,
+          restitution : restitution,
+          at_rest     : false,
+        },
+      age       : 0,
+    },
+  );
+  id
+}
+
+/// Whether a point is inside a mob's bounding box.
+fn hits(mob: &mob::Mob, p: &Point3<f32>) -> bool {
+  let d = p - mob.position;
+  d.x.abs() <= mob::SIZE.x / 2.0 && d.y.abs() <= mob::SIZE.y / 2.0 && d.z.abs() <= mob::SIZE.z / 2.0
+}
+
+/// Move every item, hurt the mobs projectiles hit, set off the bombs, and let players pick up the items they're close
+/// to. Debris that's come to rest is turned back into terrain, through `request_block`.
+pub fn update<RequestBlock>(server: &server::T, request_block: &mut RequestBlock) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  let is_solid = |world: protocol::WorldId, bounds: &voxel::bounds::T| {
+    let material =
+      match server.world(world).terrain_loader.terrain.load(bounds) {
+        voxel::Volume(material) => material,
+        voxel::Surface(voxel) => voxel.corner,
+      };
+    material::get(material).solid
+  };
+
+  let mut picked_up = Vec::new();
+  let mut landed = Vec::new();
+  let mut blasts = Vec::new();
+  {
+    // Mobs lock players, so lock mobs first.
+    let mut mobs = server.mobs.lock().unwrap();
+    let mut players = server.players.lock().unwrap();
+    let mut items = server.items.lock().unwrap();
+    items.retain(|_, item| {
+      item.age += 1;
+      let world = item.world;
+      let hit_terrain = item.body.step(|bounds| is_solid(world, bounds));
+      if item.kind == protocol::EntityKind::Projectile {
+        let position = item.body.position;
+        let mut hit_mob = false;
+        if let Some(mob) = mobs.values_mut().find(|mob| mob.world == world && hits(mob, &position)) {
+          mob.health.damage(common::item::get(item.item).damage);
+          hit_mob = true;
+          // Projectiles drop where they hit.
+          item.body.velocity = Vector3::new(0.0, 0.0, 0.0);
+          item.kind = protocol::EntityKind::DroppedItem;
+        }
+        let blast = common::item::get(item.item).blast;
+        if blast > 0.0 && (hit_terrain || hit_mob) {
+          blasts.push((world, position, blast, item.thrower));
+          return false
+        }
+      }
+      if item.kind == protocol::EntityKind::Projectile && item.body.at_rest {
+        item.kind = protocol::EntityKind::DroppedItem;
+      }
+      if item.kind == protocol::EntityKind::Debris && item.body.at_rest {
+        landed.push((item.world, item.body.position, item.item));
+        return false
+      }
+
+      if item.age > LIFETIME || item.body.position.y < MIN_HEIGHT {
+        return false
+      }
+      if item.kind != protocol::EntityKind::DroppedItem || item.age < PICKUP_DELAY {
+        return true
+      }
+
+      for player in players.values_mut().filter(|player| player.world == item.world) {
+        if (player.position - item.body.position).magnitude2() > PICKUP_RADIUS * PICKUP_RADIUS {
+          continue
+        }
+        let slots = player.inventory.add(item.item, 1);
+        if !slots.is_empty() {
+          picked_up.push((player.entity_id, slots));
+          return false
+        }
+      }
+      true
+    });
+  }
+
+  for (player_id, slots) in picked_up {
+    send_inventory_slots(server, player_id, slots);
+  }
+
+  for (world, center, radius, thrower) in blasts {
+    let edit =
+      plugin::VoxelEdit {
+        world  : world,
+        bounds : brush::sphere(&center, radius, voxel::Material::Empty).bounds,
+        player : thrower,
+      };
+    // Where the terrain's protected, bombs still go off, but don't leave a crater.
+    let breaks_terrain = server.plugins.allows_edit(server, &edit);
+    explosion::explode(server, world, center, radius, breaks_terrain);
+  }
+
+  // Debris that lands in the same place piles up.
+  let mut filled = fnv_set::new();
+  for (world, position, item) in landed {
+    let material =
+      match common::item::get(item).places {
+        None => continue,
+        Some(material) => material,
+      };
+    let mut cell =
+      voxel::bounds::new(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32, 0);
+    let mut height = 0;
+    while filled.contains(&(world, cell)) || is_solid(world, &cell) {
+      cell.y += 1;
+      height += 1;
+      if height > MAX_PILE {
+        break
+      }
+    }
+    if height > MAX_PILE {
+      continue
+    }
+    filled.insert((world, cell));
+    let center = Point3::new(cell.x as f32 + 0.5, cell.y as f32 + 0.5, cell.z as f32 + 0.5);
+    request_block(update_gaia::Message::Brush(world, brush::cube(&center, 0.5, material), None));
+  }
+}
+
+#[test]
+fn bodies_bounce_and_come_to_rest() {
+  let mut body =
+    Body {
+      position    : Point3::new(0.5, 4.0, 0.5),
+      velocity    : Vector3::new(0.2, 0.0, 0.0),
+      restitution : 0.5,
+      at_rest     : false,
+    };
+  let is_solid = |bounds: &voxel::bounds::T| bounds.y < 0;
+
+  let mut bounced = false;
+  for _ in 0 .. 100 {
+    let falling = body.velocity.y < 0.0;
+    if body.step(is_solid) && falling && body.velocity.y > 0.0 {
+      bounced = true;
+    }
+    assert!(body.position.y > 0.0);
+  }
+  assert!(bounced);
+  assert!(body.at_rest);
+
+  // Resting bodies stay put, until the ground is dug out from under them.
+  let rested_at = body.position;
+  assert!(!body.step(is_solid));
+  assert_eq!(body.position, rested_at);
+  body.step(|bounds: &voxel::bounds::T| bounds.y < -4);
+  assert!(!body.at_rest);
+  assert!(body.position.y < rested_at.y);
+}
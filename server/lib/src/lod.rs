@@ -47,6 +47,14 @@ impl Map {
     }
   }
 
+  /// Every position an owner has loaded.
+  pub fn owned_by(&self, owner: OwnerId) -> Vec<voxel::bounds::T> {
+    self.loaded.iter()
+      .filter(|&(_, bls)| bls.owner_lods.iter().any(|&(o, _)| o == owner))
+      .map(|(&position, _)| position)
+      .collect()
+  }
+
   /// Find out what T is up at a `position`.
   pub fn get<'a>(
     &'a self,
@@ -0,0 +1,227 @@
+//! Counters for what the server's sending and receiving, and how long chunks take to generate, served over HTTP
+//! in the Prometheus text format so dashboards can scrape them.
+
+use std;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+
+use common::fnv_map;
+
+use server;
+
+/// How long to wait between checks for new connections.
+const POLL_INTERVAL_MS: u64 = 100;
+/// Give up on scrapers that haven't sent their request by now.
+const READ_TIMEOUT_MS: u64 = 1000;
+
+/// The upper bounds of the chunk timing histogram buckets, in seconds.
+static LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counter {
+  messages : u64,
+  bytes    : u64,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+  /// How many samples fell in each of `LATENCY_BUCKETS`, or past the last one.
+  counts : [u64; 11],
+  /// The total of every sample, in seconds.
+  sum    : f64,
+}
+
+fn histogram() -> Histogram {
+  Histogram {
+    counts : [0; 11],
+    sum    : 0.0,
+  }
+}
+
+impl Histogram {
+  fn observe(&mut self, seconds: f64) {
+    let bucket = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound).unwrap_or(LATENCY_BUCKETS.len());
+    self.counts[bucket] += 1;
+    self.sum += seconds;
+  }
+
+  /// Append the histogram's lines, in the Prometheus text format.
+  fn write(&self, out: &mut String, name: &str, help: &str) {
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} histogram\n", name, help, name));
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(self.counts.iter()) {
+      cumulative += count;
+      out.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative));
+    }
+    cumulative += self.counts[LATENCY_BUCKETS.len()];
+    out.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, cumulative));
+    out.push_str(&format!("{}_sum {}\n{}_count {}\n", name, self.sum, name, cumulative));
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Messages sent to clients, by type.
+  sent             : Mutex<fnv_map::T<&'static str, Counter>>,
+  /// Messages received from clients, by type.
+  received         : Mutex<fnv_map::T<&'static str, Counter>>,
+  /// How long chunk requests take to generate, once a worker picks them up.
+  chunk_generation : Mutex<Histogram>,
+  /// How long chunk requests take from being queued to being sent.
+  chunk_latency    : Mutex<Histogram>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    sent             : Mutex::new(fnv_map::new()),
+    received         : Mutex::new(fnv_map::new()),
+    chunk_generation : Mutex::new(histogram()),
+    chunk_latency    : Mutex::new(histogram()),
+  }
+}
+
+fn count(counters: &Mutex<fnv_map::T<&'static str, Counter>>, name: &'static str, bytes: usize) {
+  let mut counters = counters.lock().unwrap();
+  let counter = counters.entry(name).or_insert_with(Counter::default);
+  counter.messages += 1;
+  counter.bytes += bytes as u64;
+}
+
+fn write_counters(out: &mut String, direction: &str, counters: &Mutex<fnv_map::T<&'static str, Counter>>) {
+  let mut counters: Vec<_> = counters.lock().unwrap().iter().map(|(&name, &counter)| (name, counter)).collect();
+  counters.sort_by_key(|&(name, _)| name);
+
+  let name = format!("playform_messages_{}_total", direction);
+  out.push_str(&format!("# HELP {} Messages {} clients, by type.\n# TYPE {} counter\n", name, direction, name));
+  for &(kind, counter) in &counters {
+    out.push_str(&format!("{}{{type=\"{}\"}} {}\n", name, kind, counter.messages));
+  }
+
+  let name = format!("playform_bytes_{}_total", direction);
+  out.push_str(&format!("# HELP {} Bytes {} clients, by message type.\n# TYPE {} counter\n", name, direction, name));
+  for &(kind, counter) in &counters {
+    out.push_str(&format!("{}{{type=\"{}\"}} {}\n", name, kind, counter.bytes));
+  }
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: usize) {
+  out.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n{} {}\n", name, help, name, name, value));
+}
+
+impl T {
+  /// Count a message sent to a client, `bytes` long once serialized.
+  pub fn sent(&self, name: &'static str, bytes: usize) {
+    count(&self.sent, name, bytes);
+  }
+
+  /// Count a message received from a client, `bytes` long as serialized.
+  pub fn received(&self, name: &'static str, bytes: usize) {
+    count(&self.received, name, bytes);
+  }
+
+  /// Count a served chunk request, which took `generation_ns` to generate and `latency_ns` since it was queued.
+  pub fn chunk_served(&self, generation_ns: u64, latency_ns: u64) {
+    self.chunk_generation.lock().unwrap().observe(generation_ns as f64 / 1e9);
+    self.chunk_latency.lock().unwrap().observe(latency_ns as f64 / 1e9);
+  }
+}
+
+/// Every metric, in the Prometheus text format.
+pub fn render(server: &server::T) -> String {
+  let metrics = &server.metrics;
+  let mut out = String::new();
+  write_counters(&mut out, "sent", &metrics.sent);
+  write_counters(&mut out, "received", &metrics.received);
+  metrics.chunk_generation.lock().unwrap().write(
+    &mut out,
+    "playform_chunk_generation_seconds",
+    "Time spent generating a chunk request.",
+  );
+  metrics.chunk_latency.lock().unwrap().write(
+    &mut out,
+    "playform_chunk_latency_seconds",
+    "Time from a chunk request being queued to its voxels being sent.",
+  );
+
+  let clients = server.clients.lock().unwrap().len();
+  write_gauge(&mut out, "playform_clients", "Connected clients.", clients);
+  let players = server.players.lock().unwrap().len();
+  write_gauge(&mut out, "playform_players", "Players in any world.", players);
+  let mobs = server.mobs.lock().unwrap().len();
+  write_gauge(&mut out, "playform_mobs", "Mobs in any world.", mobs);
+  write_gauge(&mut out, "playform_items", "Dropped and thrown items in any world.", server.items.lock().unwrap().len());
+  write_gauge(&mut out, "playform_voxel_requests_queued", "Chunk requests waiting for a worker.", server.voxel_requests.len());
+  out
+}
+
+/// Answer one scrape. Whatever's asked for, the metrics are sent back.
+fn respond(server: &server::T, mut stream: TcpStream) -> std::io::Result<()> {
+  stream.set_nonblocking(false)?;
+  stream.set_read_timeout(Some(std::time::Duration::from_millis(READ_TIMEOUT_MS)))?;
+  // Read the request up to the end of its headers. Its contents don't matter.
+  let mut request = Vec::new();
+  let mut buffer = [0; 1 << 10];
+  while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+    let n = stream.read(&mut buffer)?;
+    if n == 0 {
+      break
+    }
+    request.extend_from_slice(&buffer[.. n]);
+  }
+
+  let body = render(server);
+  write!(
+    stream,
+    "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    body.len(),
+    body,
+  )
+}
+
+/// Serve the metrics over HTTP on `address` until `quit_signal` is set.
+pub fn serve(address: &str, server: &server::T, quit_signal: &Mutex<bool>) {
+  let listener =
+    match TcpListener::bind(address) {
+      Ok(listener) => listener,
+      Err(err) => {
+        warn!("Couldn't serve metrics on {}: {:?}", address, err);
+        return
+      },
+    };
+  listener.set_nonblocking(true).unwrap();
+  info!("Serving metrics on http://{}/metrics.", address);
+
+  while !*quit_signal.lock().unwrap() {
+    match listener.accept() {
+      Ok((stream, _)) => {
+        if let Err(err) = respond(server, stream) {
+          warn!("Error answering a metrics request: {:?}", err);
+        }
+      },
+      Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+        std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+      },
+      Err(err) => {
+        warn!("Error accepting a metrics connection: {:?}", err);
+        std::thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+      },
+    }
+  }
+}
+
+#[test]
+fn histograms_are_cumulative() {
+  let mut histogram = histogram();
+  histogram.observe(0.002);
+  histogram.observe(0.002);
+  histogram.observe(5.0);
+  let mut out = String::new();
+  histogram.write(&mut out, "h", "A histogram.");
+  assert!(out.contains("h_bucket{le=\"0.001\"} 0\n"));
+  assert!(out.contains("h_bucket{le=\"0.0025\"} 2\n"));
+  assert!(out.contains("h_bucket{le=\"1\"} 2\n"));
+  assert!(out.contains("h_bucket{le=\"+Inf\"} 3\n"));
+  assert!(out.contains("h_count 3\n"));
+}
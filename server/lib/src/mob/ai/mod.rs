@@ -0,0 +1,315 @@
+//! Mob behaviors: deciding what a mob wants to do, and walking it there.
+
+pub mod navigation;
+
+use cgmath::{Point3, Vector3, InnerSpace, MetricSpace};
+use rand::Rng;
+use std;
+
+use common::health;
+use common::protocol;
+
+use entity;
+use mob;
+use server;
+
+/// Players closer than this get a mob's attention.
+const NOTICE_DISTANCE: f32 = 24.0;
+/// Mobs lose interest in players further away than this.
+const FORGET_DISTANCE: f32 = 48.0;
+/// Chasing mobs stop this close to their target, and attack it.
+const CATCH_DISTANCE: f32 = 2.0;
+/// The hit points each attack takes.
+const ATTACK_DAMAGE: u32 = 3;
+/// How many ticks a mob waits between attacks.
+const ATTACK_TICKS: u32 = 30;
+/// How many ticks of each attack are spent swinging.
+const SWING_TICKS: u32 = 10;
+/// How far ahead fleeing mobs plan their escape.
+const FLEE_DISTANCE: f32 = 16.0;
+/// How far from where they are wandering mobs roam.
+const WANDER_DISTANCE: f32 = 16.0;
+/// How close, horizontally, counts as having reached a waypoint.
+const WAYPOINT_DISTANCE: f32 = 0.5;
+
+const WALK_SPEED: f32 = 0.1;
+const RUN_SPEED: f32 = 0.2;
+/// The vertical speed to hop up onto a higher cell with.
+const HOP_SPEED: f32 = 0.7;
+
+/// How many ticks to follow a path before finding a new one, e.g. because the target moved.
+const REPATH_TICKS: u32 = 15;
+const MIN_IDLE_TICKS: u32 = 30;
+const MAX_IDLE_TICKS: u32 = 150;
+
+/// How a mob reacts to players.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Temperament {
+  /// Chase players that come close.
+  Aggressive,
+  /// Run from players that come close.
+  Timid,
+}
+
+/// What a mob is doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Behavior {
+  /// Stand around for some number of ticks.
+  Idle(u32),
+  /// Walk to a destination.
+  Wander(Point3<f32>),
+  /// Run toward a player.
+  Chase(entity::id::Player),
+  /// Run away from a player.
+  Flee(entity::id::Player),
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub temperament : Temperament,
+  pub behavior    : Behavior,
+  /// The direction the mob is facing, as an angle from +x toward +z.
+  pub heading     : f32,
+  pub animation   : protocol::Animation,
+  /// Waypoints to the current target. The next waypoint is last.
+  path            : Vec<Point3<f32>>,
+  /// Ticks until the path is recalculated.
+  repath_in       : u32,
+  /// Ticks until the mob can attack again.
+  attack_in       : u32,
+  /// Ticks until a mob that's been knocked back gets its footing again.
+  stunned_for     : u32,
+}
+
+#[allow(missing_docs)]
+pub fn new(temperament: Temperament) -> T {
+  T {
+    temperament : temperament,
+    behavior    : Behavior::Idle(0),
+    heading     : 0.0,
+    animation   : protocol::Animation::Idle,
+    path        : Vec::new(),
+    repath_in   : 0,
+    attack_in   : 0,
+    stunned_for : 0,
+  }
+}
+
+impl T {
+  /// Stop steering for a while, e.g. so a mob that's been knocked back flies on instead of walking off.
+  pub fn stun(&mut self, ticks: u32) {
+    self.stunned_for = std::cmp::max(self.stunned_for, ticks);
+  }
+
+  fn set_behavior(&mut self, behavior: Behavior) {
+    let same_target =
+      match (self.behavior, behavior) {
+        (Behavior::Idle(_), Behavior::Idle(_)) => true,
+        (old, new) => old == new,
+      };
+    if !same_target {
+      self.path.clear();
+      self.repath_in = 0;
+    }
+    self.behavior = behavior;
+  }
+}
+
+/// Mobs are positioned at their centers, and are 2 tall.
+fn feet(mob: &mob::Mob) -> Point3<f32> {
+  mob.position + Vector3::new(0.0, -1.0, 0.0)
+}
+
+fn horizontal(v: Vector3<f32>) -> Vector3<f32> {
+  Vector3::new(v.x, 0.0, v.z)
+}
+
+/// Where a player is, if it's in the mob's world.
+fn player_position(server: &server::T, mob: &mob::Mob, player: entity::id::Player) -> Option<Point3<f32>> {
+  server.players.lock().unwrap().get(&player)
+    .and_then(|player| if player.world == mob.world { Some(player.position) } else { None })
+}
+
+fn nearest_player(server: &server::T, mob: &mob::Mob) -> Option<(entity::id::Player, f32)> {
+  let position = &mob.position;
+  let players = server.players.lock().unwrap();
+  let mut nearest: Option<(entity::id::Player, f32)> = None;
+  for (&id, player) in players.iter().filter(|&(_, player)| player.world == mob.world) {
+    let distance = player.position.distance(*position);
+    if nearest.map_or(true, |(_, d)| distance < d) {
+      nearest = Some((id, distance));
+    }
+  }
+  nearest
+}
+
+fn idle(server: &server::T) -> Behavior {
+  Behavior::Idle(server.rng.lock().unwrap().gen_range(MIN_IDLE_TICKS, MAX_IDLE_TICKS))
+}
+
+fn wander(server: &server::T, mob: &mob::Mob) -> Behavior {
+  let mut rng = server.rng.lock().unwrap();
+  let dx = rng.gen_range(-WANDER_DISTANCE, WANDER_DISTANCE);
+  let dz = rng.gen_range(-WANDER_DISTANCE, WANDER_DISTANCE);
+  Behavior::Wander(feet(mob) + Vector3::new(dx, 0.0, dz))
+}
+
+fn next_behavior(server: &server::T, mob: &mob::Mob) -> Behavior {
+  match mob.ai.behavior {
+    Behavior::Idle(_) | Behavior::Wander(_) => {
+      if let Some((player, distance)) = nearest_player(server, mob) {
+        if distance < NOTICE_DISTANCE {
+          return
+            match mob.ai.temperament {
+              Temperament::Aggressive => Behavior::Chase(player),
+              Temperament::Timid => Behavior::Flee(player),
+            }
+        }
+      }
+
+      match mob.ai.behavior {
+        Behavior::Idle(0) => wander(server, mob),
+        Behavior::Idle(ticks) => Behavior::Idle(ticks - 1),
+        Behavior::Wander(destination) => {
+          if horizontal(destination - feet(mob)).magnitude() < WAYPOINT_DISTANCE {
+            idle(server)
+          } else {
+            mob.ai.behavior
+          }
+        },
+        _ => unreachable!(),
+      }
+    },
+    Behavior::Chase(player) | Behavior::Flee(player) => {
+      match player_position(server, mob, player) {
+        Some(position) if position.distance(mob.position) < FORGET_DISTANCE => mob.ai.behavior,
+        _ => idle(server),
+      }
+    },
+  }
+}
+
+/// Choose what a mob does this tick, and set its speed to do it.
+pub fn update(server: &server::T, mob: &mut mob::Mob) {
+  if mob.ai.attack_in > 0 {
+    mob.ai.attack_in -= 1;
+  }
+  if mob.ai.stunned_for > 0 {
+    mob.ai.stunned_for -= 1;
+    return
+  }
+
+  let behavior = next_behavior(server, mob);
+  mob.ai.set_behavior(behavior);
+
+  let walking =
+    match mob.ai.behavior {
+      Behavior::Idle(_) => false,
+      Behavior::Wander(destination) => {
+        let walking = walk_toward(server, mob, &destination, WALK_SPEED);
+        if !walking {
+          // Can't get there from here.
+          let behavior = idle(server);
+          mob.ai.set_behavior(behavior);
+        }
+        walking
+      },
+      Behavior::Chase(player) => {
+        match player_position(server, mob, player) {
+          Some(target) if target.distance(mob.position) > CATCH_DISTANCE =>
+            walk_toward(server, mob, &target, RUN_SPEED),
+          Some(_) => {
+            attack(server, mob, player);
+            false
+          },
+          None => false,
+        }
+      },
+      Behavior::Flee(player) => {
+        match player_position(server, mob, player) {
+          None => false,
+          Some(threat) => {
+            let away = horizontal(mob.position - threat);
+            let away =
+              if away.magnitude2() > 0.0 {
+                away.normalize()
+              } else {
+                Vector3::new(1.0, 0.0, 0.0)
+              };
+            let target = feet(mob) + away * FLEE_DISTANCE;
+            walk_toward(server, mob, &target, RUN_SPEED)
+          },
+        }
+      },
+    };
+
+  if !walking {
+    mob.speed.x = 0.0;
+    mob.speed.z = 0.0;
+  }
+
+  let speed = horizontal(mob.speed).magnitude();
+  mob.ai.animation =
+    if mob.ai.attack_in > ATTACK_TICKS - SWING_TICKS {
+      protocol::Animation::Attack
+    } else if speed == 0.0 {
+      protocol::Animation::Idle
+    } else if speed <= WALK_SPEED {
+      protocol::Animation::Walk
+    } else {
+      protocol::Animation::Run
+    };
+}
+
+/// Hit a player that's been caught, if the mob's ready to.
+fn attack(server: &server::T, mob: &mut mob::Mob, player: entity::id::Player) {
+  if mob.ai.attack_in > 0 {
+    return
+  }
+  if let Some(player) = server.players.lock().unwrap().get_mut(&player) {
+    player.hurt(ATTACK_DAMAGE, health::Cause::Mob);
+  }
+  mob.ai.attack_in = ATTACK_TICKS;
+}
+
+/// Follow a path toward `target`. Returns false if there's no way to go.
+fn walk_toward(server: &server::T, mob: &mut mob::Mob, target: &Point3<f32>, speed: f32) -> bool {
+  let feet = feet(mob);
+
+  if mob.ai.repath_in == 0 {
+    let world = server.world(mob.world);
+    mob.ai.path =
+      world.mob_navigation.lock().unwrap()
+      .find_path(&world.terrain_loader.terrain, &feet, target)
+      .unwrap_or_else(Vec::new);
+    mob.ai.repath_in = REPATH_TICKS;
+  } else {
+    mob.ai.repath_in -= 1;
+  }
+
+  while let Some(&waypoint) = mob.ai.path.last() {
+    if horizontal(waypoint - feet).magnitude() < WAYPOINT_DISTANCE {
+      mob.ai.path.pop();
+    } else {
+      break
+    }
+  }
+
+  let waypoint =
+    match mob.ai.path.last() {
+      None => return false,
+      Some(&waypoint) => waypoint,
+    };
+
+  let velocity = horizontal(waypoint - feet).normalize() * speed;
+  mob.speed.x = velocity.x;
+  mob.speed.z = velocity.z;
+  mob.ai.heading = velocity.z.atan2(velocity.x);
+
+  // Mobs standing on the ground have no vertical speed.
+  if waypoint.y > feet.y + 0.5 && mob.speed.y == 0.0 {
+    mob.speed.y = HOP_SPEED;
+  }
+
+  true
+}
@@ -0,0 +1,246 @@
+//! A coarse grid over the terrain surface, for finding paths that mobs can walk.
+
+use cgmath::{Point3, Vector3};
+use collision::{Aabb, Aabb3};
+use std;
+use std::cmp::Ordering;
+
+use common::fnv_map;
+use common::voxel;
+
+use terrain;
+
+/// lg of the width of a navigation cell.
+const LG_CELL_WIDTH: i16 = 1;
+/// How many cells a mob can climb or drop between neighboring columns.
+const MAX_STEP: i32 = 1;
+/// How many cells up or down to look for the ground under a point.
+const MAX_SNAP: i32 = 4;
+/// Give up searching for a path after visiting this many cells.
+const MAX_SEARCH: usize = 1 << 10;
+/// Forget everything once this many cells are cached.
+const MAX_CACHED: usize = 1 << 16;
+
+/// A cell in the navigation grid, in units of cells.
+pub type Cell = Point3<i32>;
+
+fn cell_width() -> i32 {
+  1 << LG_CELL_WIDTH
+}
+
+/// The cell containing a world position.
+pub fn cell_of(p: &Point3<f32>) -> Cell {
+  let w = cell_width() as f32;
+  Point3::new((p.x / w).floor() as i32, (p.y / w).floor() as i32, (p.z / w).floor() as i32)
+}
+
+/// Where a mob standing in a cell stands: the middle of the cell's floor.
+pub fn floor_of(cell: &Cell) -> Point3<f32> {
+  let w = cell_width() as f32;
+  Point3::new((cell.x as f32 + 0.5) * w, cell.y as f32 * w, (cell.z as f32 + 0.5) * w)
+}
+
+fn blocks(material: voxel::Material) -> bool {
+  match material {
+    voxel::Material::Empty | voxel::Material::Water => false,
+    _ => true,
+  }
+}
+
+fn distance(c1: &Cell, c2: &Cell) -> u32 {
+  ((c1.x - c2.x).abs() + (c1.y - c2.y).abs() + (c1.z - c2.z).abs()) as u32
+}
+
+/// A cell to visit during a path search.
+struct Node {
+  /// The cost to get here, plus the estimated cost from here to the goal.
+  estimate : u32,
+  cell     : Cell,
+}
+
+impl PartialEq for Node {
+  fn eq(&self, other: &Node) -> bool {
+    self.estimate == other.estimate
+  }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+  fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Node {
+  // `BinaryHeap` is a max-heap, so "greater" nodes are the cheaper ones.
+  fn cmp(&self, other: &Node) -> Ordering {
+    other.estimate.cmp(&self.estimate)
+  }
+}
+
+/// A lazily-filled cache of which cells are solid.
+pub struct T {
+  solid: fnv_map::T<Cell, bool>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    solid: fnv_map::new(),
+  }
+}
+
+impl T {
+  fn is_solid(&mut self, terrain: &terrain::T, cell: &Cell) -> bool {
+    if let Some(&solid) = self.solid.get(cell) {
+      return solid
+    }
+
+    if self.solid.len() >= MAX_CACHED {
+      self.solid.clear();
+    }
+
+    let solid =
+      match terrain.load(&voxel::bounds::new(cell.x, cell.y, cell.z, LG_CELL_WIDTH)) {
+        voxel::Volume(material) => blocks(material),
+        voxel::Surface(voxel) => blocks(voxel.corner),
+      };
+    self.solid.insert(*cell, solid);
+    solid
+  }
+
+  /// Can a mob stand in this cell?
+  pub fn is_walkable(&mut self, terrain: &terrain::T, cell: &Cell) -> bool {
+    !self.is_solid(terrain, cell) &&
+    self.is_solid(terrain, &(*cell + Vector3::new(0, -1, 0)))
+  }
+
+  /// The walkable cell closest to `p` vertically, if there's one nearby.
+  pub fn ground_near(&mut self, terrain: &terrain::T, p: &Point3<f32>) -> Option<Cell> {
+    let cell = cell_of(p);
+    if self.is_walkable(terrain, &cell) {
+      return Some(cell)
+    }
+    for dy in 1 .. MAX_SNAP + 1 {
+      for &dy in &[-dy, dy] {
+        let cell = cell + Vector3::new(0, dy, 0);
+        if self.is_walkable(terrain, &cell) {
+          return Some(cell)
+        }
+      }
+    }
+    None
+  }
+
+  /// The cell a mob ends up in by walking from `from` into the neighboring column (dx, dz).
+  fn step(&mut self, terrain: &terrain::T, from: &Cell, dx: i32, dz: i32) -> Option<Cell> {
+    // Prefer level ground, then climbing, then dropping.
+    let mut dys = vec!(0);
+    dys.extend(1 .. MAX_STEP + 1);
+    dys.extend((1 .. MAX_STEP + 1).map(|dy| -dy));
+
+    for dy in dys {
+      let to = Point3::new(from.x + dx, from.y + dy, from.z + dz);
+      if !self.is_walkable(terrain, &to) {
+        continue
+      }
+      // Make sure there's headroom to climb, or open air to drop through.
+      let clear =
+        if dy > 0 {
+          !self.is_solid(terrain, &Point3::new(from.x, to.y, from.z))
+        } else {
+          (to.y .. from.y + 1).all(|y| !self.is_solid(terrain, &Point3::new(to.x, y, to.z)))
+        };
+      if clear {
+        return Some(to)
+      }
+    }
+    None
+  }
+
+  /// Find a walkable path between two points. The waypoints are the floors of the cells along the way,
+  /// in reverse order, so the next waypoint is last. If `to` is too far to search all the way to,
+  /// this returns the path to wherever the search got closest.
+  pub fn find_path(
+    &mut self,
+    terrain : &terrain::T,
+    from    : &Point3<f32>,
+    to      : &Point3<f32>,
+  ) -> Option<Vec<Point3<f32>>> {
+    let start =
+      match self.ground_near(terrain, from) {
+        None => return None,
+        Some(cell) => cell,
+      };
+    let goal =
+      match self.ground_near(terrain, to) {
+        None => return None,
+        Some(cell) => cell,
+      };
+
+    let mut open = std::collections::BinaryHeap::new();
+    let mut came_from: fnv_map::T<Cell, Cell> = fnv_map::new();
+    let mut cost: fnv_map::T<Cell, u32> = fnv_map::new();
+    let mut closest = (distance(&start, &goal), start);
+
+    open.push(Node { estimate: closest.0, cell: start });
+    cost.insert(start, 0);
+
+    let mut visited = 0;
+    while let Some(Node { cell, .. }) = open.pop() {
+      if cell == goal {
+        closest = (0, cell);
+        break
+      }
+      visited += 1;
+      if visited > MAX_SEARCH {
+        break
+      }
+
+      let cell_cost = cost[&cell];
+      for &(dx, dz) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+        let next =
+          match self.step(terrain, &cell, dx, dz) {
+            None => continue,
+            Some(next) => next,
+          };
+        let next_cost = cell_cost + 1;
+        if cost.get(&next).map_or(false, |&c| c <= next_cost) {
+          continue
+        }
+        cost.insert(next, next_cost);
+        came_from.insert(next, cell);
+
+        let remaining = distance(&next, &goal);
+        if remaining < closest.0 {
+          closest = (remaining, next);
+        }
+        open.push(Node { estimate: next_cost + remaining, cell: next });
+      }
+    }
+
+    let (_, mut cell) = closest;
+    if cell == start {
+      return if start == goal { Some(Vec::new()) } else { None }
+    }
+
+    let mut path = Vec::new();
+    while cell != start {
+      path.push(floor_of(&cell));
+      cell = came_from[&cell];
+    }
+    Some(path)
+  }
+
+  /// Forget the cached cells overlapping a region, e.g. because the terrain there changed.
+  pub fn invalidate(&mut self, bounds: &Aabb3<i32>) {
+    let w = cell_width();
+    let (low, high) = (bounds.min(), bounds.max());
+    self.solid.retain(|cell, _| {
+      cell.x * w >= high.x || (cell.x + 1) * w <= low.x ||
+      cell.y * w >= high.y || (cell.y + 1) * w <= low.y ||
+      cell.z * w >= high.z || (cell.z + 1) * w <= low.z
+    });
+  }
+}
@@ -0,0 +1,64 @@
+use cgmath::{Point3, EuclideanSpace, Vector3};
+use collision::{Aabb3};
+
+use common::health;
+use common::protocol;
+use common::surroundings_loader;
+
+use entity;
+use lod;
+use server;
+
+pub mod ai;
+pub mod spawning;
+
+/// The size of every mob's bounding box.
+pub const SIZE: Vector3<f32> = Vector3 { x: 1.0, y: 2.0, z: 1.0 };
+
+pub struct Mob {
+  pub world               : protocol::WorldId,
+  pub position            : Point3<f32>,
+  pub speed               : Vector3<f32>,
+  pub ai                  : ai::T,
+  pub health              : health::T,
+
+  pub entity_id           : entity::id::Mob,
+  pub physics_id          : entity::id::Misc,
+  pub owner_id            : lod::OwnerId,
+  pub surroundings_loader : surroundings_loader::T,
+}
+
+/// Put a new mob in a world, with the low corner of its bounding box at `low_corner`.
+pub fn add(
+  server: &server::T,
+  world: protocol::WorldId,
+  low_corner: Point3<f32>,
+  temperament: ai::Temperament,
+) {
+  let bounds = Aabb3::new(low_corner, low_corner + SIZE);
+  let entity_id = server.mobs.lock().unwrap().allocate();
+  let physics_id = server.misc_allocator.lock().unwrap().allocate();
+
+  let mob =
+    Mob {
+      world               : world,
+      position            : (bounds.min + bounds.max.to_vec()) * 0.5,
+      speed               : Vector3::new(0.0, 0.0, 0.0),
+      ai                  : ai::new(temperament),
+      health              : health::full(health::MOB_MAX),
+      entity_id           : entity_id,
+      physics_id          : physics_id,
+      owner_id            : server.owner_allocator.lock().unwrap().allocate(),
+      surroundings_loader : surroundings_loader::new(8, Vec::new()),
+    };
+
+  server.world(mob.world).physics.lock().unwrap().insert_misc(physics_id, &bounds);
+  server.mobs.lock().unwrap().insert(entity_id, mob);
+}
+
+/// Take a mob out of its world, along with everything it had loaded.
+pub fn remove(server: &server::T, mob: &Mob) {
+  let world = server.world(mob.world);
+  world.terrain_loader.unload_all(&world.physics, mob.owner_id);
+  world.physics.lock().unwrap().remove_misc(mob.physics_id);
+}
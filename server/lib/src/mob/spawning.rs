@@ -0,0 +1,301 @@
+//! Spawning mobs around players, and despawning them once nobody's near.
+//! What spawns where depends on the biome and on whether the spot is out on the surface or down in a cave,
+//! and each area of the world only holds so many mobs at once.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use rand::Rng;
+use std;
+
+use common::fnv_map;
+use common::movement;
+use common::protocol;
+
+use mob;
+use mob::ai::navigation;
+use mob::ai::Temperament;
+use server;
+use terrain::biome::map::Biome;
+use world;
+
+/// How far below the surface a spot has to be to count as a cave.
+const CAVE_DEPTH: f32 = 8.0;
+/// How many spots around each player are tried per spawning pass.
+const ATTEMPTS: u32 = 4;
+/// How far above or below an underground player to look for spots.
+const CAVE_SPREAD: f32 = 8.0;
+
+/// How much light a spot gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Light {
+  /// Open to the sky.
+  Surface,
+  /// Underground, or in a world without a sky.
+  Cave,
+}
+
+/// The chance that a mob spawns at a spot tried, by where the spot is.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Rates {
+  #[allow(missing_docs)]
+  pub plains    : f32,
+  #[allow(missing_docs)]
+  pub desert    : f32,
+  #[allow(missing_docs)]
+  pub mountains : f32,
+  #[allow(missing_docs)]
+  pub tundra    : f32,
+  /// Anywhere underground, whatever the biome above.
+  pub caves     : f32,
+}
+
+impl Default for Rates {
+  fn default() -> Self {
+    Rates {
+      plains    : 0.2,
+      desert    : 0.05,
+      mountains : 0.1,
+      tundra    : 0.05,
+      caves     : 0.15,
+    }
+  }
+}
+
+impl Rates {
+  #[allow(missing_docs)]
+  pub fn get(&self, light: Light, biome: Option<Biome>) -> f32 {
+    match (light, biome) {
+      (Light::Cave, _) | (_, None) => self.caves,
+      (Light::Surface, Some(Biome::Plains))    => self.plains,
+      (Light::Surface, Some(Biome::Desert))    => self.desert,
+      (Light::Surface, Some(Biome::Mountains)) => self.mountains,
+      (Light::Surface, Some(Biome::Tundra))    => self.tundra,
+    }
+  }
+}
+
+/// Which mobs spawn where, and how many there can be.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Params {
+  /// Whether to spawn mobs at all. Mobs from a save stay either way.
+  pub enabled          : bool,
+  /// Seconds between spawning passes.
+  pub interval         : f32,
+  /// Mobs spawn at least this far from every player, so they don't pop in in plain view.
+  pub min_distance     : f32,
+  /// Mobs spawn at most this far from some player.
+  pub max_distance     : f32,
+  /// Mobs this far from every player in their world are removed.
+  pub despawn_distance : f32,
+  /// The width of the square areas mobs are counted in.
+  pub area_size        : f32,
+  /// The most mobs spawned into an area that already has this many.
+  pub area_cap         : u32,
+  #[allow(missing_docs)]
+  pub rates            : Rates,
+}
+
+impl Default for Params {
+  fn default() -> Self {
+    Params {
+      enabled          : true,
+      interval         : 2.0,
+      min_distance     : 24.0,
+      max_distance     : 64.0,
+      despawn_distance : 128.0,
+      area_size        : 64.0,
+      area_cap         : 4,
+      rates            : Rates::default(),
+    }
+  }
+}
+
+/// What kind of mob spawns at a spot: timid ones graze the gentler biomes, and aggressive ones roam
+/// the harsh ones and the dark.
+pub fn temperament(light: Light, biome: Option<Biome>) -> Temperament {
+  match (light, biome) {
+    (Light::Cave, _) | (_, None) => Temperament::Aggressive,
+    (Light::Surface, Some(Biome::Plains)) | (Light::Surface, Some(Biome::Mountains)) => Temperament::Timid,
+    (Light::Surface, Some(Biome::Desert)) | (Light::Surface, Some(Biome::Tundra)) => Temperament::Aggressive,
+  }
+}
+
+/// An area mobs are counted in.
+type Area = (protocol::WorldId, i32, i32);
+
+/// The area containing a position.
+pub fn area_of(world: protocol::WorldId, p: &Point3<f32>, area_size: f32) -> Area {
+  (world, (p.x / area_size).floor() as i32, (p.z / area_size).floor() as i32)
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  params             : Params,
+  updates_until_pass : u32,
+}
+
+#[allow(missing_docs)]
+pub fn new(params: Params) -> T {
+  T {
+    params             : params,
+    updates_until_pass : 0,
+  }
+}
+
+impl T {
+  /// Count down to the next spawning pass. Returns whether it's time for one.
+  fn tick(&mut self) -> bool {
+    if !self.params.enabled {
+      return false
+    }
+    if self.updates_until_pass > 0 {
+      self.updates_until_pass -= 1;
+      return false
+    }
+    self.updates_until_pass = (self.params.interval * movement::UPDATES_PER_SECOND as f32).max(1.0) as u32;
+    true
+  }
+}
+
+/// Despawn far-away mobs and spawn new ones around players, if it's time to.
+pub fn update(server: &server::T) {
+  let params = {
+    let mut spawner = server.spawner.lock().unwrap();
+    if !spawner.tick() {
+      return
+    }
+    spawner.params
+  };
+
+  let players: Vec<(protocol::WorldId, Point3<f32>)> =
+    server.players.lock().unwrap().values()
+    .map(|player| (player.world, player.position))
+    .collect();
+
+  despawn(server, &params, &players);
+  for &(world, position) in &players {
+    spawn_near(server, &params, &players, world, &position);
+  }
+}
+
+/// Remove mobs that no player is close to. Mobs in worlds nobody's in are left alone, so saved mobs are still
+/// there when players come back.
+fn despawn(server: &server::T, params: &Params, players: &[(protocol::WorldId, Point3<f32>)]) {
+  let max_distance2 = params.despawn_distance * params.despawn_distance;
+  let mut despawned = 0;
+  server.mobs.lock().unwrap().retain(|_, mob| {
+    let mut occupied = false;
+    for &(world, ref position) in players {
+      if world != mob.world {
+        continue
+      }
+      occupied = true;
+      if (position - mob.position).magnitude2() <= max_distance2 {
+        return true
+      }
+    }
+    if !occupied {
+      return true
+    }
+    mob::remove(server, mob);
+    despawned += 1;
+    false
+  });
+  if despawned > 0 {
+    debug!("Despawned {} mobs", despawned);
+  }
+}
+
+/// Try a few random spots around a player, and spawn whatever the rules allow there.
+fn spawn_near(
+  server: &server::T,
+  params: &Params,
+  players: &[(protocol::WorldId, Point3<f32>)],
+  world_id: protocol::WorldId,
+  center: &Point3<f32>,
+) {
+  let world = server.world(world_id);
+  let terrain = &world.terrain_loader.terrain;
+
+  let mut populations: fnv_map::T<Area, u32> = fnv_map::new();
+  for mob in server.mobs.lock().unwrap().values() {
+    *populations.entry(area_of(mob.world, &mob.position, params.area_size)).or_insert(0) += 1;
+  }
+
+  // Players deep underground find mobs in the caves around them, rather than up on the surface.
+  let underground =
+    match terrain.surface_height(center.x, center.z) {
+      None => true,
+      Some(height) => center.y < height - CAVE_DEPTH,
+    };
+
+  for _ in 0 .. ATTEMPTS {
+    let (x, z, dy, roll) = {
+      let mut rng = server.rng.lock().unwrap();
+      let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+      let distance = rng.gen_range(params.min_distance, params.max_distance.max(params.min_distance + 1.0));
+      (
+        center.x + distance * angle.cos(),
+        center.z + distance * angle.sin(),
+        rng.gen_range(-CAVE_SPREAD, CAVE_SPREAD),
+        rng.gen::<f32>(),
+      )
+    };
+
+    let surface = terrain.surface_height(x, z);
+    let y =
+      match surface {
+        Some(height) if !underground => height,
+        _ => center.y + dy,
+      };
+    let floor =
+      match world.mob_navigation.lock().unwrap().ground_near(terrain, &Point3::new(x, y, z)) {
+        None => continue,
+        Some(cell) => navigation::floor_of(&cell),
+      };
+
+    let min_distance2 = params.min_distance * params.min_distance;
+    let too_close =
+      players.iter()
+      .any(|&(world, ref position)| world == world_id && (position - floor).magnitude2() < min_distance2);
+    if too_close {
+      continue
+    }
+
+    let light =
+      match surface {
+        Some(height) if floor.y > height - CAVE_DEPTH => Light::Surface,
+        _ => Light::Cave,
+      };
+    let biome = terrain.biome_at(floor.x, floor.z);
+    if roll >= params.rates.get(light, biome) {
+      continue
+    }
+
+    let population = populations.entry(area_of(world_id, &floor, params.area_size)).or_insert(0);
+    if *population >= params.area_cap {
+      continue
+    }
+    *population += 1;
+
+    let low_corner = floor - Vector3::new(mob::SIZE.x / 2.0, 0.0, mob::SIZE.z / 2.0);
+    let temperament = temperament(light, biome);
+    debug!("Spawning a {:?} mob at {:?} in the {}", temperament, floor, world::name(world_id));
+    mob::add(server, world_id, low_corner, temperament);
+  }
+}
+
+#[test]
+fn rules_follow_light_and_biome() {
+  let rates = Rates::default();
+  assert_eq!(rates.get(Light::Surface, Some(Biome::Desert)), rates.desert);
+  assert_eq!(rates.get(Light::Cave, Some(Biome::Desert)), rates.caves);
+  assert_eq!(rates.get(Light::Surface, None), rates.caves);
+
+  assert_eq!(temperament(Light::Surface, Some(Biome::Plains)), Temperament::Timid);
+  assert_eq!(temperament(Light::Cave, Some(Biome::Plains)), Temperament::Aggressive);
+
+  let world = protocol::WorldId(0);
+  assert_eq!(area_of(world, &Point3::new(-1.0, 50.0, 63.0), 64.0), (world, -1, 0));
+}
@@ -12,26 +12,54 @@ extern crate log;
 extern crate nanomsg;
 extern crate num;
 extern crate rand;
+extern crate rlua;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate stopwatch;
 extern crate terrain;
 extern crate thread_scoped;
 extern crate time;
+extern crate toml;
 extern crate voxel_data;
 
+mod autosave;
+mod brush;
 mod client_recv_thread;
+mod clock;
+pub mod config;
+pub mod console;
 mod entity;
+mod explosion;
+mod grass;
+mod history;
 mod in_progress_terrain;
-mod init_mobs;
+mod interest;
+mod inventory;
+mod item;
 mod lod;
+mod metrics;
 mod mob;
 mod octree;
+mod persistence;
 mod physics;
 mod player;
+pub mod plugin;
+mod replication;
 mod run;
+pub mod script;
 pub mod server;
+mod storage;
+mod stability;
 mod sun;
+mod teleport;
 mod terrain_loader;
+mod tick;
 pub mod update_gaia;
 mod update_world;
+mod voxel_requests;
+mod weather;
+pub mod world;
 
-pub use run::run;
+pub use common::net::Transport;
+pub use run::{run, DEFAULT_TERRAIN_WORKERS};
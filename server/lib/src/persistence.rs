@@ -0,0 +1,361 @@
+//! On-disk storage of voxels, grouped into region files.
+
+use bincode;
+use std;
+use std::io::Read;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::voxel;
+
+use terrain;
+
+/// lg(width) of a region, in voxels of the region's size.
+const LG_REGION_WIDTH: i32 = 4;
+/// The most regions kept in memory. Past this, the least recently used are written out (if they need it) and dropped.
+const MAX_LOADED_REGIONS: usize = 1 << 10;
+/// The file in a store's directory that says which version of the terrain generator its voxels came from.
+const GENERATOR_FILE: &'static str = "generator";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct RegionId {
+  x       : i32,
+  y       : i32,
+  z       : i32,
+  lg_size : i16,
+}
+
+impl RegionId {
+  fn containing(bounds: &voxel::bounds::T) -> RegionId {
+    RegionId {
+      x       : bounds.x >> LG_REGION_WIDTH,
+      y       : bounds.y >> LG_REGION_WIDTH,
+      z       : bounds.z >> LG_REGION_WIDTH,
+      lg_size : bounds.lg_size,
+    }
+  }
+
+  fn path(&self, root: &std::path::Path) -> std::path::PathBuf {
+    root
+      .join(format!("{}", self.lg_size))
+      .join(format!("{}.{}.{}.region", self.x, self.y, self.z))
+  }
+}
+
+struct Region {
+  voxels    : fnv_map::T<voxel::bounds::T, voxel::T>,
+  dirty     : bool,
+  /// When the region was last used, by `T::clock`.
+  last_used : u64,
+}
+
+/// A lazily-loaded, lazily-written set of region files.
+pub struct T {
+  root        : std::path::PathBuf,
+  regions     : fnv_map::T<RegionId, Region>,
+  max_regions : usize,
+  /// Counts region uses, to find the least recently used.
+  clock       : u64,
+  /// Regions handed out by `take_dirty` and not yet written. They aren't evicted until then, so they can't be read
+  /// back from disk in a stale state.
+  writing     : fnv_set::T<RegionId>,
+}
+
+#[allow(missing_docs)]
+pub fn new(root: &std::path::Path) -> T {
+  T {
+    root        : root.to_owned(),
+    regions     : fnv_map::new(),
+    max_regions : MAX_LOADED_REGIONS,
+    clock       : 0,
+    writing     : fnv_set::new(),
+  }
+}
+
+/// Modified regions, copied out of a `T` so they can be written without holding it.
+pub struct Dirty {
+  /// Every region this was taken with, written or not, to release in `finish_write`.
+  taken   : Vec<RegionId>,
+  regions : Vec<(RegionId, Vec<(voxel::bounds::T, voxel::T)>)>,
+}
+
+impl T {
+  fn region(&mut self, id: RegionId) -> &mut Region {
+    self.clock += 1;
+    if !self.regions.contains_key(&id) {
+      let max_regions = self.max_regions;
+      self.evict_down_to(max_regions.saturating_sub(1));
+      let region = read_region(&id.path(&self.root));
+      self.regions.insert(id, region);
+    }
+    let region = self.regions.get_mut(&id).unwrap();
+    region.last_used = self.clock;
+    region
+  }
+
+  /// Drop the least recently used regions until there are at most `count`, writing out any modified ones first.
+  /// Regions that can't be written are kept.
+  fn evict_down_to(&mut self, count: usize) {
+    if self.regions.len() <= count {
+      return
+    }
+    let mut by_age: Vec<(u64, RegionId)> =
+      self.regions.iter()
+        .filter(|&(id, _)| !self.writing.contains(id))
+        .map(|(&id, region)| (region.last_used, id))
+        .collect();
+    by_age.sort_by_key(|&(last_used, _)| last_used);
+    let excess = self.regions.len() - count;
+    for (_, id) in by_age.into_iter().take(excess) {
+      let written = {
+        let region = &self.regions[&id];
+        !region.dirty || {
+          let path = id.path(&self.root);
+          match write_region(&path, &voxels_of(region)) {
+            Ok(()) => true,
+            Err(err) => {
+              warn!("Error writing region {}: {:?}", path.display(), err);
+              false
+            },
+          }
+        }
+      };
+      if written {
+        self.regions.remove(&id);
+      }
+    }
+  }
+
+  /// Fetch a stored voxel, if there is one.
+  pub fn get(&mut self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    self.region(RegionId::containing(bounds)).voxels.get(bounds).cloned()
+  }
+
+  /// Store a voxel. It won't be written to disk until the next `flush`, or until its region is evicted.
+  pub fn insert(&mut self, bounds: &voxel::bounds::T, voxel: voxel::T) {
+    let region = self.region(RegionId::containing(bounds));
+    region.voxels.insert(*bounds, voxel);
+    region.dirty = true;
+  }
+
+  /// Copy out the regions modified since the last call, to write with `Dirty::write` after letting go of this.
+  /// They count as written from now on; hand whatever fails back with `finish_write`.
+  /// Regions still being written by an earlier `take_dirty` are left for the next call, so an older copy can't be
+  /// written over a newer one.
+  pub fn take_dirty(&mut self) -> Dirty {
+    let mut taken = Vec::new();
+    let mut regions = Vec::new();
+    for (&id, region) in &mut self.regions {
+      if region.dirty && !self.writing.contains(&id) {
+        region.dirty = false;
+        self.writing.insert(id);
+        taken.push(id);
+        regions.push((id, voxels_of(region)));
+      }
+    }
+    Dirty {
+      taken   : taken,
+      regions : regions,
+    }
+  }
+
+  /// Finish a `take_dirty`: let its regions be evicted again, and mark the ones that couldn't be written as modified,
+  /// so the next flush tries them again. Regions taken by other, unfinished writes stay held.
+  pub fn finish_write(&mut self, failed: Dirty) {
+    for id in &failed.taken {
+      self.writing.remove(id);
+    }
+    for (id, _) in failed.regions {
+      if let Some(region) = self.regions.get_mut(&id) {
+        region.dirty = true;
+      }
+    }
+  }
+
+  /// The directory the region files are under.
+  pub fn root(&self) -> &std::path::Path {
+    &self.root
+  }
+}
+
+impl Dirty {
+  /// Write the regions to disk under `root`. Returns the ones that couldn't be written.
+  pub fn write(self, root: &std::path::Path) -> Dirty {
+    let count = self.regions.len();
+    let mut failed = Vec::new();
+    for (id, voxels) in self.regions {
+      let path = id.path(root);
+      if let Err(err) = write_region(&path, &voxels) {
+        warn!("Error writing region {}: {:?}", path.display(), err);
+        failed.push((id, voxels));
+      }
+    }
+    debug!("Flushed {} regions", count - failed.len());
+    Dirty {
+      taken   : self.taken,
+      regions : failed,
+    }
+  }
+}
+
+/// Which version of the terrain generator the voxels stored under `root` came from, so the rest can be generated to
+/// match. Stores from before this was recorded came from `terrain::Version::Scalar`. New stores get the latest version,
+/// which is recorded for next time.
+pub fn generator_version(root: &std::path::Path) -> terrain::Version {
+  let path = root.join(GENERATOR_FILE);
+  let mut contents = String::new();
+  match std::fs::File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+    Ok(_) =>
+      match contents.trim().parse().ok().and_then(terrain::Version::of_number) {
+        Some(version) => return version,
+        None => {
+          warn!("{} doesn't name a terrain generator version. Generating with the latest.", path.display());
+          return terrain::LATEST_VERSION
+        },
+      },
+    Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {},
+    Err(err) => {
+      warn!("Error reading {}: {:?}. Generating with the latest terrain generator.", path.display(), err);
+      return terrain::LATEST_VERSION
+    },
+  }
+
+  let version = if root.exists() { terrain::Version::Scalar } else { terrain::LATEST_VERSION };
+  let written =
+    std::fs::create_dir_all(root)
+    .and_then(|()| std::fs::write(&path, format!("{}\n", version.number())));
+  if let Err(err) = written {
+    warn!("Error writing {}: {:?}", path.display(), err);
+  }
+  version
+}
+
+/// Write all the modified regions in a store to disk, without holding its lock while writing.
+pub fn flush(store: &std::sync::Mutex<T>) {
+  let (dirty, root) = {
+    let mut store = store.lock().unwrap();
+    (store.take_dirty(), store.root().to_owned())
+  };
+  let failed = dirty.write(&root);
+  store.lock().unwrap().finish_write(failed);
+}
+
+fn voxels_of(region: &Region) -> Vec<(voxel::bounds::T, voxel::T)> {
+  region.voxels.iter().map(|(&bounds, &voxel)| (bounds, voxel)).collect()
+}
+
+fn read_region(path: &std::path::Path) -> Region {
+  let mut region =
+    Region {
+      voxels    : fnv_map::new(),
+      dirty     : false,
+      last_used : 0,
+    };
+
+  let mut file =
+    match std::fs::File::open(path) {
+      Err(err) => {
+        if err.kind() != std::io::ErrorKind::NotFound {
+          warn!("Error opening region file {}: {:?}", path.display(), err);
+        }
+        return region
+      },
+      Ok(file) => file,
+    };
+
+  let voxels: Vec<(voxel::bounds::T, voxel::T)> =
+    match bincode::deserialize_from(&mut file, bincode::Infinite) {
+      Ok(voxels) => voxels,
+      Err(err) => {
+        warn!("Error loading region file {}: {:?}", path.display(), err);
+        return region
+      },
+    };
+
+  region.voxels.extend(voxels.into_iter());
+  region
+}
+
+fn write_region(path: &std::path::Path, voxels: &Vec<(voxel::bounds::T, voxel::T)>) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+
+  // Write to a temporary file first so a crash mid-write can't corrupt the region.
+  let tmp_path = path.with_extension("region.tmp");
+  {
+    let mut file = std::fs::File::create(&tmp_path)?;
+    bincode::serialize_into(&mut file, voxels, bincode::Infinite)
+      .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err)))?;
+  }
+  std::fs::rename(&tmp_path, path)
+}
+
+#[test]
+fn evicted_regions_are_written_first() {
+  let root = std::env::temp_dir().join(format!("playform-persistence-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&root);
+
+  let mut store = new(&root);
+  store.max_regions = 2;
+  let region_width = 1 << LG_REGION_WIDTH;
+  let bounds: Vec<voxel::bounds::T> = (0 .. 3).map(|i| voxel::bounds::new(i * region_width, 0, 0, 0)).collect();
+  for b in &bounds {
+    store.insert(b, voxel::Volume(voxel::Material::Stone));
+  }
+  // Loading the third region evicted the first, which had to be written out.
+  assert_eq!(store.regions.len(), 2);
+  assert!(RegionId::containing(&bounds[0]).path(&root).exists());
+
+  let mut reloaded = new(&root);
+  assert_eq!(reloaded.get(&bounds[0]), Some(voxel::Volume(voxel::Material::Stone)));
+  assert_eq!(reloaded.get(&bounds[2]), None);
+
+  let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn overlapping_flushes_release_only_their_own_regions() {
+  let root = std::env::temp_dir().join(format!("playform-overlapping-flush-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&root);
+
+  let mut store = new(&root);
+  let region_width = 1 << LG_REGION_WIDTH;
+  let a = voxel::bounds::new(0, 0, 0, 0);
+  let b = voxel::bounds::new(region_width, 0, 0, 0);
+
+  store.insert(&a, voxel::Volume(voxel::Material::Stone));
+  let first = store.take_dirty();
+  // `a` is modified again while the first flush is still writing it, and `b` for the first time.
+  store.insert(&a, voxel::Volume(voxel::Material::Marble));
+  store.insert(&b, voxel::Volume(voxel::Material::Stone));
+  let second = store.take_dirty();
+  assert_eq!(second.taken, vec!(RegionId::containing(&b)));
+
+  store.finish_write(Dirty { taken: second.taken, regions: Vec::new() });
+  assert!(store.writing.contains(&RegionId::containing(&a)));
+  assert!(!store.writing.contains(&RegionId::containing(&b)));
+
+  store.finish_write(Dirty { taken: first.taken, regions: Vec::new() });
+  assert!(store.writing.is_empty());
+  assert_eq!(store.take_dirty().taken, vec!(RegionId::containing(&a)));
+
+  let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn stores_keep_their_generator_version() {
+  let root = std::env::temp_dir().join(format!("playform-generator-version-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&root);
+
+  // New stores get the latest generator, and keep it.
+  assert_eq!(generator_version(&root), terrain::LATEST_VERSION);
+  assert_eq!(generator_version(&root), terrain::LATEST_VERSION);
+
+  // Stores from before versions were recorded came from the first one.
+  std::fs::remove_file(root.join(GENERATOR_FILE)).unwrap();
+  assert_eq!(generator_version(&root), terrain::Version::Scalar);
+  assert_eq!(generator_version(&root), terrain::Version::Scalar);
+
+  let _ = std::fs::remove_dir_all(&root);
+}
@@ -29,29 +29,37 @@ impl T {
   }
 
   pub fn insert_terrain(&mut self, id: entity::id::Terrain, bounds: &Aabb3<f32>) {
+    if self.terrain_bounds.contains_key(&id) {
+      warn!("Ignoring terrain {:?}, which is already in physics", id);
+      return
+    }
     self.terrain_octree.insert(bounds, id);
     self.terrain_bounds.insert(id, *bounds);
   }
 
   pub fn insert_misc(&mut self, id: entity::id::Misc, bounds: &Aabb3<f32>) {
+    if self.misc_bounds.contains_key(&id) {
+      warn!("Ignoring {:?}, which is already in physics", id);
+      return
+    }
     self.misc_octree.insert(bounds, id);
     self.misc_bounds.insert(id, *bounds);
   }
 
   pub fn remove_terrain(&mut self, id: entity::id::Terrain) {
-    match self.terrain_bounds.get(&id) {
+    match self.terrain_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+        self.terrain_octree.remove(&bounds, id);
       },
     }
   }
 
   pub fn remove_misc(&mut self, id: entity::id::Misc) {
-    match self.misc_bounds.get(&id) {
+    match self.misc_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_octree.remove(&bounds, id);
       },
     }
   }
@@ -61,7 +69,15 @@ impl T {
   }
 
   pub fn translate_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
-    let bounds = self.misc_bounds.get_mut(&id).unwrap();
+    let bounds =
+      match self.misc_bounds.get_mut(&id) {
+        None => {
+          // A stale id, whose entity's gone from physics.
+          warn!("Not moving {:?}, which isn't in physics", id);
+          return None
+        },
+        Some(bounds) => bounds,
+      };
     let new_bounds =
       Aabb3::new(
         bounds.min + amount,
@@ -1,24 +1,31 @@
 use cgmath;
-use cgmath::{Point3, Matrix3, Vector3, ElementWise};
+use cgmath::{Point3, Matrix3, Vector3};
 use collision::{Aabb3, Ray3};
 use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::sync::Mutex;
 use stopwatch;
 
+use common::health;
 use common::id_allocator;
+use common::movement;
+use common::protocol;
 use common::surroundings_loader;
 use common::voxel;
 
 use entity;
+use history;
+use inventory;
 use lod;
 use physics;
 use server;
+use terrain;
 use update_gaia;
 use update_world::load_placeholders;
+use world;
 
-const MAX_JUMP_FUEL: u32 = 4;
-const MAX_STEP_HEIGHT: f32 = 1.0;
+/// The low corner of new players' bounding boxes, and of players' who died, when no spawn point's been set.
+// TODO: shift upward until outside terrain
+pub const SPAWN_CORNER: Point3<f32> = Point3 { x: 0.0, y: 64.0, z: 4.0 };
 
 #[derive(Debug, Clone)]
 pub enum Collision {
@@ -27,25 +34,29 @@ pub enum Collision {
 }
 
 pub struct T {
+  pub world: protocol::WorldId,
   pub position: Point3<f32>,
-  // speed; units are world coordinates
-  pub speed: Vector3<f32>,
-  // acceleration; units are world coordinates
-  pub accel: Vector3<f32>,
-  // acceleration; x/z units are relative to player facing
-  pub walk_accel: Vector3<f32>,
-  // this is depleted as we jump and replenished as we stand.
-  pub jump_fuel: u32,
-  // are we currently trying to jump? (e.g. holding the key).
-  pub is_jumping: bool,
+  pub movement: movement::T,
+  // the most recent movement input applied, so the client can tell which of its predictions to redo.
+  pub last_input: Option<movement::InputId>,
   pub entity_id: entity::id::Player,
   pub physics_id: entity::id::Misc,
+  // shown above the player, and next to their chat messages.
+  pub name: String,
 
   // rotation around the y-axis, in radians
   pub lateral_rotation: f32,
   // "pitch", in radians
   pub vertical_rotation: f32,
 
+  pub inventory: inventory::T,
+
+  pub health: health::T,
+  // What took the player's last hit point, if it's been taken since the player last respawned.
+  pub killed_by: Option<health::Cause>,
+  // The brush edits the player can undo and redo.
+  pub history: history::T,
+
   surroundings_loader: surroundings_loader::T,
   surroundings_owner: lod::OwnerId,
   // Nearby blocks should be made solid if they aren't loaded yet.
@@ -56,21 +67,25 @@ pub struct T {
 pub fn new(
   entity_id: entity::id::Player,
   physics_id: entity::id::Misc,
+  name: String,
   owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>,
 ) -> T {
   let surroundings_owner = owner_allocator.lock().unwrap().allocate();
   let solid_owner = owner_allocator.lock().unwrap().allocate();
   T {
+    world               : world::OVERWORLD,
     position            : Point3::new(0.0, 0.0, 0.0),
-    speed               : Vector3::new(0.0, 0.0, 0.0),
-    accel               : Vector3::new(0.0, -0.1, 0.0),
-    walk_accel          : Vector3::new(0.0, 0.0, 0.0),
-    jump_fuel           : 0,
-    is_jumping          : false,
+    movement            : movement::new(),
+    last_input          : None,
     entity_id           : entity_id,
     physics_id          : physics_id,
+    name                : name,
     lateral_rotation    : 0.0,
     vertical_rotation   : 0.0,
+    inventory           : inventory::new(),
+    health              : health::full(health::PLAYER_MAX),
+    killed_by           : None,
+    history             : history::new(),
 
     surroundings_loader : surroundings_loader::new(8, Vec::new()),
     solid_boundary      : surroundings_loader::new(8, Vec::new()),
@@ -79,75 +94,42 @@ pub fn new(
   }
 }
 
-impl T {
-  /// Translates the player by a vector.
-  /// If the player collides with something with a small height jump, the player will shift upward.
-  /// Returns the actual amount moved by.
-  fn translate(
-    &mut self,
-    physics: &Mutex<physics::T>,
-    requested_shift: Vector3<f32>,
-  ) -> (Aabb3<f32>, Vec<Collision>)
-  {
-    let mut physics = physics.lock().unwrap();
-    let physics = physics.deref_mut();
-    let init_bounds = *physics.get_bounds(self.physics_id).unwrap();
-    let requested_bounds =
-      Aabb3::new(
-        init_bounds.min + requested_shift,
-        init_bounds.max + requested_shift,
-      );
+/// A player's body in the server's physics.
+struct PhysicsBody<'a> {
+  physics : &'a mut physics::T,
+  terrain : &'a terrain::T,
+  id      : entity::id::Misc,
+}
 
-    let mut shift = requested_shift;
-    let mut collisions = Vec::new();
-    let mut collided = false;
-    loop {
-      match physics.translate_misc(self.physics_id, shift) {
-        None => {
-          break
-        },
-        Some((_, physics::Collision::Misc(id))) => {
-          collided = true;
-          collisions.push(Collision::Misc(id));
-          break
-        },
-        Some((collision_bounds, physics::Collision::Terrain(id))) => {
-          collisions.push(Collision::Terrain(id));
-          collided = true;
-
-          // Step to the top of whatever we hit.
-          let step_height = collision_bounds.max.y - requested_bounds.min.y;
-          assert!(step_height > 0.0);
-
-          if step_height > MAX_STEP_HEIGHT {
-            // Step is too big; we just ran into something.
-            break
-          }
-
-          shift += Vector3::new(0.0, step_height, 0.0);
-        },
-      }
-    }
+impl<'a> movement::Body for PhysicsBody<'a> {
+  type Obstacle = Collision;
 
-    let shifted = *physics.get_bounds(self.physics_id).unwrap();
-    self.position += shifted.min - init_bounds.min;
+  fn bounds(&self) -> Aabb3<f32> {
+    *self.physics.get_bounds(self.id).unwrap()
+  }
 
-    if collided {
-      if requested_shift.y < 0.0 {
-        self.jump_fuel = MAX_JUMP_FUEL;
-      }
+  fn translate(&mut self, shift: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
+    self.physics.translate_misc(self.id, shift).map(|(bounds, collision)| {
+      let collision =
+        match collision {
+          physics::Collision::Terrain(id) => Collision::Terrain(id),
+          physics::Collision::Misc(id)    => Collision::Misc(id),
+        };
+      (bounds, collision)
+    })
+  }
 
-      self.speed.y -= requested_shift.y;
-    } else {
-      if requested_shift.y < 0.0 {
-        self.jump_fuel = 0;
+  fn submersion(&self) -> f32 {
+    movement::submersion(&self.bounds(), |bounds| {
+      match self.terrain.load(bounds) {
+        voxel::Volume(voxel::Material::Water) => true,
+        _ => false,
       }
-    }
-
-    (shifted, collisions)
+    })
   }
+}
 
-
+impl T {
   pub fn update<RequestBlock>(
     &mut self,
     server: &server::T,
@@ -155,6 +137,7 @@ impl T {
   ) -> (Aabb3<f32>, Vec<Collision>) where
     RequestBlock: FnMut(update_gaia::Message),
   {
+    let world = server.world(self.world);
     let player_position =
       Point3::new(
         self.position.x as i32,
@@ -168,9 +151,9 @@ impl T {
         let pos = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
         match load_type {
           surroundings_loader::LoadType::Load | surroundings_loader::LoadType::Downgrade => {
-            server.terrain_loader.load(
+            world.terrain_loader.load(
               &server.misc_allocator,
-              &server.physics,
+              &world.physics,
               &pos,
               lod::Full,
               owner,
@@ -178,8 +161,8 @@ impl T {
             );
           },
           surroundings_loader::LoadType::Unload => {
-            server.terrain_loader.unload(
-              &server.physics,
+            world.terrain_loader.unload(
+              &world.physics,
               &pos,
               owner,
             );
@@ -193,6 +176,7 @@ impl T {
         load_placeholders(
           owner,
           server,
+          self.world,
           request_block,
           &block_position,
           load_type,
@@ -200,50 +184,80 @@ impl T {
       }
     });
 
-    if self.is_jumping {
-      if self.jump_fuel > 0 {
-        self.jump_fuel -= 1;
-      } else {
-        // this code is duplicated in a few places
-        self.is_jumping = false;
-        self.accel.y = self.accel.y - 0.3;
-      }
+    let falling_speed = -self.movement.speed.y;
+    let (shift, collisions) = {
+      let mut physics = world.physics.lock().unwrap();
+      let mut body =
+        PhysicsBody {
+          physics : &mut *physics,
+          terrain : &world.terrain_loader.terrain,
+          id      : self.physics_id,
+        };
+      self.movement.update(&mut body, self.lateral_rotation)
+    };
+    self.position += shift;
+
+    // Falling into water doesn't hurt.
+    let landed = falling_speed > 0.0 && shift.y > -falling_speed && self.movement.submersion == 0.0;
+    if landed {
+      self.hurt(health::fall_damage(falling_speed), health::Cause::Fall);
     }
 
-    let delta_p = self.speed;
-    let mut new_bounds = *server.physics.lock().unwrap().get_bounds(self.physics_id).unwrap();
-    let mut collisions = Vec::new();
-    if delta_p.x != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.y != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.z != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
+    let bounds = *world.physics.lock().unwrap().get_bounds(self.physics_id).unwrap();
+    (bounds, collisions)
+  }
+
+  /// Take some damage. Dead players stay dead until they respawn.
+  pub fn hurt(&mut self, amount: u32, cause: health::Cause) {
+    if self.health.damage(amount) {
+      self.killed_by = Some(cause);
     }
+  }
 
-    let y_axis = Vector3::new(0.0, 1.0, 0.0);
-    let walk_v =
-        Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
-        * self.walk_accel;
-    self.speed += walk_v;
-    self.speed += self.accel;
-    // friction
-    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
-
-    (new_bounds, collisions)
+  /// Bring a dead player back to life at the spawn point, with full health.
+  /// Returns what killed it, or None if it wasn't dead.
+  pub fn respawn(&mut self, server: &server::T) -> Option<health::Cause> {
+    let cause =
+      match self.killed_by.take() {
+        None => return None,
+        Some(cause) => cause,
+      };
+    let spawn = server.teleports.lock().unwrap().spawn();
+    self.move_to(server, spawn.world, spawn.position);
+    self.health = health::full(health::PLAYER_MAX);
+    Some(cause)
   }
 
-  /// Changes the player's acceleration by the given `da`.
-  pub fn walk(&mut self, da: Vector3<f32>) {
-    self.walk_accel += &da * 0.1;
+  /// Move the player to `position`, possibly in another world, leaving behind whatever it had loaded around where
+  /// it was.
+  pub fn move_to(&mut self, server: &server::T, world: protocol::WorldId, position: Point3<f32>) {
+    let bounds = {
+      let old = server.world(self.world);
+      old.terrain_loader.unload_all(&old.physics, self.surroundings_owner);
+      old.terrain_loader.unload_all(&old.physics, self.solid_owner);
+      let mut physics = old.physics.lock().unwrap();
+      let bounds = *physics.get_bounds(self.physics_id).unwrap();
+      physics.remove_misc(self.physics_id);
+      bounds
+    };
+
+    let shift = position - self.position;
+    let bounds = Aabb3::new(bounds.min + shift, bounds.max + shift);
+    server.world(world).physics.lock().unwrap().insert_misc(self.physics_id, &bounds);
+
+    self.world = world;
+    self.position = position;
+    self.movement.speed = Vector3::new(0.0, 0.0, 0.0);
+    self.surroundings_loader = surroundings_loader::new(8, Vec::new());
+    self.solid_boundary = surroundings_loader::new(8, Vec::new());
+  }
+
+  /// Apply a movement input from the client.
+  pub fn input<Apply>(&mut self, id: movement::InputId, apply: Apply) where
+    Apply: FnOnce(&mut movement::T),
+  {
+    apply(&mut self.movement);
+    self.last_input = Some(id);
   }
 
   /// Rotate the player around the y axis, by `r` radians. Positive is counterclockwise.
@@ -267,6 +281,16 @@ impl T {
 
   // axes
 
+  /// The direction the player faces, as an angle from +x toward +z.
+  pub fn heading(&self) -> f32 {
+    movement::heading(self.lateral_rotation)
+  }
+
+  /// What the player is visibly doing.
+  pub fn animation(&self) -> protocol::Animation {
+    self.movement.animation()
+  }
+
   /// Return the "right" axis (i.e. the x-axis rotated to match you).
   pub fn right(&self) -> Vector3<f32> {
     Matrix3::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(self.lateral_rotation))
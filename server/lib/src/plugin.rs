@@ -0,0 +1,125 @@
+//! Gameplay extensions. A plugin is a Rust type, usually in its own crate, that's linked into the server binary
+//! and registered before the server starts. The server calls each plugin's hooks as things happen, so minigames,
+//! protection rules and the like can be added without patching the core modules.
+//!
+//! Hooks are called on the server's own threads, in the middle of whatever triggered them, so they should be quick.
+//! The ones given the server are called without any of its locks held, so they're free to lock what they need.
+
+use collision::Aabb3;
+
+use common::entity;
+use common::protocol;
+use common::voxel;
+
+use server;
+
+/// A change to the terrain, before it's made.
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelEdit {
+  #[allow(missing_docs)]
+  pub world  : protocol::WorldId,
+  /// The region that may change.
+  pub bounds : Aabb3<i32>,
+  /// The player making the change, if it's one player's doing.
+  pub player : Option<entity::id::Player>,
+}
+
+/// Hooks into the server. Every hook does nothing by default, so a plugin only needs the ones it cares about.
+pub trait Plugin: Send + Sync {
+  /// The name the plugin's logged under.
+  fn name(&self) -> &str;
+
+  /// The world's been stepped forward by a tick.
+  fn on_tick(&self, _server: &server::T) {}
+
+  /// A block of terrain has just been generated, and hasn't been stored or sent anywhere yet.
+  /// Changes made to `voxel` are kept as if they'd been generated.
+  fn on_chunk_generate(&self, _world: protocol::WorldId, _bounds: &voxel::bounds::T, _voxel: &mut voxel::T) {}
+
+  /// A player has joined, and their client has been told where they are.
+  fn on_player_join(&self, _server: &server::T, _player: entity::id::Player) {}
+
+  /// Someone wants to change the terrain. Return false to stop them.
+  fn on_voxel_edit(&self, _server: &server::T, _edit: &VoxelEdit) -> bool {
+    true
+  }
+
+  /// A player said something. `text` can be changed before everyone hears it; return false to silence it.
+  fn on_chat(&self, _server: &server::T, _player: entity::id::Player, _text: &mut String) -> bool {
+    true
+  }
+}
+
+/// The plugins a server runs, in the order their hooks are called.
+pub struct Registry {
+  plugins : Vec<Box<dyn Plugin>>,
+}
+
+/// A registry without any plugins.
+pub fn new() -> Registry {
+  Registry {
+    plugins : Vec::new(),
+  }
+}
+
+impl Registry {
+  /// Add a plugin. Its hooks are called after those of the plugins registered before it.
+  pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+    info!("Registered plugin {}", plugin.name());
+    self.plugins.push(plugin);
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.plugins.len()
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_empty(&self) -> bool {
+    self.plugins.is_empty()
+  }
+
+  #[allow(missing_docs)]
+  pub fn tick(&self, server: &server::T) {
+    for plugin in &self.plugins {
+      plugin.on_tick(server);
+    }
+  }
+
+  /// Let every plugin have a go at a freshly-generated voxel.
+  pub fn chunk_generated(&self, world: protocol::WorldId, bounds: &voxel::bounds::T, voxel: &mut voxel::T) {
+    for plugin in &self.plugins {
+      plugin.on_chunk_generate(world, bounds, voxel);
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn player_joined(&self, server: &server::T, player: entity::id::Player) {
+    for plugin in &self.plugins {
+      plugin.on_player_join(server, player);
+    }
+  }
+
+  /// Whether every plugin allows an edit. Plugins after the first to refuse aren't asked.
+  pub fn allows_edit(&self, server: &server::T, edit: &VoxelEdit) -> bool {
+    self.plugins.iter().all(|plugin| {
+      let allowed = plugin.on_voxel_edit(server, edit);
+      if !allowed {
+        debug!("{} refused {:?}", plugin.name(), edit);
+      }
+      allowed
+    })
+  }
+
+  /// Pass a chat message through every plugin. Returns the message to send, or None if it's been silenced.
+  pub fn chat(&self, server: &server::T, player: entity::id::Player, text: String) -> Option<String> {
+    let mut text = text;
+    for plugin in &self.plugins {
+      if !plugin.on_chat(server, player, &mut text) {
+        debug!("{} silenced {:?}", plugin.name(), player);
+        return None
+      }
+    }
+    Some(text)
+  }
+}
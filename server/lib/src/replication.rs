@@ -0,0 +1,157 @@
+//! Send each client snapshots of the world's entities, as changes against the last snapshot it acknowledged.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use common::protocol;
+use common::snapshot;
+
+use server;
+
+/// Collect the state of every entity in a world that clients there can see.
+pub fn capture(server: &server::T, id: protocol::WorldId) -> snapshot::World {
+  let mut world = snapshot::empty();
+  world.time_ns = server.clock.now_ns();
+  let physics = server.world(id).physics.lock().unwrap();
+
+  for player in server.players.lock().unwrap().values().filter(|player| player.world == id) {
+    let bounds =
+      match physics.get_bounds(player.physics_id) {
+        None => continue,
+        Some(bounds) => *bounds,
+      };
+    world.players.insert(
+      player.entity_id,
+      protocol::PlayerState {
+        id        : player.entity_id,
+        bounds    : bounds,
+        heading   : player.heading(),
+        animation : player.animation(),
+        health    : player.health,
+      },
+    );
+  }
+
+  for mob in server.mobs.lock().unwrap().values().filter(|mob| mob.world == id) {
+    let bounds =
+      match physics.get_bounds(mob.physics_id) {
+        None => continue,
+        Some(bounds) => *bounds,
+      };
+    world.mobs.insert(
+      mob.entity_id,
+      protocol::MobState {
+        id        : mob.entity_id,
+        bounds    : bounds,
+        heading   : mob.ai.heading,
+        animation : mob.ai.animation,
+        health    : mob.health,
+      },
+    );
+  }
+
+  for item in server.items.lock().unwrap().values().filter(|item| item.world == id) {
+    world.items.insert(item.entity_id, item.state());
+  }
+
+  world
+}
+
+/// What's been sent to one client.
+pub struct T {
+  next_id : u32,
+  /// Snapshots that haven't been acknowledged yet, oldest first.
+  sent    : VecDeque<(protocol::SnapshotId, Arc<snapshot::World>)>,
+  /// The most recent snapshot the client acknowledged.
+  acked   : Option<(protocol::SnapshotId, Arc<snapshot::World>)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    next_id : 0,
+    sent    : VecDeque::new(),
+    acked   : None,
+  }
+}
+
+impl T {
+  /// Make the next snapshot to send this client.
+  pub fn encode(&mut self, world: &Arc<snapshot::World>) -> protocol::Snapshot {
+    let id = protocol::SnapshotId(self.next_id);
+    self.next_id += 1;
+
+    let is_stale =
+      self.acked.as_ref()
+      .map_or(false, |&(acked_id, _)| id.0 - acked_id.0 > snapshot::MAX_BASELINE_AGE);
+    if is_stale {
+      self.acked = None;
+    }
+
+    let snapshot = world.delta(id, self.acked.as_ref().map(|&(id, ref world)| (id, &**world)));
+
+    self.sent.push_back((id, world.clone()));
+    while self.sent.len() > snapshot::MAX_BASELINE_AGE as usize {
+      self.sent.pop_front();
+    }
+
+    snapshot
+  }
+
+  /// The client has a snapshot, so it can be used as a baseline.
+  pub fn ack(&mut self, id: protocol::SnapshotId) {
+    // Anything sent before this snapshot is no use as a baseline anymore.
+    while self.sent.front().map_or(false, |&(sent_id, _)| sent_id < id) {
+      self.sent.pop_front();
+    }
+    if self.sent.front().map_or(false, |&(sent_id, _)| sent_id == id) {
+      self.acked = self.sent.pop_front();
+    }
+  }
+}
+
+#[test]
+fn snapshots_are_sent_against_the_last_ack() {
+  use common::entity;
+  use common::health;
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  let world = |x: f32| {
+    let mut world = snapshot::empty();
+    let id = entity::id::Player::default();
+    let bounds = Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x, 1.0, 0.0));
+    world.players.insert(
+      id,
+      protocol::PlayerState {
+        id        : id,
+        bounds    : bounds,
+        heading   : 0.0,
+        animation : protocol::Animation::Idle,
+        health    : health::full(health::PLAYER_MAX),
+      },
+    );
+    Arc::new(world)
+  };
+
+  let mut replication = new();
+  let first = replication.encode(&world(0.0));
+  assert_eq!(first.baseline, None);
+  let second = replication.encode(&world(0.0));
+  assert_eq!(second.baseline, None);
+
+  replication.ack(first.id);
+  let third = replication.encode(&world(0.0));
+  assert_eq!(third.baseline, Some(first.id));
+  assert!(third.players.is_empty());
+
+  // A late ack doesn't move the baseline backward.
+  replication.ack(third.id);
+  replication.ack(second.id);
+  assert_eq!(replication.encode(&world(1.0)).baseline, Some(third.id));
+
+  for _ in 0 .. snapshot::MAX_BASELINE_AGE {
+    replication.encode(&world(1.0));
+  }
+  assert_eq!(replication.encode(&world(1.0)).baseline, None);
+}
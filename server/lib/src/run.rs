@@ -1,7 +1,7 @@
 use std;
+use std::collections::VecDeque;
 use std::convert::AsRef;
 use std::sync::Mutex;
-use bincode;
 use stopwatch;
 use thread_scoped;
 use time;
@@ -9,41 +9,79 @@ use voxel_data;
 
 use common;
 use common::closure_series;
-use common::socket::ReceiveSocket;
+use common::net;
+use common::protocol;
+use common::wire;
 
-use client_recv_thread::apply_client_update;
+use autosave;
+use client_recv_thread::{apply_client_update, finish_connections, reject_unreadable};
+use config;
+use console;
+use metrics;
+use plugin;
 use server;
+use tick;
 use update_gaia;
 use update_gaia::update_gaia;
 use update_world::update_world;
+use voxel_requests;
 
-mod terrain {
-  pub use ::terrain::*;
-}
+/// How often terrain and entities get written to disk.
+const AUTOSAVE_INTERVAL_SECS: u64 = 10;
 
-const SAVE_TERRAIN: bool = false;
+/// The default number of threads generating voxels for clients.
+pub const DEFAULT_TERRAIN_WORKERS: usize = 4;
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
+/// Run the server until `quit_signal` is set, listening for clients on `listen_url` over `transport`.
+/// Admin commands are taken from `console`, and from TCP connections to `admin_address` if one is given.
+/// If `seed` is given, it's used instead of the one in the settings. Gameplay extensions go in `plugins`.
+/// Fails if `listen_url` can't be listened on.
+pub fn run(
+  listen_url      : &str,
+  transport       : net::Transport,
+  terrain_workers : usize,
+  seed            : Option<u32>,
+  admin_address   : Option<&str>,
+  console         : &console::Queue,
+  plugins         : plugin::Registry,
+  quit_signal     : &Mutex<bool>,
+) -> std::io::Result<()> {
   let gaia_updates = Mutex::new(std::collections::VecDeque::new());
+  // In deterministic mode, what clients send waits here for the next step.
+  let inputs = Mutex::new(VecDeque::new());
 
-  let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
+  let listen_socket = transport.bind(listen_url.as_ref(), None)?;
   let listen_socket = Mutex::new(listen_socket);
 
-  let server = server::new();
+  let mut config = config::load();
+  if let Some(seed) = seed {
+    config.seed = seed;
+  }
+  let metrics_address = config.metrics_address.clone();
+  let admin_secret = config.admin_secret.clone();
+  info!("World seed is {}.", config.seed);
+  info!("Running {} plugins.", plugins.len());
+  let server = server::with_plugins(config, plugins);
   let server = &server;
-
-  let terrain_path = std::path::Path::new("default.terrain");
-
-  println!("Loading terrain from {}", terrain_path.to_str().unwrap());
-  load_terrain(&server.terrain_loader.terrain, &terrain_path);
+  let deterministic = server.clock.is_logical();
+  if deterministic {
+    info!("Running deterministically. The world only steps when told to.");
+  }
 
   let mut threads = Vec::new();
 
   unsafe {
     threads.push(thread_scoped::scoped(|| {
+      let mut ticks = 0;
       while !*quit_signal.lock().unwrap() {
         info!("Outstanding gaia updates: {}", gaia_updates.lock().unwrap().len());
+        info!("Outstanding voxel requests: {}", server.voxel_requests.len());
+        ticks += 1;
+        if ticks % AUTOSAVE_INTERVAL_SECS == 0 {
+          stopwatch::time("autosave", || {
+            autosave::save(server);
+          });
+        }
         std::thread::sleep(std::time::Duration::from_secs(1));
       }
 
@@ -54,12 +92,25 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
   unsafe {
     let server = &server;
     let gaia_updates = &gaia_updates;
+    let inputs = &inputs;
     let listen_socket = &listen_socket;
     threads.push(thread_scoped::scoped(move || {
+      let deferred = if deterministic { Some(inputs) } else { None };
       closure_series::new(vec!(
         quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
+        if deterministic {
+          consider_lockstep_update(
+            &server,
+            transport,
+            inputs,
+            |up| { gaia_updates.lock().unwrap().push_back(up) },
+            || { gaia_updates.lock().unwrap().pop_front() },
+          )
+        } else {
+          consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) })
+        },
+        network_listen(&listen_socket, transport, server, deferred, |up| { gaia_updates.lock().unwrap().push_back(up) }),
+        consider_console_request(console, |up| { gaia_updates.lock().unwrap().push_back(up) }),
         consider_gaia_update(&server, || { gaia_updates.lock().unwrap().pop_front() } ),
       ))
       .until_quit();
@@ -70,20 +121,61 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
   unsafe {
     let server = &server;
     let gaia_updates = &gaia_updates;
+    let inputs = &inputs;
     let quit_signal = &quit_signal;
     let listen_socket = &listen_socket;
     threads.push(thread_scoped::scoped(move || {
-      closure_series::new(vec!(
+      let deferred = if deterministic { Some(inputs) } else { None };
+      let mut closures = vec!(
         quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-      ))
+        network_listen(&listen_socket, transport, server, deferred, |up| { gaia_updates.lock().unwrap().push_back(up) }),
+      );
+      // Lockstep has to happen on the thread running gaia updates, so only that one steps the world.
+      if !deterministic {
+        closures.insert(1, consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }));
+      }
+      closure_series::new(closures)
       .until_quit();
 
       stopwatch::clone()
     }));
   }
 
+  if let Some(admin_address) = admin_address {
+    unsafe {
+      let quit_signal = &quit_signal;
+      threads.push(thread_scoped::scoped(move || {
+        console::admin::listen(admin_address, admin_secret.as_ref().map(|secret| secret.as_str()), console, quit_signal);
+
+        stopwatch::clone()
+      }));
+    }
+  }
+
+  if let Some(ref metrics_address) = metrics_address {
+    unsafe {
+      let server = &server;
+      let quit_signal = &quit_signal;
+      threads.push(thread_scoped::scoped(move || {
+        metrics::serve(metrics_address, server, quit_signal);
+
+        stopwatch::clone()
+      }));
+    }
+  }
+
+  for _ in 0 .. terrain_workers {
+    unsafe {
+      let server = &server;
+      let quit_signal = &quit_signal;
+      threads.push(thread_scoped::scoped(move || {
+        voxel_requests::work(server, quit_signal);
+
+        stopwatch::clone()
+      }));
+    }
+  }
+
   for thread in threads {
     let stopwatch = thread.join();
     stopwatch.print();
@@ -91,19 +183,24 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
 
   info!("Voxel takes {} bytes", std::mem::size_of::<common::voxel::T>());
 
-  println!(
-    "Terrain is using {} MB",
-    tree_ram_usage(&server.terrain_loader.terrain.voxels.lock().unwrap()) as f32 / (1 << 20) as f32,
-  );
+  let ram_usage: usize =
+    server.worlds.iter()
+    .map(|world| tree_ram_usage(&world.terrain_loader.terrain.voxels.lock().unwrap()))
+    .sum();
+  println!("Terrain is using {} MB", ram_usage as f32 / (1 << 20) as f32);
 
-  if SAVE_TERRAIN {
-    println!("Saving terrain to {}", terrain_path.to_str().unwrap());
-    stopwatch::time("save_terrain", || {
-      save_terrain(&server.terrain_loader.terrain, &terrain_path);
-    });
+  // Clients would otherwise just stop hearing from the server, and not know why.
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::ShuttingDown);
   }
 
+  println!("Saving");
+  stopwatch::time("autosave", || {
+    autosave::save(server);
+  });
+
   stopwatch::clone().print();
+  Ok(())
 }
 
 fn quit_upon(signal: &Mutex<bool>) -> closure_series::Closure {
@@ -123,38 +220,144 @@ fn consider_world_update<'a, ToGaia>(
   ToGaia: FnMut(update_gaia::Message) + 'a,
 {
   Box::new(move || {
-    if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
+    // Only one thread ticks at a time; the others get on with networking meanwhile.
+    let mut clock =
+      match server.tick.try_lock() {
+        Ok(clock) => clock,
+        Err(_) => return closure_series::Continue,
+      };
+
+    let steps = clock.advance(time::precise_time_ns());
+    if steps == 0 {
+      return closure_series::Continue
+    }
+    for step in 0 .. steps {
+      let start = time::precise_time_ns();
+      let mut timings = tick::timings();
       update_world(
         server,
         &mut to_gaia,
+        &mut timings,
+        step + 1 < steps,
       );
-      closure_series::Restart
-    } else {
-      closure_series::Continue
+      clock.record(time::precise_time_ns() - start, &timings);
     }
+
+    send_tick_stats(server, &mut clock);
+    closure_series::Restart
   })
 }
 
+/// Run the steps admins have asked for, one at a time. Before each one, everything clients have sent is applied, and
+/// every terrain update waiting is finished, so a step never depends on when things happened to arrive.
+fn consider_lockstep_update<'a, ToGaia, Get>(
+  server: &'a server::T,
+  transport: net::Transport,
+  inputs: &'a Mutex<VecDeque<protocol::ClientToServer>>,
+  mut to_gaia: ToGaia,
+  mut get_update: Get,
+) -> closure_series::Closure<'a> where
+  ToGaia: FnMut(update_gaia::Message) + 'a,
+  Get: FnMut() -> Option<update_gaia::Message> + 'a,
+{
+  Box::new(move || {
+    let step_ns = {
+      let mut clock = server.tick.lock().unwrap();
+      if !clock.take_queued() {
+        return closure_series::Continue
+      }
+      clock.step_ns()
+    };
+
+    loop {
+      let up = inputs.lock().unwrap().pop_front();
+      match up {
+        Some(up) => apply_client_update(server, transport, &mut to_gaia, up),
+        None => break,
+      }
+    }
+    while let Some(up) = get_update() {
+      update_gaia(server, up);
+    }
+
+    server.clock.step(step_ns);
+    let start = time::precise_time_ns();
+    let mut timings = tick::timings();
+    update_world(server, &mut to_gaia, &mut timings, false);
+
+    let mut clock = server.tick.lock().unwrap();
+    clock.record(time::precise_time_ns() - start, &timings);
+    send_tick_stats(server, &mut clock);
+    closure_series::Restart
+  })
+}
+
+fn send_tick_stats(server: &server::T, clock: &mut tick::T) {
+  if let Some(stats) = clock.report() {
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::TickStats(stats));
+    }
+  }
+}
+
+/// Read what clients send, and apply it, or hold it in `deferred` until the next lockstep step.
 fn network_listen<'a, ToGaia>(
-  socket: &'a Mutex<ReceiveSocket>,
+  socket: &'a Mutex<Box<dyn net::Receiver>>,
+  transport: net::Transport,
   server: &'a server::T,
+  deferred: Option<&'a Mutex<VecDeque<protocol::ClientToServer>>>,
   mut to_gaia: ToGaia,
 ) -> closure_series::Closure<'a> where
   ToGaia: FnMut(update_gaia::Message) + 'a,
 {
   Box::new(move || {
+    finish_connections(server);
     match socket.lock().unwrap().try_read() {
-      common::socket::Result::Empty => closure_series::Continue,
-      common::socket::Result::Terminating => closure_series::Quit,
-      common::socket::Result::Success(up) => {
-        let up = bincode::deserialize(up.as_ref()).unwrap();
-        apply_client_update(server, &mut to_gaia, up);
+      net::Result::Empty => closure_series::Continue,
+      net::Result::Terminating => closure_series::Quit,
+      net::Result::Success(up) => {
+        let bytes = up.len();
+        let up: protocol::ClientToServer =
+          match wire::decode(up.as_ref()) {
+            Ok(up) => up,
+            Err(wire::Error::Unknown(tag)) => {
+              debug!("Skipping a kind of message we don't know, tagged {:x}", tag);
+              return closure_series::Restart
+            },
+            Err(wire::Error::Malformed) => {
+              reject_unreadable(server, transport, up.as_ref());
+              return closure_series::Restart
+            },
+          };
+        server.metrics.received(up.name(), bytes);
+        // The socket's still locked, so other listening threads can't queue anything ahead of this.
+        match deferred {
+          Some(deferred) => deferred.lock().unwrap().push_back(up),
+          None => apply_client_update(server, transport, &mut to_gaia, up),
+        }
         closure_series::Restart
       },
     }
   })
 }
 
+fn consider_console_request<'a, ToGaia>(
+  console: &'a console::Queue,
+  mut to_gaia: ToGaia,
+) -> closure_series::Closure<'a> where
+  ToGaia: FnMut(update_gaia::Message) + 'a,
+{
+  Box::new(move || {
+    match console.lock().unwrap().pop_front() {
+      Some(request) => {
+        to_gaia(update_gaia::Message::Console(request));
+        closure_series::Restart
+      },
+      None => closure_series::Continue,
+    }
+  })
+}
+
 fn consider_gaia_update<'a, Get>(
   server: &'a server::T,
   mut get_update: Get,
@@ -172,40 +375,6 @@ fn consider_gaia_update<'a, Get>(
   })
 }
 
-fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file =
-    match std::fs::File::open(path) {
-      Err(err) => {
-        warn!("Error opening terrain file: {:?}", err);
-        return
-      },
-      Ok(file) => file,
-    };
-  let loaded =
-    bincode::deserialize_from(
-      &mut file,
-      bincode::Infinite,
-    );
-  let loaded =
-    match loaded {
-      Ok(loaded) => loaded,
-      Err(err) => {
-        warn!("Error loading terrain: {:?}", err);
-        return
-      },
-    };
-  *terrain.voxels.lock().unwrap() = loaded;
-}
-
-fn save_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file = std::fs::File::create(path).unwrap();
-  bincode::serialize_into(
-    &mut file,
-    &*terrain.voxels.lock().unwrap(),
-    bincode::Infinite,
-  ).unwrap();
-}
-
 fn tree_ram_usage(tree: &common::voxel::tree::T) -> usize {
   fn tree_ram_usage_inner(branches: &common::voxel::tree::Branches, size: &mut usize) {
     *size += std::mem::size_of_val(branches);
@@ -0,0 +1,445 @@
+//! Gameplay scripts, written in Lua and loaded from the `scripts` directory. Each script runs in a sandbox of its
+//! own, with only Lua's base, table, string and math libraries, and defines whichever event handlers it wants as
+//! globals:
+//!
+//!   * `on_tick()`
+//!   * `on_player_join(id, name)`
+//!   * `on_chat(id, text)`: return false to silence the message, or a string to say instead
+//!   * `on_voxel_edit(world, player, low_x, low_y, low_z, high_x, high_y, high_z)`: return false to refuse the edit.
+//!     `player` is nil if the edit isn't one player's doing.
+//!
+//! Scripts can't touch the server directly. What they ask for with `spawn_mob`, `edit_voxels`, `explode` and `chat`
+//! is queued, and done once the handler returns; `players()` lists where every player was when the handler was
+//! called.
+//! A handler that runs too long is stopped.
+//!
+//! Scripts are loaded again when their files change, and picked up or dropped as they're added to or removed from
+//! the directory.
+
+use cgmath::{Point3, Vector3};
+use rlua;
+use std;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::entity;
+use common::material;
+use common::movement;
+use common::protocol;
+use common::voxel;
+
+use brush;
+use explosion;
+use mob;
+use plugin;
+use server;
+use update_gaia;
+use world;
+
+/// Where scripts are loaded from, relative to the working directory.
+pub const DIRECTORY: &'static str = "scripts";
+/// How often to look for changed scripts, in ticks.
+const RELOAD_INTERVAL: usize = movement::UPDATES_PER_SECOND as usize;
+/// How long a handler can run before it's stopped, in milliseconds.
+const TIME_LIMIT_MS: u64 = 20;
+/// How many Lua instructions run between checks of the time limit.
+const INSTRUCTIONS_PER_CHECK: u32 = 1000;
+/// The biggest sphere of voxels a script can change at once.
+const MAX_EDIT_RADIUS: f32 = 32.0;
+
+/// The materials scripts can name, in `Material` order.
+const MATERIALS: [voxel::Material; material::COUNT] = [
+  voxel::Material::Empty,
+  voxel::Material::Terrain,
+  voxel::Material::Bark,
+  voxel::Material::Leaves,
+  voxel::Material::Stone,
+  voxel::Material::Marble,
+  voxel::Material::Sand,
+  voxel::Material::Snow,
+  voxel::Material::Water,
+];
+
+/// Something a script asked for.
+#[derive(Debug, Clone)]
+enum Action {
+  SpawnMob {
+    world      : protocol::WorldId,
+    low_corner : Point3<f32>,
+    aggressive : bool,
+  },
+  EditVoxels {
+    world    : protocol::WorldId,
+    center   : Point3<f32>,
+    radius   : f32,
+    material : voxel::Material,
+  },
+  Explode {
+    world  : protocol::WorldId,
+    center : Point3<f32>,
+    radius : f32,
+  },
+  Chat(String),
+}
+
+/// Where a player was, as `players()` tells scripts.
+#[derive(Debug, Clone)]
+struct Player {
+  id       : u32,
+  name     : String,
+  world    : &'static str,
+  position : Point3<f32>,
+}
+
+/// What scripts share with the server.
+#[derive(Clone)]
+struct Shared {
+  actions  : Arc<Mutex<Vec<Action>>>,
+  players  : Arc<Mutex<Vec<Player>>>,
+  /// When whatever script is running has to be done by.
+  deadline : Arc<Mutex<std::time::Instant>>,
+}
+
+impl Shared {
+  fn start_clock(&self) {
+    *self.deadline.lock().unwrap() = std::time::Instant::now() + std::time::Duration::from_millis(TIME_LIMIT_MS);
+  }
+}
+
+struct Script {
+  path     : std::path::PathBuf,
+  modified : std::time::SystemTime,
+  lua      : rlua::Lua,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  scripts : Mutex<Vec<Script>>,
+  shared  : Shared,
+  ticks   : AtomicUsize,
+}
+
+/// Load every script in `DIRECTORY`.
+pub fn new() -> T {
+  let scripts =
+    T {
+      scripts : Mutex::new(Vec::new()),
+      shared  :
+        Shared {
+          actions  : Arc::new(Mutex::new(Vec::new())),
+          players  : Arc::new(Mutex::new(Vec::new())),
+          deadline : Arc::new(Mutex::new(std::time::Instant::now())),
+        },
+      ticks   : AtomicUsize::new(0),
+    };
+  scripts.reload();
+  scripts
+}
+
+fn runtime_error(message: String) -> rlua::Error {
+  rlua::Error::RuntimeError(message)
+}
+
+fn world_named(name: &str) -> rlua::Result<protocol::WorldId> {
+  world::parse(name).ok_or_else(|| runtime_error(format!("There's no world called {}", name)))
+}
+
+fn material_named(name: &str) -> rlua::Result<voxel::Material> {
+  MATERIALS.iter()
+  .find(|&&material| material::get(material).name == name)
+  .cloned()
+  .ok_or_else(|| runtime_error(format!("There's no material called {}", name)))
+}
+
+/// Give a script's globals the functions it can call.
+fn api(lua: rlua::Context, shared: &Shared) -> rlua::Result<()> {
+  let globals = lua.globals();
+
+  let actions = shared.actions.clone();
+  globals.set(
+    "spawn_mob",
+    lua.create_function(move |_, (world, x, y, z, aggressive): (String, f32, f32, f32, bool)| {
+      let world = world_named(&world)?;
+      let low_corner = Point3::new(x, y, z) - Vector3::new(mob::SIZE.x / 2.0, 0.0, mob::SIZE.z / 2.0);
+      actions.lock().unwrap().push(Action::SpawnMob { world: world, low_corner: low_corner, aggressive: aggressive });
+      Ok(())
+    })?,
+  )?;
+
+  let actions = shared.actions.clone();
+  globals.set(
+    "edit_voxels",
+    lua.create_function(move |_, (world, x, y, z, radius, material): (String, f32, f32, f32, f32, String)| {
+      let world = world_named(&world)?;
+      let material = material_named(&material)?;
+      if !(0.0 < radius && radius <= MAX_EDIT_RADIUS) {
+        return Err(runtime_error(format!("The radius has to be more than 0 and at most {}", MAX_EDIT_RADIUS)))
+      }
+      actions.lock().unwrap().push(
+        Action::EditVoxels { world: world, center: Point3::new(x, y, z), radius: radius, material: material }
+      );
+      Ok(())
+    })?,
+  )?;
+
+  let actions = shared.actions.clone();
+  globals.set(
+    "explode",
+    lua.create_function(move |_, (world, x, y, z, radius): (String, f32, f32, f32, f32)| {
+      let world = world_named(&world)?;
+      if !(0.0 < radius && radius <= explosion::MAX_RADIUS) {
+        return Err(runtime_error(format!("The radius has to be more than 0 and at most {}", explosion::MAX_RADIUS)))
+      }
+      actions.lock().unwrap().push(Action::Explode { world: world, center: Point3::new(x, y, z), radius: radius });
+      Ok(())
+    })?,
+  )?;
+
+  let actions = shared.actions.clone();
+  globals.set(
+    "chat",
+    lua.create_function(move |_, text: String| {
+      actions.lock().unwrap().push(Action::Chat(text));
+      Ok(())
+    })?,
+  )?;
+
+  let players = shared.players.clone();
+  globals.set(
+    "players",
+    lua.create_function(move |lua, ()| {
+      let list = lua.create_table()?;
+      for (i, player) in players.lock().unwrap().iter().enumerate() {
+        let entry = lua.create_table()?;
+        entry.set("id", player.id)?;
+        entry.set("name", player.name.clone())?;
+        entry.set("world", player.world)?;
+        entry.set("x", player.position.x)?;
+        entry.set("y", player.position.y)?;
+        entry.set("z", player.position.z)?;
+        list.set(i + 1, entry)?;
+      }
+      Ok(list)
+    })?,
+  )?;
+
+  Ok(())
+}
+
+/// Load and run a script, so its handlers are defined.
+fn load(path: &std::path::Path, modified: std::time::SystemTime, shared: &Shared) -> Result<Script, String> {
+  let mut source = String::new();
+  std::fs::File::open(path)
+    .and_then(|mut file| file.read_to_string(&mut source))
+    .map_err(|err| err.to_string())?;
+
+  let lua =
+    rlua::Lua::new_with(rlua::StdLib::BASE | rlua::StdLib::TABLE | rlua::StdLib::STRING | rlua::StdLib::MATH);
+  {
+    let deadline = shared.deadline.clone();
+    lua.set_hook(
+      rlua::HookTriggers { every_nth_instruction: Some(INSTRUCTIONS_PER_CHECK), ..Default::default() },
+      move |_, _| {
+        if std::time::Instant::now() > *deadline.lock().unwrap() {
+          Err(runtime_error(format!("Stopped after running for more than {}ms", TIME_LIMIT_MS)))
+        } else {
+          Ok(())
+        }
+      },
+    );
+  }
+
+  let name = path.display().to_string();
+  shared.start_clock();
+  lua.context(|lua| {
+    api(lua, shared)?;
+    lua.load(&source).set_name(&name)?.exec()
+  }).map_err(|err| err.to_string())?;
+
+  Ok(Script {
+    path     : path.to_owned(),
+    modified : modified,
+    lua      : lua,
+  })
+}
+
+impl T {
+  /// Load new and changed scripts, and drop deleted ones. A script that fails to load again keeps its old version.
+  fn reload(&self) {
+    let mut paths: Vec<std::path::PathBuf> =
+      match std::fs::read_dir(DIRECTORY) {
+        // Without a scripts directory, there just aren't any scripts.
+        Err(_) => Vec::new(),
+        Ok(entries) =>
+          entries
+          .filter_map(|entry| entry.ok())
+          .map(|entry| entry.path())
+          .filter(|path| path.extension().map_or(false, |extension| extension == "lua"))
+          .collect(),
+      };
+    paths.sort();
+
+    let mut scripts = self.scripts.lock().unwrap();
+    scripts.retain(|script| {
+      let kept = paths.contains(&script.path);
+      if !kept {
+        info!("Unloaded script {}", script.path.display());
+      }
+      kept
+    });
+
+    for path in paths {
+      let modified =
+        match std::fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+          Err(err) => {
+            warn!("Couldn't read {}: {}", path.display(), err);
+            continue
+          },
+          Ok(modified) => modified,
+        };
+      let loaded = scripts.iter().position(|script| script.path == path);
+      if let Some(i) = loaded {
+        if scripts[i].modified == modified {
+          continue
+        }
+      }
+
+      match (load(&path, modified, &self.shared), loaded) {
+        (Ok(script), Some(i)) => {
+          info!("Reloaded script {}", path.display());
+          scripts[i] = script;
+        },
+        (Ok(script), None) => {
+          info!("Loaded script {}", path.display());
+          scripts.push(script);
+        },
+        (Err(err), Some(i)) => {
+          warn!("Couldn't reload {}: {}. Keeping the old version.", path.display(), err);
+          // Don't try again until it changes again.
+          scripts[i].modified = modified;
+        },
+        (Err(err), None) => {
+          warn!("Couldn't load {}: {}", path.display(), err);
+        },
+      }
+    }
+  }
+
+  /// Call a handler in every script that defines it, passing what each returns to `reply`, then do whatever the
+  /// scripts asked for.
+  fn call<Args, Reply>(&self, server: &server::T, handler: &str, args: Args, mut reply: Reply) where
+    Args  : for<'lua> rlua::ToLuaMulti<'lua> + Clone,
+    Reply : for<'lua> FnMut(rlua::Value<'lua>),
+  {
+    {
+      let scripts = self.scripts.lock().unwrap();
+      if scripts.is_empty() {
+        return
+      }
+
+      *self.shared.players.lock().unwrap() =
+        server.players.lock().unwrap().values()
+        .map(|player| {
+          Player {
+            id       : player.entity_id.to_u32(),
+            name     : player.name.clone(),
+            world    : world::name(player.world),
+            position : player.position,
+          }
+        })
+        .collect();
+
+      for script in scripts.iter() {
+        self.shared.start_clock();
+        let result =
+          script.lua.context(|lua| -> rlua::Result<()> {
+            match lua.globals().get::<_, Option<rlua::Function>>(handler)? {
+              None => {},
+              Some(handler) => reply(handler.call::<_, rlua::Value>(args.clone())?),
+            }
+            Ok(())
+          });
+        if let Err(err) = result {
+          warn!("{} failed in {}: {}", handler, script.path.display(), err);
+        }
+      }
+    }
+
+    self.act(server);
+  }
+
+  /// Do what the scripts have asked for.
+  fn act(&self, server: &server::T) {
+    let actions = std::mem::replace(&mut *self.shared.actions.lock().unwrap(), Vec::new());
+    for action in actions {
+      match action {
+        Action::SpawnMob { world, low_corner, aggressive } => {
+          let temperament = if aggressive { mob::ai::Temperament::Aggressive } else { mob::ai::Temperament::Timid };
+          mob::add(server, world, low_corner, temperament);
+        },
+        Action::EditVoxels { world, center, radius, material } => {
+          let brush = brush::sphere(&center, radius, material);
+          update_gaia::update_gaia(server, update_gaia::Message::Brush(world, brush, None));
+        },
+        Action::Explode { world, center, radius } => {
+          explosion::explode(server, world, center, radius, true);
+        },
+        Action::Chat(text) => {
+          for (_, client) in server.clients.lock().unwrap().iter_mut() {
+            if client.player.is_some() {
+              client.send(protocol::ServerToClient::Notice(text.clone()));
+            }
+          }
+        },
+      }
+    }
+  }
+}
+
+impl plugin::Plugin for T {
+  fn name(&self) -> &str {
+    "scripts"
+  }
+
+  fn on_tick(&self, server: &server::T) {
+    if self.ticks.fetch_add(1, Ordering::Relaxed) % RELOAD_INTERVAL == 0 {
+      self.reload();
+    }
+    self.call(server, "on_tick", (), |_| {});
+  }
+
+  fn on_player_join(&self, server: &server::T, player: entity::id::Player) {
+    let name = server.players.lock().unwrap().get(&player).map(|player| player.name.clone());
+    self.call(server, "on_player_join", (player.to_u32(), name), |_| {});
+  }
+
+  fn on_voxel_edit(&self, server: &server::T, edit: &plugin::VoxelEdit) -> bool {
+    let (low, high) = (edit.bounds.min, edit.bounds.max);
+    let args =
+      (world::name(edit.world), edit.player.map(|player| player.to_u32()), low.x, low.y, low.z, high.x, high.y, high.z);
+    let mut allowed = true;
+    self.call(server, "on_voxel_edit", args, |reply| {
+      if let rlua::Value::Boolean(false) = reply {
+        allowed = false;
+      }
+    });
+    allowed
+  }
+
+  fn on_chat(&self, server: &server::T, player: entity::id::Player, text: &mut String) -> bool {
+    let mut heard = true;
+    let mut replacement = None;
+    self.call(server, "on_chat", (player.to_u32(), text.clone()), |reply| {
+      match reply {
+        rlua::Value::Boolean(false) => heard = false,
+        rlua::Value::String(s) => replacement = s.to_str().ok().map(String::from),
+        _ => {},
+      }
+    });
+    if let Some(replacement) = replacement {
+      *text = replacement;
+    }
+    heard
+  }
+}
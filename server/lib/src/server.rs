@@ -1,113 +1,286 @@
 //! Server state
 
-use cgmath::{Point3};
-use collision::{Aabb3};
+use cgmath::{Point3, Vector3};
 use rand;
-use std::sync::Mutex;
-use time;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::sync::{Arc, Mutex};
 
+use common::compression;
 use common::protocol;
 use common::fnv_map;
 use common::id_allocator;
-use common::interval_timer::IntervalTimer;
-use common::socket::SendSocket;
+use common::journal;
+use common::movement;
+use common::net;
+use common::voxel;
+use common::wire;
 
+use autosave;
+use client_recv_thread;
+use clock;
+use config;
 use entity;
-use init_mobs::init_mobs;
+use history;
+use interest;
+use item;
 use lod;
+use metrics;
 use mob;
-use physics;
 use player;
+use plugin;
+use replication;
+use storage;
 use sun::Sun;
-use terrain_loader;
+use teleport;
+use tick;
+use voxel_requests;
+use world;
 
-const UPDATES_PER_SECOND: u64 = 30;
 const SUN_TICK_NS: u64 = 1600000;
 
 /// Client handle
 pub struct Client {
   /// Socket to the client
-  pub socket: SendSocket,
+  pub socket: Box<dyn net::Sender>,
+  /// How to compress large payloads sent to this client.
+  pub compression: compression::Scheme,
+  /// The player this client controls, once it's been added.
+  pub player: Option<entity::id::Player>,
+  /// The entity snapshots sent to this client.
+  pub replication: replication::T,
+  /// The part of the world this client is sent changes to.
+  pub interest: interest::T,
+  /// Where messages sent to this client are counted.
+  pub metrics: Arc<metrics::T>,
+  /// The token this client can resume its session with, over a new connection.
+  pub session: protocol::SessionToken,
+  /// The optional protocol features this connection uses.
+  pub features: Vec<protocol::Feature>,
+  /// The weather last sent to this client, so it's only sent again once it changes.
+  pub weather: Option<protocol::Weather>,
+  /// The sun's `changes`, and whether the player's world had a sky, as of the last `SetSky` sent to this client.
+  pub sky: Option<(u32, bool)>,
 }
 
 impl Client {
   #[allow(missing_docs)]
   pub fn send(&mut self, msg: protocol::ServerToClient) {
-    use bincode::serialize;
-    let msg = serialize(&msg, bincode::Infinite).unwrap();
+    let name = msg.name();
+    let msg = wire::encode(&msg);
+    self.metrics.sent(name, msg.len());
     match self.socket.write(msg.as_ref()) {
       Ok(()) => {},
       Err(err) => warn!("Error sending to client: {:?}", err),
     }
   }
+
+  /// Send the voxels that changed within this client's range, and the versions they brought their chunks to.
+  pub fn send_voxels(
+    &mut self,
+    world    : protocol::WorldId,
+    updates  : &[(voxel::bounds::T, voxel::T)],
+    versions : &[(Point3<i32>, u32)],
+  ) {
+    if world != self.interest.world() {
+      return
+    }
+    let updates = self.interest.voxels_updated(updates);
+    if updates.is_empty() {
+      return
+    }
+    let versions =
+      versions.iter()
+      .filter(|&&(chunk, _)| updates.iter().any(|&(bounds, _)| journal::chunk(&bounds) == Some(chunk)))
+      .cloned()
+      .collect();
+    let updates = compression::compress(self.compression, &updates);
+    self.send(protocol::ServerToClient::VoxelsUpdated(updates, versions));
+  }
+
+  /// Send every light in a world the client's player has just arrived in.
+  pub fn send_lights(&mut self, world: &world::T) {
+    for (&id, &light) in world.lights.lock().unwrap().iter() {
+      self.send(protocol::ServerToClient::AddLight(id, light));
+    }
+  }
 }
 
 // TODO: Audit for s/Mutex/RwLock.
 #[allow(missing_docs)]
 pub struct T {
-  pub players           : Mutex<fnv_map::T<entity::id::Player, player::T>>,
-  pub mobs              : Mutex<fnv_map::T<entity::id::Mob, mob::Mob>>,
+  /// Players, mobs and items each hand out their own ids.
+  pub players           : Mutex<storage::T<entity::types::Player, player::T>>,
+  pub mobs              : Mutex<storage::T<entity::types::Mob, mob::Mob>>,
+  pub items             : Mutex<storage::T<entity::types::Item, item::T>>,
+  /// Players from the last save that haven't joined since.
+  pub saved_players     : Mutex<fnv_map::T<entity::id::Player, autosave::Player>>,
 
-  pub player_allocator  : Mutex<id_allocator::T<entity::id::Player>>,
-  pub mob_allocator     : Mutex<id_allocator::T<entity::id::Mob>>,
   pub terrain_allocator : Mutex<id_allocator::T<entity::id::Terrain>>,
   pub misc_allocator    : Mutex<id_allocator::T<entity::id::Misc>>,
   pub owner_allocator   : Mutex<id_allocator::T<lod::OwnerId>>,
   pub client_allocator  : Mutex<id_allocator::T<protocol::ClientId>>,
+  pub light_allocator   : Mutex<id_allocator::T<protocol::LightId>>,
 
-  pub physics           : Mutex<physics::T>,
-  pub terrain_loader    : terrain_loader::T,
+  /// Indexed by `WorldId`.
+  pub worlds            : Vec<world::T>,
+  pub voxel_requests    : voxel_requests::T,
   pub rng               : Mutex<rand::StdRng>,
+  /// The seed everything random in the world comes from.
+  pub seed              : u32,
+  /// How many of their brush edits each player can undo.
+  pub undo_history      : usize,
 
   pub clients           : Mutex<fnv_map::T<protocol::ClientId, Client>>,
+  /// Connections back to clients that are still being opened.
+  pub connecting        : Mutex<Vec<client_recv_thread::Connecting>>,
+  /// Named places players can teleport to.
+  pub teleports         : Mutex<teleport::T>,
 
+  pub spawner           : Mutex<mob::spawning::T>,
   pub sun               : Mutex<Sun>,
-  pub update_timer      : Mutex<IntervalTimer>,
+  /// The fixed timestep the world's simulated at.
+  pub tick              : Mutex<tick::T>,
+  /// What the world's systems take the time from.
+  pub clock             : clock::T,
+  /// Counts of messages, bytes and chunk timings, for scraping.
+  pub metrics           : Arc<metrics::T>,
+  /// Gameplay extensions, whose hooks are called as things happen.
+  pub plugins           : Arc<plugin::Registry>,
 }
 
 #[allow(missing_docs)]
-pub fn new() -> T {
-  let world_width: u32 = 1 << 11;
-  let world_width = world_width as f32;
-  let physics =
-    physics::T::new(
-      Aabb3::new(
-        Point3 { x: -world_width, y: -512.0, z: -world_width },
-        Point3 { x: world_width, y: 512.0, z: world_width },
-      )
-    );
+pub fn new(config: config::T) -> T {
+  with_plugins(config, plugin::new())
+}
 
+/// A server running some plugins.
+pub fn with_plugins(config: config::T, plugins: plugin::Registry) -> T {
+  let plugins = Arc::new(plugins);
+  let clock = clock::new(config.deterministic);
   let server = T {
-    players           : Mutex::new(fnv_map::new()),
-    mobs              : Mutex::new(fnv_map::new()),
+    players           : Mutex::new(storage::new()),
+    mobs              : Mutex::new(storage::new()),
+    items             : Mutex::new(storage::new()),
+    saved_players     : Mutex::new(fnv_map::new()),
 
-    player_allocator  : Mutex::new(id_allocator::new()),
-    mob_allocator     : Mutex::new(id_allocator::new()),
     terrain_allocator : Mutex::new(id_allocator::new()),
     misc_allocator    : Mutex::new(id_allocator::new()),
     owner_allocator   : Mutex::new(id_allocator::new()),
     client_allocator  : Mutex::new(id_allocator::new()),
+    light_allocator   : Mutex::new(id_allocator::new()),
 
-    physics: Mutex::new(physics),
-    terrain_loader: terrain_loader::T::new(),
+    worlds: world::all(&config, &plugins, clock.now_ns()),
+    voxel_requests: voxel_requests::new(),
     rng: {
-      let seed = [0];
+      let seed = [config.seed as usize];
       let seed: &[usize] = &seed;
       Mutex::new(rand::SeedableRng::from_seed(seed))
     },
 
+    seed: config.seed,
+    undo_history: config.undo_history.unwrap_or(history::DEFAULT_LENGTH),
+
     clients: Mutex::new(fnv_map::new()),
-    sun: Mutex::new(Sun::new(SUN_TICK_NS)),
-
-    update_timer: {
-      let now = time::precise_time_ns();
-      let nanoseconds_per_second = 1000000000;
-      Mutex::new(
-        IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
-      )
-    }
+    connecting: Mutex::new(Vec::new()),
+    teleports: Mutex::new(teleport::new()),
+    spawner: Mutex::new(mob::spawning::new(config.spawning)),
+    sun: Mutex::new(Sun::new(SUN_TICK_NS, clock.now_ns())),
+
+    tick: Mutex::new(tick::new(movement::UPDATES_PER_SECOND)),
+    clock: clock,
+    metrics: Arc::new(metrics::new()),
+    plugins: plugins,
   };
 
-  init_mobs(&server);
+  autosave::restore(&server);
   server
 }
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn world(&self, id: protocol::WorldId) -> &world::T {
+    &self.worlds[id.0 as usize]
+  }
+
+  /// Tell a player something, e.g. why what they asked for didn't happen.
+  pub fn notify(&self, player_id: entity::id::Player, message: &str) {
+    for (_, client) in self.clients.lock().unwrap().iter_mut() {
+      if client.player == Some(player_id) {
+        client.send(protocol::ServerToClient::Notice(message.to_owned()));
+      }
+    }
+  }
+
+  /// A hash of the simulated state: the sun, each world's weather and lights, and every player, mob and item.
+  /// Deterministic runs given the same inputs always agree on it, so runs can be compared by their hashes.
+  pub fn state_hash(&self) -> u64 {
+    fn point<H: Hasher>(h: &mut H, p: Point3<f32>) {
+      h.write_u32(p.x.to_bits());
+      h.write_u32(p.y.to_bits());
+      h.write_u32(p.z.to_bits());
+    }
+    fn vector<H: Hasher>(h: &mut H, v: Vector3<f32>) {
+      point(h, Point3::new(v.x, v.y, v.z));
+    }
+
+    let mut h = DefaultHasher::new();
+    {
+      let sun = self.sun.lock().unwrap();
+      h.write_u16(sun.position);
+      h.write_u32(sun.day);
+    }
+
+    for world in &self.worlds {
+      world.weather.lock().unwrap().hash(&mut h);
+      let lights = world.lights.lock().unwrap();
+      let mut ids: Vec<_> = lights.keys().cloned().collect();
+      ids.sort();
+      for id in ids {
+        h.write_u32(id.to_u32());
+        point(&mut h, lights[&id].position);
+        h.write_u32(lights[&id].radius.to_bits());
+      }
+    }
+
+    // Packed order depends on what's been removed, so go by ID instead.
+    let players = self.players.lock().unwrap();
+    let mut ids: Vec<_> = players.keys().cloned().collect();
+    ids.sort();
+    for id in ids {
+      let player = &players[&id];
+      h.write_u32(id.to_u32());
+      h.write_u32(player.world.0);
+      point(&mut h, player.position);
+      vector(&mut h, player.movement.speed);
+      h.write_u32(player.lateral_rotation.to_bits());
+      h.write_u32(player.vertical_rotation.to_bits());
+      h.write_u32(player.health.current);
+    }
+
+    let mobs = self.mobs.lock().unwrap();
+    let mut ids: Vec<_> = mobs.keys().cloned().collect();
+    ids.sort();
+    for id in ids {
+      let mob = &mobs[&id];
+      h.write_u32(id.to_u32());
+      h.write_u32(mob.world.0);
+      point(&mut h, mob.position);
+      vector(&mut h, mob.speed);
+      h.write_u32(mob.health.current);
+    }
+
+    let items = self.items.lock().unwrap();
+    let mut ids: Vec<_> = items.keys().cloned().collect();
+    ids.sort();
+    for id in ids {
+      let item = &items[&id];
+      h.write_u32(id.to_u32());
+      h.write_u32(item.world.0);
+      point(&mut h, item.body.position);
+      vector(&mut h, item.body.velocity);
+    }
+
+    h.finish()
+  }
+}
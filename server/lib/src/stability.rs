@@ -0,0 +1,100 @@
+//! Terrain that's been cut off from the ground falls. After an edit, the solid voxels around it are searched for
+//! pieces that aren't attached to anything else. The search is bounded: once a piece grows past `MAX_FRAGMENT`
+//! voxels, it's taken to be part of the ground, so only small pieces ever break off.
+
+use cgmath::{Point3, Vector3};
+use std::collections::VecDeque;
+
+use common::fnv_map;
+use common::fnv_set;
+
+/// The most voxels that can break off in one piece.
+pub const MAX_FRAGMENT: usize = 128;
+
+const NEIGHBORS: [Vector3<i32>; 6] = [
+  Vector3 { x: -1, y: 0, z: 0 },
+  Vector3 { x: 1, y: 0, z: 0 },
+  Vector3 { x: 0, y: -1, z: 0 },
+  Vector3 { x: 0, y: 1, z: 0 },
+  Vector3 { x: 0, y: 0, z: -1 },
+  Vector3 { x: 0, y: 0, z: 1 },
+];
+
+/// The pieces of solid terrain touching the voxels from `low` up to `high` that aren't attached to the ground.
+/// Voxels touch when they share a face.
+pub fn fragments<IsSolid>(low: &Point3<i32>, high: &Point3<i32>, mut is_solid: IsSolid) -> Vec<Vec<Point3<i32>>> where
+  IsSolid: FnMut(&Point3<i32>) -> bool,
+{
+  let mut solid = fnv_map::new();
+  let mut is_solid = |p: &Point3<i32>| *solid.entry(*p).or_insert_with(|| is_solid(p));
+  // Voxels already known to be part of the ground, or of a fragment.
+  let mut found = fnv_set::new();
+  let mut fragments = Vec::new();
+
+  for x in low.x .. high.x {
+  for y in low.y .. high.y {
+  for z in low.z .. high.z {
+    let start = Point3::new(x, y, z);
+    if found.contains(&start) || !is_solid(&start) {
+      continue
+    }
+
+    let mut piece = vec!(start);
+    let mut seen = fnv_set::new();
+    seen.insert(start);
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    let mut grounded = false;
+    while let Some(p) = queue.pop_front() {
+      for d in &NEIGHBORS {
+        let n = p + d;
+        if seen.contains(&n) {
+          continue
+        }
+        // Fragments are found whole, so anything found already that this touches is the ground.
+        if found.contains(&n) {
+          grounded = true;
+          break
+        }
+        if is_solid(&n) {
+          seen.insert(n);
+          piece.push(n);
+          queue.push_back(n);
+        }
+      }
+      if grounded || piece.len() > MAX_FRAGMENT {
+        grounded = true;
+        break
+      }
+    }
+
+    found.extend(piece.iter().cloned());
+    if !grounded {
+      fragments.push(piece);
+    }
+  }}}
+
+  fragments
+}
+
+#[test]
+fn floating_pieces_break_off() {
+  let is_solid = |p: &Point3<i32>| {
+    // The ground, a pillar standing on it, and a 2x2x2 block floating over it.
+    p.y < 0 ||
+    (p.x == 0 && p.z == 0 && p.y < 4) ||
+    (2 <= p.x && p.x < 4 && 5 <= p.y && p.y < 7 && 2 <= p.z && p.z < 4)
+  };
+
+  let pieces = fragments(&Point3::new(-1, -1, -1), &Point3::new(5, 8, 5), is_solid);
+  assert_eq!(pieces.len(), 1);
+  let mut piece = pieces[0].clone();
+  piece.sort_by_key(|p| (p.x, p.y, p.z));
+  assert_eq!(piece.len(), 8);
+  assert_eq!(piece[0], Point3::new(2, 5, 2));
+  assert_eq!(piece[7], Point3::new(3, 6, 3));
+
+  // Nothing's floating once the block is big enough to count as ground.
+  let big = |p: &Point3<i32>| p.y >= 5 && p.y < 10;
+  assert!(fragments(&Point3::new(0, 4, 0), &Point3::new(2, 6, 2), big).is_empty());
+}
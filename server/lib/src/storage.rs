@@ -0,0 +1,201 @@
+//! Dense storage for one kind of entity, addressed by generational ids.
+//!
+//! Entities are packed together in one `Vec`, so systems walk them contiguously, and removing one moves the last into
+//! its place. The storage hands out the ids itself. An id's number is the slot saying where its entity is packed, and
+//! once the entity's removed, the number's handed out again a generation on; an id kept around after its entity is
+//! gone finds nothing, instead of whatever reuses its slot.
+
+use std;
+
+use common::id_allocator;
+
+use entity;
+
+#[allow(missing_docs)]
+pub struct T<U, C> {
+  allocator : id_allocator::T<entity::id::T<U>>,
+  /// Where each slot's entity is packed, if it has one.
+  slots     : Vec<Option<usize>>,
+  /// The id of each packed entity.
+  ids       : Vec<entity::id::T<U>>,
+  entities  : Vec<C>,
+}
+
+#[allow(missing_docs)]
+pub fn new<U, C>() -> T<U, C> {
+  T {
+    allocator : id_allocator::new(),
+    slots     : Vec::new(),
+    ids       : Vec::new(),
+    entities  : Vec::new(),
+  }
+}
+
+fn same<U: Copy>(a: entity::id::T<U>, b: entity::id::T<U>) -> bool {
+  a.to_u32() == b.to_u32() && a.generation() == b.generation()
+}
+
+impl<U: Copy, C> T<U, C> {
+  /// Pick an id for a new entity, to `insert` it under once it's made.
+  pub fn allocate(&mut self) -> entity::id::T<U> {
+    self.allocator.allocate()
+  }
+
+  fn index(&self, id: &entity::id::T<U>) -> Option<usize> {
+    match self.slots.get(id.to_u32() as usize) {
+      Some(&Some(i)) if same(self.ids[i], *id) => Some(i),
+      _ => None,
+    }
+  }
+
+  /// The live id with this number, whatever its generation, e.g. for admins who name entities by number.
+  pub fn current(&self, number: u32) -> Option<entity::id::T<U>> {
+    match self.slots.get(number as usize) {
+      Some(&Some(i)) => Some(self.ids[i]),
+      _ => None,
+    }
+  }
+
+  /// Put an entity under an id from `allocate`, and return the one that was there before, if there was one.
+  /// Panics if another generation of the id is in its slot.
+  pub fn insert(&mut self, id: entity::id::T<U>, entity: C) -> Option<C> {
+    let slot = id.to_u32() as usize;
+    if self.slots.len() <= slot {
+      self.slots.resize(slot + 1, None);
+    }
+    match self.slots[slot] {
+      Some(i) => {
+        assert!(same(self.ids[i], id), "Inserting under stale id {:?}", (id.to_u32(), id.generation()));
+        Some(std::mem::replace(&mut self.entities[i], entity))
+      },
+      None => {
+        self.slots[slot] = Some(self.entities.len());
+        self.ids.push(id);
+        self.entities.push(entity);
+        None
+      },
+    }
+  }
+
+  /// Take an entity out. Its id goes stale, and its slot is reused under a new one.
+  pub fn remove(&mut self, id: &entity::id::T<U>) -> Option<C> {
+    let i = self.index(id)?;
+    self.slots[id.to_u32() as usize] = None;
+    self.allocator.free(*id);
+
+    self.ids.swap_remove(i);
+    let entity = self.entities.swap_remove(i);
+    if i < self.ids.len() {
+      self.slots[self.ids[i].to_u32() as usize] = Some(i);
+    }
+    Some(entity)
+  }
+
+  /// Keep only the entities `keep` returns true for.
+  pub fn retain<F>(&mut self, mut keep: F) where F: FnMut(&entity::id::T<U>, &mut C) -> bool {
+    let mut i = 0;
+    while i < self.entities.len() {
+      if keep(&self.ids[i], &mut self.entities[i]) {
+        i += 1;
+      } else {
+        // The last entity moves into `i`, so look at `i` again.
+        let id = self.ids[i];
+        self.remove(&id);
+      }
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn get(&self, id: &entity::id::T<U>) -> Option<&C> {
+    self.index(id).map(move |i| &self.entities[i])
+  }
+
+  #[allow(missing_docs)]
+  pub fn get_mut(&mut self, id: &entity::id::T<U>) -> Option<&mut C> {
+    match self.index(id) {
+      None => None,
+      Some(i) => Some(&mut self.entities[i]),
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn contains_key(&self, id: &entity::id::T<U>) -> bool {
+    self.index(id).is_some()
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.entities.len()
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_empty(&self) -> bool {
+    self.entities.is_empty()
+  }
+
+  /// Every entity and its id, in packed order.
+  pub fn iter(&self) -> std::iter::Zip<std::slice::Iter<entity::id::T<U>>, std::slice::Iter<C>> {
+    self.ids.iter().zip(self.entities.iter())
+  }
+
+  #[allow(missing_docs)]
+  pub fn iter_mut(&mut self) -> std::iter::Zip<std::slice::Iter<entity::id::T<U>>, std::slice::IterMut<C>> {
+    self.ids.iter().zip(self.entities.iter_mut())
+  }
+
+  #[allow(missing_docs)]
+  pub fn keys(&self) -> std::slice::Iter<entity::id::T<U>> {
+    self.ids.iter()
+  }
+
+  #[allow(missing_docs)]
+  pub fn values(&self) -> std::slice::Iter<C> {
+    self.entities.iter()
+  }
+
+  #[allow(missing_docs)]
+  pub fn values_mut(&mut self) -> std::slice::IterMut<C> {
+    self.entities.iter_mut()
+  }
+}
+
+impl<'a, U: Copy, C> std::ops::Index<&'a entity::id::T<U>> for T<U, C> {
+  type Output = C;
+  fn index(&self, id: &entity::id::T<U>) -> &C {
+    self.get(id).unwrap_or_else(|| panic!("No entity {}", id.to_u32()))
+  }
+}
+
+#[test]
+fn stale_ids_find_nothing() {
+  #[derive(Debug, Clone, Copy, PartialEq)]
+  struct Thing;
+  let mut storage: T<Thing, &str> = new();
+  let a = storage.allocate();
+  storage.insert(a, "a");
+  let b = storage.allocate();
+  storage.insert(b, "b");
+  let c = storage.allocate();
+  storage.insert(c, "c");
+
+  // `c` moves into `a`'s place.
+  assert_eq!(storage.remove(&a), Some("a"));
+  assert_eq!(storage.remove(&a), None);
+  assert_eq!(storage.get(&c), Some(&"c"));
+  assert_eq!(storage.values().cloned().collect::<Vec<_>>(), vec!("c", "b"));
+
+  // `d` reuses `a`'s slot, but `a` still doesn't find it.
+  let d = storage.allocate();
+  storage.insert(d, "d");
+  assert!(d != a);
+  assert_eq!(storage.current(a.to_u32()), Some(d));
+  assert!(!storage.contains_key(&a));
+  assert_eq!(storage[&d], "d");
+
+  storage.retain(|_, &mut name| name != "b");
+  assert_eq!(storage.len(), 2);
+  assert_eq!(storage.get(&b), None);
+  assert_eq!(storage.current(b.to_u32()), None);
+  assert_eq!(storage.get(&c), Some(&"c"));
+  assert_eq!(storage.get(&d), Some(&"d"));
+}
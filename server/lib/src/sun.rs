@@ -1,41 +1,84 @@
 use common::interval_timer::IntervalTimer;
-use time;
-use std;
+use common::season;
 
 pub struct Sun {
   // The sun as portions of a 65536-degree circle.
   pub position: u16,
+  // The number of days that have gone by.
+  pub day: u32,
   pub timer: IntervalTimer,
   pub print_timer: IntervalTimer,
+  // How long the sun takes to move one degree, in ns.
+  pub tick_ns: u64,
+  // How many times the sun's been moved by hand, so clients can be told when it jumps.
+  pub changes: u32,
 }
 
 impl Sun {
-  pub fn new(tick_ns: u64) -> Sun {
+  pub fn new(tick_ns: u64, now: u64) -> Sun {
     Sun {
       position: 0,
-      timer: IntervalTimer::new(tick_ns, time::precise_time_ns()),
-      print_timer: IntervalTimer::new(2e9 as u64, time::precise_time_ns()),
+      day: 0,
+      timer: IntervalTimer::new(tick_ns, now),
+      print_timer: IntervalTimer::new(2e9 as u64, now),
+      tick_ns: tick_ns,
+      changes: 0,
     }
   }
 
-  pub fn update(&mut self) -> Option<f32> {
-    let ticks = self.timer.update(time::precise_time_ns());
+  // How far through the day it is, in [0, 1).
+  fn fraction(&self) -> f32 {
+    (self.position as f32) / 65536.0
+  }
+
+  // How many days have gone by, counting the part of today that has; see `season::at`.
+  pub fn days(&self) -> f64 {
+    self.day as f64 + self.fraction() as f64
+  }
+
+  // How fast the days go by, in days per second.
+  pub fn rate(&self) -> f64 {
+    1e9 / (self.tick_ns as f64 * 65536.0)
+  }
+
+  // How far through the year it is, in [0, 1); see `season`.
+  pub fn season(&self) -> f32 {
+    ((self.day % season::DAYS_PER_YEAR) as f32 + self.fraction()) / season::DAYS_PER_YEAR as f32
+  }
+
+  // Move the sun to some time of day, as `update` returns it, keeping the day.
+  pub fn set_time_of_day(&mut self, time_of_day: f32) {
+    let fraction = season::clock(time_of_day, self.season());
+    self.position = (fraction * 65536.0) as u16;
+    self.changes += 1;
+  }
+
+  // Skip to the day some portion of the way through the year, keeping the time of day.
+  pub fn set_season(&mut self, season: f32) {
+    let year = self.day - self.day % season::DAYS_PER_YEAR;
+    self.day = year + (season * season::DAYS_PER_YEAR as f32) as u32;
+    self.changes += 1;
+  }
+
+  // Move the sun along to `now`. Returns whether it moved.
+  pub fn update(&mut self, now: u64) -> bool {
+    let ticks = self.timer.update(now);
 
     if ticks == 0 {
-      return None;
+      return false;
     }
 
-    self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks as u16)).0;
+    let position = self.position as u64 + ticks;
+    self.day = self.day.wrapping_add((position >> 16) as u32);
+    self.position = position as u16;
 
-    // Fraction completed of a full cycle.
-    let fraction = (self.position as f32) / 65536.0;
-    // Longer day, shorter night.
-    let fraction = fraction * fraction;
+    let season = self.season();
+    let time_of_day = season::time_of_day(self.fraction(), season);
 
-    if self.print_timer.update(time::precise_time_ns()) > 0 {
-      debug!("Sun is at {:.1}%.", fraction * 100.0);
+    if self.print_timer.update(now) > 0 {
+      debug!("Sun is at {:.1}%, {:.1}% through the year.", time_of_day * 100.0, season * 100.0);
     }
 
-    Some(fraction)
+    true
   }
 }
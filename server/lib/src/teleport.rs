@@ -0,0 +1,125 @@
+//! Named places players can teleport to. Admins set them, and they're saved with the world.
+//! The one named `spawn`, if there is one, is where players join and respawn.
+
+use cgmath::Point3;
+use std;
+use std::collections::BTreeMap;
+
+use common::movement;
+use common::protocol;
+
+use entity;
+use player;
+use server;
+use world;
+
+/// The name of the point players join and respawn at.
+pub const SPAWN: &'static str = "spawn";
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+  pub world    : protocol::WorldId,
+  /// Where a player's center ends up.
+  pub position : Point3<f32>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// By name, in order, so they're listed and saved the same way every time.
+  points : BTreeMap<String, Point>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    points : BTreeMap::new(),
+  }
+}
+
+impl T {
+  /// Add a point, or move the one with this name. Returns where it was before, if it was already set.
+  pub fn set(&mut self, name: &str, point: Point) -> Option<Point> {
+    self.points.insert(name.to_owned(), point)
+  }
+
+  #[allow(missing_docs)]
+  pub fn remove(&mut self, name: &str) -> Option<Point> {
+    self.points.remove(name)
+  }
+
+  #[allow(missing_docs)]
+  pub fn get(&self, name: &str) -> Option<Point> {
+    self.points.get(name).cloned()
+  }
+
+  /// Every point, by name.
+  pub fn iter(&self) -> std::collections::btree_map::Iter<String, Point> {
+    self.points.iter()
+  }
+
+  /// Where players join and respawn: the point named `spawn`, or a spot in the overworld if there isn't one.
+  pub fn spawn(&self) -> Point {
+    self.get(SPAWN).unwrap_or_else(|| {
+      Point {
+        world    : world::OVERWORLD,
+        position : player::SPAWN_CORNER + movement::PLAYER_SIZE / 2.0,
+      }
+    })
+  }
+
+  /// The names of every point, for telling players what there is.
+  pub fn names(&self) -> Vec<&str> {
+    self.points.keys().map(|name| name.as_str()).collect()
+  }
+}
+
+/// Move a player to a point, possibly in another world, and tell its client to start over around it.
+/// Returns false if there's no such player.
+pub fn teleport(server: &server::T, player_id: entity::id::Player, point: Point) -> bool {
+  let changed_world;
+  {
+    let mut players = server.players.lock().unwrap();
+    let player =
+      match players.get_mut(&player_id) {
+        None => return false,
+        Some(player) => player,
+      };
+    changed_world = player.world != point.world;
+    player.move_to(server, point.world, point.position);
+  }
+
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    if client.player != Some(player_id) {
+      continue
+    }
+    if changed_world {
+      // Everything the client's loaded is from the old world.
+      client.interest.enter(point.world, point.position);
+      client.send(protocol::ServerToClient::ChangedWorld { world: point.world, position: point.position });
+      client.send_lights(server.world(point.world));
+    } else {
+      client.interest.move_to(point.position);
+      client.send(protocol::ServerToClient::Teleported(point.position));
+    }
+  }
+  true
+}
+
+#[test]
+fn spawn_falls_back_to_the_overworld() {
+  let mut points = new();
+  assert_eq!(points.spawn().world, world::OVERWORLD);
+
+  let camp = Point { world: world::CAVES, position: Point3::new(1.0, -40.0, 2.0) };
+  assert_eq!(points.set("camp", camp), None);
+  assert_eq!(points.set(SPAWN, camp), None);
+  assert_eq!(points.spawn(), camp);
+  assert_eq!(points.names(), vec!("camp", "spawn"));
+
+  let moved = Point { world: world::OVERWORLD, position: Point3::new(0.5, 70.0, 0.5) };
+  assert_eq!(points.set(SPAWN, moved), Some(camp));
+  assert_eq!(points.remove("camp"), Some(camp));
+  assert_eq!(points.get("camp"), None);
+  assert_eq!(points.spawn(), moved);
+}
@@ -1,40 +1,110 @@
 use collision::{Aabb3};
-use std::sync::Mutex;
+use std;
+use std::sync::{Arc, Mutex};
 use stopwatch;
 use time;
 
 use common::fnv_map;
 use common::id_allocator;
+use common::protocol;
 use common::voxel;
 
+use config;
 use entity;
 use in_progress_terrain;
 use lod;
+use persistence;
 use physics;
+use plugin;
 use terrain;
 use update_gaia;
 use update_gaia::LoadDestination;
+use world;
 
 // TODO: Consider factoring this logic such that what to load is separated from how it's loaded.
 
+/// Where the overworld's generated and modified voxels are stored on disk. Other worlds are stored next to it.
+const WORLD_PATH: &'static str = "world";
+
 /// Load and unload terrain::TerrainBlocks from the game.
 /// Each terrain::TerrainBlock can be owned by a set of owners, each of which can independently request LODs.
 /// The maximum lod::T requested is the one that is actually loaded.
 pub struct T {
+  /// The world this terrain is in.
+  pub world               : protocol::WorldId,
   pub terrain             : terrain::T,
   pub in_progress_terrain : Mutex<in_progress_terrain::T>,
   pub lod_map             : Mutex<lod::Map>,
   pub loaded              : Mutex<fnv_map::T<voxel::bounds::T, Vec<entity::id::Terrain>>>,
+  pub store               : Mutex<persistence::T>,
+  /// Plugins get to change newly-generated voxels.
+  plugins                 : Arc<plugin::Registry>,
 }
 
 impl T {
-  pub fn new() -> T {
+  pub fn new(
+    world   : protocol::WorldId,
+    name    : &str,
+    shape   : terrain::Shape,
+    config  : &config::T,
+    plugins : Arc<plugin::Registry>,
+  ) -> T {
+    let path =
+      if world == world::OVERWORLD {
+        WORLD_PATH.to_owned()
+      } else {
+        format!("{}-{}", WORLD_PATH, name)
+      };
+    let version = persistence::generator_version(std::path::Path::new(&path));
+    if version != terrain::LATEST_VERSION {
+      info!("Generating the {} with {:?} noise, to match the terrain already stored.", name, version);
+    }
     T {
-      terrain             : terrain::T::new(terrain::Seed::new(0)),
+      world               : world,
+      terrain             : terrain::T::new(terrain::Seed::new(config.seed), version, shape),
       in_progress_terrain : Mutex::new(in_progress_terrain::T::new()),
       lod_map             : Mutex::new(lod::Map::new()),
       loaded              : Mutex::new(fnv_map::new()),
+      store               : Mutex::new(persistence::new(std::path::Path::new(&path))),
+      plugins             : plugins,
+    }
+  }
+
+  /// Load a single voxel, checking memory, then the on-disk store, before generating it.
+  pub fn load_voxel(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    self.load_voxel_from(bounds, |bounds| self.terrain.load(bounds))
+  }
+
+  /// Like `load_voxel`, but generate using a specific generator.
+  pub fn load_voxel_with(&self, generator: &mut terrain::Generator, bounds: &voxel::bounds::T) -> voxel::T {
+    self.load_voxel_from(bounds, |bounds| self.terrain.load_with(generator, bounds))
+  }
+
+  fn load_voxel_from<Generate>(&self, bounds: &voxel::bounds::T, generate: Generate) -> voxel::T where
+    Generate: FnOnce(&voxel::bounds::T) -> voxel::T,
+  {
+    {
+      let mut voxels = self.terrain.voxels.lock().unwrap();
+      let node = voxels.get_mut_or_create(bounds);
+      if let Some(voxel) = node.data {
+        return voxel
+      }
+      if let Some(voxel) = self.store.lock().unwrap().get(bounds) {
+        node.data = Some(voxel);
+        return voxel
+      }
+    }
+
+    let mut voxel = generate(bounds);
+    if !self.plugins.is_empty() {
+      let generated = voxel;
+      self.plugins.chunk_generated(self.world, bounds, &mut voxel);
+      if voxel != generated {
+        self.terrain.voxels.lock().unwrap().get_mut_or_create(bounds).data = Some(voxel);
+      }
     }
+    self.store.lock().unwrap().insert(bounds, voxel);
+    voxel
   }
 
   // TODO: Avoid the double-lookup when unload and load the same index.
@@ -95,7 +165,7 @@ impl T {
       lod::Full => {
         debug!("{:?} requested from gaia", position);
         load_block(
-          update_gaia::Message::Load(time::precise_time_ns(), vec!(*position), LoadDestination::Local(owner))
+          update_gaia::Message::Load(self.world, time::precise_time_ns(), vec!(*position), LoadDestination::Local(owner))
         );
       },
     };
@@ -109,6 +179,7 @@ impl T {
     lod_map             : &mut lod::Map,
     in_progress_terrain : &mut in_progress_terrain::T,
     loaded              : &mut fnv_map::T<voxel::bounds::T, Vec<entity::id::Terrain>>,
+    terrain             : &terrain::T,
   ) {
     let lod = lod::Full;
     let (_, change) = lod_map.insert(*position, lod, owner);
@@ -147,6 +218,7 @@ impl T {
       let prev = loaded.insert(*position, ids);
       assert!(prev.is_none());
     });
+    terrain.loaded(position);
   }
 
   pub fn unload(
@@ -177,6 +249,7 @@ impl T {
                 for id in ids {
                   physics.remove_terrain(id);
                 }
+                self.terrain.unloaded(position);
               },
             }
           });
@@ -184,6 +257,18 @@ impl T {
       }
     });
   }
+
+  /// Unload everything `owner` has loaded.
+  pub fn unload_all(
+    &self,
+    physics : &Mutex<physics::T>,
+    owner   : lod::OwnerId,
+  ) {
+    let positions = self.lod_map.lock().unwrap().owned_by(owner);
+    for position in positions {
+      self.unload(physics, &position, owner);
+    }
+  }
 }
 
 pub struct LoadedTerrain {
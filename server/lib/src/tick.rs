@@ -0,0 +1,269 @@
+//! The fixed timestep the world is simulated at.
+//! Real time builds up in an accumulator, and the world is stepped once for every whole step in it, so the
+//! simulation keeps pace with the clock even when a tick runs long. Each system in a tick has a share of the
+//! step as its budget, and running over it is counted and reported.
+//! In deterministic mode the clock is ignored, and the world only steps as many times as it's told to.
+
+use stopwatch;
+use time;
+
+use common::protocol;
+
+/// The most steps run back to back to catch up. Anything further behind is dropped, so a long stall doesn't
+/// turn into a long burst of ticks.
+const MAX_CATCH_UP: u64 = 5;
+/// How much each new tick moves the averages.
+const SMOOTHING: f64 = 0.05;
+
+/// The parts of a tick, in the order they run.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum System {
+  Players,
+  Mobs,
+  Spawning,
+  Items,
+  Snapshots,
+  Sun,
+  Weather,
+  Plugins,
+}
+
+/// The number of systems.
+pub const SYSTEM_COUNT: usize = 8;
+
+static SYSTEMS: [System; SYSTEM_COUNT] = [
+  System::Players, System::Mobs, System::Spawning, System::Items, System::Snapshots, System::Sun, System::Weather,
+  System::Plugins,
+];
+
+impl System {
+  /// The name the system is timed under with `stopwatch`.
+  pub fn name(self) -> &'static str {
+    match self {
+      System::Players   => "update_world.player",
+      System::Mobs      => "update_world.mobs",
+      System::Spawning  => "update_world.spawning",
+      System::Items     => "update_world.items",
+      System::Snapshots => "update_world.snapshots",
+      System::Sun       => "update_world.sun",
+      System::Weather   => "update_world.weather",
+      System::Plugins   => "update_world.plugins",
+    }
+  }
+
+  /// The portion of a step the system should fit in.
+  pub fn budget(self) -> f64 {
+    match self {
+      System::Players   => 0.28,
+      System::Mobs      => 0.25,
+      System::Spawning  => 0.1,
+      System::Items     => 0.1,
+      System::Snapshots => 0.17,
+      System::Sun       => 0.03,
+      System::Weather   => 0.02,
+      System::Plugins   => 0.05,
+    }
+  }
+}
+
+/// How long each system took in one tick.
+pub struct Timings {
+  ns : [u64; SYSTEM_COUNT],
+}
+
+#[allow(missing_docs)]
+pub fn timings() -> Timings {
+  Timings {
+    ns : [0; SYSTEM_COUNT],
+  }
+}
+
+impl Timings {
+  /// Run a system under `stopwatch::time`, and record how long it took.
+  pub fn time<R, F: FnOnce() -> R>(&mut self, system: System, f: F) -> R {
+    stopwatch::time(system.name(), || {
+      let start = time::precise_time_ns();
+      let r = f();
+      self.ns[system as usize] += time::precise_time_ns() - start;
+      r
+    })
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  step_ns         : u64,
+  /// When `advance` was last called, if it has been.
+  last_ns         : Option<u64>,
+  /// Time that's passed but hasn't been simulated yet.
+  accumulator_ns  : u64,
+  /// Steps asked for in lockstep, that haven't been simulated yet.
+  queued          : u64,
+
+  /// Average nanoseconds per tick.
+  average_ns      : f64,
+  /// Average nanoseconds per tick, per system.
+  system_ns       : [f64; SYSTEM_COUNT],
+  /// The longest tick since the last report.
+  max_ns          : u64,
+  /// Steps dropped since the last report, because the simulation fell too far behind.
+  dropped         : u64,
+  /// How many times each system ran over its budget since the last report.
+  overruns        : [u32; SYSTEM_COUNT],
+  ticks_to_report : u64,
+}
+
+/// A clock stepping `steps_per_second` times a second.
+pub fn new(steps_per_second: u64) -> T {
+  T {
+    step_ns         : 1_000_000_000 / steps_per_second,
+    last_ns         : None,
+    accumulator_ns  : 0,
+    queued          : 0,
+    average_ns      : 0.0,
+    system_ns       : [0.0; SYSTEM_COUNT],
+    max_ns          : 0,
+    dropped         : 0,
+    overruns        : [0; SYSTEM_COUNT],
+    ticks_to_report : steps_per_second,
+  }
+}
+
+impl T {
+  /// Let time pass until `now_ns`, and return how many steps should be simulated to catch up to it.
+  pub fn advance(&mut self, now_ns: u64) -> u64 {
+    let last_ns = self.last_ns.unwrap_or(now_ns);
+    self.last_ns = Some(now_ns);
+    self.accumulator_ns += now_ns.saturating_sub(last_ns);
+
+    let steps = self.accumulator_ns / self.step_ns;
+    self.accumulator_ns -= steps * self.step_ns;
+    if steps > MAX_CATCH_UP {
+      self.dropped += steps - MAX_CATCH_UP;
+      MAX_CATCH_UP
+    } else {
+      steps
+    }
+  }
+
+  /// Ask for `steps` more steps in lockstep.
+  pub fn queue(&mut self, steps: u64) {
+    self.queued += steps;
+  }
+
+  /// Take one of the queued steps, if there are any.
+  pub fn take_queued(&mut self) -> bool {
+    if self.queued == 0 {
+      return false
+    }
+    self.queued -= 1;
+    true
+  }
+
+  #[allow(missing_docs)]
+  pub fn step_ns(&self) -> u64 {
+    self.step_ns
+  }
+
+  /// Count one tick, which took `ns` nanoseconds in all.
+  pub fn record(&mut self, ns: u64, timings: &Timings) {
+    if self.average_ns == 0.0 {
+      self.average_ns = ns as f64;
+    } else {
+      self.average_ns += (ns as f64 - self.average_ns) * SMOOTHING;
+    }
+    self.max_ns = self.max_ns.max(ns);
+
+    for &system in SYSTEMS.iter() {
+      let i = system as usize;
+      let ns = timings.ns[i];
+      self.system_ns[i] += (ns as f64 - self.system_ns[i]) * SMOOTHING;
+      if ns as f64 > system.budget() * self.step_ns as f64 {
+        self.overruns[i] += 1;
+      }
+    }
+
+    if self.ticks_to_report > 0 {
+      self.ticks_to_report -= 1;
+    }
+  }
+
+  /// The tick stats, as of now.
+  pub fn stats(&self) -> protocol::TickStats {
+    protocol::TickStats {
+      step_ms    : self.step_ns as f32 / 1_000_000.0,
+      average_ms : self.average_ns as f32 / 1_000_000.0,
+      max_ms     : self.max_ns as f32 / 1_000_000.0,
+      dropped    : self.dropped as u32,
+    }
+  }
+
+  /// Once a second's worth of ticks has been recorded, return the stats for them and start counting again.
+  /// Systems that ran over their budgets are logged.
+  pub fn report(&mut self) -> Option<protocol::TickStats> {
+    if self.ticks_to_report > 0 {
+      return None
+    }
+    let stats = self.stats();
+
+    for &system in SYSTEMS.iter() {
+      let overruns = self.overruns[system as usize];
+      if overruns > 0 {
+        warn!(
+          "{} ran over its {:.1}ms budget in {} ticks",
+          system.name(),
+          system.budget() * stats.step_ms as f64,
+          overruns,
+        );
+      }
+    }
+    if self.dropped > 0 {
+      warn!("Fell behind, and dropped {} steps", self.dropped);
+    }
+
+    self.max_ns = 0;
+    self.dropped = 0;
+    self.overruns = [0; SYSTEM_COUNT];
+    self.ticks_to_report = 1_000_000_000 / self.step_ns;
+    Some(stats)
+  }
+
+  /// Each system's name and its average time, in milliseconds.
+  pub fn systems_ms(&self) -> Vec<(&'static str, f64)> {
+    SYSTEMS.iter().map(|&system| (system.name(), self.system_ns[system as usize] / 1_000_000.0)).collect()
+  }
+}
+
+#[test]
+fn steps_catch_up_but_not_too_far() {
+  let mut clock = new(10);
+  assert_eq!(clock.advance(5_000_000_000), 0);
+  assert_eq!(clock.advance(5_150_000_000), 1);
+  assert_eq!(clock.advance(5_250_000_000), 1);
+  assert_eq!(clock.advance(5_460_000_000), 2);
+  assert_eq!(clock.advance(9_000_000_000), MAX_CATCH_UP);
+  assert_eq!(clock.stats().dropped, 36 - MAX_CATCH_UP as u32);
+
+  let mut timings = timings();
+  timings.ns[System::Items as usize] = 50_000_000;
+  for _ in 0 .. 10 {
+    clock.record(60_000_000, &timings);
+  }
+  let stats = clock.report().unwrap();
+  assert_eq!(stats.max_ms, 60.0);
+  assert!(clock.report().is_none());
+  assert_eq!(clock.stats().dropped, 0);
+}
+
+#[test]
+fn queued_steps_are_taken_one_at_a_time() {
+  let mut clock = new(10);
+  assert!(!clock.take_queued());
+  clock.queue(2);
+  clock.queue(1);
+  assert!(clock.take_queued());
+  assert!(clock.take_queued());
+  assert!(clock.take_queued());
+  assert!(!clock.take_queued());
+}
@@ -1,14 +1,22 @@
 //! Creator of the earth.
 
+use cgmath::{Point3, Vector3};
 use collision::{Aabb3};
 use stopwatch;
 
 use common;
+use common::material;
 use common::protocol;
 use common::voxel;
 
+use brush;
+use console;
+use entity;
+use history;
+use item;
 use lod;
 use server;
+use stability;
 use terrain_loader;
 use voxel_data;
 
@@ -17,8 +25,6 @@ use voxel_data;
 pub enum LoadDestination {
   /// The server requested this block. Load it into local state.
   Local(lod::OwnerId),
-  /// A client requested this block. Send it to them.
-  Client(protocol::ClientId),
   /// Drop the loaded voxels on the floor.
   None
 }
@@ -26,12 +32,21 @@ pub enum LoadDestination {
 #[allow(missing_docs)]
 pub enum Message {
   /// Load some voxels
-  Load(u64, Vec<voxel::bounds::T>, LoadDestination),
-  /// Apply a brush operation
-  Brush(voxel_data::brush::T<Box<dyn voxel_data::mosaic::T<common::voxel::Material> + Send>>),
+  Load(protocol::WorldId, u64, Vec<voxel::bounds::T>, LoadDestination),
+  /// Apply a brush operation, which the player it's for, if any, can undo
+  Brush(
+    protocol::WorldId,
+    voxel_data::brush::T<Box<dyn voxel_data::mosaic::T<common::voxel::Material> + Send>>,
+    Option<entity::id::Player>,
+  ),
+  /// Undo a player's most recent brush operation
+  Undo(entity::id::Player),
+  /// Redo a player's most recently undone brush operation
+  Redo(entity::id::Player),
+  /// Run an admin command
+  Console(console::Request),
 }
 
-// TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
 #[allow(missing_docs)]
 pub fn update_gaia(
   server: &server::T,
@@ -39,58 +54,172 @@ pub fn update_gaia(
 ) {
   stopwatch::time("update_gaia", move || {
     match update {
-      Message::Load(time_requested, voxel_bounds, load_reason) => {
+      Message::Load(world, time_requested, voxel_bounds, load_reason) => {
         stopwatch::time("terrain.load", || {
-          load(server, time_requested, voxel_bounds, load_reason);
+          load(server, world, time_requested, voxel_bounds, load_reason);
         });
       },
-      Message::Brush(mut brush) => {
-        let mut updates = Vec::new();
-        server.terrain_loader.terrain.brush(
-          &mut brush,
-          |block, bounds| {
-            trace!("update bounds {:?}", bounds);
-            updates.push((*bounds, *block));
-          },
-        );
-
-        let mut clients = server.clients.lock().unwrap();
-        for (_, client) in clients.iter_mut() {
-          client.send(
-            protocol::ServerToClient::Voxels {
-              voxels : updates.clone(),
-              reason : protocol::VoxelReason::Updated,
-            }
-          );
+      Message::Brush(world_id, brush, player) => {
+        let bounds = brush.bounds;
+        let edit = apply_brush(server, world_id, brush);
+        if let Some(player) = player {
+          if let Some(player) = server.players.lock().unwrap().get_mut(&player) {
+            player.history.push(edit, server.undo_history);
+          }
+        }
+        break_off(server, world_id, &bounds);
+      },
+      Message::Undo(player_id) => {
+        let edit = server.players.lock().unwrap().get_mut(&player_id).and_then(|player| player.history.undo());
+        match edit {
+          None => server.notify(player_id, "Nothing to undo."),
+          Some(edit) => redo(server, edit),
+        }
+      },
+      Message::Redo(player_id) => {
+        let edit = server.players.lock().unwrap().get_mut(&player_id).and_then(|player| player.history.redo());
+        match edit {
+          None => server.notify(player_id, "Nothing to redo."),
+          Some(edit) => redo(server, edit),
         }
       },
+      Message::Console(request) => {
+        console::apply(server, request);
+      },
     };
   })
 }
 
+/// Terrain an edit within `bounds` cut off from the ground breaks away and falls.
+fn break_off(server: &server::T, world_id: protocol::WorldId, bounds: &Aabb3<i32>) {
+  let low = bounds.min + Vector3::new(-1, -1, -1);
+  let high = bounds.max + Vector3::new(1, 1, 1);
+  let terrain_loader = &server.world(world_id).terrain_loader;
+  for fragment in stability::fragments(&low, &high, |p| material_at(terrain_loader, p).is_some()) {
+    collapse(server, world_id, &fragment);
+  }
+}
+
+fn apply_brush(server: &server::T, world_id: protocol::WorldId, mut brush: brush::T) -> history::Edit {
+  let world = server.world(world_id);
+  let mut updates = Vec::new();
+  world.terrain_loader.terrain.brush(
+    &mut brush,
+    |block, bounds| {
+      trace!("update bounds {:?}", bounds);
+      updates.push((*bounds, *block));
+    },
+  );
+  send_edit(server, world_id, brush.bounds, updates)
+}
+
+/// Make an edit from a player's history again, e.g. the one that undoes another.
+fn redo(server: &server::T, edit: history::Edit) {
+  let terrain = &server.world(edit.world).terrain_loader.terrain;
+  for &(bounds, voxel) in &edit.after {
+    terrain.set(&bounds, voxel);
+  }
+  let (world_id, bounds) = (edit.world, edit.bounds);
+  send_edit(server, world_id, bounds, edit.after);
+  break_off(server, world_id, &bounds);
+}
+
+/// Save some voxels that were just changed within `bounds`, and send them to clients. Returns the edit, with what
+/// the voxels were before.
+fn send_edit(
+  server   : &server::T,
+  world_id : protocol::WorldId,
+  bounds   : Aabb3<i32>,
+  updates  : Vec<(voxel::bounds::T, voxel::T)>,
+) -> history::Edit {
+  let world = server.world(world_id);
+  world.mob_navigation.lock().unwrap().invalidate(&bounds);
+
+  let mut before = Vec::with_capacity(updates.len());
+  {
+    let mut store = world.terrain_loader.store.lock().unwrap();
+    for &(bounds, voxel) in &updates {
+      // Every voxel that's been loaded is in the store, so one that isn't was generated just for this edit.
+      let was = store.get(&bounds).unwrap_or_else(|| world.terrain_loader.terrain.generate(&bounds));
+      before.push((bounds, was));
+      store.insert(&bounds, voxel);
+    }
+  }
+  let versions = world.journal.lock().unwrap().record(&updates);
+
+  let mut clients = server.clients.lock().unwrap();
+  for (_, client) in clients.iter_mut() {
+    client.send_voxels(world_id, &updates, &versions);
+  }
+
+  history::Edit {
+    world  : world_id,
+    bounds : bounds,
+    before : before,
+    after  : updates,
+  }
+}
+
+/// The material of a voxel, if it's solid.
+fn material_at(terrain_loader: &terrain_loader::T, p: &Point3<i32>) -> Option<voxel::Material> {
+  let material =
+    match terrain_loader.load_voxel(&voxel::bounds::new(p.x, p.y, p.z, 0)) {
+      voxel::Volume(material) => material,
+      voxel::Surface(voxel) => voxel.corner,
+    };
+  if material::get(material).solid {
+    Some(material)
+  } else {
+    None
+  }
+}
+
+/// Turn a piece of terrain into debris, which falls and becomes terrain again where it lands.
+fn collapse(server: &server::T, world_id: protocol::WorldId, fragment: &[Point3<i32>]) {
+  let terrain_loader = &server.world(world_id).terrain_loader;
+  let carved =
+    match brush::carve(terrain_loader, fragment) {
+      None => return,
+      Some(carved) => carved,
+    };
+  debug!("{} voxels broke off around {:?}", fragment.len(), fragment[0]);
+  let materials: Vec<_> = fragment.iter().map(|p| material_at(terrain_loader, p)).collect();
+  apply_brush(server, world_id, carved);
+
+  for (p, material) in fragment.iter().zip(materials.into_iter()) {
+    if let Some(item) = material.and_then(common::item::of_material) {
+      let center = Point3::new(p.x as f32 + 0.5, p.y as f32 + 0.5, p.z as f32 + 0.5);
+      item::spawn(server, world_id, protocol::EntityKind::Debris, item, None, center, Vector3::new(0.0, 0.0, 0.0));
+    }
+  }
+}
+
 #[inline(never)]
 fn load(
   server: &server::T,
+  world: protocol::WorldId,
   time_requested: u64,
   voxel_bounds: Vec<voxel::bounds::T>,
   load_reason: LoadDestination,
 ) {
+  let world = server.world(world);
   // TODO: Just lock `terrain` for the check and then the move;
   // don't lock for the whole time where we're generating the block.
-  let mut lod_map = server.terrain_loader.lod_map.lock().unwrap();
-  let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.lock().unwrap();
+  let mut lod_map = world.terrain_loader.lod_map.lock().unwrap();
+  let mut in_progress_terrain = world.terrain_loader.in_progress_terrain.lock().unwrap();
   match load_reason {
     LoadDestination::None => {
       for voxel_bounds in voxel_bounds {
-        server.terrain_loader.terrain.load(&voxel_bounds);
+        world.terrain_loader.load_voxel(&voxel_bounds);
       }
     },
     LoadDestination::Local(owner) => {
       for voxel_bounds in voxel_bounds {
-        let block = server.terrain_loader.terrain.load(&voxel_bounds);
+        let block = world.terrain_loader.load_voxel(&voxel_bounds);
         let bounds =
           match block {
-            voxel::Volume(voxel::Material::Empty) => Vec::new(),
+            voxel::Volume(voxel::Material::Empty) |
+            voxel::Volume(voxel::Material::Water) => Vec::new(),
             _ => {
               let (low, high) = voxel_bounds.corners();
               let id = server.terrain_allocator.lock().unwrap().allocate();
@@ -103,28 +232,13 @@ fn load(
           &terrain_loader::LoadedTerrain { bounds: bounds },
           &voxel_bounds,
           owner,
-          &server.physics,
+          &world.physics,
           &mut *lod_map,
           &mut *in_progress_terrain,
-          &mut *server.terrain_loader.loaded.lock().unwrap(),
+          &mut *world.terrain_loader.loaded.lock().unwrap(),
+          &world.terrain_loader.terrain,
         );
       }
     },
-    LoadDestination::Client(id) => {
-      let mut voxels = Vec::new();
-      for voxel_bounds in voxel_bounds {
-        let voxel = server.terrain_loader.terrain.load(&voxel_bounds);
-        voxels.push((voxel_bounds, voxel));
-      }
-
-      let mut clients = server.clients.lock().unwrap();
-      let client = clients.get_mut(&id).unwrap();
-      client.send(
-        protocol::ServerToClient::Voxels {
-          voxels : voxels,
-          reason : protocol::VoxelReason::Requested { at: time_requested },
-        }
-      );
-    },
   }
 }
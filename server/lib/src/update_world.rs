@@ -1,32 +1,65 @@
 use cgmath::{Point3, Vector3};
+use collision::Aabb3;
 use std::ops::Neg;
+use std::sync::Arc;
 use stopwatch;
 
+use common::fnv_map;
+use common::health;
 use common::protocol;
 use common::surroundings_loader::LoadType;
 use common::voxel;
 
+use grass;
+use item;
 use lod;
 use mob;
 use player;
+use replication;
 use server;
+use tick;
 use update_gaia;
+use weather;
 
-// TODO: Consider removing the IntervalTimer.
-
+/// Step the world forward by one tick, timing each system in `timings`.
+/// While `catching_up`, another tick follows right away, so clients aren't sent snapshots of this one.
 pub fn update_world<RequestBlock>(
   server: &server::T,
   request_block: &mut RequestBlock,
+  timings: &mut tick::Timings,
+  catching_up: bool,
 ) where
   RequestBlock: FnMut(update_gaia::Message),
 {
   stopwatch::time("update_world", || {
-    stopwatch::time("update_world.player", || {
+    timings.time(tick::System::Players, || {
+      // Updates, and where they happened.
       let mut updates = Vec::new();
+      // Each player's own client gets the details it needs to correct its predictions.
+      let mut movements = fnv_map::new();
+      let mut positions = fnv_map::new();
+      let mut deaths = fnv_map::new();
 
       for (_, player) in server.players.lock().unwrap().iter_mut() {
+        if let Some(cause) = player.respawn(server) {
+          info!("Player {} died: {:?}", player.entity_id.to_u32(), cause);
+          deaths.insert(player.entity_id, (cause, player.world, player.position));
+        }
         let (bounds, collisions) = player.update(server, request_block);
-        updates.push(protocol::ServerToClient::UpdatePlayer(player.entity_id, bounds));
+        if let Some(cell) = trampling(server, player, &bounds) {
+          if server.world(player.world).grass.lock().unwrap().trample(cell, server.clock.now_ns()) {
+            updates.push((player.world, player.position, protocol::ServerToClient::TrampleGrass(cell)));
+          }
+        }
+        positions.insert(player.entity_id, player.position);
+        movements.insert(
+          player.entity_id,
+          protocol::ServerToClient::PlayerMovement {
+            bounds     : bounds,
+            movement   : player.movement,
+            last_input : player.last_input,
+          },
+        );
         updates.extend(
           collisions.into_iter()
           .map(|c| {
@@ -36,21 +69,62 @@ pub fn update_world<RequestBlock>(
             }
           })
           .map(|c| {
-            protocol::ServerToClient::Collision(c)
+            (player.world, player.position, protocol::ServerToClient::Collision(c))
           })
         );
       }
 
-      let mut clients = server.clients.lock().unwrap();
-      for (_, client) in &mut *clients {
-        for update in &updates {
-          client.send(update.clone());
+      // Voxels that changed while they were out of their clients' range, and have come back into it.
+      let mut withheld = Vec::new();
+      {
+        let mut clients = server.clients.lock().unwrap();
+        for (&client_id, client) in &mut *clients {
+          if let Some((cause, world, position)) = client.player.and_then(|id| deaths.remove(&id)) {
+            let changed_world = client.interest.world() != world;
+            client.interest.enter(world, position);
+            client.send(protocol::ServerToClient::YouDied { cause: cause, world: world, position: position });
+            if changed_world {
+              client.send_lights(server.world(world));
+            }
+          }
+          if let Some(&position) = client.player.and_then(|id| positions.get(&id)) {
+            client.interest.move_to(position);
+          }
+          for &(world, ref position, ref update) in &updates {
+            if client.interest.sees(world, position) {
+              client.send(update.clone());
+            }
+          }
+          if let Some(movement) = client.player.and_then(|id| movements.remove(&id)) {
+            client.send(movement);
+          }
+
+          let in_range = client.interest.take_withheld_in_range();
+          if !in_range.is_empty() {
+            withheld.push((client_id, client.interest.world(), in_range));
+          }
+        }
+      }
+
+      for (client_id, world, bounds) in withheld {
+        let world_id = world;
+        let world = server.world(world_id);
+        // Read before loading, so an edit in between is put back on top instead of undone.
+        let versions = world.journal.lock().unwrap().versions(&bounds);
+        let terrain = &world.terrain_loader.terrain;
+        let voxels: Vec<_> =
+          bounds.into_iter()
+          .map(|bounds| (bounds, terrain.load(&bounds)))
+          .collect();
+        if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+          client.send_voxels(world_id, &voxels, &versions);
         }
       }
     });
 
-    stopwatch::time("update_world.mobs", || {
-      for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
+    timings.time(tick::System::Mobs, || {
+      let mut mobs = server.mobs.lock().unwrap();
+      for (_, mob) in mobs.iter_mut() {
         let position =
           Point3::new(
             mob.position.x as i32,
@@ -59,20 +133,19 @@ pub fn update_world<RequestBlock>(
           );
 
         let owner_id = mob.owner_id;
+        let world = mob.world;
         for (position, load_type) in mob.surroundings_loader.updates(&position) {
           load_placeholders(
             owner_id,
             server,
+            world,
             request_block,
             &voxel::bounds::new(position.x, position.y, position.z, 0),
             load_type,
           )
         }
 
-        {
-          let behavior = mob.behavior;
-          (behavior)(server, mob);
-        }
+        mob::ai::update(server, mob);
 
         mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
 
@@ -82,61 +155,164 @@ pub fn update_world<RequestBlock>(
           translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
         }
         if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
+          let landed = !translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0)) && delta_p.y < 0.0;
+          if landed {
+            mob.health.damage(health::fall_damage(-delta_p.y));
+          }
         }
         if delta_p.z != 0.0 {
           translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
         }
       }
+
+      mobs.retain(|_, mob| {
+        if mob.health.is_dead() {
+          debug!("Mob {} died", mob.entity_id.to_u32());
+          mob::remove(server, mob);
+          return false
+        }
+        true
+      });
+    });
+
+    timings.time(tick::System::Spawning, || {
+      mob::spawning::update(server);
+    });
+
+    timings.time(tick::System::Items, || {
+      item::update(server, request_block);
+    });
+
+    timings.time(tick::System::Snapshots, || {
+      if catching_up {
+        return
+      }
+      let worlds: Vec<_> = server.worlds.iter().map(|world| replication::capture(server, world.id)).collect();
+      for (_, client) in server.clients.lock().unwrap().iter_mut() {
+        let world = Arc::new(client.interest.filter(&worlds[client.interest.world().0 as usize]));
+        let snapshot = client.replication.encode(&world);
+        client.send(protocol::ServerToClient::Snapshot(snapshot));
+      }
+    });
+
+    timings.time(tick::System::Sun, || {
+      let (changes, time, rate) = {
+        let mut sun = server.sun.lock().unwrap();
+        let moved = sun.update(server.clock.now_ns());
+        if server.clock.is_logical() {
+          // A logical clock only moves when the world's stepped, so clients are told every time the sun moves,
+          // instead of moving it along themselves.
+          if moved {
+            sun.changes += 1;
+          }
+          (sun.changes, sun.days(), 0.0)
+        } else {
+          (sun.changes, sun.days(), sun.rate())
+        }
+      };
+      // Whether each player's world has seasons.
+      let has_sky: fnv_map::T<_, _> =
+        server.players.lock().unwrap().values()
+        .map(|player| (player.entity_id, server.world(player.world).terrain_loader.terrain.has_sky()))
+        .collect();
+      // Clients without a player aren't anywhere in the world yet. The rest move the sun along themselves, so
+      // they're only told where it is when it's jumped, or their sky's come or gone.
+      for (_, client) in server.clients.lock().unwrap().iter_mut() {
+        if let Some(&has_sky) = client.player.and_then(|id| has_sky.get(&id)) {
+          if client.sky != Some((changes, has_sky)) {
+            client.sky = Some((changes, has_sky));
+            client.send(protocol::ServerToClient::SetSky { time: time, rate: rate, has_sky: has_sky });
+          }
+        }
+      }
     });
 
-    server.sun.lock().unwrap().update().map(|fraction| {
+    timings.time(tick::System::Weather, || {
+      // The world and biome each player is in.
+      let players: fnv_map::T<_, _> =
+        server.players.lock().unwrap().values()
+        .map(|player| {
+          let terrain = &server.world(player.world).terrain_loader.terrain;
+          (player.entity_id, (player.world, terrain.biome_at(player.position.x, player.position.z)))
+        })
+        .collect();
+
+      let now = server.clock.now_ns();
+      for world in &server.worlds {
+        let biomes = players.values().filter(|&&(w, _)| w == world.id).filter_map(|&(_, biome)| biome);
+        let prevailing = weather::prevailing(biomes);
+        world.weather.lock().unwrap().update(&mut *server.rng.lock().unwrap(), now, prevailing);
+        world.grass.lock().unwrap().spring_back(now);
+      }
+
       for (_, client) in server.clients.lock().unwrap().iter_mut() {
-        client.send(protocol::ServerToClient::UpdateSun(fraction));
+        let (world, biome) =
+          match client.player.and_then(|id| players.get(&id)) {
+            None => continue,
+            Some(&world_biome) => world_biome,
+          };
+        let weather = server.world(world).weather.lock().unwrap().local(biome);
+        if client.weather != Some(weather) {
+          client.weather = Some(weather);
+          client.send(protocol::ServerToClient::UpdateWeather(weather));
+        }
       }
     });
+
+    timings.time(tick::System::Plugins, || {
+      server.plugins.tick(server);
+    });
   });
 }
 
+/// The grass a player is walking through, if any.
+fn trampling(server: &server::T, player: &player::T, bounds: &Aabb3<f32>) -> Option<Point3<i32>> {
+  let speed = player.movement.speed;
+  if speed.x == 0.0 && speed.z == 0.0 {
+    return None
+  }
+  // Just under the player's feet.
+  let feet =
+    Point3::new((bounds.min.x + bounds.max.x) / 2.0, bounds.min.y - 0.25, (bounds.min.z + bounds.max.z) / 2.0);
+  let cell = grass::cell(&feet);
+  if grass::grows_in(&server.world(player.world).terrain_loader.terrain, &cell) {
+    Some(cell)
+  } else {
+    None
+  }
+}
+
+/// Move a mob, unless it would run into something. Returns whether it moved.
 fn translate_mob(
   server: &server::T,
   mob: &mut mob::Mob,
   delta_p: &Vector3<f32>,
-) {
-  let bounds;
-  {
-    let mut physics = server.physics.lock().unwrap();
-    if physics.translate_misc(mob.physics_id, *delta_p).is_some() {
-      mob.speed += delta_p.neg();
-      return;
-    } else {
-      bounds = *physics.get_bounds(mob.physics_id).unwrap();
-    }
+) -> bool {
+  if server.world(mob.world).physics.lock().unwrap().translate_misc(mob.physics_id, *delta_p).is_some() {
+    mob.speed += delta_p.neg();
+    return false;
   }
 
   mob.position += *delta_p;
-
-  for (_, client) in server.clients.lock().unwrap().iter_mut() {
-    client.send(
-      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds),
-    );
-  }
+  true
 }
 
 pub fn load_placeholders<RequestBlock>(
   owner: lod::OwnerId,
   server: &server::T,
+  world: protocol::WorldId,
   request_block: &mut RequestBlock,
   pos: &voxel::bounds::T,
   load_type: LoadType,
 ) where
   RequestBlock: FnMut(update_gaia::Message),
 {
+  let world = server.world(world);
   match load_type {
     LoadType::Load | LoadType::Downgrade => {
-      server.terrain_loader.load(
+      world.terrain_loader.load(
         &server.misc_allocator,
-        &server.physics,
+        &world.physics,
         &pos,
         lod::Placeholder,
         owner,
@@ -144,8 +320,8 @@ pub fn load_placeholders<RequestBlock>(
       );
     },
     LoadType::Unload => {
-      server.terrain_loader.unload(
-        &server.physics,
+      world.terrain_loader.unload(
+        &world.physics,
         &pos,
         owner,
       );
@@ -0,0 +1,233 @@
+//! A queue of client voxel requests, served by a pool of worker threads, closest requests first.
+
+use cgmath::{Point3, EuclideanSpace, MetricSpace};
+use std;
+use std::cmp::Ordering;
+use std::sync::{Condvar, Mutex};
+use stopwatch;
+use time;
+
+use common::compression;
+use common::journal;
+use common::protocol;
+use common::voxel;
+
+use server;
+use terrain;
+
+/// How long workers wait for a request before checking whether they should quit.
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// A client's request for some voxels.
+struct Request {
+  time_requested_ns : u64,
+  client_id         : protocol::ClientId,
+  world             : protocol::WorldId,
+  voxels            : Vec<voxel::bounds::T>,
+  /// When the request was queued, by the server's clock.
+  queued_ns         : u64,
+  /// Squared distance from the requesting player; lower is served sooner.
+  priority          : u64,
+  /// Breaks priority ties in favor of older requests.
+  sequence          : u64,
+}
+
+impl PartialEq for Request {
+  fn eq(&self, other: &Request) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Request {}
+
+impl PartialOrd for Request {
+  fn partial_cmp(&self, other: &Request) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Request {
+  // `BinaryHeap` is a max-heap, so "greater" requests are the closer and older ones.
+  fn cmp(&self, other: &Request) -> Ordering {
+    other.priority.cmp(&self.priority)
+      .then_with(|| other.sequence.cmp(&self.sequence))
+  }
+}
+
+struct Queue {
+  requests      : std::collections::BinaryHeap<Request>,
+  next_sequence : u64,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  queue     : Mutex<Queue>,
+  available : Condvar,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    queue:
+      Mutex::new(
+        Queue {
+          requests      : std::collections::BinaryHeap::new(),
+          next_sequence : 0,
+        }
+      ),
+    available: Condvar::new(),
+  }
+}
+
+impl T {
+  /// Enqueue a request. If `requester` is known, voxels closer to it get served first.
+  pub fn push(
+    &self,
+    time_requested_ns : u64,
+    client_id         : protocol::ClientId,
+    world             : protocol::WorldId,
+    voxels            : Vec<voxel::bounds::T>,
+    requester         : Option<Point3<f32>>,
+  ) {
+    let priority =
+      match requester {
+        None => 0,
+        Some(requester) => {
+          voxels.iter()
+            .map(|bounds| {
+              let (low, high) = bounds.corners();
+              let center = (low + high.to_vec()) / 2.0;
+              center.distance2(requester) as u64
+            })
+            .min()
+            .unwrap_or(0)
+        },
+      };
+
+    let mut queue = self.queue.lock().unwrap();
+    let sequence = queue.next_sequence;
+    queue.next_sequence += 1;
+    queue.requests.push(
+      Request {
+        time_requested_ns : time_requested_ns,
+        client_id         : client_id,
+        world             : world,
+        voxels            : voxels,
+        queued_ns         : time::precise_time_ns(),
+        priority          : priority,
+        sequence          : sequence,
+      }
+    );
+    self.available.notify_one();
+  }
+
+  /// Drop a client's request that hasn't been served yet. Returns false if there was no such request.
+  pub fn cancel(&self, client_id: protocol::ClientId, time_requested_ns: u64) -> bool {
+    let mut queue = self.queue.lock().unwrap();
+    let before = queue.requests.len();
+    let requests = std::mem::replace(&mut queue.requests, std::collections::BinaryHeap::new());
+    queue.requests =
+      requests.into_iter()
+      .filter(|request| request.client_id != client_id || request.time_requested_ns != time_requested_ns)
+      .collect();
+    queue.requests.len() < before
+  }
+
+  /// The number of requests waiting to be served.
+  pub fn len(&self) -> usize {
+    self.queue.lock().unwrap().requests.len()
+  }
+
+  /// Take the most urgent request, waiting a little while for one if there are none.
+  fn pop(&self) -> Option<Request> {
+    let mut queue = self.queue.lock().unwrap();
+    if queue.requests.is_empty() {
+      queue =
+        self.available.wait_timeout(queue, std::time::Duration::from_millis(POLL_INTERVAL_MS))
+        .unwrap().0;
+    }
+    queue.requests.pop()
+  }
+}
+
+/// Serve voxel requests until `quit_signal` is set. Run this in as many threads as desired.
+pub fn work(server: &server::T, quit_signal: &Mutex<bool>) {
+  // One generator per world, indexed by `WorldId`.
+  let mut generators: Vec<_> = server.worlds.iter().map(|world| world.terrain_loader.terrain.generator()).collect();
+  while !*quit_signal.lock().unwrap() {
+    if let Some(request) = server.voxel_requests.pop() {
+      stopwatch::time("voxel_requests.serve", || {
+        let generator = &mut generators[request.world.0 as usize];
+        serve(server, generator, request);
+      });
+    }
+  }
+}
+
+/// How much of the grassy ground in the region covered by some voxels grows grass.
+fn grass_density(server: &server::T, world: protocol::WorldId, voxels: &[voxel::bounds::T]) -> f32 {
+  if voxels.is_empty() {
+    return 1.0
+  }
+  let mut center = Point3::new(0.0, 0.0, 0.0);
+  for bounds in voxels {
+    let (low, high) = bounds.corners();
+    center = center + (low.to_vec() + high.to_vec()) / 2.0;
+  }
+  let center = center / voxels.len() as f32;
+  server.world(world).terrain_loader.terrain.grass_density(center.x, center.z)
+}
+
+/// Put the edits made since some voxels were loaded on top of them, and bring their versions up to date.
+/// Chunks that have had more edits since than the journal keeps are left as they are, for the client to catch up
+/// from the edits it's been sent.
+fn put_back_edits(
+  journal  : &journal::T,
+  voxels   : &mut Vec<(voxel::bounds::T, voxel::T)>,
+  versions : &mut Vec<(Point3<i32>, u32)>,
+) {
+  for &mut (chunk, ref mut version) in versions.iter_mut() {
+    if journal.version(&chunk) > *version && journal.has_all_since(&chunk, *version) {
+      voxels.extend(journal.since(&chunk, *version));
+      *version = journal.version(&chunk);
+    }
+  }
+}
+
+fn serve(server: &server::T, generator: &mut terrain::Generator, request: Request) {
+  let start_ns = time::precise_time_ns();
+  let grass_density = grass_density(server, request.world, &request.voxels);
+  let world = server.world(request.world);
+  let terrain_loader = &world.terrain_loader;
+  // Read before loading, so edits made while loading can be put back on top.
+  let mut versions = world.journal.lock().unwrap().versions(&request.voxels);
+  let mut voxels = Vec::with_capacity(request.voxels.len());
+  generator.expect(&request.voxels);
+  for bounds in request.voxels {
+    let voxel = terrain_loader.load_voxel_with(generator, &bounds);
+    voxels.push((bounds, voxel));
+  }
+  let generated_ns = time::precise_time_ns();
+
+  let mut clients = server.clients.lock().unwrap();
+  let client =
+    match clients.get_mut(&request.client_id) {
+      None => {
+        warn!("Dropping voxels for unknown client {:?}", request.client_id);
+        return
+      },
+      Some(client) => client,
+    };
+  put_back_edits(&world.journal.lock().unwrap(), &mut voxels, &mut versions);
+  let voxels = compression::compress(client.compression, &voxels);
+  client.send(
+    protocol::ServerToClient::Voxels {
+      world  : request.world,
+      voxels : voxels,
+      reason : protocol::VoxelReason::Requested { at: request.time_requested_ns },
+      grass_density : grass_density,
+      versions : versions,
+    }
+  );
+  server.metrics.chunk_served(generated_ns - start_ns, time::precise_time_ns() - request.queued_ns);
+}
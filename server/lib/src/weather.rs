@@ -0,0 +1,173 @@
+//! Each world's weather drifts between clear skies, clouds and storms. What falls out of the sky, and how much,
+//! depends on the biome under each player, so the same storm snows on the tundra and passes over the desert.
+//! The biome most of a world's players are in also biases which way the weather drifts.
+
+use cgmath::Vector2;
+use rand::Rng;
+use std;
+use std::hash::Hasher;
+
+use common::interval_timer::IntervalTimer;
+use common::protocol;
+
+use terrain::biome::map;
+use terrain::biome::map::Biome;
+
+/// How often the weather gets a chance to change, in ns.
+const CHANGE_INTERVAL_NS: u64 = 90_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Sky {
+  Clear,
+  Cloudy,
+  Stormy,
+}
+
+/// Every state of the sky.
+const SKIES: [Sky; 3] = [Sky::Clear, Sky::Cloudy, Sky::Stormy];
+
+/// How much more (or less) likely a biome makes clouds and storms, next to the plains.
+fn wetness(biome: Option<Biome>) -> f32 {
+  match biome {
+    None                   => 1.0,
+    Some(Biome::Plains)    => 1.0,
+    Some(Biome::Desert)    => 0.2,
+    Some(Biome::Mountains) => 1.4,
+    Some(Biome::Tundra)    => 1.0,
+  }
+}
+
+/// The relative chance of the sky turning into each of `SKIES` next, under a world mostly in some biome.
+fn transitions(sky: Sky, biome: Option<Biome>) -> [f32; 3] {
+  let w = wetness(biome);
+  match sky {
+    Sky::Clear  => [0.6, 0.35 * w, 0.05 * w],
+    Sky::Cloudy => [0.4, 0.3     , 0.3  * w],
+    Sky::Stormy => [0.2, 0.5     , 0.3  * w],
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  sky   : Sky,
+  /// The wind under clear skies. Clouds and storms blow harder.
+  wind  : Vector2<f32>,
+  timer : IntervalTimer,
+}
+
+#[allow(missing_docs)]
+pub fn new(now: u64) -> T {
+  T {
+    sky   : Sky::Clear,
+    wind  : Vector2::new(0.4, 0.15),
+    timer : IntervalTimer::new(CHANGE_INTERVAL_NS, now),
+  }
+}
+
+impl T {
+  /// Roll for new weather, if it's time. `prevailing` is the biome most of the world's players are in.
+  pub fn update<R: Rng>(&mut self, rng: &mut R, now: u64, prevailing: Option<Biome>) {
+    if self.timer.update(now) == 0 {
+      return
+    }
+
+    let weights = transitions(self.sky, prevailing);
+    let total: f32 = weights.iter().sum();
+    let mut roll = rng.gen_range(0.0, total);
+    for (&sky, &weight) in SKIES.iter().zip(weights.iter()) {
+      if roll < weight {
+        self.sky = sky;
+        break
+      }
+      roll -= weight;
+    }
+
+    let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    let strength = rng.gen_range(0.2, 0.6);
+    self.wind = Vector2::new(angle.cos(), angle.sin()) * strength;
+    debug!("The weather turned {:?}", self.sky);
+  }
+
+  /// Feed the sky and wind into `h`.
+  pub fn hash<H: Hasher>(&self, h: &mut H) {
+    h.write_u8(self.sky as u8);
+    h.write_u32(self.wind.x.to_bits());
+    h.write_u32(self.wind.y.to_bits());
+  }
+
+  /// The weather over some biome, or in a world with no sky, if it's `None`.
+  pub fn local(&self, biome: Option<Biome>) -> protocol::Weather {
+    let biome =
+      match biome {
+        None => {
+          return protocol::Weather {
+            precipitation : protocol::Precipitation::None,
+            intensity     : 0.0,
+            wind          : Vector2::new(0.0, 0.0),
+          }
+        },
+        Some(biome) => biome,
+      };
+
+    let (intensity, gustiness) =
+      match self.sky {
+        Sky::Clear  => (0.0, 1.0),
+        Sky::Cloudy => (0.3, 1.5),
+        Sky::Stormy => (1.0, 2.5),
+      };
+    let precipitation =
+      match biome {
+        _ if intensity == 0.0 => protocol::Precipitation::None,
+        // Clouds dry up before they reach the desert.
+        Biome::Desert => protocol::Precipitation::None,
+        Biome::Tundra | Biome::Mountains => protocol::Precipitation::Snow,
+        Biome::Plains => protocol::Precipitation::Rain,
+      };
+    protocol::Weather {
+      precipitation : precipitation,
+      intensity     : if precipitation == protocol::Precipitation::None { 0.0 } else { intensity },
+      wind          : self.wind * gustiness,
+    }
+  }
+}
+
+/// The biome that turns up most often in a list, if there's anything in it.
+pub fn prevailing<I: Iterator<Item=Biome>>(biomes: I) -> Option<Biome> {
+  let mut counts = vec!(0; map::ALL.len());
+  for biome in biomes {
+    counts[map::ALL.iter().position(|&b| b == biome).unwrap()] += 1;
+  }
+  let (i, &count) = counts.iter().enumerate().max_by_key(|&(_, &count)| count).unwrap();
+  if count == 0 {
+    None
+  } else {
+    Some(map::ALL[i])
+  }
+}
+
+#[test]
+fn storms_snow_in_the_tundra_and_skip_the_desert() {
+  let mut weather = new(0);
+  weather.sky = Sky::Stormy;
+  assert_eq!(weather.local(Some(Biome::Tundra)).precipitation, protocol::Precipitation::Snow);
+  assert_eq!(weather.local(Some(Biome::Plains)).precipitation, protocol::Precipitation::Rain);
+  assert_eq!(weather.local(Some(Biome::Plains)).intensity, 1.0);
+  let desert = weather.local(Some(Biome::Desert));
+  assert_eq!(desert.precipitation, protocol::Precipitation::None);
+  assert_eq!(desert.intensity, 0.0);
+  assert!(desert.wind.x.abs() + desert.wind.y.abs() > 0.0);
+  assert_eq!(weather.local(None).wind, Vector2::new(0.0, 0.0));
+
+  weather.sky = Sky::Clear;
+  assert_eq!(weather.local(Some(Biome::Tundra)).precipitation, protocol::Precipitation::None);
+}
+
+#[test]
+fn deserts_are_rarely_stormy() {
+  let desert = transitions(Sky::Clear, Some(Biome::Desert));
+  let mountains = transitions(Sky::Clear, Some(Biome::Mountains));
+  assert!(desert[2] < mountains[2]);
+  assert_eq!(prevailing(vec!(Biome::Tundra, Biome::Desert, Biome::Tundra).into_iter()), Some(Biome::Tundra));
+  assert_eq!(prevailing(Vec::new().into_iter()), None);
+}
@@ -0,0 +1,109 @@
+//! The worlds a server hosts. Each has its own terrain and physics, and players can be moved between them.
+
+use cgmath::Point3;
+use collision::Aabb3;
+use std::sync::{Arc, Mutex};
+
+use common::fnv_map;
+use common::journal;
+use common::protocol;
+
+use config;
+use grass;
+use mob;
+use physics;
+use plugin;
+use terrain;
+use terrain_loader;
+use weather;
+
+/// Where players join.
+pub const OVERWORLD: protocol::WorldId = protocol::WorldId(0);
+/// Underground, with no surface at all.
+pub const CAVES: protocol::WorldId = protocol::WorldId(1);
+
+/// The name of each world, indexed by ID.
+const NAMES: [&'static str; 2] = ["overworld", "caves"];
+
+#[allow(missing_docs)]
+pub struct T {
+  pub id             : protocol::WorldId,
+  pub terrain_loader : terrain_loader::T,
+  pub physics        : Mutex<physics::T>,
+  pub mob_navigation : Mutex<mob::ai::navigation::T>,
+  /// Point lights placed by admins. There are never more than `protocol::MAX_LIGHTS`.
+  pub lights         : Mutex<fnv_map::T<protocol::LightId, protocol::Light>>,
+  #[allow(missing_docs)]
+  pub weather        : Mutex<weather::T>,
+  #[allow(missing_docs)]
+  pub grass          : Mutex<grass::T>,
+  /// Recent terrain edits, so voxels loaded before an edit can be sent with it on top.
+  pub journal        : Mutex<journal::T>,
+}
+
+fn new(
+  id      : protocol::WorldId,
+  shape   : terrain::Shape,
+  config  : &config::T,
+  plugins : &Arc<plugin::Registry>,
+  now     : u64,
+) -> T {
+  let world_width: u32 = 1 << 11;
+  let world_width = world_width as f32;
+  let physics =
+    physics::T::new(
+      Aabb3::new(
+        Point3 { x: -world_width, y: -512.0, z: -world_width },
+        Point3 { x: world_width, y: 512.0, z: world_width },
+      )
+    );
+
+  T {
+    id             : id,
+    terrain_loader : terrain_loader::T::new(id, name(id), shape, config, plugins.clone()),
+    physics        : Mutex::new(physics),
+    mob_navigation : Mutex::new(mob::ai::navigation::new()),
+    lights         : Mutex::new(fnv_map::new()),
+    weather        : Mutex::new(weather::new(now)),
+    grass          : Mutex::new(grass::new()),
+    journal        : Mutex::new(journal::new()),
+  }
+}
+
+/// Make every world, in ID order, as of `now`. Their terrain is generated through `plugins`.
+pub fn all(config: &config::T, plugins: &Arc<plugin::Registry>, now: u64) -> Vec<T> {
+  let (structures, errors) = terrain::structures::load(&config.structures);
+  for err in errors {
+    warn!("{}. Leaving it out.", err);
+  }
+  let overworld_shape =
+    match terrain::heightmap::load(&config.heightmap) {
+      Ok(Some(heightmap)) => terrain::Shape::Heightmap(heightmap),
+      Ok(None) => terrain::Shape::Biomes(config.caves, structures),
+      Err(err) => {
+        warn!("{}. Generating terrain instead.", err);
+        terrain::Shape::Biomes(config.caves, structures)
+      },
+    };
+  vec!(
+    new(OVERWORLD, overworld_shape, config, plugins, now),
+    new(CAVES, terrain::Shape::Caves, config, plugins, now),
+  )
+}
+
+#[allow(missing_docs)]
+pub fn name(id: protocol::WorldId) -> &'static str {
+  NAMES[id.0 as usize]
+}
+
+/// Look up a world by name.
+pub fn parse(name: &str) -> Option<protocol::WorldId> {
+  NAMES.iter().position(|&n| n == name).map(|i| protocol::WorldId(i as u32))
+}
+
+#[test]
+fn names_round_trip() {
+  assert_eq!(parse(name(CAVES)), Some(CAVES));
+  assert_eq!(parse("overworld"), Some(OVERWORLD));
+  assert_eq!(parse("nether"), None);
+}
@@ -5,6 +5,8 @@ use noise::{Seed, perlin3};
 
 use common::voxel;
 
+use Mosaic;
+
 #[allow(missing_docs)]
 pub struct T {
   pub seed: Seed,
@@ -66,3 +68,5 @@ impl voxel::mosaic::T<voxel::Material> for T {
     )
   }
 }
+
+impl Mosaic for T {}
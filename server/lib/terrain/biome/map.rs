@@ -0,0 +1,514 @@
+//! Partition the world into biomes with low-frequency climate noise,
+//! and generate terrain (and water) by dispatching to each biome's parameters.
+//!
+//! The ground is worked out 8 points at a time (see `simd`). When a generator's told which voxels are coming, the
+//! ground is worked out over a grid covering all of their corners at once, and looked up from there. Worlds from
+//! before that get their 3D features from the `noise` crate, one point at a time, as they always did.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
+use std;
+use std::sync::Arc;
+
+use common::material;
+use common::voxel;
+
+use carver;
+use simd;
+use simd::{F32x8, LANES};
+use structures;
+use vegetation;
+use water;
+use Mosaic;
+use Version;
+
+/// The width of the climate features that biomes are chosen by.
+const CLIMATE_SCALE: f64 = 1024.0;
+/// How sharply biomes transition into one another. Higher is sharper.
+const BLEND_SHARPNESS: f32 = 16.0;
+/// How deep the surface material goes before turning into the subsurface material.
+const SURFACE_DEPTH: f32 = 2.0;
+/// The most points to work out ahead of time in one grid.
+const MAX_GRID_POINTS: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Biome {
+  Plains,
+  Desert,
+  Mountains,
+  Tundra,
+}
+
+/// Every biome.
+pub const ALL: [Biome; 4] = [Biome::Plains, Biome::Desert, Biome::Mountains, Biome::Tundra];
+
+/// Parameters to a biome's density function.
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+  /// The average height of the ground.
+  pub base_height       : f32,
+  /// How far the heightmap deviates from `base_height`.
+  pub height_amplitude  : f32,
+  /// The strength of the 3D features (overhangs, lumps) added to the heightmap.
+  pub feature_amplitude : f32,
+}
+
+impl Biome {
+  /// The parameters to this biome's density function.
+  pub fn params(self) -> Params {
+    match self {
+      Biome::Plains    => Params { base_height: 0.0 , height_amplitude: 8.0 , feature_amplitude: 4.0  },
+      Biome::Desert    => Params { base_height: 4.0 , height_amplitude: 12.0, feature_amplitude: 2.0  },
+      Biome::Mountains => Params { base_height: 32.0, height_amplitude: 96.0, feature_amplitude: 16.0 },
+      Biome::Tundra    => Params { base_height: 8.0 , height_amplitude: 16.0, feature_amplitude: 6.0  },
+    }
+  }
+
+  /// The material at the surface of this biome.
+  pub fn surface_material(self) -> voxel::Material {
+    match self {
+      Biome::Plains    => voxel::Material::Terrain,
+      Biome::Desert    => voxel::Material::Sand,
+      Biome::Mountains => voxel::Material::Stone,
+      Biome::Tundra    => voxel::Material::Snow,
+    }
+  }
+
+  /// The material underneath the surface of this biome.
+  pub fn subsurface_material(self) -> voxel::Material {
+    material::get(self.surface_material()).subsurface
+  }
+
+  /// The portion of this biome's surface that is grassy, in [0, 1].
+  pub fn grass_density(self) -> f32 {
+    match self {
+      Biome::Plains    => 1.0,
+      Biome::Desert    => 0.0,
+      Biome::Mountains => 0.3,
+      Biome::Tundra    => 0.1,
+    }
+  }
+
+  /// The chance that a patch of this biome has a tree, in [0, 1].
+  pub fn tree_density(self) -> f32 {
+    match self {
+      Biome::Plains    => 0.3,
+      Biome::Desert    => 0.0,
+      Biome::Mountains => 0.15,
+      Biome::Tundra    => 0.05,
+    }
+  }
+
+  /// Where this biome sits in (temperature, moisture, ruggedness) space, each in [-1, 1].
+  fn climate(self) -> [f32; 3] {
+    match self {
+      Biome::Plains    => [ 0.0,  0.3, -0.3],
+      Biome::Desert    => [ 0.6, -0.6, -0.2],
+      Biome::Mountains => [ 0.0,  0.0,  0.6],
+      Biome::Tundra    => [-0.6,  0.0,  0.0],
+    }
+  }
+}
+
+/// The biome-dependent heightmap of the terrain, without 3D features.
+pub struct Heights {
+  height : Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
+  seed   : Seed,
+}
+
+#[allow(missing_docs)]
+pub fn heights(seed: Seed) -> Heights {
+  let perlin2: fn(&Seed, &[f64; 2]) -> f64 = perlin2;
+  Heights {
+    seed: seed,
+    height:
+      Brownian2::new(perlin2, 5)
+      .frequency(1.0 / 4.0)
+      .persistence(2.0)
+      .lacunarity(1.0 / 2.0)
+    ,
+  }
+}
+
+impl Heights {
+  /// How much each biome contributes at a given (x, z) position. The weights sum to 1.
+  pub fn weights(&self, x: f32, z: f32) -> [f32; 4] {
+    let x = x as f64 / CLIMATE_SCALE;
+    let z = z as f64 / CLIMATE_SCALE;
+    // Offset each climate axis so they're uncorrelated.
+    let climate = [
+      perlin2(&self.seed, &[x + 1000.0, z]) as f32,
+      perlin2(&self.seed, &[x, z - 1000.0]) as f32,
+      perlin2(&self.seed, &[2.0 * x - 500.0, 2.0 * z + 500.0]) as f32,
+    ];
+
+    let mut weights = [0.0; 4];
+    let mut total = 0.0;
+    for (i, biome) in ALL.iter().enumerate() {
+      let center = biome.climate();
+      let d2 =
+        (climate[0] - center[0]).powi(2) +
+        (climate[1] - center[1]).powi(2) +
+        (climate[2] - center[2]).powi(2);
+      weights[i] = (-BLEND_SHARPNESS * d2).exp();
+      total += weights[i];
+    }
+    for w in weights.iter_mut() {
+      *w /= total;
+    }
+    weights
+  }
+
+  /// The predominant biome at a given (x, z) position.
+  pub fn biome_at(&self, x: f32, z: f32) -> Biome {
+    predominant(&self.weights(x, z))
+  }
+
+  /// The portion of the surface that is grassy at a given (x, z) position, blended between neighboring biomes.
+  pub fn grass_density(&self, x: f32, z: f32) -> f32 {
+    let weights = self.weights(x, z);
+    ALL.iter().zip(weights.iter()).map(|(biome, &w)| w * biome.grass_density()).sum()
+  }
+
+  /// The chance of a tree at a given (x, z) position, blended between neighboring biomes.
+  pub fn tree_density(&self, x: f32, z: f32) -> f32 {
+    let weights = self.weights(x, z);
+    ALL.iter().zip(weights.iter()).map(|(biome, &w)| w * biome.tree_density()).sum()
+  }
+
+  /// The height of the ground at a given (x, z) position, without 3D features.
+  pub fn height(&self, x: f32, z: f32) -> f32 {
+    let params = self.params(x, z);
+    self.height_with(&params, x, z)
+  }
+
+  /// The biome parameters at a given (x, z) position, blended between neighboring biomes.
+  pub fn params(&self, x: f32, z: f32) -> Params {
+    blended_params(&self.weights(x, z))
+  }
+
+  fn height_with(&self, params: &Params, x: f32, z: f32) -> f32 {
+    // The heightmap noise has an amplitude of roughly 32.
+    let height = self.height.apply(&self.seed, &[x as f64, z as f64]) as f32 / 32.0;
+    params.base_height + params.height_amplitude * height
+  }
+}
+
+/// The biome with the most weight.
+fn predominant(weights: &[f32; 4]) -> Biome {
+  let mut best = 0;
+  for i in 1 .. weights.len() {
+    if weights[i] > weights[best] {
+      best = i;
+    }
+  }
+  ALL[best]
+}
+
+/// The biomes' parameters, blended by weight.
+fn blended_params(weights: &[f32; 4]) -> Params {
+  let mut params = Params { base_height: 0.0, height_amplitude: 0.0, feature_amplitude: 0.0 };
+  for (biome, &w) in ALL.iter().zip(weights.iter()) {
+    let p = biome.params();
+    params.base_height       += w * p.base_height;
+    params.height_amplitude  += w * p.height_amplitude;
+    params.feature_amplitude += w * p.feature_amplitude;
+  }
+  params
+}
+
+impl water::Heightmap for Heights {
+  fn height(&mut self, x: f32, z: f32) -> f32 {
+    Heights::height(self, x, z)
+  }
+}
+
+/// The parts of the ground's density that are the same all the way up a column.
+#[derive(Debug, Clone, Copy)]
+struct Column {
+  params     : Params,
+  height     : f32,
+  biome      : Biome,
+  grassiness : f32,
+}
+
+/// The ground's density and material over a grid of points.
+struct Grid {
+  low     : Point3<f32>,
+  spacing : f32,
+  /// The number of points along each axis.
+  size    : [usize; 3],
+  /// Indexed by x, then z, then y, so points up a column are next to each other.
+  ground  : Vec<(f32, voxel::Material)>,
+}
+
+impl Grid {
+  fn get(&self, p: &Point3<f32>) -> Option<(f32, voxel::Material)> {
+    let index = |x: f32, low: f32, size: usize| {
+      let i = (x - low) / self.spacing;
+      if i >= 0.0 && i.fract() == 0.0 && (i as usize) < size { Some(i as usize) } else { None }
+    };
+    let x = index(p.x, self.low.x, self.size[0])?;
+    let y = index(p.y, self.low.y, self.size[1])?;
+    let z = index(p.z, self.low.z, self.size[2])?;
+    Some(self.ground[(x * self.size[2] + z) * self.size[1] + y])
+  }
+}
+
+/// Overhangs and the like, on top of the heightmap.
+enum Features {
+  /// For `Version::Scalar` worlds.
+  Scalar(Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>),
+  Batched(simd::Brownian3),
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  heights    : Heights,
+  features   : Features,
+  water      : water::T<Heights>,
+  vegetation : vegetation::T,
+  caves      : carver::T,
+  structures : structures::T,
+  seed       : Seed,
+  /// Voxels that are about to be generated, which no grid's been made for yet.
+  expected   : Vec<voxel::bounds::T>,
+  grids      : Vec<Grid>,
+}
+
+#[allow(missing_docs)]
+pub fn new(
+  seed       : Seed,
+  version    : Version,
+  caves      : carver::Params,
+  structures : structures::Catalog,
+  lakes      : Arc<water::Lakes>,
+) -> T {
+  T {
+    seed: seed,
+    heights: heights(seed),
+    features:
+      match version {
+        Version::Scalar => {
+          let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
+          Features::Scalar(
+            Brownian3::new(perlin3, 2)
+            .frequency(1.0 / 32.0)
+            .persistence(8.0)
+            .lacunarity(1.0 / 4.0)
+          )
+        },
+        Version::Batched =>
+          Features::Batched(
+            simd::Brownian3 {
+              noise       : simd::noise(&seed),
+              octaves     : 2,
+              frequency   : 1.0 / 32.0,
+              persistence : 8.0,
+              lacunarity  : 1.0 / 4.0,
+            }
+          ),
+      },
+    water: water::new(heights(seed), lakes),
+    vegetation: vegetation::new(seed),
+    caves: carver::new(seed, version, caves),
+    structures: structures::new(seed, structures),
+    expected: Vec::new(),
+    grids: Vec::new(),
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn biome_at(&self, x: f32, z: f32) -> Biome {
+    self.heights.biome_at(x, z)
+  }
+
+  fn mat_density(&mut self, p: &Point3<f32>) -> (f32, voxel::Material) {
+    let (d, material) = self.ground_mat_density(p);
+    let mat_density =
+      match self.vegetation.mat_density(&self.heights, &mut self.water, p) {
+        Some((tree_d, tree_material)) if tree_d > d => (tree_d, tree_material),
+        _ => (d, material),
+      };
+    self.structures.apply(&self.heights, &mut self.water, p, mat_density)
+  }
+
+  fn ground_mat_density(&mut self, p: &Point3<f32>) -> (f32, voxel::Material) {
+    if !self.expected.is_empty() {
+      let expected = std::mem::replace(&mut self.expected, Vec::new());
+      self.grids = self.grids_around(&expected);
+    }
+    for grid in &self.grids {
+      if let Some(ground) = grid.get(p) {
+        return ground
+      }
+    }
+    let column = self.column(p.x, p.z);
+    self.ground(&[column; LANES], F32x8::splat(p.x), F32x8::splat(p.y), F32x8::splat(p.z))[0]
+  }
+
+  fn column(&self, x: f32, z: f32) -> Column {
+    let weights = self.heights.weights(x, z);
+    let params = blended_params(&weights);
+    Column {
+      params     : params,
+      height     : self.heights.height_with(&params, x, z),
+      biome      : predominant(&weights),
+      grassiness : (perlin2(&self.seed, &[x as f64 / 8.0, z as f64 / 8.0]) as f32 + 1.0) / 2.0,
+    }
+  }
+
+  /// The ground's density and material at 8 points, each in the column alongside it.
+  fn ground(&self, columns: &[Column; LANES], x: F32x8, y: F32x8, z: F32x8) -> [(f32, voxel::Material); LANES] {
+    let heightmap_density = F32x8::from_fn(|i| columns[i].height) - y;
+
+    let feature_density =
+      match self.features {
+        Features::Scalar(ref features) =>
+          F32x8::from_fn(|i| features.apply(&self.seed, &[x.0[i] as f64, y.0[i] as f64, z.0[i] as f64]) as f32),
+        Features::Batched(ref features) => features.apply(x, y, z),
+      };
+    let feature_density = F32x8::from_fn(|i| columns[i].params.feature_amplitude) * feature_density;
+
+    let d = heightmap_density + feature_density;
+
+    // Cave walls are made of whatever is down there, so carve out caves after picking the material.
+    let carved = d - self.caves.carve(x, y, z, heightmap_density);
+
+    let mut ground = [(0.0, voxel::Material::Empty); LANES];
+    for i in 0 .. LANES {
+      let column = &columns[i];
+      let material =
+        if d.0[i] >= SURFACE_DEPTH {
+          column.biome.subsurface_material()
+        } else if column.grassiness < column.biome.grass_density() {
+          voxel::Material::Terrain
+        } else {
+          column.biome.surface_material()
+        };
+      ground[i] = (carved.0[i], material);
+    }
+    ground
+  }
+
+  /// Work out the ground at every corner of some voxels, with a grid for each voxel size.
+  fn grids_around(&self, voxels: &[voxel::bounds::T]) -> Vec<Grid> {
+    let mut lg_sizes: Vec<i16> = voxels.iter().map(|bounds| bounds.lg_size).collect();
+    lg_sizes.sort();
+    lg_sizes.dedup();
+
+    let mut grids = Vec::new();
+    for lg_size in lg_sizes {
+      let mut low = [std::i32::MAX; 3];
+      let mut high = [std::i32::MIN; 3];
+      for bounds in voxels.iter().filter(|bounds| bounds.lg_size == lg_size) {
+        let corner = [bounds.x, bounds.y, bounds.z];
+        for i in 0 .. 3 {
+          low[i] = std::cmp::min(low[i], corner[i]);
+          // Include the voxel's high corner.
+          high[i] = std::cmp::max(high[i], corner[i] + 1);
+        }
+      }
+      let size = [(high[0] - low[0] + 1) as usize, (high[1] - low[1] + 1) as usize, (high[2] - low[2] + 1) as usize];
+      if size[0] * size[1] * size[2] > MAX_GRID_POINTS {
+        continue
+      }
+      let spacing = (2.0 as f32).powi(lg_size as i32);
+      let low = Point3::new(low[0] as f32 * spacing, low[1] as f32 * spacing, low[2] as f32 * spacing);
+      grids.push(self.grid(low, spacing, size));
+    }
+    grids
+  }
+
+  fn grid(&self, low: Point3<f32>, spacing: f32, size: [usize; 3]) -> Grid {
+    let mut columns = Vec::with_capacity(size[0] * size[2]);
+    for x in 0 .. size[0] {
+    for z in 0 .. size[2] {
+      columns.push(self.column(low.x + x as f32 * spacing, low.z + z as f32 * spacing));
+    }}
+
+    // Go through the points in order, 8 at a time. Lanes past the last point repeat it.
+    let count = columns.len() * size[1];
+    let mut ground = Vec::with_capacity(count);
+    let mut start = 0;
+    while start < count {
+      let mut lane_columns = [columns[0]; LANES];
+      let (mut x, mut y, mut z) = ([0.0; LANES], [0.0; LANES], [0.0; LANES]);
+      for lane in 0 .. LANES {
+        let i = std::cmp::min(start + lane, count - 1);
+        let column = i / size[1];
+        lane_columns[lane] = columns[column];
+        x[lane] = low.x + (column / size[2]) as f32 * spacing;
+        y[lane] = low.y + (i % size[1]) as f32 * spacing;
+        z[lane] = low.z + (column % size[2]) as f32 * spacing;
+      }
+      let lanes = self.ground(&lane_columns, F32x8(x), F32x8(y), F32x8(z));
+      ground.extend_from_slice(&lanes[.. std::cmp::min(LANES, count - start)]);
+      start += LANES;
+    }
+
+    Grid {
+      low     : low,
+      spacing : spacing,
+      size    : size,
+      ground  : ground,
+    }
+  }
+}
+impl voxel::field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    let (d, _) = self.mat_density(p);
+    d
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    // Use density differential in each dimension as an approximation of the normal.
+
+    let delta = 0.01;
+
+    macro_rules! differential(($d:ident) => {{
+      let high: f32 = {
+        let mut p = *p;
+        p.$d += delta;
+        voxel::field::T::density(self, &p)
+      };
+      let low: f32 = {
+        let mut p = *p;
+        p.$d -= delta;
+        voxel::field::T::density(self, &p)
+      };
+      high - low
+    }});
+
+    let v = Vector3::new(differential!(x), differential!(y), differential!(z));
+    // Negate because we're leaving the volume when density is decreasing.
+    let v = -v;
+    v.normalize()
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let (d, mat) = self.mat_density(p);
+    Some(
+      if d >= 0.0 {
+        mat
+      } else if self.heights.height(p.x, p.z) > p.y {
+        // Caves stay dry, even below the water level.
+        voxel::Material::Empty
+      } else if voxel::field::T::density(&mut self.water, p) >= 0.0 {
+        voxel::Material::Water
+      } else {
+        voxel::Material::Empty
+      }
+    )
+  }
+}
+
+impl Mosaic for T {
+  fn expect(&mut self, voxels: &[voxel::bounds::T]) {
+    self.expected = voxels.to_vec();
+  }
+}
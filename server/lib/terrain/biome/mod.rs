@@ -3,4 +3,5 @@
 pub mod caves;
 pub mod demo;
 pub mod hills;
+pub mod map;
 pub mod mountains;
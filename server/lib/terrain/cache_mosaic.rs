@@ -5,6 +5,8 @@ use std;
 
 use common::voxel;
 
+use Mosaic;
+
 #[derive(Clone, PartialEq)]
 pub struct Key(cgmath::Point3<f32>);
 
@@ -21,15 +23,15 @@ impl std::hash::Hash for Key {
 
 pub type Cache<T> = lru_cache::LruCache<Key, T, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
 
-pub struct T<Material> {
-  pub mosaic          : Box<dyn voxel::mosaic::T<Material> + Send>,
+pub struct T {
+  pub mosaic          : Box<dyn Mosaic>,
   pub density         : Cache<f32>,
   pub normal          : Cache<cgmath::Vector3<f32>>,
   pub mosaic_density  : Cache<f32>,
-  pub mosaic_material : Cache<Option<Material>>,
+  pub mosaic_material : Cache<Option<voxel::Material>>,
 }
 
-pub fn new<Material>(mosaic: Box<dyn voxel::mosaic::T<Material> + Send>) -> T<Material> {
+pub fn new(mosaic: Box<dyn Mosaic>) -> T {
   T {
     mosaic          : mosaic,
     density         : lru_cache::LruCache::with_hasher(1 << 10, Default::default()),
@@ -51,7 +53,7 @@ fn get_or_init<T: Clone, F>(cache: &mut Cache<T>, k: Key, f: F) -> T where
   x
 }
 
-impl<Material> voxel::field::T for T<Material> {
+impl voxel::field::T for T {
   fn density(&mut self, p: &cgmath::Point3<f32>) -> f32 {
     let mosaic = &mut self.mosaic;
     get_or_init(
@@ -71,7 +73,7 @@ impl<Material> voxel::field::T for T<Material> {
   }
 }
 
-impl<Material> voxel::mosaic::T<Material> for T<Material> where Material: Clone {
+impl voxel::mosaic::T<voxel::Material> for T {
   fn density(&mut self, p: &cgmath::Point3<f32>) -> f32 {
     let mosaic = &mut self.mosaic;
     get_or_init(
@@ -81,7 +83,7 @@ impl<Material> voxel::mosaic::T<Material> for T<Material> where Material: Clone
     )
   }
 
-  fn material(&mut self, p: &cgmath::Point3<f32>) -> Option<Material> {
+  fn material(&mut self, p: &cgmath::Point3<f32>) -> Option<voxel::Material> {
     let mosaic = &mut self.mosaic;
     get_or_init(
       &mut self.mosaic_material,
@@ -0,0 +1,174 @@
+//! Carve caves out of the ground with ridged multifractal noise. A single ridged field peaks
+//! along sheets, so tunnels are carved where two of them cross.
+//!
+//! Caves are carved 8 points at a time; see `simd`. Worlds from before that are carved with the `noise` crate, one
+//! point at a time, as they always were.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use noise::{Seed, perlin3};
+
+use common::voxel;
+
+use simd;
+use simd::F32x8;
+use Version;
+
+/// How far apart the two ridged fields are sampled, so they're uncorrelated.
+const SECOND_FIELD_OFFSET: f32 = 1000.0;
+/// Scales cave density up to roughly match the terrain's, so cave walls are about as sharp as the surface.
+const STRENGTH: f32 = 32.0;
+
+/// How caves are carved.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Params {
+  /// Whether to carve caves at all.
+  pub enabled   : bool,
+  /// Roughly the distance between caves.
+  pub scale     : f32,
+  /// The number of noise octaves. More octaves make rougher cave walls.
+  pub octaves   : u32,
+  /// Caves are carved where the ridged noise exceeds this, in [0, 1]. Higher makes narrower tunnels.
+  pub threshold : f32,
+  /// Caves taper off as they get closer than this to the surface, so they don't riddle the ground with holes.
+  pub min_depth : f32,
+}
+
+impl Default for Params {
+  fn default() -> Self {
+    Params {
+      enabled   : true,
+      scale     : 64.0,
+      octaves   : 3,
+      threshold : 0.8,
+      min_depth : 8.0,
+    }
+  }
+}
+
+/// A cave field: positive density is inside a cave.
+pub struct T {
+  seed    : Seed,
+  noise   : simd::Noise,
+  version : Version,
+  params  : Params,
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: Seed, version: Version, params: Params) -> T {
+  T {
+    seed    : seed,
+    noise   : simd::noise(&seed),
+    version : version,
+    params  : params,
+  }
+}
+
+impl T {
+  /// How much to take away from the ground's density at 8 points, each `depth` below the surface.
+  /// Zero outside of caves.
+  pub fn carve(&self, x: F32x8, y: F32x8, z: F32x8, depth: F32x8) -> F32x8 {
+    if !self.params.enabled {
+      return F32x8::splat(0.0)
+    }
+    let taper =
+      if self.params.min_depth > 0.0 {
+        let min_depth = self.params.min_depth;
+        depth.map(|depth| f32::max(0.0, min_depth - depth) / min_depth)
+      } else {
+        F32x8::splat(0.0)
+      };
+    let carved = (self.density_at(x, y, z) - taper).max(F32x8::splat(0.0)) * STRENGTH;
+    // Nothing above the surface is carved.
+    depth.zip(carved, |depth, carved| if depth > 0.0 { carved } else { 0.0 })
+  }
+
+  fn density_at(&self, x: F32x8, y: F32x8, z: F32x8) -> F32x8 {
+    if self.version == Version::Scalar {
+      return F32x8::from_fn(|i| self.scalar_density_at([x.0[i] as f64, y.0[i] as f64, z.0[i] as f64]))
+    }
+    let first = self.ridged(x, y, z);
+    let second = self.ridged(x.map(|x| x + SECOND_FIELD_OFFSET), y, z.map(|z| z - SECOND_FIELD_OFFSET));
+    first.min(second).map(|d| d - self.params.threshold)
+  }
+
+  /// Ridged multifractal noise in [0, 1]. Each octave is weighted by the one before it, so
+  /// detail only shows up near the ridges.
+  fn ridged(&self, x: F32x8, y: F32x8, z: F32x8) -> F32x8 {
+    let mut frequency = 1.0 / self.params.scale;
+    let mut amplitude = 1.0;
+    let mut weight = F32x8::splat(1.0);
+    let mut total = F32x8::splat(0.0);
+    let mut max = 0.0;
+    for _ in 0 .. self.params.octaves {
+      let n = self.noise.perlin3(x * frequency, y * frequency, z * frequency);
+      let n = n.map(|n| 1.0 - n.abs());
+      let n = n * n * weight;
+      weight = n.map(|n| f32::max(0.0, f32::min(1.0, 2.0 * n)));
+      total = total + n * amplitude;
+      max += amplitude;
+      amplitude /= 2.0;
+      frequency *= 2.0;
+    }
+    if max > 0.0 { total * (1.0 / max) } else { F32x8::splat(0.0) }
+  }
+
+  /// `density_at` for `Version::Scalar` worlds.
+  fn scalar_density_at(&self, p: [f64; 3]) -> f32 {
+    let offset = SECOND_FIELD_OFFSET as f64;
+    let q = [p[0] + offset, p[1], p[2] - offset];
+    let d = f64::min(self.scalar_ridged(p), self.scalar_ridged(q));
+    d as f32 - self.params.threshold
+  }
+
+  /// `ridged` for `Version::Scalar` worlds.
+  fn scalar_ridged(&self, p: [f64; 3]) -> f64 {
+    let mut frequency = 1.0 / self.params.scale as f64;
+    let mut amplitude = 1.0;
+    let mut weight = 1.0;
+    let mut total = 0.0;
+    let mut max = 0.0;
+    for _ in 0 .. self.params.octaves {
+      let n = perlin3(&self.seed, &[p[0] * frequency, p[1] * frequency, p[2] * frequency]);
+      let n = 1.0 - n.abs();
+      let n = n * n * weight;
+      weight = f64::max(0.0, f64::min(1.0, 2.0 * n));
+      total += amplitude * n;
+      max += amplitude;
+      amplitude /= 2.0;
+      frequency *= 2.0;
+    }
+    if max > 0.0 { total / max } else { 0.0 }
+  }
+}
+
+impl voxel::field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.density_at(F32x8::splat(p.x), F32x8::splat(p.y), F32x8::splat(p.z)).0[0]
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    // Use density differential in each dimension as an approximation of the normal.
+
+    let delta = 0.01;
+
+    macro_rules! differential(($d:ident) => {{
+      let high: f32 = {
+        let mut p = *p;
+        p.$d += delta;
+        voxel::field::T::density(self, &p)
+      };
+      let low: f32 = {
+        let mut p = *p;
+        p.$d -= delta;
+        voxel::field::T::density(self, &p)
+      };
+      high - low
+    }});
+
+    let v = Vector3::new(differential!(x), differential!(y), differential!(z));
+    // Negate because we're leaving the volume when density is decreasing.
+    let v = -v;
+    v.normalize()
+  }
+}
@@ -0,0 +1,202 @@
+//! Terrain shaped by a grayscale image instead of noise, for real-world or hand-made maps.
+//! Images are read as binary PGM files, 8 or 16 bits per pixel; most image editors and GIS tools can export these.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use std;
+use std::io::Read;
+use std::sync::Arc;
+
+use common::material;
+use common::voxel;
+
+use Mosaic;
+
+/// How deep the surface material goes before turning into the subsurface material.
+const SURFACE_DEPTH: f32 = 2.0;
+
+/// How a heightmap is placed in the world.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Params {
+  /// The image to read. If this is None, terrain is generated from noise instead.
+  pub path             : Option<String>,
+  /// World units per pixel. The image is centered on the origin.
+  pub horizontal_scale : f32,
+  /// The height of a white pixel above a black one.
+  pub vertical_scale   : f32,
+  /// The height of a black pixel.
+  pub base_height      : f32,
+}
+
+impl Default for Params {
+  fn default() -> Self {
+    Params {
+      path             : None,
+      horizontal_scale : 1.0,
+      vertical_scale   : 128.0,
+      base_height      : -32.0,
+    }
+  }
+}
+
+/// A loaded heightmap. Cloning it is cheap; the pixels are shared.
+#[derive(Clone)]
+pub struct T {
+  width   : usize,
+  depth   : usize,
+  /// Row-major, normalized to [0, 1].
+  samples : Arc<Vec<f32>>,
+  params  : Params,
+}
+
+/// Read the heightmap named in `params`, if there is one.
+pub fn load(params: &Params) -> Result<Option<T>, String> {
+  let path =
+    match params.path {
+      None => return Ok(None),
+      Some(ref path) => path,
+    };
+  let mut contents = Vec::new();
+  std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut contents))
+    .map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+  parse(&contents, params.clone())
+    .map(Some)
+    .map_err(|err| format!("Couldn't parse {}: {}", path, err))
+}
+
+/// Parse a binary PGM image.
+fn parse(contents: &[u8], params: Params) -> Result<T, String> {
+  let mut header = Vec::new();
+  let mut i = 0;
+  while header.len() < 4 {
+    while i < contents.len() && (contents[i] as char).is_whitespace() {
+      i += 1;
+    }
+    if i < contents.len() && contents[i] == b'#' {
+      while i < contents.len() && contents[i] != b'\n' {
+        i += 1;
+      }
+      continue
+    }
+    let start = i;
+    while i < contents.len() && !(contents[i] as char).is_whitespace() {
+      i += 1;
+    }
+    if start == i {
+      return Err(String::from("header is incomplete"))
+    }
+    header.push(String::from_utf8_lossy(&contents[start .. i]).into_owned());
+  }
+  // Exactly one whitespace character separates the header from the pixels.
+  i += 1;
+
+  if header[0] != "P5" {
+    return Err(format!("expected a binary PGM (P5), not {}", header[0]))
+  }
+  let number = |s: &str| s.parse::<usize>().map_err(|err| format!("bad header value {}: {}", s, err));
+  let width = number(&header[1])?;
+  let depth = number(&header[2])?;
+  let max = number(&header[3])?;
+  if width == 0 || depth == 0 || max == 0 || max > 65535 {
+    return Err(format!("bad dimensions {}x{} or maximum value {}", width, depth, max))
+  }
+
+  let bytes_per_sample = if max < 256 { 1 } else { 2 };
+  let pixels = if i <= contents.len() { &contents[i ..] } else { &[] };
+  if pixels.len() < width * depth * bytes_per_sample {
+    return Err(format!("expected {} bytes of pixels, found {}", width * depth * bytes_per_sample, pixels.len()))
+  }
+
+  let samples =
+    pixels.chunks(bytes_per_sample)
+    .take(width * depth)
+    .map(|sample| {
+      let value = if bytes_per_sample == 1 { sample[0] as u32 } else { ((sample[0] as u32) << 8) | sample[1] as u32 };
+      value as f32 / max as f32
+    })
+    .collect();
+
+  Ok(T {
+    width   : width,
+    depth   : depth,
+    samples : Arc::new(samples),
+    params  : params,
+  })
+}
+
+impl T {
+  fn sample(&self, x: isize, z: isize) -> f32 {
+    let x = std::cmp::max(0, std::cmp::min(x, self.width as isize - 1)) as usize;
+    let z = std::cmp::max(0, std::cmp::min(z, self.depth as isize - 1)) as usize;
+    self.samples[z * self.width + x]
+  }
+
+  /// The height of the ground at a given (x, z) position, interpolated between pixels. Past the edges of the
+  /// image, the edge pixels carry on.
+  pub fn height(&self, x: f32, z: f32) -> f32 {
+    let x = x / self.params.horizontal_scale + (self.width as f32 - 1.0) / 2.0;
+    let z = z / self.params.horizontal_scale + (self.depth as f32 - 1.0) / 2.0;
+    let (x0, z0) = (x.floor(), z.floor());
+    let (fx, fz) = (x - x0, z - z0);
+    let (x0, z0) = (x0 as isize, z0 as isize);
+
+    let near = self.sample(x0, z0) * (1.0 - fx) + self.sample(x0 + 1, z0) * fx;
+    let far = self.sample(x0, z0 + 1) * (1.0 - fx) + self.sample(x0 + 1, z0 + 1) * fx;
+    let v = near * (1.0 - fz) + far * fz;
+    self.params.base_height + v * self.params.vertical_scale
+  }
+}
+
+impl voxel::field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.height(p.x, p.z) - p.y
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    // The density only changes with y through the -y term, so the normal only needs the height's slope.
+    let delta = self.params.horizontal_scale / 2.0;
+    let dx = self.height(p.x + delta, p.z) - self.height(p.x - delta, p.z);
+    let dz = self.height(p.x, p.z + delta) - self.height(p.x, p.z - delta);
+    Vector3::new(-dx, 2.0 * delta, -dz).normalize()
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let d = voxel::field::T::density(self, p);
+    Some(
+      if d < 0.0 {
+        voxel::Material::Empty
+      } else if d < SURFACE_DEPTH {
+        voxel::Material::Terrain
+      } else {
+        material::get(voxel::Material::Terrain).subsurface
+      }
+    )
+  }
+}
+
+impl Mosaic for T {}
+
+#[test]
+fn heights_are_interpolated_between_pixels() {
+  use voxel::field::T;
+
+  let params = Params { vertical_scale: 10.0, base_height: 0.0, ..Params::default() };
+  // A 2x2 image, 16-bit, with a comment in the header.
+  let mut contents = b"P5\n# ramp\n2 2\n65535\n".to_vec();
+  contents.extend_from_slice(&[0, 0, 255, 255, 0, 0, 255, 255]);
+  let mut heightmap = parse(&contents, params).unwrap();
+
+  assert_eq!(heightmap.height(-0.5, 0.0), 0.0);
+  assert_eq!(heightmap.height(0.0, 0.0), 5.0);
+  assert_eq!(heightmap.height(0.5, 0.5), 10.0);
+  assert_eq!(heightmap.height(100.0, -100.0), 10.0);
+  assert!(heightmap.density(&Point3::new(0.0, 4.0, 0.0)) > 0.0);
+  assert!(heightmap.density(&Point3::new(0.0, 6.0, 0.0)) < 0.0);
+  let normal = heightmap.normal(&Point3::new(0.0, 5.0, 0.0));
+  assert!(normal.x < 0.0 && normal.y > 0.0 && normal.z == 0.0);
+
+  assert!(parse(b"P2\n2 2\n255\n", Params::default()).is_err());
+  assert!(parse(b"P5\n2 2\n255\n\x00", Params::default()).is_err());
+}
@@ -11,6 +11,9 @@ extern crate log;
 extern crate lru_cache;
 extern crate noise;
 extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate stopwatch;
 extern crate time;
 extern crate voxel_data;
@@ -19,47 +22,220 @@ extern crate num;
 mod cache_mosaic;
 
 pub mod biome;
+pub mod carver;
+pub mod heightmap;
+pub mod prefab;
+pub mod simd;
+pub mod structures;
 pub mod tree;
+pub mod vegetation;
+pub mod water;
 
 pub use noise::Seed;
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use common::voxel;
 
+/// Which noise terrain is generated from. A world keeps the version it was first generated with, so voxels generated
+/// later line up with the ones already stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+  /// The `noise` crate's noise, one point at a time.
+  Scalar,
+  /// `simd`'s noise, 8 points at a time.
+  Batched,
+}
+
+/// What new worlds are generated with.
+pub const LATEST_VERSION: Version = Version::Batched;
+
+impl Version {
+  /// The number this version is stored as.
+  pub fn number(self) -> u32 {
+    match self {
+      Version::Scalar  => 1,
+      Version::Batched => 2,
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn of_number(number: u32) -> Option<Version> {
+    match number {
+      1 => Some(Version::Scalar),
+      2 => Some(Version::Batched),
+      _ => None,
+    }
+  }
+}
+
+/// Generates voxels from scratch. Generators keep their own caches, so each thread generating
+/// terrain in parallel should have its own.
+pub struct Generator {
+  mosaic: cache_mosaic::T,
+}
+
+/// The terrain a generator generates.
+pub trait Mosaic: voxel::mosaic::T<voxel::Material> + Send {
+  /// Some voxels are about to be generated, so points around them can be worked out together, which can be much
+  /// faster than one at a time. Nothing needs to be done until the first of them is generated, in case they
+  /// don't all need generating. Does nothing by default.
+  fn expect(&mut self, _voxels: &[voxel::bounds::T]) {}
+}
+
+impl Generator {
+  /// Say which voxels are likely to be generated next, like all those in a chunk.
+  pub fn expect(&mut self, voxels: &[voxel::bounds::T]) {
+    self.mosaic.mosaic.expect(voxels);
+  }
+
+  fn generate(&mut self, bounds: &voxel::bounds::T) -> voxel::T {
+    voxel::unwrap(voxel::of_field(&mut self.mosaic, bounds))
+  }
+}
+
+/// What generated terrain looks like.
+#[derive(Clone)]
+pub enum Shape {
+  /// Ground shaped by the biomes, with caves carved out of it and structures built on it.
+  Biomes(carver::Params, structures::Catalog),
+  /// Ground shaped by an image.
+  Heightmap(heightmap::T),
+  /// Solid rock, riddled with caverns.
+  Caves,
+}
+
+#[allow(missing_docs)]
+pub fn generator(terrain_seed: Seed, version: Version, shape: &Shape, lakes: &Arc<water::Lakes>) -> Generator {
+  let mosaic: Box<dyn Mosaic> =
+    match *shape {
+      Shape::Biomes(caves, ref structures) =>
+        Box::new(biome::map::new(terrain_seed, version, caves, structures.clone(), lakes.clone())),
+      Shape::Heightmap(ref heightmap) => Box::new(heightmap.clone()),
+      Shape::Caves => Box::new(biome::caves::new(terrain_seed)),
+    };
+  Generator {
+    mosaic: cache_mosaic::new(mosaic),
+  }
+}
+
 /// This struct contains and lazily generates the world's terrain.
 #[allow(missing_docs)]
 pub struct T {
-  pub mosaic: Mutex<cache_mosaic::T<voxel::Material>>,
+  pub mosaic: Mutex<Generator>,
   pub voxels: Mutex<voxel::tree::T>,
+  heights: biome::map::Heights,
+  lakes: Arc<water::Lakes>,
+  seed: Seed,
+  version: Version,
+  shape: Shape,
 }
 
 impl T {
   #[allow(missing_docs)]
-  pub fn new(terrain_seed: Seed) -> T {
+  pub fn new(terrain_seed: Seed, version: Version, shape: Shape) -> T {
+    let lakes = water::lakes();
     T {
-      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
+      mosaic: Mutex::new(generator(terrain_seed, version, &shape, &lakes)),
       voxels: Mutex::new(voxel::tree::new()),
+      heights: biome::map::heights(terrain_seed),
+      lakes: lakes,
+      seed: terrain_seed,
+      version: version,
+      shape: shape,
+    }
+  }
+
+  /// Make a new generator for this terrain.
+  pub fn generator(&self) -> Generator {
+    generator(self.seed, self.version, &self.shape, &self.lakes)
+  }
+
+  /// Note that a voxel's been loaded, so the water worked out around it is worth keeping.
+  pub fn loaded(&self, bounds: &voxel::bounds::T) {
+    self.lakes.loaded(bounds);
+  }
+
+  /// Note that a voxel's been unloaded, so water worked out around it can be forgotten.
+  pub fn unloaded(&self, bounds: &voxel::bounds::T) {
+    self.lakes.unloaded(bounds);
+  }
+
+  /// The portion of grassy ground that grows grass at a given (x, z) position, in [0, 1].
+  pub fn grass_density(&self, x: f32, z: f32) -> f32 {
+    match self.shape {
+      // Nothing grows underground.
+      Shape::Caves => 0.0,
+      // The biomes already only make ground grassy in proportion to their grass density, so it all grows grass.
+      Shape::Biomes(..) => 1.0,
+      // Heightmaps make all their ground grassy, so thin the grass on it instead.
+      Shape::Heightmap(_) => self.heights.grass_density(x, z),
+    }
+  }
+
+  /// Whether this terrain is under an open sky, and so has weather and seasons.
+  pub fn has_sky(&self) -> bool {
+    match self.shape {
+      Shape::Caves => false,
+      Shape::Biomes(..) | Shape::Heightmap(_) => true,
+    }
+  }
+
+  /// The predominant biome at a given (x, z) position, or `None` underground.
+  pub fn biome_at(&self, x: f32, z: f32) -> Option<biome::map::Biome> {
+    match self.shape {
+      Shape::Caves => None,
+      Shape::Biomes(..) | Shape::Heightmap(_) => Some(self.heights.biome_at(x, z)),
+    }
+  }
+
+  /// The rough height of the ground at a given (x, z) position, without 3D features like overhangs, caves or trees.
+  /// `None` if there's no surface at all.
+  pub fn surface_height(&self, x: f32, z: f32) -> Option<f32> {
+    match self.shape {
+      Shape::Caves => None,
+      Shape::Biomes(..) => Some(self.heights.height(x, z)),
+      Shape::Heightmap(ref heightmap) => Some(heightmap.height(x, z)),
     }
   }
 
   /// Load the block of terrain at a given position.
-  // TODO: Allow this to be performed in such a way that self is only briefly locked.
   pub fn load(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let mut generator = self.mosaic.lock().unwrap();
+    self.load_with(&mut *generator, bounds)
+  }
+
+  /// Load the block of terrain at a given position, using a specific generator if it needs to be
+  /// generated. The voxel tree is not locked while generating.
+  pub fn load_with(&self, generator: &mut Generator, bounds: &voxel::bounds::T) -> voxel::T {
+    if let Some(voxel) = self.voxels.lock().unwrap().get(bounds) {
+      return *voxel
+    }
+
+    let voxel = generator.generate(bounds);
+
     let mut voxels = self.voxels.lock().unwrap();
     let node = voxels.get_mut_or_create(bounds);
-    match node.data {
-      None => {
-        let mut mosaic = self.mosaic.lock().unwrap();
-        let voxel = voxel::unwrap(voxel::of_field(&mut *mosaic, bounds));
-        let r = voxel;
-        node.data = Some(voxel);
-        r
-      },
-      Some(data) => {
-        data
-      },
-    }
+    // Someone else may have loaded or modified this voxel while we were generating it.
+    *node.data.get_or_insert(voxel)
+  }
+
+  /// Throw away a voxel, including any changes made to it, and generate it again from scratch.
+  pub fn regenerate(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let voxel = self.generate(bounds);
+    self.set(bounds, voxel);
+    voxel
+  }
+
+  /// Generate a voxel from scratch, without keeping it or touching what's there now.
+  pub fn generate(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let mut generator = self.mosaic.lock().unwrap();
+    generator.generate(bounds)
+  }
+
+  /// Set a voxel outright, e.g. to put back what an edit replaced.
+  pub fn set(&self, bounds: &voxel::bounds::T, voxel: voxel::T) {
+    self.voxels.lock().unwrap().get_mut_or_create(bounds).data = Some(voxel);
   }
 
   /// Apply a voxel brush to the terrain.
@@ -79,8 +255,8 @@ impl T {
         if bounds.lg_size > 3 {
           None
         } else {
-          let mut mosaic = self.mosaic.lock().unwrap();
-          Some(voxel::unwrap(voxel::of_field(&mut *mosaic, bounds)))
+          let mut generator = self.mosaic.lock().unwrap();
+          Some(generator.generate(bounds))
         }
       },
       &mut voxel_changed,
@@ -0,0 +1,187 @@
+//! Prefab structures: recipes of simple shapes that add material to the ground or carve it away, and blobs of
+//! voxels read from disk. Both are stamped over the ground the same way, part by part.
+
+use cgmath::Point3;
+use collision::Aabb3;
+use std;
+use std::sync::Arc;
+
+use common::schematic;
+use common::voxel;
+
+/// A shape, positive inside.
+#[derive(Debug, Clone, Copy)]
+pub enum Solid {
+  /// An axis-aligned box.
+  Cuboid { min: Point3<f32>, max: Point3<f32> },
+  /// An upright cylinder standing on `base`.
+  Cylinder { base: Point3<f32>, radius: f32, height: f32 },
+}
+
+impl Solid {
+  #[allow(missing_docs)]
+  pub fn density(&self, p: &Point3<f32>) -> f32 {
+    match *self {
+      Solid::Cuboid { min, max } => {
+        (p.x - min.x).min(max.x - p.x)
+        .min((p.y - min.y).min(max.y - p.y))
+        .min((p.z - min.z).min(max.z - p.z))
+      },
+      Solid::Cylinder { base, radius, height } => {
+        let (dx, dz) = (p.x - base.x, p.z - base.z);
+        let across = radius - (dx * dx + dz * dz).sqrt();
+        let along = (p.y - base.y).min(base.y + height - p.y);
+        across.min(along)
+      },
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn bounds(&self) -> Aabb3<f32> {
+    match *self {
+      Solid::Cuboid { min, max } => Aabb3::new(min, max),
+      Solid::Cylinder { base, radius, height } => {
+        Aabb3::new(
+          Point3::new(base.x - radius, base.y, base.z - radius),
+          Point3::new(base.x + radius, base.y + height, base.z + radius),
+        )
+      },
+    }
+  }
+}
+
+/// Blobs are schematics, e.g. ones exported from a client.
+pub type Blob = schematic::T;
+
+/// The density and material at a point relative to a blob's low corner. Solid cells are +1 at their centers and
+/// empty ones -1, blended linearly in between so the surface has a slope to take normals from.
+fn mat_density(blob: &Blob, p: &Point3<f32>) -> (f32, voxel::Material) {
+  let (x, y, z) = (p.x - 0.5, p.y - 0.5, p.z - 0.5);
+  let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+  let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+  let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+
+  let mut density = 0.0;
+  let mut material = voxel::Material::Empty;
+  let mut heaviest = 0.0;
+  for &(dx, wx) in &[(0, 1.0 - fx), (1, fx)] {
+  for &(dy, wy) in &[(0, 1.0 - fy), (1, fy)] {
+  for &(dz, wz) in &[(0, 1.0 - fz), (1, fz)] {
+    let weight = wx * wy * wz;
+    let cell = blob.get(x0 + dx, y0 + dy, z0 + dz);
+    if cell == voxel::Material::Empty {
+      density -= weight;
+    } else {
+      density += weight;
+      if weight > heaviest {
+        heaviest = weight;
+        material = cell;
+      }
+    }
+  }}}
+  (density, material)
+}
+
+/// Read a blob file.
+pub fn load(path: &str) -> Result<Blob, String> {
+  schematic::load(std::path::Path::new(path))
+}
+
+/// One step of a structure's recipe.
+#[derive(Debug, Clone)]
+pub enum Part {
+  /// Fill a shape with some material.
+  Fill(Solid, voxel::Material),
+  /// Hollow a shape out.
+  Carve(Solid),
+  /// Replace everything in a blob's box with the blob, placed with its low corner at a point.
+  Blob(Point3<f32>, Arc<Blob>),
+}
+
+impl Part {
+  #[allow(missing_docs)]
+  pub fn bounds(&self) -> Aabb3<f32> {
+    match *self {
+      Part::Fill(ref solid, _) | Part::Carve(ref solid) => solid.bounds(),
+      Part::Blob(low, ref blob) => Aabb3::new(low, low + blob.extent()),
+    }
+  }
+
+  /// Stamp this part over the density and material at a point.
+  pub fn apply(&self, p: &Point3<f32>, (d, material): (f32, voxel::Material)) -> (f32, voxel::Material) {
+    match *self {
+      Part::Fill(ref solid, fill) => {
+        let solid_d = solid.density(p);
+        if solid_d > d { (solid_d, fill) } else { (d, material) }
+      },
+      Part::Carve(ref solid) => {
+        (d.min(-solid.density(p)), material)
+      },
+      Part::Blob(low, ref blob) => {
+        let extent = blob.extent();
+        let inside =
+          low.x <= p.x && p.x <= low.x + extent.x &&
+          low.y <= p.y && p.y <= low.y + extent.y &&
+          low.z <= p.z && p.z <= low.z + extent.z;
+        if inside {
+          mat_density(blob, &Point3::new(p.x - low.x, p.y - low.y, p.z - low.z))
+        } else {
+          (d, material)
+        }
+      },
+    }
+  }
+}
+
+/// A structure, in world coordinates.
+pub struct T {
+  /// Applied in order, so later parts (e.g. doorways) cut into earlier ones.
+  pub parts  : Vec<Part>,
+  /// Everything the parts touch.
+  pub bounds : Aabb3<f32>,
+}
+
+#[allow(missing_docs)]
+pub fn new(parts: Vec<Part>) -> T {
+  let mut bounds = parts[0].bounds();
+  for part in &parts[1 ..] {
+    let b = part.bounds();
+    bounds.min = Point3::new(bounds.min.x.min(b.min.x), bounds.min.y.min(b.min.y), bounds.min.z.min(b.min.z));
+    bounds.max = Point3::new(bounds.max.x.max(b.max.x), bounds.max.y.max(b.max.y), bounds.max.z.max(b.max.z));
+  }
+  T {
+    parts  : parts,
+    bounds : bounds,
+  }
+}
+
+impl T {
+  /// Stamp the structure over the density and material at a point.
+  pub fn apply(&self, p: &Point3<f32>, mut mat_density: (f32, voxel::Material)) -> (f32, voxel::Material) {
+    for part in &self.parts {
+      mat_density = part.apply(p, mat_density);
+    }
+    mat_density
+  }
+}
+
+#[test]
+fn parts_fill_carve_and_replace() {
+  let ground = (1.0, voxel::Material::Terrain);
+  let wall = Solid::Cuboid { min: Point3::new(0.0, 0.0, 0.0), max: Point3::new(4.0, 4.0, 4.0) };
+  let room = Solid::Cuboid { min: Point3::new(1.0, 1.0, 1.0), max: Point3::new(3.0, 3.0, 3.0) };
+  let hut = new(vec!(Part::Fill(wall, voxel::Material::Stone), Part::Carve(room)));
+
+  assert_eq!(hut.apply(&Point3::new(0.5, 2.0, 2.0), ground), (0.5, voxel::Material::Terrain));
+  assert_eq!(hut.apply(&Point3::new(0.5, 2.0, 2.0), (-3.0, voxel::Material::Empty)), (0.5, voxel::Material::Stone));
+  assert!(hut.apply(&Point3::new(2.0, 2.0, 2.0), ground).0 < 0.0);
+
+  let blob = Blob {
+    size      : [2, 1, 1],
+    materials : vec!(voxel::Material::Marble, voxel::Material::Empty),
+  };
+  let blob = Part::Blob(Point3::new(10.0, 0.0, 0.0), Arc::new(blob));
+  assert_eq!(blob.apply(&Point3::new(10.5, 0.5, 0.5), ground), (1.0, voxel::Material::Marble));
+  assert!(blob.apply(&Point3::new(11.5, 0.5, 0.5), ground).0 < 0.0);
+  assert_eq!(blob.apply(&Point3::new(20.0, 0.5, 0.5), ground), ground);
+}
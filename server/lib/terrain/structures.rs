@@ -0,0 +1,305 @@
+//! Scatter villages, ruins and dungeons over the terrain. Like trees, structures are placed deterministically by
+//! position: each one is rebuilt from scratch wherever it's needed, so a structure spanning several chunks comes
+//! out whole no matter which generator makes which chunk, or in what order.
+
+use cgmath::{Point3, Vector3};
+use fnv;
+use lru_cache;
+use noise::{Seed, perlin3};
+use rand;
+use rand::Rng;
+use std;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use common::voxel;
+
+use biome::map::{Biome, Heights};
+use prefab;
+use prefab::{Part, Solid};
+use water;
+
+/// The furthest a structure reaches from its center, horizontally.
+const MAX_REACH: f32 = 48.0;
+/// The number of cells' structures to keep built.
+const CACHE_SIZE: usize = 1 << 4;
+
+/// Which structures are generated, and how often.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Params {
+  /// Whether to generate structures at all.
+  pub enabled   : bool,
+  /// The width of the cells structures are scattered in. Each cell has at most one structure.
+  pub spacing   : f32,
+  /// The chance that a cell has a structure, in [0, 1].
+  pub frequency : f32,
+  /// Blob files (see `prefab::Blob`) to scatter alongside the built-in structures.
+  pub prefabs   : Vec<String>,
+}
+
+impl Default for Params {
+  fn default() -> Self {
+    Params {
+      enabled   : true,
+      spacing   : 256.0,
+      frequency : 0.5,
+      prefabs   : Vec::new(),
+    }
+  }
+}
+
+/// The structures a world can have. Cloning it is cheap; the prefabs are shared.
+#[derive(Clone)]
+pub struct Catalog {
+  params  : Params,
+  prefabs : Arc<Vec<Arc<prefab::Blob>>>,
+}
+
+/// Read the prefabs named in `params`. Any that can't be read are skipped, with the reasons returned alongside.
+pub fn load(params: &Params) -> (Catalog, Vec<String>) {
+  let mut prefabs = Vec::new();
+  let mut errors = Vec::new();
+  for path in &params.prefabs {
+    match prefab::load(path) {
+      Ok(blob) => prefabs.push(Arc::new(blob)),
+      Err(err) => errors.push(err),
+    }
+  }
+  let catalog =
+    Catalog {
+      params  : params.clone(),
+      prefabs : Arc::new(prefabs),
+    };
+  (catalog, errors)
+}
+
+type Cache = lru_cache::LruCache<(i32, i32), Option<prefab::T>, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Distinguishes the structures of different seeds.
+  salt       : u64,
+  catalog    : Catalog,
+  structures : Cache,
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: Seed, catalog: Catalog) -> T {
+  // Seeds can't be read back, so tell them apart by their noise instead.
+  let salt = perlin3(&seed, &[0.7, 0.2, 0.4]);
+  T {
+    salt       : (salt * (1u64 << 52) as f64) as i64 as u64,
+    catalog    : catalog,
+    structures : lru_cache::LruCache::with_hasher(CACHE_SIZE, Default::default()),
+  }
+}
+
+/// A room of `size` standing on `floor`, with walls of `wall` a unit thick, a doorway and a flat roof.
+fn hut<R: Rng>(rng: &mut R, parts: &mut Vec<Part>, floor: Point3<f32>, size: Vector3<f32>, wall: voxel::Material) {
+  let min = Point3::new(floor.x - size.x / 2.0, floor.y, floor.z - size.z / 2.0);
+  let max = Point3::new(floor.x + size.x / 2.0, floor.y + size.y, floor.z + size.z / 2.0);
+  // A foundation, sunk deep enough to meet sloping ground.
+  parts.push(Part::Fill(
+    Solid::Cuboid { min: Point3::new(min.x, min.y - 4.0, min.z), max: Point3::new(max.x, min.y + 1.0, max.z) },
+    voxel::Material::Stone,
+  ));
+  parts.push(Part::Fill(Solid::Cuboid { min: min, max: max }, wall));
+  parts.push(Part::Carve(Solid::Cuboid {
+    min: Point3::new(min.x + 1.0, min.y + 1.0, min.z + 1.0),
+    max: Point3::new(max.x - 1.0, max.y - 1.0, max.z - 1.0),
+  }));
+  // Clear out above the roof, in case the hut is dug into a hillside.
+  parts.push(Part::Carve(Solid::Cuboid {
+    min: Point3::new(min.x - 1.0, max.y + 1.0, min.z - 1.0),
+    max: Point3::new(max.x + 1.0, max.y + 8.0, max.z + 1.0),
+  }));
+  parts.push(Part::Fill(
+    Solid::Cuboid {
+      min: Point3::new(min.x - 1.0, max.y - 1.0, min.z - 1.0),
+      max: Point3::new(max.x + 1.0, max.y, max.z + 1.0),
+    },
+    voxel::Material::Bark,
+  ));
+
+  // The doorway is in the front or back wall.
+  let (z0, z1) = if rng.gen() { (min.z - 1.0, min.z + 2.0) } else { (max.z - 2.0, max.z + 1.0) };
+  parts.push(Part::Carve(Solid::Cuboid {
+    min: Point3::new(floor.x - 1.0, min.y + 1.0, z0),
+    max: Point3::new(floor.x + 1.0, min.y + 4.0, z1),
+  }));
+}
+
+/// A handful of huts around a center.
+fn village<R: Rng>(rng: &mut R, heights: &Heights, center: Point3<f32>) -> Vec<Part> {
+  let mut parts = Vec::new();
+  let count = rng.gen_range(3, 6);
+  for i in 0 .. count {
+    let angle = (i as f32 + rng.gen_range(0.0, 0.5)) * 2.0 * std::f32::consts::PI / count as f32;
+    let distance = rng.gen_range(12.0, 28.0);
+    let x = center.x + distance * angle.cos();
+    let z = center.z + distance * angle.sin();
+    let size = Vector3::new(rng.gen_range(6.0, 10.0), rng.gen_range(5.0, 7.0), rng.gen_range(6.0, 10.0));
+    hut(rng, &mut parts, Point3::new(x, heights.height(x, z), z), size, voxel::Material::Bark);
+  }
+  parts
+}
+
+/// A ring of marble columns, most of them broken, on a cracked floor.
+fn ruin<R: Rng>(rng: &mut R, center: Point3<f32>) -> Vec<Part> {
+  let radius = rng.gen_range(8.0, 16.0);
+  let mut parts = vec!(
+    Part::Fill(
+      Solid::Cylinder { base: center + Vector3::new(0.0, -3.0, 0.0), radius: radius + 2.0, height: 3.5 },
+      voxel::Material::Marble,
+    ),
+  );
+  let columns = rng.gen_range(6, 12);
+  for i in 0 .. columns {
+    let angle = i as f32 * 2.0 * std::f32::consts::PI / columns as f32;
+    let height = if rng.gen_range(0.0, 1.0) < 0.3 { 10.0 } else { rng.gen_range(1.0, 7.0) };
+    parts.push(Part::Fill(
+      Solid::Cylinder {
+        base   : Point3::new(center.x + radius * angle.cos(), center.y, center.z + radius * angle.sin()),
+        radius : 1.0,
+        height : height,
+      },
+      voxel::Material::Marble,
+    ));
+  }
+  // Something's been digging.
+  for _ in 0 .. rng.gen_range(1, 4) {
+    let x = center.x + rng.gen_range(-radius, radius);
+    let z = center.z + rng.gen_range(-radius, radius);
+    parts.push(Part::Carve(Solid::Cylinder { base: Point3::new(x, center.y - 1.0, z), radius: 1.5, height: 2.0 }));
+  }
+  parts
+}
+
+/// Stone rooms buried under `center`, joined by corridors, with a shaft leading down to them.
+fn dungeon<R: Rng>(rng: &mut R, center: Point3<f32>) -> Vec<Part> {
+  let floor = center.y - rng.gen_range(16.0, 32.0);
+  let mut rooms = vec!((center.x, center.z));
+  for _ in 0 .. rng.gen_range(1, 4) {
+    let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    let distance = rng.gen_range(16.0, 28.0);
+    rooms.push((center.x + distance * angle.cos(), center.z + distance * angle.sin()));
+  }
+
+  let mut parts = Vec::new();
+  for &(x, z) in &rooms {
+    let half = rng.gen_range(4.0, 7.0);
+    let min = Point3::new(x - half, floor - 1.0, z - half);
+    let max = Point3::new(x + half, floor + 6.0, z + half);
+    parts.push(Part::Fill(Solid::Cuboid { min: min, max: max }, voxel::Material::Stone));
+  }
+  for &(x, z) in &rooms {
+    let carve = |min: Point3<f32>, max: Point3<f32>| Part::Carve(Solid::Cuboid { min: min, max: max });
+    parts.push(carve(Point3::new(x - 3.0, floor, z - 3.0), Point3::new(x + 3.0, floor + 5.0, z + 3.0)));
+    // An L-shaped corridor back to the first room.
+    let (cx, cz) = rooms[0];
+    parts.push(carve(Point3::new(x.min(cx) - 1.0, floor, z - 1.0), Point3::new(x.max(cx) + 1.0, floor + 3.0, z + 1.0)));
+    parts.push(carve(Point3::new(cx - 1.0, floor, z.min(cz) - 1.0), Point3::new(cx + 1.0, floor + 3.0, z.max(cz) + 1.0)));
+  }
+  parts.push(Part::Fill(
+    Solid::Cylinder { base: Point3::new(center.x, floor, center.z), radius: 3.0, height: center.y + 1.0 - floor },
+    voxel::Material::Stone,
+  ));
+  parts.push(Part::Carve(
+    Solid::Cylinder { base: Point3::new(center.x, floor, center.z), radius: 2.0, height: center.y + 8.0 - floor },
+  ));
+  parts
+}
+
+/// Decide whether a cell has a structure, and build it if it does.
+fn place<Water: water::Heightmap>(
+  salt    : u64,
+  catalog : &Catalog,
+  heights : &Heights,
+  water   : &mut water::T<Water>,
+  cell    : (i32, i32),
+) -> Option<prefab::T> {
+  let mut rng: rand::XorShiftRng = {
+    let mut hasher = fnv::FnvHasher::default();
+    (salt, cell).hash(&mut hasher);
+    let hash = hasher.finish();
+    // XorShift seeds can't be all zero.
+    rand::SeedableRng::from_seed([hash as u32, (hash >> 32) as u32, 0x9e3779b9, 1])
+  };
+
+  if rng.gen_range(0.0, 1.0) >= catalog.params.frequency {
+    return None
+  }
+
+  // Keep structures inside their cells where there's room, so they don't overlap.
+  let spacing = catalog.params.spacing;
+  let margin = MAX_REACH.min(spacing / 2.0);
+  let x = cell.0 as f32 * spacing + rng.gen_range(margin, spacing - margin + 0.001);
+  let z = cell.1 as f32 * spacing + rng.gen_range(margin, spacing - margin + 0.001);
+  let height = heights.height(x, z);
+  let center = Point3::new(x, height, z);
+  let dry = height > water.level(x, z);
+
+  let prefabs = catalog.prefabs.len();
+  let parts =
+    match rng.gen_range(0, 3 + prefabs) {
+      0 if dry && heights.biome_at(x, z) != Biome::Mountains => village(&mut rng, heights, center),
+      1 if dry => ruin(&mut rng, center),
+      2 => dungeon(&mut rng, center),
+      i if i >= 3 && dry => {
+        let blob = catalog.prefabs[i - 3].clone();
+        let extent = blob.extent();
+        // Sink the blob's bottom layer into the ground.
+        let low = Point3::new(x - extent.x / 2.0, height - 1.0, z - extent.z / 2.0);
+        vec!(Part::Blob(low, blob))
+      },
+      _ => return None,
+    };
+
+  let structure = prefab::new(parts);
+  assert!(structure.bounds.min.x >= x - MAX_REACH && structure.bounds.max.x <= x + MAX_REACH);
+  assert!(structure.bounds.min.z >= z - MAX_REACH && structure.bounds.max.z <= z + MAX_REACH);
+  Some(structure)
+}
+
+impl T {
+  /// Stamp any structures near a point over the density and material of the ground there.
+  pub fn apply<Water: water::Heightmap>(
+    &mut self,
+    heights     : &Heights,
+    water       : &mut water::T<Water>,
+    p           : &Point3<f32>,
+    mat_density : (f32, voxel::Material),
+  ) -> (f32, voxel::Material) {
+    if !self.catalog.params.enabled {
+      return mat_density
+    }
+
+    let spacing = self.catalog.params.spacing;
+    let cell_of = |x: f32| (x / spacing).floor() as i32;
+    let mut mat_density = mat_density;
+    for cell_x in cell_of(p.x - MAX_REACH) .. cell_of(p.x + MAX_REACH) + 1 {
+    for cell_z in cell_of(p.z - MAX_REACH) .. cell_of(p.z + MAX_REACH) + 1 {
+      let cell = (cell_x, cell_z);
+      if !self.structures.contains_key(&cell) {
+        let structure = place(self.salt, &self.catalog, heights, water, cell);
+        self.structures.insert(cell, structure);
+      }
+
+      let structure =
+        match self.structures.get_mut(&cell).unwrap() {
+          &mut None => continue,
+          &mut Some(ref structure) => structure,
+        };
+      let bounds = &structure.bounds;
+      let inside =
+        bounds.min.x <= p.x && p.x <= bounds.max.x &&
+        bounds.min.y <= p.y && p.y <= bounds.max.y &&
+        bounds.min.z <= p.z && p.z <= bounds.max.z;
+      if inside {
+        mat_density = structure.apply(p, mat_density);
+      }
+    }}
+    mat_density
+  }
+}
@@ -0,0 +1,158 @@
+//! Scatter trees over the terrain. Trees are placed deterministically by position, so every
+//! generator places the same trees, however the world is split up between them.
+
+use cgmath::{Point3, EuclideanSpace};
+use collision::Aabb3;
+use fnv;
+use lru_cache;
+use noise::{Seed, perlin3};
+use rand;
+use rand::Rng;
+use std;
+use std::hash::{Hash, Hasher};
+
+use common::voxel;
+
+use biome::map::Heights;
+use tree;
+use water;
+
+/// lg of the width of the cells trees are scattered in. Each cell has at most one tree.
+const LG_CELL_WIDTH: i32 = 4;
+/// Trees don't grow on ground steeper than this (rise over run).
+const MAX_SLOPE: f32 = 0.6;
+/// How far trunks are sunk into the ground, so they don't float over slopes.
+const ROOT_DEPTH: f32 = 1.0;
+/// The furthest a tree reaches from its trunk, horizontally.
+const MAX_REACH: f32 = 20.0;
+/// The number of cells' trees to keep built.
+const CACHE_SIZE: usize = 1 << 6;
+
+struct Tree {
+  base   : Point3<f32>,
+  /// Where the tree is, in world coordinates.
+  bounds : Aabb3<f32>,
+  shape  : tree::T,
+}
+
+type Cache = lru_cache::LruCache<(i32, i32), Option<Tree>, std::hash::BuildHasherDefault<fnv::FnvHasher>>;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Distinguishes the trees of different seeds.
+  salt  : u64,
+  trees : Cache,
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: Seed) -> T {
+  // Seeds can't be read back, so tell them apart by their noise instead.
+  let salt = perlin3(&seed, &[0.3, 0.6, 0.9]);
+  T {
+    salt  : (salt * (1u64 << 52) as f64) as i64 as u64,
+    trees : lru_cache::LruCache::with_hasher(CACHE_SIZE, Default::default()),
+  }
+}
+
+fn cell_of(x: f32) -> i32 {
+  (x.floor() as i32) >> LG_CELL_WIDTH
+}
+
+/// Decide whether a cell has a tree, and build it if it does.
+fn place<Water: water::Heightmap>(
+  salt    : u64,
+  heights : &Heights,
+  water   : &mut water::T<Water>,
+  cell    : (i32, i32),
+) -> Option<Tree> {
+  let mut rng: rand::XorShiftRng = {
+    let mut hasher = fnv::FnvHasher::default();
+    (salt, cell).hash(&mut hasher);
+    let hash = hasher.finish();
+    // XorShift seeds can't be all zero.
+    rand::SeedableRng::from_seed([hash as u32, (hash >> 32) as u32, 0x9e3779b9, 1])
+  };
+
+  let cell_width = (1 << LG_CELL_WIDTH) as f32;
+  let x = (cell.0 as f32 + rng.gen_range(0.0, 1.0)) * cell_width;
+  let z = (cell.1 as f32 + rng.gen_range(0.0, 1.0)) * cell_width;
+
+  if rng.gen_range(0.0, 1.0) >= heights.tree_density(x, z) {
+    return None
+  }
+
+  let height = heights.height(x, z);
+  if height <= water.level(x, z) {
+    return None
+  }
+
+  let slope = {
+    let dx = heights.height(x + 1.0, z) - heights.height(x - 1.0, z);
+    let dz = heights.height(x, z + 1.0) - heights.height(x, z - 1.0);
+    (dx * dx + dz * dz).sqrt() / 2.0
+  };
+  if slope > MAX_SLOPE {
+    return None
+  }
+
+  let trunk_radius = rng.gen_range(1.0, 2.0);
+  let trunk_height = rng.gen_range(6.0, 10.0) * trunk_radius;
+  let leaf_radius = rng.gen_range(3.0, 4.5) * trunk_radius;
+
+  let base = Point3::new(x, height - ROOT_DEPTH, z);
+  // Leaves are spheres of radius 4 around points within `leaf_radius` of the canopy's center.
+  let reach = leaf_radius + 4.0;
+  assert!(reach <= MAX_REACH);
+  let top = trunk_height + leaf_radius / 2.0 + reach;
+  Some(Tree {
+    base   : base,
+    bounds : Aabb3::new(Point3::new(x - reach, base.y, z - reach), Point3::new(x + reach, base.y + top, z + reach)),
+    shape  : tree::new(&mut rng, trunk_height, trunk_radius, leaf_radius),
+  })
+}
+
+impl T {
+  /// The density and material of the trees at a point, if there are any nearby.
+  pub fn mat_density<Water: water::Heightmap>(
+    &mut self,
+    heights : &Heights,
+    water   : &mut water::T<Water>,
+    p       : &Point3<f32>,
+  ) -> Option<(f32, voxel::Material)> {
+    let mut nearest: Option<(f32, voxel::Material)> = None;
+    for cell_x in cell_of(p.x - MAX_REACH) .. cell_of(p.x + MAX_REACH) + 1 {
+    for cell_z in cell_of(p.z - MAX_REACH) .. cell_of(p.z + MAX_REACH) + 1 {
+      let cell = (cell_x, cell_z);
+      if !self.trees.contains_key(&cell) {
+        let tree = place(self.salt, heights, water, cell);
+        self.trees.insert(cell, tree);
+      }
+
+      let tree =
+        match self.trees.get_mut(&cell).unwrap() {
+          &mut None => continue,
+          &mut Some(ref mut tree) => tree,
+        };
+      let inside =
+        tree.bounds.min.x <= p.x && p.x <= tree.bounds.max.x &&
+        tree.bounds.min.y <= p.y && p.y <= tree.bounds.max.y &&
+        tree.bounds.min.z <= p.z && p.z <= tree.bounds.max.z;
+      if !inside {
+        continue
+      }
+
+      let local = *p - tree.base.to_vec();
+      let density = voxel::field::T::density(&mut tree.shape, &local);
+      if nearest.map_or(true, |(d, _)| density > d) {
+        let material =
+          if density >= 0.0 {
+            voxel::mosaic::T::material(&mut tree.shape, &local).unwrap_or(voxel::Material::Empty)
+          } else {
+            voxel::Material::Empty
+          };
+        nearest = Some((density, material));
+      }
+    }}
+    nearest
+  }
+}
@@ -0,0 +1,284 @@
+//! Water: the sea, plus lakes flood-filled into basins in the terrain.
+
+use cgmath::{Point3, Vector3};
+use fnv;
+use lru_cache;
+use std;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+
+use common::fnv_map;
+use common::voxel;
+
+/// The height of the sea's surface.
+pub const SEA_LEVEL: f32 = -8.0;
+
+/// lg(width) of the cells that lakes are found in. Each cell has at most one lake.
+const LG_LAKE_CELL_WIDTH: i32 = 8;
+/// lg(distance) between heightmap samples when flood-filling a lake.
+const LG_GRID_STEP: i32 = 3;
+/// The number of heightmap samples along each side of a lake cell.
+const GRID_WIDTH: i32 = 1 << (LG_LAKE_CELL_WIDTH - LG_GRID_STEP);
+/// Basins shallower than this don't get filled.
+const MIN_LAKE_DEPTH: f32 = 1.0;
+/// The number of lake cells each generator keeps close at hand.
+const LOCAL_LAKES: usize = 16;
+
+type Cell = (i32, i32);
+
+/// A source of terrain heights to pool water in.
+pub trait Heightmap {
+  #[allow(missing_docs)]
+  fn height(&mut self, x: f32, z: f32) -> f32;
+}
+
+struct Lake {
+  level   : f32,
+  /// Which heightmap samples in the cell are under water.
+  flooded : Vec<bool>,
+}
+
+struct Cells {
+  /// Flood-filled lakes, in cells with terrain loaded.
+  lakes  : fnv_map::T<Cell, Arc<Option<Lake>>>,
+  /// How many loaded voxels overlap each cell.
+  loaded : fnv_map::T<Cell, u32>,
+}
+
+/// The lakes found so far, shared by a terrain's generators so each is only flood-filled once. A cell's lake is
+/// forgotten once none of the terrain around it is loaded.
+pub struct Lakes {
+  cells : Mutex<Cells>,
+}
+
+#[allow(missing_docs)]
+pub fn lakes() -> Arc<Lakes> {
+  Arc::new(
+    Lakes {
+      cells :
+        Mutex::new(
+          Cells {
+            lakes  : fnv_map::new(),
+            loaded : fnv_map::new(),
+          }
+        ),
+    }
+  )
+}
+
+/// The lake cells a voxel overlaps.
+fn cells_overlapping(bounds: &voxel::bounds::T) -> Vec<Cell> {
+  let (low, high) = bounds.corners();
+  let low = ((low.x.floor() as i32) >> LG_LAKE_CELL_WIDTH, (low.z.floor() as i32) >> LG_LAKE_CELL_WIDTH);
+  let high = ((high.x.ceil() as i32 - 1) >> LG_LAKE_CELL_WIDTH, (high.z.ceil() as i32 - 1) >> LG_LAKE_CELL_WIDTH);
+  let mut cells = Vec::new();
+  for x in low.0 .. high.0 + 1 {
+  for z in low.1 .. high.1 + 1 {
+    cells.push((x, z));
+  }}
+  cells
+}
+
+impl Lakes {
+  /// Note that a voxel's been loaded, so the lakes around it are worth keeping.
+  pub fn loaded(&self, bounds: &voxel::bounds::T) {
+    let mut cells = self.cells.lock().unwrap();
+    for cell in cells_overlapping(bounds) {
+      *cells.loaded.entry(cell).or_insert(0) += 1;
+    }
+  }
+
+  /// Note that a voxel's been unloaded, and forget the lakes nothing loaded overlaps anymore.
+  pub fn unloaded(&self, bounds: &voxel::bounds::T) {
+    let mut cells = self.cells.lock().unwrap();
+    for cell in cells_overlapping(bounds) {
+      let unused =
+        match cells.loaded.get_mut(&cell) {
+          None => false,
+          Some(count) => {
+            *count -= 1;
+            *count == 0
+          },
+        };
+      if unused {
+        cells.loaded.remove(&cell);
+        cells.lakes.remove(&cell);
+      }
+    }
+  }
+
+  /// The number of lakes being kept.
+  pub fn len(&self) -> usize {
+    self.cells.lock().unwrap().lakes.len()
+  }
+}
+
+/// A water field: positive density is under water.
+pub struct T<H> {
+  heightmap : H,
+  lakes     : Arc<Lakes>,
+  /// The lakes this generator's used lately, so it doesn't have to go to `lakes` for every sample.
+  local     : lru_cache::LruCache<Cell, Arc<Option<Lake>>, std::hash::BuildHasherDefault<fnv::FnvHasher>>,
+}
+
+#[allow(missing_docs)]
+pub fn new<H: Heightmap>(heightmap: H, lakes: Arc<Lakes>) -> T<H> {
+  T {
+    heightmap : heightmap,
+    lakes     : lakes,
+    local     : lru_cache::LruCache::with_hasher(LOCAL_LAKES, Default::default()),
+  }
+}
+
+impl<H: Heightmap> T<H> {
+  fn lake(&mut self, cell: Cell) -> Arc<Option<Lake>> {
+    if let Some(lake) = self.local.get_mut(&cell) {
+      return lake.clone()
+    }
+
+    let shared = self.lakes.cells.lock().unwrap().lakes.get(&cell).cloned();
+    let lake =
+      match shared {
+        Some(lake) => lake,
+        None => {
+          // Flood-fill without holding the lock; if another generator gets there first, the lakes are the same.
+          let lake = Arc::new(flood_fill(&mut self.heightmap, cell));
+          let mut cells = self.lakes.cells.lock().unwrap();
+          if cells.loaded.contains_key(&cell) {
+            cells.lakes.entry(cell).or_insert_with(|| lake.clone());
+          }
+          lake
+        },
+      };
+    self.local.insert(cell, lake.clone());
+    lake
+  }
+
+  /// The height of the water's surface at a given (x, z) position.
+  pub fn level(&mut self, x: f32, z: f32) -> f32 {
+    let cell = (
+      (x.floor() as i32) >> LG_LAKE_CELL_WIDTH,
+      (z.floor() as i32) >> LG_LAKE_CELL_WIDTH,
+    );
+
+    let lake_level = {
+      let lake = self.lake(cell);
+      (*lake).as_ref().and_then(|lake| {
+        let gx = ((x.floor() as i32) >> LG_GRID_STEP) - (cell.0 << (LG_LAKE_CELL_WIDTH - LG_GRID_STEP));
+        let gz = ((z.floor() as i32) >> LG_GRID_STEP) - (cell.1 << (LG_LAKE_CELL_WIDTH - LG_GRID_STEP));
+        if lake.flooded[(gx * GRID_WIDTH + gz) as usize] {
+          Some(lake.level)
+        } else {
+          None
+        }
+      })
+    };
+
+    match lake_level {
+      None => SEA_LEVEL,
+      Some(level) => f32::max(SEA_LEVEL, level),
+    }
+  }
+}
+
+/// Find the lake in a cell, if any, by pouring water into the lowest point of the cell until it spills out
+/// over one of the cell's edges.
+fn flood_fill<H: Heightmap>(heightmap: &mut H, cell: (i32, i32)) -> Option<Lake> {
+  let step = (1 << LG_GRID_STEP) as f32;
+  let x0 = (cell.0 << LG_LAKE_CELL_WIDTH) as f32;
+  let z0 = (cell.1 << LG_LAKE_CELL_WIDTH) as f32;
+
+  let mut heights = Vec::with_capacity((GRID_WIDTH * GRID_WIDTH) as usize);
+  for gx in 0 .. GRID_WIDTH {
+  for gz in 0 .. GRID_WIDTH {
+    heights.push(heightmap.height(x0 + (gx as f32 + 0.5) * step, z0 + (gz as f32 + 0.5) * step));
+  }}
+
+  let lowest =
+    (0 .. heights.len())
+    .fold(0, |best, i| if heights[i] < heights[best] { i } else { best });
+
+  // Heights are ordered by a fixed-point key, since f32 isn't Ord.
+  let key = |h: f32| (h * 1024.0) as i64;
+
+  let mut visited = vec!(false; heights.len());
+  let mut queue = BinaryHeap::new();
+  queue.push(Reverse((key(heights[lowest]), lowest)));
+  visited[lowest] = true;
+
+  let mut level = heights[lowest];
+  let mut filled = Vec::new();
+  while let Some(Reverse((_, i))) = queue.pop() {
+    let (gx, gz) = (i as i32 / GRID_WIDTH, i as i32 % GRID_WIDTH);
+    if gx == 0 || gz == 0 || gx == GRID_WIDTH - 1 || gz == GRID_WIDTH - 1 {
+      // The water spills out of the cell here.
+      break
+    }
+
+    level = f32::max(level, heights[i]);
+    filled.push(i);
+
+    for &(dx, dz) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+      let j = ((gx + dx) * GRID_WIDTH + (gz + dz)) as usize;
+      if !visited[j] {
+        visited[j] = true;
+        queue.push(Reverse((key(heights[j]), j)));
+      }
+    }
+  }
+
+  if level - heights[lowest] < MIN_LAKE_DEPTH || level <= SEA_LEVEL {
+    return None
+  }
+
+  let mut flooded = vec!(false; heights.len());
+  for i in filled {
+    // The last few samples popped may be above the spill level.
+    flooded[i] = heights[i] < level;
+  }
+
+  Some(Lake {
+    level   : level,
+    flooded : flooded,
+  })
+}
+
+impl<H: Heightmap> voxel::field::T for T<H> {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.level(p.x, p.z) - p.y
+  }
+
+  fn normal(&mut self, _: &Point3<f32>) -> Vector3<f32> {
+    Vector3::new(0.0, 1.0, 0.0)
+  }
+}
+
+#[cfg(test)]
+struct Bowl;
+
+#[cfg(test)]
+impl Heightmap for Bowl {
+  fn height(&mut self, x: f32, z: f32) -> f32 {
+    let r = 128.0;
+    ((x - r).powi(2) + (z - r).powi(2)).sqrt() / 4.0
+  }
+}
+
+#[test]
+fn lakes_are_forgotten_when_their_terrain_unloads() {
+  let lakes = lakes();
+  let mut water = new(Bowl, lakes.clone());
+  let bounds = voxel::bounds::new(4, 0, 4, 5);
+  let neighbor = voxel::bounds::new(5, 0, 4, 5);
+
+  lakes.loaded(&bounds);
+  lakes.loaded(&neighbor);
+  assert!(water.level(128.0, 128.0) > SEA_LEVEL);
+  assert_eq!(lakes.len(), 1);
+
+  lakes.unloaded(&bounds);
+  assert_eq!(lakes.len(), 1);
+  lakes.unloaded(&neighbor);
+  assert_eq!(lakes.len(), 0);
+}
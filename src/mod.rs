@@ -14,26 +14,46 @@ extern crate dummy_client_lib;
 extern crate server_lib;
 
 use std::borrow::Borrow;
+use std::env;
 use std::sync::Mutex;
 
 fn main() {
   env_logger::init().unwrap();
 
-  let listen_url = String::from("ipc:///tmp/client.ipc");
-  let server_url = String::from("ipc:///tmp/server.ipc");
+  // Client and server share a process, so by default they talk without any sockets.
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  let transport = server_lib::Transport::from_args(&mut args).unwrap_or(server_lib::Transport::Local);
+  let seed = server_lib::config::seed_from_args(&mut args);
+  assert!(args.is_empty());
+
+  let listen_url = String::from(transport.default_client_url());
+  let server_url = String::from(transport.default_server_url());
 
   let quit_signal = Mutex::new(false);
 
   unsafe {
     let server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        server_lib::run(
+          server_url.borrow(),
+          transport,
+          server_lib::DEFAULT_TERRAIN_WORKERS,
+          seed,
+          None,
+          &server_lib::console::new_queue(),
+          {
+            let mut plugins = server_lib::plugin::new();
+            plugins.register(Box::new(server_lib::script::new()));
+            plugins
+          },
+          &quit_signal,
+        ).expect("Couldn't start the server");
       });
 
     #[cfg(feature = "dummy-client")]
-    dummy_client_lib::run(listen_url.borrow(), server_url.borrow());
+    dummy_client_lib::run(listen_url.borrow(), server_url.borrow(), transport);
     #[cfg(not(feature = "dummy-client"))]
-    client_lib::run(listen_url.borrow(), server_url.borrow());
+    client_lib::run(listen_url.borrow(), server_url.borrow(), transport, client_lib::replay::Mode::Live);
     *quit_signal.lock().unwrap() = true;
     server_thread.join();
 
@@ -0,0 +1,84 @@
+//! Tests of a server and clients working together, from connecting through to editing terrain.
+
+extern crate cgmath;
+extern crate client_lib;
+extern crate common;
+extern crate server_lib;
+
+mod harness;
+
+use cgmath::{Point3, Vector3};
+
+use client_lib::{chunk, prediction};
+use common::protocol;
+use common::voxel;
+
+#[test]
+fn nearby_chunks_load() {
+  let client = harness::connect();
+  client.wait_until("the chunks around the player load", |_| client.nearby_chunks_loaded());
+}
+
+#[test]
+fn walking_loads_chunks_ahead() {
+  let client = harness::connect();
+  client.wait_until("the chunks around the player load", |_| client.nearby_chunks_loaded());
+
+  let start = chunk::position::of_world_position(&client.position());
+  client.input(prediction::Input::Walk(Vector3::new(0.0, 0.0, -1.0)));
+  client.wait_until("the player walks into another chunk", |_| {
+    let now = chunk::position::of_world_position(&client.position());
+    now.as_pnt().x != start.as_pnt().x || now.as_pnt().z != start.as_pnt().z
+  });
+  client.input(prediction::Input::Walk(Vector3::new(0.0, 0.0, 1.0)));
+
+  client.wait_until("the chunks around the player's new position load", |_| client.nearby_chunks_loaded());
+}
+
+#[test]
+fn edits_round_trip() {
+  let client = harness::connect();
+  client.wait_until("the chunks around the player load", |_| client.nearby_chunks_loaded());
+
+  // A few meters down, which is below the surface but too shallow for caves.
+  let p = client.position() + Vector3::new(0.0, -4.0, 0.0);
+  let bounds = voxel::bounds::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32, 0);
+  let empty = Some(voxel::Volume(voxel::Material::Empty));
+  client.wait_until("the voxel under the player loads", |client| client.terrain.lock().unwrap().voxel(&bounds).is_some());
+  assert!(client.state().terrain.lock().unwrap().voxel(&bounds) != empty, "{:?} is already empty", bounds);
+
+  client.tell(
+    protocol::ClientToServer::ModifyVoxels {
+      player   : client.state().player_id,
+      world    : *client.state().world.lock().unwrap(),
+      brush    : protocol::Brush::Sphere { radius: 2.0 },
+      position : Point3::new(bounds.x as f32 + 0.5, bounds.y as f32 + 0.5, bounds.z as f32 + 0.5),
+      action   : protocol::BrushAction::Remove,
+    }
+  );
+  client.wait_until("the dug-out voxel comes back empty", |client| client.terrain.lock().unwrap().voxel(&bounds) == empty);
+
+  client.tell(protocol::ClientToServer::Undo(client.state().player_id));
+  client.wait_until("the voxel is filled back in", |client| client.terrain.lock().unwrap().voxel(&bounds) != empty);
+}
+
+#[test]
+fn players_agree_on_ids() {
+  let a = harness::connect();
+  let b = harness::connect();
+  let (a_id, b_id) = (a.state().player_id, b.state().player_id);
+  assert!(a_id != b_id);
+
+  for &(client, other) in &[(&a, b_id), (&b, a_id)] {
+    let own = client.state().player_id;
+    client.wait_until("both players are in a snapshot", |client| {
+      client.replication.lock().unwrap().shown().map_or(false, |world| {
+        world.players.contains_key(&own) && world.players.contains_key(&other)
+      })
+    });
+    client.wait_until("both players' names are known", |client| {
+      let names = client.player_names.lock().unwrap();
+      names.contains_key(&own) && names.contains_key(&other)
+    });
+  }
+}
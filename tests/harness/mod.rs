@@ -0,0 +1,160 @@
+//! Runs a server and headless clients together in the test process, talking over the local transport.
+//!
+//! Every test shares one server, which starts the first time a client connects and runs until the process exits.
+//! Its world is saved in a fresh temporary directory, so tests always start from freshly-generated terrain. Each
+//! test connects its own clients, and they're all in the same world, so tests shouldn't assume they're alone.
+
+use cgmath::{Point3, Vector3};
+use std;
+use std::sync::{Arc, Mutex, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use client_lib;
+use client_lib::{chunk, client, prediction, process_event, terrain};
+use client_lib::update_thread::update_thread;
+use common::net;
+use common::protocol;
+use server_lib;
+
+/// Where the server listens.
+const SERVER_URL: &'static str = "end-to-end-server";
+/// The world's seed, so every run generates the same terrain.
+const SEED: u32 = 0;
+/// How long to wait for something to happen before failing.
+const TIMEOUT_SECS: u64 = 60;
+/// How often to check whether something's happened yet.
+const POLL_MS: u64 = 50;
+
+static START_SERVER: Once = Once::new();
+
+fn start_server() {
+  START_SERVER.call_once(|| {
+    let directory = std::env::temp_dir().join(format!("playform-end-to-end-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::env::set_current_dir(&directory).unwrap();
+
+    std::thread::spawn(|| {
+      // Never set; the server goes when the process does.
+      let quit = Mutex::new(false);
+      server_lib::run(
+        SERVER_URL,
+        net::Transport::Local,
+        server_lib::DEFAULT_TERRAIN_WORKERS,
+        Some(SEED),
+        None,
+        &server_lib::console::new_queue(),
+        server_lib::plugin::new(),
+        &quit,
+      ).unwrap();
+    });
+  });
+}
+
+/// A headless client connected to the test server. It keeps processing what the server sends, and loading terrain
+/// around its player, until it's dropped.
+pub struct Client {
+  client : Arc<client::T>,
+  server : client_lib::server::T,
+  quit   : Arc<Mutex<bool>>,
+  thread : Option<std::thread::JoinHandle<()>>,
+}
+
+/// Connect a new client, with a new player, to the test server.
+pub fn connect() -> Client {
+  static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+  start_server();
+
+  let listen_url = format!("end-to-end-client-{}", CLIENTS.fetch_add(1, Ordering::Relaxed));
+  let server = client_lib::server::new(SERVER_URL, &listen_url, net::Transport::Local).unwrap();
+  let client = Arc::new(client_lib::run::connect_client(&listen_url, &server));
+  let quit = Arc::new(Mutex::new(false));
+
+  let thread = {
+    let client = client.clone();
+    let server = server.clone();
+    let quit = quit.clone();
+    std::thread::spawn(move || {
+      update_thread(
+        &quit,
+        &client,
+        &mut || { server.listen.try() },
+        &mut |_| {},
+        &mut |_| {},
+        &mut |_| {},
+        &mut |up| { server.talk.tell(&up) },
+        &mut |msg| {
+          if let terrain::Load::Voxels { time_requested: Some(time_requested), .. } = msg {
+            client.chunk_requests.lock().unwrap().received(time_requested);
+          }
+          client.terrain.lock().unwrap().enqueue(msg);
+        },
+      );
+    })
+  };
+
+  Client {
+    client : client,
+    server : server,
+    quit   : quit,
+    thread : Some(thread),
+  }
+}
+
+impl Client {
+  /// Everything the client knows.
+  pub fn state(&self) -> &client::T {
+    &self.client
+  }
+
+  /// Send the server a message, as this client.
+  pub fn tell(&self, msg: protocol::ClientToServer) {
+    self.server.talk.tell(&msg);
+  }
+
+  /// Act on some player input, the way a keypress would.
+  pub fn input(&self, input: prediction::Input) {
+    process_event::input(&self.client, &mut |up| self.server.talk.tell(&up), input);
+  }
+
+  /// Where the server last said the player is.
+  pub fn position(&self) -> Point3<f32> {
+    *self.client.player_position.lock().unwrap()
+  }
+
+  /// Wait until `done` holds, failing the test if it takes too long.
+  pub fn wait_until<Done>(&self, what: &str, mut done: Done) where Done: FnMut(&client::T) -> bool {
+    let start = std::time::Instant::now();
+    while !done(&self.client) {
+      if start.elapsed() > std::time::Duration::from_secs(TIMEOUT_SECS) {
+        panic!("Timed out after {}s waiting until {}", TIMEOUT_SECS, what);
+      }
+      std::thread::sleep(std::time::Duration::from_millis(POLL_MS));
+    }
+  }
+
+  /// Whether the chunk the player's in, and every chunk touching it, is loaded.
+  pub fn nearby_chunks_loaded(&self) -> bool {
+    let center = *chunk::position::of_world_position(&self.position()).as_pnt();
+    let loaded: Vec<Point3<i32>> =
+      self.client.terrain.lock().unwrap().loaded_chunks().iter().map(|&(ref position, _)| *position.as_pnt()).collect();
+    for x in -1 .. 2 {
+    for y in -1 .. 2 {
+    for z in -1 .. 2 {
+      if !loaded.contains(&(center + Vector3::new(x, y, z))) {
+        return false
+      }
+    }}}
+    true
+  }
+}
+
+impl Drop for Client {
+  fn drop(&mut self) {
+    *self.quit.lock().unwrap() = true;
+    if let Some(thread) = self.thread.take() {
+      // Don't panic while a failed test is already panicking.
+      let _ = thread.join();
+    }
+  }
+}